@@ -0,0 +1,71 @@
+//! Cursor scans, over the whole bucket and over short ranges.
+//!
+//! Run with `cargo bench --bench cursor`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+mod workload;
+
+use workload::{key, keys, Fixture, Order, BUCKET};
+
+const KEYS: u64 = 100_000;
+const RANGE: u64 = 100;
+
+fn cursor(c: &mut Criterion) {
+    let fixture = Fixture::with_keys(KEYS, Order::Random, 64);
+
+    let mut group = c.benchmark_group("cursor");
+    group.throughput(Throughput::Elements(KEYS));
+    group.bench_function("scan", |b| {
+        b.iter(|| {
+            fixture.db.view(|tx| {
+                let mut cursor = tx.bucket(BUCKET).unwrap().cursor();
+                let mut item = cursor.first();
+                while let Some((k, v)) = item {
+                    black_box((k, v));
+                    item = cursor.next();
+                }
+                Ok(())
+            })
+        })
+    });
+    group.bench_function("scan_reverse", |b| {
+        b.iter(|| {
+            fixture.db.view(|tx| {
+                let mut cursor = tx.bucket(BUCKET).unwrap().cursor();
+                let mut item = cursor.last();
+                while let Some((k, v)) = item {
+                    black_box((k, v));
+                    item = cursor.prev();
+                }
+                Ok(())
+            })
+        })
+    });
+
+    // Seeks to random keys and reads the RANGE keys from there.
+    let starts = keys(KEYS / RANGE, Order::Random);
+    group.bench_function("range", |b| {
+        b.iter(|| {
+            fixture.db.view(|tx| {
+                let mut cursor = tx.bucket(BUCKET).unwrap().cursor();
+                for start in &starts {
+                    let start = u64::from_be_bytes(*start) * RANGE;
+                    let end = key(start + RANGE);
+                    let mut item = cursor.seek(&key(start));
+                    while let Some((k, v)) = item.filter(|(k, _)| *k < &end[..]) {
+                        black_box((k, v));
+                        item = cursor.next();
+                    }
+                }
+                Ok(())
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, cursor);
+criterion_main!(benches);
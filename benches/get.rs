@@ -0,0 +1,74 @@
+//! Point lookups of keys present and missing, with small and large values.
+//!
+//! Lookups run in a single read transaction over a database of KEYS keys,
+//! in random order, so most of them miss the leaf read last.
+//!
+//! Run with `cargo bench --bench get`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+mod workload;
+
+use workload::{keys, missing_keys, Fixture, Order, BUCKET};
+
+const KEYS: u64 = 100_000;
+
+fn get(c: &mut Criterion) {
+    let fixture = Fixture::with_keys(KEYS, Order::Random, 64);
+    let hits = keys(KEYS, Order::Random);
+    let misses = missing_keys(KEYS);
+
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(KEYS));
+    group.bench_function("hit", |b| {
+        b.iter(|| {
+            fixture.db.view(|tx| {
+                let bucket = tx.bucket(BUCKET).unwrap();
+                for key in &hits {
+                    black_box(bucket.get(key).unwrap());
+                }
+                Ok(())
+            })
+        })
+    });
+    group.bench_function("miss", |b| {
+        b.iter(|| {
+            fixture.db.view(|tx| {
+                let bucket = tx.bucket(BUCKET).unwrap();
+                for key in &misses {
+                    assert!(black_box(bucket.get(key)).is_none());
+                }
+                Ok(())
+            })
+        })
+    });
+    group.finish();
+}
+
+// Large values are read from overflow pages, without copying them.
+fn get_large(c: &mut Criterion) {
+    const KEYS: u64 = 100;
+    let mut group = c.benchmark_group("get_large");
+    for size in [4 << 10, 64 << 10, 1 << 20] {
+        let fixture = Fixture::with_keys(KEYS, Order::Sequential, size);
+        let keys = keys(KEYS, Order::Random);
+        group.throughput(Throughput::Bytes(KEYS * size as u64));
+        group.bench_function(format!("{}KiB", size >> 10), |b| {
+            b.iter(|| {
+                fixture.db.view(|tx| {
+                    let bucket = tx.bucket(BUCKET).unwrap();
+                    for key in &keys {
+                        black_box(bucket.get(key).unwrap());
+                    }
+                    Ok(())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, get, get_large);
+criterion_main!(benches);
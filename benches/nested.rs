@@ -0,0 +1,66 @@
+//! Writing and reading keys spread over nested buckets.
+//!
+//! The keys are spread over FANOUT buckets at each of DEPTH levels, so most
+//! buckets are small enough to stay inline in their parent until they grow.
+//!
+//! Run with `cargo bench --bench nested`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+mod workload;
+
+use workload::{create_nested, key, nested, nested_path, value, Fixture, KEYS_PER_TX};
+
+const KEYS: u64 = 50_000;
+const DEPTH: u32 = 3;
+const FANOUT: u64 = 10;
+
+// put_nested writes KEYS keys to their nested buckets, in key order.
+fn put_nested(fixture: &Fixture) {
+    let value = value(64);
+    for chunk in (0..KEYS).collect::<Vec<_>>().chunks(KEYS_PER_TX) {
+        fixture
+            .db
+            .update(|tx| {
+                for &i in chunk {
+                    let b = create_nested(tx, &nested_path(i, DEPTH, FANOUT))?;
+                    b.put(&key(i), &value)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+    }
+}
+
+fn bench_nested(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nested");
+    group.throughput(Throughput::Elements(KEYS));
+    group.sample_size(10);
+    group.bench_function("put", |b| {
+        b.iter_batched(
+            Fixture::new,
+            |fixture| put_nested(&fixture),
+            BatchSize::PerIteration,
+        )
+    });
+
+    let fixture = Fixture::new();
+    put_nested(&fixture);
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            fixture.db.view(|tx| {
+                for i in 0..KEYS {
+                    let b = nested(tx, &nested_path(i, DEPTH, FANOUT)).unwrap();
+                    black_box(b.get(&key(i)).unwrap());
+                }
+                Ok(())
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_nested);
+criterion_main!(benches);
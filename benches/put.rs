@@ -0,0 +1,53 @@
+//! Writing keys, in order and at random, with small and large values.
+//!
+//! Each iteration fills an empty database, so the numbers include spilling
+//! and writing the pages at commit, but not syncing them.
+//!
+//! Run with `cargo bench --bench put`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+mod workload;
+
+use workload::{keys, Fixture, Order};
+
+const KEYS: u64 = 50_000;
+
+fn put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("put");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(KEYS));
+    for order in [Order::Sequential, Order::Random] {
+        let keys = keys(KEYS, order);
+        group.bench_function(order.name(), |b| {
+            b.iter_batched(
+                Fixture::new,
+                |fixture| fixture.put(&keys, 64),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Values of a page or more are written to overflow pages of their own.
+fn put_large(c: &mut Criterion) {
+    const KEYS: u64 = 100;
+    let mut group = c.benchmark_group("put_large");
+    group.sample_size(10);
+    for size in [4 << 10, 64 << 10, 1 << 20] {
+        group.throughput(Throughput::Bytes(KEYS * size as u64));
+        let keys = keys(KEYS, Order::Random);
+        group.bench_function(format!("{}KiB", size >> 10), |b| {
+            b.iter_batched(
+                Fixture::new,
+                |fixture| fixture.put(&keys, size),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, put, put_large);
+criterion_main!(benches);
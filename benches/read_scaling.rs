@@ -0,0 +1,71 @@
+//! Read scalability across threads.
+//!
+//! Fills a database with 100k keys, then measures random point lookups from
+//! an increasing number of threads, each running its own read transactions.
+//! Throughput should grow with the threads up to the number of cores, since
+//! readers never wait for each other.
+//!
+//! Run with `cargo bench --bench read_scaling`.
+
+use std::thread;
+use std::time::Instant;
+
+use boltdb_rs::{Options, DB};
+
+const KEYS: u64 = 100_000;
+const LOOKUPS_PER_THREAD: u64 = 500_000;
+const LOOKUPS_PER_TX: u64 = 1_000;
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = DB::open(dir.path().join("bench.db"), Options::default()).unwrap();
+    for chunk in 0..KEYS / 1_000 {
+        db.update(|tx| {
+            let b = tx.create_bucket_if_not_exists(b"bench")?;
+            for i in chunk * 1_000..(chunk + 1) * 1_000 {
+                b.put(&i.to_be_bytes(), &[0x5a; 64])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    println!("{:>8} {:>14} {:>8}", "threads", "lookups/s", "speedup");
+    let mut base = None;
+    let mut threads = 1;
+    while threads <= cores.max(4) {
+        let rate = measure(&db, threads);
+        let base = *base.get_or_insert(rate);
+        println!("{:>8} {:>14.0} {:>7.2}x", threads, rate, rate / base);
+        threads *= 2;
+    }
+    db.close().unwrap();
+}
+
+// measure returns the lookups per second of threads concurrent readers.
+fn measure(db: &DB, threads: usize) -> f64 {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for t in 0..threads {
+            scope.spawn(move || {
+                // xorshift, seeded per thread.
+                let mut x = 0x9e37_79b9_7f4a_7c15u64 ^ t as u64;
+                for _ in 0..LOOKUPS_PER_THREAD / LOOKUPS_PER_TX {
+                    db.view(|tx| {
+                        let b = tx.bucket(b"bench").unwrap();
+                        for _ in 0..LOOKUPS_PER_TX {
+                            x ^= x << 13;
+                            x ^= x >> 7;
+                            x ^= x << 17;
+                            assert!(b.get(&(x % KEYS).to_be_bytes()).is_some());
+                        }
+                        Ok(())
+                    })
+                    .unwrap();
+                }
+            });
+        }
+    });
+    (threads as u64 * LOOKUPS_PER_THREAD) as f64 / start.elapsed().as_secs_f64()
+}
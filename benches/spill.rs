@@ -0,0 +1,59 @@
+//! Commit throughput of 10k-key write transactions.
+//!
+//! Each transaction overwrites 10k keys, so every leaf is spilled to a new
+//! dirty page. With small values the leaves fit in a page, with large ones
+//! every leaf has overflow pages. The buffers of the dirty pages are
+//! recycled from one transaction to the next.
+//!
+//! Run with `cargo bench --bench spill`.
+
+use std::time::Instant;
+
+use boltdb_rs::{Options, DB};
+
+const KEYS: u64 = 10_000;
+const TXS: u32 = 20;
+
+fn main() {
+    println!("{:>8} {:>10} {:>12}", "value", "tx/s", "MiB/s");
+    for value_size in [100, 5_000] {
+        let (rate, bytes) = measure(value_size);
+        println!(
+            "{:>8} {:>10.1} {:>12.1}",
+            value_size,
+            rate,
+            rate * bytes / (1 << 20) as f64
+        );
+    }
+}
+
+// measure returns the commits per second of transactions overwriting every
+// key with values of value_size bytes, and the bytes they write.
+fn measure(value_size: usize) -> (f64, f64) {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        no_sync: true,
+        ..Default::default()
+    };
+    let db = DB::open(dir.path().join("bench.db"), options).unwrap();
+    let put = |fill: u8| {
+        db.update(|tx| {
+            let b = tx.create_bucket_if_not_exists(b"bench")?;
+            let value = vec![fill; value_size];
+            for i in 0..KEYS {
+                b.put(&i.to_be_bytes(), &value)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    };
+    put(0);
+
+    let start = Instant::now();
+    for i in 0..TXS {
+        put(i as u8);
+    }
+    let rate = TXS as f64 / start.elapsed().as_secs_f64();
+    db.close().unwrap();
+    (rate, (KEYS as usize * value_size) as f64)
+}
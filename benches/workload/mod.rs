@@ -0,0 +1,163 @@
+//! Workload generators shared by the criterion benchmarks.
+//!
+//! Keys are 8-byte big-endian integers, so that their order as integers is
+//! their order in the tree: a sequential workload appends to the last leaf,
+//! a random one touches leaves all over the tree. Random orders come from a
+//! fixed seed, so that runs compare with each other.
+//!
+//! Each benchmark only uses some of the generators.
+#![allow(dead_code)]
+
+use boltdb_rs::{Bucket, Options, Result, Tx, DB};
+use tempfile::TempDir;
+
+/// BUCKET is the name of the bucket the keys are written to.
+pub const BUCKET: &[u8] = b"bench";
+
+/// KEYS_PER_TX is the number of keys written by each transaction when
+/// filling a database.
+pub const KEYS_PER_TX: usize = 10_000;
+
+/// Order is the order in which keys are generated.
+#[derive(Clone, Copy, Debug)]
+pub enum Order {
+    Sequential,
+    Random,
+}
+
+impl Order {
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Sequential => "sequential",
+            Order::Random => "random",
+        }
+    }
+}
+
+/// key returns the key of integer i.
+pub fn key(i: u64) -> [u8; 8] {
+    i.to_be_bytes()
+}
+
+/// keys returns the keys of the integers from 0 up to n, in order.
+pub fn keys(n: u64, order: Order) -> Vec<[u8; 8]> {
+    let mut ids: Vec<u64> = (0..n).collect();
+    if let Order::Random = order {
+        let mut rng = Rng::new(n);
+        for i in (1..ids.len()).rev() {
+            ids.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+    }
+    ids.into_iter().map(key).collect()
+}
+
+/// missing_keys returns n keys that keys(n, _) does not hold, interleaved
+/// with them, so that lookups descend the tree as deep as hits do.
+pub fn missing_keys(n: u64) -> Vec<Vec<u8>> {
+    keys(n, Order::Random)
+        .into_iter()
+        .map(|key| {
+            let mut key = key.to_vec();
+            key.push(0);
+            key
+        })
+        .collect()
+}
+
+/// value returns a value of size bytes.
+pub fn value(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+/// Fixture is a database in a temporary directory, removed on drop.
+pub struct Fixture {
+    pub db: DB,
+    _dir: TempDir,
+}
+
+impl Fixture {
+    /// new opens an empty database. Commits are not synced, so that the
+    /// benchmarks measure the database rather than the disk.
+    pub fn new() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            no_sync: true,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("bench.db"), options).unwrap();
+        Fixture { db, _dir: dir }
+    }
+
+    /// with_keys returns a database holding n keys in BUCKET, written in
+    /// order, with values of value_size bytes.
+    pub fn with_keys(n: u64, order: Order, value_size: usize) -> Fixture {
+        let fixture = Fixture::new();
+        fixture.put(&keys(n, order), value_size);
+        fixture
+    }
+
+    /// put writes keys to BUCKET with values of value_size bytes, in
+    /// transactions of KEYS_PER_TX keys.
+    pub fn put(&self, keys: &[[u8; 8]], value_size: usize) {
+        let value = value(value_size);
+        for chunk in keys.chunks(KEYS_PER_TX) {
+            self.db
+                .update(|tx| {
+                    let b = tx.create_bucket_if_not_exists(BUCKET)?;
+                    chunk.iter().try_for_each(|key| b.put(key, &value))
+                })
+                .unwrap();
+        }
+    }
+}
+
+/// nested_path returns the names of the buckets leading to the bucket of
+/// depth levels holding key i, spreading the keys over fanout buckets at
+/// each level.
+pub fn nested_path(i: u64, depth: u32, fanout: u64) -> Vec<[u8; 8]> {
+    (0..depth)
+        .map(|level| key(i / fanout.pow(level) % fanout))
+        .collect()
+}
+
+/// create_nested returns the bucket at path below the root, creating the
+/// buckets missing on the way.
+pub fn create_nested<'tx>(tx: &'tx Tx, path: &[[u8; 8]]) -> Result<Bucket<'tx>> {
+    let mut b = tx.create_bucket_if_not_exists(BUCKET)?;
+    for name in path {
+        b = b.create_bucket_if_not_exists(name)?;
+    }
+    Ok(b)
+}
+
+/// nested returns the bucket at path below the root, if it exists.
+pub fn nested<'tx>(tx: &'tx Tx, path: &[[u8; 8]]) -> Option<Bucket<'tx>> {
+    let mut b = tx.bucket(BUCKET)?;
+    for name in path {
+        b = b.bucket(name)?;
+    }
+    Some(b)
+}
+
+// Rng is a splitmix64 generator, good enough to shuffle keys.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // below returns a number from 0 up to n, with a negligible bias for the
+    // sizes benchmarked.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
@@ -0,0 +1,83 @@
+/*
+ * C interface of boltdb-rs, built with the ffi feature:
+ *
+ *     cargo build --release --features ffi
+ *
+ * and linked against target/release/libboltdb_rs.so (or .dylib, .dll).
+ * See src/ffi.rs for the ownership rules of the handles.
+ */
+#ifndef BOLTDB_H
+#define BOLTDB_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+    BOLT_OK = 0,
+    BOLT_NOT_FOUND = 1,
+    BOLT_EXISTS = 2,
+    BOLT_INVALID_ARGUMENT = 3,
+    BOLT_NOT_WRITABLE = 4,
+    BOLT_CLOSED = 5,
+    BOLT_TIMEOUT = 6,
+    BOLT_CORRUPTED = 7,
+    BOLT_IO = 8,
+    BOLT_PANIC = 9,
+    BOLT_OTHER = 10,
+} BoltStatus;
+
+typedef struct BoltDb BoltDb;
+typedef struct BoltTx BoltTx;
+typedef struct BoltCursor BoltCursor;
+
+/* Message of the last failed call of the thread, or NULL. */
+const char *bolt_last_error(void);
+/* BoltError::code of the last failed call of the thread, or 0. */
+uint32_t bolt_last_error_code(void);
+
+BoltStatus bolt_open(const char *path, bool read_only, BoltDb **db);
+BoltStatus bolt_close(BoltDb *db);
+
+BoltStatus bolt_begin(BoltDb *db, bool writable, BoltTx **tx);
+BoltStatus bolt_commit(BoltTx *tx);
+BoltStatus bolt_rollback(BoltTx *tx);
+
+BoltStatus bolt_create_bucket(BoltTx *tx, const uint8_t *name, size_t name_len);
+BoltStatus bolt_delete_bucket(BoltTx *tx, const uint8_t *name, size_t name_len);
+
+BoltStatus bolt_put(BoltTx *tx, const uint8_t *bucket, size_t bucket_len,
+                    const uint8_t *key, size_t key_len,
+                    const uint8_t *value, size_t value_len);
+BoltStatus bolt_get(BoltTx *tx, const uint8_t *bucket, size_t bucket_len,
+                    const uint8_t *key, size_t key_len,
+                    const uint8_t **value, size_t *value_len);
+BoltStatus bolt_delete(BoltTx *tx, const uint8_t *bucket, size_t bucket_len,
+                       const uint8_t *key, size_t key_len);
+
+BoltStatus bolt_cursor_open(BoltTx *tx, const uint8_t *bucket, size_t bucket_len,
+                            BoltCursor **cursor);
+void bolt_cursor_close(BoltCursor *cursor);
+
+/* The key and value out pointers may be NULL. Nested buckets have a NULL value. */
+BoltStatus bolt_cursor_first(BoltCursor *cursor, const uint8_t **key, size_t *key_len,
+                             const uint8_t **value, size_t *value_len);
+BoltStatus bolt_cursor_last(BoltCursor *cursor, const uint8_t **key, size_t *key_len,
+                            const uint8_t **value, size_t *value_len);
+BoltStatus bolt_cursor_next(BoltCursor *cursor, const uint8_t **key, size_t *key_len,
+                            const uint8_t **value, size_t *value_len);
+BoltStatus bolt_cursor_prev(BoltCursor *cursor, const uint8_t **key, size_t *key_len,
+                            const uint8_t **value, size_t *value_len);
+BoltStatus bolt_cursor_seek(BoltCursor *cursor, const uint8_t *seek, size_t seek_len,
+                            const uint8_t **key, size_t *key_len,
+                            const uint8_t **value, size_t *value_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BOLTDB_H */
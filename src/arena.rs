@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use typed_arena::Arena;
+
+use crate::common::page::OwnedPage;
+use crate::node::RawNode;
+
+/// TxArena owns the nodes and dirty pages of a transaction.
+///
+/// Everything allocated here is released at once when the transaction is
+/// dropped, so nodes can point at each other with plain handles instead of
+/// reference counts, and a dirty page never moves while it is being read.
+#[derive(Default)]
+pub(crate) struct TxArena {
+    nodes: Arena<RawNode>,
+    pages: Arena<OwnedPage>,
+}
+
+impl TxArena {
+    /// alloc_node moves a node into the arena and returns a handle to it.
+    pub(crate) fn alloc_node(&self, node: RawNode) -> ArenaRef<RawNode> {
+        ArenaRef(NonNull::from(self.nodes.alloc(node)))
+    }
+
+    /// alloc_page moves a page buffer into the arena and returns a handle to it.
+    pub(crate) fn alloc_page(&self, page: OwnedPage) -> ArenaRef<OwnedPage> {
+        ArenaRef(NonNull::from(self.pages.alloc(page)))
+    }
+
+    /// take_pages moves the page buffers out of the arena. It must only be
+    /// called once no handle to a page is used anymore.
+    pub(crate) fn take_pages(&mut self) -> Vec<OwnedPage> {
+        std::mem::take(&mut self.pages).into_vec()
+    }
+
+    /// len returns the number of nodes and pages allocated so far.
+    pub(crate) fn len(&self) -> (usize, usize) {
+        (self.nodes.len(), self.pages.len())
+    }
+}
+
+impl fmt::Debug for TxArena {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (nodes, pages) = self.len();
+        f.debug_struct("TxArena")
+            .field("nodes", &nodes)
+            .field("pages", &pages)
+            .finish()
+    }
+}
+
+/// PagePool recycles the dirty page buffers of write transactions, so a
+/// busy writer does not allocate and free a buffer for every dirty page.
+///
+/// The buffers are made of u64 words, which keeps them aligned for the page
+/// header like a fresh OwnedPage. Buffers of nodes with overflow pages are
+/// pooled too and handed out to any node they fit, so they end up sized to
+/// the largest nodes. At most max_bytes of buffers are pooled, the rest are
+/// freed.
+#[derive(Debug)]
+pub(crate) struct PagePool {
+    page_size: usize,
+    max_bytes: usize,
+    pooled: Mutex<Pooled>,
+}
+
+#[derive(Debug, Default)]
+struct Pooled {
+    // Buffers of one page.
+    pages: Vec<OwnedPage>,
+    // Buffers of several pages, by capacity.
+    large: BTreeMap<usize, Vec<OwnedPage>>,
+    // Capacity of all the buffers.
+    bytes: usize,
+}
+
+impl PagePool {
+    /// new returns an empty pool of buffers of page_size bytes and more.
+    pub(crate) fn new(page_size: usize, max_bytes: usize) -> PagePool {
+        PagePool {
+            page_size,
+            max_bytes,
+            pooled: Mutex::new(Pooled::default()),
+        }
+    }
+
+    /// get returns a zeroed buffer of count pages, recycled when one is
+    /// pooled. Several pages are taken from the smallest buffer they fit.
+    pub(crate) fn get(&self, count: usize) -> OwnedPage {
+        let size = count * self.page_size;
+        let mut pooled = self.pooled.lock().unwrap();
+        let page = match count {
+            1 => pooled.pages.pop(),
+            _ => match pooled.large.range_mut(size..).next() {
+                Some((&capacity, pages)) => {
+                    let page = pages.pop();
+                    if pages.is_empty() {
+                        pooled.large.remove(&capacity);
+                    }
+                    page
+                }
+                None => None,
+            },
+        };
+        if let Some(page) = &page {
+            pooled.bytes -= page.capacity();
+        }
+        drop(pooled);
+
+        match page {
+            Some(mut page) => {
+                page.resize(size);
+                page.buf_mut().fill(0);
+                page
+            }
+            None => OwnedPage::new(size),
+        }
+    }
+
+    /// put hands buffers back to the pool. Buffers smaller than a page and
+    /// those beyond the cap of the pool are freed.
+    pub(crate) fn put<I: IntoIterator<Item = OwnedPage>>(&self, pages: I) {
+        let mut pooled = self.pooled.lock().unwrap();
+        for page in pages {
+            let capacity = page.capacity();
+            if capacity < self.page_size || pooled.bytes + capacity > self.max_bytes {
+                continue;
+            }
+            pooled.bytes += capacity;
+            match capacity == self.page_size {
+                true => pooled.pages.push(page),
+                false => pooled.large.entry(capacity).or_default().push(page),
+            }
+        }
+    }
+
+    /// len returns the number of pooled buffers.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        let pooled = self.pooled.lock().unwrap();
+        pooled.pages.len() + pooled.large.values().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// ArenaRef is a handle to a value owned by a TxArena.
+///
+/// Handles are only stored inside the transaction that owns the arena (in its
+/// buckets, nodes and dirty page table), so they never outlive the value.
+pub(crate) struct ArenaRef<T>(NonNull<T>);
+
+impl<T> ArenaRef<T> {
+    /// ptr_eq returns whether both handles point at the same value.
+    pub(crate) fn ptr_eq(a: &Self, b: &Self) -> bool {
+        a.0 == b.0
+    }
+
+    /// get_mut returns a mutable reference to the value.
+    ///
+    /// # Safety
+    /// The caller must make sure no other reference to the value is in use.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_mut(&self) -> &mut T {
+        &mut *self.0.as_ptr()
+    }
+}
+
+impl ArenaRef<OwnedPage> {
+    /// slice returns a range of the page buffer as Bytes, without copying
+    /// it. The Bytes must not be read once the transaction owning the arena
+    /// is dropped, which holds for anything stored in its nodes or handed out
+    /// borrowed from it.
+    pub(crate) fn slice(self, range: Range<usize>) -> Bytes {
+        Bytes::from_owner(PageSlice { page: self, range })
+    }
+}
+
+// PageSlice is a range of a page buffer owned by a TxArena.
+struct PageSlice {
+    page: ArenaRef<OwnedPage>,
+    range: Range<usize>,
+}
+
+// SAFETY: the page buffer is only read through the slice, and transactions,
+// which own the arena, stay on the thread that began them.
+unsafe impl Send for PageSlice {}
+
+impl AsRef<[u8]> for PageSlice {
+    fn as_ref(&self) -> &[u8] {
+        &self.page.buf()[self.range.clone()]
+    }
+}
+
+impl<T> Clone for ArenaRef<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ArenaRef<T> {}
+
+impl<T> Deref for ArenaRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArenaRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ensure that arena handles stay valid while more values are allocated.
+    #[test]
+    fn test_arena_handles_are_stable() {
+        let arena = TxArena::default();
+        let first = arena.alloc_page(OwnedPage::new(64));
+        let handles: Vec<_> = (0..1000)
+            .map(|_| arena.alloc_page(OwnedPage::new(64)))
+            .collect();
+
+        assert_eq!(first.buf().len(), 64);
+        assert!(ArenaRef::ptr_eq(&first, &first.clone()));
+        assert!(!ArenaRef::ptr_eq(&first, &handles[0]));
+        assert_eq!(arena.len(), (0, 1001));
+    }
+
+    // Ensure that the pool hands out zeroed single pages and keeps no more
+    // than its cap.
+    #[test]
+    fn test_page_pool() {
+        let pool = PagePool::new(64, 64 * 2);
+        let mut page = pool.get(1);
+        page.buf_mut().fill(0xff);
+        pool.put([
+            page,
+            OwnedPage::new(128),
+            OwnedPage::new(64),
+            OwnedPage::new(64),
+        ]);
+        assert_eq!(pool.len(), 2);
+
+        let page = pool.get(1);
+        assert_eq!(page.buf(), &[0; 64][..]);
+        assert_eq!(pool.len(), 1);
+    }
+
+    // Ensure that buffers of several pages are handed out to the smallest
+    // one they fit, and resized to the pages asked for.
+    #[test]
+    fn test_page_pool_large() {
+        let pool = PagePool::new(64, 64 * 16);
+        let mut page = OwnedPage::new(64 * 4);
+        page.buf_mut().fill(0xff);
+        pool.put([OwnedPage::new(64 * 8), page, OwnedPage::new(64)]);
+
+        let page = pool.get(3);
+        assert_eq!(page.capacity(), 64 * 4);
+        assert_eq!(page.buf(), &[0; 64 * 3][..]);
+        assert_eq!(pool.get(5).capacity(), 64 * 8);
+        assert_eq!(pool.get(2).capacity(), 64 * 2);
+        assert_eq!(pool.len(), 1);
+
+        // The shrunk buffer keeps its capacity when handed back.
+        pool.put([page]);
+        assert_eq!(pool.get(4).size(), 64 * 4);
+    }
+}
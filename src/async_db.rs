@@ -0,0 +1,113 @@
+//! Async facade over DB for services running on tokio.
+
+use std::panic;
+use std::path::PathBuf;
+
+use crate::db::{Options, DB};
+use crate::errors::{BoltError, Result};
+use crate::tx::Tx;
+
+/// AsyncDB wraps a DB for use from async code. Each call runs on the
+/// blocking thread pool of the current tokio runtime, so transactions, which
+/// wait for locks and do file io, never stall the async workers.
+///
+/// The calls must be made from within a tokio runtime. A panic in a
+/// transaction function is resumed in the awaiting task.
+#[derive(Clone)]
+pub struct AsyncDB(DB);
+
+impl AsyncDB {
+    /// open opens a database like DB::open, without blocking the caller.
+    pub async fn open<P: Into<PathBuf>>(path: P, options: Options) -> Result<AsyncDB> {
+        let path = path.into();
+        blocking(move || DB::open(path, options)).await.map(AsyncDB)
+    }
+
+    /// db returns the wrapped database, for the calls that do not block.
+    pub fn db(&self) -> &DB {
+        &self.0
+    }
+
+    /// view executes a function within a managed read-only transaction,
+    /// like DB::view.
+    pub async fn view<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Tx) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.0.clone();
+        blocking(move || db.view(f)).await
+    }
+
+    /// update executes a function within a managed read-write transaction,
+    /// like DB::update.
+    pub async fn update<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Tx) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.0.clone();
+        blocking(move || db.update(f)).await
+    }
+
+    /// close closes the database like DB::close, waiting for the open
+    /// transactions to finish without blocking the caller.
+    pub async fn close(&self) -> Result<()> {
+        let db = self.0.clone();
+        blocking(move || db.close()).await
+    }
+}
+
+impl From<DB> for AsyncDB {
+    fn from(db: DB) -> AsyncDB {
+        AsyncDB(db)
+    }
+}
+
+// blocking runs f on the blocking thread pool and waits for its result.
+async fn blocking<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) => match err.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            Err(_) => Err(BoltError::Unexpected("blocking task was cancelled")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_async_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let db = AsyncDB::open(path, Options::default()).await.unwrap();
+            db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+                .await
+                .unwrap();
+            let value = db
+                .view(|tx| Ok(tx.bucket(b"widgets").unwrap().get(b"foo").unwrap().to_vec()))
+                .await
+                .unwrap();
+            assert_eq!(value, b"bar");
+
+            // Errors of the transaction function are returned as they are.
+            let err = db
+                .update(|_| Err::<(), _>(BoltError::BucketNotFound))
+                .await
+                .unwrap_err();
+            assert_eq!(err, BoltError::BucketNotFound);
+            db.close().await.unwrap();
+        });
+    }
+}
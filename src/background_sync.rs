@@ -0,0 +1,246 @@
+//! Syncing the commits of a NoSync database in the background.
+//!
+//! NoSync commits skip syncing the data file, so a crash loses every commit
+//! since the last sync, or worse. With Options.sync_interval or
+//! Options.sync_every_commits set, a background thread syncs the data file
+//! once commits are left unsynced for that long, or that many of them, which
+//! bounds what a crash can lose without paying for a sync on every commit.
+//! Closing the database syncs the commits left and stops the thread.
+//!
+//! The thread only holds a weak reference to the database, so that dropping
+//! the last handle closes it as usual.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::db::{WeakDB, DB};
+use crate::errors::{BoltError, IoOp, Result};
+
+/// BackgroundSync tells the background thread of a database when to sync.
+#[derive(Debug)]
+pub(crate) struct BackgroundSync {
+    interval: Option<Duration>,
+    every_commits: Option<usize>,
+    state: Mutex<State>,
+    // Signalled when a commit reaches every_commits, and when stopping.
+    cond: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // Number of commits since the last sync.
+    unsynced: usize,
+    stopped: bool,
+}
+
+impl BackgroundSync {
+    /// new returns the syncing schedule of a database, or None if neither
+    /// interval nor every_commits is set.
+    pub(crate) fn new(
+        interval: Option<Duration>,
+        every_commits: Option<usize>,
+    ) -> Option<Arc<BackgroundSync>> {
+        let every_commits = every_commits.filter(|&n| n > 0);
+        if interval.is_none() && every_commits.is_none() {
+            return None;
+        }
+        Some(Arc::new(BackgroundSync {
+            interval,
+            every_commits,
+            state: Mutex::default(),
+            cond: Condvar::new(),
+        }))
+    }
+
+    /// start spawns the thread syncing db.
+    pub(crate) fn start(self: &Arc<Self>, db: &DB) -> Result<()> {
+        let (sync, db) = (self.clone(), WeakDB::from(db));
+        thread::Builder::new()
+            .name("boltdb-sync".to_string())
+            .spawn(move || sync.run(db))
+            .map_err(|err| BoltError::io(IoOp::Other, err))?;
+        Ok(())
+    }
+
+    /// committed records a commit left unsynced.
+    pub(crate) fn committed(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.unsynced += 1;
+        if self.every_commits.is_some_and(|n| state.unsynced >= n) {
+            self.cond.notify_all();
+        }
+    }
+
+    /// stop stops the thread, and returns whether commits are left unsynced.
+    pub(crate) fn stop(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        self.cond.notify_all();
+        std::mem::take(&mut state.unsynced) > 0
+    }
+
+    // run syncs db whenever commits are due, until stopped or db is gone.
+    fn run(&self, db: WeakDB) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let due = self.every_commits.is_some_and(|n| state.unsynced >= n);
+            if !due {
+                state = match self.interval {
+                    Some(interval) => self.cond.wait_timeout(state, interval).unwrap().0,
+                    None => self.cond.wait(state).unwrap(),
+                };
+            }
+            if state.stopped {
+                return;
+            }
+            if state.unsynced == 0 {
+                continue;
+            }
+            let due = self.every_commits.is_some_and(|n| state.unsynced >= n);
+            if !due && self.interval.is_none() {
+                continue;
+            }
+
+            // The commits stay unsynced until the sync is done, so that
+            // closing meanwhile syncs them again rather than not at all.
+            let pending = state.unsynced;
+            drop(state);
+            let db = match db.upgrade() {
+                Some(db) => db,
+                None => return,
+            };
+            let result = db.0.sync();
+            if let Err(err) = &result {
+                db.0.logger().error(
+                    "background sync failed",
+                    &[("path", &db.path().display()), ("error", err)],
+                );
+            }
+            drop(db);
+            state = self.state.lock().unwrap();
+            if result.is_ok() {
+                state.unsynced = state.unsynced.saturating_sub(pending);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::db::Options;
+    use crate::os::Stopwatch;
+    use crate::vfs::{MemoryVfs, Vfs};
+
+    // CountingVfs counts the syncs of the storage.
+    #[derive(Debug, Default)]
+    struct CountingVfs {
+        inner: MemoryVfs,
+        syncs: AtomicUsize,
+    }
+
+    impl Vfs for CountingVfs {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.inner.read_at(buf, offset)
+        }
+
+        fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.inner.write_at(buf, offset)
+        }
+
+        fn sync(&self) -> io::Result<()> {
+            self.syncs.fetch_add(1, Ordering::SeqCst);
+            self.inner.sync()
+        }
+
+        fn len(&self) -> io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn truncate(&self, len: u64) -> io::Result<()> {
+            self.inner.truncate(len)
+        }
+
+        fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()> {
+            self.inner.lock(exclusive, timeout)
+        }
+
+        fn unlock(&self) -> Result<()> {
+            self.inner.unlock()
+        }
+
+        fn map(&self, len: usize) -> io::Result<Bytes> {
+            self.inner.map(len)
+        }
+    }
+
+    fn open(interval: Option<Duration>, every_commits: Option<usize>) -> (Arc<CountingVfs>, DB) {
+        let vfs = Arc::new(CountingVfs::default());
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            no_sync: true,
+            no_grow_sync: true,
+            sync_interval: interval,
+            sync_every_commits: every_commits,
+            ..Default::default()
+        };
+        let db = DB::open("background", options).unwrap();
+        (vfs, db)
+    }
+
+    fn put(db: &DB, i: u32) {
+        db.update(|tx| {
+            tx.create_bucket_if_not_exists(b"widgets")?
+                .put(&i.to_be_bytes(), b"bar")
+        })
+        .unwrap();
+    }
+
+    // wait_for waits up to a second for the storage to be synced n times.
+    fn wait_for(vfs: &CountingVfs, n: usize) -> usize {
+        let start = Stopwatch::start();
+        while vfs.syncs.load(Ordering::SeqCst) < n && start.elapsed() < Duration::from_secs(1) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        vfs.syncs.load(Ordering::SeqCst)
+    }
+
+    // Ensure that commits are synced once the interval has passed, only if
+    // there are any, and on close.
+    #[test]
+    fn test_sync_interval() {
+        let (vfs, db) = open(Some(Duration::from_millis(10)), None);
+        let opened = vfs.syncs.load(Ordering::SeqCst);
+        put(&db, 0);
+        let synced = wait_for(&vfs, opened + 1);
+        assert_eq!(synced, opened + 1);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(vfs.syncs.load(Ordering::SeqCst), synced);
+
+        put(&db, 1);
+        db.close().unwrap();
+        assert!(vfs.syncs.load(Ordering::SeqCst) > synced);
+    }
+
+    // Ensure that commits are synced every so many commits.
+    #[test]
+    fn test_sync_every_commits() {
+        let (vfs, db) = open(None, Some(3));
+        let opened = vfs.syncs.load(Ordering::SeqCst);
+        put(&db, 0);
+        put(&db, 1);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(vfs.syncs.load(Ordering::SeqCst), opened);
+        put(&db, 2);
+        assert_eq!(wait_for(&vfs, opened + 1), opened + 1);
+
+        // Dropping the last handle closes the database as usual.
+        drop(db);
+    }
+}
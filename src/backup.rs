@@ -0,0 +1,479 @@
+//! Full and incremental backups.
+//!
+//! Tx::write_to copies the database as of a transaction. DB::backup_since
+//! then only emits the pages written by the transactions committed after a
+//! previous backup, and DB::apply_backup brings a copy of that backup up to
+//! date with them. An incremental backup is laid out as:
+//!
+//! ```text
+//! magic "bboltinc" | page size u32 | base txid u64 | txid u64 | high water mark u64 | count u64
+//! count * (page id u64 | page)
+//! ```
+//!
+//! All integers are little-endian. The pages are the raw pages of the file,
+//! so the backup of an encrypted database stays encrypted.
+//!
+//! The pages written are only tracked in memory, from the moment the database
+//! is opened, so a backup can only be taken since a transaction committed
+//! after that. Take a full backup otherwise.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::page::{OwnedPage, PgId};
+use crate::common::types::Txid;
+use crate::db::{get_page_size, read_meta_at, DB};
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::os;
+use crate::tx::Tx;
+use crate::vfs::Vfs;
+
+const MAGIC: &[u8; 8] = b"bboltinc";
+
+// How long apply_backup waits for the lock of a database that is open.
+const APPLY_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Largest write of a throttled copy.
+const THROTTLE_CHUNK_SIZE: u64 = 64 * 1024;
+
+// Largest write of an unthrottled copy.
+const COPY_CHUNK_SIZE: usize = 4 << 20;
+
+/// PageTxids remembers the transaction that last wrote each page since the
+/// database was opened.
+#[derive(Debug, Default)]
+pub(crate) struct PageTxids {
+    // transaction the database was opened at
+    since: Txid,
+    pages: HashMap<PgId, Txid>,
+}
+
+impl PageTxids {
+    /// reset forgets the pages written before txid.
+    pub(crate) fn reset(&mut self, txid: Txid) {
+        self.since = txid;
+        self.pages.clear();
+    }
+
+    /// record notes that a page, with its overflow pages, was written by txid.
+    pub(crate) fn record(&mut self, id: PgId, overflow: u32, txid: Txid) {
+        for id in id..=id + overflow as PgId {
+            self.pages.insert(id, txid);
+        }
+    }
+}
+
+impl Tx {
+    /// write_to writes the entire database as of the transaction to a writer,
+    /// as a file that can be opened as is. Returns the number of bytes written.
+    /// The database stays available for reads and writes meanwhile.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<u64> {
+        self.copy_to(w, None)
+    }
+
+    /// copy_file writes the database like write_to to a file at path, which
+    /// is created or truncated, and syncs it with the sync strategy of the
+    /// database. Returns the number of bytes written.
+    pub fn copy_file<P: AsRef<Path>>(&self, path: P) -> Result<u64> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .op(IoOp::Open)?;
+        let mut w = BufWriter::new(file);
+        let n = self.write_to(&mut w)?;
+        let file = w
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .op(IoOp::Write)?;
+        let strategy = self.db()?.0.sync_strategy();
+        file.sync_with(strategy).op(IoOp::Sync)?;
+        Ok(n)
+    }
+
+    /// write_to_throttled writes the database like write_to, at no more than
+    /// bytes_per_sec on average, so that copying a large database does not
+    /// take up all the disk bandwidth the writer needs.
+    pub fn write_to_throttled<W: Write>(&self, w: &mut W, bytes_per_sec: u64) -> Result<u64> {
+        if bytes_per_sec == 0 {
+            return Err(BoltError::Config(
+                "bytes_per_sec must be positive".to_string(),
+            ));
+        }
+        self.copy_to(w, Some(Throttle::new(bytes_per_sec)))
+    }
+
+    // copy_to writes the database to w, throttled if a throttle is given.
+    fn copy_to<W: Write>(&self, w: &mut W, mut throttle: Option<Throttle>) -> Result<u64> {
+        if self.writable() {
+            return Err(BoltError::Unexpected(
+                "write_to requires a read-only transaction",
+            ));
+        }
+        let page_size = self.db()?.0.page_size();
+
+        // Both meta pages are written from the meta of the transaction.
+        for id in 0..2 {
+            w.write_all(self.meta_page(id)?.buf())?;
+        }
+
+        // The pages are copied in chunks, so that a database read without
+        // mmap is not read into memory all at once.
+        let end = self.meta().pgid() as usize * page_size;
+        let chunk_size = throttle
+            .as_ref()
+            .map_or(COPY_CHUNK_SIZE, |throttle| throttle.chunk_size());
+        let mut pos = 2 * page_size;
+        while pos < end {
+            let chunk = self.data(pos, (pos + chunk_size).min(end))?;
+            w.write_all(&chunk)?;
+            if let Some(throttle) = throttle.as_mut() {
+                throttle.wait(chunk.len());
+            }
+            pos += chunk.len();
+        }
+        Ok(end as u64)
+    }
+
+    // meta_page returns a meta page with the meta of the transaction.
+    fn meta_page(&self, id: PgId) -> Result<OwnedPage> {
+        let db = self.db()?;
+        let mut page = OwnedPage::new(db.0.page_size());
+        self.meta().write(&mut page)?;
+        page.set_id(id);
+        db.0.crypt().write_verifier(page.buf_mut())?;
+        Ok(page)
+    }
+}
+
+// Throttle paces writes to an average number of bytes per second.
+struct Throttle {
+    bytes_per_sec: u64,
+    start: Instant,
+    written: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Throttle {
+        Throttle {
+            bytes_per_sec,
+            start: Instant::now(),
+            written: 0,
+        }
+    }
+
+    // chunk_size returns how much to write at once, small enough for the
+    // pace to stay even at low rates.
+    fn chunk_size(&self) -> usize {
+        self.bytes_per_sec.clamp(1, THROTTLE_CHUNK_SIZE) as usize
+    }
+
+    // wait sleeps until n more written bytes are within the rate.
+    fn wait(&mut self, n: usize) {
+        self.written += n as u64;
+        let due = Duration::from_secs_f64(self.written as f64 / self.bytes_per_sec as f64);
+        if let Some(ahead) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
+
+impl DB {
+    /// backup_since writes the pages changed by the transactions committed
+    /// after txid to a writer, for DB::apply_backup to update a backup of
+    /// txid with. txid is the id of the transaction a full backup was written
+    /// from, or the id returned by the previous incremental backup.
+    ///
+    /// Returns the id of the transaction the backup brings the database to.
+    /// Fails with BoltError::Backup if the changes since txid are unknown
+    /// because the database was opened after it.
+    pub fn backup_since<W: Write>(&self, txid: u64, w: &mut W) -> Result<u64> {
+        self.view(|tx| {
+            let page_size = self.0.page_size();
+            let pgid = tx.meta().pgid();
+            let mut ids: Vec<PgId> = {
+                let written = self.0.written.lock().unwrap();
+                if txid < written.since || txid > tx.id() {
+                    return Err(BoltError::Backup(format!(
+                        "changes since txid {} are not tracked, since {}",
+                        txid, written.since
+                    )));
+                }
+                written
+                    .pages
+                    .iter()
+                    .filter(|(&id, &written)| written > txid && id >= 2 && id < pgid)
+                    .map(|(&id, _)| id)
+                    .collect()
+            };
+            ids.sort_unstable();
+
+            w.write_all(MAGIC)?;
+            w.write_all(&(page_size as u32).to_le_bytes())?;
+            for n in [txid, tx.id(), pgid, ids.len() as u64 + 2] {
+                w.write_all(&n.to_le_bytes())?;
+            }
+            for id in 0..2 as PgId {
+                w.write_all(&id.to_le_bytes())?;
+                w.write_all(tx.meta_page(id)?.buf())?;
+            }
+            for id in ids {
+                let pos = id as usize * page_size;
+                w.write_all(&id.to_le_bytes())?;
+                w.write_all(&tx.data(pos, pos + page_size)?)?;
+            }
+            Ok(tx.id())
+        })
+    }
+
+    /// apply_backup applies an incremental backup written by backup_since to
+    /// the database file at path, which has to be a backup of the transaction
+    /// the incremental backup was taken since. The database must not be open.
+    ///
+    /// Returns the id of the transaction the database is at afterwards.
+    pub fn apply_backup<P: AsRef<Path>, R: Read>(path: P, r: &mut R) -> Result<u64> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .op(IoOp::Open)?;
+        os::flock(&file, true, APPLY_LOCK_TIMEOUT)?;
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic).op(IoOp::Read)?;
+        if &magic != MAGIC {
+            return Err(BoltError::Backup("not an incremental backup".to_string()));
+        }
+        let page_size = read_u32(r)? as usize;
+        let (base, txid, pgid, count) = (read_u64(r)?, read_u64(r)?, read_u64(r)?, read_u64(r)?);
+
+        if get_page_size(&file, file.metadata()?.len() as usize)? != page_size {
+            return Err(BoltError::Backup("page size mismatch".to_string()));
+        }
+        let current = [0, page_size as u64]
+            .iter()
+            .filter_map(|&pos| read_meta_at(&file, pos).ok())
+            .filter(|meta| meta.validate().is_ok())
+            .map(|meta| meta.txid())
+            .max()
+            .ok_or(BoltError::Invalid)?;
+        if current != base {
+            return Err(BoltError::Backup(format!(
+                "backup is since txid {}, the database is at {}",
+                base, current
+            )));
+        }
+
+        // Write the meta pages last. The pages before them may already have
+        // been in use at base, so a backup interrupted midway has to be
+        // restored from scratch either way.
+        let mut metas = Vec::new();
+        for _ in 0..count {
+            let id = read_u64(r)?;
+            let mut page = vec![0u8; page_size];
+            r.read_exact(&mut page).op(IoOp::Read)?;
+            if id < 2 {
+                metas.push((id, page));
+            } else if id < pgid {
+                os::write_all_at(&file, &page, id * page_size as u64)
+                    .op(IoOp::Write)
+                    .map_err(|err| err.with_pgid(id))?;
+            } else {
+                return Err(BoltError::Backup(format!(
+                    "page {} above high water mark {}",
+                    id, pgid
+                )));
+            }
+        }
+        file.set_len(pgid * page_size as u64).op(IoOp::Resize)?;
+        os::fdatasync(&file)?;
+        for (id, page) in metas {
+            os::write_all_at(&file, &page, id * page_size as u64)
+                .op(IoOp::Write)
+                .map_err(|err| err.with_pgid(id))?;
+        }
+        os::fdatasync(&file)?;
+        Ok(txid)
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf).op(IoOp::Read)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf).op(IoOp::Read)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+    use crate::vfs::SyncStrategy;
+
+    #[test]
+    fn test_write_to_throttled() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        put(&db, 0, 200);
+
+        db.view(|tx| {
+            let mut full = Vec::new();
+            tx.write_to(&mut full)?;
+
+            let rate = full.len() as u64 * 4;
+            let start = Instant::now();
+            let mut throttled = Vec::new();
+            assert_eq!(
+                tx.write_to_throttled(&mut throttled, rate)?,
+                full.len() as u64
+            );
+            assert!(start.elapsed() >= Duration::from_millis(150));
+            assert_eq!(throttled, full);
+
+            assert!(matches!(
+                tx.write_to_throttled(&mut Vec::new(), 0),
+                Err(BoltError::Config(_))
+            ));
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    // Ensure that a copied file opens with the data of the transaction.
+    #[test]
+    fn test_copy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let copy = dir.path().join("copy");
+        let options = Options {
+            sync_strategy: SyncStrategy::Fsync,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        put(&db, 0, 200);
+
+        let n = db.view(|tx| tx.copy_file(&copy)).unwrap();
+        assert_eq!(n, std::fs::metadata(&copy).unwrap().len());
+
+        let copied = DB::open(&copy, Options::default()).unwrap();
+        copied
+            .view(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.get(b"00000199"), Some(&[0x5a; 100][..]));
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    fn put(db: &DB, from: u32, to: u32) {
+        db.update(|tx| {
+            let b = tx.create_bucket_if_not_exists(b"widgets")?;
+            for i in from..to {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_backup_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, copy) = (dir.path().join("db"), dir.path().join("copy"));
+        let db = DB::open(&path, Options::default()).unwrap();
+        put(&db, 0, 5000);
+
+        // Take a full backup, then two incremental ones.
+        let mut full = Vec::new();
+        let base = db
+            .view(|tx| {
+                tx.write_to(&mut full)?;
+                Ok(tx.id())
+            })
+            .unwrap();
+        std::fs::write(&copy, &full).unwrap();
+
+        let mut incrementals = Vec::new();
+        let mut since = base;
+        for i in 0..2 {
+            put(&db, 5000 + i * 10, 5010 + i * 10);
+            db.update(|tx| tx.bucket(b"widgets").unwrap().delete(b"00000000"))
+                .unwrap();
+            let mut buf = Vec::new();
+            since = db.backup_since(since, &mut buf).unwrap();
+            assert!(buf.len() < full.len() / 10);
+            incrementals.push(buf);
+        }
+        assert_eq!(since, db.view(|tx| Ok(tx.id())).unwrap());
+
+        // Incremental backups only apply in order.
+        assert!(matches!(
+            DB::apply_backup(&copy, &mut &incrementals[1][..]),
+            Err(BoltError::Backup(_))
+        ));
+        for buf in &incrementals {
+            DB::apply_backup(&copy, &mut &buf[..]).unwrap();
+        }
+
+        let restored = DB::open(&copy, Options::default()).unwrap();
+        restored
+            .view(|tx| {
+                assert_eq!(tx.id(), since);
+                assert_eq!(tx.check()?.errors, vec![]);
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.get(b"00000000"), None);
+                assert_eq!(b.get(b"00005019"), Some(&[0x5a; 100][..]));
+                assert_eq!(b.stats()?.key_n, 5019);
+                Ok(())
+            })
+            .unwrap();
+        restored.close().unwrap();
+        db.close().unwrap();
+
+        // Changes made before the database was opened are unknown.
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert!(matches!(
+            db.backup_since(base, &mut Vec::new()),
+            Err(BoltError::Backup(_))
+        ));
+        db.close().unwrap();
+    }
+
+    // Ensure that the pages of a commit failing to write or sync them are
+    // not recorded as written, so backups only track pages that are on disk.
+    #[test]
+    #[cfg(feature = "test-utils")]
+    fn test_backup_failed_commit() {
+        use crate::fault::{Fault, FaultVfs};
+        use crate::vfs::MemoryVfs;
+        use std::sync::Arc;
+
+        let vfs = Arc::new(FaultVfs::new(Arc::new(MemoryVfs::new())));
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            ..Default::default()
+        };
+        let db = DB::open("backup", options).unwrap();
+        put(&db, 0, 100);
+        let recorded = || db.0.written.lock().unwrap().pages.clone();
+        let before = recorded();
+
+        for (op, fault) in [(IoOp::Write, Fault::Nth(2)), (IoOp::Sync, Fault::Once)] {
+            vfs.inject(op, fault);
+            let result = db.update(|tx| tx.bucket(b"widgets").unwrap().put(b"foo", b"bar"));
+            assert!(matches!(result, Err(BoltError::Io { .. })));
+            assert_eq!(recorded(), before);
+        }
+        db.close().unwrap();
+    }
+}
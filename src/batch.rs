@@ -0,0 +1,300 @@
+//! Batching the writes of concurrent callers.
+//!
+//! DB::batch runs the functions of concurrent callers in one write
+//! transaction, so that they share its commit and sync. The first caller to
+//! join a batch waits up to the max batch delay for others; a batch runs at
+//! once when it reaches the max batch size. Both can be changed at runtime,
+//! to tune how writes are coalesced under load.
+//!
+//! As in Go bbolt, a function failing fails the batch, which is then run
+//! again without it, and the failed function is run again alone by its
+//! caller. Functions may therefore be called more than once and must be
+//! idempotent.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::os::Stopwatch;
+use crate::tx::Tx;
+
+/// DEFAULT_MAX_BATCH_SIZE is the number of calls DB::batch runs in one
+/// transaction at most unless Options.max_batch_size says otherwise.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+
+/// DEFAULT_MAX_BATCH_DELAY is how long DB::batch waits for more calls
+/// unless Options.max_batch_delay says otherwise.
+pub const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(10);
+
+type BatchFn = Box<dyn Fn(&Tx) -> Result<()> + Send>;
+
+// Call is a function waiting in a batch, with where to send its outcome.
+struct Call {
+    f: BatchFn,
+    done: Sender<Outcome>,
+}
+
+enum Outcome {
+    // The batch ran, with the result of its commit.
+    Done(Result<()>),
+    // The function failed the batch and has to run alone.
+    Solo(BatchFn),
+}
+
+/// Batcher collects the calls of DB::batch into batches.
+#[derive(Debug)]
+pub(crate) struct Batcher {
+    max_size: AtomicUsize,
+    max_delay: AtomicU64, // in nanoseconds
+    state: Mutex<Pending>,
+    // Signalled when a batch is taken to run.
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct Pending {
+    calls: Vec<Call>,
+    // Incremented each time a batch is taken to run.
+    generation: u64,
+}
+
+impl std::fmt::Debug for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pending")
+            .field("calls", &self.calls.len())
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl Batcher {
+    /// new returns a batcher with the given limits.
+    pub(crate) fn new(max_size: usize, max_delay: Duration) -> Batcher {
+        Batcher {
+            max_size: AtomicUsize::new(max_size),
+            max_delay: AtomicU64::new(max_delay.as_nanos() as u64),
+            state: Mutex::new(Pending::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn max_size(&self) -> usize {
+        self.max_size.load(Ordering::Acquire)
+    }
+
+    fn max_delay(&self) -> Duration {
+        Duration::from_nanos(self.max_delay.load(Ordering::Acquire))
+    }
+
+    // take removes the pending calls, for the caller to run them.
+    fn take(&self, pending: &mut Pending) -> Vec<Call> {
+        pending.generation += 1;
+        self.cond.notify_all();
+        std::mem::take(&mut pending.calls)
+    }
+}
+
+impl DB {
+    /// batch calls f as part of a batch. It behaves like update, except
+    /// that concurrent batch calls are combined into a single write
+    /// transaction, which raises the throughput of many small writes.
+    ///
+    /// f may be called more than once, whether or not the batch succeeds,
+    /// so it must be idempotent. Its side effects must only take effect
+    /// once batch returns successfully. A panic in f is raised in the
+    /// caller that passed it.
+    ///
+    /// Batches gain nothing when a single caller writes, which only delays
+    /// its writes by the max batch delay.
+    pub fn batch<F>(&self, f: F) -> Result<()>
+    where
+        F: Fn(&Tx) -> Result<()> + Send + 'static,
+    {
+        let batcher = self.0.batcher();
+        let (done, outcome) = mpsc::channel();
+        let mut pending = batcher.state.lock().unwrap();
+        pending.calls.push(Call {
+            f: Box::new(f),
+            done,
+        });
+
+        let calls = if pending.calls.len() >= batcher.max_size() {
+            Some(batcher.take(&mut pending))
+        } else if pending.calls.len() == 1 {
+            // The first caller runs the batch once the delay has passed,
+            // unless it filled up and was taken by another caller before.
+            let generation = pending.generation;
+            let (start, max_delay) = (Stopwatch::start(), batcher.max_delay());
+            loop {
+                let elapsed = start.elapsed();
+                if pending.generation != generation {
+                    break None;
+                }
+                if elapsed >= max_delay || pending.calls.len() >= batcher.max_size() {
+                    break Some(batcher.take(&mut pending));
+                }
+                let timeout = max_delay - elapsed;
+                pending = batcher.cond.wait_timeout(pending, timeout).unwrap().0;
+            }
+        } else {
+            None
+        };
+        drop(pending);
+
+        if let Some(calls) = calls {
+            run(self, calls);
+        }
+        match outcome.recv() {
+            Ok(Outcome::Done(result)) => result,
+            Ok(Outcome::Solo(f)) => self.update(|tx| f(tx)),
+            Err(_) => Err(BoltError::Unexpected("batch dropped without running")),
+        }
+    }
+
+    /// max_batch_size returns the number of calls batch runs in one
+    /// transaction at most.
+    pub fn max_batch_size(&self) -> usize {
+        self.0.batcher().max_size()
+    }
+
+    /// set_max_batch_size changes the number of calls batch runs in one
+    /// transaction at most. A pending batch that reaches it runs at once.
+    /// Zero or one disables batching.
+    pub fn set_max_batch_size(&self, max_size: usize) {
+        let batcher = self.0.batcher();
+        batcher.max_size.store(max_size, Ordering::Release);
+        batcher.cond.notify_all();
+    }
+
+    /// max_batch_delay returns how long batch waits for more calls.
+    pub fn max_batch_delay(&self) -> Duration {
+        self.0.batcher().max_delay()
+    }
+
+    /// set_max_batch_delay changes how long batch waits for more calls
+    /// before running a batch. Batches pending already keep waiting as long
+    /// as they were going to. Zero disables batching.
+    pub fn set_max_batch_delay(&self, max_delay: Duration) {
+        let nanos = max_delay.as_nanos().min(u64::MAX as u128) as u64;
+        self.0.batcher().max_delay.store(nanos, Ordering::Release);
+    }
+}
+
+// run runs a batch of calls in one write transaction, and sends them the
+// outcome. A call failing is removed and told to run alone, and the others
+// are run again.
+fn run(db: &DB, mut calls: Vec<Call>) {
+    while !calls.is_empty() {
+        let mut failed = None;
+        let result = db.update(|tx| {
+            for (i, call) in calls.iter().enumerate() {
+                // A panic fails the call like an error, and is raised again
+                // when its caller runs it alone.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| (call.f)(tx)))
+                    .unwrap_or(Err(BoltError::Unexpected("batch function panicked")));
+                if let Err(err) = result {
+                    failed = Some(i);
+                    return Err(err);
+                }
+            }
+            Ok(())
+        });
+
+        if let Some(i) = failed {
+            let call = calls.swap_remove(i);
+            let _ = call.done.send(Outcome::Solo(call.f));
+            continue;
+        }
+        // Success, or an error of the database, is shared by every call.
+        for call in calls.drain(..) {
+            let _ = call.done.send(Outcome::Done(result.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::db::Options;
+
+    fn open() -> (tempfile::TempDir, Arc<DB>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets").map(|_| ()))
+            .unwrap();
+        (dir, Arc::new(db))
+    }
+
+    fn txid(db: &DB) -> u64 {
+        db.view(|tx| Ok(tx.id())).unwrap()
+    }
+
+    // Ensure that concurrent calls share one transaction once the batch is
+    // full, and that a failing call only fails its own caller.
+    #[test]
+    fn test_batch() {
+        let (_dir, db) = open();
+        db.set_max_batch_size(4);
+        db.set_max_batch_delay(Duration::from_secs(3600));
+        assert_eq!(db.max_batch_size(), 4);
+
+        let before = txid(&db);
+        let handles: Vec<_> = (0..4u8)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.batch(move |tx| {
+                        if i == 3 {
+                            return Err(BoltError::KeyRequired);
+                        }
+                        tx.bucket(b"widgets").unwrap().put(&[i], &[i])
+                    })
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert!(results[..3].iter().all(|result| result.is_ok()));
+        assert!(matches!(results[3], Err(BoltError::KeyRequired)));
+        // The batch failed once, then ran without the failing call.
+        assert_eq!(txid(&db), before + 1);
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.stats()?.key_n, 3);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a lone call runs once the delay has passed, and that a
+    // panic reaches its caller.
+    #[test]
+    fn test_batch_delay() {
+        let (_dir, db) = open();
+        db.set_max_batch_delay(Duration::from_millis(1));
+        db.batch(|tx| tx.bucket(b"widgets").unwrap().put(b"foo", b"bar"))
+            .unwrap();
+        db.view(|tx| {
+            assert_eq!(
+                tx.bucket(b"widgets").unwrap().get(b"foo"),
+                Some(&b"bar"[..])
+            );
+            Ok(())
+        })
+        .unwrap();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            db.batch(|_| panic!("boom")).unwrap();
+        }));
+        assert!(result.is_err());
+        db.batch(|tx| tx.bucket(b"widgets").unwrap().delete(b"foo"))
+            .unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+use boltdb_rs::{BoltError, Options, Result, TxStats, DB};
+use clap::{Args, ValueEnum};
+
+const BENCH_BUCKET: &[u8] = b"bench";
+
+/// Mode selects the order in which keys are written or read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Mode {
+    /// keys in ascending order
+    #[default]
+    Seq,
+    /// keys in random order
+    Rnd,
+}
+
+/// BenchOptions describes the workload run by the bench subcommand.
+#[derive(Debug, Args)]
+pub(crate) struct BenchOptions {
+    /// path to the database file, a temporary file is used when omitted
+    #[arg(long)]
+    pub(crate) path: Option<PathBuf>,
+    /// keep the temporary database file once the run is over
+    #[arg(long)]
+    pub(crate) work: bool,
+    /// number of keys to write
+    #[arg(long, default_value_t = 1000)]
+    pub(crate) count: usize,
+    /// number of keys written or read per transaction
+    #[arg(long, default_value_t = 1000)]
+    pub(crate) batch_size: usize,
+    /// size of the keys, in bytes
+    #[arg(long, default_value_t = 8)]
+    pub(crate) key_size: usize,
+    /// size of the values, in bytes
+    #[arg(long, default_value_t = 32)]
+    pub(crate) value_size: usize,
+    /// order in which keys are written
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) write_mode: Mode,
+    /// order in which keys are read
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) read_mode: Mode,
+    /// number of reads performed per key written
+    #[arg(long, default_value_t = 1.0)]
+    pub(crate) read_ratio: f64,
+}
+
+/// run writes and then reads back keys as described by opts and prints the
+/// throughput, the latency percentiles and the transaction stats of the run.
+pub(crate) fn run(opts: &BenchOptions, out: &mut dyn Write) -> Result<()> {
+    if opts.batch_size == 0 {
+        return Err(BoltError::Config("batch-size must be positive".to_string()));
+    }
+    if opts.key_size == 0 {
+        return Err(BoltError::Config("key-size must be positive".to_string()));
+    }
+    if opts.read_ratio.is_nan() || opts.read_ratio < 0.0 {
+        return Err(BoltError::Config(
+            "read-ratio must not be negative".to_string(),
+        ));
+    }
+
+    // Use a temporary file unless a path is specified.
+    let temporary = opts.path.is_none();
+    let path = opts
+        .path
+        .clone()
+        .unwrap_or_else(|| env::temp_dir().join(format!("bolt-bench-{}", process::id())));
+
+    let db = DB::open(&path, Options::default())?;
+    let result = run_workload(&db, opts, out);
+    db.close()?;
+
+    if temporary && !opts.work {
+        fs::remove_file(&path)?;
+    } else {
+        writeln!(out, "work: {}", path.display())?;
+    }
+    result
+}
+
+fn run_workload(db: &DB, opts: &BenchOptions, out: &mut dyn Write) -> Result<()> {
+    let value = vec![0u8; opts.value_size];
+    let mut keys = Vec::with_capacity(opts.count);
+
+    // Write the keys, one transaction per batch.
+    let mut latencies = Vec::new();
+    let start = Instant::now();
+    while keys.len() < opts.count {
+        let n = opts.batch_size.min(opts.count - keys.len());
+        let t = Instant::now();
+        db.update(|tx| {
+            let b = tx.create_bucket_if_not_exists(BENCH_BUCKET)?;
+            for _ in 0..n {
+                let id = match opts.write_mode {
+                    Mode::Seq => keys.len() as u64,
+                    Mode::Rnd => fastrand::u64(..),
+                };
+                let key = make_key(id, opts.key_size);
+                b.put(&key, &value)?;
+                keys.push(key);
+            }
+            Ok(())
+        })?;
+        latencies.push(t.elapsed());
+    }
+    report(
+        out,
+        "Write",
+        keys.len(),
+        start.elapsed(),
+        "batch",
+        latencies,
+    )?;
+
+    // Read the keys back, one transaction per batch.
+    let reads = if keys.is_empty() {
+        0
+    } else {
+        (keys.len() as f64 * opts.read_ratio).round() as usize
+    };
+    let mut latencies = Vec::with_capacity(reads);
+    let start = Instant::now();
+    while latencies.len() < reads {
+        let n = opts.batch_size.min(reads - latencies.len());
+        db.view(|tx| {
+            let b = tx.bucket(BENCH_BUCKET).ok_or(BoltError::BucketNotFound)?;
+            for _ in 0..n {
+                let key = match opts.read_mode {
+                    Mode::Seq => &keys[latencies.len() % keys.len()],
+                    Mode::Rnd => &keys[fastrand::usize(..keys.len())],
+                };
+                let t = Instant::now();
+                if b.get(key).is_none() {
+                    return Err(BoltError::Unexpected("bench: key not found"));
+                }
+                latencies.push(t.elapsed());
+            }
+            Ok(())
+        })?;
+    }
+    report(out, "Read", reads, start.elapsed(), "op", latencies)?;
+
+    write_tx_stats(out, &db.stats().tx_stats)
+}
+
+/// make_key encodes id as a big-endian key of size bytes. Longer keys are
+/// padded with leading zeros, shorter ones keep the low bytes of the id.
+fn make_key(id: u64, size: usize) -> Vec<u8> {
+    let mut key = vec![0u8; size];
+    let id = id.to_be_bytes();
+    let n = size.min(id.len());
+    key[size - n..].copy_from_slice(&id[id.len() - n..]);
+    key
+}
+
+fn report(
+    out: &mut dyn Write,
+    name: &str,
+    ops: usize,
+    elapsed: Duration,
+    unit: &str,
+    mut latencies: Vec<Duration>,
+) -> Result<()> {
+    let per_op = elapsed.checked_div(ops as u32).unwrap_or_default();
+    let ops_per_sec = if elapsed.is_zero() {
+        0
+    } else {
+        (ops as f64 / elapsed.as_secs_f64()) as u64
+    };
+    writeln!(
+        out,
+        "# {}\t{}(ops)\t{:?}\t({:?}/op)\t({} op/sec)",
+        name, ops, elapsed, per_op, ops_per_sec
+    )?;
+
+    latencies.sort();
+    writeln!(
+        out,
+        "# {} latency per {}\tp50={:?}\tp90={:?}\tp99={:?}\tmax={:?}",
+        name,
+        unit,
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.90),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default()
+    )?;
+    Ok(())
+}
+
+/// percentile returns the p-th percentile of sorted latencies.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn write_tx_stats(out: &mut dyn Write, s: &TxStats) -> Result<()> {
+    writeln!(out, "# TxStats")?;
+    writeln!(out, "\tPage Count: {}", s.page_count)?;
+    writeln!(out, "\tPage Alloc: {}", s.page_alloc)?;
+    writeln!(out, "\tCursor Count: {}", s.cursor_count)?;
+    writeln!(out, "\tNode Count: {}", s.node_count)?;
+    writeln!(out, "\tNode Deref: {}", s.node_deref)?;
+    writeln!(out, "\tRebalance: {} ({:?})", s.rebalance, s.rebalance_time)?;
+    writeln!(out, "\tSplit: {}", s.split)?;
+    writeln!(out, "\tSpill: {} ({:?})", s.spill, s.spill_time)?;
+    writeln!(out, "\tWrite: {} ({:?})", s.write, s.write_time)?;
+    writeln!(out, "\tSync: {:?}", s.sync_time)?;
+    writeln!(out, "\tThrottle: {:?}", s.throttle_time)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(path: PathBuf) -> BenchOptions {
+        BenchOptions {
+            path: Some(path),
+            work: false,
+            count: 500,
+            batch_size: 100,
+            key_size: 16,
+            value_size: 64,
+            write_mode: Mode::Rnd,
+            read_mode: Mode::Rnd,
+            read_ratio: 2.0,
+        }
+    }
+
+    #[test]
+    fn test_bench() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = options(dir.path().join("bench"));
+
+        let mut out = Vec::new();
+        run(&opts, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("# Write\t500(ops)\t"), "{}", out);
+        assert!(out.contains("\n# Write latency per batch\tp50="));
+        assert!(out.contains("\n# Read\t1000(ops)\t"));
+        assert!(out.contains("\n# Read latency per op\tp50="));
+        assert!(out.contains("\n# TxStats\n"));
+        assert!(out.ends_with(&format!("work: {}\n", opts.path.unwrap().display())));
+    }
+
+    #[test]
+    fn test_bench_invalid_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut opts = options(dir.path().join("bench"));
+        opts.batch_size = 0;
+        assert!(matches!(
+            run(&opts, &mut Vec::new()),
+            Err(BoltError::Config(_))
+        ));
+        assert!(!dir.path().join("bench").exists());
+    }
+
+    #[test]
+    fn test_make_key() {
+        assert_eq!(make_key(0x0102, 4), vec![0, 0, 1, 2]);
+        assert_eq!(make_key(0x0102, 10), vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(make_key(0x0102, 1), vec![2]);
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+}
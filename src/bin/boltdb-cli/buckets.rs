@@ -0,0 +1,34 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::Result;
+
+use crate::{format_bytes, open_db};
+
+/// run prints the name of every top-level bucket.
+pub(crate) fn run(path: &Path, out: &mut dyn Write) -> Result<()> {
+    let db = open_db(path)?;
+    db.view(|tx| {
+        tx.for_each(|name, _| {
+            writeln!(out, "{}", format_bytes(name))?;
+            Ok(())
+        })
+    })?;
+    db.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_buckets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let mut out = Vec::new();
+        run(&path, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "widgets\nwoojits\n");
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::{BoltError, Result};
+
+use crate::open_db;
+
+/// run performs a consistency check on the database and prints every
+/// inconsistency found. Returns BoltError::CheckFailed if there is any.
+pub(crate) fn run(path: &Path, out: &mut dyn Write) -> Result<()> {
+    let db = open_db(path)?;
+    let report = db.view(|tx| tx.check())?;
+    db.close()?;
+
+    // Print every error, then a summary of how many there were.
+    for err in &report.errors {
+        writeln!(out, "{}", err)?;
+    }
+    if !report.is_ok() {
+        writeln!(out, "{} errors found", report.errors.len())?;
+        return Err(BoltError::CheckFailed("database is corrupt".to_string()));
+    }
+
+    writeln!(out, "OK")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom};
+
+    use super::*;
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_check_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let mut out = Vec::new();
+        run(&path, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "OK\n");
+    }
+
+    // Ensure that a corrupt database fails the check and every error is printed.
+    #[test]
+    fn test_check_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+        let db = open_db(&path).unwrap();
+        let info = db.info().unwrap();
+        db.close().unwrap();
+
+        // Turn the page of the root bucket into a freelist page.
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        let offset = info.root * info.page_size as u64 + 8;
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&0x10u16.to_le_bytes()).unwrap();
+        drop(file);
+
+        let mut out = Vec::new();
+        let err = run(&path, &mut out).unwrap_err();
+        assert!(matches!(err, BoltError::CheckFailed(_)));
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&format!("page {}: invalid type: freelist", info.root)));
+        assert!(out.ends_with(" errors found\n"));
+    }
+}
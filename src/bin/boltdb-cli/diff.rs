@@ -0,0 +1,89 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::Result;
+
+use crate::{format_bytes, open_db};
+
+/// run prints the keys added, removed and changed from the database at a to
+/// the one at b, followed by their numbers.
+pub(crate) fn run(a: &Path, b: &Path, out: &mut dyn Write) -> Result<()> {
+    let (da, db) = (open_db(a)?, open_db(b)?);
+    let changes = boltdb_rs::diff(&da, &db)?;
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in &changes {
+        let bucket: Vec<String> = change.bucket.iter().map(|n| format_bytes(n)).collect();
+        let key = format_bytes(&change.key);
+        match (&change.old, &change.new) {
+            (None, Some(new)) => {
+                added += 1;
+                writeln!(
+                    out,
+                    "+ {}: {} = {}",
+                    bucket.join("/"),
+                    key,
+                    format_bytes(new)
+                )?;
+            }
+            (Some(_), None) => {
+                removed += 1;
+                writeln!(out, "- {}: {}", bucket.join("/"), key)?;
+            }
+            (old, new) => {
+                changed += 1;
+                let old = format_bytes(old.as_deref().unwrap_or_default());
+                let new = format_bytes(new.as_deref().unwrap_or_default());
+                writeln!(out, "~ {}: {} = {} -> {}", bucket.join("/"), key, old, new)?;
+            }
+        }
+    }
+    writeln!(
+        out,
+        "{} added, {} removed, {} changed",
+        added, removed, changed
+    )?;
+    da.close()?;
+    db.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+    use boltdb_rs::{Options, DB};
+
+    #[test]
+    fn test_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = fixture(&dir);
+        let b = dir.path().join("b");
+        std::fs::copy(&a, &b).unwrap();
+
+        let mut out = Vec::new();
+        run(&a, &b, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0 added, 0 removed, 0 changed\n"
+        );
+
+        let db = DB::open(&b, Options::default()).unwrap();
+        db.update(|tx| {
+            let w = tx.bucket(b"widgets").unwrap();
+            w.delete(b"00000000")?;
+            w.bucket(b"sub").unwrap().put(b"foo", b"baz")?;
+            tx.bucket(b"woojits").unwrap().put(b"new", b"key")
+        })
+        .unwrap();
+        db.close().unwrap();
+
+        let mut out = Vec::new();
+        run(&a, &b, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "- widgets: 00000000\n\
+             ~ widgets/sub: foo = bar -> baz\n\
+             + woojits: new = key\n\
+             1 added, 1 removed, 1 changed\n"
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::Result;
+
+use crate::page::RawPage;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// run prints a hexadecimal dump of a page and its overflow pages. Runs of
+/// identical lines are collapsed into a single "*" line.
+pub(crate) fn run(path: &Path, pgid: u64, out: &mut dyn Write) -> Result<()> {
+    let page = RawPage::read(path, pgid)?;
+    let addr = pgid as usize * page.page_size;
+
+    let mut prev: Option<&[u8]> = None;
+    let mut skipped = false;
+    let lines = page.buf.chunks(BYTES_PER_LINE);
+    let last = lines.len() - 1;
+    for (i, line) in lines.enumerate() {
+        let offset = addr + i * BYTES_PER_LINE;
+
+        // If it's the same as the previous line then print a skip.
+        if prev == Some(line) && i != last {
+            if !skipped {
+                writeln!(out, "{:07x} *", offset)?;
+                skipped = true;
+            }
+        } else {
+            // Print line as hexadecimal in 2-byte groups.
+            write!(out, "{:07x}", offset)?;
+            for group in line.chunks(2) {
+                write!(out, " ")?;
+                for b in group {
+                    write!(out, "{:02x}", b)?;
+                }
+            }
+            writeln!(out)?;
+            skipped = false;
+        }
+        prev = Some(line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let mut out = Vec::new();
+        run(&path, 1, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        let page_size = page_size::get();
+
+        // The header holds the page id (1) and the meta flag (0x04).
+        assert_eq!(
+            lines[0],
+            format!("{:07x} 0100 0000 0000 0000 0400 0000 0000 0000", page_size)
+        );
+        // The zeroed tail of the page is collapsed, except for its last line.
+        assert!(lines[lines.len() - 2].ends_with(" *"));
+        assert!(
+            lines[lines.len() - 1].starts_with(&format!("{:07x} ", 2 * page_size - BYTES_PER_LINE))
+        );
+    }
+}
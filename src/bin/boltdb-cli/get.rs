@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::{BoltError, Result};
+use clap::ValueEnum;
+
+use crate::open_db;
+
+/// Format selects how a value is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    /// hexadecimal, two digits per byte
+    Hex,
+    /// UTF-8 text; values that are not valid UTF-8 are rejected
+    #[default]
+    Utf8,
+}
+
+/// run prints the value of a key in a top-level bucket.
+pub(crate) fn run(
+    path: &Path,
+    bucket: &[u8],
+    key: &[u8],
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let db = open_db(path)?;
+    let value = db.view(|tx| {
+        let b = tx.bucket(bucket).ok_or(BoltError::BucketNotFound)?;
+        b.get(key)
+            .map(|value| value.to_vec())
+            .ok_or(BoltError::Unexpected("key not found"))
+    })?;
+    db.close()?;
+
+    match format {
+        Format::Hex => {
+            let hex: String = value.iter().map(|c| format!("{:02x}", c)).collect();
+            writeln!(out, "{}", hex)?;
+        }
+        Format::Utf8 => {
+            let text = std::str::from_utf8(&value)
+                .map_err(|_| BoltError::Unexpected("value is not valid UTF-8, use --format hex"))?;
+            writeln!(out, "{}", text)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+
+    fn get(path: &Path, bucket: &[u8], key: &[u8], format: Format) -> Result<String> {
+        let mut out = Vec::new();
+        run(path, bucket, key, format, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        assert_eq!(
+            get(&path, b"woojits", b"baz", Format::Utf8).unwrap(),
+            "bat\n"
+        );
+        assert_eq!(
+            get(&path, b"woojits", b"baz", Format::Hex).unwrap(),
+            "626174\n"
+        );
+        assert_eq!(
+            get(&path, b"woojits", b"missing", Format::Utf8),
+            Err(BoltError::Unexpected("key not found"))
+        );
+        assert_eq!(
+            get(&path, b"missing", b"baz", Format::Utf8),
+            Err(BoltError::BucketNotFound)
+        );
+    }
+}
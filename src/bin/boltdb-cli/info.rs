@@ -0,0 +1,42 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::Result;
+
+use crate::open_db;
+
+/// run prints the page size and the active meta page of the database.
+pub(crate) fn run(path: &Path, out: &mut dyn Write) -> Result<()> {
+    let db = open_db(path)?;
+    let info = db.info()?;
+    db.close()?;
+
+    writeln!(out, "Page Size: {}", info.page_size)?;
+    writeln!(out, "Version: {}", info.version)?;
+    writeln!(out, "Flags: 0x{:08x}", info.flags)?;
+    writeln!(out, "Root Page: {}", info.root)?;
+    writeln!(out, "Freelist Page: {}", info.freelist)?;
+    writeln!(out, "High Water Mark: {}", info.pgid)?;
+    writeln!(out, "Transaction ID: {}", info.txid)?;
+    writeln!(out, "Checksum: 0x{:016x}", info.checksum)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let mut out = Vec::new();
+        run(&path, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&format!("Page Size: {}\n", page_size::get())));
+        assert!(out.contains("Version: 2\n"));
+        assert!(out.contains("Transaction ID: 2\n"));
+    }
+}
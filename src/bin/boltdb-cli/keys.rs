@@ -0,0 +1,45 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::{BoltError, Result};
+
+use crate::{format_bytes, open_db};
+
+/// run prints every key of a top-level bucket, including the names of its
+/// nested buckets.
+pub(crate) fn run(path: &Path, bucket: &[u8], out: &mut dyn Write) -> Result<()> {
+    let db = open_db(path)?;
+    db.view(|tx| {
+        let b = tx.bucket(bucket).ok_or(BoltError::BucketNotFound)?;
+        b.for_each(|k, _| {
+            writeln!(out, "{}", format_bytes(k))?;
+            Ok(())
+        })
+    })?;
+    db.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let mut out = Vec::new();
+        run(&path, b"woojits", &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "baz\n");
+
+        let mut out = Vec::new();
+        run(&path, b"widgets", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 201);
+        assert_eq!(out.lines().last(), Some("sub"));
+
+        let err = run(&path, b"missing", &mut Vec::new()).unwrap_err();
+        assert_eq!(err, BoltError::BucketNotFound);
+    }
+}
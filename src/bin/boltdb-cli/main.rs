@@ -0,0 +1,247 @@
+//! boltdb-cli inspects database files created by boltdb-rs.
+//!
+//! Apart from `put`, every subcommand opens the database read-only, so it is
+//! safe to point the tool at a file another process has open for reading.
+//! A process having the file open for writing holds its lock exclusively:
+//! the tool then gives up after LOCK_TIMEOUT rather than waiting for it.
+//! `surgery` only ever modifies a copy of the database.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use boltdb_rs::{BoltError, Options, Result, DB};
+use clap::{Parser, Subcommand};
+
+mod bench;
+mod buckets;
+mod check;
+mod diff;
+mod dump;
+mod get;
+mod info;
+mod keys;
+mod page;
+mod put;
+mod stats;
+mod surgery;
+
+/// LOCK_TIMEOUT is how long to wait for the lock of the database file.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "boltdb-cli",
+    version,
+    about = "Inspect boltdb-rs database files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run a synthetic workload against a database and report its performance.
+    Bench(bench::BenchOptions),
+    /// Print the names of the top-level buckets.
+    Buckets {
+        /// path to the database file
+        path: PathBuf,
+    },
+    /// Print the keys added, removed and changed from one database to another.
+    Diff {
+        /// path to the first database file
+        a: PathBuf,
+        /// path to the second database file
+        b: PathBuf,
+    },
+    /// Print a hexadecimal dump of a page.
+    Dump {
+        /// path to the database file
+        path: PathBuf,
+        /// id of the page
+        pgid: u64,
+    },
+    /// Print the value of a key.
+    Get {
+        /// path to the database file
+        path: PathBuf,
+        /// name of the top-level bucket
+        bucket: String,
+        /// key to look up
+        key: String,
+        /// how to print the value
+        #[arg(long, value_enum, default_value_t)]
+        format: get::Format,
+    },
+    /// Print the page size and the contents of the active meta page.
+    Info {
+        /// path to the database file
+        path: PathBuf,
+    },
+    /// Verify the integrity of the database and print every inconsistency found.
+    Check {
+        /// path to the database file
+        path: PathBuf,
+    },
+    /// Print the keys of a bucket.
+    Keys {
+        /// path to the database file
+        path: PathBuf,
+        /// name of the top-level bucket
+        bucket: String,
+    },
+    /// Print the header and the decoded contents of a page.
+    Page {
+        /// path to the database file
+        path: PathBuf,
+        /// id of the page
+        pgid: u64,
+        /// only print the key and value of the item at this index
+        #[arg(long)]
+        item: Option<usize>,
+    },
+    /// Set the value of a key in an existing bucket.
+    Put {
+        /// path to the database file
+        path: PathBuf,
+        /// name of the top-level bucket
+        bucket: String,
+        /// key to set
+        key: String,
+        /// value to store
+        value: String,
+    },
+    /// Print statistics aggregated over all buckets.
+    Stats {
+        /// path to the database file
+        path: PathBuf,
+        /// only include top-level buckets whose name starts with the prefix
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    /// Repair a damaged database, page by page.
+    Surgery {
+        #[command(subcommand)]
+        command: surgery::SurgeryCommand,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut out = io::stdout().lock();
+    match run(&cli.command, &mut out) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: &Command, out: &mut dyn Write) -> Result<()> {
+    match command {
+        Command::Bench(opts) => bench::run(opts, out),
+        Command::Buckets { path } => buckets::run(path, out),
+        Command::Check { path } => check::run(path, out),
+        Command::Diff { a, b } => diff::run(a, b, out),
+        Command::Dump { path, pgid } => dump::run(path, *pgid, out),
+        Command::Get {
+            path,
+            bucket,
+            key,
+            format,
+        } => get::run(path, bucket.as_bytes(), key.as_bytes(), *format, out),
+        Command::Info { path } => info::run(path, out),
+        Command::Keys { path, bucket } => keys::run(path, bucket.as_bytes(), out),
+        Command::Page { path, pgid, item } => page::run(path, *pgid, *item, out),
+        Command::Put {
+            path,
+            bucket,
+            key,
+            value,
+        } => put::run(path, bucket.as_bytes(), key.as_bytes(), value.as_bytes()),
+        Command::Stats { path, prefix } => stats::run(path, prefix.as_bytes(), out),
+        Command::Surgery { command } => surgery::run(command, out),
+    }
+}
+
+/// open_db opens an existing database file in read-only mode.
+pub(crate) fn open_db(path: &Path) -> Result<DB> {
+    open(path, true)
+}
+
+/// open_db_writable opens an existing database file for writing.
+pub(crate) fn open_db_writable(path: &Path) -> Result<DB> {
+    open(path, false)
+}
+
+fn open(path: &Path, read_only: bool) -> Result<DB> {
+    if !path.exists() {
+        let msg = format!("file not found: {}", path.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+    }
+    let options = Options {
+        read_only,
+        timeout: LOCK_TIMEOUT,
+        ..Default::default()
+    };
+    DB::open(path, options).map_err(|err| match err {
+        BoltError::Timeout => {
+            let msg = format!("database is locked by another process: {}", path.display());
+            io::Error::new(io::ErrorKind::WouldBlock, msg).into()
+        }
+        err => err,
+    })
+}
+
+/// format_bytes formats a key or value for display: printable ASCII is
+/// printed as is, anything else as hexadecimal.
+pub(crate) fn format_bytes(b: &[u8]) -> String {
+    if !b.is_empty() && b.iter().all(|c| (0x20..0x7f).contains(c)) {
+        return String::from_utf8_lossy(b).into_owned();
+    }
+    b.iter().map(|c| format!("{:02x}", c)).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// fixture creates a database with a couple of buckets and returns its path.
+    pub(crate) fn fixture(dir: &tempfile::TempDir) -> PathBuf {
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..200u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x2a; 64])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            tx.create_bucket(b"woojits")?.put(b"baz", b"bat")?;
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+        path
+    }
+
+    // Ensure that a missing file is reported instead of being created.
+    #[test]
+    fn test_open_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing");
+        assert!(open_db(&path).is_err());
+        assert!(open_db_writable(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(b"foo bar"), "foo bar");
+        assert_eq!(format_bytes(&[0x00, 0x2a, 0xff]), "002aff");
+        assert_eq!(format_bytes(b""), "");
+    }
+}
@@ -0,0 +1,323 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use boltdb_rs::{BoltError, Result};
+
+use crate::{format_bytes, open_db};
+
+// Layout of the on-disk structures, see src/common/page.rs.
+const PAGE_HEADER_SIZE: usize = 16;
+const LEAF_ELEMENT_SIZE: usize = 16;
+const BRANCH_ELEMENT_SIZE: usize = 16;
+const BUCKET_LEAF_FLAG: u32 = 0x01;
+
+/// RawPage is a page read straight from the database file, including its
+/// overflow pages. Every accessor checks bounds, so corrupt pages can be
+/// inspected without trusting their contents.
+pub(crate) struct RawPage {
+    pub(crate) buf: Vec<u8>,
+    pub(crate) page_size: usize,
+}
+
+/// Element is a decoded entry of a branch or leaf page.
+pub(crate) struct Element<'a> {
+    pub(crate) flags: u32,
+    pub(crate) pos: u32,
+    /// key is copied if it is stored without the prefix of a prefix page.
+    pub(crate) key: Cow<'a, [u8]>,
+    /// value holds the value of a leaf element; it is empty on branch pages.
+    pub(crate) value: &'a [u8],
+    /// pgid holds the child of a branch element; it is zero on leaf pages.
+    pub(crate) pgid: u64,
+}
+
+impl Element<'_> {
+    pub(crate) fn is_bucket(&self) -> bool {
+        self.flags & BUCKET_LEAF_FLAG != 0
+    }
+}
+
+impl RawPage {
+    /// read reads the page with the given id, and its overflow pages, from
+    /// the database file at path.
+    pub(crate) fn read(path: &Path, pgid: u64) -> Result<RawPage> {
+        let db = open_db(path)?;
+        let page_size = db.info()?.page_size;
+        db.close()?;
+
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let read = |file: &mut File, buf: &mut [u8], pgid: u64| -> Result<()> {
+            let pos = pgid
+                .checked_mul(page_size as u64)
+                .filter(|pos| pos + buf.len() as u64 <= len)
+                .ok_or(BoltError::Unexpected("page not found"))?;
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(buf)?;
+            Ok(())
+        };
+
+        let mut buf = vec![0u8; page_size];
+        read(&mut file, &mut buf, pgid)?;
+        let mut page = RawPage { buf, page_size };
+
+        let overflow = page.overflow() as usize;
+        if overflow > 0 {
+            page.buf.resize((overflow + 1) * page_size, 0);
+            read(&mut file, &mut page.buf, pgid)?;
+        }
+        Ok(page)
+    }
+
+    fn u16_at(&self, off: usize) -> u16 {
+        u16::from_le_bytes(self.buf[off..off + 2].try_into().unwrap())
+    }
+
+    fn u32_at(&self, off: usize) -> u32 {
+        u32::from_le_bytes(self.buf[off..off + 4].try_into().unwrap())
+    }
+
+    fn u64_at(&self, off: usize) -> u64 {
+        u64::from_le_bytes(self.buf[off..off + 8].try_into().unwrap())
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.u64_at(0)
+    }
+
+    pub(crate) fn flags(&self) -> u16 {
+        self.u16_at(8)
+    }
+
+    pub(crate) fn count(&self) -> u16 {
+        self.u16_at(10)
+    }
+
+    pub(crate) fn overflow(&self) -> u32 {
+        self.u32_at(12)
+    }
+
+    pub(crate) fn typ(&self) -> String {
+        match self.flags() {
+            0x01 | 0x21 => "branch".to_string(),
+            0x02 => "leaf".to_string(),
+            0x04 => "meta".to_string(),
+            0x10 => "freelist".to_string(),
+            flags => format!("unknown<{:02x}>", flags),
+        }
+    }
+
+    fn slice(&self, range: Range<usize>) -> Result<&[u8]> {
+        self.buf.get(range).ok_or(BoltError::Invalid)
+    }
+
+    /// elements decodes the element table of a branch or leaf page.
+    /// Returns BoltError::Invalid if an element points past the page.
+    pub(crate) fn elements(&self) -> Result<Vec<Element<'_>>> {
+        let is_leaf = match self.typ().as_str() {
+            "leaf" => true,
+            "branch" => false,
+            _ => return Err(BoltError::Unexpected("page has no elements")),
+        };
+        let elem_size = if is_leaf {
+            LEAF_ELEMENT_SIZE
+        } else {
+            BRANCH_ELEMENT_SIZE
+        };
+
+        let mut elems = Vec::with_capacity(self.count() as usize);
+        for i in 0..self.count() as usize {
+            let off = PAGE_HEADER_SIZE + i * elem_size;
+            self.slice(off..off + elem_size)?;
+            // The position of the key is relative to the element itself.
+            let elem = if is_leaf {
+                let (flags, pos) = (self.u32_at(off), self.u32_at(off + 4));
+                let (ksize, vsize) = (self.u32_at(off + 8), self.u32_at(off + 12));
+                let start = off + pos as usize;
+                let mid = start + ksize as usize;
+                Element {
+                    flags,
+                    pos,
+                    key: Cow::Borrowed(self.slice(start..mid)?),
+                    value: self.slice(mid..mid + vsize as usize)?,
+                    pgid: 0,
+                }
+            } else {
+                let (pos, ksize) = (self.u32_at(off), self.u32_at(off + 4));
+                let start = off + pos as usize;
+                let key = self.slice(start..start + ksize as usize)?;
+                Element {
+                    flags: 0,
+                    pos,
+                    key: match self.key_prefix()? {
+                        [] => Cow::Borrowed(key),
+                        prefix => Cow::Owned([prefix, key].concat()),
+                    },
+                    value: &[],
+                    pgid: self.u64_at(off + 8),
+                }
+            };
+            elems.push(elem);
+        }
+        Ok(elems)
+    }
+
+    /// key_prefix returns the prefix shared by the keys of a branch page
+    /// stored without it, which lies between the element table and the first
+    /// key. It is empty on other pages.
+    fn key_prefix(&self) -> Result<&[u8]> {
+        if self.flags() != 0x21 || self.count() == 0 {
+            return Ok(&[]);
+        }
+        let table = PAGE_HEADER_SIZE + self.count() as usize * BRANCH_ELEMENT_SIZE;
+        let first = PAGE_HEADER_SIZE + self.u32_at(PAGE_HEADER_SIZE) as usize;
+        self.slice(table..first.max(table))
+    }
+
+    /// freelist_ids decodes the page ids stored on a freelist page.
+    pub(crate) fn freelist_ids(&self) -> Result<Vec<u64>> {
+        // A count of 0xFFFF means the real count is stored in the first slot.
+        let (start, count) = match self.count() {
+            0xFFFF => (PAGE_HEADER_SIZE + 8, self.u64_at(PAGE_HEADER_SIZE) as usize),
+            count => (PAGE_HEADER_SIZE, count as usize),
+        };
+        let end = count
+            .checked_mul(8)
+            .and_then(|n| n.checked_add(start))
+            .ok_or(BoltError::Invalid)?;
+        Ok(self
+            .slice(start..end)?
+            .chunks_exact(8)
+            .map(|id| u64::from_le_bytes(id.try_into().unwrap()))
+            .collect())
+    }
+
+    fn write_meta(&self, out: &mut dyn Write) -> Result<()> {
+        let m = PAGE_HEADER_SIZE;
+        self.slice(m..m + 64)?;
+        writeln!(out, "Version:    {}", self.u32_at(m + 4))?;
+        writeln!(out, "Page Size:  {} bytes", self.u32_at(m + 8))?;
+        writeln!(out, "Flags:      {:08x}", self.u32_at(m + 12))?;
+        writeln!(out, "Root:       <pgid={}>", self.u64_at(m + 16))?;
+        writeln!(out, "Freelist:   <pgid={}>", self.u64_at(m + 32))?;
+        writeln!(out, "HWM:        <pgid={}>", self.u64_at(m + 40))?;
+        writeln!(out, "Txn ID:     {}", self.u64_at(m + 48))?;
+        writeln!(out, "Checksum:   {:016x}", self.u64_at(m + 56))?;
+        Ok(())
+    }
+}
+
+/// format_value formats the value of an element: the header of a nested
+/// bucket, the child of a branch element, or the value itself.
+fn format_value(elem: &Element) -> String {
+    if elem.pgid != 0 {
+        return format!("<pgid={}>", elem.pgid);
+    }
+    if elem.is_bucket() && elem.value.len() >= 16 {
+        let root = u64::from_le_bytes(elem.value[..8].try_into().unwrap());
+        let seq = u64::from_le_bytes(elem.value[8..16].try_into().unwrap());
+        return format!("<pgid={},seq={}>", root, seq);
+    }
+    format_bytes(elem.value)
+}
+
+/// run prints the header of a page followed by its decoded contents. When
+/// item is set only the key and value of that element are printed.
+pub(crate) fn run(path: &Path, pgid: u64, item: Option<usize>, out: &mut dyn Write) -> Result<()> {
+    let page = RawPage::read(path, pgid)?;
+
+    if let Some(index) = item {
+        let elems = page.elements()?;
+        let elem = elems
+            .get(index)
+            .ok_or(BoltError::Unexpected("item index out of range"))?;
+        writeln!(out, "Key:   {}", format_bytes(&elem.key))?;
+        writeln!(out, "Value: {}", format_value(elem))?;
+        return Ok(());
+    }
+
+    writeln!(out, "Page ID:    {}", page.id())?;
+    writeln!(out, "Page Type:  {}", page.typ())?;
+    writeln!(out, "Total Size: {} bytes", page.buf.len())?;
+    writeln!(out, "Overflow pages: {}", page.overflow())?;
+
+    match page.typ().as_str() {
+        "meta" => page.write_meta(out)?,
+        "freelist" => {
+            let ids = page.freelist_ids()?;
+            writeln!(out, "Item Count: {}\n", ids.len())?;
+            for id in ids {
+                writeln!(out, "{}", id)?;
+            }
+        }
+        "leaf" | "branch" => {
+            let elems = page.elements()?;
+            writeln!(out, "Item Count: {}\n", elems.len())?;
+            writeln!(out, "Index  Flags  Pos    KSize  VSize  Key: Value")?;
+            for (i, elem) in elems.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{:<6} {:<6} {:<6} {:<6} {:<6} {}: {}",
+                    i,
+                    format!("0x{:02x}", elem.flags),
+                    elem.pos,
+                    elem.key.len(),
+                    elem.value.len(),
+                    format_bytes(&elem.key),
+                    format_value(elem)
+                )?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use boltdb_rs::{Options, DB};
+
+    use super::*;
+    use crate::tests::fixture;
+
+    fn page_output(path: &Path, pgid: u64, item: Option<usize>) -> String {
+        let mut out = Vec::new();
+        run(path, pgid, item, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // Ensure that meta pages and the leaf of a bucket are decoded.
+    #[test]
+    fn test_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let out = page_output(&path, 0, None);
+        assert!(out.starts_with("Page ID:    0\nPage Type:  meta\n"));
+        assert!(out.contains("Version:    2\n"));
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        let root = db.info().unwrap().root;
+        db.close().unwrap();
+        let out = page_output(&path, root, None);
+        assert!(out.contains("Page Type:  leaf\n"));
+        assert!(out.contains("Item Count: 2\n"));
+        assert!(out.contains("woojits: <pgid=0,seq=0>\n"));
+        assert_eq!(
+            page_output(&path, root, Some(0)).lines().next(),
+            Some("Key:   widgets")
+        );
+    }
+
+    // Ensure that pages past the end of the file are reported.
+    #[test]
+    fn test_page_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+        let err = run(&path, 1 << 20, None, &mut Vec::new()).unwrap_err();
+        assert_eq!(err, BoltError::Unexpected("page not found"));
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use boltdb_rs::{BoltError, Result};
+
+use crate::open_db_writable;
+
+/// run sets the value of a key in an existing top-level bucket.
+pub(crate) fn run(path: &Path, bucket: &[u8], key: &[u8], value: &[u8]) -> Result<()> {
+    let db = open_db_writable(path)?;
+    db.update(|tx| {
+        let b = tx.bucket(bucket).ok_or(BoltError::BucketNotFound)?;
+        b.put(key, value)
+    })?;
+    db.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get::{self, Format};
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        run(&path, b"woojits", b"baz", b"qux").unwrap();
+        run(&path, b"woojits", b"new", b"value").unwrap();
+        assert_eq!(
+            run(&path, b"missing", b"key", b"value"),
+            Err(BoltError::BucketNotFound)
+        );
+
+        let mut out = Vec::new();
+        get::run(&path, b"woojits", b"baz", Format::Utf8, &mut out).unwrap();
+        get::run(&path, b"woojits", b"new", Format::Utf8, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "qux\nvalue\n");
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::path::Path;
+
+use boltdb_rs::{BucketStats, Result};
+
+use crate::open_db;
+
+/// run aggregates the stats of every top-level bucket whose name starts with
+/// prefix, including their nested buckets, and prints them.
+pub(crate) fn run(path: &Path, prefix: &[u8], out: &mut dyn Write) -> Result<()> {
+    let db = open_db(path)?;
+    let mut s = BucketStats::default();
+    let mut count = 0;
+    db.view(|tx| {
+        tx.for_each(|name, b| {
+            if name.starts_with(prefix) {
+                s.add(&b.stats()?);
+                count += 1;
+            }
+            Ok(())
+        })
+    })?;
+    db.close()?;
+
+    writeln!(out, "Aggregate statistics for {} buckets\n", count)?;
+
+    writeln!(out, "Page count statistics")?;
+    writeln!(out, "\tNumber of logical branch pages: {}", s.branch_page_n)?;
+    writeln!(
+        out,
+        "\tNumber of physical branch overflow pages: {}",
+        s.branch_overflow_n
+    )?;
+    writeln!(out, "\tNumber of logical leaf pages: {}", s.leaf_page_n)?;
+    writeln!(
+        out,
+        "\tNumber of physical leaf overflow pages: {}",
+        s.leaf_overflow_n
+    )?;
+
+    writeln!(out, "Tree statistics")?;
+    writeln!(out, "\tNumber of keys/value pairs: {}", s.key_n)?;
+    writeln!(out, "\tNumber of levels in B+tree: {}", s.depth)?;
+
+    writeln!(out, "Page size utilization")?;
+    writeln!(
+        out,
+        "\tBytes allocated for physical branch pages: {}",
+        s.branch_alloc
+    )?;
+    writeln!(
+        out,
+        "\tBytes actually used for branch data: {} ({}%)",
+        s.branch_inuse,
+        percent(s.branch_inuse, s.branch_alloc)
+    )?;
+    writeln!(
+        out,
+        "\tBytes allocated for physical leaf pages: {}",
+        s.leaf_alloc
+    )?;
+    writeln!(
+        out,
+        "\tBytes actually used for leaf data: {} ({}%)",
+        s.leaf_inuse,
+        percent(s.leaf_inuse, s.leaf_alloc)
+    )?;
+
+    writeln!(out, "Bucket statistics")?;
+    writeln!(out, "\tTotal number of buckets: {}", s.bucket_n)?;
+    writeln!(
+        out,
+        "\tTotal number on inlined buckets: {} ({}%)",
+        s.inline_bucket_n,
+        percent(s.inline_bucket_n, s.bucket_n)
+    )?;
+    writeln!(
+        out,
+        "\tBytes used for inlined buckets: {} ({}%)",
+        s.inline_bucket_inuse,
+        percent(s.inline_bucket_inuse, s.leaf_inuse)
+    )?;
+
+    writeln!(out, "Rebalancing")?;
+    writeln!(
+        out,
+        "\tNodes merged below (bytes): {}",
+        s.rebalance_threshold
+    )?;
+    Ok(())
+}
+
+// percent returns part as a whole percentage of total, or 0 for an empty total.
+fn percent(part: usize, total: usize) -> usize {
+    match total {
+        0 => 0,
+        _ => part * 100 / total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixture;
+
+    #[test]
+    fn test_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+
+        let mut out = Vec::new();
+        run(&path, b"", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Aggregate statistics for 2 buckets\n"));
+        assert!(out.contains("\tNumber of keys/value pairs: 203\n"));
+        assert!(out.contains("\tTotal number of buckets: 3\n"));
+        assert!(out.contains("\tTotal number on inlined buckets: 2 (66%)\n"));
+        assert!(out.contains("\tNodes merged below (bytes): 1024\n"));
+
+        // Only the buckets matching the prefix are counted.
+        let mut out = Vec::new();
+        run(&path, b"woo", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Aggregate statistics for 1 buckets\n"));
+        assert!(out.contains("\tNumber of keys/value pairs: 1\n"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use boltdb_rs::{surgeon, Result};
+use clap::Subcommand;
+
+/// SurgeryCommand lists the operations of the surgery subcommand. Each one
+/// works on a copy of the database written to --output, leaving the original
+/// file untouched.
+#[derive(Debug, Subcommand)]
+pub(crate) enum SurgeryCommand {
+    /// Remove the freelist from the meta pages, so it is rebuilt on the next open.
+    AbandonFreelist {
+        /// path to the database file
+        path: PathBuf,
+        /// path to write the repaired copy of the database to
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Remove every element of a branch or leaf page.
+    ClearPage {
+        /// path to the database file
+        path: PathBuf,
+        /// path to write the repaired copy of the database to
+        #[arg(long)]
+        output: PathBuf,
+        /// id of the page to clear
+        #[arg(long)]
+        pgid: u64,
+    },
+    /// Copy a page, with its overflow pages, over another page.
+    CopyPage {
+        /// path to the database file
+        path: PathBuf,
+        /// path to write the repaired copy of the database to
+        #[arg(long)]
+        output: PathBuf,
+        /// id of the page to copy
+        #[arg(long)]
+        from_page: u64,
+        /// id of the page to overwrite
+        #[arg(long)]
+        to_page: u64,
+    },
+    /// Revert the meta page to the one of the previous transaction.
+    RevertMeta {
+        /// path to the database file
+        path: PathBuf,
+        /// path to write the repaired copy of the database to
+        #[arg(long)]
+        output: PathBuf,
+    },
+}
+
+/// run copies the database to the output path and operates on the copy.
+pub(crate) fn run(command: &SurgeryCommand, out: &mut dyn Write) -> Result<()> {
+    match command {
+        SurgeryCommand::AbandonFreelist { path, output } => {
+            copy_db(path, output)?;
+            surgeon::abandon_freelist(output)?;
+            writeln!(out, "The freelist was abandoned in both meta pages.")?;
+            writeln!(
+                out,
+                "It may cause some delay on next startup because the whole database \
+                 is scanned to reconstruct the freelist."
+            )?;
+        }
+        SurgeryCommand::ClearPage { path, output, pgid } => {
+            copy_db(path, output)?;
+            let abandoned = surgeon::clear_page(output, *pgid)?;
+            writeln!(out, "The page {} was cleared", pgid)?;
+            if abandoned {
+                writeln!(
+                    out,
+                    "WARNING: The clearing has abandoned some pages that are not yet referenced \
+                     from the freelist. Please consider executing `surgery abandon-freelist`."
+                )?;
+            }
+        }
+        SurgeryCommand::CopyPage {
+            path,
+            output,
+            from_page,
+            to_page,
+        } => {
+            copy_db(path, output)?;
+            surgeon::copy_page(output, *from_page, *to_page)?;
+            writeln!(out, "The page {} was copied to page {}", from_page, to_page)?;
+            writeln!(
+                out,
+                "WARNING: The pages previously reachable from page {} are abandoned. \
+                 Please consider executing `surgery abandon-freelist`.",
+                to_page
+            )?;
+        }
+        SurgeryCommand::RevertMeta { path, output } => {
+            copy_db(path, output)?;
+            surgeon::revert_meta_page(output)?;
+            writeln!(out, "The meta page is reverted.")?;
+        }
+    }
+    Ok(())
+}
+
+/// copy_db copies the database to dst, which must not exist yet.
+fn copy_db(src: &Path, dst: &Path) -> Result<()> {
+    if !src.exists() {
+        let msg = format!("file not found: {}", src.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg).into());
+    }
+    if dst.exists() {
+        let msg = format!("file already exists: {}", dst.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg).into());
+    }
+    fs::copy(src, dst)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use boltdb_rs::{BoltError, Options, DB};
+
+    use super::*;
+    use crate::tests::fixture;
+
+    fn surgery(command: SurgeryCommand) -> Result<String> {
+        let mut out = Vec::new();
+        run(&command, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_revert_meta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+        let output = dir.path().join("output");
+        let txid = |path: &Path| {
+            let db = DB::open(path, Options::default()).unwrap();
+            let txid = db.info().unwrap().txid;
+            db.close().unwrap();
+            txid
+        };
+
+        let command = || SurgeryCommand::RevertMeta {
+            path: path.clone(),
+            output: output.clone(),
+        };
+        assert_eq!(surgery(command()).unwrap(), "The meta page is reverted.\n");
+        assert_eq!(txid(&output), txid(&path) - 1);
+
+        // The output is never overwritten.
+        assert!(matches!(surgery(command()), Err(BoltError::Io { .. })));
+    }
+
+    #[test]
+    fn test_abandon_freelist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+        let output = dir.path().join("output");
+
+        let out = surgery(SurgeryCommand::AbandonFreelist {
+            path,
+            output: output.clone(),
+        })
+        .unwrap();
+        assert!(out.starts_with("The freelist was abandoned in both meta pages.\n"));
+
+        let db = DB::open(&output, Options::default()).unwrap();
+        assert_eq!(db.view(|tx| tx.check()).unwrap().errors, vec![]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_clear_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+        let output = dir.path().join("output");
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        let root = db.info().unwrap().root;
+        db.close().unwrap();
+
+        // The root bucket only holds inline buckets and widgets.
+        let out = surgery(SurgeryCommand::ClearPage {
+            path: path.clone(),
+            output: output.clone(),
+            pgid: root,
+        })
+        .unwrap();
+        assert!(out.starts_with(&format!("The page {} was cleared\nWARNING", root)));
+
+        let db = DB::open(&output, Options::default()).unwrap();
+        db.view(|tx| {
+            assert!(tx.bucket(b"widgets").is_none());
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_copy_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture(&dir);
+        let output = dir.path().join("output");
+
+        let out = surgery(SurgeryCommand::CopyPage {
+            path,
+            output,
+            from_page: 0,
+            to_page: 1,
+        })
+        .unwrap();
+        assert!(out.starts_with("The page 0 was copied to page 1\n"));
+    }
+}
@@ -0,0 +1,338 @@
+//! Tracking the pages borrowed by cursors and nodes, in debug builds.
+//!
+//! Cursors and nodes read keys and values in place from the pages of their
+//! transaction. Those pages stay mapped and unchanged only as long as the
+//! database keeps its side of the deal: a page is not allocated again while
+//! a transaction can reach it, the file is not truncated or remapped below a
+//! page in use, and a transaction is not closed while its cursors are alive.
+//! Breaking it shows up as wrong data or a SIGBUS, far from the cause.
+//!
+//! In debug builds every page a cursor or node reads is recorded until it is
+//! let go, and allocating, truncating or remapping over a recorded page, or
+//! closing a transaction whose cursors still hold pages, panics naming the
+//! page, its borrower and their transaction. In release builds the tracking
+//! compiles to nothing.
+
+#[cfg(debug_assertions)]
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(debug_assertions)]
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::common::page::PgId;
+#[cfg(debug_assertions)]
+use crate::common::types::Txid;
+use crate::tx::Tx;
+
+/// Borrower tells what holds on to a page.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Borrower {
+    /// A cursor, until it moves off the page or is dropped.
+    Cursor,
+    /// A node read from the page, until its transaction is closed.
+    Node,
+}
+
+#[cfg(debug_assertions)]
+impl fmt::Display for Borrower {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Borrower::Cursor => write!(f, "cursor"),
+            Borrower::Node => write!(f, "node"),
+        }
+    }
+}
+
+/// PageBorrows records the pages borrowed from a database.
+#[derive(Debug, Default)]
+pub(crate) struct PageBorrows {
+    #[cfg(debug_assertions)]
+    state: Mutex<State>,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Debug, Default)]
+struct State {
+    // Borrows by the first page of the borrowed range.
+    pages: BTreeMap<PgId, Vec<Borrow>>,
+    // Length of the longest range borrowed so far, to look up overlaps.
+    max_len: u64,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Debug)]
+struct Borrow {
+    // Address of the transaction, which tells transactions with the same
+    // txid apart.
+    tx: usize,
+    txid: Txid,
+    // Number of pages, overflow pages included.
+    len: u64,
+    by: Borrower,
+    // Number of borrows of the range by the same borrower.
+    count: usize,
+}
+
+#[cfg(debug_assertions)]
+impl State {
+    // overlap returns a borrow of a page from start to end, exclusive.
+    fn overlap(&self, start: PgId, end: PgId) -> Option<(PgId, &Borrow)> {
+        let from = start.saturating_sub(self.max_len.saturating_sub(1));
+        self.pages
+            .range(from..end)
+            .flat_map(|(id, borrows)| borrows.iter().map(move |b| (*id, b)))
+            .find(|(id, b)| id + b.len > start)
+    }
+}
+
+impl PageBorrows {
+    /// check_reuse panics if a page from pgid on, for count pages, is
+    /// borrowed, since it is about to be allocated again and overwritten.
+    pub(crate) fn check_reuse(&self, _pgid: PgId, _count: usize) {
+        #[cfg(debug_assertions)]
+        self.check(_pgid, _pgid + _count as PgId, "allocating it again");
+    }
+
+    /// check_truncate panics if a page at or beyond pgid is borrowed, since
+    /// the file or the mmap is about to end before it. what describes the
+    /// operation for the diagnostic.
+    pub(crate) fn check_truncate(&self, _pgid: PgId, _what: &str) {
+        #[cfg(debug_assertions)]
+        self.check(_pgid, PgId::MAX, _what);
+    }
+
+    #[cfg(debug_assertions)]
+    fn check(&self, start: PgId, end: PgId, what: &str) {
+        let state = self.state();
+        let borrow = state.overlap(start, end).map(|(id, b)| (id, b.by, b.txid));
+        drop(state);
+        if let Some((id, by, txid)) = borrow {
+            panic!(
+                "{} would invalidate page {} borrowed by a {} of tx {}",
+                what, id, by, txid
+            );
+        }
+    }
+
+    // state locks the borrows. They stay usable after a panic, which is how
+    // the checks fail, so that the borrows dropped while unwinding can let go.
+    #[cfg(debug_assertions)]
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// close_tx lets go of the pages borrowed by the nodes of a transaction
+    /// being closed. Panics if its cursors still hold pages, which another
+    /// writer may reuse from now on.
+    pub(crate) fn close_tx(&self, _tx: usize) {
+        #[cfg(debug_assertions)]
+        {
+            let mut state = self.state();
+            let cursor = state.pages.iter().find_map(|(id, borrows)| {
+                borrows
+                    .iter()
+                    .find(|b| b.tx == _tx && b.by == Borrower::Cursor)
+                    .map(|b| (*id, b.txid))
+            });
+            if let Some((id, txid)) = cursor {
+                drop(state);
+                panic!(
+                    "closing tx {} would invalidate page {} borrowed by a cursor",
+                    txid, id
+                );
+            }
+            state.pages.retain(|_, borrows| {
+                borrows.retain(|b| b.tx != _tx);
+                !borrows.is_empty()
+            });
+        }
+    }
+
+    /// len returns the number of borrowed ranges.
+    #[cfg(all(test, debug_assertions))]
+    pub(crate) fn len(&self) -> usize {
+        let state = self.state();
+        state.pages.values().map(Vec::len).sum()
+    }
+
+    #[cfg(debug_assertions)]
+    fn borrow(&self, tx: usize, txid: Txid, pgid: PgId, len: u64, by: Borrower) {
+        let mut state = self.state();
+        state.max_len = state.max_len.max(len);
+        let borrows = state.pages.entry(pgid).or_default();
+        match borrows
+            .iter_mut()
+            .find(|b| b.tx == tx && b.by == by && b.len == len)
+        {
+            Some(b) => b.count += 1,
+            None => borrows.push(Borrow {
+                tx,
+                txid,
+                len,
+                by,
+                count: 1,
+            }),
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn release(&self, tx: usize, pgid: PgId, by: Borrower) {
+        let mut state = self.state();
+        if let Some(borrows) = state.pages.get_mut(&pgid) {
+            if let Some(i) = borrows.iter().position(|b| b.tx == tx && b.by == by) {
+                borrows[i].count -= 1;
+                if borrows[i].count == 0 {
+                    borrows.swap_remove(i);
+                }
+            }
+            if borrows.is_empty() {
+                state.pages.remove(&pgid);
+            }
+        }
+    }
+}
+
+/// PageBorrow is the borrow of a page by a cursor, let go when dropped.
+#[derive(Default)]
+pub(crate) struct PageBorrow {
+    #[cfg(debug_assertions)]
+    page: Option<(Arc<PageBorrows>, usize, Txid, PgId, u64)>,
+}
+
+impl Clone for PageBorrow {
+    fn clone(&self) -> Self {
+        #[cfg(debug_assertions)]
+        if let Some((borrows, tx, txid, pgid, len)) = &self.page {
+            borrows.borrow(*tx, *txid, *pgid, *len, Borrower::Cursor);
+        }
+        PageBorrow {
+            #[cfg(debug_assertions)]
+            page: self.page.clone(),
+        }
+    }
+}
+
+impl Drop for PageBorrow {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        if let Some((borrows, tx, _, pgid, _)) = &self.page {
+            borrows.release(*tx, *pgid, Borrower::Cursor);
+        }
+    }
+}
+
+impl fmt::Debug for PageBorrow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[cfg(debug_assertions)]
+        if let Some((_, _, _, pgid, _)) = &self.page {
+            return write!(f, "PageBorrow({})", pgid);
+        }
+        write!(f, "PageBorrow")
+    }
+}
+
+impl Tx {
+    /// borrow_page records that a cursor reads len pages from pgid on, until
+    /// the returned borrow is dropped.
+    pub(crate) fn borrow_page(&self, _pgid: PgId, _len: u64) -> PageBorrow {
+        #[cfg(debug_assertions)]
+        if let Ok(db) = self.db() {
+            let (tx, txid) = (self.addr(), self.id());
+            let borrows = db.0.borrows().clone();
+            borrows.borrow(tx, txid, _pgid, _len, Borrower::Cursor);
+            return PageBorrow {
+                page: Some((borrows, tx, txid, _pgid, _len)),
+            };
+        }
+        PageBorrow::default()
+    }
+
+    /// borrow_page_for_node records that a node reads len pages from pgid on,
+    /// until the transaction is closed.
+    pub(crate) fn borrow_page_for_node(&self, _pgid: PgId, _len: u64) {
+        #[cfg(debug_assertions)]
+        if let Ok(db) = self.db() {
+            let borrows = db.0.borrows();
+            borrows.borrow(self.addr(), self.id(), _pgid, _len, Borrower::Node);
+        }
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::db::{Options, DB};
+
+    fn open() -> (tempfile::TempDir, DB) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        (dir, db)
+    }
+
+    fn panic_message(f: impl FnOnce()) -> String {
+        let err = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+        err.downcast_ref::<String>().cloned().unwrap_or_default()
+    }
+
+    // Ensure that the pages of a cursor are recorded while it holds them, and
+    // that truncating below them panics.
+    #[test]
+    fn test_page_borrows() {
+        let (_dir, db) = open();
+        let borrows = db.0.borrows().clone();
+        let tx = db.begin(false).unwrap();
+        let b = tx.bucket(b"widgets").unwrap();
+        let mut c = b.cursor();
+        let (key, _) = c.seek(b"0500").unwrap();
+        assert_eq!(key, b"0500");
+        // The root branch page and a leaf.
+        assert_eq!(borrows.len(), 2);
+
+        let msg = panic_message(|| borrows.check_truncate(2, "truncating the file"));
+        assert!(
+            msg.starts_with("truncating the file would invalidate page"),
+            "{}",
+            msg
+        );
+        assert!(msg.ends_with(&format!("borrowed by a cursor of tx {}", tx.id())));
+        borrows.check_truncate(tx.meta().pgid(), "truncating the file");
+
+        drop(c);
+        assert_eq!(borrows.len(), 0);
+        tx.rollback().unwrap();
+    }
+
+    // Ensure that nodes hold their pages until their transaction is closed,
+    // and that allocating one of them again panics meanwhile.
+    #[test]
+    fn test_page_borrows_node() {
+        let (_dir, db) = open();
+        let borrows = db.0.borrows().clone();
+        let tx = db.begin_rw().unwrap();
+        tx.bucket(b"widgets").unwrap().delete(b"0500").unwrap();
+        assert!(borrows.len() > 0);
+        let root = tx.bucket(b"widgets").unwrap().root();
+
+        let msg = panic_message(|| borrows.check_reuse(root, 1));
+        assert_eq!(
+            msg,
+            format!(
+                "allocating it again would invalidate page {} borrowed by a node of tx {}",
+                root,
+                tx.id()
+            )
+        );
+        tx.rollback().unwrap();
+        assert_eq!(borrows.len(), 0);
+    }
+}
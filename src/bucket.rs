@@ -1,40 +1,84 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
 use std::mem;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
 
+use bytes::Bytes;
+
+use crate::bulk::BulkLoader;
 use crate::common::bucket::InBucket;
-use crate::common::inode::Key;
-use crate::common::page::{OwnedPage, Page, PgId};
-use crate::node::Node;
-use crate::tx::{self, Tx, WeakTx};
-// MaxKeySize is the maximum length of a key, in bytes.
-const MAX_KEY_SIZE: usize = 32768;
+use crate::common::inode::{Key, Value};
+use crate::common::page::{
+    OwnedPage, Page, PgId, BRANCH_PAGE_ELEMENT_SIZE, BUCKET_LEAF_FLAG, LEAF_PAGE_ELEMENT_SIZE,
+    PAGE_HEADER_SIZE,
+};
+use crate::compression::Compression;
+use crate::cursor::{Cursor, Item};
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::node::{Node, WeakNode};
+use crate::page_ref::PageRef;
+use crate::sequence::SEQUENCE_BUCKET;
+use crate::stats_cache::{StatsCache, SubtreeStats};
+use crate::ttl::TTL_BUCKET;
+use crate::tx::{Tx, WeakTx};
+/// MaxKeySize is the maximum length of a key, in bytes. Options.max_key_size
+/// can only lower it.
+pub const MAX_KEY_SIZE: usize = 32768;
+
+/// MaxValueSize is the maximum length of a value, in bytes.
+/// Options.max_value_size can only lower it.
+pub const MAX_VALUE_SIZE: usize = (1 << 31) - 2;
+
+const BUCKET_HEADER_SIZE: usize = mem::size_of::<InBucket>();
+
+pub(crate) const MIN_FILL_PERCENT: f64 = 0.1;
+pub(crate) const MAX_FILL_PERCENT: f64 = 1.0;
+
+/// DEFAULT_REBALANCE_PERCENT is the fill of a node, relative to the page
+/// size, below which a commit merges it with a sibling, unless
+/// Options.rebalance_percent says otherwise.
+pub const DEFAULT_REBALANCE_PERCENT: f64 = 0.25;
 
-// MaxValueSize is the maximum length of a value, in bytes.
-const MAX_VALUE_SIZE: usize = (1 << 31) - 2;
+// Above half a page a merged node could be split again right away.
+pub(crate) const MAX_REBALANCE_PERCENT: f64 = 0.5;
 
-const BUCKET_HEADER_SIZE: usize = mem::size_of::<Bucket>();
+/// RESERVED_PREFIX starts the names of the buckets the crate keeps at the
+/// root for itself, see is_hidden.
+const RESERVED_PREFIX: &[u8] = b"\x00bbolt.";
 
-pub(crate)const MIN_FILL_PERCENT: f64 = 0.1;
-pub(crate)const MAX_FILL_PERCENT: f64 = 1.0;
+/// is_hidden tells whether name is reserved for a bucket the crate keeps at
+/// the root for itself, such as those holding the TTLs of keys or the
+/// sequence of the database. Transactions do not list or hand them out.
+pub(crate) fn is_hidden(name: &[u8]) -> bool {
+    debug_assert!(TTL_BUCKET.starts_with(RESERVED_PREFIX));
+    debug_assert!(SEQUENCE_BUCKET.starts_with(RESERVED_PREFIX));
+    name.starts_with(RESERVED_PREFIX)
+}
 
 /// DefaultFillPercent is the percentage that split pages are filled.
 /// This value can be changed by setting Bucket.FillPercent.
 const DEFAULT_FILL_PERCENT: f64 = 0.5;
 
-// Bucket represents a collection of key/value pairs inside the database.
-
+// RawBucket holds the state of a bucket. It lives behind an Rc and is only
+// ever handed out through Bucket, and its nodes refer back to it weakly.
 #[derive(Debug)]
-pub struct Bucket {
-    pub(crate) bucket: InBucket,
+pub(crate) struct RawBucket {
+    // the Rc holding the bucket, handed to its nodes
+    this: Weak<RawBucket>,
+    pub(crate) bucket: RefCell<InBucket>,
+    // names of the bucket and its ancestors, empty for the root bucket
+    pub(crate) path: RefCell<Vec<Key>>,
     // the associated transaction, WeakTx
     pub(crate) tx: WeakTx,
     // subbucket cache
-    pub(crate) buckets: RefCell<HashMap<Key, Bucket>>,
-    // inline page reference
-    pub(crate) page: Option<OwnedPage>,
+    pub(crate) buckets: RefCell<HashMap<Key, Bucket<'static>>>,
+    // inline page reference, shared with the parent's value
+    pub(crate) page: Option<Bytes>,
     // materialized node for the root page
-    pub(crate) root_node: Option<Node>,
+    pub(crate) root_node: RefCell<Option<Node>>,
     // node cache
     // TODO: maybe use refHashMap
     pub(crate) nodes: RefCell<HashMap<PgId, Node>>,
@@ -43,11 +87,2097 @@ pub struct Bucket {
     // amount if you know that your write workloads are mostly append-only.
     //
     // This is non-persisted across transactions so it must be set in every Tx.
-    pub(crate) fill_percent: f64,
+    pub(crate) fill_percent: Cell<f64>,
+    // Compression of the values, non-persisted like fill_percent.
+    pub(crate) compression: Cell<Compression>,
+    // Fill below which nodes are merged, the DB's when unset. Non-persisted
+    // like fill_percent.
+    pub(crate) rebalance_percent: Cell<Option<f64>>,
 }
-impl Bucket {
-    pub(crate) fn node(&self, child_pgid: PgId, from: crate::node::WeakNode) -> Node {
-        todo!()
+
+/// Bucket represents a collection of key/value pairs inside the database.
+///
+/// A bucket borrows the transaction it was retrieved from, so neither the
+/// bucket nor the keys and values read through it can outlive the
+/// transaction:
+///
+/// ```compile_fail
+/// # let dir = tempfile::tempdir().unwrap();
+/// # let db = boltdb_rs::DB::open(dir.path().join("db"), Default::default()).unwrap();
+/// let mut value = None;
+/// db.update(|tx| {
+///     value = tx.create_bucket(b"widgets")?.get(b"foo");
+///     Ok(())
+/// })
+/// .unwrap();
+/// println!("{:?}", value);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Bucket<'tx>(pub(crate) Rc<RawBucket>, PhantomData<&'tx Tx>);
+
+impl<'tx> Bucket<'tx> {
+    /// new returns a bucket that is not yet attached to any root page.
+    pub(crate) fn new(tx: WeakTx) -> Self {
+        Self(
+            RawBucket::new(tx, Vec::new(), InBucket::default(), None),
+            PhantomData,
+        )
+    }
+
+    /// tx returns the tx of the bucket.
+    pub(crate) fn tx(&self) -> Result<Tx> {
+        self.0.tx()
+    }
+
+    /// root returns the root of the bucket.
+    pub fn root(&self) -> u64 {
+        self.0.root_page()
+    }
+
+    /// writable returns whether the bucket is writable.
+    pub fn writable(&self) -> bool {
+        self.0.tx().map(|tx| tx.writable()).unwrap_or(false)
+    }
+
+    /// fill_percent returns the threshold for filling nodes when they split.
+    pub fn fill_percent(&self) -> f64 {
+        self.0.fill_percent.get()
+    }
+
+    /// set_fill_percent sets the threshold for filling nodes when they split.
+    /// It is not persisted, so it must be set again in every transaction.
+    pub fn set_fill_percent(&self, fill_percent: f64) {
+        self.0.fill_percent.set(fill_percent);
+    }
+
+    /// rebalance_percent returns the fill of a node, relative to the page
+    /// size, below which a commit merges it with a sibling.
+    pub fn rebalance_percent(&self) -> f64 {
+        self.0.rebalance_percent()
+    }
+
+    /// set_rebalance_percent sets the fill of a node, relative to the page
+    /// size, below which a commit merges it with a sibling, in place of
+    /// Options.rebalance_percent. Lowering it trades space for fewer merges
+    /// in delete-heavy workloads; zero only merges nodes left with too few
+    /// keys. It is clamped to [0, 0.5] and not persisted, so it must be set
+    /// again in every transaction.
+    pub fn set_rebalance_percent(&self, rebalance_percent: f64) {
+        self.0.rebalance_percent.set(Some(rebalance_percent));
+    }
+
+    /// compression returns how the values of the bucket are compressed.
+    pub fn compression(&self) -> Compression {
+        self.0.compression.get()
+    }
+
+    /// set_compression enables transparent compression of the values of the
+    /// bucket: put compresses them, and get and cursors decompress them.
+    ///
+    /// Compressed values carry a header, so compression has to be enabled on
+    /// every handle of the bucket, in every transaction, from the time the
+    /// bucket is created. Nested buckets do not inherit it.
+    pub fn set_compression(&self, compression: Compression) {
+        self.0.compression.set(compression);
+    }
+
+    /// cursor creates a cursor associated with the bucket.
+    /// The cursor is only valid as long as the transaction is open.
+    /// Do not use a cursor after the transaction is closed.
+    pub fn cursor(&self) -> Cursor<'tx> {
+        // Update transaction statistics.
+        if let Ok(tx) = self.0.tx() {
+            tx.stats_mut().cursor_count += 1;
+        }
+
+        // Allocate and return a cursor.
+        Cursor::new(self.clone())
+    }
+
+    /// bucket retrieves a nested bucket by name.
+    /// Returns None if the bucket does not exist or the key is not a bucket,
+    /// and also if the pages on the way to it or its root cannot be read.
+    pub fn bucket(&self, name: &[u8]) -> Option<Bucket<'tx>> {
+        if let Some(child) = self.0.buckets.borrow().get(name) {
+            return Some(child.clone());
+        }
+
+        // Move cursor to key.
+        let mut c = self.cursor();
+        let (k, v, flags) = c.seek_item(name).ok()??;
+
+        // Return None if the key doesn't exist or it is not a bucket.
+        if name != &k[..] || (flags & BUCKET_LEAF_FLAG) == 0 {
+            return None;
+        }
+
+        // Otherwise create a bucket and cache it.
+        let child = self.0.open_bucket(name, &v).ok()?;
+        if self.writable() {
+            self.0
+                .buckets
+                .borrow_mut()
+                .insert(Key::copy_from_slice(name), child.clone());
+        }
+
+        Some(child)
+    }
+
+    /// create_bucket creates a new bucket at the given key and returns the new bucket.
+    /// Returns an error if the key already exists, if the bucket name is blank, or if the bucket name is too long.
+    pub fn create_bucket(&self, key: &[u8]) -> Result<Bucket<'tx>> {
+        self.check_writable()?;
+        if key.is_empty() {
+            return Err(BoltError::BucketNameRequired);
+        } else if key.len() > self.tx()?.db()?.0.max_key_size() {
+            return Err(BoltError::KeyTooLarge);
+        }
+
+        // Move cursor to correct position.
+        let mut c = self.cursor();
+        if let Some((k, _, flags)) = c.seek_item(key)? {
+            // Return an error if there is an existing key.
+            if key == &k[..] {
+                if (flags & BUCKET_LEAF_FLAG) != 0 {
+                    return Err(BoltError::BucketExists);
+                }
+                return Err(BoltError::IncompatibleValue);
+            }
+        }
+
+        self.0.insert_bucket(&mut c, key)?;
+
+        self.bucket(key)
+            .ok_or(BoltError::Unexpected("created bucket not found"))
+    }
+
+    /// bulk_loader creates a new bucket at the given key and returns a loader
+    /// filling it with keys given in ascending order.
+    /// Returns the errors of create_bucket.
+    pub fn bulk_loader(&self, key: &[u8]) -> Result<BulkLoader<'tx>> {
+        BulkLoader::new(self, key)
+    }
+
+    /// create_bucket_if_not_exists creates a new bucket if it doesn't already exist and returns a reference to it.
+    /// Returns an error if the bucket name is blank, or if the bucket name is too long.
+    pub fn create_bucket_if_not_exists(&self, key: &[u8]) -> Result<Bucket<'tx>> {
+        self.check_writable()?;
+        if key.is_empty() {
+            return Err(BoltError::BucketNameRequired);
+        } else if key.len() > self.tx()?.db()?.0.max_key_size() {
+            return Err(BoltError::KeyTooLarge);
+        }
+
+        if let Some(child) = self.0.buckets.borrow().get(key) {
+            return Ok(child.clone());
+        }
+
+        // Move cursor to correct position.
+        let mut c = self.cursor();
+        if let Some((k, v, flags)) = c.seek_item(key)? {
+            // Return an error if there is an existing non-bucket key.
+            if key == &k[..] {
+                if (flags & BUCKET_LEAF_FLAG) == 0 {
+                    return Err(BoltError::IncompatibleValue);
+                }
+                let child = self.0.open_bucket(key, &v)?;
+                self.0
+                    .buckets
+                    .borrow_mut()
+                    .insert(Key::copy_from_slice(key), child.clone());
+                return Ok(child);
+            }
+        }
+
+        self.0.insert_bucket(&mut c, key)?;
+
+        self.bucket(key)
+            .ok_or(BoltError::Unexpected("created bucket not found"))
+    }
+
+    /// delete_bucket deletes a bucket at the given key, along with the TTLs
+    /// of its keys.
+    /// Returns an error if the bucket does not exist, or if the key represents a non-bucket value.
+    pub fn delete_bucket(&self, key: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        if self.0.path.borrow().is_empty() && is_hidden(key) {
+            return Err(BoltError::IncompatibleValue);
+        }
+
+        // Move cursor to correct position.
+        let mut c = self.cursor();
+        let (k, _, flags) = c.seek_item(key)?.ok_or(BoltError::BucketNotFound)?;
+
+        // Return an error if bucket doesn't exist or is not a bucket.
+        if key != &k[..] {
+            return Err(BoltError::BucketNotFound);
+        } else if (flags & BUCKET_LEAF_FLAG) == 0 {
+            return Err(BoltError::IncompatibleValue);
+        }
+
+        // Recursively delete all child buckets.
+        let child = self.bucket(key).ok_or(BoltError::BucketNotFound)?;
+        if child.is_capturing() {
+            child.for_each(|k, v| {
+                if let Some(v) = v {
+                    child.record_change(k, Some(v.to_vec()), None);
+                }
+                Ok(())
+            })?;
+        }
+        let mut names = Vec::new();
+        child.for_each_bucket(|name| {
+            names.push(name.to_vec());
+            Ok(())
+        })?;
+        for name in names {
+            child.delete_bucket(&name)?;
+        }
+
+        // Remove cached copy.
+        self.0.buckets.borrow_mut().remove(key);
+
+        // Release all bucket pages to freelist.
+        child.0.nodes.borrow_mut().clear();
+        *child.0.root_node.borrow_mut() = None;
+        child.0.free()?;
+
+        // Delete the node if we have a matching key.
+        c.node()?.del(key);
+
+        // A bucket created with the same name later starts without TTLs.
+        self.clear_nested_ttls(key)
+    }
+
+    /// get retrieves the value for a key in the bucket.
+    /// Returns None if the key does not exist or if the key is a nested bucket.
+    /// The value is borrowed from the transaction, without being copied
+    /// unless it has to be decompressed.
+    /// A page that cannot be read or a value that fails to decompress also
+    /// reads as None; get_many and contains_key return those errors.
+    pub fn get(&self, key: &[u8]) -> Option<&'tx [u8]> {
+        let item = self.cursor().seek_item(key).ok()??;
+        self.found_value(key, item).ok()?
     }
+
+    /// get_many retrieves the values for several keys like get, returning
+    /// them in the order of the keys. The keys are looked up in sorted order
+    /// with a single cursor, which only descends from the pages where the
+    /// paths to consecutive keys diverge.
+    /// Unlike get, it returns the error of a page that cannot be read or a
+    /// value that fails to decompress.
+    pub fn get_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<&'tx [u8]>>> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_unstable_by(|&a, &b| keys[a].as_ref().cmp(keys[b].as_ref()));
+
+        let mut values = vec![None; keys.len()];
+        let mut c = self.cursor();
+        for i in order {
+            let key = keys[i].as_ref();
+            if let Some(item) = c.reseek_item(key)? {
+                values[i] = self.found_value(key, item)?;
+            }
+        }
+        Ok(values)
+    }
+
+    /// contains_key returns whether a key has a value in the bucket, like get
+    /// without decompressing or handing out the value. Unlike get, it returns
+    /// the error of a page that cannot be read, rather than reporting the key
+    /// as missing.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        Ok(match self.cursor().seek_item(key)? {
+            Some((k, _, flags)) => {
+                key == &k[..] && flags & BUCKET_LEAF_FLAG == 0 && !self.is_expired(key)
+            }
+            None => false,
+        })
+    }
+
+    /// key_count returns the number of keys of the bucket, nested buckets
+    /// included but not their keys. It adds up the element counts of the
+    /// leaves without reading the keys, so keys whose TTL passed count until
+    /// they are purged.
+    pub fn key_count(&self) -> Result<usize> {
+        let mut n = 0;
+        self.0.for_each_page_node(&mut |page_node, _| {
+            if page_node.is_leaf() {
+                n += page_node.count();
+            }
+        })?;
+        Ok(n)
+    }
+
+    /// first returns the first key of the bucket with its value, skipping
+    /// nested buckets. Returns None if the bucket holds no values.
+    pub fn first(&self) -> Option<(&'tx [u8], &'tx [u8])> {
+        let mut c = self.cursor();
+        let item = c.first();
+        first_value(&mut c, item, true)
+    }
+
+    /// last returns the last key of the bucket with its value, skipping
+    /// nested buckets. Returns None if the bucket holds no values.
+    pub fn last(&self) -> Option<(&'tx [u8], &'tx [u8])> {
+        let mut c = self.cursor();
+        let item = c.last();
+        first_value(&mut c, item, false)
+    }
+
+    /// pop_first removes the first key of the bucket with a value, like
+    /// first finds it, and returns it with its value.
+    pub fn pop_first(&self) -> Result<Option<(&'tx [u8], &'tx [u8])>> {
+        self.check_writable()?;
+        let mut c = self.cursor();
+        let item = c.first();
+        pop_value(&mut c, item, true)
+    }
+
+    /// pop_last removes the last key of the bucket with a value, like last
+    /// finds it, and returns it with its value.
+    pub fn pop_last(&self) -> Result<Option<(&'tx [u8], &'tx [u8])>> {
+        self.check_writable()?;
+        let mut c = self.cursor();
+        let item = c.last();
+        pop_value(&mut c, item, false)
+    }
+
+    // found_value returns the value of the item a cursor found for a key, if
+    // it is the value of that key.
+    fn found_value(&self, key: &[u8], (k, v, flags): Item) -> Result<Option<&'tx [u8]>> {
+        // Return None if this is a bucket.
+        if (flags & BUCKET_LEAF_FLAG) != 0 {
+            return Ok(None);
+        }
+
+        // If our target node isn't the same key as what's passed in then return None.
+        if key != &k[..] || self.is_expired(key) {
+            return Ok(None);
+        }
+
+        let value = self.compression().decompress_bytes(&v)?;
+        self.pin(value).ok_or(BoltError::TxClosed).map(Some)
+    }
+
+    /// pin returns a slice of value that stays valid as long as the
+    /// transaction. Values of the mmap snapshot are valid that long already;
+    /// others, such as the values of dirty nodes, which a later put may
+    /// replace, are kept alive by the transaction.
+    pub(crate) fn pin(&self, value: Bytes) -> Option<&'tx [u8]> {
+        let tx = self.0.tx().ok()?;
+        let value = tx.retain(value);
+        // SAFETY: 'tx borrows a handle of the transaction, which keeps it
+        // open and its snapshot unchanged, and the retained buffers alive.
+        Some(unsafe { &*value })
+    }
+
+    /// put sets the value for a key in the bucket.
+    /// If the key exist then its previous value will be overwritten.
+    /// Returns an error if the bucket was created from a read-only transaction,
+    /// if the key is blank, if the key is too large, or if the value is too large.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.check_put(key, value.len())?;
+        let stored = Value::from(self.compression().compress(value)?);
+        self.put_stored(key, stored, value)
+    }
+
+    /// put_reader sets the value for a key in the bucket to the next len
+    /// bytes of reader. A value that needs overflow pages is read straight
+    /// into the pages of a leaf of its own, which the commit writes to the
+    /// file as they are, rather than into a buffer copied to the pages of the
+    /// leaf. It is stored uncompressed, in buckets compressing their values.
+    /// Shorter values are put as with put.
+    /// Returns the errors of put, and an io error if reader fails or ends
+    /// before len bytes.
+    pub fn put_reader<R: Read>(&self, key: &[u8], mut reader: R, len: usize) -> Result<()> {
+        self.check_put(key, len)?;
+        let tx = self.tx()?;
+        let db = tx.db()?;
+        let header = self.compression().stored_header();
+        let size = PAGE_HEADER_SIZE + LEAF_PAGE_ELEMENT_SIZE + key.len() + header.len() + len;
+        if size + db.0.crypt().overhead() <= db.0.page_size() {
+            let mut value = vec![0; len];
+            reader.read_exact(&mut value).op(IoOp::Read)?;
+            return self.put(key, &value);
+        }
+
+        let (mut c, old) = self.seek_put(key)?;
+        let (id, stored) = tx.stream_value(key, header, reader, len)?;
+        let value = stored.slice(header.len()..);
+        self.put_at(&mut c, key, stored, id, old, &value)
+    }
+
+    // check_put returns the error of a put of a value of len bytes, if any.
+    pub(crate) fn check_put(&self, key: &[u8], len: usize) -> Result<()> {
+        self.check_writable()?;
+        let db = self.tx()?.db()?;
+        if key.is_empty() {
+            return Err(BoltError::KeyRequired);
+        } else if key.len() > db.0.max_key_size() {
+            return Err(BoltError::KeyTooLarge);
+        } else if len > db.0.max_value_size() {
+            return Err(BoltError::ValueTooLarge);
+        }
+        Ok(())
+    }
+
+    // put_stored inserts the stored form of value for a key.
+    fn put_stored(&self, key: &[u8], stored: Value, value: &[u8]) -> Result<()> {
+        let (mut c, old) = self.seek_put(key)?;
+        self.put_at(&mut c, key, stored, 0, old, value)
+    }
+
+    // seek_put returns a cursor seeked to a key about to be put, and its
+    // previous value if observers are to be told about it.
+    fn seek_put(&self, key: &[u8]) -> Result<(Cursor<'tx>, Option<Vec<u8>>)> {
+        // Move cursor to correct position.
+        let mut c = self.cursor();
+        let mut old = None;
+        if let Some((k, v, flags)) = c.seek_item(key).map_err(|err| err.with_key(key))? {
+            // Return an error if there is an existing key with a bucket value.
+            if key == &k[..] && (flags & BUCKET_LEAF_FLAG) != 0 {
+                return Err(BoltError::IncompatibleValue);
+            }
+            if key == &k[..] && self.is_capturing() {
+                old = Some(self.compression().decompress(&v)?);
+            }
+        }
+        Ok((c, old))
+    }
+
+    // put_at inserts the stored form of value for a key at the position of a
+    // cursor seeked to it. streamed is the id of the pages the value was
+    // streamed to, or zero, and old is the previous value, for observers.
+    pub(crate) fn put_at(
+        &self,
+        c: &mut Cursor<'tx>,
+        key: &[u8],
+        stored: Value,
+        streamed: PgId,
+        old: Option<Vec<u8>>,
+        value: &[u8],
+    ) -> Result<()> {
+        // Insert into node.
+        let key = Key::copy_from_slice(key);
+        c.node()
+            .map_err(|err| err.with_key(&key))?
+            .put(&key, key.clone(), stored, streamed, 0);
+
+        if self.is_capturing() {
+            self.record_change(&key, old, Some(value.to_vec()));
+        }
+        self.clear_ttl(&key)
+    }
+
+    /// get_writer writes the value for a key in the bucket to writer, a page
+    /// at a time, returning its length. The value is written from the pages
+    /// of the mmap, so it is neither copied nor read whole before it is
+    /// written. Values that are compressed, encrypted or read without mmap
+    /// are held in memory as with get.
+    /// Returns None if the key does not exist or if the key is a nested
+    /// bucket, and an io error if writer fails.
+    pub fn get_writer<W: Write>(&self, key: &[u8], mut writer: W) -> Result<Option<u64>> {
+        let Some((k, stored, flags)) = self.cursor().seek_item(key)? else {
+            return Ok(None);
+        };
+        if key != &k[..] || (flags & BUCKET_LEAF_FLAG) != 0 || self.is_expired(key) {
+            return Ok(None);
+        }
+
+        let decompressed;
+        let value = match self.compression().stored_value(&stored) {
+            Some(value) => value,
+            None => {
+                decompressed = self.compression().decompress(&stored)?;
+                &decompressed
+            }
+        };
+        for chunk in value.chunks(self.tx()?.db()?.0.page_size()) {
+            writer.write_all(chunk).op(IoOp::Write)?;
+        }
+        Ok(Some(value.len() as u64))
+    }
+
+    /// delete removes a key from the bucket.
+    /// If the key does not exist then nothing is done and Ok is returned.
+    /// Returns an error if the bucket was created from a read-only transaction.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.check_writable()?;
+
+        // Move cursor to correct position.
+        let mut c = self.cursor();
+        let (k, v, flags) = match c.seek_item(key).map_err(|err| err.with_key(key))? {
+            Some(item) => item,
+            None => return Ok(()),
+        };
+
+        // Return Ok if the key doesn't exist.
+        if key != &k[..] {
+            return Ok(());
+        }
+
+        // Return an error if there is already existing bucket value.
+        if (flags & BUCKET_LEAF_FLAG) != 0 {
+            return Err(BoltError::IncompatibleValue);
+        }
+
+        // Delete the node if we have a matching key.
+        c.node().map_err(|err| err.with_key(key))?.del(key);
+
+        if self.is_capturing() {
+            let old = self.compression().decompress(&v)?;
+            self.record_change(key, Some(old), None);
+        }
+        self.clear_ttl(key)
+    }
+
+    /// put_if sets the value for a key in the bucket if its current value is
+    /// expected, where None expects the key not to exist.
+    /// Returns BoltError::ValueMismatch if the value is a different one, and
+    /// the errors of put otherwise.
+    pub fn put_if(&self, key: &[u8], expected: Option<&[u8]>, value: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        if self.get(key) != expected {
+            return Err(BoltError::ValueMismatch);
+        }
+        self.put(key, value)
+    }
+
+    /// delete_if removes a key from the bucket if its current value is
+    /// expected.
+    /// Returns BoltError::ValueMismatch if the key does not exist or has a
+    /// different value, and the errors of delete otherwise.
+    pub fn delete_if(&self, key: &[u8], expected: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        if self.get(key) != Some(expected) {
+            return Err(BoltError::ValueMismatch);
+        }
+        self.delete(key)
+    }
+
+    /// sequence returns the current integer for the bucket without incrementing it.
+    pub fn sequence(&self) -> u64 {
+        self.0.bucket.borrow().in_sequence()
+    }
+
+    /// set_sequence updates the sequence number for the bucket.
+    pub fn set_sequence(&self, v: u64) -> Result<()> {
+        self.check_writable()?;
+
+        // Materialize the root node if it hasn't been already so that the
+        // bucket will be saved during commit.
+        self.0.materialize_root()?;
+
+        // Set the sequence.
+        self.0.bucket.borrow_mut().set_in_sequence(v);
+        Ok(())
+    }
+
+    /// next_sequence returns an autoincrementing integer for the bucket.
+    pub fn next_sequence(&self) -> Result<u64> {
+        self.check_writable()?;
+
+        // Materialize the root node if it hasn't been already so that the
+        // bucket will be saved during commit.
+        self.0.materialize_root()?;
+
+        // Increment and return the sequence.
+        let mut bucket = self.0.bucket.borrow_mut();
+        bucket.inc_sequence();
+        Ok(bucket.in_sequence())
+    }
+
+    /// for_each executes a function for each key/value pair in a bucket.
+    /// Nested buckets are passed with a None value.
+    /// If the provided function returns an error then the iteration is stopped and
+    /// the error is returned to the caller. So is the error of a page that
+    /// cannot be read or a value that fails to decompress.
+    /// The keys and values are only valid during the call, so unlike those of
+    /// a cursor they are not kept until the transaction closes.
+    pub fn for_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], Option<&[u8]>) -> Result<()>,
+    {
+        self.0.tx()?.db()?;
+        let mut c = self.cursor();
+        let mut item = c.first_live()?;
+        while let Some((k, v, flags)) = item {
+            if (flags & BUCKET_LEAF_FLAG) != 0 {
+                f(&k, None)?;
+            } else {
+                f(&k, Some(&self.compression().decompress_bytes(&v)?))?;
+            }
+            item = c.next_live()?;
+        }
+        Ok(())
+    }
+
+    /// for_each_bucket executes a function for each nested bucket name.
+    pub fn for_each_bucket<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        self.0.tx()?.db()?;
+        let mut c = self.cursor();
+        let mut item = c.first_item()?;
+        while let Some((k, _, flags)) = item {
+            if (flags & BUCKET_LEAF_FLAG) != 0 {
+                f(&k)?;
+            }
+            item = c.next_item()?;
+        }
+        Ok(())
+    }
+
+    /// stats retrieves stats on a bucket, including its nested buckets.
+    /// Stats are collected from the committed pages, pending changes of a write
+    /// transaction are not taken into account. With Options.cache_bucket_stats
+    /// only the pages written since the last call are walked.
+    pub fn stats(&self) -> Result<BucketStats> {
+        let tx = self.0.tx()?;
+        let db = tx.db()?;
+        match db.0.stats_cache() {
+            Some(cache) if self.root() != 0 => self.cached_stats(&tx, cache, db.0.page_size()),
+            _ => self.walk_stats(),
+        }
+    }
+
+    /// walk_stats computes the stats of the bucket from all of its pages.
+    pub(crate) fn walk_stats(&self) -> Result<BucketStats> {
+        let tx = self.0.tx()?;
+        let page_size = tx.db()?.0.page_size();
+        let mut s = BucketStats::default();
+        let mut sub_stats = BucketStats::default();
+        let mut err = Ok(());
+
+        s.bucket_n += 1;
+        if self.root() == 0 {
+            s.inline_bucket_n += 1;
+        }
+        self.0.for_each_page(&mut |p, depth, _| {
+            if p.is_leaf_page() {
+                s.key_n += p.count() as usize;
+
+                // used totals the used bytes for the page
+                let mut used = PAGE_HEADER_SIZE;
+                if p.count() != 0 {
+                    // If page has any elements, add all element headers.
+                    used += LEAF_PAGE_ELEMENT_SIZE * (p.count() as usize - 1);
+
+                    // Add all element key, value sizes.
+                    // The computation takes advantage of the fact that the position
+                    // of the last element's key/value equals to the total of the sizes
+                    // of all previous elements' keys and values.
+                    // It also includes the last element's header.
+                    let last = p.leaf_page_element(p.count() as usize - 1);
+                    used += (last.pos() + last.ksize() + last.vsize()) as usize;
+                }
+
+                if self.root() == 0 {
+                    // For inlined bucket just update the inline stats
+                    s.inline_bucket_inuse += used;
+                } else {
+                    // For non-inlined bucket update all the leaf stats
+                    s.leaf_page_n += 1;
+                    s.leaf_inuse += used;
+                    s.leaf_overflow_n += p.overflow() as usize;
+
+                    // Collect stats from sub-buckets.
+                    // Do that by iterating over all element headers
+                    // looking for the ones with the bucketLeafFlag.
+                    for e in p.leaf_page_elements() {
+                        if e.is_bucket_entry() {
+                            // For any bucket element, open the element value
+                            // and recursively call Stats on the contained bucket.
+                            let value = Value::copy_from_slice(e.value());
+                            let child = self.0.open_bucket(e.key(), &value);
+                            match child.and_then(|child| child.stats()) {
+                                Ok(child) => sub_stats.add(&child),
+                                Err(e) => err = Err(e),
+                            }
+                        }
+                    }
+                }
+            } else if p.is_branch_page() {
+                s.branch_page_n += 1;
+                let last = p.branch_page_element(p.count() as usize - 1);
+
+                // used totals the used bytes for the page
+                // Add header and all element headers.
+                let mut used =
+                    PAGE_HEADER_SIZE + BRANCH_PAGE_ELEMENT_SIZE * (p.count() as usize - 1);
+
+                // Add size of all keys and values.
+                // Again, use the fact that last element's position equals to
+                // the total of key, value sizes of all previous elements.
+                used += (last.pos() + last.ksize()) as usize;
+                s.branch_inuse += used;
+                s.branch_overflow_n += p.overflow() as usize;
+            }
+
+            // Keep track of maximum page depth.
+            s.depth = s.depth.max(depth + 1);
+        })?;
+        err?;
+
+        // Alloc stats can be computed from page counts and pageSize.
+        s.branch_alloc = (s.branch_page_n + s.branch_overflow_n) * page_size;
+        s.leaf_alloc = (s.leaf_page_n + s.leaf_overflow_n) * page_size;
+
+        // Add the max depth of sub-buckets to get total nested depth.
+        s.depth += sub_stats.depth;
+        // Add the stats for all sub-buckets
+        s.add(&sub_stats);
+        s.rebalance_threshold = self.0.rebalance_threshold(page_size);
+        Ok(s)
+    }
+
+    /// cached_stats computes the stats of a bucket with a root page, taking
+    /// the stats of the subtrees that did not change from the cache.
+    fn cached_stats(&self, tx: &Tx, cache: &StatsCache, page_size: usize) -> Result<BucketStats> {
+        let subtree = self.subtree_stats(tx, cache, page_size, self.root())?;
+        let mut s = subtree.pages.clone();
+        s.bucket_n += 1;
+        s.branch_alloc = (s.branch_page_n + s.branch_overflow_n) * page_size;
+        s.leaf_alloc = (s.leaf_page_n + s.leaf_overflow_n) * page_size;
+        s.depth += subtree.nested.depth;
+        s.add(&subtree.nested);
+        s.rebalance_threshold = self.0.rebalance_threshold(page_size);
+        Ok(s)
+    }
+
+    // subtree_stats returns the stats of the pages below pgid and of the
+    // buckets nested in them, from the cache if they are known.
+    fn subtree_stats(
+        &self,
+        tx: &Tx,
+        cache: &StatsCache,
+        page_size: usize,
+        pgid: PgId,
+    ) -> Result<Arc<SubtreeStats>> {
+        if let Some(subtree) = cache.get(pgid) {
+            return Ok(subtree);
+        }
+        let page = tx.page(pgid)?;
+        let page = PageRef::new(&page, page_size * (page.overflow() as usize + 1), 0)?;
+        let mut subtree = SubtreeStats::default();
+        let s = &mut subtree.pages;
+        if page.is_leaf() {
+            s.leaf_page_n = 1;
+            s.leaf_overflow_n = page.overflow() as usize;
+            s.leaf_inuse = page.used();
+            s.key_n = page.count();
+            s.depth = 1;
+            for index in 0..page.count() {
+                if page.is_bucket(index) {
+                    let name = page.key(index).unwrap_or_default();
+                    let value = Value::copy_from_slice(page.value(index).unwrap_or_default());
+                    let child = self.0.open_bucket(&name, &value)?;
+                    subtree.nested.add(&child.stats()?);
+                }
+            }
+        } else {
+            s.branch_page_n = 1;
+            s.branch_overflow_n = page.overflow() as usize;
+            s.branch_inuse = page.used();
+            let mut height = 0;
+            for index in 0..page.count() {
+                let child = page.child(index).unwrap_or_default();
+                let child = self.subtree_stats(tx, cache, page_size, child)?;
+                height = height.max(child.pages.depth);
+                subtree.pages.add(&child.pages);
+                subtree.nested.add(&child.nested);
+            }
+            subtree.pages.depth = height + 1;
+        }
+
+        let subtree = Arc::new(subtree);
+        cache.insert(pgid, subtree.clone());
+        Ok(subtree)
+    }
+
+    /// spill writes all the nodes for this bucket to dirty pages.
+    pub(crate) fn spill(&self) -> Result<()> {
+        // Spill all child buckets first.
+        let buckets: Vec<(Key, Bucket<'_>)> = self
+            .0
+            .buckets
+            .borrow()
+            .iter()
+            .map(|(name, child)| (name.clone(), child.clone()))
+            .collect();
+        for (name, child) in buckets {
+            // If the child bucket is small enough and it has no child buckets then
+            // write it inline into the parent bucket's page. Otherwise spill it
+            // like a normal bucket and make the parent value a pointer to the page.
+            let value = if child.0.inlineable()? {
+                child.0.free()?;
+                child.0.write()
+            } else {
+                child.spill()?;
+
+                // Update the child bucket header in this bucket.
+                Value::copy_from_slice(&child.0.bucket.borrow().to_bytes())
+            };
+
+            // Skip writing the bucket if there are no materialized nodes.
+            if child.0.root_node.borrow().is_none() {
+                continue;
+            }
+
+            // Update parent node.
+            let mut c = self.cursor();
+            let (k, _, flags) = c.seek_item(&name)?.unwrap_or_default();
+            assert!(
+                name == k,
+                "misplaced bucket header: {:x?} -> {:x?}",
+                name,
+                k
+            );
+            assert!(
+                flags & BUCKET_LEAF_FLAG != 0,
+                "unexpected bucket header flag: {:x}",
+                flags
+            );
+            c.node()?
+                .put(&name, name.clone(), value, 0, BUCKET_LEAF_FLAG);
+        }
+
+        // Ignore if there's not a materialized root node.
+        let root = match self.0.root_node.borrow().clone() {
+            Some(root) => root,
+            None => return Ok(()),
+        };
+
+        // Spill nodes. A split of the root leaves a new root above it.
+        root.spill()?;
+        let root = root.root();
+
+        // Update the root node for this bucket.
+        let tx = self.0.tx()?;
+        let pgid = root.pgid();
+        assert!(
+            pgid < tx.meta().pgid(),
+            "pgid ({}) above high water mark ({})",
+            pgid,
+            tx.meta().pgid()
+        );
+        self.0.bucket.borrow_mut().set_root_page(pgid);
+        *self.0.root_node.borrow_mut() = Some(root);
+
+        Ok(())
+    }
+
+    // check_writable returns an error unless the bucket belongs to an open,
+    // writable transaction.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        let tx = self.0.tx()?;
+        tx.db()?;
+        if !tx.writable() {
+            return Err(BoltError::TxNotWritable);
+        }
+        Ok(())
+    }
+}
+
+/// BucketStats records statistics about resources used by a bucket.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BucketStats {
+    // Page count statistics.
+    /// number of logical branch pages
+    pub branch_page_n: usize,
+    /// number of physical branch overflow pages
+    pub branch_overflow_n: usize,
+    /// number of logical leaf pages
+    pub leaf_page_n: usize,
+    /// number of physical leaf overflow pages
+    pub leaf_overflow_n: usize,
+
+    // Tree statistics.
+    /// number of keys/value pairs
+    pub key_n: usize,
+    /// number of levels in B+tree
+    pub depth: usize,
+
+    // Page size utilization.
+    /// bytes allocated for physical branch pages
+    pub branch_alloc: usize,
+    /// bytes actually used for branch data
+    pub branch_inuse: usize,
+    /// bytes allocated for physical leaf pages
+    pub leaf_alloc: usize,
+    /// bytes actually used for leaf data
+    pub leaf_inuse: usize,
+
+    // Bucket statistics
+    /// total number of buckets including the top bucket
+    pub bucket_n: usize,
+    /// total number on inlined buckets
+    pub inline_bucket_n: usize,
+    /// bytes used for inlined buckets (also accounted for in leaf_inuse)
+    pub inline_bucket_inuse: usize,
+
+    // Rebalancing.
+    /// size in bytes below which a commit merges a node of the bucket with
+    /// a sibling (the largest one of the buckets added up)
+    pub rebalance_threshold: usize,
 }
 
+impl BucketStats {
+    /// add accumulates the stats of another bucket.
+    pub fn add(&mut self, other: &BucketStats) {
+        self.branch_page_n += other.branch_page_n;
+        self.branch_overflow_n += other.branch_overflow_n;
+        self.leaf_page_n += other.leaf_page_n;
+        self.leaf_overflow_n += other.leaf_overflow_n;
+        self.key_n += other.key_n;
+        if self.depth < other.depth {
+            self.depth = other.depth;
+        }
+        self.branch_alloc += other.branch_alloc;
+        self.branch_inuse += other.branch_inuse;
+        self.leaf_alloc += other.leaf_alloc;
+        self.leaf_inuse += other.leaf_inuse;
+
+        self.bucket_n += other.bucket_n;
+        self.inline_bucket_n += other.inline_bucket_n;
+        self.inline_bucket_inuse += other.inline_bucket_inuse;
+        self.rebalance_threshold = self.rebalance_threshold.max(other.rebalance_threshold);
+    }
+}
+
+/// PageNode is what page_node resolves a page id to: either a materialized
+/// node or the page it would be read from.
+#[derive(Debug, Clone)]
+pub(crate) enum PageNode {
+    // A page from the transaction, or the fake page of an inline bucket.
+    // The buffer holds the whole page, starting with its header.
+    Page(Bytes),
+    Node(Node),
+}
+
+impl PageNode {
+    // from_page checks the elements of a page read from the file before the
+    // unchecked accessors of the cursor get to see it.
+    fn from_page(buf: Bytes) -> Result<PageNode> {
+        let page = Page::try_from_slice(&buf)?;
+        page.try_validate(buf.len()).map_err(|_| {
+            BoltError::corrupted("elements lie outside the page").with_pgid(page.id())
+        })?;
+        Ok(PageNode::Page(buf))
+    }
+
+    pub(crate) fn page(&self) -> Option<&Page> {
+        match self {
+            PageNode::Page(buf) => Some(Page::from_slice(buf)),
+            PageNode::Node(_) => None,
+        }
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool {
+        match self {
+            PageNode::Page(buf) => Page::from_slice(buf).is_leaf_page(),
+            PageNode::Node(node) => node.is_leaf(),
+        }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        match self {
+            PageNode::Page(buf) => Page::from_slice(buf).count() as usize,
+            PageNode::Node(node) => node.num_children(),
+        }
+    }
+}
+
+impl RawBucket {
+    fn new(tx: WeakTx, path: Vec<Key>, bucket: InBucket, page: Option<Bytes>) -> Rc<Self> {
+        Rc::new_cyclic(|this| Self {
+            this: this.clone(),
+            bucket: RefCell::new(bucket),
+            path: RefCell::new(path),
+            tx,
+            buckets: RefCell::new(HashMap::new()),
+            page,
+            root_node: RefCell::new(None),
+            nodes: RefCell::new(HashMap::new()),
+            fill_percent: Cell::new(DEFAULT_FILL_PERCENT),
+            compression: Cell::new(Compression::None),
+            rebalance_percent: Cell::new(None),
+        })
+    }
+
+    /// rebalance_percent returns the fill below which nodes of the bucket are
+    /// merged, set on the bucket or else on the DB.
+    pub(crate) fn rebalance_percent(&self) -> f64 {
+        let percent = self.rebalance_percent.get().or_else(|| {
+            let db = self.tx().ok()?.db().ok()?;
+            Some(db.0.rebalance_percent())
+        });
+        (percent.unwrap_or(DEFAULT_REBALANCE_PERCENT)).clamp(0.0, MAX_REBALANCE_PERCENT)
+    }
+
+    /// rebalance_threshold returns the size in bytes below which nodes of
+    /// the bucket are merged.
+    pub(crate) fn rebalance_threshold(&self, page_size: usize) -> usize {
+        (page_size as f64 * self.rebalance_percent()) as usize
+    }
+
+    /// weak returns a weak reference to the bucket, for its nodes.
+    pub(crate) fn weak(&self) -> Weak<RawBucket> {
+        self.this.clone()
+    }
+
+    /// tx returns the transaction of the bucket.
+    pub(crate) fn tx(&self) -> Result<Tx> {
+        self.tx.upgrade().ok_or(BoltError::TxClosed)
+    }
+
+    /// root_page returns the root page id of the bucket.
+    pub(crate) fn root_page(&self) -> PgId {
+        self.bucket.borrow().root_page()
+    }
+
+    /// open_bucket creates the child bucket name from its bucket value.
+    /// Returns BoltError::Corrupted if the value is not a bucket header.
+    fn open_bucket(&self, name: &[u8], value: &Value) -> Result<Bucket<'static>> {
+        // The header is copied out since the value may be unaligned.
+        let bucket = InBucket::try_from_bytes(value).ok_or_else(|| {
+            let reason = format!("bucket header is truncated to {} bytes", value.len());
+            BoltError::corrupted(reason)
+                .with_bucket(&self.path.borrow())
+                .with_key(name)
+        })?;
+
+        // Share the inline page with the value if the bucket is inline.
+        // Pages are read byte-wise, so it need not be aligned.
+        let page = match bucket.root_page() {
+            0 => {
+                let page = value.slice(BUCKET_HEADER_SIZE..);
+                // Only a leaf fits inline, anything else would refer to pages.
+                let valid = Page::try_from_slice(&page)
+                    .is_ok_and(|p| p.is_leaf_page() && p.try_validate(page.len()).is_ok());
+                if !valid {
+                    return Err(
+                        BoltError::corrupted("inline bucket page is not a valid leaf")
+                            .with_bucket(&self.path.borrow())
+                            .with_key(name),
+                    );
+                }
+                Some(page)
+            }
+            _ => None,
+        };
+
+        let mut path = self.path.borrow().clone();
+        path.push(Key::copy_from_slice(name));
+        Ok(Bucket(
+            RawBucket::new(self.tx.clone(), path, bucket, page),
+            PhantomData,
+        ))
+    }
+
+    // insert_bucket writes an empty, inline bucket under key at the position
+    // of the cursor.
+    fn insert_bucket(&self, c: &mut Cursor<'_>, key: &[u8]) -> Result<()> {
+        // Create empty, inline bucket.
+        let bucket = RawBucket::new(self.tx.clone(), Vec::new(), InBucket::default(), None);
+        *bucket.root_node.borrow_mut() = Some(Node::new(&bucket, true, WeakNode::new())?);
+        let value = bucket.write();
+
+        // Insert into node.
+        let key = Key::copy_from_slice(key);
+        c.node()?.put(&key, key.clone(), value, 0, BUCKET_LEAF_FLAG);
+
+        // Go bbolt drops the inline page here since a bucket with sub-buckets
+        // can't stay inline. The root node was just materialized and page_node
+        // always prefers it, so the page is simply never looked at again.
+        Ok(())
+    }
+
+    // materialize_root makes sure the root node exists so the bucket is
+    // written out on commit.
+    fn materialize_root(&self) -> Result<()> {
+        if self.root_node.borrow().is_none() {
+            self.node(self.root_page(), WeakNode::new())?;
+        }
+        Ok(())
+    }
+
+    /// node creates a node from a page and associates it with a given parent.
+    pub(crate) fn node(&self, pgid: PgId, parent: WeakNode) -> Result<Node> {
+        // Retrieve node if it's already been created.
+        if let Some(node) = self.nodes.borrow().get(&pgid) {
+            return Ok(node.clone());
+        }
+
+        // Otherwise create a node and cache it.
+        let tx = self.tx()?;
+        let node = Node::new(self, false, parent.clone())?;
+        match parent.upgrade() {
+            None => *self.root_node.borrow_mut() = Some(node.clone()),
+            Some(parent) => parent.0.children.borrow_mut().push(node.clone()),
+        }
+
+        // Use the inline page if this is an inline bucket.
+        // Read the page into the node and cache it.
+        match &self.page {
+            Some(page) if self.root_page() == 0 => node.read(page),
+            _ => tx.page_bytes(pgid).and_then(|buf| {
+                node.read(&buf)?;
+                let overflow = Page::from_slice(&buf).overflow();
+                tx.borrow_page_for_node(pgid, overflow as u64 + 1);
+                Ok(())
+            }),
+        }
+        .map_err(|err| err.with_bucket(&self.path.borrow()))?;
+        self.nodes.borrow_mut().insert(pgid, node.clone());
+
+        // Update statistics.
+        tx.stats_mut().node_count += 1;
+
+        Ok(node)
+    }
+
+    /// rebalance attempts to balance all nodes.
+    pub(crate) fn rebalance(&self) -> Result<()> {
+        // Merging removes nodes from the cache, so walk a snapshot of the page
+        // ids and skip the nodes that are already gone.
+        let pgids: Vec<PgId> = self.nodes.borrow().keys().copied().collect();
+        for pgid in pgids {
+            let node = self.nodes.borrow().get(&pgid).cloned();
+            if let Some(node) = node {
+                node.rebalance()?;
+            }
+        }
+
+        let buckets: Vec<Bucket<'_>> = self.buckets.borrow().values().cloned().collect();
+        for child in buckets {
+            child.0.rebalance()?;
+        }
+
+        Ok(())
+    }
+
+    /// inlineable returns true if a bucket is small enough to be written inline
+    /// and if it contains no subbuckets. Otherwise returns false.
+    fn inlineable(&self) -> Result<bool> {
+        // Bucket must only contain a single leaf node.
+        let n = match self.root_node.borrow().clone() {
+            Some(n) if n.is_leaf() => n,
+            _ => return Ok(false),
+        };
+
+        // Bucket is not inlineable if it contains subbuckets or if it goes beyond
+        // our threshold for inline bucket size.
+        let max = self.max_inline_bucket_size()?;
+        let mut size = PAGE_HEADER_SIZE;
+        for inode in n.inodes().iter() {
+            size += LEAF_PAGE_ELEMENT_SIZE + inode.key().len() + inode.value().len();
+            if inode.flags() & BUCKET_LEAF_FLAG != 0 || size > max {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Returns the maximum total size of a bucket to make it a candidate for inlining.
+    fn max_inline_bucket_size(&self) -> Result<usize> {
+        Ok(self.tx()?.db()?.0.page_size() / 4)
+    }
+
+    /// write allocates and writes a bucket to a byte slice.
+    fn write(&self) -> Value {
+        let n = self
+            .root_node
+            .borrow()
+            .clone()
+            .expect("write: bucket has no root node");
+
+        // Convert the node into a fake page and write it.
+        let mut page = OwnedPage::new(n.size());
+        n.write(&mut page);
+
+        // Prefix the page with the bucket header.
+        let mut value = Vec::with_capacity(BUCKET_HEADER_SIZE + page.size());
+        value.extend_from_slice(&self.bucket.borrow().to_bytes());
+        value.extend_from_slice(page.buf());
+        Value::from(value)
+    }
+
+    /// free recursively frees all pages in the bucket.
+    pub(crate) fn free(&self) -> Result<()> {
+        if self.root_page() == 0 {
+            return Ok(());
+        }
+
+        let tx = self.tx()?;
+        let db = tx.db()?;
+        let mut page_nodes = Vec::new();
+        self.for_each_page_node(&mut |page_node, _| page_nodes.push(page_node.clone()))?;
+        for page_node in page_nodes {
+            match page_node {
+                PageNode::Page(buf) => db.0.freelist_mut()?.free(tx.id(), Page::from_slice(&buf)),
+                PageNode::Node(node) => node.free()?,
+            }
+        }
+        self.bucket.borrow_mut().set_root_page(0);
+
+        Ok(())
+    }
+
+    /// page_node returns the in-memory node, if it exists.
+    /// Otherwise returns the underlying page.
+    pub(crate) fn page_node(&self, id: PgId) -> Result<PageNode> {
+        // Inline buckets have a fake page embedded in their value so treat them
+        // differently. We'll return the root node (if available) or the fake page.
+        if self.root_page() == 0 {
+            assert!(
+                id == 0,
+                "inline bucket non-zero page access(2): {} != 0",
+                id
+            );
+            if let Some(root) = self.root_node.borrow().as_ref() {
+                return Ok(PageNode::Node(root.clone()));
+            }
+            let page = self.page.as_ref().expect("inline bucket has no page");
+            return PageNode::from_page(page.clone())
+                .map_err(|err| err.with_bucket(&self.path.borrow()));
+        }
+
+        // Check the node cache for non-inline buckets.
+        if let Some(node) = self.nodes.borrow().get(&id) {
+            return Ok(PageNode::Node(node.clone()));
+        }
+
+        // Finally lookup the page from the transaction if no node is materialized.
+        self.tx()?
+            .page_bytes(id)
+            .and_then(PageNode::from_page)
+            .map_err(|err| err.with_bucket(&self.path.borrow()))
+    }
+
+    /// for_each_page iterates over every committed page in a bucket, including
+    /// the inline page. The function is called with the depth and the page id stack.
+    pub(crate) fn for_each_page<F>(&self, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&Page, usize, &[PgId]),
+    {
+        // If we have an inline page then just use that.
+        if self.root_page() == 0 {
+            if let Some(page) = &self.page {
+                f(Page::from_slice(page), 0, &[self.root_page()]);
+            }
+            return Ok(());
+        }
+
+        // Otherwise traverse the page hierarchy.
+        self.tx()?.for_each_page_in(self.root_page(), f)
+    }
+
+    /// for_each_page_node iterates over every page (or node) in a bucket.
+    /// The function is called with the depth of each page.
+    pub(crate) fn for_each_page_node<F>(&self, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&PageNode, usize),
+    {
+        self.for_each_page_node_internal(self.root_page(), 0, f)
+    }
+
+    fn for_each_page_node_internal<F>(&self, pgid: PgId, depth: usize, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&PageNode, usize),
+    {
+        let page_node = self.page_node(pgid)?;
+
+        // Execute function.
+        f(&page_node, depth);
+
+        // Recursively loop over children.
+        let children: Vec<PgId> = match &page_node {
+            PageNode::Page(buf) => {
+                let page = Page::from_slice(buf);
+                match page.is_branch_page() {
+                    true => page
+                        .branch_page_elements()
+                        .iter()
+                        .map(|e| e.pgid())
+                        .collect(),
+                    false => Vec::new(),
+                }
+            }
+            PageNode::Node(node) => match node.is_leaf() {
+                true => Vec::new(),
+                false => node.inodes().iter().map(|i| i.pgid()).collect(),
+            },
+        };
+        for child in children {
+            self.for_each_page_node_internal(child, depth + 1, f)?;
+        }
+
+        Ok(())
+    }
+}
+
+// first_value moves a cursor from an item past nested buckets, forward or
+// backward, and returns the first key with a value.
+fn first_value<'tx>(
+    c: &mut Cursor<'tx>,
+    mut item: Option<(&'tx [u8], Option<&'tx [u8]>)>,
+    forward: bool,
+) -> Option<(&'tx [u8], &'tx [u8])> {
+    loop {
+        match item? {
+            (k, Some(v)) => return Some((k, v)),
+            (_, None) if forward => item = c.next(),
+            (_, None) => item = c.prev(),
+        }
+    }
+}
+
+// pop_value deletes the key first_value finds, returning it with its value.
+fn pop_value<'tx>(
+    c: &mut Cursor<'tx>,
+    item: Option<(&'tx [u8], Option<&'tx [u8]>)>,
+    forward: bool,
+) -> Result<Option<(&'tx [u8], &'tx [u8])>> {
+    let Some(found) = first_value(c, item, forward) else {
+        c.check()?;
+        return Ok(None);
+    };
+    c.delete()?;
+    Ok(Some(found))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    fn open(dir: &tempfile::TempDir) -> DB {
+        DB::open(dir.path().join("db"), Options::default()).unwrap()
+    }
+
+    // Ensure that a bucket that gets a non-existent key returns None.
+    #[test]
+    fn test_bucket_get_nonexistent() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            assert_eq!(b.get(b"foo"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that committed values are borrowed from the mmap, and that values
+    // of dirty nodes stay valid after their key is overwritten.
+    #[test]
+    fn test_bucket_get_zero_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            // Enough keys for the bucket not to be inline.
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..100u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.put(b"foo", b"bar")
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let committed = b.get(b"foo").unwrap();
+            let data = db.0.data()?;
+            assert!(data.as_ptr_range().contains(&committed.as_ptr()));
+
+            b.put(b"foo", b"baz")?;
+            let dirty = b.get(b"foo").unwrap();
+            b.put(b"foo", b"qux")?;
+            b.delete(b"foo")?;
+            assert_eq!(committed, b"bar");
+            assert_eq!(dirty, b"baz");
+            assert_eq!(b.get(b"foo"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that values larger than the buffers of the reader and the
+    // writer are streamed to pages of their own and back, that a value
+    // overwritten before the commit frees its pages, and that a reader ending
+    // early leaves the key untouched.
+    #[test]
+    fn test_bucket_put_reader() {
+        // Chunked reads and writes at most 1000 bytes at a time.
+        struct Chunked<T>(T, usize);
+        impl<R: Read> Read for Chunked<R> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(1000);
+                self.0.read(&mut buf[..len])
+            }
+        }
+        impl<W: Write> Write for Chunked<W> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.1 += 1;
+                self.0.write(&buf[..buf.len().min(1000)])
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.0.flush()
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let value = |i: usize| -> Vec<u8> { (0..3 << 20).map(|j| ((i + j) % 251) as u8).collect() };
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..100u32 {
+                b.put(format!("{:04}", i).as_bytes(), b"v")?;
+            }
+            // The values are read into pages allocated right away.
+            let before = tx.stats();
+            for (i, key) in [&b"0010a"[..], b"0050a", b"0050b", b"0090a"]
+                .iter()
+                .enumerate()
+            {
+                b.put_reader(key, Chunked(&value(i)[..], 0), 3 << 20)?;
+            }
+            let after = tx.stats();
+            assert_eq!(after.page_count, before.page_count + 4);
+            assert!(after.page_alloc >= before.page_alloc + 4 * (3 << 20));
+            b.put_reader(b"0090a", Chunked(&value(9)[..], 0), 3 << 20)?;
+            b.put(b"0050b", b"small")?;
+
+            let err = b.put_reader(b"short", &value(0)[..10], 11).unwrap_err();
+            match err {
+                BoltError::Io { op, source, .. } => {
+                    assert_eq!(op, IoOp::Read);
+                    assert_eq!(source.kind(), std::io::ErrorKind::UnexpectedEof);
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+            assert_eq!(b.get(b"short"), None);
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            for (i, key) in [(0, &b"0010a"[..]), (1, b"0050a"), (9, b"0090a")] {
+                let mut out = Chunked(Vec::new(), 0);
+                assert_eq!(b.get_writer(key, &mut out)?, Some(3 << 20));
+                assert!(out.0 == value(i));
+                assert!(out.1 >= (3 << 20) / 1000);
+            }
+            assert_eq!(b.get(b"0050b"), Some(&b"small"[..]));
+            assert_eq!(b.get(b"0051"), Some(&b"v"[..]));
+            assert_eq!(b.get_writer(b"missing", Vec::new())?, None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that get_many finds the same values as get, in the order of the
+    // keys, both on committed pages and on dirty nodes.
+    #[test]
+    fn test_bucket_get_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let key = |i: u32| format!("{:08}", i).into_bytes();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in (0..20000).step_by(2) {
+                b.put(&key(i), &key(i * 3))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let keys: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| key(i.wrapping_mul(7919) % 20001))
+            .chain([key(4), b"".to_vec(), b"zzz".to_vec(), key(4)])
+            .collect();
+        let check = |b: &Bucket| {
+            let values = b.get_many(&keys).unwrap();
+            assert_eq!(values.len(), keys.len());
+            for (key, value) in keys.iter().zip(values) {
+                assert_eq!(value, b.get(key));
+            }
+        };
+        db.view(|tx| {
+            check(&tx.bucket(b"widgets").unwrap());
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            b.put(&key(5), b"dirty")?;
+            b.delete(&key(6))?;
+            check(&b);
+            assert_eq!(
+                b.get_many(&[key(5), key(6)])?,
+                vec![Some(&b"dirty"[..]), None]
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a bucket can write key/value pairs that survive a reopen.
+    #[test]
+    fn test_bucket_put_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put(b"foo", b"bar")?;
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+        drop(db);
+
+        let db = open(&dir);
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").expect("bucket not found");
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a bucket large enough to split is written out across pages.
+    #[test]
+    fn test_bucket_put_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..2000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &i.to_be_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_ne!(b.root(), 0, "expected a non-inline bucket");
+            for i in 0..2000u32 {
+                let v = b.get(format!("{:08}", i).as_bytes());
+                assert_eq!(v, Some(&i.to_be_bytes()[..]));
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that deleting keys reclaims their pages on commit.
+    #[test]
+    fn test_bucket_delete_reclaims_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let value = [0x42u8; 100];
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &value)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            for i in 0..1000u32 {
+                b.delete(format!("{:08}", i).as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.cursor().first(), None);
+            Ok(())
+        })
+        .unwrap();
+        assert!(db.stats().free_page_n + db.stats().pending_page_n > 20);
+    }
+
+    // Ensure that nested buckets are stored inline while small and moved to
+    // their own pages once they grow.
+    #[test]
+    fn test_bucket_nested() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.create_bucket(b"foo")?.put(b"bar", b"baz")?;
+            b.put(b"baz", b"bat")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let foo = tx.bucket(b"widgets").unwrap().bucket(b"foo").unwrap();
+            assert_eq!(foo.root(), 0, "expected an inline bucket");
+            assert_eq!(foo.get(b"bar"), Some(&b"baz"[..]));
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let foo = tx.bucket(b"widgets").unwrap().bucket(b"foo").unwrap();
+            for i in 0..100u32 {
+                foo.put(format!("{:03}", i).as_bytes(), &[0u8; 64])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let widgets = tx.bucket(b"widgets").unwrap();
+            let foo = widgets.bucket(b"foo").unwrap();
+            assert_ne!(foo.root(), 0, "expected a non-inline bucket");
+            assert_eq!(foo.get(b"bar"), Some(&b"baz"[..]));
+            assert_eq!(foo.get(b"099"), Some(&[0u8; 64][..]));
+            assert_eq!(widgets.get(b"baz"), Some(&b"bat"[..]));
+            assert_eq!(widgets.get(b"foo"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that deleting a bucket removes it along with its children.
+    #[test]
+    fn test_bucket_delete_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let widgets = tx.create_bucket(b"widgets")?;
+            let foo = widgets.create_bucket(b"foo")?;
+            foo.create_bucket(b"bar")?.put(b"baz", b"bat")?;
+            for i in 0..500u32 {
+                foo.put(format!("{:08}", i).as_bytes(), &[1u8; 32])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let widgets = tx.bucket(b"widgets").unwrap();
+            widgets.delete_bucket(b"foo")?;
+            assert!(widgets.bucket(b"foo").is_none());
+            assert_eq!(
+                widgets.delete_bucket(b"foo"),
+                Err(BoltError::BucketNotFound)
+            );
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert!(tx.bucket(b"widgets").unwrap().bucket(b"foo").is_none());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that bucket and key misuse is reported.
+    #[test]
+    fn test_bucket_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            assert_eq!(
+                tx.create_bucket(b"widgets").err(),
+                Some(BoltError::BucketExists)
+            );
+            assert_eq!(
+                tx.create_bucket(b"").err(),
+                Some(BoltError::BucketNameRequired)
+            );
+            assert!(tx.create_bucket_if_not_exists(b"widgets").is_ok());
+
+            b.put(b"foo", b"bar")?;
+            assert_eq!(
+                b.create_bucket(b"foo").err(),
+                Some(BoltError::IncompatibleValue)
+            );
+            b.create_bucket(b"sub")?;
+            assert_eq!(b.put(b"sub", b"x"), Err(BoltError::IncompatibleValue));
+            assert_eq!(b.delete(b"sub"), Err(BoltError::IncompatibleValue));
+            assert_eq!(b.put(b"", b"x"), Err(BoltError::KeyRequired));
+            assert_eq!(
+                b.put(&vec![0u8; MAX_KEY_SIZE + 1], b"x"),
+                Err(BoltError::KeyTooLarge)
+            );
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.put(b"foo", b"baz"), Err(BoltError::TxNotWritable));
+            assert_eq!(b.delete(b"foo"), Err(BoltError::TxNotWritable));
+            assert_eq!(tx.create_bucket(b"x").err(), Some(BoltError::TxNotWritable));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that the size limits set in the options are enforced for keys,
+    // bucket names and values, and cannot exceed those of the format.
+    #[test]
+    fn test_bucket_size_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            max_key_size: 8,
+            max_value_size: 16,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put(b"12345678", &[0; 16])?;
+            assert_eq!(b.put(b"123456789", b"x"), Err(BoltError::KeyTooLarge));
+            assert_eq!(b.put(b"foo", &[0; 17]), Err(BoltError::ValueTooLarge));
+            assert_eq!(
+                b.put_reader(b"foo", &[0; 17][..], 17),
+                Err(BoltError::ValueTooLarge)
+            );
+            assert_eq!(
+                tx.create_bucket(b"123456789").err(),
+                Some(BoltError::KeyTooLarge)
+            );
+            assert_eq!(
+                b.create_bucket_if_not_exists(b"123456789").err(),
+                Some(BoltError::KeyTooLarge)
+            );
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+
+        let options = Options {
+            max_value_size: MAX_VALUE_SIZE + 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            DB::open(dir.path().join("db"), options).err(),
+            Some(BoltError::Config(_))
+        ));
+    }
+
+    // Ensure that conditional writes only happen when the value matches.
+    #[test]
+    fn test_bucket_put_if_delete_if() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put_if(b"foo", None, b"bar")?;
+            assert_eq!(
+                b.put_if(b"foo", None, b"baz"),
+                Err(BoltError::ValueMismatch)
+            );
+            assert_eq!(
+                b.put_if(b"foo", Some(b"qux"), b"baz"),
+                Err(BoltError::ValueMismatch)
+            );
+            b.put_if(b"foo", Some(b"bar"), b"baz")?;
+            assert_eq!(b.get(b"foo"), Some(&b"baz"[..]));
+
+            assert_eq!(b.delete_if(b"foo", b"bar"), Err(BoltError::ValueMismatch));
+            assert_eq!(b.delete_if(b"missing", b""), Err(BoltError::ValueMismatch));
+            b.delete_if(b"foo", b"baz")?;
+            assert_eq!(b.get(b"foo"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that counters start from zero, add up and wrap around, and that
+    // values other than counters are refused.
+    #[test]
+    fn test_bucket_increment() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"counters")?;
+            assert_eq!(b.increment(b"visits", 1)?, 1);
+            assert_eq!(b.increment(b"visits", 41)?, 42);
+            assert_eq!(b.get(b"visits"), Some(&42u64.to_le_bytes()[..]));
+            assert_eq!(b.increment(b"visits", u64::MAX)?, 41);
+
+            b.put(b"name", b"foo")?;
+            assert_eq!(b.increment(b"name", 1), Err(BoltError::IncompatibleValue));
+            b.create_bucket(b"sub")?;
+            assert_eq!(b.increment(b"sub", 1), Err(BoltError::IncompatibleValue));
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"counters").unwrap();
+            assert_eq!(b.increment(b"visits", 1), Err(BoltError::TxNotWritable));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that contains_key and key_count see committed and dirty keys.
+    #[test]
+    fn test_bucket_contains_key_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            assert_eq!(b.key_count()?, 0);
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0; 50])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.key_count()?, 1001);
+            assert!(b.contains_key(b"0500")?);
+            assert!(!b.contains_key(b"050")?);
+            assert!(!b.contains_key(b"sub")?);
+
+            b.delete(b"0500")?;
+            b.put(b"9999", b"")?;
+            assert!(!b.contains_key(b"0500")?);
+            assert!(b.contains_key(b"9999")?);
+            assert_eq!(b.key_count()?, 1001);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that the first and last values are found and popped past
+    // nested buckets.
+    #[test]
+    fn test_bucket_first_last_pop() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            assert_eq!(b.first(), None);
+            assert_eq!(b.pop_last(), Ok(None));
+            b.create_bucket(b"a")?;
+            b.create_bucket(b"z")?;
+            for k in [b"b", b"c", b"d"] {
+                b.put(k, &[k[0]; 2])?;
+            }
+            assert_eq!(b.first(), Some((&b"b"[..], &b"bb"[..])));
+            assert_eq!(b.last(), Some((&b"d"[..], &b"dd"[..])));
+
+            assert_eq!(b.pop_first()?, Some((&b"b"[..], &b"bb"[..])));
+            assert_eq!(b.pop_last()?, Some((&b"d"[..], &b"dd"[..])));
+            assert_eq!(b.pop_last()?, Some((&b"c"[..], &b"cc"[..])));
+            assert_eq!(b.pop_first()?, None);
+            assert!(b.bucket(b"a").is_some());
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.pop_first(), Err(BoltError::TxNotWritable));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a bucket sequence persists across transactions.
+    #[test]
+    fn test_bucket_next_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            assert_eq!(b.next_sequence()?, 1);
+            assert_eq!(b.next_sequence()?, 2);
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.sequence(), 2);
+            b.set_sequence(1000)?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.bucket(b"widgets").unwrap().sequence(), 1000);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that for_each visits every key and names nested buckets.
+    #[test]
+    fn test_bucket_for_each() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put(b"foo", b"0000")?;
+            b.put(b"baz", b"0001")?;
+            b.create_bucket(b"bar")?;
+
+            let mut items = Vec::new();
+            b.for_each(|k, v| {
+                items.push((k.to_vec(), v.map(|v| v.to_vec())));
+                Ok(())
+            })?;
+            assert_eq!(
+                items,
+                vec![
+                    (b"bar".to_vec(), None),
+                    (b"baz".to_vec(), Some(b"0001".to_vec())),
+                    (b"foo".to_vec(), Some(b"0000".to_vec())),
+                ]
+            );
+
+            // The error from the callback stops the iteration.
+            let mut n = 0;
+            let err = b.for_each(|_, _| {
+                n += 1;
+                Err(BoltError::Unexpected("marker"))
+            });
+            assert_eq!(err, Err(BoltError::Unexpected("marker")));
+            assert_eq!(n, 1);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that stats account for keys, pages and inline sub-buckets.
+    #[test]
+    fn test_bucket_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..500u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0u8; 32])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let s = tx.bucket(b"widgets").unwrap().stats()?;
+            assert_eq!(s.key_n, 502);
+            assert_eq!(s.bucket_n, 2);
+            assert_eq!(s.inline_bucket_n, 1);
+            assert!(s.inline_bucket_inuse > 0);
+            assert_eq!(s.branch_page_n, 1);
+            assert!(s.leaf_page_n > 1);
+            assert_eq!(s.depth, 3);
+            assert_eq!(s.leaf_alloc, s.leaf_page_n * 4096);
+            assert!(s.leaf_inuse <= s.leaf_alloc);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that the rebalance threshold follows the options and the bucket,
+    // and that lowering it leaves sparse nodes unmerged.
+    #[test]
+    fn test_bucket_rebalance_percent() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            rebalance_percent: Some(0.1),
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        db.update(|tx| {
+            for name in [&b"default"[..], b"lazy"] {
+                let b = tx.create_bucket(name)?;
+                for i in 0..2000u32 {
+                    b.put(format!("{:08}", i).as_bytes(), &[0u8; 32])?;
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let b = tx.bucket(b"default").unwrap();
+            assert_eq!(b.rebalance_percent(), 0.1);
+            b.set_rebalance_percent(1.0);
+            assert_eq!(b.rebalance_percent(), MAX_REBALANCE_PERCENT);
+            b.set_rebalance_percent(DEFAULT_REBALANCE_PERCENT);
+            tx.bucket(b"lazy").unwrap().set_rebalance_percent(0.0);
+            for name in [&b"default"[..], b"lazy"] {
+                let b = tx.bucket(name).unwrap();
+                for i in (0..2000u32).filter(|i| i % 4 != 0) {
+                    b.delete(format!("{:08}", i).as_bytes())?;
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let default = tx.bucket(b"default").unwrap().stats()?;
+            let lazy = tx.bucket(b"lazy").unwrap().stats()?;
+            assert_eq!(default.rebalance_threshold, 409);
+            assert_eq!(default.key_n, 500);
+            assert_eq!(lazy.key_n, 500);
+            assert!(lazy.leaf_page_n > default.leaf_page_n);
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that values are compressed on disk and read back transparently.
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_bucket_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        let value = b"compressible ".repeat(100);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.set_compression(Compression::Lz4);
+            b.put(b"foo", &value)?;
+            b.put(b"bar", b"baz")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            // Without compression the stored bytes are returned.
+            let stored = b.get(b"foo").unwrap();
+            assert!(stored.len() < value.len() / 4);
+
+            b.set_compression(Compression::Lz4);
+            assert_eq!(b.get(b"foo"), Some(&value[..]));
+            let mut c = b.cursor();
+            assert_eq!(c.first(), Some((&b"bar"[..], Some(&b"baz"[..]))));
+            assert_eq!(c.next(), Some((&b"foo"[..], Some(&value[..]))));
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! Bulk loading of sorted keys into a new bucket.
+//!
+//! Put seeks the leaf of every key and spill splits the nodes that grew too
+//! large, which dominates the cost of filling a bucket with millions of keys.
+//! When the keys arrive sorted the tree can instead be built bottom-up: leaf
+//! pages are filled in order and written as soon as they are full, and the
+//! first key of each page is pushed onto the branch page above it, which is
+//! written the same way.
+
+use std::mem;
+
+use crate::bucket::{Bucket, MAX_FILL_PERCENT, MIN_FILL_PERCENT};
+use crate::common::inode::{write_inode_to_page, Inode, Inodes, Key, Value};
+use crate::common::page::{
+    PageFlags, PgId, BRANCH_PAGE_ELEMENT_SIZE, BUCKET_LEAF_FLAG, LEAF_PAGE_ELEMENT_SIZE,
+    PAGE_HEADER_SIZE,
+};
+use crate::compression::Compression;
+use crate::errors::{BoltError, Result};
+
+/// BulkLoader fills a new bucket with keys given in ascending order. Pages
+/// are written as they fill up, without seeking a cursor or splitting a node
+/// for every key, which makes loading large amounts of data much faster than
+/// put.
+///
+/// The bucket is created empty along with the loader and gets its contents
+/// once finish is called; until then it must not be used. A loader dropped
+/// without calling finish leaves the bucket empty.
+#[derive(Debug)]
+pub struct BulkLoader<'tx> {
+    parent: Bucket<'tx>,
+    name: Key,
+    bucket: Bucket<'tx>,
+    // Usable size of a page, without the overhead of encryption.
+    page_size: usize,
+    // Page being filled at each level of the tree, the leaves first.
+    levels: Vec<Level>,
+    // Pages written so far, freed again if the load is abandoned.
+    pages: Vec<PgId>,
+    last: Option<Key>,
+    finished: bool,
+}
+
+#[derive(Debug)]
+struct Level {
+    inodes: Inodes,
+    // Size of the page holding the inodes.
+    size: usize,
+    // Whether a page of this level was written already.
+    written: bool,
+}
+
+impl Level {
+    fn new() -> Level {
+        Level {
+            inodes: Inodes::default(),
+            size: PAGE_HEADER_SIZE,
+            written: false,
+        }
+    }
+}
+
+impl<'tx> BulkLoader<'tx> {
+    /// new creates the bucket name in parent and returns a loader for it.
+    pub(crate) fn new(parent: &Bucket<'tx>, name: &[u8]) -> Result<BulkLoader<'tx>> {
+        let bucket = parent.create_bucket(name)?;
+        let db = bucket.tx()?.db()?;
+        Ok(BulkLoader {
+            parent: parent.clone(),
+            name: Key::copy_from_slice(name),
+            bucket,
+            page_size: db.0.page_size() - db.0.crypt().overhead(),
+            levels: Vec::new(),
+            pages: Vec::new(),
+            last: None,
+            finished: false,
+        })
+    }
+
+    /// set_fill_percent sets how full the pages are filled, like
+    /// Bucket::set_fill_percent does for splits. Pages of data that is
+    /// mostly read are best filled completely.
+    pub fn set_fill_percent(&self, fill_percent: f64) {
+        self.bucket.set_fill_percent(fill_percent);
+    }
+
+    /// set_compression sets how the values of the bucket are compressed,
+    /// like Bucket::set_compression.
+    pub fn set_compression(&self, compression: Compression) {
+        self.bucket.set_compression(compression);
+    }
+
+    /// put adds a key to the bucket, which must sort after the previous one.
+    /// Returns BoltError::KeyOutOfOrder if it does not, and the errors of
+    /// Bucket::put otherwise.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.bucket.check_put(key, value.len())?;
+        if self.last.as_ref().is_some_and(|last| key <= &last[..]) {
+            return Err(BoltError::KeyOutOfOrder);
+        }
+
+        let key = Key::copy_from_slice(key);
+        let mut inode = Inode::default();
+        inode.set_key(key.clone());
+        inode.set_value(Value::from(self.bucket.compression().compress(value)?));
+        self.push(0, inode)?;
+
+        if self.bucket.is_capturing() {
+            self.bucket.record_change(&key, None, Some(value.to_vec()));
+        }
+        self.last = Some(key);
+        Ok(())
+    }
+
+    /// finish writes the pages still being filled and makes the loaded keys
+    /// the contents of the bucket.
+    pub fn finish(mut self) -> Result<()> {
+        let mut level = 0;
+        let root = loop {
+            if level == self.levels.len() {
+                // Nothing was loaded, the bucket stays empty.
+                self.finished = true;
+                return Ok(());
+            }
+
+            // The root is the first page of the topmost level.
+            if level + 1 == self.levels.len() && !self.levels[level].written {
+                let top = mem::replace(&mut self.levels[level], Level::new());
+                break self.write(level, &top)?;
+            }
+            self.flush(level)?;
+            level += 1;
+        };
+
+        // Point the bucket and its header in the parent at the new root.
+        let raw = &self.bucket.0;
+        raw.bucket.borrow_mut().set_root_page(root);
+        *raw.root_node.borrow_mut() = None;
+        raw.nodes.borrow_mut().clear();
+        let header = Value::copy_from_slice(&raw.bucket.borrow().to_bytes());
+
+        let mut c = self.parent.cursor();
+        match c.seek_item(&self.name)? {
+            Some((k, _, flags)) if k == self.name && flags & BUCKET_LEAF_FLAG != 0 => {}
+            _ => return Err(BoltError::BucketNotFound),
+        }
+        c.node()?
+            .put(&self.name, self.name.clone(), header, 0, BUCKET_LEAF_FLAG);
+        self.finished = true;
+        Ok(())
+    }
+
+    // push adds an inode to the page being filled at a level, writing the
+    // page first if the inode does not fit anymore.
+    fn push(&mut self, level: usize, inode: Inode) -> Result<()> {
+        if level == self.levels.len() {
+            self.levels.push(Level::new());
+        }
+        let (elem_size, min_keys) = match level {
+            0 => (LEAF_PAGE_ELEMENT_SIZE, 1),
+            _ => (BRANCH_PAGE_ELEMENT_SIZE, 2),
+        };
+        let size = elem_size + inode.key().len() + inode.value().len();
+        let fill_percent = self
+            .bucket
+            .fill_percent()
+            .clamp(MIN_FILL_PERCENT, MAX_FILL_PERCENT);
+        let threshold = (self.page_size as f64 * fill_percent) as usize;
+
+        let page = &self.levels[level];
+        if page.inodes.len() >= min_keys
+            && (page.size + size > threshold || page.inodes.len() + 1 >= u16::MAX as usize)
+        {
+            self.flush(level)?;
+        }
+        let page = &mut self.levels[level];
+        page.size += size;
+        page.inodes.push(inode);
+        Ok(())
+    }
+
+    // flush writes the page being filled at a level and adds it to the page
+    // of the level above.
+    fn flush(&mut self, level: usize) -> Result<()> {
+        let page = mem::replace(&mut self.levels[level], Level::new());
+        self.levels[level].written = true;
+        let id = self.write(level, &page)?;
+
+        let mut inode = Inode::default();
+        inode.set_key(page.inodes.get(0).key().clone());
+        inode.set_pgid(id);
+        self.push(level + 1, inode)
+    }
+
+    // write writes a page of a level to dirty pages, returning its id.
+    fn write(&mut self, level: usize, page: &Level) -> Result<PgId> {
+        let tx = self.bucket.tx()?;
+        let page_size = tx.db()?.0.page_size();
+        let overhead = page_size - self.page_size;
+        let id = tx.allocate((page.size + overhead).div_ceil(page_size))?;
+        let flags = match level {
+            0 => PageFlags::LEAF_PAGE,
+            _ => PageFlags::BRANCH_PAGE,
+        };
+        tx.write_page(id, |buf| {
+            buf.set_flags(flags);
+            buf.set_count(page.inodes.len() as u16);
+            write_inode_to_page(&page.inodes, buf.buf_mut());
+        });
+        self.pages.push(id);
+        tx.stats_mut().spill += 1;
+        Ok(id)
+    }
+}
+
+impl Drop for BulkLoader<'_> {
+    // Free the pages of an abandoned load, which nothing refers to.
+    fn drop(&mut self) {
+        if self.finished || self.pages.is_empty() {
+            return;
+        }
+        let Ok(tx) = self.bucket.tx() else {
+            return;
+        };
+        let Ok(db) = tx.db() else {
+            return;
+        };
+        let Ok(mut freelist) = db.0.freelist_mut() else {
+            return;
+        };
+        for &id in &self.pages {
+            if let Ok(page) = tx.page(id) {
+                freelist.free(tx.id(), &page);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("{:08}", i).into_bytes()
+    }
+
+    // Ensure that a bulk loaded bucket holds all its keys, in the same and
+    // later transactions, and that the tree built is consistent.
+    #[test]
+    fn test_bulk_loader() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let mut loader = tx.bulk_loader(b"widgets")?;
+            loader.set_fill_percent(1.0);
+            for i in 0..50000 {
+                loader.put(&key(i), &[0x5a; 20])?;
+            }
+            // Values spilling over several pages fit as well.
+            loader.put(b"zz", &[7; 10000])?;
+            assert_eq!(loader.put(b"zz", b"x"), Err(BoltError::KeyOutOfOrder));
+            loader.finish()?;
+
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(&key(12345)), Some(&[0x5a; 20][..]));
+            b.put(&key(50000), b"after")?;
+            assert!(b.stats()?.depth > 2);
+            assert_eq!(
+                tx.bulk_loader(b"widgets").err(),
+                Some(BoltError::BucketExists)
+            );
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let mut c = b.cursor();
+            let mut n = 0;
+            let mut item = c.first();
+            while let Some((k, _)) = item {
+                if n <= 50000 {
+                    assert_eq!(k, &key(n)[..]);
+                }
+                n += 1;
+                item = c.next();
+            }
+            assert_eq!(n, 50002);
+            assert_eq!(b.get(b"zz"), Some(&[7; 10000][..]));
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that the pages of an abandoned load are freed.
+    #[test]
+    fn test_bulk_loader_abandoned() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let widgets = tx.create_bucket(b"widgets")?;
+            let mut loader = widgets.bulk_loader(b"sub")?;
+            for i in 0..10000 {
+                loader.put(&key(i), &[0x5a; 20])?;
+            }
+            drop(loader);
+            assert_eq!(widgets.bucket(b"sub").unwrap().get(&key(1)), None);
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
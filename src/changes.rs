@@ -0,0 +1,222 @@
+//! Change capture for committed write transactions.
+//!
+//! Observers registered with DB::add_observer receive the keys a transaction
+//! put or deleted, with their previous and new values, once it commits. Keys
+//! are only recorded while at least one observer is registered, so databases
+//! without observers pay nothing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::bucket::Bucket;
+use crate::db::DB;
+
+/// Change is a key that was put or deleted by a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// Names of the bucket holding the key and of its ancestors, from the root.
+    pub bucket: Vec<Vec<u8>>,
+    /// Key that changed.
+    pub key: Vec<u8>,
+    /// Value before the transaction changed it, None if the key was created.
+    pub old: Option<Vec<u8>>,
+    /// Value after the change, None if the key was deleted.
+    pub new: Option<Vec<u8>>,
+}
+
+/// ChangeSet is the list of changes a transaction committed, in the order
+/// they were made. A key changed several times appears once per change.
+/// Deleting a bucket reports the deletion of every key in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeSet {
+    /// Id of the committed transaction.
+    pub txid: u64,
+    /// Changes made by the transaction.
+    pub changes: Vec<Change>,
+}
+
+/// ObserverId identifies an observer, to remove it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+// Observer is called with each change set. It returns false once it is not
+// interested anymore, to be removed.
+pub(crate) type Observer = Arc<dyn Fn(&ChangeSet) -> bool + Send + Sync>;
+
+/// Observers holds the observers registered with a database.
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: AtomicU64,
+    list: RwLock<Vec<(ObserverId, Observer)>>,
+}
+
+impl Observers {
+    /// is_empty returns whether there is no observer to record changes for.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list.read().unwrap().is_empty()
+    }
+
+    /// add registers an observer.
+    pub(crate) fn add(&self, f: Observer) -> ObserverId {
+        let id = ObserverId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.list.write().unwrap().push((id, f));
+        id
+    }
+
+    /// remove unregisters an observer. Returns false if it was already removed.
+    pub(crate) fn remove(&self, id: ObserverId) -> bool {
+        let mut list = self.list.write().unwrap();
+        let len = list.len();
+        list.retain(|(other, _)| *other != id);
+        list.len() != len
+    }
+
+    /// notify calls every observer with a committed change set.
+    pub(crate) fn notify(&self, changes: &ChangeSet) {
+        // Observers may add or remove observers, so call them unlocked.
+        let observers: Vec<(ObserverId, Observer)> = self.list.read().unwrap().clone();
+        for (id, f) in observers {
+            if !f(changes) {
+                self.remove(id);
+            }
+        }
+    }
+}
+
+impl DB {
+    /// add_observer registers a function called with the changes of every
+    /// write transaction that commits and changed at least one key.
+    ///
+    /// Observers run on the committing thread before the writer lock is
+    /// released, so they see change sets in commit order. They must return
+    /// quickly and must not start write transactions, which would deadlock.
+    pub fn add_observer<F>(&self, f: F) -> ObserverId
+    where
+        F: Fn(&ChangeSet) + Send + Sync + 'static,
+    {
+        self.0.observers.add(Arc::new(move |changes| {
+            f(changes);
+            true
+        }))
+    }
+
+    /// remove_observer unregisters an observer.
+    /// Returns false if the observer was already removed.
+    pub fn remove_observer(&self, id: ObserverId) -> bool {
+        self.0.observers.remove(id)
+    }
+}
+
+impl Bucket<'_> {
+    /// is_capturing returns whether changes to keys of the bucket are recorded.
+    pub(crate) fn is_capturing(&self) -> bool {
+        !self.is_ttl_index()
+            && self
+                .tx()
+                .is_ok_and(|tx| tx.0.changes.lock().unwrap().is_some())
+    }
+
+    /// record_change records a change to a key of the bucket, if the
+    /// transaction captures changes.
+    pub(crate) fn record_change(&self, key: &[u8], old: Option<Vec<u8>>, new: Option<Vec<u8>>) {
+        if self.is_ttl_index() {
+            return;
+        }
+        let tx = match self.tx() {
+            Ok(tx) => tx,
+            Err(_) => return,
+        };
+        let mut changes = tx.0.changes.lock().unwrap();
+        if let Some(changes) = changes.as_mut() {
+            changes.push(Change {
+                bucket: self
+                    .0
+                    .path
+                    .borrow()
+                    .iter()
+                    .map(|name| name.to_vec())
+                    .collect(),
+                key: key.to_vec(),
+                old,
+                new,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::db::Options;
+    use crate::errors::BoltError;
+
+    fn change(bucket: &[&[u8]], key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> Change {
+        Change {
+            bucket: bucket.iter().map(|name| name.to_vec()).collect(),
+            key: key.to_vec(),
+            old: old.map(|v| v.to_vec()),
+            new: new.map(|v| v.to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let id = {
+            let seen = seen.clone();
+            db.add_observer(move |changes: &ChangeSet| seen.lock().unwrap().push(changes.clone()))
+        };
+
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put(b"foo", b"1")?;
+            b.put(b"foo", b"2")?;
+            b.create_bucket(b"sub")?.put(b"bar", b"3")?;
+            Ok(())
+        })
+        .unwrap();
+        // Rolled back and empty transactions are not reported.
+        let _ = db.update(|tx| {
+            tx.bucket(b"widgets").unwrap().put(b"baz", b"4")?;
+            Err::<(), _>(BoltError::Invalid)
+        });
+        db.update(|_| Ok(())).unwrap();
+        let txid = db
+            .update(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                b.delete(b"foo")?;
+                b.delete(b"missing")?;
+                b.delete_bucket(b"sub")?;
+                Ok(tx.id())
+            })
+            .unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(
+            seen[0].changes,
+            vec![
+                change(&[b"widgets"], b"foo", None, Some(b"1")),
+                change(&[b"widgets"], b"foo", Some(b"1"), Some(b"2")),
+                change(&[b"widgets", b"sub"], b"bar", None, Some(b"3")),
+            ]
+        );
+        assert_eq!(seen[1].txid, txid);
+        assert_eq!(
+            seen[1].changes,
+            vec![
+                change(&[b"widgets"], b"foo", Some(b"2"), None),
+                change(&[b"widgets", b"sub"], b"bar", Some(b"3"), None),
+            ]
+        );
+        drop(seen);
+
+        assert!(db.remove_observer(id));
+        assert!(!db.remove_observer(id));
+        db.close().unwrap();
+    }
+}
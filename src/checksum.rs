@@ -0,0 +1,136 @@
+//! Checksums of meta pages.
+//!
+//! Go bbolt checksums meta pages with FNV-1a, which is what every version 2
+//! database uses. A new database can pick a stronger algorithm instead; its
+//! id is recorded in the flags of the meta pages, which makes the database
+//! format version 3:
+//!
+//! ```text
+//! flags & 0xff00 == 0x0000    FNV-1a
+//! flags & 0xff00 == 0x0100    CRC-32C
+//! flags & 0xff00 == 0x0200    XXH3
+//! ```
+
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+
+// META_CHECKSUM_SHIFT is the position of the checksum id in the meta flags.
+const META_CHECKSUM_SHIFT: u32 = 8;
+
+/// META_CHECKSUM_MASK masks the checksum id in the meta flags.
+pub(crate) const META_CHECKSUM_MASK: u32 = 0xff << META_CHECKSUM_SHIFT;
+
+/// Checksum computes the checksum of the bytes of a meta page.
+pub(crate) trait Checksum {
+    /// sum64 returns the checksum of data.
+    fn sum64(&self, data: &[u8]) -> u64;
+}
+
+struct Fnv;
+
+impl Checksum for Fnv {
+    fn sum64(&self, data: &[u8]) -> u64 {
+        let mut h = FnvHasher::default();
+        h.write(data);
+        h.finish()
+    }
+}
+
+#[cfg(feature = "crc32c")]
+struct Crc32c;
+
+#[cfg(feature = "crc32c")]
+impl Checksum for Crc32c {
+    fn sum64(&self, data: &[u8]) -> u64 {
+        crc32c::crc32c(data) as u64
+    }
+}
+
+#[cfg(feature = "xxhash")]
+struct Xxh3;
+
+#[cfg(feature = "xxhash")]
+impl Checksum for Xxh3 {
+    fn sum64(&self, data: &[u8]) -> u64 {
+        xxhash_rust::xxh3::xxh3_64(data)
+    }
+}
+
+/// ChecksumType selects how the meta pages of a new database are
+/// checksummed. Databases are always opened with the algorithm they were
+/// created with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumType {
+    /// FNV-1a, as in Go bbolt.
+    #[default]
+    Fnv,
+    /// CRC-32C, hardware accelerated on x86_64 and aarch64.
+    #[cfg(feature = "crc32c")]
+    Crc32c,
+    /// XXH3, 64 bits and SIMD accelerated.
+    #[cfg(feature = "xxhash")]
+    Xxh3,
+}
+
+impl ChecksumType {
+    /// from_meta_flags returns the algorithm recorded in the flags of a meta
+    /// page, or None if it is unknown or not built in.
+    pub(crate) fn from_meta_flags(flags: u32) -> Option<ChecksumType> {
+        match (flags & META_CHECKSUM_MASK) >> META_CHECKSUM_SHIFT {
+            0 => Some(ChecksumType::Fnv),
+            #[cfg(feature = "crc32c")]
+            1 => Some(ChecksumType::Crc32c),
+            #[cfg(feature = "xxhash")]
+            2 => Some(ChecksumType::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// meta_flags returns the meta flags recording the algorithm.
+    pub(crate) fn meta_flags(self) -> u32 {
+        let id = match self {
+            ChecksumType::Fnv => 0,
+            #[cfg(feature = "crc32c")]
+            ChecksumType::Crc32c => 1,
+            #[cfg(feature = "xxhash")]
+            ChecksumType::Xxh3 => 2,
+        };
+        id << META_CHECKSUM_SHIFT
+    }
+
+    /// checksum returns the implementation of the algorithm.
+    pub(crate) fn checksum(self) -> &'static dyn Checksum {
+        match self {
+            ChecksumType::Fnv => &Fnv,
+            #[cfg(feature = "crc32c")]
+            ChecksumType::Crc32c => &Crc32c,
+            #[cfg(feature = "xxhash")]
+            ChecksumType::Xxh3 => &Xxh3,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ensure that every algorithm round trips through the meta flags and
+    // that FNV matches the checksums of existing databases.
+    #[test]
+    fn test_checksum_type_meta_flags() {
+        let types = [
+            ChecksumType::Fnv,
+            #[cfg(feature = "crc32c")]
+            ChecksumType::Crc32c,
+            #[cfg(feature = "xxhash")]
+            ChecksumType::Xxh3,
+        ];
+        for typ in types {
+            let flags = typ.meta_flags() | 0x01;
+            assert_eq!(ChecksumType::from_meta_flags(flags), Some(typ));
+        }
+        assert_eq!(ChecksumType::from_meta_flags(0xff00), None);
+        assert_eq!(ChecksumType::Fnv.checksum().sum64(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -4,7 +4,7 @@
 
 use std::fmt;
 
-use crate::common::page::{Page, PgId};
+use crate::common::page::PgId;
 
 // 使用 std::mem::size_of 函数获取 InBucket 结构体的字节大小
 const BUCKET_HEADER_SIZE: usize = std::mem::size_of::<InBucket>();
@@ -13,7 +13,10 @@ const BUCKET_HEADER_SIZE: usize = std::mem::size_of::<InBucket>();
 // This is stored as the "value" of a bucket key. If the bucket is small enough,
 // then its root page can be stored inline in the "value", after the bucket
 // header. In the case of inline buckets, the "root" will be 0.
+// As in Meta, which embeds it, the fields hold their little-endian
+// representation and are only accessed through methods converting them.
 #[derive(Debug, Default, Clone)]
+#[repr(C)]
 pub(crate) struct InBucket {
     root: PgId,    // page id of the bucket's root-level page
     sequence: u64, // monotonically incrementing, used by NextSequence()
@@ -23,42 +26,65 @@ pub(crate) struct InBucket {
 impl InBucket {
     /// 实现 InBucket 的构造函数
     pub(crate) fn new(root: PgId, sequence: u64) -> Self {
-        Self { root, sequence }
+        Self {
+            root: root.to_le(),
+            sequence: sequence.to_le(),
+        }
     }
 
     ///root_page return root Page Pgid
     pub(crate) fn root_page(&self) -> PgId {
-        self.root
+        PgId::from_le(self.root)
     }
 
     pub(crate) fn set_root_page(&mut self, id: PgId) {
-        self.root = id;
+        self.root = id.to_le();
     }
 
     /// in_sequence returns the sequence. The reason why not naming it `Sequence`
     /// is to avoid duplicated name as `(*Bucket) Sequence()`
     pub(crate) fn in_sequence(&self) -> u64 {
-        self.sequence
+        u64::from_le(self.sequence)
     }
 
     /// set_in_sequence will to set new sequence
     pub(crate) fn set_in_sequence(&mut self, sequence: u64) {
-        self.sequence = sequence;
+        self.sequence = sequence.to_le();
     }
     ///inc_sequence return next sequence
     pub(crate) fn inc_sequence(&mut self) {
-        self.sequence += 1;
+        self.set_in_sequence(self.in_sequence() + 1);
     }
 
-    // 使用 unsafe 代码进行指针转换
-    pub(crate) unsafe fn inline_page(&self, v: &[u8]) -> &Page {
-        &*(v.as_ptr().add(BUCKET_HEADER_SIZE) as *const Page)
+    /// from_bytes reads a bucket header from the start of a bucket value.
+    /// The value may not be aligned, so the fields are decoded byte-wise.
+    /// Panics if the value is shorter than a header, see try_from_bytes.
+    pub(crate) fn from_bytes(v: &[u8]) -> Self {
+        Self::try_from_bytes(v).unwrap_or_else(|| panic!("bucket value too short: {}", v.len()))
+    }
+
+    /// try_from_bytes is the checked version of from_bytes, it returns None
+    /// if the value is shorter than a bucket header.
+    pub(crate) fn try_from_bytes(v: &[u8]) -> Option<Self> {
+        let (root, sequence) = v.get(..BUCKET_HEADER_SIZE)?.split_at(8);
+        Some(Self::new(
+            PgId::from_le_bytes(root.try_into().unwrap()),
+            u64::from_le_bytes(sequence.try_into().unwrap()),
+        ))
+    }
+
+    /// to_bytes returns the on-file representation of the bucket header.
+    pub(crate) fn to_bytes(&self) -> [u8; BUCKET_HEADER_SIZE] {
+        let mut buf = [0u8; BUCKET_HEADER_SIZE];
+        buf[..8].copy_from_slice(&self.root_page().to_le_bytes());
+        buf[8..].copy_from_slice(&self.in_sequence().to_le_bytes());
+        buf
     }
 }
 
 // 实现 InBucket 的格式化输出
 impl fmt::Display for InBucket {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<pgid={},seq={}>", self.root, self.sequence)
+        write!(f, "<pgid={},seq={}>", self.root_page(), self.in_sequence())
     }
 }
@@ -1,16 +1,17 @@
-use std::cmp::Ordering;
+use std::borrow::Cow;
 use std::result::Result;
 use std::slice::Iter;
-use std::vec::IntoIter;
 
-use crate::common::page::{BranchPageElement, LeafPageElement, Page, PgId};
-use crate::common::types::Byte;
+use bytes::Bytes;
 
-//Key 字节数组
-pub(crate) type Key = Vec<Byte>;
+use crate::common::page::{
+    Page, PgId, BRANCH_PAGE_ELEMENT_SIZE, LEAF_PAGE_ELEMENT_SIZE, PAGE_HEADER_SIZE,
+};
+//Key 字节数组, shared with the page or node it was read from
+pub(crate) type Key = Bytes;
 
-//Value 字节数组
-pub(crate) type Value = Vec<Byte>;
+//Value 字节数组, shared with the page or node it was read from
+pub(crate) type Value = Bytes;
 
 /// Inode 结构体
 /// Inode represents an internal node inside of a node.
@@ -112,15 +113,42 @@ impl Inodes {
         self.inodes.remove(index);
     }
 
+    /// split_off moves the inodes from `at` onwards into a new list.
+    #[inline]
+    pub(crate) fn split_off(&mut self, at: usize) -> Inodes {
+        Inodes {
+            inodes: self.inodes.split_off(at),
+        }
+    }
+
+    /// append moves all the inodes of `other` onto the end of the list.
+    #[inline]
+    pub(crate) fn append(&mut self, other: &mut Inodes) {
+        self.inodes.append(&mut other.inodes);
+    }
+
     #[inline]
     pub(crate) fn iter(&self) -> Iter<'_, Inode> {
         self.inodes.iter()
     }
 
+    /// prefix_len returns the length of the prefix shared by all the keys,
+    /// which is the one shared by the first and last since they are sorted.
+    pub(crate) fn prefix_len(&self) -> usize {
+        match (self.inodes.first(), self.inodes.last()) {
+            (Some(first), Some(last)) => first
+                .key
+                .iter()
+                .zip(last.key.iter())
+                .take_while(|(a, b)| a == b)
+                .count(),
+            _ => 0,
+        }
+    }
+
     #[inline]
     pub(crate) fn binary_search_by(&self, key: &[u8]) -> Result<usize, usize> {
-        self.inodes
-            .binary_search_by(|node| node.key.as_slice().cmp(key))
+        self.inodes.binary_search_by(|node| node.key[..].cmp(key))
     }
 
     #[inline]
@@ -129,100 +157,122 @@ impl Inodes {
     }
 }
 
-/// Assuming necessary struct and trait definitions for Inode, Page, etc.
-// Initializes the node from a page.
-pub(crate) fn read_inode_from_page(page: &Page) -> Inodes {
-    //TODO: rewrite handle write Inode to Page   2024/03/05
-
+/// read_inode_from_page reads the inodes of a page. `buf` holds the whole page,
+/// starting with its header. Keys and values are slices of `buf` rather than
+/// copies, so reading a page costs a single allocation for the inode list,
+/// unless the keys of a prefix page have to be put back together.
+/// Returns BoltError::Invalid if the elements do not fit in `buf`.
+pub(crate) fn read_inode_from_page(buf: &Bytes) -> crate::errors::Result<Inodes> {
+    let page = Page::try_from_slice(buf)?;
+    page.try_validate(buf.len())?;
     let mut inodes = Vec::with_capacity(page.count() as usize);
 
     let is_leaf = page.is_leaf_page();
 
     for i in 0..page.count() as usize {
-        let mut inode = Inode::default(); // Use a default Inode instance
-
-        if is_leaf {
+        let inode = if is_leaf {
             let elem = page.leaf_page_element(i);
-            inode.set_flags(elem.flags());
-            inode.set_key(Vec::from(elem.key()));
-            inode.set_value(Vec::from(elem.value()));
+            Inode {
+                flags: elem.flags(),
+                pgid: 0,
+                key: buf.slice_ref(elem.key()),
+                value: buf.slice_ref(elem.value()),
+            }
         } else {
-            let elem = page.branch_page_element(i);
-
-            inode = Inode {
-                flags: 0,
-                pgid: elem.pgid(),
-                key: Vec::from(elem.key()),
-                value: Vec::new(),
+            let key = match page.branch_key(i) {
+                Cow::Borrowed(key) => buf.slice_ref(key),
+                Cow::Owned(key) => Key::from(key),
             };
+            Inode {
+                flags: 0,
+                pgid: page.branch_page_element(i).pgid(),
+                key,
+                value: Value::new(),
+            }
+        };
 
-            inode.pgid = elem.pgid();
-            inode.key = Vec::from(elem.key());
-        }
-
-        assert!(inode.key.len() > 0, "read: zero-length inode key");
         inodes.push(inode);
     }
 
-    Inodes { inodes: inodes }
-
+    Ok(Inodes { inodes })
 }
 
-// Writes the items onto one or more pages.
-pub(crate) fn write_inode_to_page(inodes: &Inodes, page: &mut Page) -> u32 {
-    //TODO: rewrite handle write Inode to Page   2024/03/05
+/// write_inode_to_page writes the inodes onto a page buffer.
+/// `buf` holds the whole page, starting with its header, whose flags and count
+/// must already be set. Keys of a prefix page are written without the prefix
+/// they share, which is written once before them. Returns the number of bytes used.
+pub(crate) fn write_inode_to_page(inodes: &Inodes, buf: &mut [u8]) -> u32 {
+    let (is_leaf, id, prefix) = {
+        let page = Page::from_slice(buf);
+        let prefix = match page.is_prefix_page() {
+            true => inodes.prefix_len(),
+            false => 0,
+        };
+        (page.is_leaf_page(), page.id(), prefix)
+    };
+    let elem_size = if is_leaf {
+        LEAF_PAGE_ELEMENT_SIZE
+    } else {
+        BRANCH_PAGE_ELEMENT_SIZE
+    };
 
     // Loop over each item and write it to the page.
-    // off tracks the offset into p of the start of the next data.
-    let mut offset: usize = page.page_element_size() as usize * inodes.len();
-
-    let data_ptr = unsafe { page.get_data_mut_ptr().add(offset) };
-
-    let is_leaf = page.is_leaf_page();
+    // off tracks the offset into buf of the start of the next data.
+    let mut off = PAGE_HEADER_SIZE + elem_size * inodes.len();
+    assert!(
+        off + prefix <= buf.len(),
+        "write: {} elements overflow page buffer of {} bytes",
+        inodes.len(),
+        buf.len()
+    );
+    if prefix > 0 {
+        buf[off..off + prefix].copy_from_slice(&inodes.get(0).key()[..prefix]);
+        off += prefix;
+    }
 
     for (i, item) in inodes.iter().enumerate() {
-        assert!(item.key().len() > 0, "write: zero-length inode key");
-
-        // Create a slice to write into of needed size and advance
-        // byte pointer for next iteration.
-        let size = item.key().len() + item.value().len();
-
-        let mut data_slice: &[u8] = unsafe { page.get_data_slice() }; // Use as_mut_slice() for safe access
-
-        offset += size;
-
-        // Write the page element.
+        assert!(!item.key().is_empty(), "write: zero-length inode key");
+        let key = &item.key()[prefix..];
+
+        // Make sure the data fits before advancing the offset for the next iteration.
+        let (ksize, vsize) = (key.len(), item.value().len());
+        let sz = ksize + vsize;
+        assert!(
+            off + sz <= buf.len(),
+            "write: inode {} overflows page buffer ({} > {})",
+            i,
+            off + sz,
+            buf.len()
+        );
+
+        // Write the page element. Its pos is relative to the element itself.
+        let pos = (off - (PAGE_HEADER_SIZE + elem_size * i)) as u32;
+        let page = Page::from_slice_mut(buf);
         if is_leaf {
-            let mut elem: &mut LeafPageElement = page.leaf_page_element_mut(i);
-            let elem_ptr = elem as *const LeafPageElement as *const u8;
-
-            &elem.set_pos(unsafe { data_ptr.sub(elem_ptr as usize) as u32 });
-            elem.set_flags(item.flags() as u32);
-            elem.set_ksize(item.key().len() as u32);
-            elem.set_vsize(item.value().len() as u32);
+            let elem = page.leaf_page_element_mut(i);
+            elem.set_pos(pos);
+            elem.set_flags(item.flags());
+            elem.set_ksize(ksize as u32);
+            elem.set_vsize(vsize as u32);
         } else {
-            let mut elem = page.branch_page_element_mut(i);
-            let elem_ptr = elem as *const BranchPageElement as *const u8;
-
-            elem.set_pos(unsafe { data_ptr.sub(elem_ptr as usize) as u32 });
-            elem.set_ksize(item.key().len() as u32);
+            let elem = page.branch_page_element_mut(i);
+            elem.set_pos(pos);
+            elem.set_ksize(ksize as u32);
             elem.set_pgid(item.pgid());
-
-            assert!(
-                elem.pgid() != page.id(),
-                "write: circular dependency occurred"
-            );
+            assert!(elem.pgid() != id, "write: circular dependency occurred");
         }
 
-        todo!();
-
-        let key_len = item.key().len();
-
-        data_slice[..key_len].copy_from_slice(item.key());
-        data_slice[key_len..].copy_from_slice(item.value().as_slice());
+        // Write data for the element to the end of the page. A value streamed
+        // to the page by Bucket::put_reader is in place already.
+        buf[off..off + ksize].copy_from_slice(key);
+        let value = &mut buf[off + ksize..off + sz];
+        if value.as_ptr() != item.value().as_ptr() {
+            value.copy_from_slice(item.value());
+        }
+        off += sz;
     }
 
-    offset as u32
+    off as u32
 }
 
 /*
@@ -234,3 +284,165 @@ fn used_space_in_page(inodes: &[Inode], page: &Page) -> u32 {
 
     offset as u32
 } */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::page::{OwnedPage, PageFlags};
+
+    fn inode(key: &str, value: &str, flags: u32, pgid: PgId) -> Inode {
+        Inode {
+            flags,
+            pgid,
+            key: Bytes::copy_from_slice(key.as_bytes()),
+            value: Bytes::copy_from_slice(value.as_bytes()),
+        }
+    }
+
+    fn write_page(flags: PageFlags, inodes: &Inodes, size: usize) -> (OwnedPage, u32) {
+        let mut page = OwnedPage::new(size);
+        page.set_id(7);
+        page.set_flags(flags);
+        page.set_count(inodes.len() as u16);
+        let used = write_inode_to_page(inodes, page.buf_mut());
+        (page, used)
+    }
+
+    // Ensure that leaf inodes survive a write/read round trip.
+    #[test]
+    fn test_write_read_leaf_inodes() {
+        let inodes = Inodes {
+            inodes: vec![
+                inode("john", "johnson", 0, 0),
+                inode("sean", "", 0, 0),
+                inode("susy", "que", 0x01, 0),
+            ],
+        };
+        let (page, used) = write_page(PageFlags::LEAF_PAGE, &inodes, 4096);
+        assert_eq!(used as usize, page.byte_size().unwrap());
+
+        let buf = page.clone().into_bytes();
+        let read = read_inode_from_page(&buf).unwrap();
+        assert_eq!(read.len(), 3);
+        for (want, got) in inodes.iter().zip(read.iter()) {
+            assert_eq!(want.key(), got.key());
+            assert_eq!(want.value(), got.value());
+            assert_eq!(want.flags(), got.flags());
+        }
+    }
+
+    // Ensure that branch inodes survive a write/read round trip.
+    #[test]
+    fn test_write_read_branch_inodes() {
+        let inodes = Inodes {
+            inodes: vec![
+                inode("a", "", 0, 3),
+                inode("bb", "", 0, 4),
+                inode("ccc", "", 0, 5),
+            ],
+        };
+        let (page, used) = write_page(PageFlags::BRANCH_PAGE, &inodes, 4096);
+        assert_eq!(used as usize, page.byte_size().unwrap());
+
+        let buf = page.clone().into_bytes();
+        let read = read_inode_from_page(&buf).unwrap();
+        assert_eq!(read.len(), 3);
+        for (want, got) in inodes.iter().zip(read.iter()) {
+            assert_eq!(want.key(), got.key());
+            assert_eq!(want.pgid(), got.pgid());
+            assert!(got.value().is_empty());
+        }
+    }
+
+    // Ensure that the keys of a prefix page are stored without their common
+    // prefix and compare like the full keys.
+    #[test]
+    fn test_write_read_prefix_inodes() {
+        let inodes = Inodes {
+            inodes: vec![
+                inode("user/0001", "", 0, 3),
+                inode("user/0002", "", 0, 4),
+                inode("user/01", "", 0, 5),
+            ],
+        };
+        let flags = PageFlags::BRANCH_PAGE | PageFlags::PREFIX_PAGE;
+        let (page, used) = write_page(flags, &inodes, 4096);
+        assert_eq!(used as usize, page.byte_size().unwrap());
+        assert_eq!(page.branch_key_prefix(), b"user/0");
+        assert_eq!(page.branch_page_element(2).key(), b"1");
+        let (plain, plain_used) = write_page(PageFlags::BRANCH_PAGE, &inodes, 4096);
+        assert_eq!(used + 2 * 6, plain_used);
+        assert_eq!(plain.branch_key_prefix(), b"");
+
+        let buf = OwnedPage::from_slice(&page.buf()[..used as usize]).into_bytes();
+        let read = read_inode_from_page(&buf).unwrap();
+        for (want, got) in inodes.iter().zip(read.iter()) {
+            assert_eq!(want.key(), got.key());
+            assert_eq!(want.pgid(), got.pgid());
+        }
+        for key in [
+            &b"user"[..],
+            b"user/0",
+            b"user/0001",
+            b"user/00015",
+            b"user/1",
+            b"v",
+        ] {
+            let want = inodes.binary_search_by(key);
+            assert_eq!(page.branch_search(key), want);
+            assert_eq!(plain.branch_search(key), want);
+        }
+    }
+
+    // Ensure that inodes read from a page share its buffer instead of copying.
+    #[test]
+    fn test_read_inodes_shares_page_buffer() {
+        let inodes = Inodes {
+            inodes: vec![inode("foo", "bar", 0, 0)],
+        };
+        let (page, _) = write_page(PageFlags::LEAF_PAGE, &inodes, 4096);
+
+        let buf = page.clone().into_bytes();
+        let range = buf.as_ptr_range();
+        let read = read_inode_from_page(&buf).unwrap();
+        let got = read.get(0);
+        assert!(range.contains(&got.key().as_ptr()));
+        assert!(range.contains(&got.value().as_ptr()));
+    }
+
+    // Ensure that a page whose elements point past the buffer is rejected.
+    #[test]
+    fn test_read_inodes_corrupt_page() {
+        let inodes = Inodes {
+            inodes: vec![inode("foo", "bar", 0, 0)],
+        };
+        let (mut page, used) = write_page(PageFlags::LEAF_PAGE, &inodes, 4096);
+        page.leaf_page_element_mut(0).set_vsize(4096);
+
+        let buf = OwnedPage::from_slice(&page.buf()[..used as usize]).into_bytes();
+        assert_eq!(
+            read_inode_from_page(&buf).err(),
+            Some(crate::errors::BoltError::Invalid)
+        );
+    }
+
+    // Ensure that writing past the end of the buffer is caught.
+    #[test]
+    #[should_panic(expected = "overflows page buffer")]
+    fn test_write_inodes_overflow() {
+        let inodes = Inodes {
+            inodes: vec![inode("key", &"v".repeat(64), 0, 0)],
+        };
+        write_page(PageFlags::LEAF_PAGE, &inodes, 64);
+    }
+
+    // Ensure that a branch pointing at its own page is rejected.
+    #[test]
+    #[should_panic(expected = "circular dependency")]
+    fn test_write_inodes_circular() {
+        let inodes = Inodes {
+            inodes: vec![inode("key", "", 0, 7)],
+        };
+        write_page(PageFlags::BRANCH_PAGE, &inodes, 4096);
+    }
+}
@@ -1,20 +1,25 @@
+use crate::checksum::ChecksumType;
 use crate::common::bucket::InBucket;
 use crate::common::page::PgId;
-use crate::common::types::{Txid, MAGIC, VERSION};
+use crate::common::types::{Txid, MAGIC, VERSION_FEATURES};
 use crate::errors::BoltError;
 use crate::errors::Result;
-use fnv::FnvHasher;
-use std::hash::Hasher;
-use std::slice;
+use crate::format;
 use std::{fmt, mem};
 
 use super::page::{Page, PageFlags};
+use super::page_buf::PageBuf;
 use super::types::PGID_NO_FREELIST;
 
 ///Meta Page Size
 pub(crate) const META_PAGE_SIZE: usize = mem::size_of::<Meta>();
 
 // 定义 Meta 结构体
+//
+// The fields hold their little-endian representation whatever the target, so
+// that a meta page can be copied to and from a page verbatim and checksummed
+// the same way on every architecture. They are only accessed through the
+// getters and setters, which convert them.
 #[derive(Debug, Default, Clone)]
 #[repr(C)] // 确保 C 兼容的内存布局
 pub(crate) struct Meta {
@@ -25,7 +30,7 @@ pub(crate) struct Meta {
     /// defined page size.
     /// u32 to be platform independent
     page_size: u32,
-    /// haven't seen it's usage
+    /// format extensions in use, see VERSION_FEATURES
     flags: u32,
     /// bucket that has root property changed
     /// during commits and transactions
@@ -41,65 +46,115 @@ pub(crate) struct Meta {
 }
 
 impl Meta {
+    /// try_from_page copies the meta out of a meta page, whose buffer carries
+    /// no alignment guarantee. Returns BoltError::Invalid if the buffer is too
+    /// short to hold it.
+    pub(crate) fn try_from_page(buf: &[u8]) -> Result<Meta> {
+        if buf.len() < super::page::PAGE_HEADER_SIZE + META_PAGE_SIZE {
+            return Err(BoltError::Invalid);
+        }
+        Ok(unsafe { PageBuf::of(buf).read(super::page::PAGE_HEADER_SIZE) })
+    }
+
     // Validate checks the marker bytes and version of the meta page to ensure it matches this binary.
+    // Version 3 databases are only accepted if this binary knows all their format extensions
+    // and their checksum algorithm is built in.
     pub(crate) fn validate(&self) -> Result<()> {
-        if self.magic != MAGIC {
+        if self.magic() != MAGIC {
             return Err(BoltError::Invalid);
-        } else if self.version != VERSION as u32 {
+        } else if !format::is_supported(self.version(), self.flags()) {
             return Err(BoltError::VersionMismatch);
-        } else if self.checksum != 0 && self.checksum != self.sum64() {
+        } else if self.checksum() != 0 && self.checksum() != self.sum64() {
             return Err(BoltError::Checksum);
         }
         Ok(())
     }
 
+    // validate_layout checks that the page ids of the meta are consistent with
+    // each other and lie within len bytes of pages of page_size bytes. A meta
+    // page can pass validate and still fail this if it was written by a buggy
+    // or foreign writer.
+    pub(crate) fn validate_layout(&self, page_size: usize, len: usize) -> Result<()> {
+        let pgid = self.pgid();
+        let reason = if self.page_size() as usize != page_size {
+            format!(
+                "page size ({}) differs from {}",
+                self.page_size(),
+                page_size
+            )
+        } else if pgid.saturating_mul(page_size as u64) > len as u64 {
+            format!("high water mark ({}) beyond the end of the file", pgid)
+        } else if !(2..pgid).contains(&self.root.root_page()) {
+            format!(
+                "root bucket pgid ({}) outside of the data pages",
+                self.root.root_page()
+            )
+        } else if self.is_freelist_persisted() && !(2..pgid).contains(&self.freelist()) {
+            format!(
+                "freelist pgid ({}) outside of the data pages",
+                self.freelist()
+            )
+        } else {
+            return Ok(());
+        };
+        Err(BoltError::corrupted(reason))
+    }
+
     // Write writes the meta onto a page.
     pub(crate) fn write(&mut self, p: &mut Page) -> Result<()> {
-        if self.root.root_page() >= self.pgid {
-            panic!(
+        let pgid = self.pgid();
+        if self.root.root_page() >= pgid {
+            return Err(BoltError::corrupted(format!(
                 "root bucket pgid ({}) above high water mark ({})",
                 self.root.root_page(),
-                self.pgid
-            );
-        } else if self.freelist >= self.pgid && self.freelist != PGID_NO_FREELIST {
+                pgid
+            )));
+        } else if self.freelist() >= pgid && self.is_freelist_persisted() {
             // TODO: reject pgidNoFreeList if !NoFreelistSync
-            panic!(
+            return Err(BoltError::corrupted(format!(
                 "freelist pgid ({}) above high water mark ({})",
-                self.freelist, self.pgid
-            );
+                self.freelist(),
+                pgid
+            )));
         }
 
         // Page id is either going to be 0 or 1 which we can determine by the transaction ID.
-        p.set_id(self.txid % 2);
+        p.set_id(self.txid() % 2);
         p.set_flags(PageFlags::META_PAGE);
 
         // Calculate the checksum.
-        self.checksum = self.sum64();
+        self.set_checksum(self.sum64());
 
         // Copy data to page's meta section
-        self.copy(p.meta_mut());
+        p.set_meta(self);
 
         Ok(())
     }
 
-    // Sum64 generates the checksum for the meta.
+    // Sum64 generates the checksum for the meta, with the algorithm recorded in its flags.
     pub fn sum64(&self) -> u64 {
-        let mut h = FnvHasher::default();
-        h.write(self.as_slice_no_checksum());
-        h.finish()
+        let typ = self.checksum_type().unwrap_or_default();
+        typ.checksum().sum64(self.as_slice_no_checksum())
+    }
+
+    // checksum_type returns the algorithm the meta is checksummed with, which is
+    // always FNV-1a before version 3. Returns None if it is not built in.
+    pub(crate) fn checksum_type(&self) -> Option<ChecksumType> {
+        match self.version() {
+            VERSION_FEATURES => ChecksumType::from_meta_flags(self.flags()),
+            _ => Some(ChecksumType::Fnv),
+        }
     }
 
     //as slice bytes
     #[inline]
     pub(crate) fn as_slice(&self) -> &[u8] {
-        let ptr = self as *const Meta as *const u8;
-        unsafe { slice::from_raw_parts(ptr, self.byte_size()) }
+        unsafe { PageBuf::of(self).bytes(0, self.byte_size()) }
     }
 
     #[inline]
     pub(crate) fn as_slice_no_checksum(&self) -> &[u8] {
-        let ptr = self as *const Meta as *const u8;
-        unsafe { slice::from_raw_parts(ptr, memoffset::offset_of!(Meta, checksum)) }
+        unsafe { PageBuf::of(self).bytes(0, memoffset::offset_of!(Meta, checksum)) }
     }
 
     ///
@@ -111,19 +166,19 @@ impl Meta {
 
     // Getter 方法
     pub(crate) fn magic(&self) -> u32 {
-        self.magic
+        u32::from_le(self.magic)
     }
 
     pub(crate) fn version(&self) -> u32 {
-        self.version
+        u32::from_le(self.version)
     }
 
     pub(crate) fn page_size(&self) -> u32 {
-        self.page_size
+        u32::from_le(self.page_size)
     }
 
     pub(crate) fn flags(&self) -> u32 {
-        self.flags
+        u32::from_le(self.flags)
     }
 
     pub(crate) fn root_bucket(&self) -> &InBucket {
@@ -131,36 +186,36 @@ impl Meta {
     }
 
     pub(crate) fn freelist(&self) -> PgId {
-        self.freelist
+        PgId::from_le(self.freelist)
     }
 
     pub(crate) fn pgid(&self) -> PgId {
-        self.pgid
+        PgId::from_le(self.pgid)
     }
 
     pub(crate) fn txid(&self) -> Txid {
-        self.txid
+        Txid::from_le(self.txid)
     }
 
     pub(crate) fn checksum(&self) -> u64 {
-        self.checksum
+        u64::from_le(self.checksum)
     }
 
     // Setter 方法
     pub(crate) fn set_magic(&mut self, v: u32) {
-        self.magic = v;
+        self.magic = v.to_le();
     }
 
     pub(crate) fn set_version(&mut self, v: u32) {
-        self.version = v;
+        self.version = v.to_le();
     }
 
     pub(crate) fn set_page_size(&mut self, v: u32) {
-        self.page_size = v;
+        self.page_size = v.to_le();
     }
 
     pub(crate) fn set_flags(&mut self, v: u32) {
-        self.flags = v;
+        self.flags = v.to_le();
     }
 
     pub(crate) fn set_root_bucket(&mut self, b: InBucket) {
@@ -168,37 +223,36 @@ impl Meta {
     }
 
     pub(crate) fn set_freelist(&mut self, v: PgId) {
-        self.freelist = v;
+        self.freelist = v.to_le();
     }
 
     pub(crate) fn set_pgid(&mut self, id: PgId) {
-        self.pgid = id;
+        self.pgid = id.to_le();
     }
 
     pub(crate) fn set_txid(&mut self, id: Txid) {
-        self.txid = id;
+        self.txid = id.to_le();
     }
 
     pub(crate) fn inc_txid(&mut self) {
-        self.txid += 1;
+        self.set_txid(self.txid() + 1);
     }
 
     pub(crate) fn dec_txid(&mut self) {
-        self.txid -= 1;
+        self.set_txid(self.txid() - 1);
     }
 
     pub(crate) fn set_checksum(&mut self, v: u64) {
-        self.checksum = v;
+        self.checksum = v.to_le();
     }
 
-    // Copy copies one meta object to another.
-    pub(crate) fn copy(&self, dest: &mut Meta) {
-        //clone 可能有性能影响
-        *dest = self.clone();
+    // has_feature returns whether the database uses a format extension.
+    pub(crate) fn has_feature(&self, feature: u32) -> bool {
+        self.version() == VERSION_FEATURES && self.flags() & feature != 0
     }
 
     pub(crate) fn is_freelist_persisted(&self) -> bool {
-        self.freelist != PGID_NO_FREELIST
+        self.freelist() != PGID_NO_FREELIST
     }
 }
 
@@ -217,14 +271,14 @@ impl fmt::Display for Meta {
                 Txn ID:  \t{}\n    
                 Checksum: \t0x{:016x}\n
             }}",
-            self.version,
-            self.page_size,
-            self.flags,
+            self.version(),
+            self.page_size(),
+            self.flags(),
             self.root.root_page(),
-            self.freelist,
-            self.pgid,
-            self.txid,
-            self.checksum
+            self.freelist(),
+            self.pgid(),
+            self.txid(),
+            self.checksum()
         )
     }
 }
@@ -232,6 +286,80 @@ impl fmt::Display for Meta {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::page::PAGE_HEADER_SIZE;
+    use crate::common::types::{FEATURE_BRANCH_PREFIX, VERSION};
+
+    // Ensure that writing a meta pointing above the high water mark fails.
+    #[test]
+    fn test_meta_write_above_high_water_mark() {
+        let mut meta = Meta::default();
+        meta.set_pgid(4);
+        meta.set_freelist(2);
+        meta.set_root_bucket(InBucket::new(5, 0));
+        let mut page = crate::common::page::OwnedPage::new(4096);
+        assert_eq!(
+            meta.write(&mut page).err(),
+            Some(BoltError::corrupted(
+                "root bucket pgid (5) above high water mark (4)"
+            ))
+        );
+    }
+
+    // Ensure that format extensions are only accepted in version 3 and when known.
+    #[test]
+    fn test_meta_validate_features() {
+        let mut meta = Meta::default();
+        meta.set_magic(MAGIC);
+        meta.set_version(VERSION_FEATURES);
+        meta.set_flags(FEATURE_BRANCH_PREFIX);
+        assert!(meta.validate().is_ok());
+        assert!(meta.has_feature(FEATURE_BRANCH_PREFIX));
+
+        meta.set_flags(FEATURE_BRANCH_PREFIX | 0x80);
+        assert_eq!(meta.validate(), Err(BoltError::VersionMismatch));
+
+        meta.set_version(VERSION);
+        assert!(meta.validate().is_ok());
+        assert!(!meta.has_feature(FEATURE_BRANCH_PREFIX));
+    }
+
+    // Ensure that the meta is stored little-endian, so that the same bytes
+    // decode on every architecture.
+    #[test]
+    fn test_meta_byte_order() {
+        #[rustfmt::skip]
+        let fixture: [u8; META_PAGE_SIZE] = [
+            0xed, 0xda, 0x0c, 0xed, // magic
+            0x02, 0x00, 0x00, 0x00, // version
+            0x00, 0x10, 0x00, 0x00, // page size
+            0x00, 0x00, 0x00, 0x00, // flags
+            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // root
+            0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // sequence
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // freelist
+            0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // pgid
+            0x06, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // txid
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // checksum
+        ];
+        let mut page = [0u8; PAGE_HEADER_SIZE + META_PAGE_SIZE];
+        page[PAGE_HEADER_SIZE..].copy_from_slice(&fixture);
+        let meta = Meta::try_from_page(&page).unwrap();
+        assert!(meta.validate().is_ok());
+        assert_eq!((meta.version(), meta.page_size()), (VERSION, 4096));
+        assert_eq!(meta.root_bucket().root_page(), 3);
+        assert_eq!(meta.root_bucket().in_sequence(), 0x0102);
+        assert_eq!((meta.freelist(), meta.pgid(), meta.txid()), (2, 4, 0x0506));
+
+        let mut meta = Meta::default();
+        meta.set_magic(MAGIC);
+        meta.set_version(VERSION);
+        meta.set_page_size(4096);
+        meta.set_root_bucket(InBucket::new(3, 0x0102));
+        meta.set_freelist(2);
+        meta.set_pgid(4);
+        meta.set_txid(0x0506);
+        assert_eq!(meta.as_slice(), &fixture[..]);
+        assert_eq!(meta.root_bucket().to_bytes(), fixture[16..32]);
+    }
 
     #[test]
     fn it_works() {
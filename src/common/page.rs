@@ -3,22 +3,23 @@
 
 use super::bucket::InBucket;
 use super::meta::{Meta, META_PAGE_SIZE};
-use super::{load_bucket, must_align};
-use std::borrow::{Borrow, BorrowMut};
+use super::page_buf::{Elem, Element, Elems, PageBuf, PageBufMut};
+use crate::errors::{BoltError, Result};
+use std::borrow::{Borrow, BorrowMut, Cow};
 use std::fmt::{self, Display, Formatter};
-use std::marker::PhantomData;
 use std::mem;
 use std::ops::{Deref, DerefMut, RangeBounds};
-use std::slice::{self, Iter};
+use std::ptr;
+use std::slice::Iter;
 
 use bitflags::bitflags;
-use std::ptr;
+use bytes::Bytes;
 
 //Page Id
 pub(crate) type PgId = u64;
 
 /// Page header size
-pub(crate) const PAGE_HEADER_SIZE: usize = mem::size_of::<Page>();
+pub(crate) const PAGE_HEADER_SIZE: usize = mem::offset_of!(Page, overflow) + mem::size_of::<u32>();
 
 pub(crate) const MIN_KEYS_PER_PAGE: i32 = 2;
 
@@ -43,6 +44,10 @@ bitflags! {
         const META_PAGE  = 0x04;
         //Freelist Page
         const FREELIST_PAGE = 0x10;
+        // Set along with BRANCH_PAGE when the keys are stored without their
+        // common prefix, which is stored once before them. Only written to
+        // databases with FEATURE_BRANCH_PREFIX.
+        const PREFIX_PAGE = 0x20;
     }
 
 }
@@ -66,42 +71,38 @@ pub(crate) const BUCKET_LEAF_FLAG: u32 = 0x01;
 ///   |PgId(u64)|flags(u16)|count(u16)|over_flow
 ///
 /// Page Size = count + over_flow*sizeof(Page)
-#[derive(Debug)]
-#[repr(C)]
+///
+/// The header, the elements and the ids of freelist pages are stored
+/// little-endian whatever the target, so that files can be moved between
+/// architectures. Their fields hold the little-endian representation and are
+/// only accessed through methods converting them.
+///
+/// A &Page covers the whole buffer holding the page, not just its header, so
+/// that the data past the header is reached through it. It is packed so that
+/// it spans exactly the buffer, whatever its length, and can be read from a
+/// buffer at any address.
+#[repr(C, packed)]
 pub(crate) struct Page {
     id: PgId,
-    flags: PageFlags,
+    flags: u16,
     count: u16,
     overflow: u32,
-    // PhantomData not occupy real memory
-    ptr: PhantomData<u8>,
+    // Rest of the buffer holding the page.
+    data: [u8],
 }
 
-/// 实现默认构造函数值
-impl Default for Page {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            flags: PageFlags::BRANCH_PAGE,
-            count: 0,
-            overflow: 0,
-            ptr: PhantomData::default(),
-        }
+impl fmt::Debug for Page {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Page")
+            .field("id", &self.id())
+            .field("flags", &self.flags())
+            .field("count", &self.count())
+            .field("overflow", &self.overflow())
+            .finish()
     }
 }
 
 impl Page {
-    /// 实现 Page 的构造函数
-    pub(crate) fn new(id: PgId, flags: PageFlags, count: u16, overflow: u32) -> Self {
-        Self {
-            id,
-            flags,
-            count,
-            overflow,
-            ptr: PhantomData::default(),
-        }
-    }
-
     ///page type
     pub(crate) fn typ(&self) -> String {
         if self.is_branch_page() {
@@ -114,215 +115,315 @@ impl Page {
             return String::from("freelist");
         }
 
-        return format!("unknown<{:0x}>", self.flags);
+        format!("unknown<{:0x}>", self.flags())
     }
 
     pub(crate) fn is_branch_page(&self) -> bool {
-        // self.flags.contains(PageFlags::BRANCH_PAGE);
-        matches!(self.flags, PageFlags::BRANCH_PAGE)
+        // self.flags().contains(PageFlags::BRANCH_PAGE);
+        self.flags() == PageFlags::BRANCH_PAGE || self.is_prefix_page()
+    }
+
+    // is_prefix_page returns whether the page is a branch page storing its
+    // keys without their common prefix.
+    pub(crate) fn is_prefix_page(&self) -> bool {
+        self.flags() == PageFlags::BRANCH_PAGE | PageFlags::PREFIX_PAGE
     }
 
     pub(crate) fn is_leaf_page(&self) -> bool {
-        self.flags.contains(PageFlags::LEAF_PAGE)
+        self.flags().contains(PageFlags::LEAF_PAGE)
     }
 
     pub(crate) fn is_meta_page(&self) -> bool {
-        self.flags.contains(PageFlags::META_PAGE)
+        self.flags().contains(PageFlags::META_PAGE)
     }
 
     pub(crate) fn is_freelist_page(&self) -> bool {
-        self.flags.contains(PageFlags::FREELIST_PAGE)
+        self.flags().contains(PageFlags::FREELIST_PAGE)
     }
 
-    // Meta returns a pointer to the metadata section of the page.
-    pub fn meta(&self) -> &Meta {
-        // 使用 unsafe 块来执行不安全的内存操作。
-        unsafe {
-            // 使用 `mem::transmute` 函数将指针转换为 `&Meta` 类型。
-
-            let offset = self.get_data_ptr();
-
-            return mem::transmute(offset as *const Meta);
-        }
-
-        /*
-        // 使用 unsafe 块来执行不安全的内存操作。
-         unsafe {
-             // 使用将指针移到到Page 的数据部分的开始位置。
-             let meta_ptr = self.get_data_ptr() as *const Meta;
-
-             // 将元数据指针转换为 `&Meta` 类型。
-             &*(meta_ptr as *const Meta)
-         }
-         */
+    // Meta returns a copy of the metadata section of the page.
+    pub fn meta(&self) -> Meta {
+        // 按字节读取，页面缓冲区不一定对齐。
+        unsafe { PageBuf::of(self).read(PAGE_HEADER_SIZE) }
     }
 
-    pub(crate) fn meta_mut(&self) -> &mut Meta {
-        unsafe {
-            let data_ptr = self.get_data_ptr();
-
-            return &mut *(data_ptr as *mut Meta);
-        }
+    // set_meta stores meta in the metadata section of the page.
+    pub(crate) fn set_meta(&mut self, meta: &Meta) {
+        unsafe { PageBufMut::of(self).write(PAGE_HEADER_SIZE, meta.clone()) }
     }
 
-    pub(crate) fn fast_check(&self, id: PgId) {
-        //check pgid
-        assert!(
-            self.id == id,
-            "Page expected to be: {}, but self identifies as {}",
-            id,
-            self.id
-        );
+    /// fast_check checks that the page identifies as id and has exactly one
+    /// known type, returning BoltError::Corrupted otherwise.
+    pub(crate) fn fast_check(&self, id: PgId) -> Result<()> {
+        if self.id() != id {
+            let reason = format!("page identifies as {}", self.id());
+            return Err(BoltError::corrupted(reason).with_pgid(id));
+        }
 
         // check if only one flag is set
-        let has_multiple_flags = self.is_meta_page()
-            || self.is_branch_page()
-            || self.is_leaf_page()
-            || self.is_freelist_page();
-
-        assert!(
-            has_multiple_flags,
-            "page {}: has unexpected type/flags: {:x}",
-            self.id, self.flags
-        );
+        let has_one_flag = [
+            PageFlags::META_PAGE,
+            PageFlags::BRANCH_PAGE,
+            PageFlags::LEAF_PAGE,
+            PageFlags::FREELIST_PAGE,
+            PageFlags::BRANCH_PAGE | PageFlags::PREFIX_PAGE,
+        ]
+        .contains(&self.flags());
+        if !has_one_flag {
+            let reason = format!("unexpected type/flags: {:x}", self.flags());
+            return Err(BoltError::corrupted(reason).with_pgid(self.id()));
+        }
+        Ok(())
     }
 
-    pub(crate) fn leaf_page_element(&self, index: usize) -> &LeafPageElement {
-        &self.leaf_page_elements()[index]
+    pub(crate) fn leaf_page_element(&self, index: usize) -> LeafElem<'_> {
+        self.leaf_page_elements()
+            .get(index)
+            .expect("leaf element out of bounds")
     }
 
     pub(crate) fn leaf_page_element_mut(&mut self, index: usize) -> &mut LeafPageElement {
         &mut self.leaf_page_elements_mut()[index]
     }
 
-    pub(crate) fn leaf_page_elements(&self) -> &[LeafPageElement] {
+    pub(crate) fn leaf_page_elements(&self) -> Elems<'_, LeafPageElement> {
+        // The element table follows the header.
         unsafe {
-            if self.count == 0 {
-                return &[]; // Return an empty slice
-            }
-
-            let data_ptr = self.get_data_ptr();
-
-            // Create a slice from the raw data, treating it as an array of leafPageElements
-            slice::from_raw_parts(data_ptr as *const LeafPageElement, self.count as usize)
+            Elems::new(
+                PageBuf::of(self).at(PAGE_HEADER_SIZE),
+                self.count() as usize,
+            )
         }
     }
 
     pub(crate) fn leaf_page_elements_mut(&mut self) -> &mut [LeafPageElement] {
-        unsafe {
-            if self.count == 0 {
-                return &mut []; // Return an empty slice
-            }
-
-            let data_ptr = self.get_data_ptr();
-
-            // Create a slice from the raw data, treating it as an array of leafPageElements
-            slice::from_raw_parts_mut(data_ptr as *mut LeafPageElement, self.count as usize)
-        }
+        let count = self.count() as usize;
+        unsafe { PageBufMut::of(self).elements_mut(PAGE_HEADER_SIZE, count) }
     }
 
-    pub(crate) fn branch_page_element(&self, index: usize) -> &BranchPageElement {
-        &self.branch_page_elements()[index]
+    pub(crate) fn branch_page_element(&self, index: usize) -> BranchElem<'_> {
+        self.branch_page_elements()
+            .get(index)
+            .expect("branch element out of bounds")
     }
 
     pub(crate) fn branch_page_element_mut(&mut self, index: usize) -> &mut BranchPageElement {
         &mut self.branch_page_elements_mut()[index]
     }
 
-    pub(crate) fn branch_page_elements(&self) -> &[BranchPageElement] {
+    pub(crate) fn branch_page_elements(&self) -> Elems<'_, BranchPageElement> {
+        // The element table follows the header.
         unsafe {
-            if self.count == 0 {
-                return &[]; // Return an empty slice
-            }
-
-            let data_ptr = self.get_data_ptr();
+            Elems::new(
+                PageBuf::of(self).at(PAGE_HEADER_SIZE),
+                self.count() as usize,
+            )
+        }
+    }
 
-            // Create a slice from the raw data, treating it as an array of leafPageElements
-            slice::from_raw_parts(data_ptr as *const BranchPageElement, self.count as usize)
+    /// branch_key_prefix returns the prefix shared by the keys of a prefix
+    /// page, which is empty on other pages. The prefix is stored between the
+    /// element table and the first key, so its length is the distance between
+    /// the two.
+    pub(crate) fn branch_key_prefix(&self) -> &[u8] {
+        if !self.is_prefix_page() || self.count() == 0 {
+            return &[];
+        }
+        let table = self.count() as usize * BRANCH_PAGE_ELEMENT_SIZE;
+        let len = (self.branch_page_element(0).pos() as usize).saturating_sub(table);
+        unsafe { PageBuf::of(self).bytes(PAGE_HEADER_SIZE + table, len) }
+    }
+
+    /// branch_key returns the key of a branch element, which is only borrowed
+    /// from the page if it is stored in full.
+    pub(crate) fn branch_key(&self, index: usize) -> Cow<'_, [u8]> {
+        let suffix = self.branch_page_element(index).key();
+        match self.branch_key_prefix() {
+            [] => Cow::Borrowed(suffix),
+            prefix => Cow::Owned([prefix, suffix].concat()),
         }
     }
 
-    pub(crate) fn branch_page_elements_mut(&self) -> &mut [BranchPageElement] {
-        unsafe {
-            if self.count == 0 {
-                return &mut []; // Return an empty slice
-            }
+    /// branch_search binary searches the keys of a branch page for key, like
+    /// slice::binary_search, without copying the keys of a prefix page.
+    pub(crate) fn branch_search(&self, key: &[u8]) -> std::result::Result<usize, usize> {
+        let elems = self.branch_page_elements();
+        let prefix = self.branch_key_prefix();
+        match key.strip_prefix(prefix) {
+            Some(rest) => elems.binary_search_by(|elem| elem.key().cmp(rest)),
+            // The key sorts before or after all the keys of the page.
+            None if key < prefix => Err(0),
+            None => Err(elems.len()),
+        }
+    }
 
-            let data_ptr = self.get_data_ptr();
+    pub(crate) fn branch_page_elements_mut(&mut self) -> &mut [BranchPageElement] {
+        let count = self.count() as usize;
+        unsafe { PageBufMut::of(self).elements_mut(PAGE_HEADER_SIZE, count) }
+    }
 
-            // Create a slice from the raw data, treating it as an array of leafPageElements
-            slice::from_raw_parts_mut(data_ptr as *mut BranchPageElement, self.count as usize)
+    /// try_from_slice is the checked version of from_slice, it makes sure the
+    /// buffer is large enough to hold a page header.
+    pub(crate) fn try_from_slice(buffer: &[u8]) -> Result<&Page> {
+        if buffer.len() < PAGE_HEADER_SIZE {
+            return Err(BoltError::Invalid);
         }
+        Ok(Page::from_slice(buffer))
     }
 
-    // Returns a slice to the free list section of the page.
-    pub(crate) fn free_list(&self) -> &[PgId] {
-        assert!(
-            self.is_freelist_page(),
-            "can't get freelist page IDs from a non-freelist page:{:02x}",
-            self.flags
-        );
-
-        unsafe { slice::from_raw_parts(self.get_data_ptr() as *const PgId, self.count as usize) }
+    /// try_leaf_elements returns the leaf elements of the page after checking
+    /// that the element table and every key and value lie within the page.
+    /// `page_size` is the number of bytes backing the page, including its header
+    /// and overflow. Returns BoltError::Invalid if the page is corrupt.
+    pub(crate) fn try_leaf_elements(&self, page_size: usize) -> Result<Elems<'_, LeafPageElement>> {
+        if !self.is_leaf_page() {
+            return Err(BoltError::Invalid);
+        }
+        self.check_element_table(LEAF_PAGE_ELEMENT_SIZE, page_size)?;
+
+        let elems = self.leaf_page_elements();
+        for (i, elem) in elems.iter().enumerate() {
+            let size = elem.ksize() as u64 + elem.vsize() as u64;
+            check_element(
+                i,
+                LEAF_PAGE_ELEMENT_SIZE,
+                elem.pos(),
+                elem.ksize(),
+                size,
+                page_size,
+                false,
+            )?;
+        }
+        Ok(elems)
+    }
+
+    /// try_branch_elements returns the branch elements of the page after checking
+    /// that the element table and every key lie within the page, as well as
+    /// the key prefix of a prefix page.
+    /// `page_size` is the number of bytes backing the page, including its header
+    /// and overflow. Returns BoltError::Invalid if the page is corrupt.
+    pub(crate) fn try_branch_elements(
+        &self,
+        page_size: usize,
+    ) -> Result<Elems<'_, BranchPageElement>> {
+        if !self.is_branch_page() {
+            return Err(BoltError::Invalid);
+        }
+        self.check_element_table(BRANCH_PAGE_ELEMENT_SIZE, page_size)?;
+
+        // A prefix page stores the prefix of its keys before the first one,
+        // so their remainders may be empty.
+        let prefix = self.is_prefix_page();
+        let elems = self.branch_page_elements();
+        if let Some(first) = elems.first().filter(|_| prefix) {
+            let table = elems.len() * BRANCH_PAGE_ELEMENT_SIZE;
+            if (first.pos() as usize) <= table {
+                return Err(BoltError::Invalid);
+            }
+        }
+        for (i, elem) in elems.iter().enumerate() {
+            let size = elem.ksize() as u64;
+            check_element(
+                i,
+                BRANCH_PAGE_ELEMENT_SIZE,
+                elem.pos(),
+                elem.ksize(),
+                size,
+                page_size,
+                prefix,
+            )?;
+        }
+        Ok(elems)
     }
 
-    // Returns a mut slice to the free list section of the page.
-    pub(crate) fn free_list_mut(&mut self) -> &mut [PgId] {
-        assert!(
-            self.is_freelist_page(),
-            "can't get freelist page IDs from a non-freelist page:{:02x}",
-            self.flags
-        );
+    /// try_validate checks the elements of a branch or leaf page, see
+    /// try_leaf_elements and try_branch_elements.
+    pub(crate) fn try_validate(&self, page_size: usize) -> Result<()> {
+        match self.is_leaf_page() {
+            true => self.try_leaf_elements(page_size).map(|_| ()),
+            false => self.try_branch_elements(page_size).map(|_| ()),
+        }
+    }
 
-        unsafe {
-            std::slice::from_raw_parts_mut(
-                self.get_data_mut_ptr() as *mut PgId,
-                self.count as usize,
-            )
+    // check_element_table makes sure the element table fits in the page.
+    fn check_element_table(&self, elem_size: usize, page_size: usize) -> Result<()> {
+        if PAGE_HEADER_SIZE + self.count() as usize * elem_size > page_size {
+            return Err(BoltError::Invalid);
         }
+        Ok(())
     }
 
     pub fn freelist_page_count(&self) -> (usize, usize) {
         assert!(
             self.is_freelist_page(),
             "can't get freelist page count from a non-freelist page: {:02x}",
-            self.flags
+            self.flags()
         );
 
         // If the page.count is at the max uint16 value (64k) then it's considered
         // an overflow and the size of the freelist is stored as the first element.
-        let count = self.count as usize;
+        let count = self.count() as usize;
 
         if count == 0xFFFF {
-            let data_ptr = self.get_data_ptr() as *const PgId;
-            let count = (data_ptr) as usize; // Get count from first element
-
-            if count >= std::usize::MAX {
-                panic!("leading element count overflows usize");
-            }
-            return (1, count);
+            // Get count from first element
+            let count = self.freelist_id(0);
+            assert!(
+                count < usize::MAX as PgId,
+                "leading element count overflows usize"
+            );
+            return (1, count as usize);
         }
 
         (0, count)
     }
 
-    pub fn freelist_page_ids(&self) -> &[PgId] {
+    /// freelist_page_ids returns a copy of the page ids stored on a freelist page.
+    /// The ids are read one by one since the page buffer may not be aligned.
+    pub fn freelist_page_ids(&self) -> Vec<PgId> {
         assert!(
             self.is_freelist_page(),
             "can't get freelist page IDs from a non-freelist page: {:02x}",
-            self.flags
+            self.flags()
         );
 
         let (idx, count) = self.freelist_page_count();
 
-        if count == 0 {
-            return &[];
-        }
+        (idx..idx + count).map(|i| self.freelist_id(i)).collect()
+    }
 
-        unsafe {
-            let data_ptr = self.get_data_ptr();
+    // freelist_id reads the i-th id slot of a freelist page.
+    fn freelist_id(&self, i: usize) -> PgId {
+        let offset = PAGE_HEADER_SIZE + i * PGID_SIZE;
+        PgId::from_le(unsafe { PageBuf::of(self).read(offset) })
+    }
+
+    /// try_freelist_page_ids is the checked version of freelist_page_ids, it
+    /// makes sure the ids lie within the `size` bytes backing the page.
+    /// Returns BoltError::Invalid if the page is corrupt.
+    pub(crate) fn try_freelist_page_ids(&self, size: usize) -> Result<Vec<PgId>> {
+        if !self.is_freelist_page() {
+            return Err(BoltError::Invalid);
+        }
+        let slots = (size.saturating_sub(PAGE_HEADER_SIZE) / mem::size_of::<PgId>()) as u64;
+        let (idx, count) = match self.count() {
+            0xFFFF if slots == 0 => return Err(BoltError::Invalid),
+            0xFFFF => (1, self.freelist_id(0)),
+            count => (0, count as u64),
+        };
+        if count.saturating_add(idx) > slots {
+            return Err(BoltError::Invalid);
+        }
+        Ok(self.freelist_page_ids())
+    }
 
-            std::slice::from_raw_parts(data_ptr as *const PgId, count)
+    /// put_freelist_ids stores ids in the page id slots after the page header
+    /// from slot on, regardless of the page count, so the freelist can be
+    /// serialized into them.
+    pub(crate) fn put_freelist_ids(&mut self, slot: usize, ids: &[PgId]) {
+        let mut buf = PageBufMut::of(self);
+        for (i, id) in ids.iter().enumerate() {
+            let offset = PAGE_HEADER_SIZE + (slot + i) * PGID_SIZE;
+            unsafe { buf.write(offset, id.to_le()) };
         }
     }
 
@@ -330,111 +431,88 @@ impl Page {
         if self.is_leaf_page() {
             return LEAF_PAGE_ELEMENT_SIZE;
         }
-        return BRANCH_PAGE_ELEMENT_SIZE;
+        BRANCH_PAGE_ELEMENT_SIZE
     }
 
     pub fn id(&self) -> PgId {
-        self.id
+        PgId::from_le(self.id)
     }
 
     pub fn set_id(&mut self, target: PgId) {
-        self.id = target;
+        self.id = target.to_le();
     }
 
     pub fn flags(&self) -> PageFlags {
-        self.flags
+        PageFlags::from_bits_retain(u16::from_le(self.flags))
     }
 
     pub fn set_flags(&mut self, flags: PageFlags) {
-        self.flags = flags;
+        self.flags = flags.bits().to_le();
     }
 
     pub fn count(&self) -> u16 {
-        self.count
+        u16::from_le(self.count)
     }
 
     pub fn set_count(&mut self, count: u16) {
-        self.count = count;
+        self.count = count.to_le();
     }
 
     pub fn overflow(&self) -> u32 {
-        self.overflow
+        u32::from_le(self.overflow)
     }
 
     pub fn set_overflow(&mut self, overflow: u32) {
-        self.overflow = overflow;
-    }
-
-    pub fn to_string(&self) -> String {
-        format!(
-            "ID: {}, Type: {}, count: {}, overflow: {}",
-            self.id,
-            self.typ(),
-            self.count,
-            self.overflow
-        )
-    }
-
-    pub(crate) fn pgid(&self, index: usize) -> &PgId {
-        &self.pg_ids()[index]
-    }
-
-    pub(crate) fn pg_ids(&self) -> &[PgId] {
-        unsafe { slice::from_raw_parts(self.get_data_ptr() as *const PgId, self.count as usize) }
-    }
-
-    pub(crate) fn pg_ids_mut(&mut self) -> &mut [PgId] {
-        unsafe {
-            std::slice::from_raw_parts_mut(
-                self.get_data_mut_ptr() as *mut PgId,
-                self.count as usize,
-            )
-        }
-    }
-
-    #[inline]
-    pub(crate) fn get_data_mut_ptr(&mut self) -> *mut u8 {
-        unsafe { (&mut self.ptr as *mut PhantomData<u8> as *mut u8) }
+        self.overflow = overflow.to_le();
     }
 
     #[inline]
-    pub(crate) fn get_data_ptr(&self) -> *const u8 {
-        unsafe { (&self.ptr as *const PhantomData<u8> as *const u8) }
+    pub(crate) fn get_data_slice(&self) -> Result<&[u8]> {
+        Ok(&self.as_slice()?[PAGE_HEADER_SIZE..])
     }
 
     #[inline]
-    pub(crate) fn get_data_slice(&self) -> &[u8] {
-        let ptr = self.get_data_ptr();
-        unsafe { slice::from_raw_parts(ptr, self.byte_size() - PAGE_HEADER_SIZE) }
+    pub(crate) fn as_slice(&self) -> Result<&[u8]> {
+        let size = self.byte_size()?;
+        Ok(unsafe { PageBuf::of(self).bytes(0, size) })
     }
 
     #[inline]
-    pub(crate) fn as_slice(&self) -> &[u8] {
-        let ptr: *const u8 = self as *const Page as *const u8;
-        unsafe { slice::from_raw_parts(ptr, self.byte_size()) }
-    }
-
-    #[inline]
-    pub(crate) fn as_slice_mut(&mut self) -> &mut [u8] {
-        let ptr = self as *mut Page as *mut u8;
-        unsafe { slice::from_raw_parts_mut(ptr, self.byte_size()) }
+    pub(crate) fn as_slice_mut(&mut self) -> Result<&mut [u8]> {
+        let size = self.byte_size()?;
+        Ok(unsafe { PageBufMut::of(self).bytes_mut(0, size) })
     }
 
+    /// from_slice returns the page held by buffer, which may sit at any
+    /// address. Panics if buffer is shorter than a page header, see
+    /// try_from_slice.
     #[inline]
     pub(crate) fn from_slice(buffer: &[u8]) -> &Page {
-        unsafe { &*(buffer.as_ptr() as *const Page) }
+        assert!(buffer.len() >= PAGE_HEADER_SIZE);
+        // The metadata of the pointer is the length of the data field.
+        let ptr = ptr::slice_from_raw_parts(buffer.as_ptr(), buffer.len() - PAGE_HEADER_SIZE);
+        unsafe { &*(ptr as *const Page) }
     }
 
+    /// from_slice_mut returns the page held by buffer, which may sit at any
+    /// address. Panics if buffer is shorter than a page header.
     #[inline]
-    pub(crate) fn from_slice_mut(mut buffer: &mut [u8]) -> &mut Self {
-        unsafe { &mut *(buffer.as_mut_ptr() as *mut Page) }
+    pub(crate) fn from_slice_mut(buffer: &mut [u8]) -> &mut Self {
+        assert!(buffer.len() >= PAGE_HEADER_SIZE);
+        let len = buffer.len() - PAGE_HEADER_SIZE;
+        let ptr = ptr::slice_from_raw_parts_mut(buffer.as_mut_ptr(), len);
+        unsafe { &mut *(ptr as *mut Page) }
     }
 
-    pub(crate) fn byte_size(&self) -> usize {
+    /// byte_size returns the number of bytes the page uses, or
+    /// BoltError::Corrupted if its flags name no known page type.
+    pub(crate) fn byte_size(&self) -> Result<usize> {
         let mut size = PAGE_HEADER_SIZE;
 
-        match self.flags {
-            PageFlags::BRANCH_PAGE => {
+        match self.flags() {
+            // The prefix of a prefix page sits before the first key, so the
+            // last key still ends the page.
+            _ if self.is_branch_page() => {
                 let branch = self.branch_page_elements();
                 let len = branch.len();
                 if len > 0 {
@@ -449,54 +527,66 @@ impl Page {
                 if len > 0 {
                     let last_leaf = leaves.last().unwrap();
                     size += (len - 1) * LEAF_PAGE_ELEMENT_SIZE;
-                    size += (last_leaf.pos + last_leaf.ksize + last_leaf.vsize) as usize;
+                    size += (last_leaf.pos() + last_leaf.ksize() + last_leaf.vsize()) as usize;
                 }
             }
             PageFlags::META_PAGE => {
                 size += META_PAGE_SIZE;
             }
             PageFlags::FREELIST_PAGE => {
-                size += self.pg_ids().len() * mem::size_of::<PgId>();
+                size += self.count() as usize * PGID_SIZE;
+            }
+            flags => {
+                let reason = format!("unknown page flags: {:x}", flags);
+                return Err(BoltError::corrupted(reason).with_pgid(self.id()));
             }
-            _ => panic!("Unknown page flag: {}", self.flags),
         }
-        size
+        Ok(size)
     }
 }
 
+// check_element makes sure the data of the i-th element, `size` bytes at `pos`
+// relative to the element, lies within the page and starts with a non-empty key.
+fn check_element(
+    i: usize,
+    elem_size: usize,
+    pos: u32,
+    ksize: u32,
+    size: u64,
+    page_size: usize,
+    empty_key: bool,
+) -> Result<()> {
+    let offset = (PAGE_HEADER_SIZE + i * elem_size) as u64;
+    if (ksize == 0 && !empty_key) || offset + pos as u64 + size > page_size as u64 {
+        return Err(BoltError::Invalid);
+    }
+    Ok(())
+}
+
 impl fmt::Display for Page {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "{{ ID: {}, Type: {}, count: {}, overflow: {} }}",
-            self.id,
+            self.id(),
             self.typ(),
-            self.count,
-            self.overflow
+            self.count(),
+            self.overflow()
         )
     }
 }
 
-impl ToOwned for Page {
-    type Owned = OwnedPage;
-
-    fn to_owned(&self) -> Self::Owned {
-        let ptr = self as *const Page as *const u8;
-        unsafe {
-            let slice = slice::from_raw_parts(ptr, self.byte_size()).to_owned();
-            OwnedPage::from_vec(slice)
-        }
-    }
-}
-
 ///////////////////////////////////////////////////////////
 //              Page 页面元素 BranchPageElement
 ///////////////////////////////////////////////////////////
 /**
  * BranchPageElement
+ *
+ * Packed so that elements can be read at any address, e.g. from the page of an
+ * inline bucket. The layout is the same as without packing.
  */
 #[derive(Debug, Default)]
-#[repr(C)]
+#[repr(C, packed)]
 pub(crate) struct BranchPageElement {
     pos: u32,
     ksize: u32,
@@ -505,42 +595,43 @@ pub(crate) struct BranchPageElement {
 
 impl BranchPageElement {
     pub(crate) fn pos(&self) -> u32 {
-        self.pos
+        u32::from_le(self.pos)
     }
 
     pub(crate) fn set_pos(&mut self, pos: u32) {
-        self.pos = pos;
+        self.pos = pos.to_le();
     }
 
     pub fn ksize(&self) -> u32 {
-        self.ksize
+        u32::from_le(self.ksize)
     }
 
     pub fn set_ksize(&mut self, size: u32) {
-        self.ksize = size;
+        self.ksize = size.to_le();
     }
 
     pub fn pgid(&self) -> PgId {
-        self.pgid
+        PgId::from_le(self.pgid)
     }
 
     pub fn set_pgid(&mut self, v: PgId) {
-        self.pgid = v;
+        self.pgid = v.to_le();
     }
+}
 
-    /// Key returns a byte slice of the node key.
-    pub(crate) fn key(&self) -> &[u8] {
-        must_align(self);
+/// BranchElem is an element of a branch page, see Elem.
+pub(crate) type BranchElem<'a> = Elem<'a, BranchPageElement>;
 
-        unsafe {
-            let key_ptr = ptr::addr_of!(self.pos) as *const u8;
-            std::slice::from_raw_parts(key_ptr, self.ksize as usize)
-        }
+impl<'a> BranchElem<'a> {
+    /// Key returns a byte slice of the node key.
+    pub(crate) fn key(self) -> &'a [u8] {
+        // The key is pos bytes past the element, in the same page.
+        unsafe { self.buf().bytes(self.pos() as usize, self.ksize() as usize) }
     }
 
     #[inline]
-    pub(crate) const fn as_ptr(&self) -> *const u8 {
-        self as *const Self as *const u8
+    pub(crate) fn as_ptr(self) -> *const u8 {
+        self.buf().as_ptr()
     }
 }
 
@@ -550,92 +641,104 @@ impl BranchPageElement {
 
 ///
 /// LeafPageElement represents a node on a leaf page.
+/// Packed for the same reason as BranchPageElement.
 ///
 #[derive(Debug, Default)]
-#[repr(C)]
+#[repr(C, packed)]
 pub(crate) struct LeafPageElement {
     flags: u32,
-    pub(crate) pos: u32,
-    pub(crate) ksize: u32,
-    pub(crate) vsize: u32,
+    pos: u32,
+    ksize: u32,
+    vsize: u32,
 }
 
+// Elements are packed and valid for any bytes.
+unsafe impl Element for BranchPageElement {}
+unsafe impl Element for LeafPageElement {}
+
 impl LeafPageElement {
     pub fn new(flags: u32, pos: u32, ksize: u32, vsize: u32) -> Self {
         Self {
-            flags,
-            pos,
-            ksize,
-            vsize,
+            flags: flags.to_le(),
+            pos: pos.to_le(),
+            ksize: ksize.to_le(),
+            vsize: vsize.to_le(),
         }
     }
 
     // Getters and setters for flags, pos, ksize, vsize (similar to BranchPageElement)
 
+    pub(crate) fn ksize(&self) -> u32 {
+        u32::from_le(self.ksize)
+    }
+
+    pub(crate) fn vsize(&self) -> u32 {
+        u32::from_le(self.vsize)
+    }
+
     pub(crate) fn set_ksize(&mut self, len: u32) {
-        self.ksize = len;
+        self.ksize = len.to_le();
     }
 
     pub(crate) fn set_vsize(&mut self, len: u32) {
-        self.vsize = len;
+        self.vsize = len.to_le();
     }
 
     pub(crate) fn flags(&self) -> u32 {
-        self.flags
+        u32::from_le(self.flags)
     }
 
     pub(crate) fn set_flags(&mut self, flags: u32) {
-        self.flags = flags;
+        self.flags = flags.to_le();
     }
 
     pub(crate) fn pos(&self) -> u32 {
-        self.pos
+        u32::from_le(self.pos)
     }
 
     pub(crate) fn set_pos(&mut self, pos: u32) {
-        self.pos = pos;
+        self.pos = pos.to_le();
     }
 
-    /// Key returns a byte slice of the node key.
-    pub fn key(&self) -> &[u8] {
-        unsafe {
-            let key_ptr = ptr::addr_of!(self.pos) as *const u8;
-            std::slice::from_raw_parts(key_ptr, self.ksize as usize)
-        }
+    pub(crate) fn is_bucket_entry(&self) -> bool {
+        (self.flags() & BUCKET_LEAF_FLAG) != 0
     }
+}
 
-    /// Value returns a byte slice of the node value.
-    pub(crate) fn value(&self) -> &[u8] {
-        must_align(self);
-
-        unsafe {
-            let value_ptr = ptr::addr_of!(self.vsize) as *const u8; // Adjust pointer offset
+/// LeafElem is an element of a leaf page, see Elem.
+pub(crate) type LeafElem<'a> = Elem<'a, LeafPageElement>;
 
-            slice::from_raw_parts(value_ptr, self.vsize as usize)
-        }
+impl<'a> LeafElem<'a> {
+    /// Key returns a byte slice of the node key.
+    pub(crate) fn key(self) -> &'a [u8] {
+        // The key and the value are pos bytes past the element, in the same page.
+        unsafe { self.buf().bytes(self.pos() as usize, self.ksize() as usize) }
     }
 
-    pub(crate) fn is_bucket_entry(&self) -> bool {
-        (self.flags & BUCKET_LEAF_FLAG) != 0
+    /// Value returns a byte slice of the node value.
+    pub(crate) fn value(self) -> &'a [u8] {
+        let pos = self.pos() as usize + self.ksize() as usize;
+        unsafe { self.buf().bytes(pos, self.vsize() as usize) }
     }
 
-    pub(crate) fn bucket(&self) -> Option<&InBucket> {
+    /// bucket returns a copy of the bucket header stored in the value, or None
+    /// if the element is not a bucket or its header is truncated.
+    /// The value may sit at any offset, so the header is parsed byte-wise.
+    pub(crate) fn bucket(self) -> Option<InBucket> {
         if self.is_bucket_entry() {
-            // Assuming LoadBucket loads a bucket from a byte slice
-            unsafe { load_bucket(self.value()) }
+            InBucket::try_from_bytes(self.value())
         } else {
             None
         }
     }
 
     #[inline]
-    pub(crate) const fn as_ptr(&self) -> *const u8 {
-        self as *const Self as *const u8
+    pub(crate) fn as_ptr(self) -> *const u8 {
+        self.buf().as_ptr()
     }
 }
 
-///
-///
+/// PgIds is a sorted list of page ids.
 #[derive(Clone, Debug, Default, PartialOrd, PartialEq)]
 pub(crate) struct PgIds {
     pgids: Vec<PgId>,
@@ -679,7 +782,7 @@ impl PgIds {
     }
 
     #[inline]
-    pub fn to_vec(self) -> Vec<PgId> {
+    pub fn into_vec(self) -> Vec<PgId> {
         self.pgids
     }
 
@@ -700,7 +803,7 @@ impl PgIds {
     #[inline]
     pub fn extend_from_slice(&mut self, slice: Self) {
         //extend from anther slice pgids
-        self.pgids.extend_from_slice(&*slice.pgids);
+        self.pgids.extend_from_slice(&slice.pgids);
 
         //first sorted
         self.pgids.sort();
@@ -713,11 +816,11 @@ impl PgIds {
     }
 }
 
-// represents human-readable information about a page.
-#[derive(Debug, Default)]
-pub(crate) struct PageInfo {
+/// PageInfo represents human-readable information about a page.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageInfo {
     id: u64,
-    typ: u16,
+    typ: String,
     count: usize,
     overflow_count: usize,
 }
@@ -731,19 +834,19 @@ impl PageInfo {
     }
 
     ///Getter and Setter
-    pub(crate) fn id(&self) -> u64 {
+    pub fn id(&self) -> u64 {
         self.id
     }
 
-    pub(crate) fn typ(&self) -> u16 {
-        self.typ
+    pub fn typ(&self) -> &str {
+        &self.typ
     }
 
-    pub(crate) fn count(&self) -> usize {
+    pub fn count(&self) -> usize {
         self.count
     }
 
-    pub(crate) fn overflow_count(&self) -> usize {
+    pub fn overflow_count(&self) -> usize {
         self.overflow_count
     }
 
@@ -751,7 +854,7 @@ impl PageInfo {
         self.id = id;
     }
 
-    pub(crate) fn set_typ(&mut self, typ: u16) {
+    pub(crate) fn set_typ(&mut self, typ: String) {
         self.typ = typ;
     }
 
@@ -762,32 +865,20 @@ impl PageInfo {
     pub(crate) fn set_overflow_count(&mut self, overflow_count: usize) {
         self.overflow_count = overflow_count;
     }
-
-    pub(crate) fn id_mut(&mut self) -> &mut u64 {
-        &mut self.id
-    }
-
-    pub(crate) fn typ_mut(&mut self) -> &mut u16 {
-        &mut self.typ
-    }
-
-    pub(crate) fn count_mut(&mut self) -> &mut usize {
-        &mut self.count
-    }
-
-    pub(crate) fn overflow_count_mut(&mut self) -> &mut usize {
-        &mut self.overflow_count
-    }
 }
 
 ///
 ///OwnedPage is  Page impl ToOwned  trait struct
 ///
+///
+/// The buffer is made of u64 words so that the page header, the meta and the
+/// freelist ids are naturally aligned, even on strict-alignment targets.
 #[derive(Clone, Debug)]
-#[repr(align(64))]
 pub(crate) struct OwnedPage {
-    ///Page bytes buffer
-    page: Vec<u8>,
+    ///Page buffer words
+    page: Vec<u64>,
+    ///Page size in bytes
+    len: usize,
 }
 
 impl OwnedPage {
@@ -795,64 +886,102 @@ impl OwnedPage {
     ///
     pub(crate) fn new(size: usize) -> Self {
         Self {
-            page: vec![0u8; size],
+            page: vec![0u64; size.div_ceil(PGID_SIZE)],
+            len: size,
         }
     }
 
     /// build OwnedPage from Vec<u8> buffer
     pub(crate) fn from_vec(buf: Vec<u8>) -> Self {
-        Self { page: buf }
+        Self::from_slice(&buf)
+    }
+
+    /// build OwnedPage from a copy of a byte slice
+    pub(crate) fn from_slice(buf: &[u8]) -> Self {
+        let mut page = Self::new(buf.len());
+        page.buf_mut().copy_from_slice(buf);
+        page
     }
 
     /// reserve capacity of underlying vector to size
     #[allow(dead_code)]
     pub(crate) fn reserve(&mut self, size: usize) {
-        self.page.reserve(size);
+        self.page.reserve(size.div_ceil(PGID_SIZE));
     }
 
     /// Returns pointer to page structure
     #[inline]
     pub(crate) fn as_ptr(&self) -> *const u8 {
-        self.page.as_ptr()
+        self.page.as_ptr() as *const u8
     }
 
     /// Returns pointer to page structure
     #[allow(dead_code)]
     #[inline]
     pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
-        self.page.as_mut_ptr()
+        self.page.as_mut_ptr() as *mut u8
     }
 
     /// Returns binary serialized buffer pf a page
     #[inline]
     pub(crate) fn buf(&self) -> &[u8] {
-        &self.page
+        unsafe { PageBuf::of(self.page.as_slice()).bytes(0, self.len) }
     }
 
     /// Returns binary serialized muttable buffer of a page
     #[inline]
     pub(crate) fn buf_mut(&mut self) -> &mut [u8] {
-        &mut self.page
+        let len = self.len;
+        unsafe { PageBufMut::of(self.page.as_mut_slice()).bytes_mut(0, len) }
     }
 
     /// Returns page size
     #[inline]
     pub(crate) fn size(&self) -> usize {
-        self.page.len()
+        self.len
+    }
+
+    /// capacity returns the size the page can be resized to without
+    /// reallocating its buffer.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.page.len() * PGID_SIZE
+    }
+
+    /// resize changes the size of the page, growing its buffer if needed.
+    /// Bytes past the former size are left as they were.
+    pub(crate) fn resize(&mut self, size: usize) {
+        if size > self.capacity() {
+            self.page.resize(size.div_ceil(PGID_SIZE), 0);
+        }
+        self.len = size;
+    }
+
+    /// into_bytes turns the page into a shared buffer without copying it,
+    /// so the buffer keeps the alignment of the page.
+    pub(crate) fn into_bytes(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl AsRef<[u8]> for OwnedPage {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.buf()
     }
 }
 
 impl Borrow<Page> for OwnedPage {
     #[inline]
     fn borrow(&self) -> &Page {
-        unsafe { &*(self.page.as_ptr() as *const Page) }
+        Page::from_slice(self.buf())
     }
 }
 
 impl BorrowMut<Page> for OwnedPage {
     #[inline]
     fn borrow_mut(&mut self) -> &mut Page {
-        unsafe { &mut *(self.page.as_mut_ptr() as *mut Page) }
+        Page::from_slice_mut(self.buf_mut())
     }
 }
 
@@ -876,12 +1005,16 @@ impl DerefMut for OwnedPage {
 mod tests {
     use super::*;
 
+    // page_of returns an empty page with the given flags.
+    fn page_of(flags: PageFlags) -> OwnedPage {
+        let mut page = OwnedPage::new(PAGE_HEADER_SIZE);
+        page.set_flags(flags);
+        page
+    }
+
     #[test]
     fn test_page_type() {
-        let page = Page {
-            flags: PageFlags::LEAF_PAGE,
-            ..Default::default()
-        };
+        let page = page_of(PageFlags::LEAF_PAGE);
 
         let typ = page.typ();
         println!("page type:{}", typ);
@@ -890,54 +1023,16 @@ mod tests {
         }
 
         // Test branch page
-        assert_eq!(
-            Page {
-                flags: PageFlags::BRANCH_PAGE,
-                ..Default::default()
-            }
-            .typ(),
-            "branch"
-        );
+        assert_eq!(page_of(PageFlags::BRANCH_PAGE).typ(), "branch");
 
         // Test leaf page
-        assert_eq!(
-            Page {
-                flags: PageFlags::LEAF_PAGE,
-                ..Default::default()
-            }
-            .typ(),
-            "leaf"
-        );
+        assert_eq!(page_of(PageFlags::LEAF_PAGE).typ(), "leaf");
 
         // Test meta page
-        assert_eq!(
-            Page {
-                flags: PageFlags::META_PAGE,
-                ..Default::default()
-            }
-            .typ(),
-            "meta"
-        );
+        assert_eq!(page_of(PageFlags::META_PAGE).typ(), "meta");
 
         // Test freelist page
-        assert_eq!(
-            Page {
-                flags: PageFlags::FREELIST_PAGE,
-                ..Default::default()
-            }
-            .typ(),
-            "freelist"
-        );
-
-        // Test unknown flag
-        // assert_eq!(
-        //     Page {
-        //         flags: PageFlags::from_bits(20000).unwrap(),
-        //         ..Default::default()
-        //     }
-        //     .typ(),
-        //     format!("unknown<{:x}>", 20000)
-        // );
+        assert_eq!(page_of(PageFlags::FREELIST_PAGE).typ(), "freelist");
     }
 
     #[test]
@@ -961,40 +1056,37 @@ mod tests {
 
     #[test]
     fn test_page_buffer() {
-        let page: Page = Page::default();
+        let page = page_of(PageFlags::BRANCH_PAGE);
 
-        println!("new page from default :{}", page);
+        println!("new page :{}", &*page);
 
-        println!("page ptr:{:p}", &page);
-        println!("page id:{:p}", &page.id);
-        println!("page count:{:p}", &page.count);
-        println!("page ptr pathomdata:{:p}", page.get_data_ptr());
+        println!("page ptr:{:p}", &*page);
 
-        let mut page: Page = Page::default();
+        // Elements live past the header, so back the page with a real buffer.
+        let mut page = OwnedPage::new(1024);
         page.set_id(2);
         page.set_flags(PageFlags::LEAF_PAGE);
         page.set_count(2);
         page.set_overflow(0);
 
-        let buffer = page.as_slice();
-        let mut new_page = Page::from_slice(buffer);
+        let new_page = Page::from_slice(page.buf());
 
-        assert_eq!(buffer, new_page.as_slice());
+        assert_eq!(page.as_slice().unwrap(), new_page.as_slice().unwrap());
     }
 
     #[test]
     fn test_page_new() {
-        let mut buf = vec![0u8; 1024];
-        let mut page = Page::from_slice_mut(&mut buf);
+        let mut buf = OwnedPage::new(1024);
+        let page = Page::from_slice_mut(buf.buf_mut());
 
-        assert_eq!(page.id, 0);
-        assert_eq!(page.count, 0);
+        assert_eq!(page.id(), 0);
+        assert_eq!(page.count(), 0);
 
         page.set_id(36);
-        assert_eq!(page.id, 36);
+        assert_eq!(page.id(), 36);
 
         page.set_flags(PageFlags::META_PAGE);
-        assert_eq!(page.flags, PageFlags::META_PAGE);
+        assert_eq!(page.flags(), PageFlags::META_PAGE);
 
         let mut page: OwnedPage = OwnedPage::new(1024);
         page.set_id(26);
@@ -1005,21 +1097,21 @@ mod tests {
 
     #[test]
     fn test_read_ownedpage() {
-        let mut buf: Vec<u8> = vec![0u8; 4096];
+        let mut buf = OwnedPage::new(4096);
         let len: usize = 2;
 
-        let mut page = Page::from_slice_mut(&mut buf);
+        let page = Page::from_slice_mut(buf.buf_mut());
 
         page.set_id(123);
         page.set_flags(PageFlags::LEAF_PAGE);
         page.set_count(len as u16);
         page.set_overflow(0);
 
-        let ptr = page.get_data_ptr();
+        let ptr = page.as_slice().unwrap()[PAGE_HEADER_SIZE..].as_ptr();
 
-        let nodes = unsafe { slice::from_raw_parts_mut(ptr as *mut LeafPageElement, len) };
+        let nodes = page.leaf_page_elements_mut();
 
-        assert_eq!(nodes[0].as_ptr(), ptr);
+        assert_eq!(ptr::from_ref(&nodes[0]).cast::<u8>(), ptr);
 
         // 0 node
         nodes[0].set_pos(32);
@@ -1028,34 +1120,29 @@ mod tests {
         nodes[0].set_vsize(5);
 
         // 1 node
-        nodes[1] = LeafPageElement {
-            flags: 0,
-            pos: 26,
-            ksize: 3,
-            vsize: 4,
-        };
+        nodes[1] = LeafPageElement::new(0, 26, 3, 4);
 
         //to read leaf element
         let elem = page.leaf_page_element(0);
 
-        assert_eq!(elem.pos, 32);
-        assert_eq!(elem.ksize, 5);
-        assert_eq!(elem.vsize, 5);
+        assert_eq!(elem.pos(), 32);
+        assert_eq!(elem.ksize(), 5);
+        assert_eq!(elem.vsize(), 5);
         assert_eq!(elem.flags(), 1);
 
         let elem1 = page.leaf_page_element(1);
-        assert_eq!(elem1.pos, 26);
-        assert_eq!(elem1.ksize, 3);
-        assert_eq!(elem1.vsize, 4);
+        assert_eq!(elem1.pos(), 26);
+        assert_eq!(elem1.ksize(), 3);
+        assert_eq!(elem1.vsize(), 4);
         assert_eq!(elem1.flags(), 0);
     }
 
     #[test]
     fn test_write_ownedpage() {
-        let mut buf: Vec<u8> = vec![0u8; 4096];
+        let mut buf = OwnedPage::new(4096);
         let len: usize = 2;
 
-        let mut page = Page::from_slice_mut(&mut buf);
+        let page = Page::from_slice_mut(buf.buf_mut());
 
         page.set_id(123);
         page.set_flags(PageFlags::LEAF_PAGE);
@@ -1064,35 +1151,203 @@ mod tests {
 
         assert_eq!(page.typ(), "leaf");
 
-        let ptr = page.get_data_ptr();
+        let ptr = page.as_slice().unwrap()[PAGE_HEADER_SIZE..].as_ptr();
 
-        let nodes = unsafe { slice::from_raw_parts_mut(ptr as *mut LeafPageElement, len) };
+        let nodes = page.leaf_page_elements_mut();
 
-        assert_eq!(nodes[0].as_ptr(), ptr);
+        assert_eq!(ptr::from_ref(&nodes[0]).cast::<u8>(), ptr);
         nodes[0].set_pos(32);
         nodes[0].set_ksize(5);
         nodes[0].set_flags(1);
         nodes[0].set_vsize(5);
 
-        nodes[1] = LeafPageElement {
-            flags: 0,
-            pos: 26,
-            ksize: 3,
-            vsize: 4,
-        };
+        nodes[1] = LeafPageElement::new(0, 26, 3, 4);
 
         assert_eq!(page.typ(), "leaf");
 
         println!(
             "page head:{}, size:{},leaf size:{}, buffer:{:?}",
             PAGE_HEADER_SIZE,
-            page.byte_size(),
+            page.byte_size().unwrap(),
             (len * LEAF_PAGE_ELEMENT_SIZE + 7 + 10),
-            page.as_slice(),
+            page.as_slice().unwrap(),
+        );
+
+        let owned_page = OwnedPage::from_slice(page.as_slice().unwrap());
+
+        println!("owned: {}", owned_page.size())
+    }
+
+    // Ensure that the checked accessors accept a well formed page and reject
+    // elements pointing outside of it.
+    #[test]
+    fn test_try_leaf_elements() {
+        let mut page = OwnedPage::new(128);
+        page.set_flags(PageFlags::LEAF_PAGE);
+        page.set_count(2);
+        *page.leaf_page_element_mut(0) = LeafPageElement::new(0, 32, 3, 4);
+        *page.leaf_page_element_mut(1) = LeafPageElement::new(0, 23, 3, 4);
+        assert_eq!(page.try_leaf_elements(128).unwrap().len(), 2);
+
+        // The data of the second element ends past the page.
+        page.leaf_page_element_mut(1).set_vsize(100);
+        assert_eq!(page.try_leaf_elements(128).err(), Some(BoltError::Invalid));
+
+        // The element table itself does not fit.
+        page.set_count(16);
+        assert_eq!(page.try_leaf_elements(128).err(), Some(BoltError::Invalid));
+
+        // Not a leaf page.
+        page.set_flags(PageFlags::BRANCH_PAGE);
+        assert_eq!(page.try_leaf_elements(128).err(), Some(BoltError::Invalid));
+    }
+
+    // Ensure that branch elements with an empty key or an out of range key are rejected.
+    #[test]
+    fn test_try_branch_elements() {
+        let mut page = OwnedPage::new(128);
+        page.set_flags(PageFlags::BRANCH_PAGE);
+        page.set_count(1);
+        page.branch_page_element_mut(0).set_pos(16);
+        page.branch_page_element_mut(0).set_ksize(8);
+        assert!(page.try_branch_elements(128).is_ok());
+        assert!(page.try_validate(128).is_ok());
+
+        page.branch_page_element_mut(0).set_ksize(0);
+        assert_eq!(
+            page.try_branch_elements(128).err(),
+            Some(BoltError::Invalid)
         );
 
-        let ownedPage = page.to_owned();
+        page.branch_page_element_mut(0).set_ksize(u32::MAX);
+        assert_eq!(
+            page.try_branch_elements(128).err(),
+            Some(BoltError::Invalid)
+        );
+        assert!(Page::try_from_slice(&[0u8; 8]).is_err());
+    }
+
+    // Ensure that the size of a page with unknown flags is reported as
+    // corruption.
+    #[test]
+    fn test_byte_size_unknown_flags() {
+        let mut page = OwnedPage::new(128);
+        page.set_id(3);
+        page.set_flags(PageFlags::LEAF_PAGE | PageFlags::PREFIX_PAGE);
+        let err = page.byte_size().unwrap_err();
+        assert!(matches!(err, BoltError::Corrupted { .. }));
+        assert_eq!(err.context().unwrap().pgid, Some(3));
+        assert!(page.as_slice().is_err());
+    }
+
+    // Ensure that pages and their meta are read and written at any address,
+    // rather than panicking on an unaligned buffer.
+    #[test]
+    fn test_page_unaligned() {
+        let mut buf = OwnedPage::new(1 + 4096);
+        let page = Page::from_slice_mut(&mut buf.buf_mut()[1..]);
+        page.set_id(1);
+        page.set_flags(PageFlags::META_PAGE);
+        let mut meta = page.meta();
+        meta.set_txid(42);
+        page.set_meta(&meta);
+
+        let page = Page::from_slice(&buf.buf()[1..]);
+        assert_eq!(page.id(), 1);
+        assert!(page.is_meta_page());
+        assert_eq!(page.meta().txid(), 42);
+        assert!(Page::try_from_slice(&buf.buf()[1..PAGE_HEADER_SIZE]).is_err());
+    }
+
+    // Ensure that a bucket header stored at an odd offset is parsed without
+    // relying on its alignment.
+    #[test]
+    fn test_leaf_element_unaligned_bucket() {
+        let mut page = OwnedPage::new(128);
+        page.set_flags(PageFlags::LEAF_PAGE);
+        page.set_count(1);
+
+        // A one byte key leaves the bucket header at an odd address.
+        let header = InBucket::new(42, 7).to_bytes();
+        let pos = LEAF_PAGE_ELEMENT_SIZE as u32;
+        *page.leaf_page_element_mut(0) =
+            LeafPageElement::new(BUCKET_LEAF_FLAG, pos, 1, header.len() as u32);
+        let off = PAGE_HEADER_SIZE + pos as usize;
+        page.buf_mut()[off] = b'k';
+        page.buf_mut()[off + 1..off + 1 + header.len()].copy_from_slice(&header);
+
+        let elem = page.leaf_page_element(0);
+        assert_eq!(elem.value().as_ptr() as usize % 2, 1);
+        let bucket = elem.bucket().unwrap();
+        assert_eq!(bucket.root_page(), 42);
+        assert_eq!(bucket.to_bytes(), header);
+    }
+
+    // Ensure that page headers, elements and freelist ids are stored
+    // little-endian, so that the same bytes decode on every architecture.
+    #[test]
+    fn test_page_byte_order() {
+        #[rustfmt::skip]
+        let fixture: [u8; 48] = [
+            // header: id 0x0102030405060708, flags LEAF_PAGE, count 1, overflow 0x0a0b0c0d
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0x02, 0x00, 0x01, 0x00, 0x0d, 0x0c, 0x0b, 0x0a,
+            // leaf element: flags 1, pos 0x20, ksize 0x0102, vsize 0x03040506
+            0x01, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x00, 0x06, 0x05, 0x04, 0x03,
+            // branch element, on its own: pos 0x10, ksize 3, pgid 0x1122334455667788
+            0x10, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11,
+        ];
+        let mut buf = OwnedPage::from_vec(fixture[..32].to_vec());
+        let page = Page::from_slice(buf.buf());
+        assert_eq!(page.id(), 0x0102030405060708);
+        assert_eq!(page.flags(), PageFlags::LEAF_PAGE);
+        assert_eq!(page.count(), 1);
+        assert_eq!(page.overflow(), 0x0a0b0c0d);
+        let leaf = page.leaf_page_element(0);
+        assert!(leaf.is_bucket_entry());
+        assert_eq!(
+            (leaf.pos(), leaf.ksize(), leaf.vsize()),
+            (0x20, 0x0102, 0x03040506)
+        );
+        let branch = unsafe { &PageBuf::of(&fixture).elements::<BranchPageElement>(32, 1)[0] };
+        assert_eq!(
+            (branch.pos(), branch.ksize(), branch.pgid()),
+            (0x10, 3, 0x1122334455667788)
+        );
 
-        println!("owned: {}", ownedPage.page.len())
+        let mut page = OwnedPage::new(32);
+        page.set_id(0x0102030405060708);
+        page.set_flags(PageFlags::LEAF_PAGE);
+        page.set_count(1);
+        page.set_overflow(0x0a0b0c0d);
+        *page.leaf_page_element_mut(0) = LeafPageElement::new(1, 0x20, 0x0102, 0x03040506);
+        assert_eq!(page.buf(), &fixture[..32]);
+        let mut branch = BranchPageElement::default();
+        branch.set_pos(0x10);
+        branch.set_ksize(3);
+        branch.set_pgid(0x1122334455667788);
+        let bytes = unsafe { PageBuf::of(&branch).bytes(0, BRANCH_PAGE_ELEMENT_SIZE) };
+        assert_eq!(bytes, &fixture[32..]);
+
+        // A freelist page whose count overflows the header.
+        buf.set_flags(PageFlags::FREELIST_PAGE);
+        buf.set_count(0xFFFF);
+        buf.put_freelist_ids(0, &[1, 0x0102]);
+        assert_eq!(
+            &buf.buf()[16..32],
+            &[1, 0, 0, 0, 0, 0, 0, 0, 2, 1, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(buf.freelist_page_ids(), vec![0x0102]);
+    }
+
+    // Ensure that freelist ids are read when the count overflows the header.
+    #[test]
+    fn test_freelist_page_ids_overflow() {
+        let mut page = OwnedPage::new(4096);
+        page.set_flags(PageFlags::FREELIST_PAGE);
+        page.set_count(0xFFFF);
+        page.put_freelist_ids(0, &[3, 10, 11, 12]);
+
+        assert_eq!(page.freelist_page_ids(), vec![10, 11, 12]);
+        assert_eq!(OwnedPage::from_slice(page.buf()).into_bytes().len(), 4096);
     }
 }
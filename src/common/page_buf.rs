@@ -0,0 +1,351 @@
+//! Raw access to the memory of pages.
+//!
+//! Pages are handled through &Page, which covers the whole buffer holding the
+//! page: its header, then its elements, keys, values and freelist ids. Keys
+//! and values lie after their element. PageBuf and PageBufMut are the only
+//! places making pointers into that memory:
+//!
+//! - pointers are derived from a reference to the page or to the buffer with
+//!   NonNull::from and moved with add, so they keep the provenance of the
+//!   whole buffer, and are never made from integers or from references to a
+//!   part of it;
+//! - elements are handed out as an Elem, which keeps such a pointer next to
+//!   the element, so the key and value past it are not reached through a
+//!   reference to the element alone;
+//! - typed data is read and written with read_unaligned and write_unaligned,
+//!   except for the packed page header and elements, which can be referenced
+//!   at any address, so no page access depends on the alignment of its
+//!   buffer;
+//! - the callers tell with `unsafe` that the range they reach lies within the
+//!   buffer, which is the only invariant left to them.
+//!
+//! No reference is reached past its referent, so Miri checks the tests with
+//! its default Stacked Borrows aliasing model:
+//!
+//! ```text
+//! cargo +nightly miri test --lib common::
+//! ```
+
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, Range};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+/// Element is implemented by the packed types stored in pages, which can be
+/// referenced at any address.
+///
+/// # Safety
+///
+/// The type must have an alignment of one and be valid for any bytes.
+pub(crate) unsafe trait Element {}
+
+unsafe impl Element for u8 {}
+
+/// PageBuf is the memory of a page from a given position on.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PageBuf<'a> {
+    ptr: NonNull<u8>,
+    _marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> PageBuf<'a> {
+    /// of returns the memory from value on, which lies in a page buffer.
+    #[inline]
+    pub(crate) fn of<T: ?Sized>(value: &'a T) -> Self {
+        PageBuf {
+            ptr: NonNull::from(value).cast(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// as_ptr returns a pointer to the first byte.
+    #[inline]
+    pub(crate) fn as_ptr(self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// at returns the memory from offset on.
+    ///
+    /// # Safety
+    ///
+    /// The offset must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn at(self, offset: usize) -> Self {
+        PageBuf {
+            ptr: self.ptr.add(offset),
+            _marker: PhantomData,
+        }
+    }
+
+    /// bytes returns len bytes from offset on.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn bytes(self, offset: usize, len: usize) -> &'a [u8] {
+        self.elements(offset, len)
+    }
+
+    /// elements returns n elements from offset on.
+    ///
+    /// # Safety
+    ///
+    /// The elements must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn elements<T: Element>(self, offset: usize, n: usize) -> &'a [T] {
+        debug_assert_eq!(mem::align_of::<T>(), 1);
+        slice::from_raw_parts(self.ptr.as_ptr().add(offset).cast(), n)
+    }
+
+    /// read returns a copy of the T at offset, which need not be aligned.
+    ///
+    /// # Safety
+    ///
+    /// The T must lie within the buffer holding the page, and be valid for
+    /// any bytes.
+    #[inline]
+    pub(crate) unsafe fn read<T>(self, offset: usize) -> T {
+        ptr::read_unaligned(self.ptr.as_ptr().add(offset).cast())
+    }
+}
+
+/// Elem is an element of a page, along with the memory of the page from the
+/// element on, which the data the element points to is read from.
+#[derive(Debug)]
+pub(crate) struct Elem<'a, T> {
+    buf: PageBuf<'a>,
+    _marker: PhantomData<&'a T>,
+}
+
+// Derived impls would require T: Copy.
+impl<T> Clone for Elem<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Elem<'_, T> {}
+
+impl<'a, T: Element> Elem<'a, T> {
+    /// buf returns the memory of the page from the element on.
+    #[inline]
+    pub(crate) fn buf(self) -> PageBuf<'a> {
+        self.buf
+    }
+}
+
+impl<T: Element> Deref for Elem<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &self.buf.elements(0, 1)[0] }
+    }
+}
+
+/// Elems is the element table of a page.
+#[derive(Debug)]
+pub(crate) struct Elems<'a, T> {
+    buf: PageBuf<'a>,
+    len: usize,
+    _marker: PhantomData<&'a [T]>,
+}
+
+// Derived impls would require T: Copy.
+impl<T> Clone for Elems<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Elems<'_, T> {}
+
+impl<'a, T: Element> Elems<'a, T> {
+    /// new returns the len elements from buf on.
+    ///
+    /// # Safety
+    ///
+    /// The elements must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn new(buf: PageBuf<'a>, len: usize) -> Self {
+        Elems {
+            buf,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// get returns the element at index, or None if it is out of bounds.
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> Option<Elem<'a, T>> {
+        if index >= self.len {
+            return None;
+        }
+        let buf = unsafe { self.buf.at(index * mem::size_of::<T>()) };
+        Some(Elem {
+            buf,
+            _marker: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn first(&self) -> Option<Elem<'a, T>> {
+        self.get(0)
+    }
+
+    #[inline]
+    pub(crate) fn last(&self) -> Option<Elem<'a, T>> {
+        self.get(self.len.wrapping_sub(1))
+    }
+
+    #[inline]
+    pub(crate) fn iter(&self) -> ElemIter<'a, T> {
+        ElemIter {
+            elems: *self,
+            range: 0..self.len,
+        }
+    }
+
+    /// binary_search_by is slice::binary_search_by over the elements.
+    pub(crate) fn binary_search_by<F>(&self, mut f: F) -> Result<usize, usize>
+    where
+        F: FnMut(Elem<'a, T>) -> Ordering,
+    {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match f(self.get(mid).unwrap()) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(lo)
+    }
+}
+
+impl<'a, T: Element> IntoIterator for Elems<'a, T> {
+    type Item = Elem<'a, T>;
+    type IntoIter = ElemIter<'a, T>;
+
+    fn into_iter(self) -> ElemIter<'a, T> {
+        self.iter()
+    }
+}
+
+/// ElemIter iterates over the elements of a page.
+pub(crate) struct ElemIter<'a, T> {
+    elems: Elems<'a, T>,
+    range: Range<usize>,
+}
+
+impl<'a, T: Element> Iterator for ElemIter<'a, T> {
+    type Item = Elem<'a, T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Elem<'a, T>> {
+        self.range.next().and_then(|i| self.elems.get(i))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<T: Element> DoubleEndedIterator for ElemIter<'_, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().and_then(|i| self.elems.get(i))
+    }
+}
+
+impl<T: Element> ExactSizeIterator for ElemIter<'_, T> {}
+
+/// PageBufMut is the mutable memory of a page from a given position on.
+#[derive(Debug)]
+pub(crate) struct PageBufMut<'a> {
+    ptr: NonNull<u8>,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> PageBufMut<'a> {
+    /// of returns the memory from value on, which lies in a page buffer.
+    #[inline]
+    pub(crate) fn of<T: ?Sized>(value: &'a mut T) -> Self {
+        PageBufMut {
+            ptr: NonNull::from(value).cast(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// bytes_mut returns len bytes from offset on.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn bytes_mut(self, offset: usize, len: usize) -> &'a mut [u8] {
+        self.elements_mut(offset, len)
+    }
+
+    /// elements_mut returns n elements from offset on.
+    ///
+    /// # Safety
+    ///
+    /// The elements must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn elements_mut<T: Element>(self, offset: usize, n: usize) -> &'a mut [T] {
+        debug_assert_eq!(mem::align_of::<T>(), 1);
+        slice::from_raw_parts_mut(self.ptr.as_ptr().add(offset).cast(), n)
+    }
+
+    /// write stores value at offset, which need not be aligned.
+    ///
+    /// # Safety
+    ///
+    /// The T must lie within the buffer holding the page.
+    #[inline]
+    pub(crate) unsafe fn write<T>(&mut self, offset: usize, value: T) {
+        ptr::write_unaligned(self.ptr.as_ptr().add(offset).cast(), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ensure that data at unaligned offsets of the buffer can be read and
+    // written.
+    #[test]
+    fn test_page_buf() {
+        let mut buf = [0u64; 4];
+        unsafe {
+            let mut page = PageBufMut::of(&mut buf[..]);
+            page.write::<u64>(3, 0x0102_0304_0506_0708u64.to_le());
+            page.bytes_mut(16, 4).copy_from_slice(b"key!");
+            PageBufMut::of(&mut buf[..]).write::<u64>(24, 7);
+        }
+
+        let page = PageBuf::of(&buf[..]);
+        unsafe {
+            assert_eq!(u64::from_le(page.read::<u64>(3)), 0x0102_0304_0506_0708);
+            assert_eq!(page.bytes(16, 4), b"key!");
+            assert_eq!(page.read::<u64>(24), 7);
+        }
+        assert_eq!(page.as_ptr(), buf.as_ptr().cast());
+    }
+}
@@ -2,7 +2,6 @@
 //!  Bolt default type declare
 //!
 use once_cell::sync::Lazy;
-use page_size;
 use std::time::Duration;
 use crate::common::page::PgId;
 
@@ -14,6 +13,19 @@ pub(crate) const MAX_MMAP_STEP: usize = 1 << 30; // 1GB
 // Version represents the data file format version.
 pub(crate) const VERSION: u32 = 2;
 
+// VersionFeatures is the data file format version of databases using format
+// extensions, which are listed in the flags of their meta pages.
+pub(crate) const VERSION_FEATURES: u32 = 3;
+
+// FeatureBranchPrefix marks a database whose branch pages may store their keys
+// without the prefix they share, see PageFlags::PREFIX_PAGE. The keys of its
+// branch pages are separators: the key of a child is at most its first key
+// and above the keys of the child before, so it may be truncated.
+pub(crate) const FEATURE_BRANCH_PREFIX: u32 = 0x01;
+
+// KnownFeatures are the format extensions this version can read.
+pub(crate) const KNOWN_FEATURES: u32 = FEATURE_BRANCH_PREFIX;
+
 // Bolt DB 文件标识符
 // Magic represents a marker value to indicate that a file is a Bolt DB.
 pub(crate) const MAGIC: u32 = 0xED0CDAED;
@@ -40,9 +52,12 @@ pub(crate) const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(10);
 
 pub(crate) const DEFAULT_ALLOC_SIZE: usize = 16 * 1024 * 1024;
 
+// MaxPooledBytes caps the memory kept in the page buffer pool of a DB.
+pub(crate) const MAX_POOLED_BYTES: usize = 4 * 1024 * 1024;
+
 // 默认页面大小
 // DefaultPageSize is the default page size for db which is set to the OS page size.
-pub(crate) static DEFAULT_PAGE_SIZE: Lazy<usize> = Lazy::new(|| page_size::get());
+pub(crate) static DEFAULT_PAGE_SIZE: Lazy<usize> = Lazy::new(page_size::get);
 
 // 内部事务标识符
 // Txid represents the internal transaction identifier.
@@ -65,6 +80,7 @@ mod tests {
         let page_size = *DEFAULT_PAGE_SIZE;
 
         println!("system page size:{}", page_size);
-        assert_eq!(16384, page_size);
+        assert_eq!(page_size::get(), page_size);
+        assert!(page_size.is_power_of_two());
     }
 }
@@ -0,0 +1,276 @@
+use crate::bucket::{Bucket, MAX_FILL_PERCENT};
+use std::io;
+use std::path::Path;
+
+use crate::db::{Options, DB};
+use crate::errors::{BoltError, IoOp, Result};
+use crate::sequence::SEQUENCE_BUCKET;
+use crate::ttl::TTL_BUCKET;
+use crate::tx::Tx;
+
+// The page sizes a database can be migrated to. Opening a database probes for
+// its meta pages within this range.
+const MIN_PAGE_SIZE: usize = 1 << 10;
+const MAX_PAGE_SIZE: usize = 1 << 24;
+
+/// CompactOptions represents the options of DB::compact.
+#[derive(Clone, Debug, Default)]
+pub struct CompactOptions {
+    /// TxMaxSize is the maximum number of key and value bytes copied in a single
+    /// write transaction of the destination. When set to zero everything is
+    /// copied in one transaction.
+    pub tx_max_size: u64,
+}
+
+// WalkFn is called for every bucket and key: with the path of parent buckets,
+// the key, the value (None for a bucket) and the sequence of the bucket holding it.
+type WalkFn<'a> = dyn FnMut(&[Vec<u8>], &[u8], Option<&[u8]>, u64) -> Result<()> + 'a;
+
+impl DB {
+    /// compact copies every bucket and key of the database into dst, which should
+    /// be a freshly created database. Pages are filled completely and free pages
+    /// are left behind, so dst ends up as small as the data allows.
+    ///
+    /// The source is read in a single read-only transaction. The destination is
+    /// written in batches of at most opts.tx_max_size bytes.
+    pub fn compact(&self, dst: &DB, opts: CompactOptions) -> Result<()> {
+        // Commit regularly, or we'll run out of memory for large datasets if using one transaction.
+        let mut size = 0;
+        let mut tx = Some(dst.begin_rw()?);
+        self.view(|src| {
+            walk(src, &mut |keys, k, v, seq| {
+                // On each key/value, check if we have exceeded tx size.
+                let sz = (k.len() + v.map_or(0, |v| v.len())) as u64;
+                if opts.tx_max_size != 0 && size + sz > opts.tx_max_size {
+                    // Commit previous transaction and start a new one.
+                    tx.take().unwrap().commit()?;
+                    tx = Some(dst.begin_rw()?);
+                    size = 0;
+                }
+                size += sz;
+                let tx = tx.as_ref().unwrap();
+
+                // Create bucket on the root transaction if this is the first level.
+                let (first, rest) = match keys.split_first() {
+                    Some(path) => path,
+                    None => return tx.root().create_bucket(k)?.set_sequence(seq),
+                };
+
+                // Create buckets on subsequent levels, if necessary.
+                let mut b = tx.root().bucket(first).ok_or(BoltError::BucketNotFound)?;
+                for key in rest {
+                    b = b.bucket(key).ok_or(BoltError::BucketNotFound)?;
+                }
+
+                // Fill the entire page for best compaction.
+                b.set_fill_percent(MAX_FILL_PERCENT);
+
+                // If there is no value then this is a bucket call.
+                match v {
+                    None => b.create_bucket(k)?.set_sequence(seq),
+                    Some(v) => b.put(k, v),
+                }
+            })
+        })?;
+
+        tx.unwrap().commit()
+    }
+
+    /// migrate_page_size copies the database into a new file at dst_path that
+    /// uses a different page size, e.g. to move a database created on a host
+    /// with 16KB pages to one with 4KB pages. The copy is compacted as by
+    /// DB::compact. dst_path must not exist yet.
+    pub fn migrate_page_size<P: AsRef<Path>>(&self, dst_path: P, page_size: usize) -> Result<()> {
+        if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(BoltError::Config(format!(
+                "page size {} is not a power of two between {} and {}",
+                page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+            )));
+        }
+        let dst_path = dst_path.as_ref();
+        if dst_path.exists() {
+            let err = io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("file already exists: {}", dst_path.display()),
+            );
+            return Err(BoltError::io(IoOp::Open, err));
+        }
+
+        let dst = DB::open(
+            dst_path,
+            Options {
+                page_size,
+                write_rate_limit: self.0.write_limiter().map(|l| l.bytes_per_sec()),
+                ..Default::default()
+            },
+        )?;
+        let result = self.compact(&dst, CompactOptions::default());
+        dst.close()?;
+
+        // Don't leave a partial copy behind.
+        if result.is_err() {
+            std::fs::remove_file(dst_path)?;
+        }
+        result
+    }
+}
+
+// walk walks recursively the bolt database db, calling f for each key it finds.
+fn walk(tx: &Tx, f: &mut WalkFn) -> Result<()> {
+    tx.for_each(|name, b| walk_bucket(&b, &mut Vec::new(), name, None, b.sequence(), f))?;
+
+    // Copy the hidden buckets last, putting the keys the TTLs belong to
+    // would clear them.
+    for name in [SEQUENCE_BUCKET, TTL_BUCKET] {
+        if let Some(b) = tx.root().bucket(name) {
+            walk_bucket(&b, &mut Vec::new(), name, None, b.sequence(), f)?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_bucket(
+    b: &Bucket,
+    keypath: &mut Vec<Vec<u8>>,
+    k: &[u8],
+    v: Option<&[u8]>,
+    seq: u64,
+    f: &mut WalkFn,
+) -> Result<()> {
+    // Execute callback.
+    f(keypath, k, v, seq)?;
+
+    // If this is not a bucket then stop.
+    if v.is_some() {
+        return Ok(());
+    }
+
+    // Iterate over each child key/value.
+    keypath.push(k.to_vec());
+    b.for_each(|k, v| match v {
+        None => {
+            let bkt = b.bucket(k).ok_or(BoltError::BucketNotFound)?;
+            walk_bucket(&bkt, keypath, k, None, bkt.sequence(), f)
+        }
+        Some(v) => walk_bucket(b, keypath, k, Some(v), b.sequence(), f),
+    })?;
+    keypath.pop();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ensure that compaction copies nested buckets, keys and sequences and
+    // leaves the free pages of the source behind.
+    #[test]
+    fn test_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src_path, dst_path) = (dir.path().join("src"), dir.path().join("dst"));
+        let src = DB::open(&src_path, Options::default()).unwrap();
+        src.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.set_sequence(42)?;
+            for i in 0..2000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            let sub = b.create_bucket(b"sub")?;
+            sub.put(b"foo", b"bar")?;
+            sub.set_sequence(7)?;
+            tx.create_bucket(b"empty")?;
+            tx.next_id()?;
+            Ok(())
+        })
+        .unwrap();
+        src.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            for i in 0..1900u32 {
+                b.delete(format!("{:08}", i).as_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let dst = DB::open(&dst_path, Options::default()).unwrap();
+        src.compact(&dst, CompactOptions { tx_max_size: 4096 })
+            .unwrap();
+
+        dst.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.sequence(), 42);
+            let mut count = 0;
+            b.for_each(|_, v| {
+                if v.is_some() {
+                    count += 1;
+                }
+                Ok(())
+            })?;
+            assert_eq!(count, 100);
+            assert_eq!(b.get(b"00001999"), Some(&[0x5a; 100][..]));
+
+            let sub = b.bucket(b"sub").unwrap();
+            assert_eq!(sub.sequence(), 7);
+            assert_eq!(sub.get(b"foo"), Some(&b"bar"[..]));
+            assert!(tx.bucket(b"empty").is_some());
+            assert_eq!(tx.last_id(), 1);
+            Ok(())
+        })
+        .unwrap();
+
+        src.close().unwrap();
+        dst.close().unwrap();
+        let src_size = std::fs::metadata(&src_path).unwrap().len();
+        let dst_size = std::fs::metadata(&dst_path).unwrap().len();
+        assert!(dst_size < src_size, "{} >= {}", dst_size, src_size);
+    }
+
+    // Ensure that a database can be moved to larger and smaller pages.
+    #[test]
+    fn test_migrate_page_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = DB::open(
+            dir.path().join("src"),
+            Options {
+                page_size: 4096,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        src.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")
+        })
+        .unwrap();
+
+        for page_size in [1024, 16384] {
+            let path = dir.path().join(format!("dst-{}", page_size));
+            src.migrate_page_size(&path, page_size).unwrap();
+            assert!(src.migrate_page_size(&path, page_size).is_err());
+
+            let dst = DB::open(&path, Options::default()).unwrap();
+            assert_eq!(dst.info().unwrap().page_size, page_size);
+            dst.view(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.get(b"00000999"), Some(&[0x5a; 100][..]));
+                assert_eq!(b.bucket(b"sub").unwrap().get(b"foo"), Some(&b"bar"[..]));
+                assert_eq!(tx.check()?.errors, vec![]);
+                Ok(())
+            })
+            .unwrap();
+            dst.close().unwrap();
+        }
+
+        let path = dir.path().join("invalid");
+        assert!(matches!(
+            src.migrate_page_size(&path, 3000),
+            Err(BoltError::Config(_))
+        ));
+        assert!(!path.exists());
+        src.close().unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+//! Transparent value compression for buckets.
+//!
+//! A bucket with compression enabled stores every value behind a one byte
+//! header naming the algorithm. Compressed values also record their original
+//! length, as a little-endian u32, before the compressed bytes:
+//!
+//! ```text
+//! 0x00 | value                  stored as is
+//! 0x01 | len u32 | lz4 block    compressed with LZ4
+//! 0x02 | len u32 | zstd frame   compressed with Zstandard
+//! ```
+//!
+//! Values that are short or do not shrink are stored as is, so the header is
+//! the only overhead on incompressible data. The header is always read, so a
+//! bucket needs compression enabled from the start, or values written without
+//! it are rejected as corrupt.
+
+use bytes::Bytes;
+
+use crate::errors::{BoltError, Result};
+
+const STORED: u8 = 0x00;
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 0x01;
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 0x02;
+
+const LEN_SIZE: usize = 4;
+
+// Values shorter than this are not worth compressing.
+const MIN_COMPRESS_SIZE: usize = 64;
+
+/// Compression selects how the values of a bucket are compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Values are stored without a header, as in Go bbolt.
+    #[default]
+    None,
+    /// LZ4, fast with a moderate ratio.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstandard at the given level, from 1 (fastest) to 22 (smallest).
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+impl Compression {
+    /// compress returns the value as it is stored in the bucket.
+    pub(crate) fn compress(self, value: &[u8]) -> Result<Vec<u8>> {
+        if self == Compression::None {
+            return Ok(value.to_vec());
+        }
+
+        let compressed: Option<(u8, Vec<u8>)> = match self {
+            _ if value.len() < MIN_COMPRESS_SIZE => None,
+            Compression::None => None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Some((LZ4, lz4_flex::block::compress(value))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                let buf = zstd::bulk::compress(value, level)
+                    .map_err(|e| BoltError::Codec(e.to_string()))?;
+                Some((ZSTD, buf))
+            }
+        };
+
+        match compressed {
+            Some((tag, buf)) if buf.len() + LEN_SIZE < value.len() => {
+                let mut stored = Vec::with_capacity(1 + LEN_SIZE + buf.len());
+                stored.push(tag);
+                stored.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                stored.extend_from_slice(&buf);
+                Ok(stored)
+            }
+            _ => {
+                let mut stored = Vec::with_capacity(1 + value.len());
+                stored.push(STORED);
+                stored.extend_from_slice(value);
+                Ok(stored)
+            }
+        }
+    }
+
+    /// stored_header returns the header of a value stored as is.
+    pub(crate) fn stored_header(self) -> &'static [u8] {
+        match self == Compression::None {
+            true => &[],
+            false => &[STORED],
+        }
+    }
+
+    /// stored_value returns the original value of a value stored as is,
+    /// without copying it, or None if it was compressed.
+    pub(crate) fn stored_value(self, stored: &[u8]) -> Option<&[u8]> {
+        match self == Compression::None {
+            true => Some(stored),
+            false => stored.strip_prefix(&[STORED]),
+        }
+    }
+
+    /// decompress returns the original value of a value stored in the bucket.
+    // Without any algorithm enabled, only the stored values are supported.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn decompress(self, stored: &[u8]) -> Result<Vec<u8>> {
+        if self == Compression::None {
+            return Ok(stored.to_vec());
+        }
+
+        let (&tag, body) = stored
+            .split_first()
+            .ok_or_else(|| BoltError::Codec("compressed value has no header".to_string()))?;
+        if tag == STORED {
+            return Ok(body.to_vec());
+        }
+        if body.len() < LEN_SIZE {
+            return Err(BoltError::Codec(
+                "compressed value is truncated".to_string(),
+            ));
+        }
+        let (len, body) = body.split_at(LEN_SIZE);
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+
+        let value: Result<Vec<u8>> = match tag {
+            #[cfg(feature = "lz4")]
+            LZ4 => {
+                lz4_flex::block::decompress(body, len).map_err(|e| BoltError::Codec(e.to_string()))
+            }
+            #[cfg(feature = "zstd")]
+            ZSTD => zstd::bulk::decompress(body, len).map_err(|e| BoltError::Codec(e.to_string())),
+            _ => Err(BoltError::Codec(format!(
+                "unsupported compression {:#04x}",
+                tag
+            ))),
+        };
+        let value = value?;
+        if value.len() != len {
+            return Err(BoltError::Codec(format!(
+                "decompressed {} bytes, expected {}",
+                value.len(),
+                len
+            )));
+        }
+        Ok(value)
+    }
+
+    /// decompress_bytes is decompress for a value in a shared buffer, which is
+    /// sliced instead of copied unless it was compressed.
+    pub(crate) fn decompress_bytes(self, stored: &Bytes) -> Result<Bytes> {
+        if self == Compression::None {
+            return Ok(stored.clone());
+        }
+        if stored.first() == Some(&STORED) {
+            return Ok(stored.slice(1..));
+        }
+        self.decompress(stored).map(Bytes::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_none() {
+        let value = vec![7u8; 1000];
+        assert_eq!(Compression::None.compress(&value).unwrap(), value);
+        assert_eq!(Compression::None.decompress(&value).unwrap(), value);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_compression_lz4() {
+        let c = Compression::Lz4;
+        let value = b"abcdefgh".repeat(100);
+        let stored = c.compress(&value).unwrap();
+        assert_eq!(stored[0], LZ4);
+        assert!(stored.len() < value.len() / 4);
+        assert_eq!(c.decompress(&stored).unwrap(), value);
+
+        // Short values are stored with the header only.
+        assert_eq!(c.compress(b"foo").unwrap(), b"\x00foo");
+        assert_eq!(c.decompress(b"\x00foo").unwrap(), b"foo");
+        assert!(c.decompress(b"").is_err());
+        assert!(c.decompress(&stored[..stored.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compression_zstd() {
+        let c = Compression::Zstd(3);
+        let value = b"abcdefgh".repeat(100);
+        let stored = c.compress(&value).unwrap();
+        assert_eq!(stored[0], ZSTD);
+        assert!(stored.len() < value.len() / 4);
+        assert_eq!(c.decompress(&stored).unwrap(), value);
+
+        // Incompressible values are stored as is.
+        let value: Vec<u8> = (0..200u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let stored = c.compress(&value).unwrap();
+        assert_eq!(stored[0], STORED);
+        assert_eq!(&stored[1..], &value[..]);
+        assert_eq!(c.decompress(b"\x09\x00\x00\x00\x00").ok(), None);
+    }
+}
@@ -0,0 +1,303 @@
+//! Simulating power loss.
+//!
+//! CrashVfs keeps a database in memory like MemoryVfs, and also tracks which
+//! writes have been made durable by a sync. crash returns the storage as it
+//! could be found after losing power: the synced writes, plus any of the
+//! writes since the last sync that a drive may have persisted, in any order.
+//! fail_after is the failpoint cutting the power at an arbitrary write or
+//! sync, so that every step of a commit can be interrupted:
+//!
+//! ```ignore
+//! for n in 0.. {
+//!     let vfs = Arc::new(CrashVfs::new());
+//!     // ... open a database on vfs and commit a first transaction ...
+//!     vfs.fail_after(n);
+//!     // ... commit a second transaction, which may fail ...
+//!     let disk = vfs.crash(CrashMode::DropUnsynced);
+//!     // ... reopen a database on disk, check it and its contents ...
+//! }
+//! ```
+
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::errors::Result;
+use crate::vfs::{MemoryVfs, Vfs};
+
+/// CrashMode selects which of the writes since the last sync survive a crash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashMode {
+    /// DropUnsynced loses every write since the last sync.
+    DropUnsynced,
+    /// KeepFirst keeps the first n writes since the last sync, as if the
+    /// power was cut while they were written in order.
+    KeepFirst(usize),
+    /// Reorder keeps a random subset of the writes since the last sync,
+    /// applied in a random order, as a drive with a write cache may. The
+    /// seed makes the choice reproducible.
+    Reorder(u64),
+}
+
+/// CrashVfs is an in-memory storage that can lose the writes it has not
+/// synced.
+#[derive(Debug, Default)]
+pub struct CrashVfs {
+    // What the database reads, with every write applied.
+    cache: MemoryVfs,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // Contents as of the last sync.
+    durable: Vec<u8>,
+    // Changes since the last sync.
+    unsynced: Vec<Change>,
+    // Number of writes and syncs left before the power is cut.
+    ops_left: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+enum Change {
+    Write(u64, Vec<u8>),
+    Truncate(u64),
+}
+
+impl CrashVfs {
+    /// new returns an empty storage.
+    pub fn new() -> CrashVfs {
+        CrashVfs::default()
+    }
+
+    /// fail_after cuts the power once n more writes and syncs have been made.
+    /// Every write and sync after that fails, as does every truncate.
+    pub fn fail_after(&self, n: usize) {
+        self.state.lock().unwrap().ops_left = Some(n);
+    }
+
+    /// has_failed returns whether the power has been cut by fail_after.
+    pub fn has_failed(&self) -> bool {
+        self.state.lock().unwrap().ops_left == Some(0)
+    }
+
+    /// unsynced returns the number of writes and truncates since the last sync.
+    pub fn unsynced(&self) -> usize {
+        self.state.lock().unwrap().unsynced.len()
+    }
+
+    /// crash returns a copy of the storage as found after losing power, with
+    /// the writes since the last sync that mode keeps.
+    pub fn crash(&self, mode: CrashMode) -> MemoryVfs {
+        let state = self.state.lock().unwrap();
+        let mut changes = state.unsynced.clone();
+        match mode {
+            CrashMode::DropUnsynced => changes.clear(),
+            CrashMode::KeepFirst(n) => changes.truncate(n),
+            CrashMode::Reorder(seed) => {
+                let mut rng = seed.max(1);
+                // Fisher-Yates shuffle, then keep a random prefix.
+                for i in (1..changes.len()).rev() {
+                    changes.swap(i, (next(&mut rng) % (i as u64 + 1)) as usize);
+                }
+                changes.truncate((next(&mut rng) % (changes.len() as u64 + 1)) as usize);
+            }
+        }
+
+        let mut disk = state.durable.clone();
+        for change in changes {
+            apply(&mut disk, &change);
+        }
+        let vfs = MemoryVfs::new();
+        vfs.write_at(&disk, 0).unwrap();
+        vfs
+    }
+
+    // step counts an operation against the failpoint, failing once the
+    // power has been cut.
+    fn step(state: &mut State) -> io::Result<()> {
+        match &mut state.ops_left {
+            Some(0) => Err(io::Error::other("power loss")),
+            Some(n) => {
+                *n -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// next advances an xorshift generator.
+fn next(x: &mut u64) -> u64 {
+    *x ^= *x << 13;
+    *x ^= *x >> 7;
+    *x ^= *x << 17;
+    *x
+}
+
+// apply applies a change to the contents of a storage.
+fn apply(disk: &mut Vec<u8>, change: &Change) {
+    match change {
+        Change::Write(offset, buf) => {
+            let offset = *offset as usize;
+            if disk.len() < offset + buf.len() {
+                disk.resize(offset + buf.len(), 0);
+            }
+            disk[offset..offset + buf.len()].copy_from_slice(buf);
+        }
+        Change::Truncate(len) => disk.resize(*len as usize, 0),
+    }
+}
+
+impl Vfs for CrashVfs {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.cache.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        CrashVfs::step(&mut state)?;
+        state.unsynced.push(Change::Write(offset, buf.to_vec()));
+        self.cache.write_at(buf, offset)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        CrashVfs::step(&mut state)?;
+        let changes = std::mem::take(&mut state.unsynced);
+        for change in &changes {
+            apply(&mut state.durable, change);
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        self.cache.len()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.ops_left == Some(0) {
+            return Err(io::Error::other("power loss"));
+        }
+        state.unsynced.push(Change::Truncate(len));
+        self.cache.truncate(len)
+    }
+
+    fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()> {
+        self.cache.lock(exclusive, timeout)
+    }
+
+    fn unlock(&self) -> Result<()> {
+        self.cache.unlock()
+    }
+
+    fn map(&self, len: usize) -> io::Result<Bytes> {
+        self.cache.map(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::{Options, DB};
+
+    fn open(vfs: Arc<dyn Vfs>) -> Result<DB> {
+        let options = Options {
+            vfs: Some(vfs),
+            ..Default::default()
+        };
+        DB::open("crash", options)
+    }
+
+    // value returns the value of key in the widgets bucket.
+    fn value(db: &DB, key: &[u8]) -> Option<Vec<u8>> {
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(tx.bucket(b"widgets").unwrap().get(key).map(|v| v.to_vec()))
+        })
+        .unwrap()
+    }
+
+    // Ensure that a database survives losing power at any write or sync of a
+    // commit, with whatever unsynced writes the drive kept, and holds either
+    // the previous transaction or the interrupted one afterwards.
+    #[test]
+    fn test_crash_consistency() {
+        let modes = [
+            CrashMode::DropUnsynced,
+            CrashMode::KeepFirst(1),
+            CrashMode::KeepFirst(3),
+            CrashMode::Reorder(1),
+            CrashMode::Reorder(2),
+        ];
+        for n in 0.. {
+            let vfs = Arc::new(CrashVfs::new());
+            let db = open(vfs.clone()).unwrap();
+            db.update(|tx| {
+                let b = tx.create_bucket(b"widgets")?;
+                for i in 0..500u32 {
+                    b.put(format!("{:04}", i).as_bytes(), b"old")?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+            vfs.fail_after(n);
+            let result = db.update(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                for i in 0..500u32 {
+                    b.put(format!("{:04}", i).as_bytes(), b"new")?;
+                }
+                b.put(b"last", b"new")
+            });
+            if !vfs.has_failed() {
+                // The power was never cut, so every step has been tried.
+                result.unwrap();
+                assert!(n > 2);
+                break;
+            }
+            drop(db);
+
+            for mode in modes {
+                let db = open(Arc::new(vfs.crash(mode))).unwrap();
+                match value(&db, b"last") {
+                    Some(_) => assert_eq!(value(&db, b"0000").unwrap(), b"new"),
+                    None => {
+                        assert!(result.is_err(), "lost a commit at {} with {:?}", n, mode);
+                        assert_eq!(value(&db, b"0499").unwrap(), b"old");
+                    }
+                }
+            }
+        }
+    }
+
+    // Ensure that the writes since the last sync are kept as the mode says.
+    #[test]
+    fn test_crash_modes() {
+        let vfs = CrashVfs::new();
+        vfs.write_at(b"abcd", 0).unwrap();
+        vfs.sync().unwrap();
+        vfs.write_at(b"x", 0).unwrap();
+        vfs.write_at(b"y", 1).unwrap();
+        assert_eq!(vfs.unsynced(), 2);
+
+        assert_eq!(vfs.crash(CrashMode::DropUnsynced).to_vec(), b"abcd");
+        assert_eq!(vfs.crash(CrashMode::KeepFirst(1)).to_vec(), b"xbcd");
+        assert_eq!(vfs.crash(CrashMode::KeepFirst(9)).to_vec(), b"xycd");
+        let mut seen = std::collections::HashSet::new();
+        for seed in 0..64 {
+            seen.insert(vfs.crash(CrashMode::Reorder(seed)).to_vec());
+        }
+        assert_eq!(seen.len(), 4);
+
+        vfs.fail_after(1);
+        vfs.write_at(b"z", 2).unwrap();
+        assert!(vfs.sync().is_err());
+        assert_eq!(vfs.crash(CrashMode::DropUnsynced).to_vec(), b"abcd");
+    }
+}
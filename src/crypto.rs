@@ -0,0 +1,353 @@
+//! Encryption at rest of page payloads.
+//!
+//! When a key is given in Options.encryption_key, every page but the meta
+//! pages is encrypted with AES-256-GCM as it is written and decrypted as it is
+//! read. A page, together with its overflow pages, is encrypted as a whole:
+//!
+//! ```text
+//! header (16 bytes, in the clear) | payload (encrypted) | nonce (12) | tag (16)
+//! ```
+//!
+//! The header stays readable so the length of a page is known before it is
+//! decrypted, and it is authenticated along with the payload. The nonce and
+//! tag take the last bytes of the page, which nodes and the freelist leave
+//! free, see PageCrypt::overhead.
+//!
+//! Meta pages are stored in the clear, followed by verification material: a
+//! block of zeros encrypted with the key, which tells a wrong key apart from
+//! a corrupt page as the database is opened.
+//!
+//! Encrypted files can not be read by Go bbolt, nor repaired by the surgeon.
+
+use bytes::Bytes;
+
+use crate::common::meta::META_PAGE_SIZE;
+use crate::common::page::PAGE_HEADER_SIZE;
+use crate::errors::{BoltError, Result};
+
+#[cfg(feature = "encryption")]
+use std::fmt;
+#[cfg(feature = "encryption")]
+use std::sync::Arc;
+
+#[cfg(feature = "encryption")]
+use aes_gcm::aead::{AeadInPlace, KeyInit, OsRng};
+#[cfg(feature = "encryption")]
+use aes_gcm::{AeadCore, Aes256Gcm, Nonce, Tag};
+
+// VERIFIER_MAGIC marks meta pages of encrypted databases.
+const VERIFIER_MAGIC: &[u8; 8] = b"bboltenc";
+const VERIFIER_OFFSET: usize = PAGE_HEADER_SIZE + META_PAGE_SIZE;
+
+#[cfg(feature = "encryption")]
+const NONCE_SIZE: usize = 12;
+#[cfg(feature = "encryption")]
+const TAG_SIZE: usize = 16;
+#[cfg(feature = "encryption")]
+const VERIFIER_SIZE: usize = VERIFIER_MAGIC.len() + NONCE_SIZE + 16 + TAG_SIZE;
+
+/// EncryptionKey is the 256-bit key the pages of a database are encrypted with.
+/// Derive it from a passphrase with a password hashing function, it is used as is.
+#[cfg(feature = "encryption")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+#[cfg(feature = "encryption")]
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// PageCrypt encrypts and decrypts pages. It does nothing unless the database
+/// was opened with a key.
+#[derive(Clone, Default)]
+pub(crate) struct PageCrypt {
+    #[cfg(feature = "encryption")]
+    cipher: Option<Arc<Aes256Gcm>>,
+}
+
+impl PageCrypt {
+    #[cfg(feature = "encryption")]
+    pub(crate) fn new(key: Option<&EncryptionKey>) -> PageCrypt {
+        PageCrypt {
+            cipher: key.map(|key| Arc::new(Aes256Gcm::new(&key.0.into()))),
+        }
+    }
+
+    /// is_enabled returns whether pages are encrypted.
+    pub(crate) fn is_enabled(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.cipher.is_some();
+        #[cfg(not(feature = "encryption"))]
+        return false;
+    }
+
+    /// overhead returns the number of bytes at the end of every page, or run
+    /// of overflow pages, that are reserved for the nonce and the tag.
+    pub(crate) fn overhead(&self) -> usize {
+        #[cfg(feature = "encryption")]
+        if self.is_enabled() {
+            return NONCE_SIZE + TAG_SIZE;
+        }
+        0
+    }
+
+    /// seal encrypts a page, with its overflow pages, in place.
+    pub(crate) fn seal(&self, buf: &mut [u8]) -> Result<()> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let (header, rest) = buf.split_at_mut(PAGE_HEADER_SIZE);
+            let (payload, trailer) = rest.split_at_mut(rest.len() - NONCE_SIZE - TAG_SIZE);
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let tag = cipher
+                .encrypt_in_place_detached(&nonce, header, payload)
+                .map_err(|_| BoltError::Encryption("page encryption failed"))?;
+            trailer[..NONCE_SIZE].copy_from_slice(&nonce);
+            trailer[NONCE_SIZE..].copy_from_slice(&tag);
+        }
+        #[cfg(not(feature = "encryption"))]
+        let _ = buf;
+        Ok(())
+    }
+
+    /// open returns a decrypted copy of a page, with its overflow pages.
+    pub(crate) fn open(&self, buf: Bytes) -> Result<Bytes> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            if buf.len() < PAGE_HEADER_SIZE + NONCE_SIZE + TAG_SIZE {
+                return Err(BoltError::Encryption("page decryption failed"));
+            }
+            // Keep the page aligned by decrypting into an OwnedPage.
+            let mut page = crate::common::page::OwnedPage::from_slice(&buf);
+            let (header, rest) = page.buf_mut().split_at_mut(PAGE_HEADER_SIZE);
+            let (payload, trailer) = rest.split_at_mut(rest.len() - NONCE_SIZE - TAG_SIZE);
+            let nonce = *Nonce::from_slice(&trailer[..NONCE_SIZE]);
+            let tag = *Tag::from_slice(&trailer[NONCE_SIZE..]);
+            cipher
+                .decrypt_in_place_detached(&nonce, header, payload, &tag)
+                .map_err(|_| BoltError::Encryption("page decryption failed"))?;
+            return Ok(page.into_bytes());
+        }
+        Ok(buf)
+    }
+
+    /// write_verifier stores the verification material of the key after the
+    /// meta of a meta page.
+    pub(crate) fn write_verifier(&self, page: &mut [u8]) -> Result<()> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let buf = &mut page[VERIFIER_OFFSET..VERIFIER_OFFSET + VERIFIER_SIZE];
+            let (magic, rest) = buf.split_at_mut(VERIFIER_MAGIC.len());
+            let (nonce_buf, rest) = rest.split_at_mut(NONCE_SIZE);
+            let (block, tag_buf) = rest.split_at_mut(16);
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            block.fill(0);
+            let tag = cipher
+                .encrypt_in_place_detached(&nonce, VERIFIER_MAGIC, block)
+                .map_err(|_| BoltError::Encryption("page encryption failed"))?;
+            magic.copy_from_slice(VERIFIER_MAGIC);
+            nonce_buf.copy_from_slice(&nonce);
+            tag_buf.copy_from_slice(&tag);
+        }
+        #[cfg(not(feature = "encryption"))]
+        let _ = page;
+        Ok(())
+    }
+
+    /// check_verifier makes sure that the key, or the lack of one, matches the
+    /// verification material of a meta page.
+    pub(crate) fn check_verifier(&self, page: &[u8]) -> Result<()> {
+        let magic = page.get(VERIFIER_OFFSET..VERIFIER_OFFSET + VERIFIER_MAGIC.len());
+        let encrypted = magic == Some(&VERIFIER_MAGIC[..]);
+
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            if !encrypted {
+                return Err(BoltError::Encryption("database is not encrypted"));
+            }
+            let buf = &page[VERIFIER_OFFSET + VERIFIER_MAGIC.len()..];
+            if buf.len() < NONCE_SIZE + 16 + TAG_SIZE {
+                return Err(BoltError::Invalid);
+            }
+            let nonce = Nonce::from_slice(&buf[..NONCE_SIZE]);
+            let mut block = buf[NONCE_SIZE..NONCE_SIZE + 16].to_vec();
+            let tag = Tag::from_slice(&buf[NONCE_SIZE + 16..NONCE_SIZE + 16 + TAG_SIZE]);
+            return cipher
+                .decrypt_in_place_detached(nonce, VERIFIER_MAGIC, &mut block, tag)
+                .map_err(|_| BoltError::Encryption("wrong encryption key"));
+        }
+
+        if encrypted {
+            return Err(BoltError::Encryption("database is encrypted"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+    use crate::common::page::OwnedPage;
+    use crate::db::{Options, DB};
+
+    fn options(key: u8) -> Options {
+        Options {
+            encryption_key: Some(EncryptionKey::from([key; 32])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let crypt = PageCrypt::new(Some(&EncryptionKey::from([1; 32])));
+        let mut page = OwnedPage::new(2 * 4096);
+        page.set_id(7);
+        page.set_overflow(1);
+        page.buf_mut()[100..200].fill(0x5a);
+        let plain = page.buf().to_vec();
+
+        crypt.seal(page.buf_mut()).unwrap();
+        assert_eq!(&page.buf()[..PAGE_HEADER_SIZE], &plain[..PAGE_HEADER_SIZE]);
+        assert!(!page.buf()[100..200].iter().all(|&b| b == 0x5a));
+
+        let sealed = Bytes::copy_from_slice(page.buf());
+        let opened = crypt.open(sealed.clone()).unwrap();
+        assert_eq!(&opened[..plain.len() - 28], &plain[..plain.len() - 28]);
+
+        // The header is authenticated too.
+        let mut forged = sealed.to_vec();
+        forged[0] = 8;
+        assert!(crypt.open(Bytes::from(forged)).is_err());
+        let other = PageCrypt::new(Some(&EncryptionKey::from([2; 32])));
+        assert_eq!(
+            other.open(sealed),
+            Err(BoltError::Encryption("page decryption failed"))
+        );
+    }
+
+    #[test]
+    fn test_encrypted_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, options(1)).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("secret-{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.put(b"large", &vec![0x42; 10000])?;
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| tx.bucket(b"widgets").unwrap().delete(b"secret-00000010"))
+            .unwrap();
+        db.close().unwrap();
+
+        // Nothing of the data is stored in the clear.
+        let file = std::fs::read(&path).unwrap();
+        assert!(!file.windows(7).any(|w| w == b"secret-"));
+
+        let db = DB::open(&path, options(1)).unwrap();
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"secret-00000999"), Some(&[0x5a; 100][..]));
+            assert_eq!(b.get(b"secret-00000010"), None);
+            assert_eq!(b.get(b"large"), Some(&[0x42; 10000][..]));
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+
+        assert_eq!(
+            DB::open(&path, options(2)).err(),
+            Some(BoltError::Encryption("wrong encryption key"))
+        );
+        assert_eq!(
+            DB::open(&path, Options::default()).err(),
+            Some(BoltError::Encryption("database is encrypted"))
+        );
+    }
+
+    // Ensure that the pages decrypted out of the mmap are kept in a bounded
+    // cache shared by the transactions, and not for as long as each reads.
+    #[test]
+    fn test_encrypted_db_decrypted_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cached = Options {
+            decrypted_cache_size: Some(8),
+            ..options(1)
+        };
+        let db = DB::open(dir.path().join("db"), cached).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..2000u32 {
+                b.put(format!("secret-{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let mut n = 0;
+            b.for_each(|_, v| {
+                assert_eq!(v, Some(&[0x5a; 100][..]));
+                n += 1;
+                Ok(())
+            })?;
+            assert_eq!(n, 2000);
+            assert!(db.0.decrypted().unwrap().len() <= 8);
+
+            let before = tx.stats().decrypted_page_hit;
+            assert_eq!(b.get(b"secret-00000000"), Some(&[0x5a; 100][..]));
+            assert_eq!(b.get(b"secret-00000000"), Some(&[0x5a; 100][..]));
+            assert!(tx.stats().decrypted_page_hit > before);
+            Ok(())
+        })
+        .unwrap();
+
+        // A cache of no pages decrypts every page as it is read.
+        let db = DB::open(
+            dir.path().join("db2"),
+            Options {
+                decrypted_cache_size: Some(0),
+                ..options(1)
+            },
+        )
+        .unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+        db.view(|tx| {
+            assert_eq!(
+                tx.bucket(b"widgets").unwrap().get(b"foo"),
+                Some(&b"bar"[..])
+            );
+            assert_eq!(db.0.decrypted().unwrap().len(), 0);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_plaintext_db_with_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        DB::open(&path, Options::default())
+            .unwrap()
+            .close()
+            .unwrap();
+        assert_eq!(
+            DB::open(&path, options(1)).err(),
+            Some(BoltError::Encryption("database is not encrypted"))
+        );
+    }
+}
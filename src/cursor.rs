@@ -0,0 +1,889 @@
+use crate::borrows::PageBorrow;
+use crate::bucket::{is_hidden, Bucket, PageNode};
+use crate::common::inode::{Key, Value};
+use crate::common::page::{Page, PgId, BUCKET_LEAF_FLAG};
+use crate::errors::{BoltError, Result};
+use crate::node::{Node, WeakNode};
+use crate::tx::Tx;
+
+// Item is a raw cursor entry: key, value and the element flags.
+pub(crate) type Item = (Key, Value, u32);
+
+// READAHEAD_MAX_GAP is how far apart two leaves may lie to count as
+// sequential, since branch pages are often written in between.
+const READAHEAD_MAX_GAP: PgId = 4;
+
+// READAHEAD_MIN_RUN is the number of sequential leaves after which a scan
+// reads ahead.
+const READAHEAD_MIN_RUN: usize = 2;
+
+/// Cursor represents an iterator that can traverse over all key/value pairs in a bucket
+/// in lexicographical order.
+/// Cursors see nested buckets with value == None.
+/// Cursors can be obtained from a transaction and are valid as long as the transaction is open.
+///
+/// Keys and values returned from the cursor are borrowed from the transaction,
+/// like the cursor itself, so they cannot be used after it is closed.
+///
+/// A move that fails, on a page that cannot be read or a value that fails to
+/// decompress, returns None like the end of the bucket; err tells them apart.
+///
+/// Changing data while traversing with a cursor may cause it to be invalidated
+/// and return unexpected keys and/or values. You must reposition your cursor
+/// after mutating data.
+#[derive(Debug)]
+pub struct Cursor<'tx> {
+    bucket: Bucket<'tx>,
+    stack: Vec<ElemRef>,
+    scan: Scan,
+    // Error of the last move, if it failed.
+    err: Option<BoltError>,
+    // Whether the hidden buckets of the root are skipped, see Tx::cursor.
+    skip_hidden: bool,
+}
+
+// Pair is the key and value a cursor returns.
+type Pair<'tx> = (&'tx [u8], Option<&'tx [u8]>);
+
+// Scan tracks the leaf pages next_item moves through to detect sequential
+// access.
+#[derive(Debug, Default)]
+struct Scan {
+    // Last page of the previous leaf.
+    last: Option<PgId>,
+    // Number of leaves in a row lying after the previous one.
+    run: usize,
+    // Page up to which the scan was read ahead.
+    ahead: PgId,
+}
+
+// ElemRef represents a reference to an element on a given page/node.
+#[derive(Debug, Clone)]
+struct ElemRef {
+    page_node: PageNode,
+    index: usize,
+    // Records the page as borrowed by the cursor, in debug builds.
+    _borrow: PageBorrow,
+}
+
+impl ElemRef {
+    // is_leaf returns whether the ref is pointing at a leaf page/node.
+    fn is_leaf(&self) -> bool {
+        self.page_node.is_leaf()
+    }
+
+    // count returns the number of inodes or page elements.
+    fn count(&self) -> usize {
+        self.page_node.count()
+    }
+
+    // child_pgid returns the child page id at the current index of a branch.
+    fn child_pgid(&self) -> PgId {
+        match &self.page_node {
+            PageNode::Page(_) => self
+                .page_node
+                .page()
+                .unwrap()
+                .branch_page_element(self.index)
+                .pgid(),
+            PageNode::Node(node) => node.inodes().get(self.index).pgid(),
+        }
+    }
+}
+
+impl<'tx> Cursor<'tx> {
+    pub(crate) fn new(bucket: Bucket<'tx>) -> Self {
+        Self {
+            bucket,
+            stack: Vec::new(),
+            scan: Scan::default(),
+            err: None,
+            skip_hidden: false,
+        }
+    }
+
+    // skipping_hidden makes the cursor skip the hidden buckets of the root.
+    pub(crate) fn skipping_hidden(mut self) -> Self {
+        self.skip_hidden = true;
+        self
+    }
+
+    /// bucket returns the bucket that this cursor was created from.
+    pub fn bucket(&self) -> Bucket<'tx> {
+        self.bucket.clone()
+    }
+
+    /// err returns the error that made the last move of the cursor return
+    /// None, or None if the move ran out of keys.
+    pub fn err(&self) -> Option<&BoltError> {
+        self.err.as_ref()
+    }
+
+    // check returns the error of the last move, if it failed, leaving none
+    // behind.
+    pub(crate) fn check(&mut self) -> Result<()> {
+        match self.err.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// first moves the cursor to the first item in the bucket and returns its key and value.
+    /// If the bucket is empty then None is returned.
+    pub fn first(&mut self) -> Option<(&'tx [u8], Option<&'tx [u8]>)> {
+        let item = self.first_item();
+        let item = self.skip_expired(item, true);
+        self.entry(item)
+    }
+
+    /// last moves the cursor to the last item in the bucket and returns its key and value.
+    /// If the bucket is empty then None is returned.
+    pub fn last(&mut self) -> Option<(&'tx [u8], Option<&'tx [u8]>)> {
+        let item = self.last_item();
+        let item = self.skip_expired(item, false);
+        self.entry(item)
+    }
+
+    /// next moves the cursor to the next item in the bucket and returns its key and value.
+    /// If the cursor is at the end of the bucket then None is returned.
+    // The cursor can move both ways and be repositioned, so it is not an Iterator.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&'tx [u8], Option<&'tx [u8]>)> {
+        let item = self.next_item();
+        let item = self.skip_expired(item, true);
+        self.entry(item)
+    }
+
+    /// prev moves the cursor to the previous item in the bucket and returns its key and value.
+    /// If the cursor is at the beginning of the bucket then None is returned.
+    pub fn prev(&mut self) -> Option<(&'tx [u8], Option<&'tx [u8]>)> {
+        let item = self.prev_item();
+        let item = self.skip_expired(item, false);
+        self.entry(item)
+    }
+
+    /// seek moves the cursor to a given key using a b-tree search and returns it.
+    /// If the key does not exist then the next key is used. If no keys
+    /// follow, None is returned.
+    pub fn seek(&mut self, seek: &[u8]) -> Option<(&'tx [u8], Option<&'tx [u8]>)> {
+        let mut item = self.seek_item(seek);
+
+        // If we ended up after the last element of a page then move to the next one.
+        if let Some(elem) = self.stack.last() {
+            if elem.index >= elem.count() {
+                item = self.next_item();
+            }
+        }
+
+        let item = self.skip_expired(item, true);
+        self.entry(item)
+    }
+
+    /// delete removes the current key/value under the cursor from the bucket.
+    /// Delete fails if current key/value is a bucket or if the transaction is not writable.
+    pub fn delete(&mut self) -> Result<()> {
+        let tx = self.tx()?;
+        if !tx.writable() {
+            return Err(BoltError::TxNotWritable);
+        }
+
+        let (key, value, flags) = self.key_value().ok_or(BoltError::KeyRequired)?;
+        // Return an error if current value is a bucket.
+        if (flags & BUCKET_LEAF_FLAG) != 0 {
+            return Err(BoltError::IncompatibleValue);
+        }
+        self.node()?.del(&key);
+
+        if self.bucket.is_capturing() {
+            let old = self.bucket.compression().decompress(&value)?;
+            self.bucket.record_change(&key, Some(old), None);
+        }
+        self.bucket.clear_ttl(&key)
+    }
+
+    // entry converts a raw item into the public (key, value) pair, hiding the
+    // value of nested buckets, and records the error of a failed move.
+    fn entry(&mut self, item: Result<Option<Item>>) -> Option<(&'tx [u8], Option<&'tx [u8]>)> {
+        match self.try_entry(item) {
+            Ok(entry) => {
+                self.err = None;
+                entry
+            }
+            Err(err) => {
+                self.err = Some(err);
+                None
+            }
+        }
+    }
+
+    fn try_entry(&self, item: Result<Option<Item>>) -> Result<Option<Pair<'tx>>> {
+        let Some((key, value, flags)) = item? else {
+            return Ok(None);
+        };
+        // Retaining the whole page covers the keys and values handed out
+        // from it later.
+        if let Some(PageNode::Page(buf)) = self.stack.last().map(|elem| &elem.page_node) {
+            self.bucket.pin(buf.clone()).ok_or(BoltError::TxClosed)?;
+        }
+        let key = self.bucket.pin(key).ok_or(BoltError::TxClosed)?;
+        if (flags & BUCKET_LEAF_FLAG) != 0 {
+            return Ok(Some((key, None)));
+        }
+        let value = self.bucket.compression().decompress_bytes(&value)?;
+        let value = self.bucket.pin(value).ok_or(BoltError::TxClosed)?;
+        Ok(Some((key, Some(value))))
+    }
+
+    // first_live and next_live are first_item and next_item skipping keys
+    // whose TTL has passed, for scans that do not hand out the items.
+    pub(crate) fn first_live(&mut self) -> Result<Option<Item>> {
+        let item = self.first_item();
+        self.skip_expired(item, true)
+    }
+
+    pub(crate) fn next_live(&mut self) -> Result<Option<Item>> {
+        let item = self.next_item();
+        self.skip_expired(item, true)
+    }
+
+    // skip_expired moves the cursor past keys whose TTL has passed, and hidden
+    // buckets if it skips them, forward or backward, and returns the first
+    // live item.
+    fn skip_expired(
+        &mut self,
+        mut item: Result<Option<Item>>,
+        forward: bool,
+    ) -> Result<Option<Item>> {
+        while let Ok(Some((key, _, flags))) = &item {
+            let hidden = match (flags & BUCKET_LEAF_FLAG) != 0 {
+                true => self.skip_hidden && is_hidden(key),
+                false => self.bucket.is_expired(key),
+            };
+            if !hidden {
+                break;
+            }
+            item = if forward {
+                self.next_item()
+            } else {
+                self.prev_item()
+            };
+        }
+        item
+    }
+
+    // tx returns the transaction of the cursor, or TxClosed once it is gone.
+    // Holding on to it keeps the pages on the stack alive.
+    fn tx(&self) -> Result<Tx> {
+        let tx = self.bucket.0.tx()?;
+        tx.db()?;
+        Ok(tx)
+    }
+
+    // root_ref returns an element ref for the root page/node of the bucket.
+    fn root_ref(&self) -> Result<ElemRef> {
+        self.elem_ref(self.bucket.0.root_page())
+    }
+
+    // elem_ref returns an element ref for the first element of a page/node.
+    fn elem_ref(&self, pgid: PgId) -> Result<ElemRef> {
+        let page_node = self.bucket.0.page_node(pgid)?;
+        // The fake page of an inline bucket lives in its parent's page.
+        let borrow = match page_node.page() {
+            Some(page) if pgid != 0 => self.tx()?.borrow_page(pgid, page.overflow() as u64 + 1),
+            _ => PageBorrow::default(),
+        };
+        Ok(ElemRef {
+            page_node,
+            index: 0,
+            _borrow: borrow,
+        })
+    }
+
+    /// first_item moves the cursor to the first leaf element under the last page in the stack.
+    pub(crate) fn first_item(&mut self) -> Result<Option<Item>> {
+        let _tx = self.tx()?;
+        self.stack.clear();
+        let root = self.root_ref()?;
+        self.stack.push(root);
+        self.go_to_first_element_on_the_stack()?;
+
+        // If we land on an empty page then move to the next value.
+        if self.stack.last().is_some_and(|elem| elem.count() == 0) {
+            self.next_item()?;
+        }
+
+        Ok(self.key_value())
+    }
+
+    // last_item moves the cursor to the last item in the bucket.
+    fn last_item(&mut self) -> Result<Option<Item>> {
+        let _tx = self.tx()?;
+        self.stack.clear();
+        let mut root = self.root_ref()?;
+        root.index = root.count().saturating_sub(1);
+        self.stack.push(root);
+        self.go_to_last_element_on_the_stack()?;
+
+        // If this is an empty page (calling delete may result in empty pages)
+        // we call prev to find the last page that is not empty.
+        while self.stack.len() > 1 && self.stack.last().is_some_and(|elem| elem.count() == 0) {
+            self.prev_item()?;
+        }
+
+        if self.stack.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(self.key_value())
+    }
+
+    /// next_item moves to the next leaf element and returns the key and value.
+    /// If the cursor is at the last leaf element then it stays there and returns None.
+    pub(crate) fn next_item(&mut self) -> Result<Option<Item>> {
+        let tx = self.tx()?;
+        loop {
+            // Attempt to move over one element until we're successful.
+            // Move up the stack as we hit the end of each page in our stack.
+            let mut moved = None;
+            for i in (0..self.stack.len()).rev() {
+                let elem = &mut self.stack[i];
+                if elem.index + 1 < elem.count() {
+                    elem.index += 1;
+                    moved = Some(i);
+                    break;
+                }
+            }
+
+            // If we've hit the root page then stop and return. This will leave the
+            // cursor on the last element of the last page.
+            let i = match moved {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+
+            // Otherwise start from where we left off in the stack and find the
+            // first element of the first leaf page.
+            self.stack.truncate(i + 1);
+            self.go_to_first_element_on_the_stack()?;
+            if i + 1 < self.stack.len() {
+                self.follow_scan(&tx)?;
+            }
+
+            // If this is an empty page then restart and move back up the stack.
+            if self.stack.last().is_some_and(|elem| elem.count() == 0) {
+                continue;
+            }
+
+            return Ok(self.key_value());
+        }
+    }
+
+    // follow_scan records that next_item moved to a new leaf. Once the
+    // leaves follow each other in the file, the pages after it are read
+    // ahead, a window at a time.
+    fn follow_scan(&mut self, tx: &Tx) -> Result<()> {
+        let window = tx.db()?.0.readahead() as PgId;
+        let Some(page) = self.stack.last().and_then(|elem| elem.page_node.page()) else {
+            // Nodes are in memory already.
+            self.scan = Scan::default();
+            return Ok(());
+        };
+        let id = page.id();
+        let end = id + page.overflow() as PgId;
+
+        let scan = &mut self.scan;
+        match scan.last {
+            Some(last) if id > last && id - last <= READAHEAD_MAX_GAP => scan.run += 1,
+            _ => *scan = Scan::default(),
+        }
+        scan.last = Some(end);
+
+        // Read the next window once less than half of the current one is left.
+        if window > 0 && scan.run >= READAHEAD_MIN_RUN && end + window / 2 >= scan.ahead {
+            let from = scan.ahead.max(end + 1);
+            let to = end + 1 + window;
+            tx.readahead(from, (to - from) as usize);
+            scan.ahead = to;
+        }
+        Ok(())
+    }
+
+    // prev_item moves the cursor to the previous item in the bucket and returns its key and value.
+    // If the cursor is at the beginning of the bucket then None is returned.
+    fn prev_item(&mut self) -> Result<Option<Item>> {
+        let _tx = self.tx()?;
+
+        // Attempt to move back one element until we're successful.
+        // Move up the stack as we hit the beginning of each page in our stack.
+        for i in (0..self.stack.len()).rev() {
+            let elem = &mut self.stack[i];
+            if elem.index > 0 {
+                elem.index -= 1;
+                break;
+            }
+
+            // If we've hit the beginning, we should stop moving the cursor,
+            // and stay at the first element, so that users can continue to
+            // iterate over the elements in reverse direction by calling `next`.
+            if self.stack.len() == 1 {
+                self.first_item()?;
+                return Ok(None);
+            }
+            self.stack.truncate(i);
+        }
+
+        // If we've hit the end then return None.
+        if self.stack.is_empty() {
+            return Ok(None);
+        }
+
+        // Move down the stack to find the last element of the last leaf under this branch.
+        self.go_to_last_element_on_the_stack()?;
+
+        Ok(self.key_value())
+    }
+
+    /// seek_item moves the cursor to a given key and returns it.
+    /// If the key does not exist then the next key is used.
+    pub(crate) fn seek_item(&mut self, seek: &[u8]) -> Result<Option<Item>> {
+        let _tx = self.tx()?;
+
+        // Start from root page/node and traverse to correct page.
+        self.stack.clear();
+        self.search(seek, self.bucket.0.root_page())?;
+
+        // If this is a bucket then return a nil value.
+        Ok(self.key_value())
+    }
+
+    /// reseek_item moves the cursor to a given key like seek_item, but keeps
+    /// the pages on the stack that lead to the key as well, so that seeking
+    /// nearby keys only descends from where their paths diverge.
+    pub(crate) fn reseek_item(&mut self, seek: &[u8]) -> Result<Option<Item>> {
+        let _tx = self.tx()?;
+
+        for i in 0..self.stack.len() {
+            let elem = &mut self.stack[i];
+            if elem.is_leaf() {
+                self.stack.truncate(i + 1);
+                self.nsearch(seek);
+                return Ok(self.key_value());
+            }
+
+            let index = Self::search_branch(seek, &elem.page_node);
+            if index != elem.index {
+                elem.index = index;
+                let child = elem.child_pgid();
+                self.stack.truncate(i + 1);
+                self.search(seek, child)?;
+                return Ok(self.key_value());
+            }
+        }
+        self.seek_item(seek)
+    }
+
+    // go_to_first_element_on_the_stack moves the cursor to the first leaf element
+    // under the last page in the stack.
+    fn go_to_first_element_on_the_stack(&mut self) -> Result<()> {
+        loop {
+            // Exit when we hit a leaf page.
+            let elem = self.stack.last().expect("empty cursor stack");
+            if elem.is_leaf() {
+                break;
+            }
+
+            // Keep adding pages pointing to the first element to the stack.
+            let next = self.elem_ref(elem.child_pgid())?;
+            self.stack.push(next);
+        }
+
+        Ok(())
+    }
+
+    // go_to_last_element_on_the_stack moves the cursor to the last leaf element
+    // under the last page in the stack.
+    fn go_to_last_element_on_the_stack(&mut self) -> Result<()> {
+        loop {
+            // Exit when we hit a leaf page.
+            let elem = self.stack.last().expect("empty cursor stack");
+            if elem.is_leaf() {
+                break;
+            }
+
+            // Keep adding pages pointing to the last element in the stack.
+            let mut next = self.elem_ref(elem.child_pgid())?;
+            next.index = next.count().saturating_sub(1);
+            self.stack.push(next);
+        }
+
+        Ok(())
+    }
+
+    // search recursively performs a binary search against a given page/node until it finds a given key.
+    fn search(&mut self, key: &[u8], pgid: PgId) -> Result<()> {
+        let elem = self.elem_ref(pgid)?;
+        if let Some(page) = elem.page_node.page() {
+            if !page.is_branch_page() && !page.is_leaf_page() {
+                let reason = format!("invalid page type: {:x}", page.flags().bits());
+                return Err(BoltError::corrupted(reason).with_pgid(page.id()));
+            }
+        }
+        let is_leaf = elem.is_leaf();
+        self.stack.push(elem);
+
+        // If we're on a leaf page/node then find the specific node.
+        if is_leaf {
+            self.nsearch(key);
+            return Ok(());
+        }
+
+        let elem = self.stack.last_mut().unwrap();
+        elem.index = Self::search_branch(key, &elem.page_node);
+
+        // Recursively search to the next page.
+        let child = elem.child_pgid();
+        self.search(key, child)
+    }
+
+    // search_branch finds the element of a branch page/node a key belongs to.
+    fn search_branch(key: &[u8], page_node: &PageNode) -> usize {
+        match page_node {
+            PageNode::Node(node) => Self::search_node(key, node),
+            PageNode::Page(_) => Self::search_index(page_node.page().unwrap().branch_search(key)),
+        }
+    }
+
+    // search_node finds the branch inode a key belongs to.
+    fn search_node(key: &[u8], node: &Node) -> usize {
+        Self::search_index(node.inodes().binary_search_by(key))
+    }
+
+    // search_index turns a binary search over branch keys into the index of the
+    // child that covers the key: the exact match, or the one before the
+    // insertion point.
+    fn search_index(result: std::result::Result<usize, usize>) -> usize {
+        match result {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        }
+    }
+
+    // nsearch searches the leaf node on the top of the stack for a key.
+    fn nsearch(&mut self, key: &[u8]) {
+        let elem = self.stack.last_mut().unwrap();
+
+        // If we have a node then search its inodes, otherwise its leaf elements.
+        let index = match &elem.page_node {
+            PageNode::Node(node) => node.inodes().binary_search_by(key),
+            PageNode::Page(_) => {
+                let page = elem.page_node.page().unwrap();
+                page.leaf_page_elements()
+                    .binary_search_by(|elem| elem.key().cmp(key))
+            }
+        };
+        elem.index = match index {
+            Ok(index) | Err(index) => index,
+        };
+    }
+
+    // key_value returns the key and value of the current leaf element.
+    fn key_value(&self) -> Option<Item> {
+        let elem = self.stack.last()?;
+
+        // If the cursor is pointing to the end of page/node then return None.
+        if elem.count() == 0 || elem.index >= elem.count() {
+            return None;
+        }
+
+        // Retrieve value from node or page.
+        match &elem.page_node {
+            PageNode::Node(node) => {
+                let inodes = node.inodes();
+                let inode = inodes.get(elem.index);
+                Some((inode.key().clone(), inode.value().clone(), inode.flags()))
+            }
+            PageNode::Page(buf) => {
+                let page = Page::from_slice(buf);
+                let leaf = page.leaf_page_element(elem.index);
+                Some((
+                    buf.slice_ref(leaf.key()),
+                    buf.slice_ref(leaf.value()),
+                    leaf.flags(),
+                ))
+            }
+        }
+    }
+
+    /// node returns the node that the cursor is currently positioned on.
+    pub(crate) fn node(&mut self) -> Result<Node> {
+        assert!(
+            !self.stack.is_empty(),
+            "accessing a node with a zero-length cursor stack"
+        );
+
+        // If the top of the stack is a leaf node then just return it.
+        if let Some(ElemRef {
+            page_node: PageNode::Node(node),
+            ..
+        }) = self.stack.last()
+        {
+            if node.is_leaf() {
+                return Ok(node.clone());
+            }
+        }
+
+        // Start from root and traverse down the hierarchy.
+        let mut node = match &self.stack[0].page_node {
+            PageNode::Node(node) => node.clone(),
+            PageNode::Page(_) => {
+                let id = self.stack[0].page_node.page().unwrap().id();
+                self.bucket.0.node(id, WeakNode::new())?
+            }
+        };
+        for elem in &self.stack[..self.stack.len() - 1] {
+            assert!(!node.is_leaf(), "expected branch node");
+            node = node.child_at(elem.index)?;
+        }
+        assert!(node.is_leaf(), "expected leaf node");
+
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Options, DB};
+    use crate::errors::BoltError;
+
+    fn open(dir: &tempfile::TempDir) -> DB {
+        DB::open(dir.path().join("db"), Options::default()).unwrap()
+    }
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("{:08}", i).into_bytes()
+    }
+
+    // fill creates a "widgets" bucket holding n keys, spread over several pages.
+    fn fill(db: &DB, n: u32) {
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..n {
+                b.put(&key(i), &[0x7f; 40])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a cursor on an empty bucket returns None everywhere.
+    #[test]
+    fn test_cursor_empty_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let mut c = tx.create_bucket(b"widgets")?.cursor();
+            assert_eq!(c.first(), None);
+            assert_eq!(c.last(), None);
+            assert_eq!(c.seek(b"foo"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a cursor can iterate over a multi-page bucket in both directions.
+    #[test]
+    fn test_cursor_iterate() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        fill(&db, 1000);
+
+        db.view(|tx| {
+            let mut c = tx.bucket(b"widgets").unwrap().cursor();
+            let mut keys = Vec::new();
+            let mut item = c.first();
+            while let Some((k, v)) = item {
+                assert_eq!(v, Some(&[0x7f; 40][..]));
+                keys.push(k);
+                item = c.next();
+            }
+            assert_eq!(keys, (0..1000).map(key).collect::<Vec<_>>());
+
+            keys.clear();
+            let mut item = c.last();
+            while let Some((k, _)) = item {
+                keys.push(k);
+                item = c.prev();
+            }
+            assert_eq!(keys, (0..1000).rev().map(key).collect::<Vec<_>>());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a scan over leaves lying one after the other reads ahead,
+    // unless readahead is disabled.
+    #[test]
+    fn test_cursor_readahead() {
+        let scan = |readahead| {
+            let dir = tempfile::tempdir().unwrap();
+            let options = Options {
+                readahead,
+                ..Default::default()
+            };
+            let db = DB::open(dir.path().join("db"), options).unwrap();
+            fill(&db, 10000);
+            db.view(|tx| {
+                let mut c = tx.bucket(b"widgets").unwrap().cursor();
+                let mut n = 0;
+                let mut item = c.first();
+                while item.is_some() {
+                    n += 1;
+                    item = c.next();
+                }
+                assert_eq!(n, 10000);
+                Ok(tx.stats().readahead)
+            })
+            .unwrap()
+        };
+        assert!(scan(None) > 0);
+        assert_eq!(scan(Some(0)), 0);
+    }
+
+    // Ensure that a cursor can seek to the appropriate keys.
+    #[test]
+    fn test_cursor_seek() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put(b"foo", b"0001")?;
+            b.put(b"bar", b"0002")?;
+            b.put(b"baz", b"0003")?;
+            b.create_bucket(b"bkt")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let mut c = tx.bucket(b"widgets").unwrap().cursor();
+
+            // Exact match should go to the key.
+            assert_eq!(c.seek(b"bar"), Some((&b"bar"[..], Some(&b"0002"[..]))));
+            // Inexact match should go to the next key.
+            assert_eq!(c.seek(b"bas"), Some((&b"baz"[..], Some(&b"0003"[..]))));
+            // Low key should go to the first key.
+            assert_eq!(c.seek(b""), Some((&b"bar"[..], Some(&b"0002"[..]))));
+            // High key should return no key.
+            assert_eq!(c.seek(b"zzz"), None);
+            // Buckets should return their key but no value.
+            assert_eq!(c.seek(b"bkt"), Some((&b"bkt"[..], None)));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that seeking past the end of a page moves on to the next page.
+    #[test]
+    fn test_cursor_seek_across_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        fill(&db, 1000);
+
+        db.view(|tx| {
+            let mut c = tx.bucket(b"widgets").unwrap().cursor();
+            for i in 0..999 {
+                let mut seek = key(i);
+                seek.push(b'x');
+                assert_eq!(c.seek(&seek).map(|(k, _)| k.to_vec()), Some(key(i + 1)));
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a cursor can delete while iterating over a bucket.
+    #[test]
+    fn test_cursor_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        fill(&db, 1000);
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            b.create_bucket(b"sub")?;
+            let mut c = b.cursor();
+            let mut item = c.first();
+            while let Some((k, _)) = item {
+                if k >= key(500).as_slice() {
+                    break;
+                }
+                c.delete()?;
+                item = c.next();
+            }
+
+            assert_eq!(c.seek(b"sub").map(|(k, _)| k), Some(&b"sub"[..]));
+            assert_eq!(c.delete(), Err(BoltError::IncompatibleValue));
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let mut c = b.cursor();
+            assert_eq!(c.first().map(|(k, _)| k.to_vec()), Some(key(500)));
+            let mut n = 0;
+            b.for_each(|_, _| {
+                n += 1;
+                Ok(())
+            })?;
+            assert_eq!(n, 501);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a read-only cursor cannot delete.
+    #[test]
+    fn test_cursor_delete_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        fill(&db, 10);
+
+        db.view(|tx| {
+            let mut c = tx.bucket(b"widgets").unwrap().cursor();
+            c.first();
+            assert_eq!(c.delete(), Err(BoltError::TxNotWritable));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that the root cursor lists top-level buckets without values.
+    #[test]
+    fn test_cursor_root_buckets() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir);
+        db.update(|tx| {
+            tx.create_bucket(b"widgets")?;
+            tx.create_bucket(b"woojits")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let mut c = tx.cursor();
+            assert_eq!(c.first(), Some((&b"widgets"[..], None)));
+            assert_eq!(c.next(), Some((&b"woojits"[..], None)));
+            assert_eq!(c.next(), None);
+
+            let mut names = Vec::new();
+            tx.for_each(|name, _| {
+                names.push(name.to_vec());
+                Ok(())
+            })?;
+            assert_eq!(names, vec![b"widgets".to_vec(), b"woojits".to_vec()]);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -1,84 +1,564 @@
-use std::{fs::File, sync::{Arc, Mutex, RwLock, Weak}, time::Duration};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::time::{Duration, SystemTime};
 
-use crate::{common::{self, meta::Meta}, tx::Tx};
-use  crate::errors::Result;
-struct freelist;
-struct batch;
+use bytes::Bytes;
 
-struct Stats;
+use crate::arena::PagePool;
+use crate::background_sync::BackgroundSync;
+use crate::backup::PageTxids;
+use crate::batch::Batcher;
+use crate::borrows::PageBorrows;
+use crate::bucket::{DEFAULT_REBALANCE_PERCENT, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+use crate::changes::Observers;
+use crate::checksum::ChecksumType;
+use crate::common::bucket::InBucket;
+use crate::common::meta::Meta;
+use crate::common::page::{OwnedPage, Page, PageFlags, PgId, PAGE_HEADER_SIZE};
+use crate::common::types::{
+    Txid, DEFAULT_ALLOC_SIZE, DEFAULT_PAGE_SIZE, FEATURE_BRANCH_PREFIX, IGNORE_NO_SYNC, MAGIC,
+    MAX_MMAP_STEP, MAX_POOLED_BYTES, VERSION, VERSION_FEATURES,
+};
+#[cfg(feature = "encryption")]
+use crate::crypto::EncryptionKey;
+use crate::crypto::PageCrypt;
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::freelist::{Freelist, FreelistReport, FreelistType};
+use crate::group_commit::GroupCommit;
+use crate::health::LastCheck;
+use crate::latency::{CommitLatency, CommitTimer};
+use crate::logger::{DefaultLogger, Logger};
+use crate::os::{Stopwatch, MAX_MAP_SIZE};
+use crate::page_cache::{DecryptedPages, PageCache};
+use crate::rate_limit::WriteLimiter;
+use crate::stats_cache::StatsCache;
+use crate::tx::{Tx, TxStats};
+use crate::vfs::{MapOptions, SyncStrategy, Vfs};
 
+pub use crate::batch::{DEFAULT_MAX_BATCH_DELAY, DEFAULT_MAX_BATCH_SIZE};
+pub use crate::salvage::{salvage, ORPHAN_BUCKET};
 
-// FreelistType enum (replace with actual variants)
-enum FreelistType {
-    Array,
-    HashMap,
+/// DEFAULT_READAHEAD is the number of pages read ahead of a sequential scan
+/// unless Options.readahead says otherwise.
+pub const DEFAULT_READAHEAD: usize = 32;
+
+/// DEFAULT_DECRYPTED_CACHE_SIZE is the number of decrypted pages an encrypted
+/// database keeps unless Options.decrypted_cache_size says otherwise.
+pub const DEFAULT_DECRYPTED_CACHE_SIZE: usize = 1024;
+
+/// Options represents the options that can be set when opening a database.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Timeout is the amount of time to wait to obtain a file lock.
+    /// When set to zero it will wait indefinitely.
+    pub timeout: Duration,
+
+    /// Sets the DB.NoGrowSync flag before memory mapping the file.
+    pub no_grow_sync: bool,
+
+    /// Do not sync freelist to disk. This improves the database write performance
+    /// under normal operation, but requires a full database re-sync during recovery.
+    pub no_freelist_sync: bool,
+
+    /// StrictMode runs Tx::check after every commit and fails the commit
+    /// with BoltError::CheckFailed if the database is inconsistent. The
+    /// changes are committed by then. This is expensive and only meant for
+    /// debugging.
+    pub strict_mode: bool,
+
+    /// CheckTornWrites scans every page reachable from the meta page in use
+    /// when opening the database, and fails the open with
+    /// BoltError::TornWrite if any of them extends past the end of the file
+    /// or is malformed, as a write torn by a power loss leaves it. The scan
+    /// reads the whole tree, so it slows down opening large databases.
+    pub check_torn_writes: bool,
+
+    /// FreelistType sets the backend freelist type. There are two options. Array which is simple but endures
+    /// dramatic performance degradation if database is large and fragmentation in freelist is common.
+    /// The alternative one is using hashmap, it is faster in almost all circumstances
+    /// but it doesn't guarantee that it offers the smallest page id available. In normal case it is safe.
+    /// The default type is array
+    pub freelist_type: FreelistType,
+
+    /// Open database in read-only mode. Uses flock(..., LOCK_SH |LOCK_NB) to
+    /// grab a shared lock (UNIX).
+    ///
+    /// Any number of processes may open the same file read-only at once, and
+    /// none of them writes to it: not the meta pages, nor the freelist, nor
+    /// the file size. A process opening the file for writing takes the lock
+    /// exclusively, for as long as it keeps the database open. Read-only
+    /// opens meanwhile wait for it to close, up to Timeout, and then return
+    /// BoltError::Timeout; the writer likewise waits for the readers to
+    /// close. Tools inspecting a live database should therefore set a
+    /// Timeout, or read a copy made with Tx::copy_file.
+    pub read_only: bool,
+
+    /// IgnoreChecksum uses meta pages whose checksum does not match when no
+    /// meta page is valid, to extract the data of a damaged database. The
+    /// database is then marked as degraded in its stats. Requires ReadOnly,
+    /// see Options::read_only_recovery.
+    pub ignore_checksum: bool,
+
+    /// BranchPrefixCompression creates a new database whose branch pages
+    /// store the prefix shared by their keys only once, and key leaves by the
+    /// shortest separator above the last key of the leaf before, rather than
+    /// by their first key. This fits more keys per page and keeps the tree
+    /// lower when keys share long prefixes or have long suffixes. It needs
+    /// format version 3, which older versions refuse to open; version
+    /// 2 databases are still read and written as before. Ignored for
+    /// existing databases.
+    pub branch_prefix_compression: bool,
+
+    /// Checksum is the algorithm checksumming the meta pages of a new
+    /// database. Anything but FNV-1a, the algorithm of Go bbolt, needs format
+    /// version 3. Ignored for existing databases, which keep theirs.
+    pub checksum: ChecksumType,
+
+    /// InitialMmapSize is the initial mmap size of the database
+    /// in bytes. Read transactions won't block write transaction
+    /// if the InitialMmapSize is large enough to hold database mmap
+    /// size. (See DB::begin for more information)
+    ///
+    /// If <=0, the initial map size is 0.
+    /// If initialMmapSize is smaller than the previous database size,
+    /// it takes no effect.
+    pub initial_mmap_size: usize,
+
+    /// PageSize overrides the default OS page size.
+    pub page_size: usize,
+
+    /// MaxKeySize is the maximum length of a key or bucket name in bytes.
+    /// Longer ones are rejected with BoltError::KeyTooLarge. If zero,
+    /// MAX_KEY_SIZE is used, which is also the largest limit allowed.
+    pub max_key_size: usize,
+
+    /// MaxValueSize is the maximum length of a value in bytes. Longer ones
+    /// are rejected with BoltError::ValueTooLarge. If zero, MAX_VALUE_SIZE is
+    /// used, which is also the largest limit allowed.
+    pub max_value_size: usize,
+
+    /// Readahead is the number of pages the OS is asked to read in advance
+    /// once a cursor iterates over leaf pages lying one after the other in
+    /// the file, which speeds up scans of data not cached yet. When unset
+    /// DEFAULT_READAHEAD pages are read ahead; zero disables it.
+    pub readahead: Option<usize>,
+
+    /// PreLoadFreelist sets whether to load the free pages when opening
+    /// the db file. Note when opening db in write mode, bbolt will always
+    /// load the free pages.
+    pub pre_load_freelist: bool,
+
+    /// Setting the NoSync flag will cause the database to skip fsync()
+    /// calls after each commit. This can be useful when bulk loading data
+    /// into a database and you can restart the bulk load in the event of
+    /// a system failure or database corruption. Do not set this flag for
+    /// normal use.
+    ///
+    /// THIS IS UNSAFE. PLEASE USE WITH CAUTION.
+    pub no_sync: bool,
+
+    /// SyncStrategy is how commits make the data file durable, and how
+    /// Tx::copy_file makes its copy durable. Defaults to fdatasync.
+    pub sync_strategy: SyncStrategy,
+
+    /// GroupCommitDelay enables group commit: a commit releases the writer
+    /// lock before making its meta page durable, so that one sync covers the
+    /// commits of writers queued behind it. It waits at most this long for
+    /// them before syncing. Commits still return once they are durable.
+    /// If a shared sync fails, the commits it covers have their meta page in
+    /// place but not known to be durable: they, and every later commit and
+    /// write transaction, return its error until the database is reopened.
+    /// Ignored when NoSync is set.
+    pub group_commit_delay: Option<Duration>,
+
+    /// SyncInterval makes a background thread sync the data file this often
+    /// while commits are left unsynced, which bounds the commits a crash can
+    /// lose. Ignored unless NoSync is set.
+    pub sync_interval: Option<Duration>,
+
+    /// SyncEveryCommits makes a background thread sync the data file once
+    /// this many commits are left unsynced. Ignored unless NoSync is set.
+    pub sync_every_commits: Option<usize>,
+
+    /// WriterLockTimeout is how long a write transaction waits for the one
+    /// holding the writer lock. Once it elapses, the transaction fails with
+    /// BoltError::WriterLockTimeout describing the open transactions, which
+    /// is logged as a warning as well. When unset writers wait indefinitely.
+    pub writer_lock_timeout: Option<Duration>,
+
+    /// SlowCommitThreshold logs a warning for each commit taking longer,
+    /// with the time each of its phases took and the number of pages it
+    /// wrote. Stats.commit_latency sums up every commit either way. When
+    /// unset slow commits are not logged.
+    pub slow_commit_threshold: Option<Duration>,
+
+    /// WriteRateLimit paces the writes of dirty pages by commits, and so by
+    /// compaction, to this many bytes per second on average, so that the IO
+    /// of the database does not starve other processes. TxStats.throttle_time
+    /// tells how long commits waited. When unset or zero writes are not
+    /// limited.
+    pub write_rate_limit: Option<u64>,
+
+    /// RebalancePercent is the fill of a node, relative to the page size,
+    /// below which a commit merges it with a sibling. When unset
+    /// DEFAULT_REBALANCE_PERCENT is used, like Go bbolt. Buckets can
+    /// override it with Bucket::set_rebalance_percent.
+    pub rebalance_percent: Option<f64>,
+
+    /// MaxBatchSize is the number of calls DB::batch runs in one transaction
+    /// at most. When unset DEFAULT_MAX_BATCH_SIZE is used. It can be changed
+    /// later with DB::set_max_batch_size.
+    pub max_batch_size: Option<usize>,
+
+    /// MaxBatchDelay is how long DB::batch waits for more calls before
+    /// running a batch. When unset DEFAULT_MAX_BATCH_DELAY is used. It can be
+    /// changed later with DB::set_max_batch_delay.
+    pub max_batch_delay: Option<Duration>,
+
+    /// EncryptionKey encrypts every page but the meta pages with AES-256-GCM.
+    /// A new database is encrypted if a key is set; an existing one has to be
+    /// opened with the key it was created with.
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<EncryptionKey>,
+
+    /// DecryptedCacheSize is the number of decrypted pages of an encrypted,
+    /// memory-mapped database kept in an LRU cache shared by transactions.
+    /// Other pages are decrypted each time they are read, and dropped once
+    /// nothing uses them. When unset DEFAULT_DECRYPTED_CACHE_SIZE is used.
+    /// With page_cache_size set, the page cache holds the decrypted pages.
+    #[cfg(feature = "encryption")]
+    pub decrypted_cache_size: Option<usize>,
+
+    /// Logger receives the messages of the database. When unset they are
+    /// forwarded to the log crate by DefaultLogger.
+    pub logger: Option<Arc<dyn Logger>>,
+
+    /// OpenProgress is called as each phase of opening the database ends,
+    /// to follow the open of a large file. Stats.open_phases records the
+    /// phases as well.
+    pub open_progress: Option<OpenProgress>,
+
+    /// CacheBucketStats keeps the stats of the subtree below each page once
+    /// Bucket::stats computed them, so that later calls only walk the pages
+    /// written since. Meant for dashboards polling the stats of large
+    /// buckets; the cache takes memory for each page visited.
+    pub cache_bucket_stats: bool,
+
+    /// PageCacheSize reads the data file with positional reads instead of
+    /// memory-mapping it, keeping up to this many recently read pages in an
+    /// LRU cache shared by transactions. Meant for databases too large to be
+    /// mapped, on 32-bit targets in particular, or wherever mapping a huge
+    /// file is undesirable. A transaction also keeps the pages holding the
+    /// keys and values it handed out until it closes. Readahead is disabled.
+    /// When unset the file is memory-mapped.
+    pub page_cache_size: Option<usize>,
+
+    /// MmapPopulate pre-faults the mapping of the data file with
+    /// MAP_POPULATE on Linux, when opening the database and each time the
+    /// mapping grows. Opening then reads the whole file, but the first
+    /// accesses to the pages of a large read-mostly database do not stall
+    /// on page faults.
+    pub mmap_populate: bool,
+
+    /// MmapHugePages advises Linux to back the mapping of the data file with
+    /// transparent hugepages, which cuts TLB misses over very large
+    /// databases. Kernels without support for them on files ignore it.
+    pub mmap_huge_pages: bool,
+
+    /// Vfs is the storage the database is kept in. When unset the file at
+    /// the path given to open is used, otherwise the path only names the
+    /// database in messages.
+    pub vfs: Option<Arc<dyn Vfs>>,
 }
 
+impl Options {
+    /// read_only_recovery returns the options opening a damaged database
+    /// read-only, ignoring the checksums of its meta pages.
+    pub fn read_only_recovery() -> Options {
+        Options {
+            read_only: true,
+            ignore_checksum: true,
+            ..Default::default()
+        }
+    }
+}
 
+/// Stats represents statistics about the database.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// global, ongoing stats.
+    pub tx_stats: TxStats,
 
-pub(crate) struct RawDB {
+    // Freelist stats
+    /// total number of free pages on the freelist
+    pub free_page_n: usize,
+    /// total number of pending pages on the freelist
+    pub pending_page_n: usize,
+    /// total bytes allocated in free pages
+    pub free_alloc: usize,
+    /// total bytes used by the freelist
+    pub freelist_inuse: usize,
+    /// length in pages of the largest contiguous run of free pages
+    pub free_span_max: usize,
+    /// share of free pages outside the largest contiguous run, see FreelistReport
+    pub free_fragmentation: f64,
 
-    stats: Arc<Mutex<Stats>>, // Thread-safe access to statistics
+    // Transaction stats
+    /// total number of started read transactions
+    pub tx_n: usize,
+    /// number of currently open read transactions
+    pub open_tx_n: usize,
 
-    // Flags with explicit defaults
-    strict_mode: bool,
-    no_sync: bool,
-    no_freelist_sync: bool,
-    freelist_type: FreelistType,
-    no_grow_sync: bool,
-    pre_load_freelist: bool,
-    mmap_flags: i32,
+    /// whether a meta page was used despite a checksum mismatch, so the data
+    /// read may be inconsistent
+    pub degraded: bool,
+
+    // Open stats
+    /// time opening the database took
+    pub open_time: Duration,
+    /// time each phase of opening the database took, in order
+    pub open_phases: Vec<(OpenPhase, Duration)>,
+
+    // Commit stats
+    /// percentiles of how long commits took, in total and per phase
+    pub commit_latency: CommitLatency,
+}
+
+/// OpenPhase is a phase of opening a database, see Options.open_progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenPhase {
+    /// Lock waits for the lock of the data file.
+    Lock,
+    /// Init writes the meta pages of a new database.
+    Init,
+    /// Mmap maps the data file and validates its meta pages.
+    Mmap,
+    /// CheckTornWrites scans the pages for torn writes, see
+    /// Options.check_torn_writes.
+    CheckTornWrites,
+    /// LoadFreelist reads the freelist, or rebuilds it by scanning the
+    /// database if it is not synced.
+    LoadFreelist,
+}
 
-    // Configuration options
-    max_batch_size: isize,
-    max_batch_delay: Duration,
-    alloc_size: usize,
-    mlock: bool,
+impl fmt::Display for OpenPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OpenPhase::Lock => "lock",
+            OpenPhase::Init => "init",
+            OpenPhase::Mmap => "mmap",
+            OpenPhase::CheckTornWrites => "check torn writes",
+            OpenPhase::LoadFreelist => "load freelist",
+        })
+    }
+}
+
+/// OpenProgress is a function told about each phase of opening a database
+/// as it ends, with how long it took.
+#[derive(Clone)]
+pub struct OpenProgress(Arc<dyn Fn(OpenPhase, Duration) + Send + Sync>);
+
+impl OpenProgress {
+    /// new wraps a function into an OpenProgress.
+    pub fn new<F>(f: F) -> OpenProgress
+    where
+        F: Fn(OpenPhase, Duration) + Send + Sync + 'static,
+    {
+        OpenProgress(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for OpenProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OpenProgress")
+    }
+}
+
+// OpenTimer times the phases of opening a database.
+struct OpenTimer {
+    start: Stopwatch,
+    progress: Option<OpenProgress>,
+    phases: Vec<(OpenPhase, Duration)>,
+}
+
+impl OpenTimer {
+    // phase runs a phase, and reports how long it took if it succeeds.
+    fn phase<T, F>(&mut self, phase: OpenPhase, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let start = Stopwatch::start();
+        let result = f()?;
+        let elapsed = start.elapsed();
+        self.phases.push((phase, elapsed));
+        if let Some(progress) = &self.progress {
+            (progress.0)(phase, elapsed);
+        }
+        Ok(result)
+    }
+}
 
-    // logger: Option<Logger>, // Optional logger
+/// Info describes the database file and the meta page currently in use.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Info {
+    /// page size of the database, in bytes
+    pub page_size: usize,
+    /// file format version
+    pub version: u32,
+    /// meta page flags
+    pub flags: u32,
+    /// root page of the root bucket
+    pub root: u64,
+    /// page holding the freelist
+    pub freelist: u64,
+    /// high water mark, the number of pages in use by the file
+    pub pgid: u64,
+    /// id of the last committed transaction
+    pub txid: u64,
+    /// checksum of the meta page
+    pub checksum: u64,
+}
 
-    path: String,
-    file: Option<Arc<Mutex<File>>>, // Thread-safe file handle
-    dataref: Option<Vec<u8>>, // Optional mmap'ed data (read-only)
-    data: Option<Box<[u8]>>, // Optional data pointer (writeable)
-    datasz: usize,
+/// TxInfo describes an open transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxInfo {
+    /// id of the transaction
+    pub id: u64,
+    /// time since the transaction began
+    pub age: Duration,
+}
 
-    meta0: Option<Arc<Mutex<Meta>>>, // Thread-safe meta page 0
-    meta1: Option<Arc<Mutex<Meta>>>, // Thread-safe meta page 1
+/// LockDiagnostics lists the open transactions, to find the one a blocked
+/// writer waits for. The writer lock is held by the write transaction; a read
+/// transaction left open never blocks writers, but keeps the pages freed
+/// since it began from being reused, so the file grows instead.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockDiagnostics {
+    /// write transaction holding the writer lock
+    pub writer: Option<TxInfo>,
+    /// open read-only transactions, the oldest first
+    pub readers: Vec<TxInfo>,
+}
+
+impl fmt::Display for LockDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.writer {
+            Some(tx) => write!(f, "writer tx {} open for {:?}", tx.id, tx.age)?,
+            None => write!(f, "no writer")?,
+        }
+        write!(f, ", {} readers", self.readers.len())?;
+        for (i, tx) in self.readers.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { ", " };
+            write!(f, "{}tx {} open for {:?}", sep, tx.id, tx.age)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct RawDB {
+    stats: RwLock<Stats>, // Thread-safe access to statistics
+
+    freelist_type: FreelistType,
+    pre_load_freelist: bool,
+
+    // When enabled, the database will skip fsync() calls after each commit.
+    no_sync: bool,
+    // How the data file is synced.
+    sync_strategy: SyncStrategy,
+    // When true, skips the truncate call when growing the database.
+    no_grow_sync: bool,
+    // When true, the freelist is not written out on commit and is rebuilt
+    // by scanning the database on open.
+    no_freelist_sync: bool,
+    // When true, the database is checked after every commit.
+    strict_mode: bool,
+    // When true, meta pages with a checksum mismatch are used if no other is valid.
+    ignore_checksum: bool,
+    // Set once a meta page with a checksum mismatch was used.
+    degraded: AtomicBool,
+    // Outcome of the last Tx::check, reported by DB::health.
+    last_check: Mutex<Option<LastCheck>>,
+
+    vfs: Arc<dyn Vfs>,
+    data: RwLock<Option<Bytes>>, // mmap'ed data (read-only), shared with open transactions
+    datasz: AtomicUsize,
+    filesz: AtomicUsize, // current on disk file size
 
     page_size: usize,
 
-    opened: bool,
-    rwtx: Option<Arc<Mutex<Tx>>>, // Read-write transaction (writer)
-    txs: Vec<Arc<Mutex<Tx>>>, // Read-only transactions
+    max_key_size: usize,   // Longest key or bucket name accepted
+    max_value_size: usize, // Longest value accepted
 
-    freelist: Option<Arc<Mutex<freelist>>>, // Thread-safe freelist access
-    freelist_load: Mutex<bool>, // Flag to track freelist loading
+    readahead: usize, // Pages read ahead of sequential scans
 
-    page_pool: Mutex<Vec<Box<[u8]>>>, // Pool of allocated pages
+    rebalance_percent: f64, // Fill below which nodes are merged
 
-    batch_mu: Mutex<Option<batch>>, // Mutex for batch operations
-    rwlock: Mutex<()>, // Mutex for single writer access
+    opened: AtomicBool,
+    txs: Mutex<Vec<(Txid, Stopwatch)>>, // Read-only transactions, with their age
 
-    metalock: Mutex<()>, // Mutex for meta page access
-    mmaplock: RwLock<()>, // RWLock for mmap access during remapping
+    freelist: RwLock<Freelist>, // Thread-safe freelist access
+    freelist_load: Mutex<bool>, // Flag to track freelist loading
 
-    statlock: RwLock<()>, // RWLock for stats access
+    rwlock: Mutex<bool>,                      // Allows only one writer at a time.
+    rwlock_cond: Condvar,                     // Signalled when the writer releases rwlock.
+    rwlock_waiting: AtomicUsize,              // Number of writers waiting for rwlock.
+    rwlock_timeout: Option<Duration>,         // How long writers wait for rwlock.
+    writer: Mutex<Option<(Txid, Stopwatch)>>, // Write transaction holding rwlock.
+    metalock: Mutex<()>,                      // Mutex for meta page access
 
-    ops: Ops, // Operations struct for file access
+    page_pool: Arc<PagePool>, // Recycled page buffers of write transactions
+    stats_cache: Option<StatsCache>, // Stats of page subtrees, for Bucket::stats
+    page_cache: Option<Arc<PageCache>>, // Pages read without mmap, if not mapped
+    decrypted: Option<Arc<DecryptedPages>>, // Pages decrypted out of the mmap
+    map_options: MapOptions,  // How the data file is mapped
+    group_commit: Option<GroupCommit>, // Shares meta page syncs between commits
+    batcher: Batcher,         // Combines the calls of DB::batch
+    background_sync: Option<Arc<BackgroundSync>>, // Syncs NoSync commits in the background
+    commit_timer: CommitTimer, // Latency of commits
+    write_limiter: Option<WriteLimiter>, // Paces the writes of dirty pages
+    borrows: Arc<PageBorrows>, // Pages read in place, tracked in debug builds
 
     read_only: bool, // Read-only mode flag
 
-}
+    crypt: PageCrypt, // Encryption of page payloads
 
-struct Ops {
-    write_at: fn(&[u8], i64) -> Result<usize>,
-}
+    pub(crate) observers: Observers, // Notified of the changes of committed transactions
+    pub(crate) written: Mutex<PageTxids>, // Transactions that last wrote each page, for backup_since
 
+    path: PathBuf,           // Path of the data file
+    logger: Arc<dyn Logger>, // Receives the messages of the database
+}
 
+/// DB represents a collection of buckets persisted to a file on disk.
+/// All data access is performed through transactions which can be obtained through the DB.
 #[derive(Clone)]
 pub struct DB(pub(crate) Arc<RawDB>);
 
+impl fmt::Display for DB {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DB<{:?}>", self.0.path)
+    }
+}
+
+impl fmt::Debug for DB {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.0.stats.read().unwrap();
+        f.debug_struct("DB")
+            .field("path", &self.0.path)
+            .field("page_size", &self.0.page_size)
+            .field("read_only", &self.0.read_only)
+            .field("opened", &self.opened())
+            .field("txid", &self.0.meta().ok().map(|meta| meta.txid()))
+            .field("open_tx_n", &stats.open_tx_n)
+            .field("free_page_n", &stats.free_page_n)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct WeakDB(Weak<RawDB>);
 
@@ -95,3 +575,1958 @@ impl WeakDB {
         WeakDB(Arc::downgrade(&db.0))
     }
 }
+
+impl DB {
+    /// open creates and opens a database at the given path with a given set of options.
+    /// If the file does not exist then it will be created automatically.
+    pub fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<DB> {
+        let path = path.as_ref();
+        let logger = options
+            .logger
+            .clone()
+            .unwrap_or_else(|| Arc::new(DefaultLogger));
+        logger.info("opening db", &[("path", &path.display())]);
+
+        let result = Self::open_with_logger(path, options, logger.clone());
+        match &result {
+            Ok(_) => logger.info("opened db", &[("path", &path.display())]),
+            Err(err) => logger.error(
+                "failed to open db",
+                &[("path", &path.display()), ("error", err)],
+            ),
+        }
+        result
+    }
+
+    fn open_with_logger(path: &Path, options: Options, logger: Arc<dyn Logger>) -> Result<DB> {
+        let read_only = options.read_only;
+        if options.ignore_checksum && !read_only {
+            return Err(BoltError::Config(
+                "ignore_checksum requires read_only".to_string(),
+            ));
+        }
+        let max_key_size = limit("max_key_size", options.max_key_size, MAX_KEY_SIZE)?;
+        let max_value_size = limit("max_value_size", options.max_value_size, MAX_VALUE_SIZE)?;
+        let mut timer = OpenTimer {
+            start: Stopwatch::start(),
+            progress: options.open_progress.clone(),
+            phases: Vec::new(),
+        };
+
+        // Open data file, unless the database is kept in another storage.
+        let vfs: Arc<dyn Vfs> = match options.vfs {
+            Some(vfs) => vfs,
+            None => Arc::new(
+                OpenOptions::new()
+                    .read(true)
+                    .write(!read_only)
+                    .create(!read_only)
+                    .truncate(false)
+                    .open(path)
+                    .op(IoOp::Open)?,
+            ),
+        };
+
+        // Lock file so that other processes using Bolt in read-write mode cannot
+        // use the database at the same time. This would cause corruption since
+        // the two processes would write meta pages and free pages separately.
+        // The database file is locked exclusively (only one process can grab the lock)
+        // if !options.read_only.
+        // The database file is locked using the shared lock (more than one process may
+        // hold a lock at the same time) otherwise (options.read_only is set).
+        timer.phase(OpenPhase::Lock, || vfs.lock(!read_only, options.timeout))?;
+
+        #[cfg(feature = "encryption")]
+        let crypt = PageCrypt::new(options.encryption_key.as_ref());
+        #[cfg(not(feature = "encryption"))]
+        let crypt = PageCrypt::default();
+        #[cfg(feature = "encryption")]
+        let decrypted_cache_size = options
+            .decrypted_cache_size
+            .unwrap_or(DEFAULT_DECRYPTED_CACHE_SIZE);
+        #[cfg(not(feature = "encryption"))]
+        let decrypted_cache_size = DEFAULT_DECRYPTED_CACHE_SIZE;
+        let mut page_size = match options.page_size {
+            0 => *DEFAULT_PAGE_SIZE,
+            size => size,
+        };
+
+        // Initialize the database if it doesn't exist.
+        let mut filesz = vfs.len()? as usize;
+        if filesz == 0 {
+            if read_only {
+                return Err(BoltError::Invalid);
+            }
+            // Initialize new files with meta pages.
+            let mut features = options.checksum.meta_flags();
+            if options.branch_prefix_compression {
+                features |= FEATURE_BRANCH_PREFIX;
+            }
+            filesz = timer.phase(OpenPhase::Init, || {
+                init(
+                    vfs.as_ref(),
+                    &crypt,
+                    page_size,
+                    features,
+                    options.sync_strategy,
+                )
+            })?;
+        } else {
+            // try to get the page size from the metadata pages
+            page_size = match get_page_size(vfs.as_ref(), filesz) {
+                Err(BoltError::Checksum) if options.ignore_checksum => {
+                    read_meta_at(vfs.as_ref(), 0)?.page_size() as usize
+                }
+                result => result?,
+            };
+        }
+
+        let db = DB(Arc::new(RawDB {
+            stats: RwLock::new(Stats::default()),
+            freelist_type: options.freelist_type,
+            pre_load_freelist: options.pre_load_freelist,
+            no_sync: options.no_sync,
+            no_grow_sync: options.no_grow_sync,
+            no_freelist_sync: options.no_freelist_sync,
+            strict_mode: options.strict_mode,
+            ignore_checksum: options.ignore_checksum,
+            degraded: AtomicBool::new(false),
+            last_check: Mutex::new(None),
+            page_cache: options
+                .page_cache_size
+                .map(|size| Arc::new(PageCache::new(vfs.clone(), page_size, crypt.clone(), size))),
+            decrypted: (crypt.is_enabled() && options.page_cache_size.is_none())
+                .then(|| Arc::new(DecryptedPages::new(decrypted_cache_size))),
+            map_options: MapOptions {
+                populate: options.mmap_populate,
+                huge_pages: options.mmap_huge_pages,
+            },
+            vfs,
+            data: RwLock::new(None),
+            datasz: AtomicUsize::new(0),
+            filesz: AtomicUsize::new(filesz),
+            page_size,
+            max_key_size,
+            max_value_size,
+            opened: AtomicBool::new(true),
+            txs: Mutex::new(Vec::new()),
+            freelist: RwLock::new(Freelist::new(options.freelist_type)),
+            freelist_load: Mutex::new(false),
+            rwlock: Mutex::new(false),
+            rwlock_cond: Condvar::new(),
+            rwlock_waiting: AtomicUsize::new(0),
+            rwlock_timeout: options.writer_lock_timeout,
+            rebalance_percent: options
+                .rebalance_percent
+                .unwrap_or(DEFAULT_REBALANCE_PERCENT),
+            readahead: match options.page_cache_size {
+                Some(_) => 0,
+                None => options.readahead.unwrap_or(DEFAULT_READAHEAD),
+            },
+            writer: Mutex::new(None),
+            metalock: Mutex::new(()),
+            page_pool: Arc::new(PagePool::new(page_size, MAX_POOLED_BYTES)),
+            stats_cache: options.cache_bucket_stats.then(StatsCache::default),
+            group_commit: options
+                .group_commit_delay
+                .filter(|_| !options.no_sync || IGNORE_NO_SYNC)
+                .map(|delay| GroupCommit::new(delay, options.sync_strategy)),
+            batcher: Batcher::new(
+                options.max_batch_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE),
+                options.max_batch_delay.unwrap_or(DEFAULT_MAX_BATCH_DELAY),
+            ),
+            commit_timer: CommitTimer::new(options.slow_commit_threshold),
+            background_sync: match options.no_sync && !read_only {
+                true => BackgroundSync::new(options.sync_interval, options.sync_every_commits),
+                false => None,
+            },
+            write_limiter: options
+                .write_rate_limit
+                .filter(|&rate| rate > 0)
+                .map(WriteLimiter::new),
+            borrows: Arc::default(),
+            sync_strategy: options.sync_strategy,
+            read_only,
+            crypt,
+            observers: Observers::default(),
+            written: Mutex::new(PageTxids::default()),
+            path: path.to_path_buf(),
+            logger,
+        }));
+
+        // Memory map the data file, and make sure the key matches the one the
+        // database was created with.
+        timer.phase(OpenPhase::Mmap, || {
+            db.0.mmap(options.initial_mmap_size)?;
+            let data = db.0.data()?;
+            let active = db.0.meta()?.txid() % 2;
+            let pos = active as usize * db.0.page_size;
+            db.0.crypt.check_verifier(&data[pos..pos + db.0.page_size])
+        })?;
+
+        // Refuse a database whose pages were torn before any transaction
+        // gets to read them.
+        if options.check_torn_writes {
+            timer.phase(OpenPhase::CheckTornWrites, || db.check_torn_writes())?;
+        }
+
+        // Pages are tracked for incremental backups from here on.
+        db.0.written.lock().unwrap().reset(db.0.meta()?.txid());
+
+        // Read-only opens skip the freelist unless asked for it: tooling such as
+        // `bolt stats` never allocates pages and should not pay for parsing it.
+        if db.0.pre_load_freelist || !db.0.is_read_only() {
+            timer.phase(OpenPhase::LoadFreelist, || db.load_freelist())?;
+        }
+
+        if let Some(sync) = &db.0.background_sync {
+            sync.start(&db)?;
+        }
+
+        let mut stats = db.0.stats_mut();
+        stats.open_time = timer.start.elapsed();
+        stats.open_phases = timer.phases;
+        drop(stats);
+        Ok(db)
+    }
+
+    /// close releases all database resources.
+    /// It will block waiting for any open transactions to finish
+    /// before closing the database and returning.
+    pub fn close(&self) -> Result<()> {
+        let _metalock = self.0.metalock.lock().unwrap();
+        let path = self.0.path.display();
+        self.0.logger.debug("closing db", &[("path", &path)]);
+        match self.0.close() {
+            Ok(()) => {
+                self.0.logger.info("closed db", &[("path", &path)]);
+                Ok(())
+            }
+            Err(err) => {
+                self.0
+                    .logger
+                    .error("failed to close db", &[("path", &path), ("error", &err)]);
+                Err(err)
+            }
+        }
+    }
+
+    /// sync makes the commits made so far durable, which commits leave to the
+    /// caller when NoSync is set, see also Options.sync_interval. Otherwise
+    /// commits are durable already.
+    pub fn sync(&self) -> Result<()> {
+        if !self.opened() {
+            return Err(BoltError::DatabaseNotOpen);
+        }
+        self.0.sync()
+    }
+
+    /// view executes a function within the context of a managed read-only transaction.
+    /// Any error that is returned from the function is returned from the view() method.
+    ///
+    /// The function only borrows the transaction, so it cannot roll it back.
+    pub fn view<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Tx) -> Result<T>,
+    {
+        let tx = self.begin_ro()?;
+
+        // Mark as a managed tx so that the inner function cannot manually rollback.
+        tx.set_managed(true);
+
+        // If an error is returned from the function then pass it through.
+        let result = f(&tx);
+        tx.set_managed(false);
+        tx.rollback()?;
+
+        result
+    }
+
+    /// update executes a function within the context of a read-write managed transaction.
+    /// If no error is returned from the function then the transaction is committed.
+    /// If an error is returned then the entire transaction is rolled back.
+    /// Any error that is returned from the function or returned from the commit is
+    /// returned from the update() method.
+    ///
+    /// The function only borrows the transaction, so it cannot commit or roll it back.
+    pub fn update<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Tx) -> Result<T>,
+    {
+        let tx = self.begin_rw()?;
+
+        // Mark as a managed tx so that the inner function cannot manually commit.
+        tx.set_managed(true);
+
+        // If an error is returned from the function then rollback and return error.
+        let result = f(&tx);
+        tx.set_managed(false);
+        match result {
+            Ok(value) => {
+                tx.commit()?;
+                Ok(value)
+            }
+            Err(err) => {
+                tx.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// stats retrieves ongoing performance stats for the database.
+    /// This is only updated when a transaction closes.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.0.stats.read().unwrap().clone();
+        stats.degraded = self.0.degraded.load(Ordering::Acquire);
+        stats.commit_latency = self.0.commit_timer.latency();
+        stats
+    }
+
+    /// path returns the path to the currently open database file.
+    pub fn path(&self) -> &Path {
+        &self.0.path
+    }
+
+    /// is_read_only returns whether the database was opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.0.read_only
+    }
+
+    /// page_size returns the page size of the database, in bytes.
+    pub fn page_size(&self) -> usize {
+        self.0.page_size
+    }
+
+    /// opened returns whether the database is open, that is not closed yet.
+    pub fn opened(&self) -> bool {
+        self.0.opened.load(Ordering::Acquire)
+    }
+
+    /// info returns the page size of the database along with the contents of the
+    /// meta page currently in use.
+    pub fn info(&self) -> Result<Info> {
+        let meta = self.0.meta()?;
+        Ok(Info {
+            page_size: self.0.page_size(),
+            version: meta.version(),
+            flags: meta.flags(),
+            root: meta.root_bucket().root_page(),
+            freelist: meta.freelist(),
+            pgid: meta.pgid(),
+            txid: meta.txid(),
+            checksum: meta.checksum(),
+        })
+    }
+
+    /// lock_diagnostics lists the open transactions with their ages, to find
+    /// the one holding up writers or the reuse of pages.
+    pub fn lock_diagnostics(&self) -> LockDiagnostics {
+        self.0.lock_diagnostics()
+    }
+
+    /// freelist_report summarizes the free and pending pages of the database.
+    /// Returns FreePagesNotLoaded for read-only databases opened without PreLoadFreelist.
+    pub fn freelist_report(&self) -> Result<FreelistReport> {
+        Ok(self.0.freelist()?.report())
+    }
+
+    /// begin starts a new transaction. Multiple read-only transactions can be
+    /// used concurrently but only one write transaction can be used at a time.
+    /// Starting multiple write transactions will cause the calls to block and
+    /// be serialized until the current write transaction finishes.
+    ///
+    /// Unlike view and update, the transaction is not managed: it has to be
+    /// committed or rolled back, and is rolled back when dropped.
+    ///
+    /// IMPORTANT: You must close read-only transactions after you are finished
+    /// or else the database will not reclaim old pages.
+    ///
+    /// ```
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let db = boltdb_rs::DB::open(dir.path().join("db"), Default::default()).unwrap();
+    /// let tx = db.begin(true)?;
+    /// tx.create_bucket(b"widgets")?.put(b"foo", b"bar")?;
+    /// tx.commit()?;
+    /// # Ok::<(), boltdb_rs::BoltError>(())
+    /// ```
+    pub fn begin(&self, writable: bool) -> Result<Tx> {
+        if writable {
+            self.begin_rw()
+        } else {
+            self.begin_ro()
+        }
+    }
+
+    /// begin_ro starts a new read-only transaction, like begin(false).
+    pub fn begin_ro(&self) -> Result<Tx> {
+        // Lock the meta pages while we initialize the transaction. We obtain
+        // the meta lock before the mmap snapshot because that's the order
+        // that the write transaction will obtain them.
+        let _metalock = self.0.metalock.lock().unwrap();
+
+        // Exit if the database is not open yet.
+        if !self.0.opened.load(Ordering::Acquire) {
+            return Err(BoltError::DatabaseNotOpen);
+        }
+
+        // Create a transaction associated with the database.
+        let tx = Tx::new(self, false)?;
+
+        // Keep track of transaction until it closes.
+        let n = {
+            let mut txs = self.0.txs.lock().unwrap();
+            txs.push((tx.id(), Stopwatch::start()));
+            txs.len()
+        };
+
+        // Update the transaction stats.
+        let mut stats = self.0.stats.write().unwrap();
+        stats.tx_n += 1;
+        stats.open_tx_n = n;
+
+        Ok(tx)
+    }
+
+    /// begin_rw starts a new read-write transaction, like begin(true). Only
+    /// one read-write transaction is allowed at a time, others wait until it
+    /// closes.
+    pub fn begin_rw(&self) -> Result<Tx> {
+        // If the database was opened with Options.ReadOnly, return an error.
+        if self.0.is_read_only() {
+            return Err(BoltError::DatabaseReadOnly);
+        }
+
+        // Obtain writer lock. This is released by the transaction when it closes.
+        // This enforces only one writer transaction at a time.
+        self.0.lock_writer()?;
+
+        // Once we have the writer lock then we can lock the meta pages so that
+        // we can set up the transaction.
+        let _metalock = self.0.metalock.lock().unwrap();
+
+        // Exit if the database is not open yet.
+        if !self.0.opened.load(Ordering::Acquire) {
+            self.0.unlock_writer();
+            return Err(BoltError::DatabaseNotOpen);
+        }
+
+        // Nothing is written on top of meta pages that failed to sync.
+        if let Some(Err(err)) = self.0.group_commit().map(GroupCommit::poisoned) {
+            self.0.unlock_writer();
+            return Err(err);
+        }
+
+        // Create a transaction associated with the database. From here on the
+        // transaction owns the writer lock and releases it when it closes.
+        let tx = match Tx::new(self, true) {
+            Ok(tx) => tx,
+            Err(err) => {
+                self.0.unlock_writer();
+                return Err(err);
+            }
+        };
+
+        *self.0.writer.lock().unwrap() = Some((tx.id(), Stopwatch::start()));
+
+        // Free any pages associated with closed read-only transactions.
+        self.0.free_pages()?;
+
+        Ok(tx)
+    }
+
+    /// reload_freelist rebuilds the in-memory freelist from the last committed
+    /// state, keeping the pages that are still pending.
+    pub(crate) fn reload_freelist(&self) -> Result<()> {
+        let meta = self.0.meta()?;
+        if !meta.is_freelist_persisted() {
+            // Reconstruct free page list by scanning the DB to get the whole free page list.
+            // Note: scanning the whole db is heavy if your db size is large in NoSyncFreeList mode.
+            let ids = self.freepages()?;
+            self.0.freelist_mut()?.no_sync_reload(ids);
+        } else {
+            // Read free page list from freelist page.
+            let page = self.0.read_page(meta.freelist(), meta.pgid())?;
+            self.0.freelist_mut()?.reload(&page)?;
+        }
+        Ok(())
+    }
+
+    /// load_freelist reads the freelist if it is synced, or reconstructs it
+    /// by scanning the DB if it is not synced. It assumes there are no
+    /// concurrent accesses being made to the freelist.
+    pub(crate) fn load_freelist(&self) -> Result<()> {
+        let mut loaded = self.0.freelist_load.lock().unwrap();
+        if *loaded {
+            return Ok(());
+        }
+
+        let mut freelist = Freelist::new(self.0.freelist_type);
+        let meta = self.0.meta()?;
+        if !meta.is_freelist_persisted() {
+            // Reconstruct free list by scanning the DB.
+            self.0
+                .logger
+                .info("freelist is not persisted, scanning the db", &[]);
+            freelist.read_ids(self.freepages()?);
+        } else {
+            // Read free list from freelist page.
+            let page = self.0.read_page(meta.freelist(), meta.pgid())?;
+            freelist.read(&page)?;
+        }
+
+        *self.0.freelist.write().unwrap() = freelist;
+        *loaded = true;
+        drop(loaded);
+        self.0.update_freelist_stats();
+
+        Ok(())
+    }
+
+    /// rebuild_freelist drops the freelist and reconstructs it by scanning the
+    /// pages reachable from the root bucket, then commits it. Use it to recover
+    /// a database whose freelist leaks pages or lists pages that are in use.
+    ///
+    /// Pages freed by transactions that open readers may still be using stay
+    /// pending, so the rebuild is safe while read transactions are open.
+    pub fn rebuild_freelist(&self) -> Result<()> {
+        let tx = self.begin_rw()?;
+        let ids = match self.freepages() {
+            Ok(ids) => ids,
+            Err(err) => {
+                tx.rollback()?;
+                return Err(err);
+            }
+        };
+
+        // The scan already counts the pages of the stored freelist as free,
+        // they must not be freed again by the commit.
+        tx.abandon_freelist();
+        let free = ids.len();
+        self.0.freelist_mut()?.no_sync_reload(ids);
+        tx.commit()?;
+        self.0.logger.info("rebuilt freelist", &[("free", &free)]);
+        Ok(())
+    }
+
+    /// truncate_to_high_water_mark gives the space of the free pages at the end
+    /// of the data file back to the filesystem: they are dropped from the
+    /// freelist, lowering the high water mark, and the file is truncated
+    /// right after the last page in use. Returns the new size of the file.
+    ///
+    /// Only the pages free in the freelist are dropped, not those pending
+    /// for open readers. The page holding the freelist may be the last one,
+    /// it is moved by the commit and trimmed on the next call.
+    pub fn truncate_to_high_water_mark(&self) -> Result<u64> {
+        let tx = self.begin_rw()?;
+        let size = match self.truncate_free_tail(&tx) {
+            Ok(size) => size,
+            Err(err) => {
+                tx.rollback()?;
+                return Err(err);
+            }
+        };
+        tx.commit()?;
+        self.0
+            .logger
+            .info("truncated to high water mark", &[("size", &size)]);
+        Ok(size)
+    }
+
+    // truncate_free_tail drops the free pages at the end of the file from
+    // the freelist and truncates the file without them. Neither the previous
+    // meta page nor an open reader refers to a page that is free already, so
+    // the file is truncated before the commit.
+    fn truncate_free_tail(&self, tx: &Tx) -> Result<u64> {
+        let pgid = self.0.freelist_mut()?.trim(tx.meta().pgid());
+        tx.set_high_water_mark(pgid);
+        let size = pgid as usize * self.0.page_size();
+        self.0.shrink(size)?;
+        Ok(size as u64)
+    }
+
+    /// freepages returns the ids of every page below the high water mark
+    /// that is not reachable from the root bucket.
+    fn freepages(&self) -> Result<Vec<PgId>> {
+        let tx = self.begin_ro()?;
+        let mut reachable = vec![false; tx.meta().pgid() as usize];
+        let walked = tx.for_each_bucket_page(&mut |page: &Page| {
+            for id in page.id()..=page.id() + page.overflow() as PgId {
+                if let Some(seen) = reachable.get_mut(id as usize) {
+                    *seen = true;
+                }
+            }
+        });
+        tx.rollback()?;
+        walked?;
+
+        Ok((2..reachable.len() as PgId)
+            .filter(|&id| !reachable[id as usize])
+            .collect())
+    }
+}
+
+impl RawDB {
+    /// page_size returns the page size of the database.
+    pub(crate) fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// max_key_size returns the length limit of keys and bucket names.
+    pub(crate) fn max_key_size(&self) -> usize {
+        self.max_key_size
+    }
+
+    /// max_value_size returns the length limit of values.
+    pub(crate) fn max_value_size(&self) -> usize {
+        self.max_value_size
+    }
+
+    /// rebalance_percent returns the fill below which nodes are merged,
+    /// unless their bucket overrides it.
+    pub(crate) fn rebalance_percent(&self) -> f64 {
+        self.rebalance_percent
+    }
+
+    /// readahead returns the number of pages read ahead of sequential scans.
+    pub(crate) fn readahead(&self) -> usize {
+        self.readahead
+    }
+
+    /// file_size returns the current size of the data file in bytes.
+    pub(crate) fn file_size(&self) -> Result<usize> {
+        Ok(self.vfs.len()? as usize)
+    }
+
+    /// is_read_only reports whether the database was opened in read-only mode.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub(crate) fn no_sync(&self) -> bool {
+        self.no_sync
+    }
+
+    pub(crate) fn no_freelist_sync(&self) -> bool {
+        self.no_freelist_sync
+    }
+
+    pub(crate) fn strict_mode(&self) -> bool {
+        self.strict_mode
+    }
+
+    /// vfs returns the storage of the database.
+    pub(crate) fn vfs(&self) -> &dyn Vfs {
+        self.vfs.as_ref()
+    }
+
+    /// datasz returns the size of the current mmap.
+    pub(crate) fn datasz(&self) -> usize {
+        self.datasz.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn stats_mut(&self) -> RwLockWriteGuard<'_, Stats> {
+        self.stats.write().unwrap()
+    }
+
+    /// lock_writer blocks until no other read-write transaction is open.
+    /// Returns BoltError::WriterLockTimeout if the lock is not obtained
+    /// within the writer lock timeout.
+    fn lock_writer(&self) -> Result<()> {
+        let start = Stopwatch::start();
+        let mut locked = self.rwlock.lock().unwrap();
+        self.rwlock_waiting.fetch_add(1, Ordering::AcqRel);
+        while *locked {
+            let timeout = match self.rwlock_timeout {
+                Some(timeout) => timeout,
+                None => {
+                    locked = self.rwlock_cond.wait(locked).unwrap();
+                    continue;
+                }
+            };
+            let waited = start.elapsed();
+            if waited >= timeout {
+                self.rwlock_waiting.fetch_sub(1, Ordering::AcqRel);
+                drop(locked);
+                let diagnostics = self.lock_diagnostics();
+                self.logger.warn(
+                    "timed out waiting for the writer lock",
+                    &[
+                        ("path", &self.path.display()),
+                        ("waited", &format!("{:?}", waited)),
+                        ("transactions", &diagnostics),
+                    ],
+                );
+                return Err(BoltError::WriterLockTimeout {
+                    waited,
+                    diagnostics: Box::new(diagnostics),
+                });
+            }
+            locked = self
+                .rwlock_cond
+                .wait_timeout(locked, timeout - waited)
+                .unwrap()
+                .0;
+        }
+        self.rwlock_waiting.fetch_sub(1, Ordering::AcqRel);
+        *locked = true;
+        Ok(())
+    }
+
+    /// last_check returns the outcome of the last Tx::check.
+    pub(crate) fn last_check(&self) -> Option<LastCheck> {
+        self.last_check.lock().unwrap().clone()
+    }
+
+    /// record_check records the outcome of a Tx::check of txid.
+    pub(crate) fn record_check(&self, txid: Txid, errors: usize) {
+        *self.last_check.lock().unwrap() = Some(LastCheck {
+            at: SystemTime::now(),
+            txid,
+            errors,
+        });
+    }
+
+    /// lock_diagnostics lists the open transactions.
+    pub(crate) fn lock_diagnostics(&self) -> LockDiagnostics {
+        let info = |(id, age): &(Txid, Stopwatch)| TxInfo {
+            id: *id,
+            age: age.elapsed(),
+        };
+        let writer = self.writer.lock().unwrap().as_ref().map(info);
+        let mut readers: Vec<TxInfo> = self.txs.lock().unwrap().iter().map(info).collect();
+        readers.sort_unstable_by_key(|tx| tx.id);
+        LockDiagnostics { writer, readers }
+    }
+
+    /// writer_busy returns whether a writer holds or waits for the writer lock.
+    pub(crate) fn writer_busy(&self) -> bool {
+        *self.rwlock.lock().unwrap() || self.rwlock_waiting.load(Ordering::Acquire) > 0
+    }
+
+    /// batcher returns the batcher of DB::batch.
+    pub(crate) fn batcher(&self) -> &Batcher {
+        &self.batcher
+    }
+
+    /// background_sync returns the schedule of the background syncs, if
+    /// Options.sync_interval or Options.sync_every_commits is set.
+    pub(crate) fn background_sync(&self) -> Option<&BackgroundSync> {
+        self.background_sync.as_deref()
+    }
+
+    /// commit_timer returns the latency of the commits.
+    pub(crate) fn commit_timer(&self) -> &CommitTimer {
+        &self.commit_timer
+    }
+
+    /// write_limiter returns the limiter pacing the writes of dirty pages,
+    /// if Options.write_rate_limit is set.
+    pub(crate) fn write_limiter(&self) -> Option<&WriteLimiter> {
+        self.write_limiter.as_ref()
+    }
+
+    /// borrows returns the pages borrowed by cursors and nodes, which are
+    /// only tracked in debug builds.
+    pub(crate) fn borrows(&self) -> &Arc<PageBorrows> {
+        &self.borrows
+    }
+
+    /// group_commit returns the tracker of durable meta pages, when group
+    /// commit is enabled.
+    pub(crate) fn group_commit(&self) -> Option<&GroupCommit> {
+        self.group_commit.as_ref()
+    }
+
+    /// sync makes the pages written so far durable.
+    pub(crate) fn sync(&self) -> Result<()> {
+        match &self.group_commit {
+            Some(group) => group.sync(self.vfs.as_ref()),
+            None => self.sync_vfs(),
+        }
+    }
+
+    /// sync_vfs syncs the storage with the sync strategy of the database.
+    pub(crate) fn sync_vfs(&self) -> Result<()> {
+        self.vfs.sync_with(self.sync_strategy).op(IoOp::Sync)
+    }
+
+    /// sync_strategy returns how the storage is synced.
+    pub(crate) fn sync_strategy(&self) -> SyncStrategy {
+        self.sync_strategy
+    }
+
+    /// lock_meta locks the meta pages, so that no transaction begins while a
+    /// meta page is being written.
+    pub(crate) fn lock_meta(&self) -> MutexGuard<'_, ()> {
+        self.metalock.lock().unwrap()
+    }
+
+    /// unlock_writer releases the writer lock taken by begin_rw.
+    pub(crate) fn unlock_writer(&self) {
+        *self.writer.lock().unwrap() = None;
+        *self.rwlock.lock().unwrap() = false;
+        self.rwlock_cond.notify_one();
+    }
+
+    /// write_all_at writes a buffer to the data file at the given offset.
+    pub(crate) fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> Result<()> {
+        while !buf.is_empty() {
+            let n = self.vfs.write_at(buf, offset).op(IoOp::Write)?;
+            if n == 0 {
+                return Err(BoltError::io(IoOp::Write, io::ErrorKind::WriteZero.into()));
+            }
+            buf = &buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+
+    /// grow grows the size of the database to the given sz.
+    pub(crate) fn grow(&self, mut sz: usize) -> Result<()> {
+        // Ignore if the new size is less than available file sz.
+        if sz <= self.filesz.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        // If the data is smaller than the alloc size then only allocate what's needed.
+        // Once it goes over the allocation size then allocate in chunks.
+        let datasz = self.datasz();
+        if datasz <= DEFAULT_ALLOC_SIZE {
+            sz = datasz;
+        } else {
+            sz += DEFAULT_ALLOC_SIZE;
+        }
+
+        // Truncate and fsync to ensure file size metadata is flushed.
+        // https://github.com/boltdb/bolt/issues/284
+        if !self.no_grow_sync && !self.read_only {
+            self.vfs.truncate(sz as u64).op(IoOp::Resize)?;
+            self.sync_vfs()?;
+        }
+
+        self.filesz.store(sz, Ordering::Release);
+        Ok(())
+    }
+
+    /// shrink truncates the database to the given sz.
+    pub(crate) fn shrink(&self, sz: usize) -> Result<()> {
+        let pgid = (sz / self.page_size) as PgId;
+        self.borrows.check_truncate(pgid, "truncating the file");
+        self.vfs.truncate(sz as u64).op(IoOp::Resize)?;
+        self.sync_vfs()?;
+        self.filesz.store(sz, Ordering::Release);
+        Ok(())
+    }
+
+    /// free_pages releases any pages associated with closed read-only transactions.
+    fn free_pages(&self) -> Result<()> {
+        // Free all pending pages prior to the earliest open transaction. The
+        // newest durable meta page is kept readable like a transaction, while
+        // a newer one is not durable yet.
+        let mut txs: Vec<Txid> = self.txs.lock().unwrap().iter().map(|(id, _)| *id).collect();
+        txs.extend(self.group_commit.as_ref().and_then(GroupCommit::pinned));
+        txs.sort_unstable();
+
+        let mut freelist = self.freelist_mut()?;
+        let mut minid = txs.first().copied().unwrap_or(Txid::MAX);
+        if minid > 0 {
+            freelist.release(minid - 1);
+        }
+
+        // Release unused txid extents.
+        for txid in txs {
+            freelist.release_range(minid, txid.saturating_sub(1));
+            minid = txid + 1;
+        }
+        freelist.release_range(minid, Txid::MAX);
+
+        Ok(())
+    }
+
+    /// data returns the current mmap snapshot. Without mmap it only holds the
+    /// meta pages, which are read again each time since commits overwrite them.
+    pub(crate) fn data(&self) -> Result<Bytes> {
+        let data = self
+            .data
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or(BoltError::DatabaseNotOpen)?;
+        match &self.page_cache {
+            Some(cache) => cache.read_at(0, 2 * self.page_size),
+            None => Ok(data),
+        }
+    }
+
+    /// page retrieves a page reference from a mmap snapshot based on the current page size.
+    pub(crate) fn page_in<'a>(&self, data: &'a [u8], id: PgId) -> &'a Page {
+        let pos = id as usize * self.page_size;
+        Page::from_slice(&data[pos..])
+    }
+
+    /// read_page returns the page with a given id along with its overflow
+    /// pages, decrypted if the database is encrypted. high is the high water
+    /// mark of the meta the page is read for.
+    pub(crate) fn read_page(&self, id: PgId, high: PgId) -> Result<Bytes> {
+        if let Some(cache) = &self.page_cache {
+            return cache.read(id, high).map(|(page, _)| page);
+        }
+        let data = self.data()?;
+        let pos = id as usize * self.page_size;
+        if pos + PAGE_HEADER_SIZE > data.len() {
+            return Err(BoltError::corrupted("page lies beyond the end of the file").with_pgid(id));
+        }
+        if !self.crypt.is_enabled() {
+            return Ok(data.slice(pos..));
+        }
+        let overflow = Page::try_from_slice(data.get(pos..).unwrap_or_default())?.overflow();
+        let end = pos + (overflow as usize + 1) * self.page_size;
+        if end > data.len() {
+            return Err(BoltError::Invalid);
+        }
+        self.crypt.open(data.slice(pos..end))
+    }
+
+    /// logger returns the logger of the database.
+    pub(crate) fn logger(&self) -> &dyn Logger {
+        self.logger.as_ref()
+    }
+
+    /// page_pool returns the pool recycling the page buffers of write
+    /// transactions.
+    pub(crate) fn page_pool(&self) -> &Arc<PagePool> {
+        &self.page_pool
+    }
+
+    /// stats_cache returns the cache of bucket stats, if enabled.
+    pub(crate) fn stats_cache(&self) -> Option<&StatsCache> {
+        self.stats_cache.as_ref()
+    }
+
+    /// page_cache returns the cache pages are read through, if the database
+    /// is not memory-mapped.
+    pub(crate) fn page_cache(&self) -> Option<&Arc<PageCache>> {
+        self.page_cache.as_ref()
+    }
+
+    /// decrypted returns the cache of the pages decrypted out of the mmap, if
+    /// the database is encrypted and memory-mapped.
+    pub(crate) fn decrypted(&self) -> Option<&Arc<DecryptedPages>> {
+        self.decrypted.as_ref()
+    }
+
+    /// crypt returns the encryption of the pages of the database.
+    pub(crate) fn crypt(&self) -> &PageCrypt {
+        &self.crypt
+    }
+
+    /// freelist returns the loaded freelist, or FreePagesNotLoaded when a
+    /// read-only database was opened without pre_load_freelist.
+    pub(crate) fn freelist(&self) -> Result<RwLockReadGuard<'_, Freelist>> {
+        if !*self.freelist_load.lock().unwrap() {
+            return Err(BoltError::FreePagesNotLoaded);
+        }
+        Ok(self.freelist.read().unwrap())
+    }
+
+    /// freelist_mut returns the loaded freelist for modification.
+    pub(crate) fn freelist_mut(&self) -> Result<RwLockWriteGuard<'_, Freelist>> {
+        if !*self.freelist_load.lock().unwrap() {
+            return Err(BoltError::FreePagesNotLoaded);
+        }
+        Ok(self.freelist.write().unwrap())
+    }
+
+    /// update_freelist_stats refreshes the freelist figures in the database stats.
+    pub(crate) fn update_freelist_stats(&self) {
+        let freelist = match self.freelist() {
+            Ok(freelist) => freelist,
+            Err(_) => return,
+        };
+        let report = freelist.report();
+
+        let mut stats = self.stats.write().unwrap();
+        stats.free_page_n = report.free_count;
+        stats.pending_page_n = report.pending_count;
+        stats.free_alloc = (report.free_count + report.pending_count) * self.page_size;
+        stats.freelist_inuse = freelist.size();
+        stats.free_span_max = report.largest_span;
+        stats.free_fragmentation = report.fragmentation;
+    }
+
+    /// meta retrieves the current meta page reference.
+    pub(crate) fn meta(&self) -> Result<Meta> {
+        self.meta_with_error().map(|(meta, _)| meta)
+    }
+
+    /// meta_with_error returns the current meta, along with the id and error
+    /// of the other meta page if it is unusable.
+    pub(crate) fn meta_with_error(&self) -> Result<(Meta, Option<(PgId, BoltError)>)> {
+        let data = self.data()?;
+        let len = match self.page_cache {
+            Some(_) => self.datasz(),
+            None => data.len(),
+        };
+        self.select_meta(&data, len)
+    }
+
+    /// select_meta returns the meta to use out of the two meta pages of a mmap
+    /// snapshot of len bytes, along with the id and error of the other meta page if it is
+    /// unusable. Returns the error of meta page 0 if both are unusable.
+    ///
+    /// We have to use the meta with the highest txid which doesn't fail
+    /// validation. Otherwise, we can cause errors when in fact the database is
+    /// in a consistent state. A meta page that fails validation was not saved
+    /// properly, but we can recover using the other one.
+    ///
+    /// If IgnoreChecksum is set and neither is valid, meta pages failing only
+    /// their checksum are used, which marks the database as degraded.
+    fn select_meta(&self, data: &[u8], len: usize) -> Result<(Meta, Option<(PgId, BoltError)>)> {
+        match self.select_meta_checked(data, len, false) {
+            Err(_) if self.ignore_checksum => {
+                let selected = self.select_meta_checked(data, len, true)?;
+                if !self.degraded.swap(true, Ordering::AcqRel) {
+                    self.logger.warn(
+                        "ignoring meta page checksums, the database is degraded",
+                        &[("path", &self.path.display())],
+                    );
+                }
+                Ok(selected)
+            }
+            selected => selected,
+        }
+    }
+
+    // select_meta_checked is select_meta, optionally accepting meta pages with
+    // a checksum mismatch.
+    fn select_meta_checked(
+        &self,
+        data: &[u8],
+        len: usize,
+        ignore_checksum: bool,
+    ) -> Result<(Meta, Option<(PgId, BoltError)>)> {
+        let check = |id: PgId| {
+            let meta = self.page_in(data, id).meta();
+            match meta.validate() {
+                Err(BoltError::Checksum) if ignore_checksum => Ok(()),
+                result => result,
+            }
+            .and_then(|()| meta.validate_layout(self.page_size, len))
+            .map(|()| meta.clone())
+        };
+        let (meta0, meta1) = (check(0), check(1));
+
+        // meta_a is the one with the higher txid, only trusted if it is valid.
+        let ((a, meta_a), (b, meta_b)) = match (&meta0, &meta1) {
+            (Ok(m0), Ok(m1)) if m1.txid() > m0.txid() => ((1, meta1), (0, meta0)),
+            _ => ((0, meta0), (1, meta1)),
+        };
+        match (meta_a, meta_b) {
+            (Ok(meta), Ok(_)) => Ok((meta, None)),
+            (Ok(meta), Err(err)) => Ok((meta, Some((b, err)))),
+            (Err(err), Ok(meta)) => Ok((meta, Some((a, err)))),
+            (Err(err), Err(_)) => Err(err),
+        }
+    }
+
+    /// mmap opens the underlying memory-mapped file and initializes the meta references.
+    /// minsz is the minimum size that the new mmap can be.
+    pub(crate) fn mmap(&self, minsz: usize) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+
+        let filesz = self.file_size()?;
+        if filesz < self.page_size * 2 {
+            return Err(BoltError::Invalid);
+        }
+
+        // Ensure the size is at least the minimum size.
+        let size = self.mmap_size(filesz.max(minsz))?;
+
+        // Memory-map the data file as a byte slice. Without mmap only the
+        // meta pages are read, and the size bounds the pages read.
+        let mmap = match &self.page_cache {
+            Some(cache) => cache.read_at(0, 2 * self.page_size)?,
+            None => {
+                let pgid = (size / self.page_size) as PgId;
+                self.borrows.check_truncate(pgid, "remapping the file");
+                self.vfs.map_with(size, self.map_options).op(IoOp::Mmap)?
+            }
+        };
+
+        // Validate the meta pages. We only return an error if both meta pages fail
+        // validation, since meta0 failing validation means that it wasn't saved
+        // properly -- but we can recover using meta1. And vice-versa.
+        let (_, invalid) = self.select_meta(&mmap, size)?;
+        if let Some((page, err)) = invalid {
+            self.logger.warn(
+                "meta page is invalid, using the other one",
+                &[("page", &page), ("error", &err)],
+            );
+        }
+        self.logger
+            .debug("mmap", &[("size", &size), ("file_size", &filesz)]);
+
+        // Save the new snapshot. Open transactions keep the previous one alive.
+        *data = Some(mmap);
+        self.datasz.store(size, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// mmap_size determines the appropriate size for the mmap given the current size
+    /// of the database. The minimum size is 32KB and doubles until it reaches 1GB.
+    /// Returns an error if the new mmap size is greater than the max allowed.
+    fn mmap_size(&self, size: usize) -> Result<usize> {
+        // Double the size from 32KB until 1GB.
+        for i in 15..=30 {
+            if size <= 1 << i {
+                return Ok(1 << i);
+            }
+        }
+
+        // Verify the requested size is not above the maximum allowed. Without
+        // mmap the size only sets how the file grows, so there is no maximum.
+        let max = match self.page_cache {
+            Some(_) => u64::MAX,
+            None => MAX_MAP_SIZE,
+        };
+        if size as u64 > max {
+            return Err(BoltError::Unexpected("mmap too large"));
+        }
+
+        // If larger than 1GB then grow by 1GB at a time.
+        let mut sz = size;
+        let remainder = sz % MAX_MMAP_STEP;
+        if remainder > 0 {
+            sz += MAX_MMAP_STEP - remainder;
+        }
+
+        // Ensure that the mmap size is a multiple of the page size.
+        // This should always be true since we're incrementing in MBs.
+        if !sz.is_multiple_of(self.page_size) {
+            sz = (sz / self.page_size + 1) * self.page_size;
+        }
+
+        // If we've exceeded the max size then only grow up to the max size.
+        Ok(sz.min(max as usize))
+    }
+
+    fn close(&self) -> Result<()> {
+        if !self.opened.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // Sync the commits the background thread has not synced yet.
+        if let Some(sync) = &self.background_sync {
+            if sync.stop() {
+                self.sync()?;
+            }
+        }
+
+        // Close the mmap. Open transactions still hold their own snapshot.
+        *self.data.write().unwrap() = None;
+        self.datasz.store(0, Ordering::Release);
+
+        // Unlock the file. The descriptor stays open until the last handle is
+        // dropped, so the shared lock of a read-only file is released here too.
+        self.vfs.unlock()?;
+
+        Ok(())
+    }
+
+    /// remove_tx removes a closed read-only transaction from the open list.
+    pub(crate) fn remove_tx(&self, txid: Txid, tx_stats: &TxStats) {
+        // Remove the transaction.
+        let n = {
+            let mut txs = self.txs.lock().unwrap();
+            if let Some(index) = txs.iter().position(|(id, _)| *id == txid) {
+                txs.swap_remove(index);
+            }
+            txs.len()
+        };
+
+        // Merge statistics.
+        let mut stats = self.stats.write().unwrap();
+        stats.open_tx_n = n;
+        stats.tx_stats.add(tx_stats);
+    }
+}
+
+impl Drop for RawDB {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+// limit returns the size limit set by an option, which defaults to and may not
+// exceed the limit of the file format.
+fn limit(name: &str, size: usize, max: usize) -> Result<usize> {
+    match size {
+        0 => Ok(max),
+        size if size <= max => Ok(size),
+        size => Err(BoltError::Config(format!(
+            "{} of {} exceeds the limit of {}",
+            name, size, max
+        ))),
+    }
+}
+
+/// init creates a new database file and initializes its meta pages, with
+/// format version 3 if it uses any format extension.
+/// Returns the size of the initialized file.
+fn init(
+    vfs: &dyn Vfs,
+    crypt: &PageCrypt,
+    page_size: usize,
+    features: u32,
+    strategy: SyncStrategy,
+) -> Result<usize> {
+    // Create two meta pages on a buffer.
+    let mut buf = OwnedPage::new(page_size * 4);
+    let buf = buf.buf_mut();
+    for i in 0..2 {
+        let page = page_in_buffer(buf, page_size, i);
+        page.set_id(i);
+        page.set_flags(PageFlags::META_PAGE);
+
+        // Initialize the meta page.
+        let mut meta = page.meta();
+        meta.set_magic(MAGIC);
+        match features {
+            0 => meta.set_version(VERSION),
+            _ => meta.set_version(VERSION_FEATURES),
+        }
+        meta.set_flags(features);
+        meta.set_page_size(page_size as u32);
+        meta.set_freelist(2);
+        meta.set_root_bucket(InBucket::new(3, 0));
+        meta.set_pgid(4);
+        meta.set_txid(i);
+        meta.set_checksum(meta.sum64());
+        page.set_meta(&meta);
+        crypt.write_verifier(&mut buf[i as usize * page_size..])?;
+    }
+
+    // Write an empty freelist at page 3.
+    let page = page_in_buffer(buf, page_size, 2);
+    page.set_id(2);
+    page.set_flags(PageFlags::FREELIST_PAGE);
+    page.set_count(0);
+
+    // Write an empty leaf page at page 4.
+    let page = page_in_buffer(buf, page_size, 3);
+    page.set_id(3);
+    page.set_flags(PageFlags::LEAF_PAGE);
+    page.set_count(0);
+
+    // Encrypt the freelist and leaf pages.
+    for i in 2..4 {
+        crypt.seal(&mut buf[i * page_size..(i + 1) * page_size])?;
+    }
+
+    // Write the buffer to our data file.
+    vfs.write_at(buf, 0).op(IoOp::Write)?;
+    vfs.sync_with(strategy).op(IoOp::Sync)?;
+
+    Ok(buf.len())
+}
+
+/// page_in_buffer retrieves a page reference from a given byte array based on the current page size.
+fn page_in_buffer(buf: &mut [u8], page_size: usize, id: PgId) -> &mut Page {
+    Page::from_slice_mut(&mut buf[id as usize * page_size..])
+}
+
+/// get_page_size reads the page size from the meta pages. It tries
+/// to read the first meta page firstly. If the first page is invalid,
+/// then it tries to read the second page using the default page size.
+pub(crate) fn get_page_size(vfs: &dyn Vfs, filesz: usize) -> Result<usize> {
+    // Check the first page size
+    let meta = read_meta_at(vfs, 0)?;
+    let first_err = match meta.validate() {
+        Ok(()) => return Ok(meta.page_size() as usize),
+        Err(err) => err,
+    };
+
+    // Check the second page size, trying every supported page size candidate
+    // since the first meta page could not tell us where it lives.
+    for i in 0..=14 {
+        let pos = 1024usize << i;
+        if pos >= filesz.saturating_sub(1024) {
+            break;
+        }
+        let meta = read_meta_at(vfs, pos as u64)?;
+        if meta.validate().is_ok() {
+            return Ok(meta.page_size() as usize);
+        }
+    }
+
+    Err(match first_err {
+        BoltError::Checksum | BoltError::VersionMismatch => first_err,
+        _ => BoltError::Invalid,
+    })
+}
+
+/// read_meta_at reads a meta page header from the storage at the given offset.
+pub(crate) fn read_meta_at(vfs: &dyn Vfs, offset: u64) -> Result<Meta> {
+    let mut buf = [0u8; 0x1000];
+    let n = vfs.read_at(&mut buf, offset).op(IoOp::Read)?;
+    Meta::try_from_page(&buf[..n])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_check::CheckErrorKind;
+    use std::path::PathBuf;
+
+    fn temp_path() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        (dir, path)
+    }
+
+    #[test]
+    fn test_open() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+
+        let meta = db.0.meta().unwrap();
+        assert_eq!(meta.pgid(), 4);
+        assert_eq!(meta.freelist(), 2);
+        assert_eq!(meta.root_bucket().root_page(), 3);
+        assert_eq!(db.0.page_size(), *DEFAULT_PAGE_SIZE);
+        db.close().unwrap();
+
+        // Reopen the file and read the page size back from the meta page.
+        let db = DB::open(
+            &path,
+            Options {
+                page_size: 1024,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.0.page_size(), *DEFAULT_PAGE_SIZE);
+    }
+
+    // Ensure that the phases of opening a database are reported as they end
+    // and recorded in the stats.
+    #[test]
+    fn test_open_progress() {
+        let (_dir, path) = temp_path();
+        let open = |check_torn_writes: bool| {
+            let reported = Arc::new(Mutex::new(Vec::new()));
+            let progress = reported.clone();
+            let options = Options {
+                check_torn_writes,
+                open_progress: Some(OpenProgress::new(move |phase, _| {
+                    progress.lock().unwrap().push(phase)
+                })),
+                ..Default::default()
+            };
+            let db = DB::open(&path, options).unwrap();
+            let stats = db.stats();
+            let phases: Vec<_> = stats.open_phases.iter().map(|(phase, _)| *phase).collect();
+            assert_eq!(phases, *reported.lock().unwrap());
+            let total = stats.open_phases.iter().map(|(_, elapsed)| *elapsed).sum();
+            assert!(stats.open_time >= total);
+            phases
+        };
+
+        use OpenPhase::*;
+        assert_eq!(open(false), vec![Lock, Init, Mmap, LoadFreelist]);
+        assert_eq!(open(true), vec![Lock, Mmap, CheckTornWrites, LoadFreelist]);
+    }
+
+    #[test]
+    fn test_open_invalid_file() {
+        let (_dir, path) = temp_path();
+        std::fs::write(&path, vec![0xAB; 4 * 4096]).unwrap();
+        assert_eq!(
+            DB::open(&path, Options::default()).err(),
+            Some(BoltError::Invalid)
+        );
+    }
+
+    // Ensure that a meta page with a valid checksum but pointing outside of the
+    // data pages is skipped for the other one, and that open fails once both
+    // meta pages are unusable.
+    #[test]
+    fn test_open_meta_failover() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        for value in [b"1", b"2"] {
+            db.update(|tx| {
+                tx.create_bucket_if_not_exists(b"widgets")?
+                    .put(b"foo", value)
+            })
+            .unwrap();
+        }
+        let page_size = db.0.page_size();
+        db.close().unwrap();
+
+        let corrupt = |id: PgId| {
+            let mut buf = OwnedPage::from_vec(std::fs::read(&path).unwrap());
+            let page = Page::from_slice_mut(&mut buf.buf_mut()[id as usize * page_size..]);
+            let mut meta = page.meta();
+            meta.set_root_bucket(InBucket::new(meta.pgid() + 10, 0));
+            let checksum = meta.sum64();
+            meta.set_checksum(checksum);
+            page.set_meta(&meta);
+            std::fs::write(&path, buf.buf()).unwrap();
+        };
+        let txid = |id: PgId| {
+            let buf = OwnedPage::from_vec(std::fs::read(&path).unwrap());
+            Page::from_slice(&buf.buf()[id as usize * page_size..])
+                .meta()
+                .txid()
+        };
+        let (newer, older) = if txid(1) > txid(0) { (1, 0) } else { (0, 1) };
+
+        corrupt(newer);
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.0.meta().unwrap().txid(), txid(older));
+        db.view(|tx| {
+            assert_eq!(tx.bucket(b"widgets").unwrap().get(b"foo").unwrap(), b"1");
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+
+        corrupt(older);
+        let err = DB::open(&path, Options::default()).err().unwrap();
+        assert!(matches!(err, BoltError::Corrupted { .. }), "{}", err);
+    }
+
+    // Ensure that a database whose meta pages fail their checksums can only
+    // be opened for recovery, read-only and marked as degraded.
+    #[test]
+    fn test_open_ignore_checksum() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+        let page_size = db.0.page_size();
+        assert!(!db.stats().degraded);
+        db.close().unwrap();
+
+        let mut buf = OwnedPage::from_vec(std::fs::read(&path).unwrap());
+        for id in 0..2 {
+            let page = Page::from_slice_mut(&mut buf.buf_mut()[id * page_size..]);
+            let mut meta = page.meta();
+            let checksum = meta.sum64();
+            meta.set_checksum(checksum ^ 1);
+            page.set_meta(&meta);
+        }
+        std::fs::write(&path, buf.buf()).unwrap();
+
+        assert!(DB::open(&path, Options::default()).is_err());
+        let options = Options {
+            ignore_checksum: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            DB::open(&path, options).err(),
+            Some(BoltError::Config(_))
+        ));
+
+        let db = DB::open(&path, Options::read_only_recovery()).unwrap();
+        assert!(db.stats().degraded);
+        db.view(|tx| {
+            assert_eq!(tx.bucket(b"widgets").unwrap().get(b"foo").unwrap(), b"bar");
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    // Ensure that a database with branch prefix compression stores fewer
+    // branch bytes for keys sharing a long prefix and reads back the same.
+    #[test]
+    fn test_open_branch_prefix_compression() {
+        let key = |i: u32| format!("tenants/0000000042/users/{:08}", i).into_bytes();
+        let fill = |compress: bool| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("db");
+            let options = Options {
+                branch_prefix_compression: compress,
+                ..Default::default()
+            };
+            let db = DB::open(&path, options).unwrap();
+            db.update(|tx| {
+                let b = tx.create_bucket(b"widgets")?;
+                for i in 0..50000 {
+                    b.put(&key(i), b"v")?;
+                }
+                Ok(())
+            })
+            .unwrap();
+            db.update(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                for i in (0..50000).step_by(3) {
+                    b.delete(&key(i))?;
+                }
+                Ok(())
+            })
+            .unwrap();
+            let version = db.info().unwrap().version;
+            db.close().unwrap();
+
+            // Open the database again with default options.
+            let db = DB::open(&path, Options::default()).unwrap();
+            let stats = db
+                .view(|tx| {
+                    let b = tx.bucket(b"widgets").unwrap();
+                    assert_eq!(b.get(&key(1)), Some(&b"v"[..]));
+                    assert_eq!(b.get(&key(3)), None);
+                    let mut c = b.cursor();
+                    assert_eq!(c.seek(&key(30000)).unwrap().0, &key(30001)[..]);
+                    assert_eq!(c.seek(b"tenants/0000000043").map(|(k, _)| k), None);
+                    assert_eq!(c.seek(b"a").unwrap().0, &key(1)[..]);
+                    assert_eq!(tx.check()?.errors, vec![]);
+                    b.stats()
+                })
+                .unwrap();
+            db.close().unwrap();
+            (version, stats.branch_inuse)
+        };
+
+        let (version, plain) = fill(false);
+        assert_eq!(version, VERSION);
+        let (version, compressed) = fill(true);
+        assert_eq!(version, VERSION_FEATURES);
+        assert!(compressed * 2 < plain, "{} >= {} / 2", compressed, plain);
+    }
+
+    // Ensure that a database keeps the checksum algorithm it was created with
+    // and rejects meta pages that do not match it.
+    #[test]
+    fn test_open_checksum_type() {
+        let types = [
+            ChecksumType::Fnv,
+            #[cfg(feature = "crc32c")]
+            ChecksumType::Crc32c,
+            #[cfg(feature = "xxhash")]
+            ChecksumType::Xxh3,
+        ];
+        for typ in types {
+            let (_dir, path) = temp_path();
+            let options = Options {
+                checksum: typ,
+                ..Default::default()
+            };
+            let db = DB::open(&path, options).unwrap();
+            db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+                .unwrap();
+            let page_size = db.0.page_size();
+            db.close().unwrap();
+
+            let db = DB::open(&path, Options::default()).unwrap();
+            let info = db.info().unwrap();
+            let want = if typ == ChecksumType::Fnv {
+                VERSION
+            } else {
+                VERSION_FEATURES
+            };
+            assert_eq!(info.version, want);
+            let meta = db.0.meta().unwrap();
+            assert_eq!(meta.checksum_type(), Some(typ));
+            assert_eq!(
+                info.checksum,
+                typ.checksum().sum64(meta.as_slice_no_checksum())
+            );
+            db.close().unwrap();
+
+            // Checksums of the other algorithms are rejected.
+            let mut buf = OwnedPage::from_vec(std::fs::read(&path).unwrap());
+            for id in 0..2 {
+                let page = Page::from_slice_mut(&mut buf.buf_mut()[id * page_size..]);
+                let mut meta = page.meta();
+                let sum = ChecksumType::Fnv
+                    .checksum()
+                    .sum64(meta.as_slice_no_checksum());
+                meta.set_checksum(sum ^ (typ == ChecksumType::Fnv) as u64);
+                page.set_meta(&meta);
+            }
+            std::fs::write(&path, buf.buf()).unwrap();
+            assert!(DB::open(&path, Options::default()).is_err());
+        }
+    }
+
+    // Ensure that io errors keep the operation and the error of the system.
+    #[test]
+    fn test_open_io_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = DB::open(dir.path(), Options::default()).err().unwrap();
+        match &err {
+            BoltError::Io { op, source, .. } => {
+                assert_eq!(*op, IoOp::Open);
+                assert!(source.get_ref().raw_os_error().is_some());
+                #[cfg(unix)]
+                assert_eq!(source.get_ref().raw_os_error(), Some(libc::EISDIR));
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(source.downcast_ref::<crate::errors::IoError>().is_some());
+        assert!(err.to_string().starts_with("open failed: "));
+    }
+
+    #[test]
+    fn test_open_read_only_skips_freelist() {
+        let (_dir, path) = temp_path();
+        DB::open(&path, Options::default())
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let db = DB::open(
+            &path,
+            Options {
+                read_only: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(db.0.is_read_only());
+        assert_eq!(db.0.freelist().err(), Some(BoltError::FreePagesNotLoaded));
+        db.view(|tx| {
+            assert_eq!(tx.page_info(2).err(), Some(BoltError::FreePagesNotLoaded));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that read-only opens share the lock, leave the file untouched
+    // and keep writers out. Locks taken through separate file descriptors
+    // conflict as locks of separate processes do.
+    #[test]
+    fn test_open_read_only_shared() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+        db.close().unwrap();
+        let before = std::fs::read(&path).unwrap();
+
+        let read_only = Options {
+            read_only: true,
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let a = DB::open(&path, read_only.clone()).unwrap();
+        let b = DB::open(&path, read_only).unwrap();
+        for db in [&a, &b] {
+            db.view(|tx| {
+                assert_eq!(
+                    tx.bucket(b"widgets").unwrap().get(b"foo"),
+                    Some(&b"bar"[..])
+                );
+                Ok(())
+            })
+            .unwrap();
+        }
+        let writer = Options {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        assert_eq!(DB::open(&path, writer).err(), Some(BoltError::Timeout));
+        a.close().unwrap();
+        b.close().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+    }
+
+    // Ensure that read-only opens wait for a writer to close the database,
+    // and give up after the timeout.
+    #[test]
+    fn test_open_read_only_while_writing() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        let read_only = Options {
+            read_only: true,
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            DB::open(&path, read_only.clone()).err(),
+            Some(BoltError::Timeout)
+        );
+
+        let closer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            db.close().unwrap();
+        });
+        let read_only = Options {
+            timeout: Duration::from_secs(5),
+            ..read_only
+        };
+        let reader = DB::open(&path, read_only).unwrap();
+        closer.join().unwrap();
+        assert!(reader.is_read_only());
+    }
+
+    #[test]
+    fn test_open_read_only_pre_load_freelist() {
+        let (_dir, path) = temp_path();
+        DB::open(&path, Options::default())
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let db = DB::open(
+            &path,
+            Options {
+                read_only: true,
+                pre_load_freelist: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.0.freelist().unwrap().count(), 0);
+        db.view(|tx| {
+            let info = tx.page_info(2)?.unwrap();
+            assert_eq!(info.typ(), "freelist");
+            assert!(tx.page_info(4)?.is_none());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_freelist_stats() {
+        let (_dir, path) = temp_path();
+        DB::open(&path, Options::default())
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let db = DB::open(
+            &path,
+            Options {
+                read_only: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            db.freelist_report().err(),
+            Some(BoltError::FreePagesNotLoaded)
+        );
+        db.close().unwrap();
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        let report = db.freelist_report().unwrap();
+        assert_eq!(report.free_count, 0);
+        assert_eq!(report.largest_span, 0);
+
+        let stats = db.stats();
+        assert_eq!(stats.free_page_n, 0);
+        assert_eq!(stats.freelist_inuse, PAGE_HEADER_SIZE);
+        assert_eq!(stats.free_fragmentation, 0.0);
+    }
+
+    #[test]
+    fn test_view_tracks_open_transactions() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.view(|_| {
+            assert_eq!(db.stats().open_tx_n, 1);
+            Ok(())
+        })
+        .unwrap();
+
+        let stats = db.stats();
+        assert_eq!(stats.tx_n, 1);
+        assert_eq!(stats.open_tx_n, 0);
+    }
+
+    // Ensure that unmanaged transactions register as readers and writers, and
+    // that a reader keeps its snapshot while a writer commits.
+    #[test]
+    fn test_begin() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        let tx = db.begin(true).unwrap();
+        assert!(tx.writable());
+        tx.create_bucket(b"widgets")
+            .unwrap()
+            .put(b"foo", b"bar")
+            .unwrap();
+        tx.commit().unwrap();
+
+        let reader = db.begin(false).unwrap();
+        assert!(!reader.writable());
+        assert_eq!(db.stats().open_tx_n, 1);
+        let writer = db.begin_rw().unwrap();
+        writer
+            .bucket(b"widgets")
+            .unwrap()
+            .put(b"foo", b"baz")
+            .unwrap();
+        writer.commit().unwrap();
+        assert_eq!(
+            reader.bucket(b"widgets").unwrap().get(b"foo"),
+            Some(&b"bar"[..])
+        );
+        reader.rollback().unwrap();
+        assert_eq!(db.stats().open_tx_n, 0);
+
+        let tx = db.begin_ro().unwrap();
+        assert_eq!(
+            tx.bucket(b"widgets").unwrap().get(b"foo"),
+            Some(&b"baz"[..])
+        );
+    }
+
+    // Ensure that a writer blocked for longer than the timeout gives up with
+    // the open transactions.
+    #[test]
+    fn test_writer_lock_timeout() {
+        let (_dir, path) = temp_path();
+        let options = Options {
+            writer_lock_timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let db = DB::open(&path, options).unwrap();
+        let reader = db.begin_ro().unwrap();
+        let writer = db.begin_rw().unwrap();
+
+        let blocked = db.clone();
+        let err = std::thread::spawn(move || blocked.begin_rw().map(drop))
+            .join()
+            .unwrap()
+            .unwrap_err();
+        match err {
+            BoltError::WriterLockTimeout {
+                waited,
+                diagnostics,
+            } => {
+                assert!(waited >= Duration::from_millis(20));
+                assert_eq!(diagnostics.writer.map(|tx| tx.id), Some(writer.id()));
+                let readers: Vec<u64> = diagnostics.readers.iter().map(|tx| tx.id).collect();
+                assert_eq!(readers, vec![reader.id()]);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        writer.rollback().unwrap();
+        reader.rollback().unwrap();
+        assert_eq!(db.lock_diagnostics(), LockDiagnostics::default());
+        db.begin_rw().unwrap().rollback().unwrap();
+    }
+
+    // Ensure that rebuilding the freelist recovers leaked pages and keeps the
+    // pages an open reader may still use pending.
+    #[test]
+    fn test_rebuild_freelist() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let root = db
+            .view(|tx| Ok(tx.bucket(b"widgets").unwrap().root()))
+            .unwrap();
+        db.close().unwrap();
+
+        // Clearing the branch page leaks every leaf below it.
+        assert!(crate::surgeon::clear_page(&path, root).unwrap());
+        let db = DB::open(&path, Options::default()).unwrap();
+        let report = db.view(|tx| tx.check()).unwrap();
+        assert!(!report.is_ok());
+        assert!(report
+            .errors
+            .iter()
+            .all(|e| e.kind == CheckErrorKind::Orphan));
+
+        // Free pages while a reader holds on to them.
+        db.update(|tx| tx.create_bucket(b"woojits").map(|_| ()))
+            .unwrap();
+        let reader = db.begin_ro().unwrap();
+        db.update(|tx| tx.delete_bucket(b"woojits")).unwrap();
+        let pending = db.0.freelist().unwrap().pending_count();
+        assert!(pending > 0);
+
+        db.rebuild_freelist().unwrap();
+        assert!(db.0.freelist().unwrap().pending_count() >= pending);
+        reader.rollback().unwrap();
+
+        assert_eq!(db.view(|tx| tx.check()).unwrap().errors, vec![]);
+        db.close().unwrap();
+
+        // The rebuilt freelist was committed.
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.view(|tx| tx.check()).unwrap().errors, vec![]);
+        db.close().unwrap();
+    }
+
+    // Ensure that the free pages at the end of the file are given back and
+    // the data stays intact.
+    #[test]
+    fn test_truncate_to_high_water_mark() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        for name in [&b"widgets"[..], b"woojits"] {
+            db.update(|tx| {
+                let b = tx.create_bucket(name)?;
+                for i in 0..2000u32 {
+                    b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+        db.update(|tx| tx.delete_bucket(b"woojits")).unwrap();
+        db.update(|_| Ok(())).unwrap();
+        let before = std::fs::metadata(&path).unwrap().len();
+
+        let size = db.truncate_to_high_water_mark().unwrap();
+        assert!(size < before / 2, "{} of {}", size, before);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+        assert_eq!(db.view(|tx| tx.check()).unwrap().errors, vec![]);
+
+        // The file grows again for new pages.
+        db.update(|tx| tx.bucket(b"widgets").unwrap().put(b"foo", &[1; 10000]))
+            .unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"00001999"), Some(&[0x5a; 100][..]));
+            assert_eq!(b.get(b"foo"), Some(&[1; 10000][..]));
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    // Ensure that the accessors and formatting describe the database.
+    #[test]
+    fn test_db_accessors() {
+        let (_dir, path) = temp_path();
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.path(), path);
+        assert!(!db.is_read_only());
+        assert_eq!(db.page_size(), *DEFAULT_PAGE_SIZE);
+        assert!(db.opened());
+        assert_eq!(db.to_string(), format!("DB<{:?}>", path));
+        assert!(format!("{:?}", db).contains("opened: true"));
+        db.close().unwrap();
+        assert!(!db.opened());
+        assert!(format!("{:?}", db).contains("txid: None"));
+
+        let options = Options {
+            read_only: true,
+            ..Default::default()
+        };
+        let db = DB::open(&path, options).unwrap();
+        assert!(db.is_read_only());
+    }
+}
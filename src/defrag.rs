@@ -0,0 +1,291 @@
+//! Online defragmentation.
+//!
+//! Deleting keys leaves pages sparsely filled and scattered among free pages,
+//! since a page is only rewritten when it changes. DB::defragment rewrites the
+//! pages that are out of place in ordinary write transactions: spilling a
+//! node allocates the lowest free pages, so rewritten pages move towards the
+//! start of the file next to each other, and rebalancing merges underfilled
+//! leaves with a sibling. Once no page lies above a free page anymore,
+//! DB::truncate_to_high_water_mark gives the free space back.
+
+use std::collections::HashSet;
+
+use crate::bucket::Bucket;
+use crate::common::page::{Page, PgId, LEAF_PAGE_ELEMENT_SIZE, PAGE_HEADER_SIZE};
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::node::{Node, WeakNode};
+use crate::tx::Tx;
+
+/// DefragReport summarizes a run of DB::defragment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DefragReport {
+    /// number of pages rewritten
+    pub pages: usize,
+    /// whether no page was out of place anymore
+    pub done: bool,
+}
+
+impl DB {
+    /// defragment rewrites up to budget pages within a write transaction,
+    /// moving pages that lie above a free page down to the lowest free pages
+    /// and merging leaves filled below the rebalance threshold of their
+    /// bucket, see Options.rebalance_percent, with a sibling. Call it
+    /// repeatedly, for instance while the database is idle, until a run finds
+    /// nothing left to do.
+    ///
+    /// Pages freed by a run only become free once no reader uses them, so a
+    /// later run moves the pages left above them.
+    pub fn defragment(&self, budget: usize) -> Result<DefragReport> {
+        if budget == 0 {
+            return Err(BoltError::Config("budget must be positive".to_string()));
+        }
+
+        let tx = self.begin_rw()?;
+        let mut defrag = match Defrag::new(&tx, budget) {
+            Ok(defrag) => defrag,
+            Err(err) => {
+                tx.rollback()?;
+                return Err(err);
+            }
+        };
+        if let Err(err) = defrag.bucket(&tx.root()) {
+            tx.rollback()?;
+            return Err(err);
+        }
+
+        let report = DefragReport {
+            pages: defrag.pages(),
+            done: defrag.done && defrag.pages() == 0,
+        };
+        if report.pages == 0 {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+}
+
+// Defrag selects and rewrites the pages of a run.
+struct Defrag<'a> {
+    tx: &'a Tx,
+    // Lowest free page, pages above it are moved.
+    lowest_free: PgId,
+    page_size: usize,
+    // Node count of the transaction when the run started.
+    start: i64,
+    budget: i64,
+    done: bool,
+}
+
+impl<'a> Defrag<'a> {
+    fn new(tx: &'a Tx, budget: usize) -> Result<Defrag<'a>> {
+        let db = tx.db()?;
+        let lowest_free = db.0.freelist()?.free_page_ids().first().copied();
+        Ok(Defrag {
+            tx,
+            lowest_free: lowest_free.unwrap_or(PgId::MAX),
+            page_size: db.0.page_size(),
+            start: tx.stats().node_count,
+            budget: budget as i64,
+            done: true,
+        })
+    }
+
+    // pages returns the number of pages rewritten so far.
+    fn pages(&self) -> usize {
+        (self.tx.stats().node_count - self.start) as usize
+    }
+
+    // bucket rewrites the pages of a bucket, then those of its buckets.
+    fn bucket(&mut self, bucket: &Bucket) -> Result<()> {
+        let root = bucket.root();
+        if root == 0 {
+            return Ok(());
+        }
+
+        // Select the pages to rewrite, along with the branch pages above them.
+        // Leaves are only merged below the threshold rebalancing uses, or the
+        // commit would leave them as they are and runs would never be done.
+        let threshold = bucket.0.rebalance_threshold(self.page_size);
+        let mut wanted = HashSet::new();
+        let mut children = Vec::new();
+        self.tx.for_each_page_in(root, &mut |page, _, stack| {
+            if page.is_leaf_page() {
+                children.extend(
+                    page.leaf_page_elements()
+                        .iter()
+                        .filter(|elem| elem.bucket().is_some_and(|b| b.root_page() != 0))
+                        .map(|elem| elem.key().to_vec()),
+                );
+            }
+            if self.out_of_place(page, stack.len() > 1, threshold) {
+                wanted.extend(stack.iter().copied());
+            }
+        })?;
+
+        if wanted.contains(&root) {
+            if self.pages() as i64 >= self.budget {
+                self.done = false;
+                return Ok(());
+            }
+            let node = bucket.0.node(root, WeakNode::new())?;
+            self.node(&node, &wanted)?;
+        }
+        for name in children {
+            if !self.done {
+                break;
+            }
+            if let Some(child) = bucket.bucket(&name) {
+                self.bucket(&child)?;
+            }
+        }
+        Ok(())
+    }
+
+    // out_of_place returns whether a page lies above a free page, or is a
+    // leaf with siblings using less than threshold bytes.
+    fn out_of_place(&self, page: &Page, has_parent: bool, threshold: usize) -> bool {
+        if page.overflow() == 0 && page.id() > self.lowest_free {
+            return true;
+        }
+        has_parent && page.is_leaf_page() && leaf_used(page) < threshold
+    }
+
+    // node materializes the wanted children of a node, so that the commit
+    // rebalances and spills them onto new pages.
+    fn node(&mut self, node: &Node, wanted: &HashSet<PgId>) -> Result<()> {
+        node.mark_unbalanced();
+        if node.is_leaf() {
+            return Ok(());
+        }
+        for index in 0..node.num_children() {
+            let pgid = node.inodes().get(index).pgid();
+            if !wanted.contains(&pgid) {
+                continue;
+            }
+            if self.pages() as i64 >= self.budget {
+                self.done = false;
+                return Ok(());
+            }
+            let child = node.child_at(index)?;
+            self.node(&child, wanted)?;
+        }
+        Ok(())
+    }
+}
+
+// leaf_used returns the bytes used by a leaf page.
+fn leaf_used(page: &Page) -> usize {
+    match page.leaf_page_elements().last() {
+        Some(last) => {
+            PAGE_HEADER_SIZE
+                + LEAF_PAGE_ELEMENT_SIZE * (page.count() as usize - 1)
+                + (last.pos() + last.ksize() + last.vsize()) as usize
+        }
+        None => PAGE_HEADER_SIZE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    // Ensure that repeated runs pack the pages of a fragmented database so
+    // that the file can be truncated, keeping the data intact.
+    #[test]
+    fn test_defragment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        for name in [&b"widgets"[..], b"woojits"] {
+            db.update(|tx| {
+                let b = tx.create_bucket(name)?;
+                for i in 0..4000u32 {
+                    b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+        db.update(|tx| {
+            let b = tx.bucket(b"woojits").unwrap();
+            for i in (0..4000u32).filter(|i| i % 16 != 0) {
+                b.delete(format!("{:08}", i).as_bytes())?;
+            }
+            tx.delete_bucket(b"widgets")
+        })
+        .unwrap();
+        let leaves = |db: &DB| {
+            db.view(|tx| Ok(tx.bucket(b"woojits").unwrap().stats()?.leaf_page_n))
+                .unwrap()
+        };
+        let before = leaves(&db);
+        let size = db.truncate_to_high_water_mark().unwrap();
+
+        assert!(matches!(db.defragment(0), Err(BoltError::Config(_))));
+        let mut runs = 0;
+        while !db.defragment(16).unwrap().done {
+            runs += 1;
+            assert!(runs < 1000);
+        }
+        assert!(runs > 1);
+        assert!(leaves(&db) <= before);
+        db.update(|_| Ok(())).unwrap();
+        assert!(db.truncate_to_high_water_mark().unwrap() < size / 4);
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"woojits").unwrap();
+            assert_eq!(b.get(b"00003984"), Some(&[0x5a; 100][..]));
+            assert_eq!(b.get(b"00003985"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that runs are done once no leaf lies below the rebalance
+    // threshold, when it is lower than the default.
+    #[test]
+    fn test_defragment_rebalance_percent() {
+        for rebalance_percent in [0.1, 0.05] {
+            let dir = tempfile::tempdir().unwrap();
+            let options = Options {
+                rebalance_percent: Some(rebalance_percent),
+                ..Default::default()
+            };
+            let db = DB::open(dir.path().join("db"), options).unwrap();
+            db.update(|tx| {
+                let b = tx.create_bucket(b"widgets")?;
+                for i in 0..4000u32 {
+                    b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+            db.update(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                for i in (0..4000u32).filter(|i| i % 8 != 0) {
+                    b.delete(format!("{:08}", i).as_bytes())?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+            let mut runs = 0;
+            while !db.defragment(16).unwrap().done {
+                runs += 1;
+                assert!(runs < 100, "rebalance_percent {}", rebalance_percent);
+            }
+            db.view(|tx| {
+                assert_eq!(tx.check()?.errors, vec![]);
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.get(b"00003992"), Some(&[0x5a; 100][..]));
+                Ok(())
+            })
+            .unwrap();
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! Deleting ranges of keys.
+//!
+//! Deleting keys one at a time through a cursor seeks and edits a leaf node
+//! for every key. Bucket::delete_range instead walks the tree once: subtrees
+//! whose keys all lie within the range are unlinked from their branch and
+//! their pages freed without being read into nodes, and only the leaves at
+//! the edges of the range are edited key by key.
+//!
+//! Bucket::delete_prefix deletes the keys with a prefix the same way, as the
+//! range from the prefix up to the first key past it.
+//!
+//! Unlinking subtrees may leave a branch with a single child, which rebalancing
+//! a child does not expect, so the nodes edited are rebalanced right away,
+//! parents first.
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::bucket::{is_hidden, Bucket};
+use crate::common::inode::Key;
+use crate::common::page::{Page, PgId, BUCKET_LEAF_FLAG};
+use crate::errors::Result;
+use crate::node::{Node, WeakNode};
+use crate::tx::Tx;
+
+// Range is a range of keys, with owned bounds.
+struct Range {
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+}
+
+impl Range {
+    fn new<K: AsRef<[u8]>, R: RangeBounds<K>>(range: &R) -> Range {
+        let own = |bound: Bound<&K>| match bound {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Range {
+            start: own(range.start_bound()),
+            end: own(range.end_bound()),
+        }
+    }
+
+    // contains returns whether a key lies within the range.
+    fn contains(&self, key: &[u8]) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => key >= &start[..],
+            Bound::Excluded(start) => key > &start[..],
+            Bound::Unbounded => true,
+        };
+        let before_end = match &self.end {
+            Bound::Included(end) => key <= &end[..],
+            Bound::Excluded(end) => key < &end[..],
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    // covers returns whether every key from lo up to, but not including, hi
+    // lies within the range. None stands for no bound.
+    fn covers(&self, lo: Option<&[u8]>, hi: Option<&[u8]>) -> bool {
+        let start = match (&self.start, lo) {
+            (Bound::Unbounded, _) => true,
+            (_, None) => false,
+            (Bound::Included(start), Some(lo)) => lo >= &start[..],
+            (Bound::Excluded(start), Some(lo)) => lo > &start[..],
+        };
+        let end = match (&self.end, hi) {
+            (Bound::Unbounded, _) => true,
+            (_, None) => false,
+            (Bound::Included(end) | Bound::Excluded(end), Some(hi)) => hi <= &end[..],
+        };
+        start && end
+    }
+
+    // misses returns whether no key from lo up to, but not including, hi
+    // lies within the range.
+    fn misses(&self, lo: Option<&[u8]>, hi: Option<&[u8]>) -> bool {
+        let before = match (&self.start, hi) {
+            (Bound::Included(start) | Bound::Excluded(start), Some(hi)) => hi <= &start[..],
+            _ => false,
+        };
+        let after = match (&self.end, lo) {
+            (Bound::Included(end), Some(lo)) => lo > &end[..],
+            (Bound::Excluded(end), Some(lo)) => lo >= &end[..],
+            _ => false,
+        };
+        before || after
+    }
+}
+
+// prefix_end returns the first key past the keys starting with prefix, or
+// None if there is none, when the prefix is empty or only 0xff bytes.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&b| b != 0xff)?;
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Some(end)
+}
+
+impl Bucket<'_> {
+    /// delete_range removes the keys of the bucket within a range, returning
+    /// how many were removed. Nested buckets in the range are kept. Subtrees
+    /// holding only keys within the range are freed as a whole, which makes
+    /// deleting large ranges much faster than deleting each key.
+    /// Returns an error if the bucket was created from a read-only transaction.
+    pub fn delete_range<K, R>(&self, range: R) -> Result<usize>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.check_writable()?;
+        let range = Range::new(&range);
+        let tx = self.tx()?;
+        let root = self.0.node(self.0.root_page(), WeakNode::new())?;
+        let mut edited = Vec::new();
+        let deleted = self.delete_in(&tx, &root, &range, None, None, &mut edited)?;
+
+        // Nodes merged into others are gone from the bucket.
+        for node in edited {
+            let live = self.0.nodes.borrow().get(&node.pgid()).cloned();
+            if live.is_some_and(|live| Node::ptr_eq(&live, &node)) {
+                node.rebalance()?;
+            }
+        }
+        self.clear_ttls(|key| range.contains(key))?;
+        Ok(deleted)
+    }
+
+    /// delete_prefix removes the keys of the bucket starting with prefix,
+    /// returning how many were removed, like delete_range over those keys.
+    /// Returns an error if the bucket was created from a read-only transaction.
+    pub fn delete_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        match prefix_end(prefix) {
+            Some(end) => self.delete_range(prefix..&end[..]),
+            None => self.delete_range(prefix..),
+        }
+    }
+
+    /// clear removes every key and nested bucket of the bucket, returning the
+    /// number of keys removed, like delete_range over all keys. The hidden
+    /// buckets of the root are kept.
+    /// Returns an error if the bucket was created from a read-only transaction.
+    pub fn clear(&self) -> Result<usize> {
+        self.check_writable()?;
+        let root = self.0.path.borrow().is_empty();
+        let mut names = Vec::new();
+        self.for_each_bucket(|name| {
+            if !(root && is_hidden(name)) {
+                names.push(name.to_vec());
+            }
+            Ok(())
+        })?;
+        for name in &names {
+            self.delete_bucket(name)?;
+        }
+        self.delete_range::<&[u8], _>(..)
+    }
+
+    // delete_in removes the keys within range below a node, whose keys lie
+    // from lo up to, but not including, hi. The nodes visited are added to
+    // edited, parents first.
+    fn delete_in(
+        &self,
+        tx: &Tx,
+        node: &Node,
+        range: &Range,
+        lo: Option<&[u8]>,
+        hi: Option<&[u8]>,
+        edited: &mut Vec<Node>,
+    ) -> Result<usize> {
+        edited.push(node.clone());
+        if node.is_leaf() {
+            return self.delete_in_leaf(node, range);
+        }
+
+        // Children are removed from the last, so that the index of the
+        // others stays the same.
+        let children: Vec<(Key, PgId)> = node
+            .inodes()
+            .iter()
+            .map(|inode| (inode.key().clone(), inode.pgid()))
+            .collect();
+        let mut deleted = 0;
+        for (index, (key, pgid)) in children.iter().enumerate().rev() {
+            // The first child also holds the keys put before its first key.
+            let child_lo = if index == 0 { lo } else { Some(&key[..]) };
+            let child_hi = children.get(index + 1).map(|(k, _)| &k[..]).or(hi);
+            if range.misses(child_lo, child_hi) {
+                continue;
+            }
+
+            // Free a subtree that was not read into nodes as a whole, unless
+            // it holds a nested bucket.
+            let materialized = self.0.nodes.borrow().contains_key(pgid);
+            if !materialized && range.covers(child_lo, child_hi) {
+                if let Some(n) = self.free_subtree(tx, *pgid)? {
+                    node.del(key);
+                    deleted += n;
+                    continue;
+                }
+            }
+
+            let child = node.child_at(index)?;
+            deleted += self.delete_in(tx, &child, range, child_lo, child_hi, edited)?;
+        }
+        Ok(deleted)
+    }
+
+    // delete_in_leaf removes the keys within range from a leaf node.
+    fn delete_in_leaf(&self, node: &Node, range: &Range) -> Result<usize> {
+        let keys = node
+            .inodes()
+            .iter()
+            .filter(|inode| inode.flags() & BUCKET_LEAF_FLAG == 0 && range.contains(inode.key()))
+            .map(|inode| Ok((inode.key().clone(), self.old_value(inode.value())?)))
+            .collect::<Result<Vec<(Key, Option<Vec<u8>>)>>>()?;
+        for (key, old) in &keys {
+            node.del(key);
+            if self.is_capturing() {
+                self.record_change(key, old.clone(), None);
+            }
+        }
+        Ok(keys.len())
+    }
+
+    // free_subtree frees the pages of a subtree and returns the number of
+    // keys it held, or None if it holds a nested bucket.
+    fn free_subtree(&self, tx: &Tx, pgid: PgId) -> Result<Option<usize>> {
+        let mut pages = Vec::new();
+        let mut keys = 0;
+        let mut nested = false;
+        tx.for_each_page_in(pgid, &mut |page: &Page, _, _| {
+            if page.is_leaf_page() {
+                let elems = page.leaf_page_elements();
+                nested |= elems.iter().any(|elem| elem.is_bucket_entry());
+                keys += elems.len();
+            }
+            pages.push(page.id());
+        })?;
+        if nested {
+            return Ok(None);
+        }
+
+        if self.is_capturing() {
+            let mut changes = Vec::new();
+            tx.for_each_page_in(pgid, &mut |page: &Page, _, _| {
+                if page.is_leaf_page() {
+                    for elem in page.leaf_page_elements() {
+                        changes.push((elem.key().to_vec(), self.old_value(elem.value())));
+                    }
+                }
+            })?;
+            for (key, old) in changes {
+                self.record_change(&key, old?, None);
+            }
+        }
+
+        let db = tx.db()?;
+        let mut freelist = db.0.freelist_mut()?;
+        for id in pages {
+            freelist.free(tx.id(), &tx.page(id)?);
+        }
+        Ok(Some(keys))
+    }
+
+    // old_value returns the value of a removed key for observers, if they
+    // are watching.
+    fn old_value(&self, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !self.is_capturing() {
+            return Ok(None);
+        }
+        self.compression().decompress(value).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::db::{Options, DB};
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("{:08}", i).into_bytes()
+    }
+
+    fn fill(db: &DB) {
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..20000 {
+                b.put(&key(i), &[0x5a; 50])?;
+            }
+            b.create_bucket(b"00009000-sub")?;
+            b.put_with_ttl(&key(10000), b"ttl", Duration::from_secs(60))
+        })
+        .unwrap();
+    }
+
+    // Ensure that a range is deleted without reading most of its leaves, and
+    // that the tree stays consistent.
+    #[test]
+    fn test_bucket_delete_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        fill(&db);
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let nodes = tx.stats().node_count;
+            assert_eq!(b.delete_range(key(2000)..key(18000))?, 16000);
+            assert!(tx.stats().node_count - nodes < 20);
+            assert_eq!(b.get(&key(1999)), Some(&[0x5a; 50][..]));
+            assert_eq!(b.get(&key(2000)), None);
+            assert_eq!(b.get(&key(17999)), None);
+            assert_eq!(b.get(&key(18000)), Some(&[0x5a; 50][..]));
+            assert!(b.bucket(b"00009000-sub").is_some());
+
+            b.put(&key(10000), b"again")?;
+            assert_eq!(b.ttl(&key(10000)), None);
+            assert_eq!(b.delete_range(&key(0)[..]..=&key(1)[..])?, 2);
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.key_count()?, 4000);
+            assert_eq!(b.get(&key(0)), None);
+            assert_eq!(b.get(&key(10000)), Some(&b"again"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that only the keys with the prefix are deleted, up to the last
+    // possible key.
+    #[test]
+    fn test_bucket_delete_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        fill(&db);
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.delete_prefix(b"0001")?, 10000);
+            assert_eq!(b.get(&key(9999)), Some(&[0x5a; 50][..]));
+            assert_eq!(b.get(&key(10000)), None);
+            assert_eq!(b.get(&key(19999)), None);
+
+            for k in [&b"\xfe\xff"[..], b"\xff", b"\xff\x00", b"\xff\xff"] {
+                b.put(k, b"")?;
+            }
+            assert_eq!(b.delete_prefix(b"\xff")?, 3);
+            assert_eq!(b.get(b"\xfe\xff"), Some(&b""[..]));
+            assert_eq!(b.delete_prefix(b"000090")?, 100);
+            assert!(b.bucket(b"00009000-sub").is_some());
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            assert_eq!(tx.bucket(b"widgets").unwrap().key_count()?, 9902);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that clear empties a bucket, nested buckets included.
+    #[test]
+    fn test_bucket_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        fill(&db);
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.clear()?, 20000);
+            assert_eq!(b.key_count()?, 0);
+            b.put(b"foo", b"bar")
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.key_count()?, 1);
+            assert_eq!(b.stats()?.leaf_page_n, 0);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//! Comparing two databases.
+//!
+//! diff walks the buckets of two databases in lockstep, in key order, and
+//! reports every key whose value differs as a Change from the first database
+//! to the second. It is meant to verify backups and debug replicas, which
+//! should hold the same keys, so it is fastest when there are few differences.
+
+use std::cmp::Ordering;
+
+use crate::bucket::{is_hidden, Bucket};
+use crate::changes::Change;
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::tx::Tx;
+
+/// diff returns the changes turning the contents of database a into those of
+/// database b, comparing a read transaction of each.
+pub fn diff(a: &DB, b: &DB) -> Result<Vec<Change>> {
+    let (ta, tb) = (a.begin(false)?, b.begin(false)?);
+    let changes = ta.diff(&tb);
+    ta.rollback()?;
+    tb.rollback()?;
+    changes
+}
+
+impl Tx {
+    /// diff returns the changes turning the contents seen by the transaction
+    /// into those seen by other, ordered by bucket and key. A key added
+    /// has no old value and a key removed no new one. The keys of a bucket
+    /// that only exists on one side are reported as added or removed.
+    /// Expired keys are left out, and TTLs are not compared.
+    pub fn diff(&self, other: &Tx) -> Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        let (a, b) = (self.root(), other.root());
+        diff_buckets(Some(&a), Some(&b), &mut Vec::new(), &mut changes)?;
+        Ok(changes)
+    }
+}
+
+// Side tells which of the databases a key was found in.
+#[derive(Clone, Copy)]
+enum Side {
+    A,
+    B,
+}
+
+// diff_buckets appends the changes from bucket a to bucket b, either of
+// which may be missing, to changes. path is the path of the buckets.
+fn diff_buckets(
+    a: Option<&Bucket>,
+    b: Option<&Bucket>,
+    path: &mut Vec<Vec<u8>>,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let mut ca = a.map(|b| b.cursor());
+    let mut cb = b.map(|b| b.cursor());
+    let mut ia = ca.as_mut().and_then(|c| c.first());
+    let mut ib = cb.as_mut().and_then(|c| c.first());
+
+    loop {
+        let order = match (ia, ib) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((ka, _)), Some((kb, _))) => ka.cmp(kb),
+        };
+        match order {
+            Ordering::Less => {
+                let (key, value) = ia.unwrap();
+                one_sided(a.unwrap(), Side::A, key, value, path, changes)?;
+                ia = ca.as_mut().unwrap().next();
+            }
+            Ordering::Greater => {
+                let (key, value) = ib.unwrap();
+                one_sided(b.unwrap(), Side::B, key, value, path, changes)?;
+                ib = cb.as_mut().unwrap().next();
+            }
+            Ordering::Equal => {
+                let (key, va) = ia.unwrap();
+                let (_, vb) = ib.unwrap();
+                if !(path.is_empty() && is_hidden(key)) {
+                    match (va, vb) {
+                        (Some(va), Some(vb)) if va != vb => changes.push(Change {
+                            bucket: path.clone(),
+                            key: key.to_vec(),
+                            old: Some(va.to_vec()),
+                            new: Some(vb.to_vec()),
+                        }),
+                        (Some(_), Some(_)) => {}
+                        (None, None) => {
+                            let (ba, bb) = (child(a.unwrap(), key)?, child(b.unwrap(), key)?);
+                            path.push(key.to_vec());
+                            diff_buckets(Some(&ba), Some(&bb), path, changes)?;
+                            path.pop();
+                        }
+                        // A value on one side and a bucket on the other.
+                        _ => {
+                            one_sided(a.unwrap(), Side::A, key, va, path, changes)?;
+                            one_sided(b.unwrap(), Side::B, key, vb, path, changes)?;
+                        }
+                    }
+                }
+                ia = ca.as_mut().unwrap().next();
+                ib = cb.as_mut().unwrap().next();
+            }
+        }
+    }
+}
+
+// one_sided appends the change for a key found on one side only: the key
+// itself, or every key of the nested bucket it names.
+fn one_sided(
+    b: &Bucket,
+    side: Side,
+    key: &[u8],
+    value: Option<&[u8]>,
+    path: &mut Vec<Vec<u8>>,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    if path.is_empty() && is_hidden(key) {
+        return Ok(());
+    }
+    let value = match value {
+        Some(value) => value.to_vec(),
+        None => {
+            let child = child(b, key)?;
+            path.push(key.to_vec());
+            match side {
+                Side::A => diff_buckets(Some(&child), None, path, changes)?,
+                Side::B => diff_buckets(None, Some(&child), path, changes)?,
+            }
+            path.pop();
+            return Ok(());
+        }
+    };
+    let (old, new) = match side {
+        Side::A => (Some(value), None),
+        Side::B => (None, Some(value)),
+    };
+    changes.push(Change {
+        bucket: path.clone(),
+        key: key.to_vec(),
+        old,
+        new,
+    });
+    Ok(())
+}
+
+fn child<'tx>(b: &Bucket<'tx>, name: &[u8]) -> Result<Bucket<'tx>> {
+    b.bucket(name).ok_or(BoltError::BucketNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    // Ensure that added, removed and changed keys are reported, nested
+    // buckets included.
+    #[test]
+    fn test_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = DB::open(dir.path().join("a"), Options::default()).unwrap();
+        let b = DB::open(dir.path().join("b"), Options::default()).unwrap();
+        for db in [&a, &b] {
+            db.update(|tx| {
+                let w = tx.create_bucket(b"widgets")?;
+                for i in 0..1000u32 {
+                    w.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+                }
+                w.create_bucket(b"sub")?.put(b"foo", b"bar")
+            })
+            .unwrap();
+        }
+        assert_eq!(diff(&a, &b).unwrap(), vec![]);
+
+        a.update(|tx| {
+            tx.create_bucket(b"only_a")?.put(b"x", b"1")?;
+            tx.bucket(b"widgets").unwrap().put(b"mixed", b"value")
+        })
+        .unwrap();
+        b.update(|tx| {
+            let w = tx.bucket(b"widgets").unwrap();
+            w.delete(b"0500")?;
+            w.put(b"0999", b"changed")?;
+            w.create_bucket(b"mixed")?.put(b"y", b"2")?;
+            w.bucket(b"sub").unwrap().put(b"new", b"key")
+        })
+        .unwrap();
+
+        let change =
+            |bucket: &[&[u8]], key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>| Change {
+                bucket: bucket.iter().map(|name| name.to_vec()).collect(),
+                key: key.to_vec(),
+                old: old.map(|v| v.to_vec()),
+                new: new.map(|v| v.to_vec()),
+            };
+        assert_eq!(
+            diff(&a, &b).unwrap(),
+            vec![
+                change(&[b"only_a"], b"x", Some(b"1"), None),
+                change(&[b"widgets"], b"0500", Some(&[0x5a; 100]), None),
+                change(&[b"widgets"], b"0999", Some(&[0x5a; 100]), Some(b"changed")),
+                change(&[b"widgets"], b"mixed", Some(b"value"), None),
+                change(&[b"widgets", b"mixed"], b"y", None, Some(b"2")),
+                change(&[b"widgets", b"sub"], b"new", None, Some(b"key")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Dumping the B-tree of a transaction.
+//!
+//! Tx::dump_tree renders the pages of every bucket, nested buckets included,
+//! with their ids, types, key ranges and how full they are, either as
+//! indented text or as a Graphviz graph:
+//!
+//! ```text
+//! $ dot -Tsvg tree.dot > tree.svg
+//! ```
+//!
+//! Like Bucket::stats, only the committed pages are shown, changes pending in
+//! a write transaction are not.
+
+use std::io::Write;
+
+use crate::bucket::Bucket;
+use crate::common::page::{Page, PgId};
+use crate::errors::{BoltError, Result};
+use crate::page_ref::PageRef;
+use crate::tx::Tx;
+
+// Number of bytes of a key shown in a key range.
+const KEY_PREFIX_LEN: usize = 16;
+
+/// TreeFormat is the format of Tx::dump_tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TreeFormat {
+    /// Text indents each page below its parent.
+    #[default]
+    Text,
+    /// Dot is a Graphviz digraph with a node per page and bucket.
+    Dot,
+}
+
+impl Tx {
+    /// dump_tree writes the page hierarchy of the transaction to a writer,
+    /// starting from the root bucket.
+    pub fn dump_tree<W: Write>(&self, w: &mut W, format: TreeFormat) -> Result<()> {
+        let mut dumper = Dumper {
+            tx: self,
+            w,
+            format,
+            page_size: self.db()?.0.page_size(),
+            bucket_n: 0,
+            inline_n: 0,
+        };
+        if format == TreeFormat::Dot {
+            writeln!(dumper.w, "digraph btree {{")?;
+            writeln!(dumper.w, "  node [shape=box, fontname=monospace];")?;
+        }
+        dumper.bucket(&self.root(), None, 0, None)?;
+        if format == TreeFormat::Dot {
+            writeln!(dumper.w, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+struct Dumper<'a, W> {
+    tx: &'a Tx,
+    w: &'a mut W,
+    format: TreeFormat,
+    page_size: usize,
+    // Number of buckets and inline pages so far, which name their graph nodes.
+    bucket_n: usize,
+    inline_n: usize,
+}
+
+impl<W: Write> Dumper<'_, W> {
+    // bucket writes a bucket and its pages, indented by level. parent is the
+    // graph node of the leaf holding the bucket.
+    fn bucket(
+        &mut self,
+        b: &Bucket,
+        name: Option<&[u8]>,
+        level: usize,
+        parent: Option<&str>,
+    ) -> Result<()> {
+        let name = match name {
+            Some(name) => format!("bucket {}", escape(name)),
+            None => "root bucket".to_string(),
+        };
+        self.bucket_n += 1;
+        let node = format!("b{}", self.bucket_n);
+        match self.format {
+            TreeFormat::Text => writeln!(
+                self.w,
+                "{:indent$}{} seq={}",
+                "",
+                name,
+                b.sequence(),
+                indent = 2 * level
+            )?,
+            TreeFormat::Dot => {
+                writeln!(
+                    self.w,
+                    "  {} [shape=ellipse, label=\"{}\"];",
+                    node,
+                    dot(&name)
+                )?;
+                if let Some(parent) = parent {
+                    writeln!(self.w, "  {} -> {};", parent, node)?;
+                }
+            }
+        }
+
+        if b.root() == 0 {
+            let page = match &b.0.page {
+                Some(page) => page.clone(),
+                None => return Ok(()),
+            };
+            self.inline_n += 1;
+            let id = format!("i{}", self.inline_n);
+            let page = PageRef::new(Page::from_slice(&page), page.len(), 0)?;
+            return self.page(b, page, &id, level + 1, &node);
+        }
+        self.page_tree(b, b.root(), level + 1, &node)
+    }
+
+    // page_tree writes a page and the pages below it.
+    fn page_tree(&mut self, b: &Bucket, pgid: PgId, level: usize, parent: &str) -> Result<()> {
+        let page = self.tx.page(pgid)?;
+        let size = self.page_size * (page.overflow() as usize + 1);
+        let page = PageRef::new(&page, size, 0)?;
+        let node = format!("p{}", pgid);
+        self.page(b, page, &node, level, parent)?;
+        for index in 0..page.count() {
+            if let Some(child) = page.child(index) {
+                self.page_tree(b, child, level + 1, &node)?;
+            }
+        }
+        Ok(())
+    }
+
+    // page writes a page, and the nested buckets of a leaf.
+    fn page(
+        &mut self,
+        b: &Bucket,
+        page: PageRef<'_>,
+        node: &str,
+        level: usize,
+        parent: &str,
+    ) -> Result<()> {
+        let id = match b.root() {
+            0 => "inline".to_string(),
+            _ => page.id().to_string(),
+        };
+        let typ = if page.is_branch() { "branch" } else { "leaf" };
+        let range = match page.count() {
+            0 => "empty".to_string(),
+            n => format!("{}..{}", preview(&page, 0), preview(&page, n - 1)),
+        };
+        let size = self.page_size * (page.overflow() as usize + 1);
+        let fill = 100 * page.used() / size;
+        let overflow = match page.overflow() {
+            0 => String::new(),
+            n => format!(" +{}", n),
+        };
+
+        match self.format {
+            TreeFormat::Text => writeln!(
+                self.w,
+                "{:indent$}{} {}{} keys={} {} fill={}%",
+                "",
+                typ,
+                id,
+                overflow,
+                page.count(),
+                range,
+                fill,
+                indent = 2 * level
+            )?,
+            TreeFormat::Dot => {
+                let label = format!(
+                    "{} {}{}\\n{} keys, {}% full\\n{}",
+                    typ,
+                    id,
+                    overflow,
+                    page.count(),
+                    fill,
+                    dot(&range)
+                );
+                writeln!(self.w, "  {} [label=\"{}\"];", node, label)?;
+                writeln!(self.w, "  {} -> {};", parent, node)?;
+            }
+        }
+
+        for index in 0..page.count() {
+            if !page.is_bucket(index) {
+                continue;
+            }
+            let name = page.key(index).unwrap_or_default();
+            let child = b.bucket(&name).ok_or(BoltError::BucketNotFound)?;
+            self.bucket(&child, Some(&name), level + 1, Some(node))?;
+        }
+        Ok(())
+    }
+}
+
+// escape returns a printable prefix of a key.
+fn escape(key: &[u8]) -> String {
+    let shown = &key[..key.len().min(KEY_PREFIX_LEN)];
+    let more = if key.len() > KEY_PREFIX_LEN {
+        "..."
+    } else {
+        ""
+    };
+    format!("\"{}\"{}", shown.escape_ascii(), more)
+}
+
+// preview returns the printable prefix of the key of an element.
+fn preview(page: &PageRef, index: usize) -> String {
+    escape(&page.key(index).unwrap_or_default())
+}
+
+// dot escapes a string for a quoted Graphviz label.
+fn dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // Ensure that the tree is dumped with every page and nested bucket.
+    #[test]
+    fn test_dump_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let mut text = Vec::new();
+            tx.dump_tree(&mut text, TreeFormat::Text)?;
+            let text = String::from_utf8(text).unwrap();
+            let leaves = tx.bucket(b"widgets").unwrap().stats()?.leaf_page_n;
+            assert!(text.starts_with("root bucket seq=0\n"));
+            assert!(text.contains("\n    bucket \"widgets\" seq=0\n"));
+            assert!(text.contains("\n        leaf 4 keys=16 \"0000\"..\"0015\" fill="));
+            assert!(text.contains("bucket \"sub\""));
+            assert!(text.contains("leaf inline keys=1 \"foo\"..\"foo\""));
+            assert_eq!(text.matches("leaf ").count(), leaves + 2);
+
+            let mut graph = Vec::new();
+            tx.dump_tree(&mut graph, TreeFormat::Dot)?;
+            let graph = String::from_utf8(graph).unwrap();
+            assert!(graph.starts_with("digraph btree {\n"));
+            assert!(graph.ends_with("}\n"));
+            assert_eq!(graph.matches("label=\"leaf ").count(), leaves + 2);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
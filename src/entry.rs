@@ -0,0 +1,260 @@
+//! Entries, a key of a bucket looked up once to be read and written.
+//!
+//! Bucket::entry seeks a cursor to a key and keeps it there, so an upsert
+//! writes the key where the lookup found it instead of seeking it again:
+//!
+//! ```
+//! # let dir = tempfile::tempdir().unwrap();
+//! # let db = boltdb_rs::DB::open(dir.path().join("db"), Default::default()).unwrap();
+//! db.update(|tx| {
+//!     let b = tx.create_bucket(b"counters")?;
+//!     for _ in 0..3 {
+//!         b.entry(b"visits")?
+//!             .and_modify(|v| (u64::from_be_bytes(v.try_into().unwrap()) + 1).to_be_bytes())?
+//!             .or_insert(&1u64.to_be_bytes())?;
+//!     }
+//!     assert_eq!(b.get(b"visits"), Some(&3u64.to_be_bytes()[..]));
+//!     Ok(())
+//! })?;
+//! # Ok::<(), boltdb_rs::BoltError>(())
+//! ```
+
+use bytes::Bytes;
+
+use crate::bucket::Bucket;
+use crate::common::inode::Value;
+use crate::common::page::BUCKET_LEAF_FLAG;
+use crate::cursor::Cursor;
+use crate::errors::{BoltError, Result};
+
+/// Entry is a key of a bucket, which either has a value or not.
+#[derive(Debug)]
+pub enum Entry<'tx> {
+    /// Occupied is a key with a value.
+    Occupied(OccupiedEntry<'tx>),
+    /// Vacant is a key without a value.
+    Vacant(VacantEntry<'tx>),
+}
+
+/// OccupiedEntry is a key of a bucket with a value.
+#[derive(Debug)]
+pub struct OccupiedEntry<'tx> {
+    cursor: Cursor<'tx>,
+    key: Vec<u8>,
+    value: &'tx [u8],
+}
+
+/// VacantEntry is a key of a bucket without a value.
+#[derive(Debug)]
+pub struct VacantEntry<'tx> {
+    cursor: Cursor<'tx>,
+    key: Vec<u8>,
+}
+
+impl<'tx> Bucket<'tx> {
+    /// entry looks up a key, returning its entry to read or change its value.
+    /// Expired keys are vacant.
+    /// Returns BoltError::IncompatibleValue if the key is a nested bucket.
+    pub fn entry(&self, key: &[u8]) -> Result<Entry<'tx>> {
+        let mut cursor = self.cursor();
+        let item = cursor.seek_item(key).map_err(|err| err.with_key(key))?;
+        let key = key.to_vec();
+        let (k, v, flags) = match item {
+            Some(item) if item.0 == key => item,
+            _ => return Ok(Entry::Vacant(VacantEntry { cursor, key })),
+        };
+        if flags & BUCKET_LEAF_FLAG != 0 {
+            return Err(BoltError::IncompatibleValue);
+        }
+        if self.is_expired(&k) {
+            return Ok(Entry::Vacant(VacantEntry { cursor, key }));
+        }
+
+        let value = self.compression().decompress_bytes(&v)?;
+        let value = self.pin(value).ok_or(BoltError::TxClosed)?;
+        Ok(Entry::Occupied(OccupiedEntry { cursor, key, value }))
+    }
+
+    /// increment adds delta to the counter stored under key as a
+    /// little-endian u64, missing keys counting as zero, and returns the new
+    /// count. The addition wraps around on overflow.
+    /// Returns BoltError::IncompatibleValue if the key is a nested bucket or
+    /// its value is not 8 bytes long.
+    pub fn increment(&self, key: &[u8], delta: u64) -> Result<u64> {
+        match self.entry(key)? {
+            Entry::Occupied(mut entry) => {
+                let count =
+                    <[u8; 8]>::try_from(entry.get()).map_err(|_| BoltError::IncompatibleValue)?;
+                let count = u64::from_le_bytes(count).wrapping_add(delta);
+                entry.insert(&count.to_le_bytes())?;
+                Ok(count)
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(&delta.to_le_bytes())?;
+                Ok(delta)
+            }
+        }
+    }
+}
+
+impl<'tx> Entry<'tx> {
+    /// key returns the key of the entry.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// or_insert sets the value of a vacant entry, and returns the value of
+    /// the entry.
+    pub fn or_insert(self, value: &[u8]) -> Result<&'tx [u8]> {
+        self.or_insert_with(|| value)
+    }
+
+    /// or_insert_with sets the value of a vacant entry to the result of f,
+    /// and returns the value of the entry.
+    pub fn or_insert_with<F, V>(self, f: F) -> Result<&'tx [u8]>
+    where
+        F: FnOnce() -> V,
+        V: AsRef<[u8]>,
+    {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.get()),
+            Entry::Vacant(entry) => entry.insert(f().as_ref()),
+        }
+    }
+
+    /// and_modify sets the value of an occupied entry to the result of f
+    /// called with its current value. Vacant entries are left alone.
+    pub fn and_modify<F, V>(self, f: F) -> Result<Entry<'tx>>
+    where
+        F: FnOnce(&[u8]) -> V,
+        V: AsRef<[u8]>,
+    {
+        match self {
+            Entry::Occupied(mut entry) => {
+                let value = f(entry.get());
+                entry.insert(value.as_ref())?;
+                Ok(Entry::Occupied(entry))
+            }
+            Entry::Vacant(entry) => Ok(Entry::Vacant(entry)),
+        }
+    }
+}
+
+impl<'tx> OccupiedEntry<'tx> {
+    /// key returns the key of the entry.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// get returns the value of the entry.
+    pub fn get(&self) -> &'tx [u8] {
+        self.value
+    }
+
+    /// insert sets the value of the entry, returning the previous one.
+    pub fn insert(&mut self, value: &[u8]) -> Result<&'tx [u8]> {
+        let stored = store(&self.cursor, &self.key, value)?;
+        let bucket = self.cursor.bucket();
+        let old = self.value;
+        bucket.put_at(
+            &mut self.cursor,
+            &self.key,
+            stored,
+            0,
+            Some(old.to_vec()),
+            value,
+        )?;
+        self.value = pin(&bucket, value)?;
+        Ok(old)
+    }
+
+    /// remove deletes the key, returning its value.
+    pub fn remove(mut self) -> Result<&'tx [u8]> {
+        let bucket = self.cursor.bucket();
+        self.cursor
+            .node()
+            .map_err(|err| err.with_key(&self.key))?
+            .del(&self.key);
+        if bucket.is_capturing() {
+            bucket.record_change(&self.key, Some(self.value.to_vec()), None);
+        }
+        bucket.clear_ttl(&self.key)?;
+        Ok(self.value)
+    }
+}
+
+impl<'tx> VacantEntry<'tx> {
+    /// key returns the key of the entry.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// insert sets the value of the entry, returning it.
+    pub fn insert(mut self, value: &[u8]) -> Result<&'tx [u8]> {
+        let stored = store(&self.cursor, &self.key, value)?;
+        let bucket = self.cursor.bucket();
+        bucket.put_at(&mut self.cursor, &self.key, stored, 0, None, value)?;
+        pin(&bucket, value)
+    }
+}
+
+// store checks a put of value and returns its stored form.
+fn store(cursor: &Cursor, key: &[u8], value: &[u8]) -> Result<Value> {
+    let bucket = cursor.bucket();
+    bucket.check_put(key, value.len())?;
+    Ok(Value::from(bucket.compression().compress(value)?))
+}
+
+// pin returns a copy of value that lives as long as the transaction.
+fn pin<'tx>(bucket: &Bucket<'tx>, value: &[u8]) -> Result<&'tx [u8]> {
+    bucket
+        .pin(Bytes::copy_from_slice(value))
+        .ok_or(BoltError::TxClosed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // Ensure that entries insert, modify and remove values like put and
+    // delete.
+    #[test]
+    fn test_bucket_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.create_bucket(b"sub")?;
+            assert_eq!(b.entry(b"sub").err(), Some(BoltError::IncompatibleValue));
+
+            let entry = b.entry(b"foo")?;
+            assert_eq!(entry.key(), b"foo");
+            assert_eq!(entry.or_insert_with(|| b"bar".to_vec())?, b"bar");
+            assert_eq!(b.entry(b"foo")?.or_insert(b"baz")?, b"bar");
+
+            let entry = b.entry(b"foo")?.and_modify(|v| [v, &b"!"[..]].concat())?;
+            assert_eq!(entry.or_insert(b"baz")?, b"bar!");
+            assert_eq!(b.get(b"foo"), Some(&b"bar!"[..]));
+            assert!(matches!(
+                b.entry(b"missing")?.and_modify(|v| v.to_vec())?,
+                Entry::Vacant(_)
+            ));
+
+            match b.entry(b"foo")? {
+                Entry::Occupied(mut entry) => {
+                    assert_eq!(entry.insert(b"qux")?, b"bar!");
+                    assert_eq!(entry.get(), b"qux");
+                    assert_eq!(entry.remove()?, b"qux");
+                }
+                Entry::Vacant(_) => panic!("expected an occupied entry"),
+            }
+            assert_eq!(b.get(b"foo"), None);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
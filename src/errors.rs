@@ -1,18 +1,32 @@
 //! Package errors defines the error variables that may be returned
 //!  during bbolt operations.
 
+use std::fmt;
 use std::io;
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Debug, Error, PartialEq)]
+use crate::db::LockDiagnostics;
+
+// Number of bytes of a key kept in an ErrorContext.
+const KEY_PREFIX_LEN: usize = 32;
+
+#[derive(Clone, Debug, Error, PartialEq)]
 pub enum BoltError {
     /// ErrConfig
     #[error("invalid Configuration: {0}")]
     Config(String),
 
-    /// Returned when io be opened failed.
-    #[error("io error: {0}")]
-    Io(String),
+    /// Returned when an operation on a file fails. op tells which operation
+    /// it was, so that a failed sync can be told apart from a failed write,
+    /// and source keeps the error of the operating system.
+    #[error("{op} failed{context}: {source}")]
+    Io {
+        op: IoOp,
+        context: ErrorContext,
+        #[source]
+        source: IoError,
+    },
     /// Returned when file be resized failed.
     #[error("resize failed")]
     ResizeFail,
@@ -23,6 +37,29 @@ pub enum BoltError {
     /// Returned when check sync failed.
     #[error("check failed, {0}")]
     CheckFailed(String),
+    /// Returned when a typed key or value cannot be encoded or decoded.
+    #[error("codec error: {0}")]
+    Codec(String),
+    /// Returned when encrypted pages cannot be read with the given key.
+    #[error("encryption error: {0}")]
+    Encryption(&'static str),
+    /// Returned when an incremental backup cannot be taken or applied.
+    #[error("backup error: {0}")]
+    Backup(String),
+    /// Returned when a bucket export cannot be read.
+    #[error("export error: {0}")]
+    Export(String),
+    /// Returned when a page or bucket read from the file is malformed. The
+    /// context names the page, bucket and key at fault, as far as known.
+    #[error("corrupted database{context}: {reason}")]
+    Corrupted {
+        context: ErrorContext,
+        reason: String,
+    },
+    /// Returned when opening with Options.check_torn_writes finds pages cut
+    /// short or malformed by an interrupted write. Holds their ids.
+    #[error("torn write detected on pages {0:?}")]
+    TornWrite(Vec<u64>),
 
     /// ErrUnexpected is returned when  Unexpected operation
     #[error("{0}")]
@@ -54,8 +91,9 @@ pub enum BoltError {
     #[error("checksum error")]
     Checksum,
 
-    /// ErrTimeout is returned when a database cannot obtain an exclusive lock
-    // on the data file after the timeout passed to Open().
+    /// ErrTimeout is returned when a database cannot obtain a lock on the
+    /// data file after the timeout passed to Open(), because another process
+    /// has it open for writing, or for reading when opening it for writing.
     #[error("timeout")]
     Timeout,
 
@@ -72,6 +110,15 @@ pub enum BoltError {
     #[error("tx closed")]
     TxClosed,
 
+    /// ErrWriterLockTimeout is returned when a write transaction waited longer
+    /// than Options.writer_lock_timeout for the writer lock. It lists the
+    /// open transactions, to find the one holding up writers.
+    #[error("timed out after {waited:?} waiting for the writer lock: {diagnostics}")]
+    WriterLockTimeout {
+        waited: Duration,
+        diagnostics: Box<LockDiagnostics>,
+    },
+
     /// ErrDatabaseReadOnly is returned when a mutating transaction is started on a
     /// read-only database.
     #[error("database is in read-only mode")]
@@ -102,6 +149,11 @@ pub enum BoltError {
     #[error("key required")]
     KeyRequired,
 
+    /// ErrKeyOutOfOrder is returned when a bulk loader is given a key that does
+    /// not sort after the previous one.
+    #[error("key out of order")]
+    KeyOutOfOrder,
+
     /// ErrKeyTooLarge is returned when inserting a key that is larger than MaxKeySize.
     #[error("key too large")]
     KeyTooLarge,
@@ -110,6 +162,11 @@ pub enum BoltError {
     #[error("value too large")]
     ValueTooLarge,
 
+    /// ErrValueMismatch is returned when a conditional write finds a value
+    /// other than the expected one.
+    #[error("value does not match")]
+    ValueMismatch,
+
     /// ErrIncompatibleValue is returned when trying to create or delete a bucket
     /// on an existing non-bucket key or when trying to create or delete a
     /// non-bucket key on an existing bucket key.
@@ -127,10 +184,278 @@ pub enum BoltError {
     DifferentDB,
 }
 
+impl BoltError {
+    /// io returns an error for a failed file operation.
+    pub(crate) fn io(op: IoOp, err: io::Error) -> Self {
+        Self::Io {
+            op,
+            context: ErrorContext::default(),
+            source: IoError(err),
+        }
+    }
+
+    /// corrupted returns an error for malformed data read from the file.
+    pub(crate) fn corrupted(reason: impl Into<String>) -> Self {
+        Self::Corrupted {
+            context: ErrorContext::default(),
+            reason: reason.into(),
+        }
+    }
+
+    /// context returns where in the database the error happened, for the
+    /// errors that carry a context.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Io { context, .. } | Self::Corrupted { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// with_pgid records the page the error happened at, unless it is known.
+    pub(crate) fn with_pgid(mut self, pgid: u64) -> Self {
+        if let Some(context) = self.context_mut() {
+            context.pgid.get_or_insert(pgid);
+        }
+        self
+    }
+
+    /// with_bucket records the path of the bucket the error happened in,
+    /// unless it is known.
+    pub(crate) fn with_bucket<K: AsRef<[u8]>>(mut self, path: &[K]) -> Self {
+        if let Some(context) = self.context_mut() {
+            if context.bucket.is_none() {
+                context.bucket = Some(path.iter().map(|name| name.as_ref().to_vec()).collect());
+            }
+        }
+        self
+    }
+
+    /// with_key records the key the error happened at, unless it is known.
+    /// Only the first bytes of the key are kept.
+    pub(crate) fn with_key(mut self, key: &[u8]) -> Self {
+        if let Some(context) = self.context_mut() {
+            context
+                .key
+                .get_or_insert_with(|| key[..key.len().min(KEY_PREFIX_LEN)].to_vec());
+        }
+        self
+    }
+
+    /// code returns a number identifying the kind of error, for callers that
+    /// cannot match on the enum, such as C code. Codes are stable across
+    /// releases: new errors get new codes and codes are never reused.
+    /// Hundreds group them: 1xx general, 2xx opening a database, 3xx
+    /// transactions, 4xx buckets and keys.
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Config(_) => 100,
+            Self::Io { .. } => 101,
+            Self::ResizeFail => 102,
+            Self::TxManaged => 103,
+            Self::StackEmpty => 104,
+            Self::CheckFailed(_) => 105,
+            Self::Codec(_) => 106,
+            Self::Encryption(_) => 107,
+            Self::Backup(_) => 108,
+            Self::Export(_) => 109,
+            Self::Corrupted { .. } => 110,
+            Self::TornWrite(_) => 111,
+            Self::Unexpected(_) => 112,
+            Self::DatabaseNotOpen => 200,
+            Self::Invalid => 201,
+            Self::InvalidMapping => 202,
+            Self::VersionMismatch => 203,
+            Self::Checksum => 204,
+            Self::Timeout => 205,
+            Self::TxNotWritable => 300,
+            Self::TxClosed => 301,
+            Self::WriterLockTimeout { .. } => 302,
+            Self::DatabaseReadOnly => 303,
+            Self::FreePagesNotLoaded => 304,
+            Self::BucketNotFound => 400,
+            Self::BucketExists => 401,
+            Self::BucketNameRequired => 402,
+            Self::KeyRequired => 403,
+            Self::KeyOutOfOrder => 404,
+            Self::KeyTooLarge => 405,
+            Self::ValueTooLarge => 406,
+            Self::ValueMismatch => 407,
+            Self::IncompatibleValue => 408,
+            Self::SameBuckets => 409,
+            Self::DifferentDB => 410,
+        }
+    }
+
+    /// is_retryable returns whether the operation may succeed if tried again
+    /// unchanged: a lock not taken in time, or an interrupted file operation.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout | Self::WriterLockTimeout { .. } => true,
+            Self::Io { source, .. } => matches!(
+                source.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+
+    /// is_corruption returns whether the error is caused by malformed data in
+    /// the file, which retrying cannot fix.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            Self::Invalid
+                | Self::Checksum
+                | Self::Corrupted { .. }
+                | Self::TornWrite(_)
+                | Self::CheckFailed(_)
+        )
+    }
+
+    fn context_mut(&mut self) -> Option<&mut ErrorContext> {
+        match self {
+            Self::Io { context, .. } | Self::Corrupted { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+/// ErrorContext locates an error in the database.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Id of the page the error happened at.
+    pub pgid: Option<u64>,
+    /// Names of the bucket the error happened in and of its ancestors, from
+    /// the root. Empty for the root bucket.
+    pub bucket: Option<Vec<Vec<u8>>>,
+    /// Prefix of the key the error happened at, at most 32 bytes long.
+    pub key: Option<Vec<u8>>,
+}
+
+// Display writes the known parts of the context, each with a leading space,
+// so that it can follow the description of an error.
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(pgid) = self.pgid {
+            write!(f, " at page {}", pgid)?;
+        }
+        if let Some(bucket) = self.bucket.as_ref().filter(|bucket| !bucket.is_empty()) {
+            write!(f, " in bucket \"")?;
+            for (i, name) in bucket.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "/")?;
+                }
+                write!(f, "{}", name.escape_ascii())?;
+            }
+            write!(f, "\"")?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, " for key \"{}\"", key.escape_ascii())?;
+        }
+        Ok(())
+    }
+}
+
+/// IoOp is the file operation an io error happened in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum IoOp {
+    /// Opening or creating a file.
+    Open,
+    /// Locking or unlocking a file.
+    Lock,
+    /// Reading from a file.
+    Read,
+    /// Writing to a file.
+    Write,
+    /// Flushing written data to the disk.
+    Sync,
+    /// Growing or truncating a file.
+    Resize,
+    /// Memory mapping a file.
+    Mmap,
+    /// Any other operation.
+    Other,
+}
+
+impl fmt::Display for IoOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IoOp::Open => "open",
+            IoOp::Lock => "lock",
+            IoOp::Read => "read",
+            IoOp::Write => "write",
+            IoOp::Sync => "sync",
+            IoOp::Resize => "resize",
+            IoOp::Mmap => "mmap",
+            IoOp::Other => "io",
+        })
+    }
+}
+
+/// IoError is the io::Error of a failed file operation. It makes BoltError
+/// comparable: two IoErrors are equal if they are of the same kind and have
+/// the same message. A clone keeps only the kind and message as well.
+#[derive(Debug)]
+pub struct IoError(io::Error);
+
+impl IoError {
+    /// kind returns the kind of the io::Error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+
+    /// get_ref returns the io::Error.
+    pub fn get_ref(&self) -> &io::Error {
+        &self.0
+    }
+
+    /// into_inner returns the io::Error.
+    pub fn into_inner(self) -> io::Error {
+        self.0
+    }
+}
+
+impl Clone for IoError {
+    fn clone(&self) -> Self {
+        IoError(io::Error::new(self.0.kind(), self.0.to_string()))
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.kind() == other.0.kind() && self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for IoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// IoResultExt attaches the operation to the error of an io::Result.
+pub(crate) trait IoResultExt<T> {
+    /// op converts the error into a BoltError::Io for operation op.
+    fn op(self, op: IoOp) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn op(self, op: IoOp) -> Result<T> {
+        self.map_err(|err| BoltError::io(op, err))
+    }
+}
+
 impl From<io::Error> for BoltError {
     #[inline]
     fn from(e: io::Error) -> Self {
-        Self::Io(e.kind().to_string())
+        Self::io(IoOp::Other, e)
     }
 }
 
@@ -146,3 +471,28 @@ pub type Result<T> = std::result::Result<T, BoltError>;
 // pub(crate) fn is_valid_error(err: &std::io::Error) -> bool {
 //     err.kind() == Uncategorized && err.to_string() == "Success (os error 0)"
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ensure that errors are classified and their codes are stable.
+    #[test]
+    fn test_error_classification() {
+        assert_eq!(BoltError::Timeout.code(), 205);
+        assert_eq!(BoltError::BucketNotFound.code(), 400);
+        assert_eq!(BoltError::corrupted("bad").code(), 110);
+
+        let interrupted = BoltError::io(IoOp::Read, io::ErrorKind::Interrupted.into());
+        let not_found = BoltError::io(IoOp::Open, io::ErrorKind::NotFound.into());
+        assert!(BoltError::Timeout.is_retryable());
+        assert!(interrupted.is_retryable());
+        assert!(!not_found.is_retryable());
+        assert!(!BoltError::corrupted("bad").is_retryable());
+
+        assert!(BoltError::corrupted("bad").is_corruption());
+        assert!(BoltError::Checksum.is_corruption());
+        assert!(!BoltError::VersionMismatch.is_corruption());
+        assert!(!interrupted.is_corruption());
+    }
+}
@@ -0,0 +1,228 @@
+//! Exporting and importing single buckets.
+//!
+//! Bucket::export writes the keys and nested buckets of a bucket to a stream,
+//! which Tx::import_bucket reads back into a new bucket, possibly of another
+//! database. An export is laid out as:
+//!
+//! ```text
+//! magic "bboltbkt" | sequence u64
+//! records, ending with 'e'
+//! ```
+//!
+//! with records:
+//!
+//! ```text
+//! 'k' | key | value               a key
+//! 't' | key | value | ttl u64     a key expiring after ttl milliseconds
+//! 'b' | name | sequence u64       a nested bucket, followed by its records
+//! 'e'                             the end of the bucket
+//! ```
+//!
+//! Keys, values and names are written as a u32 length followed by the bytes.
+//! All integers are little-endian. Values are written as get returns them,
+//! so they are decompressed by the handle of the export and compressed again
+//! by the handle of the import.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::bucket::Bucket;
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::tx::Tx;
+
+const MAGIC: &[u8; 8] = b"bboltbkt";
+
+const KEY: u8 = b'k';
+const TTL_KEY: u8 = b't';
+const BUCKET: u8 = b'b';
+const END: u8 = b'e';
+
+impl<'tx> Bucket<'tx> {
+    /// export writes the keys and nested buckets of the bucket to a writer,
+    /// with the sequence of the buckets and the TTLs of keys. Expired keys
+    /// are left out. Returns the number of bytes written.
+    pub fn export<W: Write>(&self, w: &mut W) -> Result<u64> {
+        let mut w = CountingWriter { inner: w, n: 0 };
+        w.write_all(MAGIC)?;
+        w.write_all(&self.sequence().to_le_bytes())?;
+        export_bucket(self, &mut w)?;
+        Ok(w.n)
+    }
+
+    /// import_bucket creates the nested bucket name from an export written by
+    /// Bucket::export, and returns it.
+    /// Returns the errors of create_bucket, and BoltError::Export if the
+    /// export is malformed.
+    pub fn import_bucket<R: Read>(&self, name: &[u8], r: &mut R) -> Result<Bucket<'tx>> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic).op(IoOp::Read)?;
+        if &magic != MAGIC {
+            return Err(BoltError::Export("not a bucket export".to_string()));
+        }
+        let b = self.create_bucket(name)?;
+        b.set_sequence(read_u64(r)?)?;
+        import_bucket(&b, r)?;
+        Ok(b)
+    }
+}
+
+impl Tx {
+    /// import_bucket creates the bucket name from an export written by
+    /// Bucket::export, and returns it.
+    /// Returns the errors of Bucket::import_bucket.
+    pub fn import_bucket<R: Read>(&self, name: &[u8], r: &mut R) -> Result<Bucket<'_>> {
+        self.root().import_bucket(name, r)
+    }
+}
+
+// export_bucket writes the records of a bucket.
+fn export_bucket<W: Write>(b: &Bucket, w: &mut W) -> Result<()> {
+    b.for_each(|k, v| {
+        match v {
+            Some(v) => {
+                let ttl = b.ttl(k);
+                w.write_all(&[if ttl.is_some() { TTL_KEY } else { KEY }])?;
+                write_bytes(w, k)?;
+                write_bytes(w, v)?;
+                if let Some(ttl) = ttl {
+                    w.write_all(&(ttl.as_millis() as u64).to_le_bytes())?;
+                }
+            }
+            None => {
+                let child = b.bucket(k).ok_or(BoltError::BucketNotFound)?;
+                w.write_all(&[BUCKET])?;
+                write_bytes(w, k)?;
+                w.write_all(&child.sequence().to_le_bytes())?;
+                export_bucket(&child, w)?;
+            }
+        }
+        Ok(())
+    })?;
+    w.write_all(&[END])?;
+    Ok(())
+}
+
+// import_bucket reads the records of a bucket into b.
+fn import_bucket<R: Read>(b: &Bucket, r: &mut R) -> Result<()> {
+    loop {
+        let mut tag = [0u8];
+        r.read_exact(&mut tag).op(IoOp::Read)?;
+        match tag[0] {
+            KEY => b.put(&read_bytes(r)?, &read_bytes(r)?)?,
+            TTL_KEY => {
+                let (k, v) = (read_bytes(r)?, read_bytes(r)?);
+                let ttl = Duration::from_millis(read_u64(r)?);
+                b.put_with_ttl(&k, &v, ttl)?;
+            }
+            BUCKET => {
+                let child = b.create_bucket(&read_bytes(r)?)?;
+                child.set_sequence(read_u64(r)?)?;
+                import_bucket(&child, r)?;
+            }
+            END => return Ok(()),
+            tag => return Err(BoltError::Export(format!("unknown record {:#04x}", tag))),
+        }
+    }
+}
+
+fn write_bytes<W: Write>(w: &mut W, buf: &[u8]) -> Result<()> {
+    w.write_all(&(buf.len() as u32).to_le_bytes())?;
+    w.write_all(buf)?;
+    Ok(())
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len).op(IoOp::Read)?;
+    let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut buf).op(IoOp::Read)?;
+    Ok(buf)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf).op(IoOp::Read)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// CountingWriter counts the bytes written through it.
+struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    n: u64,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.n += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // Ensure that a bucket exported from one database is imported into
+    // another with its nested buckets, sequences and TTLs.
+    #[test]
+    fn test_export_import_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        let other = DB::open(dir.path().join("other"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.set_sequence(7)?;
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.put_with_ttl(b"ttl", b"v", Duration::from_secs(60))?;
+            let sub = b.create_bucket(b"sub")?;
+            sub.set_sequence(3)?;
+            sub.put(b"foo", b"bar")
+        })
+        .unwrap();
+
+        let mut buf = Vec::new();
+        let n = db
+            .view(|tx| tx.bucket(b"widgets").unwrap().export(&mut buf))
+            .unwrap();
+        assert_eq!(n, buf.len() as u64);
+
+        let tx = other.begin_rw().unwrap();
+        tx.import_bucket(b"imported", &mut &buf[..]).unwrap();
+        assert_eq!(
+            tx.import_bucket(b"imported", &mut &buf[..]).err(),
+            Some(BoltError::BucketExists)
+        );
+        let truncated = &buf[..buf.len() - 1];
+        assert!(tx.import_bucket(b"partial", &mut &truncated[..]).is_err());
+        assert!(matches!(
+            tx.import_bucket(b"garbage", &mut &b"not an export"[..]),
+            Err(BoltError::Export(_))
+        ));
+        tx.rollback().unwrap();
+
+        other
+            .update(|tx| tx.import_bucket(b"imported", &mut &buf[..]).map(|_| ()))
+            .unwrap();
+        other
+            .view(|tx| {
+                assert_eq!(tx.check()?.errors, vec![]);
+                let b = tx.bucket(b"imported").unwrap();
+                assert_eq!(b.sequence(), 7);
+                assert_eq!(b.get(b"0999"), Some(&[0x5a; 100][..]));
+                assert!(b.ttl(b"ttl").is_some());
+                let sub = b.bucket(b"sub").unwrap();
+                assert_eq!(sub.sequence(), 3);
+                assert_eq!(sub.get(b"foo"), Some(&b"bar"[..]));
+                Ok(())
+            })
+            .unwrap();
+    }
+}
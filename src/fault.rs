@@ -0,0 +1,247 @@
+//! Injecting io errors.
+//!
+//! FaultVfs wraps a storage and fails the calls that the registered faults
+//! select, so that the error paths of a database can be tested: a commit
+//! failing halfway through writing its pages, a sync failing once, a file
+//! that cannot grow. Calls are counted per operation, as classified by IoOp:
+//!
+//! ```ignore
+//! let vfs = Arc::new(FaultVfs::new(Arc::new(MemoryVfs::new())));
+//! vfs.inject(IoOp::Write, Fault::Nth(3));
+//! vfs.inject(IoOp::Sync, Fault::Once);
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::errors::{BoltError, IoOp, Result};
+use crate::vfs::{MapOptions, SyncStrategy, Vfs};
+
+/// Fault selects which calls of an operation fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Once fails the next call.
+    Once,
+    /// Nth fails the nth call from now, counting from 1.
+    Nth(usize),
+    /// Always fails every call until the fault is cleared.
+    Always,
+}
+
+/// FaultVfs is a storage failing the calls selected by its faults, and
+/// passing the others on to the storage it wraps.
+#[derive(Debug)]
+pub struct FaultVfs {
+    inner: Arc<dyn Vfs>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // Number of calls made of each operation.
+    calls: HashMap<IoOp, usize>,
+    // Faults with the call of their operation they fail, if not every one.
+    faults: Vec<(IoOp, Option<usize>)>,
+}
+
+impl FaultVfs {
+    /// new wraps a storage, without any faults.
+    pub fn new(inner: Arc<dyn Vfs>) -> FaultVfs {
+        FaultVfs {
+            inner,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// inject registers a fault for an operation. Faults of the same
+    /// operation add up.
+    pub fn inject(&self, op: IoOp, fault: Fault) {
+        let mut state = self.state.lock().unwrap();
+        let calls = state.calls.get(&op).copied().unwrap_or(0);
+        let call = match fault {
+            Fault::Once => Some(calls + 1),
+            Fault::Nth(n) => Some(calls + n.max(1)),
+            Fault::Always => None,
+        };
+        state.faults.push((op, call));
+    }
+
+    /// clear removes every fault.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// calls returns the number of calls made of an operation, failed ones
+    /// included.
+    pub fn calls(&self, op: IoOp) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .get(&op)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // check counts a call of op and fails it if a fault selects it.
+    fn check(&self, op: IoOp) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let calls = state.calls.entry(op).or_default();
+        *calls += 1;
+        let call = *calls;
+        let before = state.faults.len();
+        state
+            .faults
+            .retain(|&(fault_op, fault_call)| fault_op != op || fault_call != Some(call));
+        let always = state.faults.contains(&(op, None));
+        match before != state.faults.len() || always {
+            true => Err(io::Error::other(format!("injected {} error", op))),
+            false => Ok(()),
+        }
+    }
+
+    // check_lock is check for the operations returning a BoltError.
+    fn check_lock(&self) -> Result<()> {
+        self.check(IoOp::Lock)
+            .map_err(|err| BoltError::io(IoOp::Lock, err))
+    }
+}
+
+impl Vfs for FaultVfs {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.check(IoOp::Read)?;
+        self.inner.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.check(IoOp::Write)?;
+        self.inner.write_at(buf, offset)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.check(IoOp::Sync)?;
+        self.inner.sync()
+    }
+
+    fn sync_with(&self, strategy: SyncStrategy) -> io::Result<()> {
+        if strategy != SyncStrategy::None {
+            self.check(IoOp::Sync)?;
+        }
+        self.inner.sync_with(strategy)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        self.check(IoOp::Other)?;
+        self.inner.len()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.check(IoOp::Resize)?;
+        self.inner.truncate(len)
+    }
+
+    fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()> {
+        self.check_lock()?;
+        self.inner.lock(exclusive, timeout)
+    }
+
+    fn unlock(&self) -> Result<()> {
+        self.check_lock()?;
+        self.inner.unlock()
+    }
+
+    fn map(&self, len: usize) -> io::Result<Bytes> {
+        self.check(IoOp::Mmap)?;
+        self.inner.map(len)
+    }
+
+    fn map_with(&self, len: usize, options: MapOptions) -> io::Result<Bytes> {
+        self.check(IoOp::Mmap)?;
+        self.inner.map_with(len, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+    use crate::vfs::MemoryVfs;
+
+    fn open(vfs: &Arc<FaultVfs>) -> DB {
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            ..Default::default()
+        };
+        DB::open("fault", options).unwrap()
+    }
+
+    fn put(db: &DB, value: &[u8]) -> Result<()> {
+        db.update(|tx| {
+            let b = tx.create_bucket_if_not_exists(b"widgets")?;
+            for i in 0..100u32 {
+                b.put(format!("{:04}", i).as_bytes(), &value.repeat(100))?;
+            }
+            Ok(())
+        })
+    }
+
+    // Ensure that a commit failing while writing its pages, or syncing them,
+    // leaves the previous transaction in place and the database usable.
+    #[test]
+    fn test_fault_vfs_failed_commit() {
+        let vfs = Arc::new(FaultVfs::new(Arc::new(MemoryVfs::new())));
+        let db = open(&vfs);
+        put(&db, b"a").unwrap();
+
+        for fault in [(IoOp::Write, Fault::Nth(2)), (IoOp::Sync, Fault::Once)] {
+            vfs.inject(fault.0, fault.1);
+            match put(&db, b"b") {
+                Err(BoltError::Io { op, .. }) => assert_eq!(op, fault.0),
+                result => panic!("unexpected result: {:?}", result),
+            }
+            db.view(|tx| {
+                assert_eq!(tx.check()?.errors, vec![]);
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.get(b"0099"), Some(&b"a".repeat(100)[..]));
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        put(&db, b"c").unwrap();
+        db.close().unwrap();
+        let db = open(&vfs);
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"0000"), Some(&b"c".repeat(100)[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that faults fail the calls they select and no others.
+    #[test]
+    fn test_fault_vfs_counting() {
+        let vfs = FaultVfs::new(Arc::new(MemoryVfs::new()));
+        vfs.inject(IoOp::Write, Fault::Nth(2));
+        vfs.inject(IoOp::Sync, Fault::Always);
+        assert!(vfs.write_at(b"a", 0).is_ok());
+        assert!(vfs.write_at(b"b", 1).is_err());
+        assert!(vfs.write_at(b"c", 2).is_ok());
+        assert_eq!(vfs.calls(IoOp::Write), 3);
+        assert!(vfs.sync().is_err());
+        assert!(vfs.sync().is_err());
+        assert!(vfs.sync_with(SyncStrategy::None).is_ok());
+
+        vfs.clear();
+        assert!(vfs.sync().is_ok());
+        let mut buf = [0u8; 3];
+        assert_eq!(vfs.read_at(&mut buf, 0).unwrap(), 3);
+        assert_eq!(&buf, b"a\0c");
+    }
+}
@@ -0,0 +1,691 @@
+//! C interface.
+//!
+//! With the ffi feature the crate exports functions to open a database, run
+//! transactions, and read and write the keys of its top-level buckets from
+//! C, C++ or anything else that calls C. include/boltdb.h declares them.
+//!
+//! Every function returns a BoltStatus; on failure the message of the error
+//! is kept for the calling thread until its next call and is returned by
+//! bolt_last_error. Byte strings are passed as a pointer and a length, and
+//! the pointer may be NULL when the length is zero.
+//!
+//! Ownership follows the handles:
+//!
+//! - A BoltDb returned by bolt_open is owned by the caller until it is passed
+//!   to bolt_close, which frees it even if closing fails.
+//! - A BoltTx returned by bolt_begin is owned by the caller until it is
+//!   passed to bolt_commit or bolt_rollback, which free it either way. It
+//!   must be ended before its database is closed.
+//! - A BoltCursor returned by bolt_cursor_open is owned by the caller until
+//!   it is passed to bolt_cursor_close. Ending its transaction closes it:
+//!   moving it returns BoltStatus::Closed from then on, and it still has to
+//!   be freed with bolt_cursor_close.
+//! - Keys and values handed out by bolt_get and the cursor functions belong
+//!   to the transaction. They are valid until it is ended.
+//!
+//! Handles must not be used from two threads at once, and a transaction must
+//! be used on the thread that began it. Passing a freed or foreign handle is
+//! undefined behavior, as in C.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+use crate::cursor::Cursor;
+use crate::db::{Options, DB};
+use crate::errors::BoltError;
+use crate::tx::Tx;
+
+/// BoltStatus is the outcome of a call. The values are stable.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoltStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// The key or bucket does not exist, or the cursor moved past the end.
+    NotFound = 1,
+    /// The bucket already exists.
+    Exists = 2,
+    /// An argument is NULL, empty, too large or of the wrong kind.
+    InvalidArgument = 3,
+    /// A write was attempted in a read-only transaction or database.
+    NotWritable = 4,
+    /// The database or transaction is closed.
+    Closed = 5,
+    /// A lock could not be taken in time.
+    Timeout = 6,
+    /// The file is not a database, or is corrupted.
+    Corrupted = 7,
+    /// An operation on the file failed.
+    Io = 8,
+    /// The library panicked. The handles passed should not be used again.
+    Panic = 9,
+    /// Any other error.
+    Other = 10,
+}
+
+impl From<&BoltError> for BoltStatus {
+    fn from(err: &BoltError) -> BoltStatus {
+        match err {
+            BoltError::BucketNotFound => BoltStatus::NotFound,
+            BoltError::BucketExists => BoltStatus::Exists,
+            BoltError::Config(_)
+            | BoltError::BucketNameRequired
+            | BoltError::KeyRequired
+            | BoltError::KeyOutOfOrder
+            | BoltError::KeyTooLarge
+            | BoltError::ValueTooLarge
+            | BoltError::IncompatibleValue => BoltStatus::InvalidArgument,
+            BoltError::TxNotWritable | BoltError::DatabaseReadOnly => BoltStatus::NotWritable,
+            BoltError::DatabaseNotOpen | BoltError::TxClosed => BoltStatus::Closed,
+            BoltError::Timeout | BoltError::WriterLockTimeout { .. } => BoltStatus::Timeout,
+            BoltError::Invalid
+            | BoltError::InvalidMapping
+            | BoltError::VersionMismatch
+            | BoltError::Checksum
+            | BoltError::Corrupted { .. }
+            | BoltError::TornWrite(_) => BoltStatus::Corrupted,
+            BoltError::Io { .. } | BoltError::ResizeFail => BoltStatus::Io,
+            _ => BoltStatus::Other,
+        }
+    }
+}
+
+/// BoltDb is an open database.
+pub struct BoltDb(DB);
+
+/// BoltTx is a transaction of a database.
+pub struct BoltTx {
+    tx: Tx,
+    // Cleared when the transaction ends, which closes its cursors.
+    open: Rc<Cell<bool>>,
+}
+
+impl BoltTx {
+    // end marks the transaction as ended and returns it, so that it can be
+    // committed or rolled back.
+    fn end(self) -> Tx {
+        self.open.set(false);
+        self.tx
+    }
+}
+
+/// BoltCursor is a cursor over a top-level bucket.
+pub struct BoltCursor {
+    cursor: Cursor<'static>,
+    // Whether the transaction of the cursor is still open.
+    open: Rc<Cell<bool>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+    static LAST_ERROR_CODE: Cell<u32> = const { Cell::new(0) };
+}
+
+// Failure is why a call failed.
+enum Failure {
+    Status(BoltStatus, &'static str),
+    Bolt(BoltError),
+}
+
+impl From<BoltError> for Failure {
+    fn from(err: BoltError) -> Failure {
+        Failure::Bolt(err)
+    }
+}
+
+// call runs the body of a function, turning its outcome, or a panic, into a
+// status and keeping the message of a failure for bolt_last_error.
+fn call<F: FnOnce() -> Result<(), Failure>>(f: F) -> BoltStatus {
+    let (status, code, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (BoltStatus::Ok, 0, None),
+        Ok(Err(Failure::Status(status, message))) => (status, 0, Some(message.to_string())),
+        Ok(Err(Failure::Bolt(err))) => (BoltStatus::from(&err), err.code(), Some(err.to_string())),
+        Err(_) => (BoltStatus::Panic, 0, Some("panic".to_string())),
+    };
+    let message = message.map(|m| CString::new(m.replace('\0', " ")).unwrap());
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    LAST_ERROR_CODE.with(|last| last.set(code));
+    status
+}
+
+// bytes returns the byte string at ptr.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(Failure::Status(BoltStatus::InvalidArgument, "null pointer")),
+        (false, _) => Ok(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+// handle returns the handle behind ptr.
+unsafe fn handle<'a, T>(ptr: *mut T) -> Result<&'a mut T, Failure> {
+    ptr.as_mut()
+        .ok_or(Failure::Status(BoltStatus::InvalidArgument, "null handle"))
+}
+
+// live_cursor returns the cursor behind ptr, unless its transaction has
+// ended.
+unsafe fn live_cursor<'a>(ptr: *mut BoltCursor) -> Result<&'a mut Cursor<'static>, Failure> {
+    let cursor = handle(ptr)?;
+    if !cursor.open.get() {
+        return Err(Failure::Status(BoltStatus::Closed, "transaction ended"));
+    }
+    Ok(&mut cursor.cursor)
+}
+
+// out writes a result through an out pointer.
+unsafe fn out<T>(ptr: *mut T, value: T) -> Result<(), Failure> {
+    if ptr.is_null() {
+        return Err(Failure::Status(BoltStatus::InvalidArgument, "null pointer"));
+    }
+    ptr.write(value);
+    Ok(())
+}
+
+// item writes a key and value through out pointers, either of which may be
+// NULL. Nested buckets have a NULL value.
+unsafe fn item(
+    item: Option<(&[u8], Option<&[u8]>)>,
+    key: *mut *const u8,
+    key_len: *mut usize,
+    value: *mut *const u8,
+    value_len: *mut usize,
+) -> Result<(), Failure> {
+    let (k, v) = item.ok_or(Failure::Status(BoltStatus::NotFound, "no more items"))?;
+    let v = v.map_or((ptr::null(), 0), |v| (v.as_ptr(), v.len()));
+    for (ptr, len, (p, l)) in [(key, key_len, (k.as_ptr(), k.len())), (value, value_len, v)] {
+        if !ptr.is_null() {
+            ptr.write(p);
+        }
+        if !len.is_null() {
+            len.write(l);
+        }
+    }
+    Ok(())
+}
+
+/// bolt_last_error returns the message of the last failed call of the thread,
+/// or NULL if it succeeded. The string is valid until the next call.
+#[no_mangle]
+pub extern "C" fn bolt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// bolt_last_error_code returns the code of the error of the last failed
+/// call of the thread, as BoltError::code returns it, or zero if the call
+/// succeeded or failed before reaching the database.
+#[no_mangle]
+pub extern "C" fn bolt_last_error_code() -> u32 {
+    LAST_ERROR_CODE.with(|last| last.get())
+}
+
+/// bolt_open opens the database at path, a NUL-terminated UTF-8 string,
+/// creating it if it does not exist and read_only is false.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_open(
+    path: *const c_char,
+    read_only: bool,
+    db: *mut *mut BoltDb,
+) -> BoltStatus {
+    call(|| {
+        if path.is_null() {
+            return Err(Failure::Status(BoltStatus::InvalidArgument, "null path"));
+        }
+        let path = CStr::from_ptr(path)
+            .to_str()
+            .map_err(|_| Failure::Status(BoltStatus::InvalidArgument, "path is not UTF-8"))?;
+        let options = Options {
+            read_only,
+            ..Default::default()
+        };
+        let handle = Box::new(BoltDb(DB::open(path, options)?));
+        out(db, Box::into_raw(handle))
+    })
+}
+
+/// bolt_close closes a database and frees its handle.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_close(db: *mut BoltDb) -> BoltStatus {
+    call(|| {
+        handle(db)?;
+        let db = Box::from_raw(db);
+        db.0.close()?;
+        Ok(())
+    })
+}
+
+/// bolt_begin begins a transaction, a write transaction if writable is set.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_begin(
+    db: *mut BoltDb,
+    writable: bool,
+    tx: *mut *mut BoltTx,
+) -> BoltStatus {
+    call(|| {
+        let handle = Box::new(BoltTx {
+            tx: handle(db)?.0.begin(writable)?,
+            open: Rc::new(Cell::new(true)),
+        });
+        out(tx, Box::into_raw(handle))
+    })
+}
+
+/// bolt_commit commits a write transaction, closes its cursors and frees
+/// its handle.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_commit(tx: *mut BoltTx) -> BoltStatus {
+    call(|| {
+        handle(tx)?;
+        Box::from_raw(tx).end().commit()?;
+        Ok(())
+    })
+}
+
+/// bolt_rollback ends a transaction without committing it, closes its
+/// cursors and frees its handle.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_rollback(tx: *mut BoltTx) -> BoltStatus {
+    call(|| {
+        handle(tx)?;
+        Box::from_raw(tx).end().rollback()?;
+        Ok(())
+    })
+}
+
+/// bolt_create_bucket creates a top-level bucket if it does not exist yet.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_create_bucket(
+    tx: *mut BoltTx,
+    name: *const u8,
+    name_len: usize,
+) -> BoltStatus {
+    call(|| {
+        let tx = &handle(tx)?.tx;
+        tx.create_bucket_if_not_exists(bytes(name, name_len)?)?;
+        Ok(())
+    })
+}
+
+/// bolt_delete_bucket deletes a top-level bucket and everything in it.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_delete_bucket(
+    tx: *mut BoltTx,
+    name: *const u8,
+    name_len: usize,
+) -> BoltStatus {
+    call(|| {
+        let tx = &handle(tx)?.tx;
+        tx.delete_bucket(bytes(name, name_len)?)?;
+        Ok(())
+    })
+}
+
+/// bolt_put sets the value of a key in a top-level bucket.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_put(
+    tx: *mut BoltTx,
+    bucket: *const u8,
+    bucket_len: usize,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> BoltStatus {
+    call(|| {
+        let tx = &handle(tx)?.tx;
+        let b = tx.bucket(bytes(bucket, bucket_len)?);
+        let b = b.ok_or(BoltError::BucketNotFound)?;
+        b.put(bytes(key, key_len)?, bytes(value, value_len)?)?;
+        Ok(())
+    })
+}
+
+/// bolt_get looks up the value of a key in a top-level bucket. Returns
+/// BoltStatus::NotFound if the key does not exist or is a nested bucket.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_get(
+    tx: *mut BoltTx,
+    bucket: *const u8,
+    bucket_len: usize,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *const u8,
+    value_len: *mut usize,
+) -> BoltStatus {
+    call(|| {
+        let tx = &handle(tx)?.tx;
+        let b = tx.bucket(bytes(bucket, bucket_len)?);
+        let b = b.ok_or(BoltError::BucketNotFound)?;
+        let v = b.get(bytes(key, key_len)?);
+        let v = v.ok_or(Failure::Status(BoltStatus::NotFound, "key not found"))?;
+        out(value, v.as_ptr())?;
+        out(value_len, v.len())
+    })
+}
+
+/// bolt_delete removes a key from a top-level bucket. Deleting a key that
+/// does not exist succeeds.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_delete(
+    tx: *mut BoltTx,
+    bucket: *const u8,
+    bucket_len: usize,
+    key: *const u8,
+    key_len: usize,
+) -> BoltStatus {
+    call(|| {
+        let tx = &handle(tx)?.tx;
+        let b = tx.bucket(bytes(bucket, bucket_len)?);
+        b.ok_or(BoltError::BucketNotFound)?
+            .delete(bytes(key, key_len)?)?;
+        Ok(())
+    })
+}
+
+/// bolt_cursor_open creates a cursor over a top-level bucket.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_cursor_open(
+    tx: *mut BoltTx,
+    bucket: *const u8,
+    bucket_len: usize,
+    cursor: *mut *mut BoltCursor,
+) -> BoltStatus {
+    call(|| {
+        // The cursor is only moved while the transaction is open, see
+        // live_cursor.
+        let handle: &'static BoltTx = handle(tx)?;
+        let b = handle.tx.bucket(bytes(bucket, bucket_len)?);
+        let b = b.ok_or(BoltError::BucketNotFound)?;
+        let handle = BoltCursor {
+            cursor: b.cursor(),
+            open: handle.open.clone(),
+        };
+        out(cursor, Box::into_raw(Box::new(handle)))
+    })
+}
+
+/// bolt_cursor_close frees a cursor, whether or not its transaction has
+/// ended.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_cursor_close(cursor: *mut BoltCursor) {
+    if !cursor.is_null() {
+        drop(Box::from_raw(cursor));
+    }
+}
+
+macro_rules! cursor_move {
+    ($(#[$doc:meta])* $name:ident, $method:ident) => {
+        $(#[$doc])*
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(
+            cursor: *mut BoltCursor,
+            key: *mut *const u8,
+            key_len: *mut usize,
+            value: *mut *const u8,
+            value_len: *mut usize,
+        ) -> BoltStatus {
+            call(|| item(live_cursor(cursor)?.$method(), key, key_len, value, value_len))
+        }
+    };
+}
+
+cursor_move!(
+    /// bolt_cursor_first moves the cursor to the first key of the bucket.
+    /// Returns BoltStatus::NotFound if the bucket is empty.
+    bolt_cursor_first,
+    first
+);
+cursor_move!(
+    /// bolt_cursor_last moves the cursor to the last key of the bucket.
+    /// Returns BoltStatus::NotFound if the bucket is empty.
+    bolt_cursor_last,
+    last
+);
+cursor_move!(
+    /// bolt_cursor_next moves the cursor to the next key. Returns
+    /// BoltStatus::NotFound at the end of the bucket.
+    bolt_cursor_next,
+    next
+);
+cursor_move!(
+    /// bolt_cursor_prev moves the cursor to the previous key. Returns
+    /// BoltStatus::NotFound at the start of the bucket.
+    bolt_cursor_prev,
+    prev
+);
+
+/// bolt_cursor_seek moves the cursor to the first key at or after seek.
+/// Returns BoltStatus::NotFound if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn bolt_cursor_seek(
+    cursor: *mut BoltCursor,
+    seek: *const u8,
+    seek_len: usize,
+    key: *mut *const u8,
+    key_len: *mut usize,
+    value: *mut *const u8,
+    value_len: *mut usize,
+) -> BoltStatus {
+    call(|| {
+        let seek = bytes(seek, seek_len)?;
+        item(
+            live_cursor(cursor)?.seek(seek),
+            key,
+            key_len,
+            value,
+            value_len,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn last_error() -> String {
+        CStr::from_ptr(bolt_last_error())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Ensure that a database can be written and read through the C interface.
+    #[test]
+    fn test_ffi() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = CString::new(dir.path().join("db").to_str().unwrap()).unwrap();
+        unsafe {
+            let mut db = ptr::null_mut();
+            assert_eq!(bolt_open(path.as_ptr(), false, &mut db), BoltStatus::Ok);
+            assert!(bolt_last_error().is_null());
+
+            let mut tx = ptr::null_mut();
+            assert_eq!(bolt_begin(db, true, &mut tx), BoltStatus::Ok);
+            assert_eq!(
+                bolt_create_bucket(tx, b"widgets".as_ptr(), 7),
+                BoltStatus::Ok
+            );
+            for (k, v) in [("foo", "bar"), ("baz", "bat"), ("qux", "")] {
+                let status = bolt_put(
+                    tx,
+                    b"widgets".as_ptr(),
+                    7,
+                    k.as_ptr(),
+                    3,
+                    v.as_ptr(),
+                    v.len(),
+                );
+                assert_eq!(status, BoltStatus::Ok);
+            }
+            let status = bolt_put(tx, b"widgets".as_ptr(), 7, ptr::null(), 0, ptr::null(), 0);
+            assert_eq!(status, BoltStatus::InvalidArgument);
+            assert_eq!(last_error(), "key required");
+            assert_eq!(bolt_last_error_code(), BoltError::KeyRequired.code());
+            let status = bolt_put(tx, b"nothing".as_ptr(), 7, b"k".as_ptr(), 1, ptr::null(), 0);
+            assert_eq!(status, BoltStatus::NotFound);
+            assert_eq!(bolt_commit(tx), BoltStatus::Ok);
+
+            assert_eq!(bolt_begin(db, false, &mut tx), BoltStatus::Ok);
+            let (mut value, mut value_len) = (ptr::null(), 0);
+            let status = bolt_get(
+                tx,
+                b"widgets".as_ptr(),
+                7,
+                b"foo".as_ptr(),
+                3,
+                &mut value,
+                &mut value_len,
+            );
+            assert_eq!(status, BoltStatus::Ok);
+            assert_eq!(slice::from_raw_parts(value, value_len), b"bar");
+            let status = bolt_get(
+                tx,
+                b"widgets".as_ptr(),
+                7,
+                b"zzz".as_ptr(),
+                3,
+                &mut value,
+                &mut value_len,
+            );
+            assert_eq!(status, BoltStatus::NotFound);
+            let status = bolt_put(tx, b"widgets".as_ptr(), 7, b"k".as_ptr(), 1, ptr::null(), 0);
+            assert_eq!(status, BoltStatus::NotWritable);
+
+            let mut cursor = ptr::null_mut();
+            assert_eq!(
+                bolt_cursor_open(tx, b"widgets".as_ptr(), 7, &mut cursor),
+                BoltStatus::Ok
+            );
+            let (mut key, mut key_len) = (ptr::null(), 0);
+            let mut keys = Vec::new();
+            let mut status = bolt_cursor_first(
+                cursor,
+                &mut key,
+                &mut key_len,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            while status == BoltStatus::Ok {
+                keys.push(slice::from_raw_parts(key, key_len).to_vec());
+                status = bolt_cursor_next(
+                    cursor,
+                    &mut key,
+                    &mut key_len,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                );
+            }
+            assert_eq!(status, BoltStatus::NotFound);
+            assert_eq!(
+                keys,
+                vec![b"baz".to_vec(), b"foo".to_vec(), b"qux".to_vec()]
+            );
+            let status = bolt_cursor_seek(
+                cursor,
+                b"c".as_ptr(),
+                1,
+                &mut key,
+                &mut key_len,
+                &mut value,
+                &mut value_len,
+            );
+            assert_eq!(status, BoltStatus::Ok);
+            assert_eq!(slice::from_raw_parts(key, key_len), b"foo");
+            bolt_cursor_close(cursor);
+            assert_eq!(bolt_rollback(tx), BoltStatus::Ok);
+
+            assert_eq!(bolt_close(db), BoltStatus::Ok);
+            assert_eq!(
+                bolt_open(ptr::null(), false, &mut db),
+                BoltStatus::InvalidArgument
+            );
+        }
+    }
+
+    // Ensure that values stay valid until their transaction ends, even as it
+    // keeps writing, and that ending a transaction closes its cursors.
+    #[test]
+    fn test_ffi_tx_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = CString::new(dir.path().join("db").to_str().unwrap()).unwrap();
+        unsafe {
+            let mut db = ptr::null_mut();
+            assert_eq!(bolt_open(path.as_ptr(), false, &mut db), BoltStatus::Ok);
+            let mut tx = ptr::null_mut();
+            assert_eq!(bolt_begin(db, true, &mut tx), BoltStatus::Ok);
+            assert_eq!(
+                bolt_create_bucket(tx, b"widgets".as_ptr(), 7),
+                BoltStatus::Ok
+            );
+            let put = |tx, k: &[u8], v: &[u8]| {
+                bolt_put(
+                    tx,
+                    b"widgets".as_ptr(),
+                    7,
+                    k.as_ptr(),
+                    k.len(),
+                    v.as_ptr(),
+                    v.len(),
+                )
+            };
+            assert_eq!(put(tx, b"foo", b"bar"), BoltStatus::Ok);
+            let (mut value, mut value_len) = (ptr::null(), 0);
+            let status = bolt_get(
+                tx,
+                b"widgets".as_ptr(),
+                7,
+                b"foo".as_ptr(),
+                3,
+                &mut value,
+                &mut value_len,
+            );
+            assert_eq!(status, BoltStatus::Ok);
+            assert_eq!(put(tx, b"foo", b"baz"), BoltStatus::Ok);
+            for i in 0..1000u32 {
+                assert_eq!(put(tx, &i.to_be_bytes(), &[0x5a; 100]), BoltStatus::Ok);
+            }
+            assert_eq!(slice::from_raw_parts(value, value_len), b"bar");
+
+            let mut cursor = ptr::null_mut();
+            assert_eq!(
+                bolt_cursor_open(tx, b"widgets".as_ptr(), 7, &mut cursor),
+                BoltStatus::Ok
+            );
+            let status = bolt_cursor_first(
+                cursor,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            assert_eq!(status, BoltStatus::Ok);
+            assert_eq!(bolt_commit(tx), BoltStatus::Ok);
+            let status = bolt_cursor_next(
+                cursor,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            assert_eq!(status, BoltStatus::Closed);
+            assert_eq!(last_error(), "transaction ended");
+            let status = bolt_cursor_seek(
+                cursor,
+                b"foo".as_ptr(),
+                3,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            assert_eq!(status, BoltStatus::Closed);
+            bolt_cursor_close(cursor);
+
+            assert_eq!(bolt_close(db), BoltStatus::Ok);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Versions of the file format.
+//!
+//! Version 2 follows the layout of Go bbolt, see the crate docs on
+//! compatibility. Version 3 adds format extensions, recorded in the flags of
+//! the meta pages: branch prefix compression and meta page checksums other
+//! than FNV-1a. New databases are created with version 3 only when they use
+//! one of them, so that they keep the version 2 layout otherwise. Value
+//! compression needs no particular version, since it is recorded in each
+//! compressed value.
+//!
+//! A database of a version this build does not know, or using an extension
+//! it does not know, is refused with VersionMismatch before anything is
+//! written to it. DB::upgrade_format moves a database between versions.
+
+use crate::checksum::{ChecksumType, META_CHECKSUM_MASK};
+use crate::common::types::{FEATURE_BRANCH_PREFIX, KNOWN_FEATURES, VERSION, VERSION_FEATURES};
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+
+/// MIN_FORMAT_VERSION is the oldest format version this build can open.
+pub const MIN_FORMAT_VERSION: u32 = VERSION;
+
+/// MAX_FORMAT_VERSION is the newest format version this build can open.
+pub const MAX_FORMAT_VERSION: u32 = VERSION_FEATURES;
+
+/// Feature is an optional extension of the file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// BranchPrefix stores the prefix shared by the keys of a branch page
+    /// once and truncates the keys of leaves to separators, see
+    /// Options.branch_prefix_compression.
+    BranchPrefix,
+    /// Checksum checksums the meta pages with an algorithm other than
+    /// FNV-1a, see Options.checksum.
+    Checksum,
+}
+
+impl Feature {
+    /// min_version returns the first format version supporting the feature.
+    pub fn min_version(self) -> u32 {
+        VERSION_FEATURES
+    }
+}
+
+/// features returns the extensions a format version supports, or None if
+/// the version is unknown to this build.
+pub fn features(version: u32) -> Option<&'static [Feature]> {
+    match version {
+        VERSION => Some(&[]),
+        VERSION_FEATURES => Some(&[Feature::BranchPrefix, Feature::Checksum]),
+        _ => None,
+    }
+}
+
+/// Format describes the file format of a database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Format {
+    /// Format version from the meta page.
+    pub version: u32,
+    /// Extensions in use.
+    pub features: Vec<Feature>,
+    /// Algorithm checksumming the meta pages.
+    pub checksum: ChecksumType,
+}
+
+// is_supported tells whether this build can open a database of a version
+// whose meta pages have the given flags.
+pub(crate) fn is_supported(version: u32, flags: u32) -> bool {
+    match version {
+        VERSION => true,
+        VERSION_FEATURES => {
+            flags & !(KNOWN_FEATURES | META_CHECKSUM_MASK) == 0
+                && ChecksumType::from_meta_flags(flags).is_some()
+        }
+        _ => false,
+    }
+}
+
+impl DB {
+    /// format returns the format version of the database and the extensions
+    /// it uses.
+    pub fn format(&self) -> Result<Format> {
+        let meta = self.0.meta()?;
+        let checksum = meta.checksum_type().ok_or(BoltError::VersionMismatch)?;
+        let mut features = Vec::new();
+        if meta.has_feature(FEATURE_BRANCH_PREFIX) {
+            features.push(Feature::BranchPrefix);
+        }
+        if checksum != ChecksumType::Fnv {
+            features.push(Feature::Checksum);
+        }
+        Ok(Format {
+            version: meta.version(),
+            features,
+            checksum,
+        })
+    }
+
+    /// upgrade_format moves the database to format version to_version, in a
+    /// write transaction of its own. It does nothing if the database has that
+    /// version already.
+    ///
+    /// Upgrading from version 2 to 3 only rewrites the meta pages, enabling no
+    /// extension. Go bbolt, and releases of this crate without version 3,
+    /// refuse to open the database afterwards. Going back to version 2 is
+    /// allowed as long as no extension is in use, and returns
+    /// BoltError::Config otherwise. Returns VersionMismatch if this build
+    /// does not know to_version.
+    pub fn upgrade_format(&self, to_version: u32) -> Result<()> {
+        if features(to_version).is_none() {
+            return Err(BoltError::VersionMismatch);
+        }
+        self.update(|tx| {
+            let meta = tx.meta();
+            if meta.version() == to_version {
+                return Ok(());
+            }
+            if to_version == VERSION && meta.flags() != 0 {
+                return Err(BoltError::Config(format!(
+                    "format extensions in use ({:#x}) need version {}",
+                    meta.flags(),
+                    VERSION_FEATURES
+                )));
+            }
+            tx.set_format(to_version, meta.flags());
+            Ok(())
+        })?;
+        self.0
+            .logger()
+            .info("upgraded format", &[("version", &to_version)]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    // Ensure that a version 2 database can be upgraded to version 3 and back,
+    // keeping its data, and that extensions prevent the downgrade.
+    #[test]
+    fn test_upgrade_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+        assert_eq!(db.format().unwrap().version, 2);
+        assert_eq!(db.upgrade_format(4), Err(BoltError::VersionMismatch));
+
+        db.upgrade_format(3).unwrap();
+        db.close().unwrap();
+        let db = DB::open(&path, Options::default()).unwrap();
+        let format = db.format().unwrap();
+        assert_eq!(format.version, 3);
+        assert_eq!(format.features, vec![]);
+        db.view(|tx| {
+            assert_eq!(
+                tx.bucket(b"widgets").unwrap().get(b"foo"),
+                Some(&b"bar"[..])
+            );
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+        db.upgrade_format(2).unwrap();
+        assert_eq!(db.info().unwrap().version, 2);
+
+        let options = Options {
+            branch_prefix_compression: true,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("prefix"), options).unwrap();
+        assert_eq!(db.format().unwrap().features, vec![Feature::BranchPrefix]);
+        assert!(matches!(db.upgrade_format(2), Err(BoltError::Config(_))));
+        assert_eq!(db.format().unwrap().version, 3);
+    }
+
+    // Ensure that a database of a future version is refused without being
+    // modified.
+    #[test]
+    fn test_future_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        let page_size = db.0.page_size();
+        db.close().unwrap();
+
+        let mut data = std::fs::read(&path).unwrap();
+        for id in 0..2 {
+            let page = crate::common::page::Page::from_slice_mut(&mut data[id * page_size..]);
+            let mut meta = page.meta();
+            meta.set_version(MAX_FORMAT_VERSION + 1);
+            meta.set_checksum(meta.sum64());
+            page.set_meta(&meta);
+        }
+        std::fs::write(&path, &data).unwrap();
+
+        assert!(matches!(
+            DB::open(&path, Options::default()),
+            Err(BoltError::VersionMismatch)
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert_eq!(features(MAX_FORMAT_VERSION + 1), None);
+    }
+}
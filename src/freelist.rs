@@ -0,0 +1,735 @@
+//!
+//! freelist represents a list of all pages that are available for allocation.
+//! It also tracks pages that have been freed but are still in use by open transactions.
+//!
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::common::page::{Page, PageFlags, PgId, PAGE_HEADER_SIZE, PGID_SIZE};
+use crate::common::types::Txid;
+use crate::errors::{BoltError, Result};
+
+/// FreelistType is the type of the freelist backend
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FreelistType {
+    /// FreelistArrayType indicates backend freelist type is array
+    #[default]
+    Array,
+    /// FreelistMapType indicates backend freelist type is hashmap
+    HashMap,
+}
+
+/// FreelistReport summarizes the free pages of a database so operators can
+/// decide when it is worth compacting the file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FreelistReport {
+    /// number of free pages available for allocation
+    pub free_count: usize,
+    /// number of pages freed by transactions that readers may still be using
+    pub pending_count: usize,
+    /// pending page count keyed by the id of the transaction that freed them
+    pub pending: BTreeMap<u64, usize>,
+    /// number of contiguous runs of free pages
+    pub span_count: usize,
+    /// length in pages of the largest contiguous run of free pages
+    pub largest_span: usize,
+    /// share of free pages outside the largest span, from 0.0 (a single
+    /// contiguous run) towards 1.0 (scattered single pages)
+    pub fragmentation: f64,
+}
+
+// txPending holds a list of pgids and corresponding allocation txns
+// that are pending to be freed.
+#[derive(Debug, Default, Clone)]
+struct TxPending {
+    ids: Vec<PgId>,
+    // txids allocating the ids
+    alloctx: Vec<Txid>,
+    // beginning txid of last matching releaseRange
+    last_release_begin: Txid,
+}
+
+/// Freelist represents a list of all pages that are available for allocation.
+/// It also tracks pages that have been freed but are still in use by open transactions.
+#[derive(Debug, Default)]
+pub(crate) struct Freelist {
+    // freelist type
+    freelist_type: FreelistType,
+    // all free and available free page ids, sorted. (array version)
+    ids: Vec<PgId>,
+    // count of free pages (hashmap version)
+    free_pages_count: usize,
+    // key is the size of continuous pages (span), value is a set which contains the
+    // starting pgids of same size (hashmap version)
+    freemaps: BTreeMap<u64, HashSet<PgId>>,
+    // key is start pgid, value is its span size (hashmap version)
+    forward_map: HashMap<PgId, u64>,
+    // key is end pgid, value is its span size (hashmap version)
+    backward_map: HashMap<PgId, u64>,
+    // mapping of txid that allocated a pgid.
+    allocs: HashMap<PgId, Txid>,
+    // mapping of soon-to-be free page ids by tx.
+    pending: HashMap<Txid, TxPending>,
+    // fast lookup of all free and pending page ids.
+    cache: HashSet<PgId>,
+}
+
+impl Freelist {
+    /// new returns an empty, initialized freelist.
+    pub(crate) fn new(freelist_type: FreelistType) -> Self {
+        Self {
+            freelist_type,
+            ..Default::default()
+        }
+    }
+
+    /// size returns the size of the page after serialization.
+    pub(crate) fn size(&self) -> usize {
+        let mut n = self.count();
+        if n >= 0xFFFF {
+            // The first element will be used to store the count. See freelist.write.
+            n += 1;
+        }
+        PAGE_HEADER_SIZE + PGID_SIZE * n
+    }
+
+    /// count returns count of pages on the freelist
+    pub(crate) fn count(&self) -> usize {
+        self.free_count() + self.pending_count()
+    }
+
+    /// free_count returns count of free pages
+    pub(crate) fn free_count(&self) -> usize {
+        match self.freelist_type {
+            FreelistType::Array => self.ids.len(),
+            FreelistType::HashMap => self.free_pages_count,
+        }
+    }
+
+    /// pending_count returns count of pending pages
+    pub(crate) fn pending_count(&self) -> usize {
+        self.pending.values().map(|txp| txp.ids.len()).sum()
+    }
+
+    /// free_page_ids returns the sorted list of free page ids.
+    pub(crate) fn free_page_ids(&self) -> Vec<PgId> {
+        match self.freelist_type {
+            FreelistType::Array => self.ids.clone(),
+            FreelistType::HashMap => {
+                let mut spans: Vec<(PgId, u64)> = self
+                    .forward_map
+                    .iter()
+                    .map(|(&start, &size)| (start, size))
+                    .collect();
+                spans.sort_unstable();
+                spans
+                    .into_iter()
+                    .flat_map(|(start, size)| start..start + size)
+                    .collect()
+            }
+        }
+    }
+
+    /// copy_all returns a sorted list of all free ids and all pending ids.
+    pub(crate) fn copy_all(&self) -> Vec<PgId> {
+        let mut all = Vec::with_capacity(self.count());
+        for txp in self.pending.values() {
+            all.extend_from_slice(&txp.ids);
+        }
+        all.extend_from_slice(&self.free_page_ids());
+        all.sort_unstable();
+        all
+    }
+
+    /// report summarizes the free and pending pages and how fragmented the free pages are.
+    pub(crate) fn report(&self) -> FreelistReport {
+        let (span_count, largest_span) = match self.freelist_type {
+            FreelistType::Array => {
+                let (mut count, mut largest, mut run) = (0, 0, 0);
+                for (i, &id) in self.ids.iter().enumerate() {
+                    if i == 0 || id - self.ids[i - 1] != 1 {
+                        count += 1;
+                        run = 0;
+                    }
+                    run += 1;
+                    largest = largest.max(run);
+                }
+                (count, largest)
+            }
+            FreelistType::HashMap => (
+                self.forward_map.len(),
+                self.freemaps.keys().next_back().copied().unwrap_or(0) as usize,
+            ),
+        };
+
+        let free_count = self.free_count();
+        let fragmentation = if free_count == 0 {
+            0.0
+        } else {
+            1.0 - largest_span as f64 / free_count as f64
+        };
+
+        FreelistReport {
+            free_count,
+            pending_count: self.pending_count(),
+            pending: self
+                .pending
+                .iter()
+                .map(|(&txid, txp)| (txid, txp.ids.len()))
+                .collect(),
+            span_count,
+            largest_span,
+            fragmentation,
+        }
+    }
+
+    /// allocate returns the starting page id of a contiguous list of pages of a given size.
+    /// If a contiguous block cannot be found then None is returned.
+    pub(crate) fn allocate(&mut self, txid: Txid, n: usize) -> Option<PgId> {
+        if n == 0 {
+            return None;
+        }
+
+        let initial = match self.freelist_type {
+            FreelistType::Array => self.array_allocate(n)?,
+            FreelistType::HashMap => self.hashmap_allocate(n)?,
+        };
+
+        // Remove from the free cache.
+        for pgid in initial..initial + n as PgId {
+            self.cache.remove(&pgid);
+        }
+        self.allocs.insert(initial, txid);
+
+        Some(initial)
+    }
+
+    /// array_allocate scans the sorted free ids for the first contiguous run of `n` pages.
+    fn array_allocate(&mut self, n: usize) -> Option<PgId> {
+        if self.ids.is_empty() {
+            return None;
+        }
+
+        let (mut initial, mut previd): (PgId, PgId) = (0, 0);
+        for (i, &id) in self.ids.iter().enumerate() {
+            assert!(id > 1, "invalid page allocation: {}", id);
+
+            // Reset initial page if this is not contiguous.
+            if previd == 0 || id - previd != 1 {
+                initial = id;
+            }
+
+            // If we found a contiguous block then remove it and return it.
+            if (id - initial) + 1 == n as PgId {
+                self.ids.drain(i + 1 - n..=i);
+                return Some(initial);
+            }
+
+            previd = id;
+        }
+
+        None
+    }
+
+    /// hashmap_allocate takes the smallest free span that can hold `n` pages,
+    /// preferring an exact size match, and returns the unused tail to the freelist.
+    fn hashmap_allocate(&mut self, n: usize) -> Option<PgId> {
+        let n = n as u64;
+        let (size, pid) = self
+            .freemaps
+            .range(n..)
+            .find_map(|(&size, pids)| pids.iter().next().map(|&pid| (size, pid)))?;
+
+        // remove the span and add the remaining span back
+        self.del_span(pid, size);
+        if size > n {
+            self.add_span(pid + n, size - n);
+        }
+
+        Some(pid)
+    }
+
+    fn add_span(&mut self, start: PgId, size: u64) {
+        self.backward_map.insert(start - 1 + size, size);
+        self.forward_map.insert(start, size);
+        self.freemaps.entry(size).or_default().insert(start);
+        self.free_pages_count += size as usize;
+    }
+
+    fn del_span(&mut self, start: PgId, size: u64) {
+        self.forward_map.remove(&start);
+        self.backward_map.remove(&(start + size - 1));
+        if let Some(pids) = self.freemaps.get_mut(&size) {
+            pids.remove(&start);
+            if pids.is_empty() {
+                self.freemaps.remove(&size);
+            }
+        }
+        self.free_pages_count -= size as usize;
+    }
+
+    /// merge_with_existing_span merges pid to the existing free spans, try to merge it backward and forward
+    fn merge_with_existing_span(&mut self, pid: PgId) {
+        let prev = pid - 1;
+        let next = pid + 1;
+
+        let mut new_start = pid;
+        let mut new_size = 1;
+
+        if let Some(&pre_size) = self.backward_map.get(&prev) {
+            // merge with previous span
+            let start = prev + 1 - pre_size;
+            self.del_span(start, pre_size);
+            new_start -= pre_size;
+            new_size += pre_size;
+        }
+
+        if let Some(&next_size) = self.forward_map.get(&next) {
+            // merge with next span
+            self.del_span(next, next_size);
+            new_size += next_size;
+        }
+
+        self.add_span(new_start, new_size);
+    }
+
+    /// free releases a page and its overflow for a given transaction id.
+    /// If the page is already free then a panic will occur.
+    pub(crate) fn free(&mut self, txid: Txid, page: &Page) {
+        assert!(page.id() > 1, "cannot free page 0 or 1: {}", page.id());
+
+        // Free page and all its overflow pages.
+        let alloc_txid = match self.allocs.remove(&page.id()) {
+            Some(tx) => tx,
+            // Freelist is always allocated by prior tx.
+            None if page.is_freelist_page() => txid - 1,
+            None => 0,
+        };
+
+        let txp = self.pending.entry(txid).or_default();
+        for id in page.id()..=page.id() + page.overflow() as PgId {
+            // Verify that page is not already free.
+            assert!(!self.cache.contains(&id), "page {} already freed", id);
+
+            // Add to the freelist and cache.
+            txp.ids.push(id);
+            txp.alloctx.push(alloc_txid);
+            self.cache.insert(id);
+        }
+    }
+
+    /// release moves all page ids for a transaction id (or older) to the freelist.
+    pub(crate) fn release(&mut self, txid: Txid) {
+        let mut m = Vec::new();
+        self.pending.retain(|&tid, txp| {
+            if tid <= txid {
+                // Move transaction's pending pages to the available freelist.
+                // Don't remove from the cache since the page is still free.
+                m.append(&mut txp.ids);
+                false
+            } else {
+                true
+            }
+        });
+        self.merge_spans(m);
+    }
+
+    /// release_range moves pending pages allocated within an extent [begin,end] to the free list.
+    pub(crate) fn release_range(&mut self, begin: Txid, end: Txid) {
+        if begin > end {
+            return;
+        }
+
+        let mut m = Vec::new();
+        self.pending.retain(|&tid, txp| {
+            if tid < begin || tid > end {
+                return true;
+            }
+            // Don't recompute freed pages if ranges haven't updated.
+            if txp.last_release_begin == begin {
+                return true;
+            }
+
+            let mut i = 0;
+            while i < txp.ids.len() {
+                let atx = txp.alloctx[i];
+                if atx < begin || atx > end {
+                    i += 1;
+                    continue;
+                }
+                m.push(txp.ids.swap_remove(i));
+                txp.alloctx.swap_remove(i);
+            }
+            txp.last_release_begin = begin;
+
+            !txp.ids.is_empty()
+        });
+        self.merge_spans(m);
+    }
+
+    /// rollback removes the pages from a given pending tx.
+    pub(crate) fn rollback(&mut self, txid: Txid) {
+        // Remove page ids from cache.
+        let txp = match self.pending.remove(&txid) {
+            Some(txp) => txp,
+            None => return,
+        };
+
+        let mut m = Vec::new();
+        for (&pgid, &tx) in txp.ids.iter().zip(txp.alloctx.iter()) {
+            self.cache.remove(&pgid);
+            if tx == 0 {
+                continue;
+            }
+            if tx != txid {
+                // Pending free aborted; restore page back to alloc list.
+                self.allocs.insert(pgid, tx);
+            } else {
+                // Freed page was allocated by this txn; OK to throw away.
+                m.push(pgid);
+            }
+        }
+
+        // Remove pages from pending list and mark as free if allocated by txid.
+        self.merge_spans(m);
+    }
+
+    /// trim removes the free pages ending right below the high water mark hwm
+    /// and returns the high water mark without them.
+    pub(crate) fn trim(&mut self, mut hwm: PgId) -> PgId {
+        let mut ids = self.free_page_ids();
+        let n = ids.len();
+        while ids.last() == Some(&(hwm - 1)) {
+            ids.pop();
+            hwm -= 1;
+        }
+        if ids.len() < n {
+            self.read_ids(ids);
+        }
+        hwm
+    }
+
+    /// freed returns whether a given page is in the free list.
+    pub(crate) fn freed(&self, pgid: PgId) -> bool {
+        self.cache.contains(&pgid)
+    }
+
+    /// read initializes the freelist from a freelist page.
+    /// Returns BoltError::Corrupted if the page is not a freelist page.
+    pub(crate) fn read(&mut self, buf: &[u8]) -> Result<()> {
+        let page = Page::try_from_slice(buf)?;
+        if !page.is_freelist_page() {
+            let reason = format!("invalid freelist page, page type is {}", page.typ());
+            return Err(BoltError::corrupted(reason).with_pgid(page.id()));
+        }
+
+        let mut ids = page.try_freelist_page_ids(buf.len()).map_err(|_| {
+            BoltError::corrupted("freelist ids lie outside the page").with_pgid(page.id())
+        })?;
+        // Make sure they're sorted.
+        ids.sort_unstable();
+        self.read_ids(ids);
+        Ok(())
+    }
+
+    /// read_ids initializes the freelist from a given list of ids.
+    pub(crate) fn read_ids(&mut self, ids: Vec<PgId>) {
+        match self.freelist_type {
+            FreelistType::Array => self.ids = ids,
+            FreelistType::HashMap => {
+                self.free_pages_count = 0;
+                self.freemaps.clear();
+                self.forward_map.clear();
+                self.backward_map.clear();
+
+                // Group the sorted ids into contiguous spans.
+                let mut iter = ids.into_iter().peekable();
+                while let Some(start) = iter.next() {
+                    let mut size = 1;
+                    while iter.peek() == Some(&(start + size)) {
+                        iter.next();
+                        size += 1;
+                    }
+                    self.add_span(start, size);
+                }
+            }
+        }
+        self.reindex();
+    }
+
+    /// write writes the page ids onto a freelist page. All free and pending ids are
+    /// saved to disk since in the event of a program crash, all pending ids will
+    /// become free.
+    pub(crate) fn write(&self, page: &mut Page) {
+        // Combine the old free pgids and pgids waiting on an open transaction.
+
+        // Update the header flag.
+        page.set_flags(PageFlags::FREELIST_PAGE);
+
+        // The page.count can only hold up to 64k elements so if we overflow that
+        // number then we handle it by putting the size in the first element.
+        let ids = self.copy_all();
+        let l = ids.len();
+        if l == 0 {
+            page.set_count(0);
+        } else if l < 0xFFFF {
+            page.set_count(l as u16);
+            page.put_freelist_ids(0, &ids);
+        } else {
+            page.set_count(0xFFFF);
+            page.put_freelist_ids(0, &[l as PgId]);
+            page.put_freelist_ids(1, &ids);
+        }
+    }
+
+    /// reload reads the freelist from a page and filters out pending items.
+    pub(crate) fn reload(&mut self, buf: &[u8]) -> Result<()> {
+        self.read(buf)?;
+        self.no_sync_reload(self.free_page_ids());
+        Ok(())
+    }
+
+    /// no_sync_reload reads the freelist from pgids and filters out pending items.
+    pub(crate) fn no_sync_reload(&mut self, pgids: Vec<PgId>) {
+        // Build a cache of only pending pages.
+        let pcache: HashSet<PgId> = self
+            .pending
+            .values()
+            .flat_map(|txp| txp.ids.iter().copied())
+            .collect();
+
+        // Check each page in the freelist and build a new available freelist
+        // with any pages not in the pending lists.
+        let ids = pgids
+            .into_iter()
+            .filter(|id| !pcache.contains(id))
+            .collect();
+
+        // Once the available list is rebuilt then rebuild the free cache so that
+        // it includes the available and pending free pages.
+        self.read_ids(ids);
+    }
+
+    /// reindex rebuilds the free cache based on available and pending free lists.
+    fn reindex(&mut self) {
+        self.cache = self.free_page_ids().into_iter().collect();
+        for txp in self.pending.values() {
+            self.cache.extend(txp.ids.iter().copied());
+        }
+    }
+
+    /// merge_spans merges the given pages into the free list.
+    fn merge_spans(&mut self, mut ids: Vec<PgId>) {
+        if ids.is_empty() {
+            return;
+        }
+        ids.sort_unstable();
+        match self.freelist_type {
+            FreelistType::Array => {
+                self.ids.extend_from_slice(&ids);
+                self.ids.sort_unstable();
+                self.ids.dedup();
+            }
+            FreelistType::HashMap => {
+                for id in ids {
+                    self.merge_with_existing_span(id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::page::OwnedPage;
+
+    fn page(id: PgId, overflow: u32) -> OwnedPage {
+        let mut page = OwnedPage::new(PAGE_HEADER_SIZE);
+        page.set_id(id);
+        page.set_flags(PageFlags::LEAF_PAGE);
+        page.set_overflow(overflow);
+        page
+    }
+
+    // Ensure that a page is added to a transaction's freelist.
+    #[test]
+    fn test_freelist_free() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.free(100, &page(12, 0));
+        assert_eq!(f.pending[&100].ids, vec![12]);
+    }
+
+    // Ensure that a page and its overflow is added to a transaction's freelist.
+    #[test]
+    fn test_freelist_free_overflow() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.free(100, &page(12, 3));
+        assert_eq!(f.pending[&100].ids, vec![12, 13, 14, 15]);
+    }
+
+    // Ensure that a transaction's free pages can be released.
+    #[test]
+    fn test_freelist_release() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.free(100, &page(12, 1));
+        f.free(100, &page(9, 0));
+        f.free(102, &page(39, 0));
+        f.release(100);
+        f.release(101);
+        assert_eq!(f.ids, vec![9, 12, 13]);
+
+        f.release(102);
+        assert_eq!(f.ids, vec![9, 12, 13, 39]);
+    }
+
+    // Ensure that releaseRange handles boundary conditions correctly
+    #[test]
+    fn test_freelist_release_range() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.allocs.insert(20, 1);
+        f.free(3, &page(20, 0));
+        f.allocs.insert(21, 3);
+        f.free(5, &page(21, 0));
+
+        // Readers open at txid 2 and 4 pin both pages.
+        f.release_range(0, 1);
+        f.release_range(3, 3);
+        f.release_range(5, u64::MAX);
+        assert!(f.ids.is_empty());
+
+        // Once the reader at txid 4 closes, page 21 was allocated and freed
+        // inside [3, 5] and can be reused.
+        f.release_range(3, u64::MAX);
+        assert_eq!(f.ids, vec![21]);
+        assert_eq!(f.pending_count(), 1);
+    }
+
+    // Ensure that a freelist can find contiguous blocks of pages.
+    #[test]
+    fn test_freelist_allocate() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.read_ids(vec![3, 4, 5, 6, 7, 9, 12, 13, 18]);
+
+        assert_eq!(f.allocate(1, 3), Some(3));
+        assert_eq!(f.allocate(1, 1), Some(6));
+        assert_eq!(f.allocate(1, 3), None);
+        assert_eq!(f.allocate(1, 2), Some(12));
+        assert_eq!(f.allocate(1, 1), Some(7));
+        assert_eq!(f.allocate(1, 0), None);
+        assert_eq!(f.ids, vec![9, 18]);
+
+        assert_eq!(f.allocate(1, 1), Some(9));
+        assert_eq!(f.allocate(1, 1), Some(18));
+        assert_eq!(f.allocate(1, 1), None);
+        assert!(f.ids.is_empty());
+    }
+
+    // Ensure that the hashmap freelist prefers exact spans and splits larger ones.
+    #[test]
+    fn test_freelist_hashmap_allocate() {
+        let mut f = Freelist::new(FreelistType::HashMap);
+        f.read_ids(vec![3, 4, 5, 6, 7, 9, 12, 13, 18]);
+        assert_eq!(f.free_count(), 9);
+
+        assert_eq!(f.allocate(1, 2), Some(12));
+        assert_eq!(f.allocate(1, 3), Some(3));
+        assert_eq!(f.allocate(1, 3), None);
+        assert_eq!(f.free_page_ids(), vec![6, 7, 9, 18]);
+        assert!(!f.freed(3));
+
+        // Releasing freed pages merges them back into their neighbouring spans.
+        f.free(2, &page(5, 0));
+        f.release(2);
+        assert_eq!(f.free_page_ids(), vec![5, 6, 7, 9, 18]);
+        assert_eq!(f.allocate(1, 3), Some(5));
+    }
+
+    // Ensure that rolling back a transaction restores pages it did not allocate.
+    #[test]
+    fn test_freelist_rollback() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.read_ids(vec![3, 4]);
+        assert_eq!(f.allocate(5, 1), Some(3));
+
+        f.allocs.insert(10, 2);
+        f.free(5, &page(10, 0));
+        f.free(5, &page(3, 0));
+        f.rollback(5);
+
+        assert_eq!(f.ids, vec![3, 4]);
+        assert_eq!(f.allocs.get(&10), Some(&2));
+        assert!(!f.freed(10));
+        assert!(f.pending.is_empty());
+    }
+
+    // Ensure that the report measures spans the same way for both backends.
+    // Ensure that only the free pages at the end are trimmed.
+    #[test]
+    fn test_freelist_trim() {
+        for typ in [FreelistType::Array, FreelistType::HashMap] {
+            let mut f = Freelist::new(typ);
+            f.read_ids(vec![3, 4, 7, 8, 9]);
+            f.free(100, &page(12, 0));
+            assert_eq!(f.trim(11), 11);
+            assert_eq!(f.trim(10), 7);
+            assert_eq!(f.free_page_ids(), vec![3, 4]);
+            assert!(!f.freed(8));
+            assert!(f.freed(12));
+        }
+    }
+
+    #[test]
+    fn test_freelist_report() {
+        for typ in [FreelistType::Array, FreelistType::HashMap] {
+            let mut f = Freelist::new(typ);
+            assert_eq!(f.report(), FreelistReport::default());
+
+            f.read_ids(vec![3, 4, 5, 6, 9, 12, 13]);
+            f.free(100, &page(20, 1));
+            f.free(101, &page(30, 0));
+
+            let report = f.report();
+            assert_eq!(report.free_count, 7);
+            assert_eq!(report.pending_count, 3);
+            assert_eq!(report.pending, BTreeMap::from([(100, 2), (101, 1)]));
+            assert_eq!(report.span_count, 3);
+            assert_eq!(report.largest_span, 4);
+            assert!((report.fragmentation - 3.0 / 7.0).abs() < f64::EPSILON);
+        }
+    }
+
+    // Ensure that a freelist can deserialize from a freelist page.
+    #[test]
+    fn test_freelist_read_write() {
+        let mut f = Freelist::new(FreelistType::Array);
+        f.read_ids(vec![12, 39]);
+        f.free(100, &page(28, 0));
+        f.free(100, &page(11, 0));
+        f.free(101, &page(3, 0));
+
+        let mut buf = OwnedPage::new(f.size());
+        f.write(&mut buf);
+
+        let mut f2 = Freelist::new(FreelistType::Array);
+        f2.read(buf.buf()).unwrap();
+        assert_eq!(f2.ids, vec![3, 11, 12, 28, 39]);
+        assert!(f2.freed(28));
+    }
+
+    // Ensure that the freelist overflow count is stored in the first element.
+    #[test]
+    fn test_freelist_read_write_overflow() {
+        let ids: Vec<PgId> = (2..0xFFFF + 10).collect();
+        let mut f = Freelist::new(FreelistType::Array);
+        f.read_ids(ids.clone());
+
+        let mut buf = OwnedPage::new(f.size());
+        f.write(&mut buf);
+        assert_eq!(buf.count(), 0xFFFF);
+
+        let mut f2 = Freelist::new(FreelistType::Array);
+        f2.read(buf.buf()).unwrap();
+        assert_eq!(f2.ids, ids);
+    }
+}
@@ -0,0 +1,151 @@
+//! Entry points for the fuzz targets in fuzz/.
+//!
+//! Each function feeds arbitrary bytes to one of the parsers of the on-disk
+//! format, the way they are reached when opening an untrusted file. Parse
+//! errors are expected and ignored; a panic, or a read out of bounds caught
+//! by the sanitizer, is a bug. The targets are run with cargo-fuzz:
+//!
+//! ```text
+//! $ cargo +nightly fuzz run page
+//! ```
+//!
+//! This module is only built with the fuzzing feature and is not part of the
+//! public API.
+
+use std::sync::Arc;
+
+use crate::common::inode::{read_inode_from_page, Key, Value};
+use crate::common::meta::Meta;
+use crate::common::page::{OwnedPage, Page, BUCKET_LEAF_FLAG};
+use crate::db::{Options, DB};
+use crate::errors::Result;
+use crate::freelist::{Freelist, FreelistType};
+use crate::page_ref::PageRef;
+use crate::tx::Tx;
+use crate::vfs::MemoryVfs;
+
+/// page parses a branch or leaf page and reads every element of it.
+pub fn page(data: &[u8]) {
+    // Pages are aligned in the mmap, so they are here too.
+    let buf = OwnedPage::from_slice(data).into_bytes();
+    let page = match Page::try_from_slice(&buf) {
+        Ok(page) => page,
+        Err(_) => return,
+    };
+    if let Ok(page) = PageRef::new(page, buf.len(), 0) {
+        page.used();
+        for index in 0..page.count() {
+            page.key_preview(index, 16);
+            page.value(index);
+            page.is_bucket(index);
+            page.child(index);
+        }
+    }
+    let _ = read_inode_from_page(&buf);
+}
+
+/// meta parses a meta page and checks it like opening a database does.
+pub fn meta(data: &[u8]) {
+    if let Ok(meta) = Meta::try_from_page(data) {
+        if meta.validate().is_ok() {
+            let _ = meta.validate_layout(meta.page_size() as usize, data.len());
+        }
+    }
+}
+
+/// freelist reads a freelist page into both kinds of freelist.
+pub fn freelist(data: &[u8]) {
+    let buf = OwnedPage::from_slice(data).into_bytes();
+    for typ in [FreelistType::Array, FreelistType::HashMap] {
+        let _ = Freelist::new(typ).read(&buf);
+    }
+}
+
+/// inline_bucket stores data as the header of a nested bucket of an empty
+/// database, then opens the bucket and walks it.
+pub fn inline_bucket(data: &[u8]) {
+    let options = Options {
+        vfs: Some(Arc::new(MemoryVfs::default())),
+        ..Default::default()
+    };
+    let db = match DB::open("fuzz", options) {
+        Ok(db) => db,
+        Err(_) => return,
+    };
+    // Don't commit, the header may refer to any page.
+    if let Ok(tx) = db.begin_rw() {
+        let _ = walk_inline_bucket(&tx, data);
+        let _ = tx.rollback();
+    }
+}
+
+fn walk_inline_bucket(tx: &Tx, data: &[u8]) -> Result<()> {
+    let root = tx.root();
+    let name = Key::from_static(b"fuzz");
+    let mut c = root.cursor();
+    c.seek_item(&name)?;
+    c.node()?.put(
+        &name,
+        name.clone(),
+        Value::copy_from_slice(data),
+        0,
+        BUCKET_LEAF_FLAG,
+    );
+
+    if let Some(b) = root.bucket(&name) {
+        b.for_each(|_, _| Ok(()))?;
+        b.stats()?;
+        b.key_count()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ensure that the entry points survive valid pages and garbage alike.
+    #[test]
+    fn test_fuzzing_entry_points() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..100u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            tx.create_bucket(b"inline")?.put(b"foo", b"bar")
+        })
+        .unwrap();
+        let page_size = db.page_size();
+        db.close().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        let mut inputs: Vec<Vec<u8>> = file.chunks(page_size).map(|p| p.to_vec()).collect();
+        inputs.push(Vec::new());
+        inputs.push(vec![0xff; 64]);
+        for input in inputs.clone() {
+            for len in [input.len(), input.len() / 2, 17] {
+                let input = &input[..len.min(input.len())];
+                page(input);
+                meta(input);
+                freelist(input);
+            }
+        }
+
+        // A header with garbage after it, and one of a real inline bucket.
+        let mut header = vec![0u8; 16];
+        header.extend_from_slice(&[0xff; 32]);
+        inline_bucket(&header);
+        inline_bucket(&[]);
+        let db = DB::open(&path, Options::default()).unwrap();
+        let value = db
+            .view(|tx| {
+                let mut c = tx.root().cursor();
+                Ok(c.seek_item(b"inline")?.unwrap().1.to_vec())
+            })
+            .unwrap();
+        inline_bucket(&value);
+    }
+}
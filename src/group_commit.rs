@@ -0,0 +1,314 @@
+//! Group commit, sharing the sync of meta pages between writers.
+//!
+//! A commit syncs its data pages before writing the meta page, and syncs
+//! again to make the meta page durable. With group commit the second sync is
+//! shared: the committer releases the writer lock once its meta page is
+//! written and waits until a sync covers it. That is either the data page
+//! sync of the next writer, which makes the meta pages written before it
+//! durable as well, or a sync made by a waiting committer once no writer is
+//! left queued, or the max delay passed.
+//!
+//! Until its meta page is durable, the previous meta page is the one found
+//! after a crash, so the pages a commit freed are not reused before then.
+//!
+//! When a shared sync fails, the meta pages it covers are already in place
+//! and read by new transactions, but may or may not be durable, and a sync
+//! retried after a failure may succeed without having written them. So the
+//! failure poisons the tracker: the commits waiting on it, later syncs and
+//! later write transactions all return its error, until the database is
+//! reopened and its meta pages are read back from the storage.
+
+use std::io;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::common::types::Txid;
+use crate::errors::{BoltError, IoOp, Result};
+use crate::os::Stopwatch;
+use crate::vfs::{SyncStrategy, Vfs};
+
+/// GroupCommit tracks which written meta pages are durable.
+#[derive(Debug)]
+pub(crate) struct GroupCommit {
+    max_delay: Duration,
+    strategy: SyncStrategy,
+    state: Mutex<SyncState>,
+    // Signalled when a meta page is written or synced, and when a committer
+    // starts waiting.
+    cond: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct SyncState {
+    // Newest transaction whose meta page was written.
+    written: Txid,
+    // Newest transaction whose meta page is durable.
+    synced: Txid,
+    // Whether a committer is about to sync for the others.
+    leading: bool,
+    // Error of the first failed sync, after which no meta page is durable.
+    failed: Option<(io::ErrorKind, String)>,
+}
+
+impl GroupCommit {
+    /// new returns the tracker of a database whose meta pages are durable,
+    /// syncing its storage as strategy says.
+    pub(crate) fn new(max_delay: Duration, strategy: SyncStrategy) -> GroupCommit {
+        GroupCommit {
+            max_delay,
+            strategy,
+            state: Mutex::new(SyncState::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// written records that the meta page of txid was written.
+    pub(crate) fn written(&self, txid: Txid) {
+        self.state.lock().unwrap().written = txid;
+        self.cond.notify_all();
+    }
+
+    /// pinned returns the newest transaction whose meta page is durable,
+    /// when one written since is not durable yet.
+    pub(crate) fn pinned(&self) -> Option<Txid> {
+        let state = self.state.lock().unwrap();
+        (state.synced < state.written).then_some(state.synced)
+    }
+
+    /// poisoned returns the error of the failed sync, if a sync failed.
+    pub(crate) fn poisoned(&self) -> Result<()> {
+        SyncState::check(&self.state.lock().unwrap())
+    }
+
+    /// sync syncs the storage, which makes the meta pages written so far
+    /// durable. Once a sync failed, returns its error without syncing.
+    pub(crate) fn sync(&self, vfs: &dyn Vfs) -> Result<()> {
+        let target = {
+            let state = self.state.lock().unwrap();
+            SyncState::check(&state)?;
+            state.written
+        };
+        let result = vfs.sync_with(self.strategy);
+
+        let mut state = self.state.lock().unwrap();
+        match &result {
+            Ok(()) => state.synced = state.synced.max(target),
+            Err(err) => {
+                if state.failed.is_none() {
+                    state.failed = Some((err.kind(), err.to_string()));
+                }
+            }
+        }
+        drop(state);
+        self.cond.notify_all();
+        result.map_err(|err| BoltError::io(IoOp::Sync, err))
+    }
+
+    /// wait returns once the meta page of txid is durable. If no other
+    /// committer syncs it, the storage is synced as soon as busy reports no
+    /// more writers, or after the max delay. Returns the error of the failed
+    /// sync if the meta page was not durable before a sync failed.
+    pub(crate) fn wait<F>(&self, txid: Txid, vfs: &dyn Vfs, busy: F) -> Result<()>
+    where
+        F: Fn() -> bool,
+    {
+        // Wake a leader waiting for this committer to be done.
+        self.cond.notify_all();
+
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.synced >= txid {
+                return Ok(());
+            }
+            SyncState::check(&state)?;
+            if state.leading {
+                state = self.cond.wait(state).unwrap();
+                continue;
+            }
+
+            // Lead the next sync, giving the queued writers some time to
+            // write their meta pages first.
+            state.leading = true;
+            let start = Stopwatch::start();
+            while state.synced < txid && busy() {
+                let elapsed = start.elapsed();
+                if elapsed >= self.max_delay {
+                    break;
+                }
+                let timeout = self.max_delay - elapsed;
+                state = self.cond.wait_timeout(state, timeout).unwrap().0;
+            }
+            state.leading = false;
+            if state.synced >= txid {
+                self.cond.notify_all();
+                continue;
+            }
+            drop(state);
+
+            // The outcome is recorded for all the committers it covers.
+            let _ = self.sync(vfs);
+            state = self.state.lock().unwrap();
+        }
+    }
+}
+
+impl SyncState {
+    // check returns the error of the failed sync, if a sync failed.
+    fn check(&self) -> Result<()> {
+        match &self.failed {
+            Some((kind, msg)) => {
+                let err = io::Error::new(*kind, msg.clone());
+                Err(BoltError::io(IoOp::Sync, err))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::db::{Options, DB};
+    use crate::vfs::MemoryVfs;
+
+    // CountingVfs counts the syncs of a MemoryVfs, which take as long as on a
+    // fast disk. The sync numbered fail_at fails, if set.
+    #[derive(Debug, Default)]
+    struct CountingVfs {
+        inner: MemoryVfs,
+        syncs: AtomicUsize,
+        fail_at: AtomicUsize,
+    }
+
+    impl Vfs for CountingVfs {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.inner.read_at(buf, offset)
+        }
+
+        fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.inner.write_at(buf, offset)
+        }
+
+        fn sync(&self) -> io::Result<()> {
+            let n = self.syncs.fetch_add(1, Ordering::Relaxed) + 1;
+            if n == self.fail_at.load(Ordering::Relaxed) {
+                return Err(io::Error::other("sync failed"));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+            self.inner.sync()
+        }
+
+        fn len(&self) -> io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn truncate(&self, len: u64) -> io::Result<()> {
+            self.inner.truncate(len)
+        }
+
+        fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()> {
+            self.inner.lock(exclusive, timeout)
+        }
+
+        fn unlock(&self) -> Result<()> {
+            self.inner.unlock()
+        }
+
+        fn map(&self, len: usize) -> io::Result<Bytes> {
+            self.inner.map(len)
+        }
+    }
+
+    // Ensure that concurrent commits share syncs and all of them persist.
+    #[test]
+    fn test_group_commit() {
+        const WRITERS: usize = 4;
+        const COMMITS: usize = 25;
+
+        let vfs = Arc::new(CountingVfs::default());
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            group_commit_delay: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let db = DB::open("group", options.clone()).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets").map(|_| ()))
+            .unwrap();
+        let before = vfs.syncs.load(Ordering::Relaxed);
+
+        std::thread::scope(|s| {
+            for w in 0..WRITERS {
+                let db = db.clone();
+                s.spawn(move || {
+                    for i in 0..COMMITS {
+                        let key = format!("{}-{:04}", w, i);
+                        db.update(|tx| tx.bucket(b"widgets").unwrap().put(key.as_bytes(), b"v"))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        // Without group commit every commit syncs twice, with it the meta
+        // page is mostly synced along with the pages of the next commit.
+        let syncs = vfs.syncs.load(Ordering::Relaxed) - before;
+        assert!(syncs < 3 * WRITERS * COMMITS / 2, "{} syncs", syncs);
+        db.close().unwrap();
+
+        let db = DB::open("group", options).unwrap();
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            for w in 0..WRITERS {
+                for i in 0..COMMITS {
+                    assert!(b.get(format!("{}-{:04}", w, i).as_bytes()).is_some());
+                }
+            }
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a failed group sync fails the commit it covers and poisons
+    // the database until it is reopened, while reads keep working.
+    #[test]
+    fn test_group_commit_sync_error() {
+        let vfs = Arc::new(CountingVfs::default());
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            group_commit_delay: Some(Duration::from_millis(1)),
+            ..Default::default()
+        };
+        let db = DB::open("group", options.clone()).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets").map(|_| ()))
+            .unwrap();
+
+        // The data pages are synced, then the meta page sync fails.
+        vfs.fail_at
+            .store(vfs.syncs.load(Ordering::Relaxed) + 2, Ordering::Relaxed);
+        let is_sync_err = |err: BoltError| matches!(err, BoltError::Io { op: IoOp::Sync, .. });
+        let put =
+            |db: &DB, key: &[u8]| db.update(|tx| tx.bucket(b"widgets").unwrap().put(key, b"v"));
+        assert!(is_sync_err(put(&db, b"foo").unwrap_err()));
+
+        // The meta page is in place, but nothing is written on top of it.
+        db.view(|tx| {
+            assert!(tx.bucket(b"widgets").unwrap().get(b"foo").is_some());
+            Ok(())
+        })
+        .unwrap();
+        assert!(is_sync_err(put(&db, b"bar").unwrap_err()));
+        assert!(is_sync_err(db.begin_rw().err().unwrap()));
+        assert!(is_sync_err(db.sync().unwrap_err()));
+        db.close().unwrap();
+
+        let db = DB::open("group", options).unwrap();
+        put(&db, b"bar").unwrap();
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Quick health checks.
+//!
+//! Tx::check walks every page of the database, which takes too long to be
+//! run by a readiness probe. DB::health instead looks at what is known
+//! without reading the tree: the meta pages, the freelist held in memory,
+//! the open readers and the outcome of the last full check.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use crate::common::page::PgId;
+use crate::common::types::Txid;
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::tx_check::{CheckError, CheckErrorKind};
+
+/// LastCheck is the outcome of the last Tx::check run on a database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LastCheck {
+    /// when the check ended
+    pub at: SystemTime,
+    /// id of the transaction checked
+    pub txid: Txid,
+    /// number of problems found
+    pub errors: usize,
+}
+
+/// Health is a quick report on the state of a database, see DB::health.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Health {
+    /// problem of the meta page not in use, which the next commit overwrites
+    pub meta_error: Option<CheckError>,
+    /// whether a meta page was used despite a checksum mismatch
+    pub degraded: bool,
+    /// problems of the freelist, or None if it is not loaded
+    pub freelist_errors: Option<Vec<CheckError>>,
+    /// number of open read-only transactions
+    pub readers: usize,
+    /// time since the oldest open read-only transaction began
+    pub oldest_reader_age: Option<Duration>,
+    /// number of freed pages the open readers keep from being reused
+    pub pending_pages: usize,
+    /// share of free pages outside the largest contiguous run, see
+    /// FreelistReport
+    pub fragmentation: f64,
+    /// outcome of the last Tx::check since the database was opened
+    pub last_check: Option<LastCheck>,
+}
+
+impl Health {
+    /// is_healthy returns whether no problem was found: both meta pages are
+    /// valid, the freelist is consistent and the last check, if any, found
+    /// nothing. The reader backlog and the fragmentation are only reported,
+    /// what is too much of them depends on the application.
+    pub fn is_healthy(&self) -> bool {
+        self.meta_error.is_none()
+            && !self.degraded
+            && self.freelist_errors.as_ref().is_none_or(Vec::is_empty)
+            && self
+                .last_check
+                .as_ref()
+                .is_none_or(|check| check.errors == 0)
+    }
+}
+
+impl DB {
+    /// health returns a quick report on the state of the database, for
+    /// readiness probes. Unlike Tx::check it does not read the tree, so it
+    /// only finds the problems of the meta pages and the freelist; the last
+    /// check run tells about the rest.
+    pub fn health(&self) -> Result<Health> {
+        let (meta, meta_error) = self.0.meta_with_error()?;
+        let meta_error = meta_error.map(|(pgid, err)| CheckError {
+            pgid,
+            bucket: Vec::new(),
+            kind: CheckErrorKind::Malformed {
+                reason: err.to_string(),
+            },
+        });
+
+        let (freelist_errors, pending_pages, fragmentation) = match self.0.freelist() {
+            Ok(freelist) => {
+                let errors = check_freelist(&freelist.copy_all(), meta.pgid());
+                let report = freelist.report();
+                (Some(errors), report.pending_count, report.fragmentation)
+            }
+            Err(BoltError::FreePagesNotLoaded) => (None, 0, 0.0),
+            Err(err) => return Err(err),
+        };
+
+        let readers = self.0.lock_diagnostics().readers;
+        Ok(Health {
+            meta_error,
+            degraded: self.stats().degraded,
+            freelist_errors,
+            readers: readers.len(),
+            oldest_reader_age: readers.iter().map(|tx| tx.age).max(),
+            pending_pages,
+            fragmentation,
+            last_check: self.0.last_check(),
+        })
+    }
+}
+
+// check_freelist returns the problems of the free and pending pages of a
+// freelist, below the high water mark high.
+fn check_freelist(ids: &[PgId], high: PgId) -> Vec<CheckError> {
+    let mut seen = HashSet::new();
+    let mut errors = Vec::new();
+    for &pgid in ids {
+        // Meta pages are always reachable.
+        let kind = if pgid < 2 {
+            CheckErrorKind::ReachableFreed
+        } else if pgid >= high {
+            CheckErrorKind::OutOfBounds { high }
+        } else if !seen.insert(pgid) {
+            CheckErrorKind::DoubleFree
+        } else {
+            continue;
+        };
+        errors.push(CheckError {
+            pgid,
+            bucket: Vec::new(),
+            kind,
+        });
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    // Ensure that a healthy database reports its readers and the last check,
+    // and that a corrupt meta page or a failed check makes it unhealthy.
+    #[test]
+    fn test_health() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        for i in 0..3u32 {
+            db.update(|tx| {
+                tx.create_bucket_if_not_exists(b"widgets")?
+                    .put(&i.to_be_bytes(), &[0x5a; 100])
+            })
+            .unwrap();
+        }
+        let health = db.health().unwrap();
+        assert!(health.is_healthy(), "{:?}", health);
+        assert_eq!(health.freelist_errors, Some(vec![]));
+        assert_eq!(health.last_check, None);
+
+        let tx = db.begin_ro().unwrap();
+        assert!(tx.check().unwrap().is_ok());
+        db.update(|tx| tx.bucket(b"widgets").unwrap().delete(&0u32.to_be_bytes()))
+            .unwrap();
+        let health = db.health().unwrap();
+        assert_eq!(health.readers, 1);
+        assert!(health.oldest_reader_age.is_some());
+        assert!(health.pending_pages > 0);
+        let last_check = health.last_check.unwrap();
+        assert_eq!((last_check.txid, last_check.errors), (tx.id(), 0));
+        tx.rollback().unwrap();
+
+        // Corrupt the meta page not in use.
+        let txid = db.info().unwrap().txid;
+        db.close().unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        let page_size = 4096;
+        let spare = (txid as usize + 1) % 2;
+        data[spare * page_size + 32] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        let health = db.health().unwrap();
+        assert!(!health.is_healthy());
+        assert_eq!(health.meta_error.unwrap().pgid, spare as PgId);
+    }
+
+    // Ensure that freed pages out of bounds or freed twice are reported.
+    #[test]
+    fn test_check_freelist() {
+        assert_eq!(check_freelist(&[2, 3, 5], 6), vec![]);
+        let errors: Vec<_> = check_freelist(&[1, 3, 3, 6], 6)
+            .into_iter()
+            .map(|err| (err.pgid, err.kind))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (1, CheckErrorKind::ReachableFreed),
+                (3, CheckErrorKind::DoubleFree),
+                (6, CheckErrorKind::OutOfBounds { high: 6 }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Package keys encodes numbers, UUIDs and tuples of them as keys whose byte
+//! order, the order cursors iterate in, matches the order of the values.
+//!
+//! Integers are stored big-endian, with the sign bit flipped for signed types
+//! so negative numbers sort first. Floats are ordered by their IEEE 754 bits:
+//! the sign bit is flipped for positive numbers and all bits are flipped for
+//! negative ones. UUIDs are their 16 bytes as is.
+//!
+//! Byte strings can be followed by other parts of a tuple, so they have to be
+//! delimited. A length prefix would sort short strings before long ones, so
+//! instead every 0x00 in the string is escaped as 0x00 0xFF and the string is
+//! terminated with 0x00 0x01, which sorts below any escaped content.
+//!
+//! ```
+//! use boltdb_rs::keys;
+//!
+//! let a = keys::encode(&(7u64, -1i64));
+//! let b = keys::encode(&(7u64, 2i64));
+//! assert!(a < b);
+//! assert_eq!(keys::decode::<(u64, i64)>(&a).unwrap(), (7, -1));
+//! ```
+
+use crate::errors::{BoltError, Result};
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+/// KeyPart is a value that can be encoded as an order-preserving key, or as
+/// one part of a tuple key.
+pub trait KeyPart: Sized {
+    /// encode_to appends the encoded value to buf.
+    fn encode_to(&self, buf: &mut Vec<u8>);
+
+    /// decode_from decodes a value from the front of buf and advances buf
+    /// past it.
+    fn decode_from(buf: &mut &[u8]) -> Result<Self>;
+}
+
+/// encode returns the order-preserving encoding of a value.
+pub fn encode<T: KeyPart>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode_to(&mut buf);
+    buf
+}
+
+/// decode decodes a key produced by encode. The whole key has to be consumed.
+pub fn decode<T: KeyPart>(mut key: &[u8]) -> Result<T> {
+    let value = T::decode_from(&mut key)?;
+    if !key.is_empty() {
+        return Err(BoltError::Codec(format!(
+            "{} trailing bytes after key",
+            key.len()
+        )));
+    }
+    Ok(value)
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if buf.len() < n {
+        return Err(BoltError::Codec(format!(
+            "key truncated: need {} bytes, have {}",
+            n,
+            buf.len()
+        )));
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+macro_rules! unsigned_key_part {
+    ($($t:ty),*) => {$(
+        impl KeyPart for $t {
+            fn encode_to(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+                let bytes = take(buf, std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_be_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+macro_rules! signed_key_part {
+    ($($t:ty => $u:ty),*) => {$(
+        impl KeyPart for $t {
+            fn encode_to(&self, buf: &mut Vec<u8>) {
+                let flipped = (*self as $u) ^ (1 << (<$u>::BITS - 1));
+                flipped.encode_to(buf);
+            }
+
+            fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+                let flipped = <$u>::decode_from(buf)?;
+                Ok((flipped ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+    )*};
+}
+
+unsigned_key_part!(u8, u16, u32, u64, u128);
+signed_key_part!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+macro_rules! float_key_part {
+    ($($t:ty => $u:ty),*) => {$(
+        impl KeyPart for $t {
+            fn encode_to(&self, buf: &mut Vec<u8>) {
+                const SIGN: $u = 1 << (<$u>::BITS - 1);
+                let bits = self.to_bits();
+                let ordered = if bits & SIGN != 0 { !bits } else { bits ^ SIGN };
+                ordered.encode_to(buf);
+            }
+
+            fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+                const SIGN: $u = 1 << (<$u>::BITS - 1);
+                let ordered = <$u>::decode_from(buf)?;
+                let bits = if ordered & SIGN != 0 { ordered ^ SIGN } else { !ordered };
+                Ok(<$t>::from_bits(bits))
+            }
+        }
+    )*};
+}
+
+float_key_part!(f32 => u32, f64 => u64);
+
+/// UUIDs are stored as their 16 bytes, e.g. `uuid::Uuid::as_bytes`. Time
+/// ordered UUIDs (version 7) therefore sort by creation time.
+impl KeyPart for [u8; 16] {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+        Ok(take(buf, 16)?.try_into().unwrap())
+    }
+}
+
+// encode_bytes appends the escaped and terminated form of a byte string.
+fn encode_bytes(value: &[u8], buf: &mut Vec<u8>) {
+    for &b in value {
+        buf.push(b);
+        if b == ESCAPE {
+            buf.push(ESCAPED_ZERO);
+        }
+    }
+    buf.extend_from_slice(&[ESCAPE, TERMINATOR]);
+}
+
+impl KeyPart for Vec<u8> {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self, buf);
+    }
+
+    fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+        let mut value = Vec::new();
+        loop {
+            match take(buf, 1)?[0] {
+                ESCAPE => match take(buf, 1)?[0] {
+                    ESCAPED_ZERO => value.push(ESCAPE),
+                    TERMINATOR => return Ok(value),
+                    b => {
+                        return Err(BoltError::Codec(format!(
+                            "invalid escape sequence 0x00 {:#04x}",
+                            b
+                        )))
+                    }
+                },
+                b => value.push(b),
+            }
+        }
+    }
+}
+
+impl KeyPart for String {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), buf);
+    }
+
+    fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+        String::from_utf8(Vec::decode_from(buf)?).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+}
+
+macro_rules! tuple_key_part {
+    ($($name:ident),+) => {
+        impl<$($name: KeyPart),+> KeyPart for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_to(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_to(buf);)+
+            }
+
+            fn decode_from(buf: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::decode_from(buf)?,)+))
+            }
+        }
+    };
+}
+
+tuple_key_part!(A);
+tuple_key_part!(A, B);
+tuple_key_part!(A, B, C);
+tuple_key_part!(A, B, C, D);
+tuple_key_part!(A, B, C, D, E);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_sorted<T: KeyPart + std::fmt::Debug + PartialEq>(values: &[T]) {
+        let keys: Vec<Vec<u8>> = values.iter().map(encode).collect();
+        for (i, pair) in keys.windows(2).enumerate() {
+            assert!(
+                pair[0] < pair[1],
+                "{:?} sorts after {:?}",
+                values[i],
+                values[i + 1]
+            );
+        }
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(&decode::<T>(key).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_sorted(&[0u64, 1, 255, 256, u64::MAX]);
+        assert_sorted(&[i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_sorted(&[i8::MIN, -1, 0, i8::MAX]);
+        assert_sorted(&[
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.5,
+            f64::MAX,
+            f64::INFINITY,
+        ]);
+        assert_eq!(encode(&1u32), vec![0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_bytes() {
+        assert_sorted(&[
+            b"".to_vec(),
+            b"\x00".to_vec(),
+            b"\x00\x00".to_vec(),
+            b"\x00\x01".to_vec(),
+            b"a".to_vec(),
+            b"a\x00".to_vec(),
+            b"ab".to_vec(),
+            b"b".to_vec(),
+            b"\xff".to_vec(),
+        ]);
+        assert_sorted(&["".to_string(), "a".to_string(), "ab".to_string()]);
+        assert!(decode::<Vec<u8>>(b"a\x00\x02").is_err());
+        assert!(decode::<Vec<u8>>(b"a").is_err());
+    }
+
+    #[test]
+    fn test_tuples() {
+        assert_sorted(&[
+            ("a".to_string(), -1i64),
+            ("a".to_string(), 0),
+            ("a\x00".to_string(), -5),
+            ("b".to_string(), i64::MIN),
+        ]);
+        assert_sorted(&[
+            (1u32, [0u8; 16], 2.5f64),
+            (1, [1; 16], -1.0),
+            (2, [0; 16], 0.0),
+        ]);
+        assert!(matches!(
+            decode::<(u64,)>(&[0; 9]),
+            Err(BoltError::Codec(_))
+        ));
+        assert!(matches!(decode::<u64>(&[0; 7]), Err(BoltError::Codec(_))));
+    }
+}
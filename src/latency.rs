@@ -0,0 +1,262 @@
+//! Latency of commits.
+//!
+//! Each commit records how long it took, in total and in each of its
+//! phases, into histograms kept by the database, whose percentiles
+//! DB::stats reports in Stats.commit_latency. The phases are:
+//!
+//! - spill: rebalancing and spilling the dirty nodes onto pages;
+//! - write: writing the pages and the meta page;
+//! - sync: syncing them, waiting for the shared sync with group commit.
+//!
+//! The histograms have a bucket per power of two of microseconds, so the
+//! percentiles are rounded up to the next power of two, at a fixed cost per
+//! commit. Commits slower than Options.slow_commit_threshold are logged as
+//! well, along with their phases and the number of pages they wrote.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::common::types::Txid;
+use crate::logger::Logger;
+
+// Number of buckets of a histogram, the last one holds every duration above
+// 2^(BUCKETS-2) microseconds, about three days.
+const BUCKETS: usize = 40;
+
+/// LatencySummary sums up how long a phase of the commits took.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// number of commits recorded
+    pub count: u64,
+    /// median duration, rounded up to a power of two of microseconds
+    pub p50: Duration,
+    /// 90th percentile, rounded up to a power of two of microseconds
+    pub p90: Duration,
+    /// 99th percentile, rounded up to a power of two of microseconds
+    pub p99: Duration,
+    /// longest duration
+    pub max: Duration,
+}
+
+/// CommitLatency sums up how long the commits since the database was opened
+/// took, in total and in each phase.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommitLatency {
+    /// whole commit, from the call to commit until it is durable
+    pub total: LatencySummary,
+    /// rebalancing and spilling the dirty nodes
+    pub spill: LatencySummary,
+    /// writing the pages and the meta page
+    pub write: LatencySummary,
+    /// syncing the data file
+    pub sync: LatencySummary,
+}
+
+/// CommitTimes is how long a commit took, in total and in each phase.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CommitTimes {
+    pub(crate) total: Duration,
+    pub(crate) spill: Duration,
+    pub(crate) write: Duration,
+    pub(crate) sync: Duration,
+}
+
+// Histogram counts durations by power of two of microseconds.
+#[derive(Clone, Debug)]
+struct Histogram {
+    // Bucket 0 counts durations below a microsecond, bucket i durations from
+    // 2^(i-1) up to 2^i microseconds.
+    counts: [u64; BUCKETS],
+    count: u64,
+    max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; BUCKETS],
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, d: Duration) {
+        let micros = d.as_micros();
+        let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+        self.counts[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.max = self.max.max(d);
+    }
+
+    // percentile returns the upper bound of the bucket holding the p-th
+    // percentile, at most the longest duration.
+    fn percentile(&self, p: f64) -> Duration {
+        let rank = ((p * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, n) in self.counts[..BUCKETS - 1].iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_micros(1 << bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            p50: self.percentile(0.50),
+            p90: self.percentile(0.90),
+            p99: self.percentile(0.99),
+            max: self.max,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histograms {
+    total: Histogram,
+    spill: Histogram,
+    write: Histogram,
+    sync: Histogram,
+}
+
+/// CommitTimer records the latency of the commits of a database.
+#[derive(Debug, Default)]
+pub(crate) struct CommitTimer {
+    slow_threshold: Option<Duration>,
+    histograms: Mutex<Histograms>,
+}
+
+impl CommitTimer {
+    /// new returns a timer logging the commits slower than slow_threshold,
+    /// if set.
+    pub(crate) fn new(slow_threshold: Option<Duration>) -> CommitTimer {
+        CommitTimer {
+            slow_threshold,
+            histograms: Mutex::default(),
+        }
+    }
+
+    /// record adds a commit of txid, which wrote pages pages, to the
+    /// histograms, and logs it if it was slow.
+    pub(crate) fn record(&self, logger: &dyn Logger, txid: Txid, times: CommitTimes, pages: i64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        histograms.total.record(times.total);
+        histograms.spill.record(times.spill);
+        histograms.write.record(times.write);
+        histograms.sync.record(times.sync);
+        drop(histograms);
+
+        if self.slow_threshold.is_some_and(|slow| times.total > slow) {
+            logger.warn(
+                "slow commit",
+                &[
+                    ("txid", &txid),
+                    ("total", &format!("{:?}", times.total)),
+                    ("spill", &format!("{:?}", times.spill)),
+                    ("write", &format!("{:?}", times.write)),
+                    ("sync", &format!("{:?}", times.sync)),
+                    ("pages", &pages),
+                ],
+            );
+        }
+    }
+
+    /// latency returns the percentiles of the commits recorded so far.
+    pub(crate) fn latency(&self) -> CommitLatency {
+        let histograms = self.histograms.lock().unwrap();
+        CommitLatency {
+            total: histograms.total.summary(),
+            spill: histograms.spill.summary(),
+            write: histograms.write.summary(),
+            sync: histograms.sync.summary(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::{Options, DB};
+    use crate::logger::Field;
+
+    // Ensure that percentiles are rounded up to a power of two of
+    // microseconds, and never exceed the longest duration.
+    #[test]
+    fn test_histogram() {
+        let mut h = Histogram::default();
+        assert_eq!(h.summary(), LatencySummary::default());
+        for micros in 1..=100 {
+            h.record(Duration::from_micros(micros));
+        }
+        h.record(Duration::from_secs(1 << 40));
+        let s = h.summary();
+        assert_eq!(s.count, 101);
+        assert_eq!(s.p50, Duration::from_micros(64));
+        assert_eq!(s.p90, Duration::from_micros(128));
+        assert_eq!(s.max, Duration::from_secs(1 << 40));
+        assert_eq!(h.percentile(1.0), s.max);
+
+        let mut h = Histogram::default();
+        h.record(Duration::from_micros(100));
+        assert_eq!(h.percentile(0.5), Duration::from_micros(100));
+    }
+
+    #[derive(Debug, Default)]
+    struct SlowCommits(Mutex<Vec<String>>);
+
+    impl Logger for SlowCommits {
+        fn debug(&self, _: &str, _: &[Field<'_>]) {}
+
+        fn info(&self, _: &str, _: &[Field<'_>]) {}
+
+        fn warn(&self, msg: &str, fields: &[Field<'_>]) {
+            let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", msg, fields.join(" ")));
+        }
+
+        fn error(&self, _: &str, _: &[Field<'_>]) {}
+    }
+
+    // Ensure that every commit is recorded, and that commits over the
+    // threshold are logged.
+    #[test]
+    fn test_commit_latency() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = Arc::new(SlowCommits::default());
+        let options = Options {
+            slow_commit_threshold: Some(Duration::ZERO),
+            logger: Some(logger.clone()),
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        for i in 0..10u32 {
+            db.update(|tx| {
+                tx.create_bucket_if_not_exists(b"widgets")?
+                    .put(&i.to_be_bytes(), b"bar")
+            })
+            .unwrap();
+        }
+        db.view(|_| Ok(())).unwrap();
+
+        let latency = db.stats().commit_latency;
+        assert_eq!(latency.total.count, 10);
+        assert_eq!(latency.sync.count, 10);
+        assert!(latency.total.p50 <= latency.total.max);
+        assert!(latency.total.max >= latency.spill.max);
+        assert!(latency.total.max >= latency.sync.max);
+
+        let logged = logger.0.lock().unwrap();
+        assert_eq!(logged.len(), 10);
+        assert!(logged[0].starts_with("slow commit txid=2 total="));
+        assert!(logged[0].ends_with(" pages=3"), "{}", logged[0]);
+    }
+}
@@ -25,6 +25,18 @@
 //! delete buckets and can insert and remove keys. Only one read-write transaction
 //! is allowed at a time.
 //!
+//! # Compatibility
+//!
+//! Format version 2 follows the page, meta, freelist and bucket layout of Go
+//! bbolt, with the same FNV-1a meta checksum; tests/file_layout.rs pins the
+//! layout down. Files written by Go bbolt and by this crate are not tested
+//! against each other, since the build does not depend on Go, so sharing a
+//! file between them is not supported.
+//!
+//! Databases created with Options.branch_prefix_compression or a checksum
+//! other than ChecksumType::Fnv use format version 3, which Go bbolt refuses
+//! to open.
+//!
 //! # Caveats
 //!
 //! The database uses a read-only, memory-mapped data file to ensure that
@@ -33,20 +45,120 @@
 //! will cause Go to panic.
 //!
 //! Keys and values retrieved from the database are only valid for the life of
-//! the transaction. When used outside the transaction, these byte slices can
-//! point to different data or can point to invalid memory which will cause a panic.
+//! the transaction. They are slices borrowed from the transaction, like the
+//! buckets and cursors they are read through, so the compiler rejects any use
+//! of them after the transaction is closed. Copy them to keep them longer.
 
 extern crate bitflags;
 extern crate fnv;
 extern crate page_size;
 
+// The bucket and node layers and the shared on-disk structures are still
+// being ported from bbolt and are only partially wired into the transaction code.
+mod arena;
+#[cfg(feature = "tokio")]
+mod async_db;
+mod background_sync;
+mod backup;
+mod batch;
+mod borrows;
+#[allow(dead_code)]
 mod bucket;
+mod bulk;
+mod changes;
+mod checksum;
+#[allow(dead_code)]
 mod common;
+mod compact;
+mod compression;
+#[cfg(feature = "failpoints")]
+mod crash;
+mod crypto;
+mod cursor;
 pub mod db;
+mod defrag;
+mod delete_range;
+mod diff;
+mod dump;
+mod entry;
 mod errors;
+mod export;
+#[cfg(feature = "test-utils")]
+mod fault;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod format;
+// Allocation and rollback are only used once the write path lands.
+#[allow(dead_code)]
+mod freelist;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod group_commit;
+mod health;
+pub mod keys;
+mod latency;
+mod logger;
+#[allow(dead_code)]
 mod node;
 mod os;
+mod page_cache;
+mod page_ref;
+mod rate_limit;
+mod rename;
+mod salvage;
+mod sequence;
+mod stats_cache;
+pub mod surgeon;
+mod ttl;
 pub mod tx;
+mod tx_check;
+#[cfg(feature = "serde")]
+mod typed;
+mod vfs;
+mod watch;
+mod write_batch;
+
+#[cfg(feature = "tokio")]
+pub use async_db::AsyncDB;
+pub use bucket::{Bucket, BucketStats, DEFAULT_REBALANCE_PERCENT, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+pub use bulk::BulkLoader;
+pub use changes::{Change, ChangeSet, ObserverId};
+pub use checksum::ChecksumType;
+pub use common::page::PageInfo;
+pub use compact::CompactOptions;
+pub use compression::Compression;
+#[cfg(feature = "failpoints")]
+pub use crash::{CrashMode, CrashVfs};
+#[cfg(feature = "encryption")]
+pub use crypto::EncryptionKey;
+pub use cursor::Cursor;
+pub use db::{
+    Info, LockDiagnostics, OpenPhase, OpenProgress, Options, Stats, TxInfo, DB,
+    DEFAULT_DECRYPTED_CACHE_SIZE, DEFAULT_MAX_BATCH_DELAY, DEFAULT_MAX_BATCH_SIZE,
+    DEFAULT_READAHEAD,
+};
+pub use defrag::DefragReport;
+pub use diff::diff;
+pub use dump::TreeFormat;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use errors::{BoltError, ErrorContext, IoError, IoOp, Result};
+#[cfg(feature = "test-utils")]
+pub use fault::{Fault, FaultVfs};
+pub use format::{Feature, Format, MAX_FORMAT_VERSION, MIN_FORMAT_VERSION};
+pub use freelist::{FreelistReport, FreelistType};
+pub use health::{Health, LastCheck};
+pub use latency::{CommitLatency, LatencySummary};
+pub use logger::{DefaultLogger, DiscardLogger, Field, Logger};
+pub use page_ref::PageRef;
+pub use salvage::SalvageReport;
+pub use tx::{Tx, TxStats};
+pub use tx_check::{CheckError, CheckErrorKind, CheckReport};
+#[cfg(feature = "serde")]
+pub use typed::{Bincode, Codec, Json, MessagePack, TypedBucket};
+pub use vfs::{MapOptions, MemoryVfs, SyncStrategy, Vfs};
+pub use watch::Event;
+pub use write_batch::{BatchOp, WriteBatch};
 
 #[cfg(test)]
 mod tests {
@@ -56,7 +168,6 @@ mod tests {
     fn it_works() {
         println!("{}", errors::BoltError::Checksum);
 
-        let pid: common::page::PgId = 64;
         assert_eq!(2 + 2, 4);
     }
 }
@@ -0,0 +1,159 @@
+//! Pluggable logging of database events.
+
+use std::fmt;
+
+/// Field is a named value giving context to a log message.
+pub type Field<'a> = (&'a str, &'a dyn fmt::Display);
+
+/// Logger receives the messages of a database, such as it being opened and
+/// closed, remapped, recovering from an invalid meta page, rebuilding its
+/// freelist or failing a consistency check. Set it with Options.logger.
+pub trait Logger: fmt::Debug + Send + Sync {
+    /// debug logs a message useful to follow what the database does.
+    fn debug(&self, msg: &str, fields: &[Field<'_>]);
+
+    /// info logs a message about the normal operation of the database.
+    fn info(&self, msg: &str, fields: &[Field<'_>]);
+
+    /// warn logs a problem the database recovered from.
+    fn warn(&self, msg: &str, fields: &[Field<'_>]);
+
+    /// error logs a problem the database could not recover from.
+    fn error(&self, msg: &str, fields: &[Field<'_>]);
+}
+
+/// DefaultLogger forwards messages to the log crate, with the fields
+/// appended as key=value pairs. It is used unless another logger is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultLogger;
+
+impl DefaultLogger {
+    fn log(&self, level: log::Level, msg: &str, fields: &[Field<'_>]) {
+        log::log!(level, "{}{}", msg, Fields(fields));
+    }
+}
+
+impl Logger for DefaultLogger {
+    fn debug(&self, msg: &str, fields: &[Field<'_>]) {
+        self.log(log::Level::Debug, msg, fields);
+    }
+
+    fn info(&self, msg: &str, fields: &[Field<'_>]) {
+        self.log(log::Level::Info, msg, fields);
+    }
+
+    fn warn(&self, msg: &str, fields: &[Field<'_>]) {
+        self.log(log::Level::Warn, msg, fields);
+    }
+
+    fn error(&self, msg: &str, fields: &[Field<'_>]) {
+        self.log(log::Level::Error, msg, fields);
+    }
+}
+
+/// DiscardLogger drops every message.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiscardLogger;
+
+impl Logger for DiscardLogger {
+    fn debug(&self, _: &str, _: &[Field<'_>]) {}
+
+    fn info(&self, _: &str, _: &[Field<'_>]) {}
+
+    fn warn(&self, _: &str, _: &[Field<'_>]) {}
+
+    fn error(&self, _: &str, _: &[Field<'_>]) {}
+}
+
+// Fields formats fields as " key=value" pairs.
+struct Fields<'a>(&'a [Field<'a>]);
+
+impl fmt::Display for Fields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.0 {
+            write!(f, " {}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // RecordingLogger keeps the messages it receives.
+    #[derive(Debug, Default)]
+    struct RecordingLogger(Mutex<Vec<String>>);
+
+    impl RecordingLogger {
+        fn record(&self, level: &str, msg: &str, fields: &[Field<'_>]) {
+            let line = format!("{} {}{}", level, msg, Fields(fields));
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    impl Logger for RecordingLogger {
+        fn debug(&self, msg: &str, fields: &[Field<'_>]) {
+            self.record("debug", msg, fields);
+        }
+
+        fn info(&self, msg: &str, fields: &[Field<'_>]) {
+            self.record("info", msg, fields);
+        }
+
+        fn warn(&self, msg: &str, fields: &[Field<'_>]) {
+            self.record("warn", msg, fields);
+        }
+
+        fn error(&self, msg: &str, fields: &[Field<'_>]) {
+            self.record("error", msg, fields);
+        }
+    }
+
+    #[test]
+    fn test_logger() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let logger = Arc::new(RecordingLogger::default());
+        let options = Options {
+            logger: Some(logger.clone()),
+            ..Default::default()
+        };
+        let db = DB::open(&path, options.clone()).unwrap();
+        db.rebuild_freelist().unwrap();
+        db.close().unwrap();
+
+        // Corrupt the first meta page, the second one is used instead.
+        let mut buf = std::fs::read(&path).unwrap();
+        buf[56] ^= 0xff;
+        std::fs::write(&path, &buf).unwrap();
+        DB::open(&path, options).unwrap().close().unwrap();
+        assert!(DB::open(
+            dir.path(),
+            Options {
+                logger: Some(logger.clone()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+
+        let lines = logger.0.lock().unwrap();
+        let path = path.display();
+        for line in [
+            format!("info opening db path={}", path),
+            format!("info opened db path={}", path),
+            "info rebuilt freelist free=1".to_string(),
+            format!("info closed db path={}", path),
+            "warn meta page is invalid, using the other one page=0 error=checksum error"
+                .to_string(),
+        ] {
+            assert!(lines.contains(&line), "{:?} not in {:#?}", line, lines);
+        }
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("error failed to open db")));
+    }
+}
@@ -1,35 +1,35 @@
-use crate::bucket::{self, Bucket, MAX_FILL_PERCENT, MIN_FILL_PERCENT};
+use crate::arena::ArenaRef;
+use crate::bucket::{RawBucket, MAX_FILL_PERCENT, MIN_FILL_PERCENT};
 use crate::common;
-use crate::common::inode::{Inode, Inodes, Key};
-use crate::common::page::{Page, PageFlags};
+use crate::common::inode::{Inodes, Key, Value};
+use crate::common::page::{OwnedPage, Page, PageFlags};
 use crate::common::page::{
     PgId, BRANCH_PAGE_ELEMENT_SIZE, LEAF_PAGE_ELEMENT_SIZE, PAGE_HEADER_SIZE,
 };
-use crate::common::types::Byte;
-use std::borrow::{Borrow, BorrowMut};
-use std::cell::RefCell;
-use std::io::Read;
-use std::ops::{Deref, Index};
-use std::ptr::{self, NonNull};
-use std::rc::Rc;
-use std::rc::Weak;
+use crate::common::types::FEATURE_BRANCH_PREFIX;
+use bytes::Bytes;
+use std::cell::{Ref, RefCell};
+use std::fmt;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
 use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::errors::Result;
+use crate::errors::{BoltError, Result};
 
 // Assuming `Bucket`, `common::Pgid`, `common::Inodes`, and `nodes` are defined elsewhere
 
 #[derive(Debug)]
-// Struct representing an in-memory, deserialized page
+// Struct representing an in-memory, deserialized page.
+// Nodes are owned by the arena of their transaction.
 pub(crate) struct RawNode {
-    bucket: *const Bucket, // Use Option<NonNull<T>> for optional non-null pointers
+    bucket: Weak<RawBucket>, // the bucket caches its nodes, which must not keep it alive
     is_leaf: AtomicBool,
     unbalanced: AtomicBool,
     spilled: AtomicBool,
     key: RefCell<Key>,
     pgid: RefCell<PgId>,
     parent: RefCell<WeakNode>, // Use Option<NonNull<T>> for optional non-null pointers
-    children: RefCell<Nodes>,  // Assuming nodes is already defined
+    pub(crate) children: RefCell<Nodes>, // Assuming nodes is already defined
     inodes: RefCell<Inodes>,
 }
 
@@ -39,29 +39,63 @@ impl RawNode {
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub(crate) struct WeakNode(pub(crate) Weak<RawNode>);
+// WeakNode is an optional link to a parent node. It does not own the node,
+// the transaction arena does.
+#[derive(Default, Clone)]
+pub(crate) struct WeakNode(pub(crate) Option<Node>);
 
 impl WeakNode {
-    // 创建新的弱引用节点
+    // 创建新的空引用节点
     pub(crate) fn new() -> Self {
         WeakNode::default()
     }
 
-    // 升级弱引用到强引用
+    // 获取引用的节点
     pub(crate) fn upgrade(&self) -> Option<Node> {
-        self.0.upgrade().map(Node)
+        self.0.clone()
     }
 
-    pub(crate) fn from(tx: &Node) -> Self {
-        WeakNode(Rc::downgrade(&tx.0))
+    pub(crate) fn from(node: &Node) -> Self {
+        WeakNode(Some(node.clone()))
+    }
+}
+
+impl fmt::Debug for WeakNode {
+    // Only print the page id, printing the parent would recurse into its children.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(node) => write!(f, "WeakNode({})", node.pgid()),
+            None => write!(f, "WeakNode(None)"),
+        }
     }
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct Node(pub(crate) Rc<RawNode>);
+pub(crate) struct Node(pub(crate) ArenaRef<RawNode>);
 
 impl Node {
+    /// new creates an empty node that belongs to a bucket.
+    /// The node is allocated in the arena of the bucket's transaction.
+    pub(crate) fn new(bucket: &RawBucket, is_leaf: bool, parent: WeakNode) -> Result<Node> {
+        let tx = bucket.tx()?;
+        Ok(Node(tx.arena().alloc_node(RawNode {
+            bucket: bucket.weak(),
+            is_leaf: AtomicBool::new(is_leaf),
+            unbalanced: AtomicBool::new(false),
+            spilled: AtomicBool::new(false),
+            key: RefCell::new(Key::new()),
+            pgid: RefCell::new(0),
+            parent: RefCell::new(parent),
+            children: RefCell::new(Nodes::default()),
+            inodes: RefCell::new(Inodes::default()),
+        })))
+    }
+
+    /// ptr_eq returns whether both handles refer to the same node.
+    pub(crate) fn ptr_eq(a: &Node, b: &Node) -> bool {
+        ArenaRef::ptr_eq(&a.0, &b.0)
+    }
+
     // Returns the top-level node this node is attached to.
     pub(crate) fn root(&self) -> Node {
         match self.parent() {
@@ -70,10 +104,18 @@ impl Node {
         }
     }
 
-    fn parent(&self) -> Option<Node> {
+    pub(crate) fn parent(&self) -> Option<Node> {
         self.0.parent.borrow().upgrade()
     }
 
+    pub(crate) fn pgid(&self) -> PgId {
+        *self.0.pgid.borrow()
+    }
+
+    pub(crate) fn key(&self) -> Key {
+        self.0.key.borrow().clone()
+    }
+
     // Returns the minimum number of inodes this node should have.
     pub fn min_keys(&self) -> usize {
         if self.is_leaf() {
@@ -87,16 +129,21 @@ impl Node {
         self.0.is_leaf()
     }
 
+    /// inodes borrows the inodes of the node.
+    pub(crate) fn inodes(&self) -> Ref<'_, Inodes> {
+        self.0.inodes.borrow()
+    }
+
     // size returns the size of the node after serialization.
     pub fn size(&self) -> usize {
-        let mut size = PAGE_HEADER_SIZE;
-        size += self.page_element_size();
+        let prefix = self.prefix_len();
+        let mut size = PAGE_HEADER_SIZE + prefix;
 
         let inodes = &self.0.inodes.borrow();
 
         for inode in inodes.iter() {
             size += self.page_element_size();
-            size += inode.key().len() + inode.value().len();
+            size += inode.key().len() - prefix + inode.value().len();
         }
 
         size
@@ -118,12 +165,13 @@ impl Node {
     // This is an optimization to avoid calculating a large node when we only need
     // to know if it fits inside a certain page size.
     pub(crate) fn size_less_than(&self, size: usize) -> bool {
-        let (mut sz, elsz) = (PAGE_HEADER_SIZE, self.page_element_size());
+        let prefix = self.prefix_len();
+        let (mut sz, elsz) = (PAGE_HEADER_SIZE + prefix, self.page_element_size());
 
         let inodes = &self.0.inodes.borrow();
 
         for inode in inodes.iter() {
-            sz += elsz + inode.key().len() + inode.value().len();
+            sz += elsz + inode.key().len() - prefix + inode.value().len();
             if sz >= size {
                 return false;
             }
@@ -132,6 +180,26 @@ impl Node {
         true
     }
 
+    // prefix_len returns the length of the key prefix the node is written
+    // without, which is zero unless it is a branch node of a database with
+    // FEATURE_BRANCH_PREFIX.
+    fn prefix_len(&self) -> usize {
+        match !self.is_leaf() && self.compact_branches() {
+            true => self.0.inodes.borrow().prefix_len(),
+            false => 0,
+        }
+    }
+
+    // compact_branches returns whether the database has FEATURE_BRANCH_PREFIX:
+    // its branch pages store the prefix shared by their keys once, and their
+    // keys are separators truncated to the bytes that tell children apart.
+    fn compact_branches(&self) -> bool {
+        match self.bucket().and_then(|bucket| bucket.tx()) {
+            Ok(tx) => tx.meta().has_feature(FEATURE_BRANCH_PREFIX),
+            Err(_) => false,
+        }
+    }
+
     // Returns the size of each page element based on type of node.
     fn page_element_size(&self) -> usize {
         if self.is_leaf() {
@@ -151,18 +219,19 @@ impl Node {
 
         let child_pgid = self.0.inodes.borrow().get(index).pgid();
 
-        Ok(self
-            .bucket_mut()
-            .unwrap()
-            .node(child_pgid, WeakNode::from(self)))
+        self.bucket()?.node(child_pgid, WeakNode::from(self))
     }
 
-    // childIndex returns the index of a given child node.
-    pub(crate) fn child_index(&self, child: &Node) -> Option<usize> {
+    // childIndex returns the index of a given child node. The key of a child
+    // may lie above its separator, which is truncated, so the child is the
+    // last one whose key is not above the key of the child.
+    pub(crate) fn child_index(&self, child: &Node) -> usize {
         let key = &child.0.key.borrow();
 
-        let index = self.0.inodes.borrow().binary_search_by(&key).ok();
-        index
+        match self.0.inodes.borrow().binary_search_by(key) {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        }
     }
 
     // numChildren returns the number of children.
@@ -170,30 +239,19 @@ impl Node {
         self.0.inodes.borrow().len()
     }
 
-    pub(super) fn bucket<'a, 'b: 'a>(&'a self) -> Option<&'b Bucket> {
-        if self.0.bucket.is_null() {
-            return None;
-        }
-        Some(unsafe { &*(self.0.bucket as *const Bucket) })
-    }
-
-    pub(super) fn bucket_mut<'a, 'b: 'a>(&'a self) -> Option<&'b mut Bucket> {
-        if self.0.bucket.is_null() {
-            return None;
-        }
-        Some(unsafe { &mut *(self.0.bucket as *mut Bucket) })
+    /// bucket returns the bucket of the node. Returns BoltError::TxClosed
+    /// if the bucket is gone, which only happens once its transaction is
+    /// closed or it has been deleted.
+    pub(crate) fn bucket(&self) -> Result<Rc<RawBucket>> {
+        self.0.bucket.upgrade().ok_or(BoltError::TxClosed)
     }
 
     // nextSibling returns the next node with the same parent.
     pub(crate) fn next_sibling(&self) -> Option<Node> {
-        if self.parent().is_none() {
-            // No parent, so no sibling
-            return None;
-        }
-
-        let parent = self.parent().unwrap();
+        // No parent, so no sibling
+        let parent = self.parent()?;
 
-        let index = parent.child_index(self).unwrap();
+        let index = parent.child_index(self);
 
         if index >= parent.num_children() - 1 {
             // Last child, so no next sibling
@@ -224,12 +282,9 @@ impl Node {
 
     // prevSibling returns the previous node with the same parent.
     pub(crate) fn prev_sibling(&self) -> Option<Node> {
-        if self.parent().is_none() {
-            return None;
-        }
-        let parent = self.parent().unwrap();
+        let parent = self.parent()?;
 
-        let index = parent.child_index(self).unwrap();
+        let index = parent.child_index(self);
         if index == 0 {
             // First child, so no previous sibling
 
@@ -259,14 +314,8 @@ impl Node {
     // }
 
     /// put inserts a key/value.
-    pub(crate) fn put(
-        &mut self,
-        old_key: &[u8],
-        new_key: &[u8],
-        value: &[u8],
-        pg_id: PgId,
-        flags: u32,
-    ) {
+    /// The new key and value are shared, not copied.
+    pub(crate) fn put(&self, old_key: &[u8], new_key: Key, value: Value, pg_id: PgId, flags: u32) {
         //todo
         // assert!(pg_id < self.bucket().unwrap().tx.meta.pgid(),
         //         "pgId ({}) above high water mark ({})",
@@ -278,21 +327,20 @@ impl Node {
         let mut inodes = self.0.inodes.borrow_mut();
 
         // Find insertion index using binary_search_by.
+        // Shift nodes if we don't have an exact match and need to insert.
         let index = match inodes.binary_search_by(old_key) {
             Ok(index) => index,
-            Err(index) => index, // Position for insertion
+            Err(index) => {
+                inodes.insert(index, Default::default());
+                index
+            }
         };
 
-        // Shift nodes if needed for insertion.
-        if index < inodes.len() && !inodes.get(index).key().eq(old_key) {
-            inodes.insert(index, Default::default());
-        }
-
         let inode = inodes.get_mut(index);
 
         inode.set_flags(flags);
-        inode.set_key(new_key.to_vec()); // Assuming key needs to be owned
-        inode.set_value(value.to_vec()); // Assuming value needs to be owned
+        inode.set_key(new_key);
+        inode.set_value(value);
         inode.set_pgid(pg_id);
 
         assert!(!inode.key().is_empty(), "put: zero-length inode key");
@@ -327,29 +375,14 @@ impl Node {
     // }
 
     /// del removes a key from the node.
-    pub(crate) fn del(&mut self, key: &[u8]) {
+    pub(crate) fn del(&self, key: &[u8]) {
         // Find index of key.
         let index = match self.0.inodes.borrow().binary_search_by(key) {
             Ok(index) => index,
             // Exit if the key isn't found.
-            Err(_) => return, // Key not found
+            Err(_) => return,
         };
 
-        // Exit if the key isn't found.
-        if index >= self.0.inodes.borrow().len()
-            || self
-                .0
-                .inodes
-                .borrow()
-                .get(index)
-                .key()
-                .as_slice()
-                .cmp(key)
-                .is_eq()
-        {
-            return;
-        }
-
         // Delete inode from the node.
         self.0.inodes.borrow_mut().remove(index);
 
@@ -358,13 +391,18 @@ impl Node {
     }
 
     /// read initializes the node from a page.
-    pub(crate) fn read(&mut self, page: &Page) {
+    /// `buf` holds the whole page; the inodes keep slices of it.
+    /// Returns BoltError::Corrupted if the page is corrupt.
+    pub(crate) fn read(&self, buf: &Bytes) -> Result<()> {
+        let page = Page::try_from_slice(buf)?;
+        let inodes = common::inode::read_inode_from_page(buf).map_err(|_| {
+            BoltError::corrupted("elements lie outside the page").with_pgid(page.id())
+        })?;
+
         *self.0.pgid.borrow_mut() = page.id();
 
         self.0.is_leaf.store(page.is_leaf_page(), Ordering::Release);
 
-        let inodes = common::inode::read_inode_from_page(page);
-
         *self.0.inodes.borrow_mut() = inodes;
 
         if !(self.0.inodes.borrow().is_empty()) {
@@ -376,19 +414,21 @@ impl Node {
                 .first()
                 .map(|inode| inode.key().clone());
 
-            assert!(
-                key.is_none() || key.as_ref().unwrap().len() == 0,
-                "read: zero-length node key"
-            );
-
-            self.0.key.replace(key.unwrap());
+            match key {
+                Some(key) if !key.is_empty() => self.0.key.replace(key),
+                _ => return Err(BoltError::corrupted("zero-length node key").with_pgid(page.id())),
+            };
+        } else {
+            self.0.key.replace(Key::new());
         }
+
+        Ok(())
     }
 
     /// write writes the items onto one or more pages.
     /// The page should have p.id (might be 0 for meta or bucket-inline page) and p.overflow set
     /// and the rest should be zeroed.
-    pub(crate) fn write(&self, page: &mut Page) {
+    pub(crate) fn write(&self, page: &mut OwnedPage) {
         // Assert preconditions
         assert!(
             page.count() == 0 && page.flags().bits() == 0,
@@ -398,6 +438,7 @@ impl Node {
         // Initialize page flags
         let flags = match self.is_leaf() {
             true => PageFlags::LEAF_PAGE,
+            false if self.prefix_len() > 0 => PageFlags::BRANCH_PAGE | PageFlags::PREFIX_PAGE,
             false => PageFlags::BRANCH_PAGE,
         };
 
@@ -417,112 +458,254 @@ impl Node {
             return;
         }
 
-        // Write inodes to page
-        common::inode::write_inode_to_page(self.0.inodes.borrow().deref(), page);
+        // Loop over each item and write it to the page.
+        common::inode::write_inode_to_page(self.0.inodes.borrow().deref(), page.buf_mut());
+    }
+
+    /// spill writes the nodes to dirty pages and splits nodes as it goes.
+    /// Returns an error if dirty pages cannot be allocated.
+    pub(crate) fn spill(&self) -> Result<()> {
+        if self.0.spilled.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let tx = self.bucket()?.tx()?;
+        let db = tx.db()?;
+        let page_size = db.0.page_size();
+        // Encrypted pages end with the nonce and tag, which nodes must leave free.
+        let overhead = db.0.crypt().overhead();
+
+        // Spill child nodes first. Child nodes can materialize sibling nodes in
+        // the case of split-merge so we cannot use a range loop. We have to check
+        // the children size on every loop iteration.
+        self.0.children.borrow_mut().sort();
+        let mut i = 0;
+        loop {
+            let child = match self.0.children.borrow().inner.get(i) {
+                Some(child) => child.clone(),
+                None => break,
+            };
+            child.spill()?;
+            i += 1;
+        }
+
+        // We no longer need the child list because it's only used for spill tracking.
+        self.0.children.borrow_mut().clear();
 
-        // Remove debug-only code (n.dump())
+        // Split nodes into appropriate sizes. The first node will always be n.
+        for node in self.split(page_size - overhead)? {
+            // Add node's page to the freelist if it's not new.
+            node.free()?;
+
+            // Allocate contiguous space for the node, unless it holds a value
+            // streamed to pages of its own, which it is written to.
+            let count = (node.size() + overhead).div_ceil(page_size);
+            let id = match node.streamed_page() {
+                Some(id) if tx.adopt_streamed(id, count) => id,
+                _ => tx.allocate(count)?,
+            };
+
+            // Write the node.
+            assert!(
+                id < tx.meta().pgid(),
+                "pgid ({}) above high water mark ({})",
+                id,
+                tx.meta().pgid()
+            );
+            *node.0.pgid.borrow_mut() = id;
+            tx.write_page(id, |page| node.write(page));
+            node.0.spilled.store(true, Ordering::Release);
+
+            // Insert into parent inodes.
+            if let Some(parent) = node.parent() {
+                parent.put_child(&node);
+            }
+
+            // Update the statistics.
+            tx.stats_mut().spill += 1;
+        }
+
+        // If the root node split and created a new root then we need to spill that
+        // as well. We'll clear out the children to make sure it doesn't try to respill.
+        if let Some(parent) = self.parent() {
+            if parent.pgid() == 0 {
+                self.0.children.borrow_mut().clear();
+                return parent.spill();
+            }
+        }
+
+        Ok(())
     }
 
-   fn split(&mut self, page_size: usize) -> Vec<Node> {
+    /// split breaks up a node into multiple smaller nodes, if appropriate.
+    /// This should only be called from the spill() function.
+    fn split(&self, page_size: usize) -> Result<Vec<Node>> {
         let mut nodes = Vec::new();
 
-        let mut node = self;
+        let mut node = self.clone();
         loop {
             // Split node into two.
-            let (a, b) = node.split_two(page_size);
+            let (a, b) = node.split_two(page_size)?;
             nodes.push(a);
 
             // If we can't split then exit the loop.
-            if b.is_none() {
-                break;
+            match b {
+                Some(b) => node = b,
+                None => break,
             }
-
-            // Set node to b so it gets split on the next iteration.
-            node = b.unwrap();
         }
 
-        nodes
+        Ok(nodes)
     }
 
-     fn split_two(&mut self, page_size: usize) -> (Node, Option<&mut Node>) {
-        // Ignore the split if conditions aren't met.
-        if self.0.inodes.borrow().len() <= (common::page::MIN_KEYS_PER_PAGE * 2) as usize
+    /// split_two breaks up a node into two smaller nodes, if appropriate.
+    /// This should only be called from the split() function.
+    fn split_two(&self, page_size: usize) -> Result<(Node, Option<Node>)> {
+        // Ignore the split if the page doesn't have at least enough nodes for
+        // two pages or if the nodes can fit in a single page.
+        let bucket = self.bucket()?;
+        let split_index = if self.0.inodes.borrow().len()
+            <= (common::page::MIN_KEYS_PER_PAGE * 2) as usize
             || self.size_less_than(page_size)
         {
-            return (self.clone(), None);
-        }
+            None
+        } else {
+            // Determine the threshold before starting a new node.
+            let fill_percent = bucket
+                .fill_percent
+                .get()
+                .clamp(MIN_FILL_PERCENT, MAX_FILL_PERCENT);
+            let threshold = (page_size as f64 * fill_percent) as usize;
+
+            // Determine split position and sizes of the two pages.
+            Some(self.split_index(threshold).0)
+        };
 
-        let clamp = |n, min, max| -> f64 {
-            if n < min {
-                min
-            } else if n > max {
-                max
-            } else {
-                n
+        // A value streamed to pages of its own is written there alone, so it
+        // is split off from the keys around it.
+        let split_index = match (split_index, self.streamed_split()) {
+            (Some(index), Some(streamed)) => index.min(streamed),
+            (Some(index), None) | (None, Some(index)) => index,
+            (None, None) => return Ok((self.clone(), None)),
+        };
+
+        // Split node into two separate nodes.
+        // If there's no parent then we'll need to create one.
+        let parent = match self.parent() {
+            Some(parent) => parent,
+            None => {
+                let parent = Node::new(&bucket, false, WeakNode::new())?;
+                parent.0.children.borrow_mut().push(self.clone());
+                *self.0.parent.borrow_mut() = WeakNode::from(&parent);
+                // The bucket tracks the new root until it has been spilled.
+                *bucket.root_node.borrow_mut() = Some(parent.clone());
+                parent
             }
         };
 
-        // Calculate fill threshold.
-        let fill_percent = clamp(
-            self.bucket().unwrap().fill_percent,
-            MIN_FILL_PERCENT,
-            MAX_FILL_PERCENT,
-        );
+        // Create a new node and add it to the parent.
+        let next = Node::new(&bucket, self.is_leaf(), WeakNode::from(&parent))?;
+        parent.0.children.borrow_mut().push(next.clone());
+
+        // Split inodes across two nodes.
+        let inodes = self.0.inodes.borrow_mut().split_off(split_index);
+        *next.0.inodes.borrow_mut() = inodes;
+
+        // Key both nodes in the parent, so that it routes to them right away.
+        // Spill sets their page ids once allocated. The new node is keyed by
+        // its first key, or with FEATURE_BRANCH_PREFIX by the shortest prefix
+        // of it above the last key of a leaf, which tells them apart as well.
+        // Below leaves the last key of the subtree is not known.
+        let separator = {
+            let (left, right) = (self.0.inodes.borrow(), next.0.inodes.borrow());
+            let first = right.get(0).key().clone();
+            match self.is_leaf() && self.compact_branches() {
+                true => {
+                    let last = left.get(left.len() - 1).key();
+                    let len = separator_len(last, &first);
+                    first.slice(..len)
+                }
+                false => first,
+            }
+        };
+        parent.put_child(self);
+        parent.insert_child(&next, separator);
 
-        let threshold = (page_size as f64 * fill_percent) as usize;
-
-        // Determine split position.
-        let split_index = self.split_index(threshold); // Assuming split_index returns Option
-
-        // Create a new node.
-        let mut next = Node(Rc::new(RawNode {
-            bucket: todo!(),
-            is_leaf: todo!(),
-            parent: todo!(),
-            inodes: todo!(),
-            unbalanced: todo!(),
-            spilled: todo!(),
-            key: todo!(),
-            pgid: todo!(),
-            children: todo!(),
-        }));
-
-        // // Ensure parent exists.
-        // if self.parent.is_none() {
-        //     self.parent = Some(Box::new(Node {
-        //         bucket: self.bucket,
-        //         children: vec![self],
-        //         // ...other fields
-        //     }));
-        // }
-
-        // Add new node to parent.
-        self.parent()
-            .as_mut()
-            .unwrap()
-            .0
-            .children
-            .borrow()
-            .push(next);
-
-        // Split inodes.
-        // next.inodes = self.inodes.split_off(split_index);
+        // Update the statistics.
+        if let Ok(tx) = bucket.tx() {
+            tx.stats_mut().split += 1;
+        }
 
-        // Update statistics.
-        // self.bucket().tx.stats.inc_split(1);
+        Ok((self.clone(), Some(next)))
+    }
+
+    // put_child inserts or updates the inode of a child node, keyed by the
+    // first key of the child, and makes that key the key of the child.
+    //
+    // With FEATURE_BRANCH_PREFIX the inode of a child may be keyed by a
+    // truncated separator, found below the key of the child. The separator
+    // is kept as long as it is not above the first key: the keys of the
+    // child are routed by it, so those of the previous child stay below it.
+    fn put_child(&self, child: &Node) {
+        let first_key = child.0.inodes.borrow().get(0).key().clone();
+        assert!(!first_key.is_empty(), "spill: zero-length node key");
+        let mut key = child.key();
+        if key.is_empty() {
+            key = first_key.clone();
+        }
+        let routed = match self.0.inodes.borrow().binary_search_by(&key) {
+            Err(index) if index > 0 && self.compact_branches() => Some(index - 1),
+            _ => None,
+        };
+        match routed {
+            Some(index) => {
+                let mut inodes = self.0.inodes.borrow_mut();
+                let inode = inodes.get_mut(index);
+                if inode.key() > &first_key {
+                    inode.set_key(first_key.clone());
+                }
+                inode.set_pgid(child.pgid());
+            }
+            None => self.put(&key, first_key.clone(), Value::new(), child.pgid(), 0),
+        }
+        child.0.key.replace(first_key);
+    }
+
+    // insert_child inserts the inode of a child split off from its previous
+    // sibling, keyed by separator, and makes the first key of the child its
+    // key.
+    fn insert_child(&self, child: &Node, separator: Key) {
+        let first_key = child.0.inodes.borrow().get(0).key().clone();
+        self.put(&separator, separator.clone(), Value::new(), child.pgid(), 0);
+        child.0.key.replace(first_key);
+    }
 
-        (self.clone(), Some(&mut next)) // Return both nodes as an Option
+    // del_child removes the inode of a child node.
+    fn del_child(&self, child: &Node) {
+        if self.0.inodes.borrow().is_empty() {
+            return;
+        }
+        let index = self.child_index(child);
+        self.0.inodes.borrow_mut().remove(index);
+        self.0.unbalanced.store(true, Ordering::Release);
     }
 
-     fn split_index(&self, threshold: usize) -> (usize, usize) {
-        let mut sz = common::page::PAGE_HEADER_SIZE;
+    /// split_index finds the position where a page will fill a given threshold.
+    /// It returns the index as well as the size of the first page.
+    /// This is only be called from split().
+    fn split_index(&self, threshold: usize) -> (usize, usize) {
+        // Both pages share at least the prefix of the whole node.
+        let prefix = self.prefix_len();
+        let mut sz = common::page::PAGE_HEADER_SIZE + prefix;
         let mut index = 0;
 
         // Loop until minimum keys remain for the second page.
         for i in 0..self.0.inodes.borrow().len() - common::page::MIN_KEYS_PER_PAGE as usize {
+            index = i;
+
             // Calculate element size.
-            let elsize = self.page_element_size()
-                + self.0.inodes.borrow().inodes[i].key().len()
+            let elsize = self.page_element_size() + self.0.inodes.borrow().inodes[i].key().len()
+                - prefix
                 + self.0.inodes.borrow().inodes[i].value().len();
 
             // Check for split condition.
@@ -530,33 +713,588 @@ impl Node {
                 break;
             }
 
-            // Update size and index.
+            // Update size.
             sz += elsize;
-            index = i;
         }
 
         (index, sz)
     }
 
+    // streamed_split returns where to split a leaf so that the first value
+    // in it streamed by Bucket::put_reader ends up alone in a node, if any.
+    // The pages of a streamed value are the pgid of its leaf inode.
+    fn streamed_split(&self) -> Option<usize> {
+        let inodes = self.0.inodes.borrow();
+        if !self.is_leaf() || inodes.len() < 2 {
+            return None;
+        }
+        match inodes.iter().position(|inode| inode.pgid() != 0)? {
+            0 => Some(1),
+            index => Some(index),
+        }
+    }
+
+    // streamed_page returns the pages the value of a leaf was streamed to by
+    // Bucket::put_reader, if it is the only key of the leaf.
+    fn streamed_page(&self) -> Option<PgId> {
+        let inodes = self.0.inodes.borrow();
+        match self.is_leaf() && inodes.len() == 1 {
+            true => Some(inodes.get(0).pgid()).filter(|&id| id != 0),
+            false => None,
+        }
+    }
+
+    /// mark_unbalanced makes the commit rebalance the node, merging it with a
+    /// sibling if it is underfilled.
+    pub(crate) fn mark_unbalanced(&self) {
+        self.0.unbalanced.store(true, Ordering::Release);
+    }
+
+    /// rebalance attempts to combine the node with sibling nodes if the node fill
+    /// size is below a threshold or if there are not enough keys.
+    pub(crate) fn rebalance(&self) -> Result<()> {
+        if !self.0.unbalanced.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // Update statistics.
+        let tx = self.bucket()?.tx()?;
+        tx.stats_mut().rebalance += 1;
+
+        // Ignore if node is above threshold (25% by default) and has enough keys.
+        let threshold = self.bucket()?.rebalance_threshold(tx.db()?.0.page_size());
+        if self.size() > threshold && self.num_children() > self.min_keys() {
+            return Ok(());
+        }
+
+        // Root node has special handling.
+        let parent = match self.parent() {
+            Some(parent) => parent,
+            None => {
+                // If root node is a branch whose children were all removed
+                // then it is an empty leaf.
+                if !self.is_leaf() && self.num_children() == 0 {
+                    self.0.is_leaf.store(true, Ordering::Release);
+                    return Ok(());
+                }
+
+                // If root node is a branch and only has one node then collapse it.
+                if !self.is_leaf() && self.num_children() == 1 {
+                    // Move root's child up.
+                    let child = self.child_at(0)?;
+                    self.0.is_leaf.store(child.is_leaf(), Ordering::Release);
+                    *self.0.inodes.borrow_mut() = child.0.inodes.take();
+                    *self.0.children.borrow_mut() = child.0.children.take();
+
+                    // Reparent all child nodes being moved.
+                    let bucket = self.bucket()?;
+                    for inode in self.0.inodes.borrow().iter() {
+                        if let Some(child) = bucket.nodes.borrow().get(&inode.pgid()) {
+                            *child.0.parent.borrow_mut() = WeakNode::from(self);
+                        }
+                    }
+
+                    // Remove old child.
+                    *child.0.parent.borrow_mut() = WeakNode::new();
+                    bucket.nodes.borrow_mut().remove(&child.pgid());
+                    child.free()?;
+                }
+
+                return Ok(());
+            }
+        };
+
+        // If node has no keys then just remove it.
+        if self.num_children() == 0 {
+            parent.del_child(self);
+            parent.remove_child(self);
+            self.bucket()?.nodes.borrow_mut().remove(&self.pgid());
+            self.free()?;
+            return parent.rebalance();
+        }
+
+        assert!(
+            parent.num_children() > 1,
+            "parent must have at least 2 children"
+        );
+
+        // Merge with right sibling if idx == 0, otherwise left sibling.
+        let (left, right) = if parent.child_index(self) == 0 {
+            (
+                self.clone(),
+                self.next_sibling().expect("missing next sibling"),
+            )
+        } else {
+            (
+                self.prev_sibling().expect("missing prev sibling"),
+                self.clone(),
+            )
+        };
+
+        // Reparent all child nodes being moved.
+        let pgids: Vec<PgId> = right.0.inodes.borrow().iter().map(|i| i.pgid()).collect();
+        for pgid in pgids {
+            let child = self.bucket()?.nodes.borrow().get(&pgid).cloned();
+            if let Some(child) = child {
+                if let Some(old) = child.parent() {
+                    old.remove_child(&child);
+                }
+                *child.0.parent.borrow_mut() = WeakNode::from(&left);
+                left.0.children.borrow_mut().push(child);
+            }
+        }
+
+        // Copy over inodes from right node to left node and remove right node.
+        left.0
+            .inodes
+            .borrow_mut()
+            .append(&mut right.0.inodes.borrow_mut());
+        parent.del_child(&right);
+        parent.remove_child(&right);
+        self.bucket()?.nodes.borrow_mut().remove(&right.pgid());
+        right.free()?;
+
+        // Either this node or the sibling node was underflowed, so rebalance the parent.
+        parent.rebalance()
+    }
+
+    /// free adds the node's underlying page to the freelist.
+    pub(crate) fn free(&self) -> Result<()> {
+        let pgid = self.pgid();
+        if pgid != 0 {
+            let tx = self.bucket()?.tx()?;
+            tx.db()?.0.freelist_mut()?.free(tx.id(), &tx.page(pgid)?);
+            *self.0.pgid.borrow_mut() = 0;
+        }
+        Ok(())
+    }
+
     // removes a node from the list of in-memory children.
     // This does not affect the inodes.
-     fn remove_child(&mut self, target: &Node) {
+    fn remove_child(&self, target: &Node) {
         //可能有性能问题
         self.0.children.borrow_mut().retain(target);
     }
 }
 
-#[derive(Debug)]
+// separator_len returns the length of the shortest prefix of next above
+// prev, which is below next.
+fn separator_len(prev: &[u8], next: &[u8]) -> usize {
+    let common = prev.iter().zip(next).take_while(|(a, b)| a == b).count();
+    (common + 1).min(next.len())
+}
+
+#[derive(Debug, Default)]
 pub(crate) struct Nodes {
     inner: Vec<Node>,
 }
 
 impl Nodes {
     fn retain(&mut self, target: &Node) {
-        self.inner.retain(|child| !(ptr::eq(child, target)));
+        self.inner.retain(|child| !Node::ptr_eq(child, target));
     }
 
-    fn push(&mut self, value: Node) {
+    pub(crate) fn push(&mut self, value: Node) {
         self.inner.push(value);
     }
+
+    pub(crate) fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    // sort orders the nodes by their first key.
+    fn sort(&mut self) {
+        self.inner.sort_by(|a, b| {
+            let a = a.0.inodes.borrow();
+            let b = b.0.inodes.borrow();
+            a.get(0).key().cmp(b.get(0).key())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    fn with_bucket<F: FnOnce(&RawBucket)>(f: F) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        let tx = db.begin_rw().unwrap();
+        f(&tx.root().0);
+        tx.rollback().unwrap();
+    }
+
+    fn leaf(bucket: &RawBucket, n: usize) -> Node {
+        let node = Node::new(bucket, true, WeakNode::new()).unwrap();
+        for i in 1..=n {
+            let key = format!("{:08}", i);
+            node.put(
+                key.as_bytes(),
+                Key::from(key.clone()),
+                Value::from_static(b"0123456701234567"),
+                0,
+                0,
+            );
+        }
+        node
+    }
+
+    // attach hangs a cached leaf holding `keys` off a branch node, backed by a
+    // freshly allocated page so that freeing it has something to release.
+    fn attach(bucket: &RawBucket, parent: &Node, keys: &[&str]) -> Node {
+        let pgid = bucket.tx().unwrap().allocate(1).unwrap();
+        let node = Node::new(bucket, true, WeakNode::from(parent)).unwrap();
+        for key in keys {
+            let key = Key::copy_from_slice(key.as_bytes());
+            node.put(&key, key.clone(), key.clone(), 0, 0);
+        }
+        node.0.key.replace(Key::copy_from_slice(keys[0].as_bytes()));
+        *node.0.pgid.borrow_mut() = pgid;
+
+        let key = Key::copy_from_slice(keys[0].as_bytes());
+        parent.put(&key, key.clone(), Value::new(), pgid, 0);
+        parent.0.children.borrow_mut().push(node.clone());
+        bucket.nodes.borrow_mut().insert(pgid, node.clone());
+        node
+    }
+
+    // Ensure that deleting a key marks the node as unbalanced.
+    #[test]
+    fn test_node_del() {
+        with_bucket(|bucket| {
+            let node = leaf(bucket, 3);
+            node.del(b"00000009");
+            assert!(!node.0.unbalanced.load(Ordering::Acquire));
+            assert_eq!(node.num_children(), 3);
+
+            node.del(b"00000002");
+            assert!(node.0.unbalanced.load(Ordering::Acquire));
+            assert_eq!(node.num_children(), 2);
+            assert_eq!(node.0.inodes.borrow().get(1).key(), &b"00000003"[..]);
+        });
+    }
+
+    // Ensure that a root branch with a single child is collapsed into that child.
+    #[test]
+    fn test_node_rebalance_collapse_root() {
+        with_bucket(|bucket| {
+            let root = Node::new(bucket, false, WeakNode::new()).unwrap();
+            let child = attach(bucket, &root, &["a", "b"]);
+            let pgid = child.pgid();
+
+            root.0.unbalanced.store(true, Ordering::Release);
+            root.rebalance().unwrap();
+
+            assert!(root.is_leaf());
+            assert_eq!(root.num_children(), 2);
+            assert!(bucket.nodes.borrow().is_empty());
+            assert_eq!(child.pgid(), 0);
+
+            let tx = bucket.tx().unwrap();
+            assert_eq!(tx.stats().rebalance, 1);
+            assert!(tx.db().unwrap().0.freelist().unwrap().freed(pgid));
+        });
+    }
+
+    // Ensure that an under-filled node is merged into its sibling and the
+    // emptied branch above them collapses.
+    #[test]
+    fn test_node_rebalance_merge() {
+        with_bucket(|bucket| {
+            let root = Node::new(bucket, false, WeakNode::new()).unwrap();
+            *bucket.root_node.borrow_mut() = Some(root.clone());
+            let left = attach(bucket, &root, &["a", "b"]);
+            let right = attach(bucket, &root, &["c", "d"]);
+            let (lpgid, rpgid) = (left.pgid(), right.pgid());
+
+            right.del(b"d");
+            bucket.rebalance().unwrap();
+
+            assert!(root.is_leaf());
+            let keys: Vec<Key> = root
+                .0
+                .inodes
+                .borrow()
+                .iter()
+                .map(|i| i.key().clone())
+                .collect();
+            assert_eq!(keys, vec![&b"a"[..], &b"b"[..], &b"c"[..]]);
+            assert!(bucket.nodes.borrow().is_empty());
+
+            let tx = bucket.tx().unwrap();
+            let db = tx.db().unwrap();
+            assert!(db.0.freelist().unwrap().freed(lpgid));
+            assert!(db.0.freelist().unwrap().freed(rpgid));
+        });
+    }
+
+    // Ensure that a node left without keys is removed from its parent.
+    #[test]
+    fn test_node_rebalance_remove_empty() {
+        with_bucket(|bucket| {
+            let root = Node::new(bucket, false, WeakNode::new()).unwrap();
+            *bucket.root_node.borrow_mut() = Some(root.clone());
+            attach(bucket, &root, &["a", "b"]);
+            attach(bucket, &root, &["c", "d"]);
+            let last = attach(bucket, &root, &["e"]);
+
+            last.del(b"e");
+            last.rebalance().unwrap();
+
+            assert!(!root.is_leaf());
+            assert_eq!(root.num_children(), 2);
+            assert_eq!(root.0.children.borrow().len(), 2);
+            assert_eq!(bucket.nodes.borrow().len(), 2);
+            assert_eq!(last.pgid(), 0);
+        });
+    }
+
+    // Ensure that spilled nodes are written to disk and can be read back.
+    #[test]
+    fn test_node_spill_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        {
+            let db = DB::open(&path, Options::default()).unwrap();
+            let tx = db.begin_rw().unwrap();
+            let root = tx.root();
+            let node = root.0.node(root.0.root_page(), WeakNode::new()).unwrap();
+            for i in 0..500 {
+                let key = format!("{:08}", i);
+                node.put(
+                    key.as_bytes(),
+                    Key::from(key.clone()),
+                    Value::from_static(b"0123456701234567"),
+                    0,
+                    0,
+                );
+            }
+            tx.commit().unwrap();
+            db.close().unwrap();
+        }
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        let tx = db.begin_ro().unwrap();
+        let page = tx.page_bytes(tx.meta().root_bucket().root_page()).unwrap();
+        assert!(Page::from_slice(&page).is_branch_page());
+
+        let mut count = 0;
+        for branch in common::inode::read_inode_from_page(&page).unwrap().iter() {
+            let leaf = tx.page_bytes(branch.pgid()).unwrap();
+            let leaf = common::inode::read_inode_from_page(&leaf).unwrap();
+            assert_eq!(leaf.get(0).key(), branch.key());
+            for inode in leaf.iter() {
+                assert_eq!(inode.key(), format!("{:08}", count).as_bytes());
+                assert_eq!(inode.value(), &b"0123456701234567"[..]);
+                count += 1;
+            }
+        }
+        assert_eq!(count, 500);
+    }
+
+    // Ensure that with FEATURE_BRANCH_PREFIX the leaves are keyed by the
+    // shortest separators telling them apart, and that keys put before the
+    // first key of a leaf or deleted are still routed to the right one.
+    #[test]
+    fn test_node_spill_separators() {
+        let key = |i: u32| format!("users/{:06}/settings/theme", i).into_bytes();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = Options {
+            branch_prefix_compression: true,
+            ..Default::default()
+        };
+        let db = DB::open(&path, options).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in (0..20000).step_by(2) {
+                b.put(&key(i), b"v")?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        // walk returns the first and last keys below a page, and counts the
+        // separators shorter than the first key of their child.
+        fn walk(tx: &crate::Tx, pgid: PgId, counts: &mut (usize, usize)) -> (Key, Key) {
+            let page = tx.page_bytes(pgid).unwrap();
+            let inodes = common::inode::read_inode_from_page(&page).unwrap();
+            if Page::from_slice(&page).is_leaf_page() {
+                return (
+                    inodes.get(0).key().clone(),
+                    inodes.get(inodes.len() - 1).key().clone(),
+                );
+            }
+            let mut bounds: Option<(Key, Key)> = None;
+            for inode in inodes.iter() {
+                let (first, last) = walk(tx, inode.pgid(), counts);
+                assert!(inode.key() <= &first);
+                if let Some((_, prev)) = &bounds {
+                    assert!(prev < inode.key());
+                    counts.0 += (inode.key().len() < first.len()) as usize;
+                    counts.1 += 1;
+                }
+                bounds = Some((bounds.map_or(first, |(first, _)| first), last));
+            }
+            bounds.unwrap()
+        }
+        let check_separators = |tx: &crate::Tx| {
+            let mut counts = (0, 0);
+            walk(
+                tx,
+                tx.bucket(b"widgets").unwrap().0.root_page(),
+                &mut counts,
+            );
+            assert!(counts.0 * 2 > counts.1, "{} of {}", counts.0, counts.1);
+        };
+        db.view(|tx| {
+            check_separators(tx);
+            Ok(())
+        })
+        .unwrap();
+
+        // Fill the gaps, which puts keys before the first key of most leaves,
+        // then delete enough to merge leaves.
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            for i in (1..20000).step_by(2) {
+                b.put(&key(i), b"v")?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            for i in (0..20000).filter(|i| i % 10 != 0) {
+                b.delete(&key(i))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.view(|tx| {
+            check_separators(tx);
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            for i in 0..20000 {
+                assert_eq!(b.get(&key(i)).is_some(), i % 10 == 0, "{}", i);
+            }
+            let mut keys = Vec::new();
+            let mut c = b.cursor();
+            let mut item = c.first();
+            while let Some((k, _)) = item {
+                keys.push(k.to_vec());
+                item = c.next();
+            }
+            assert_eq!(keys, (0..20000).step_by(10).map(key).collect::<Vec<_>>());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a node can split into appropriate subgroups.
+    #[test]
+    fn test_node_split() {
+        with_bucket(|bucket| {
+            let node = leaf(bucket, 5);
+
+            // Split between 2 & 3.
+            node.split(100).unwrap();
+
+            let parent = node.parent().expect("expected parent");
+            let children = parent.0.children.borrow();
+            assert_eq!(children.len(), 2);
+            assert_eq!(children.inner[0].0.inodes.borrow().len(), 2);
+            assert_eq!(children.inner[1].0.inodes.borrow().len(), 3);
+
+            // The parent routes to both halves by their first key.
+            let inodes = parent.0.inodes.borrow();
+            let keys: Vec<_> = inodes.iter().map(|inode| inode.key().clone()).collect();
+            assert_eq!(keys, vec![Key::from("00000001"), Key::from("00000003")]);
+            assert_eq!(children.inner[1].key(), Key::from("00000003"));
+
+            // The bucket keeps the new root alive until it is spilled.
+            let root = bucket.root_node.borrow().clone().unwrap();
+            assert!(Node::ptr_eq(&root, &parent));
+        });
+    }
+
+    // Ensure that splits cascade up to a new root when the branch above the
+    // split leaves overflows in turn.
+    #[test]
+    fn test_node_split_cascade() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        for batch in 0..4u32 {
+            db.update(|tx| {
+                let b = tx.create_bucket_if_not_exists(b"widgets")?;
+                for i in batch * 10000..(batch + 1) * 10000 {
+                    b.put(format!("{:08}", i).as_bytes(), b"0123456701234567")?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            let stats = b.stats()?;
+            assert_eq!(stats.depth, 3);
+            assert_eq!(stats.key_n, 40000);
+            let mut c = b.cursor();
+            let mut i = 0;
+            let mut item = c.first();
+            while let Some((key, _)) = item {
+                assert_eq!(key, format!("{:08}", i).as_bytes());
+                i += 1;
+                item = c.next();
+            }
+            assert_eq!(i, 40000);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a page with the minimum number of inodes just returns a single node.
+    #[test]
+    fn test_node_split_min_keys() {
+        with_bucket(|bucket| {
+            let node = leaf(bucket, 2);
+            node.split(20).unwrap();
+            assert!(node.parent().is_none(), "expected nil parent");
+        });
+    }
+
+    // Ensure that a node that has keys that all fit on a page just returns one leaf.
+    #[test]
+    fn test_node_split_single_page() {
+        with_bucket(|bucket| {
+            let node = leaf(bucket, 5);
+            node.split(4096).unwrap();
+            assert!(node.parent().is_none(), "expected nil parent");
+        });
+    }
+
+    // Ensure that a node outliving its bucket reports it instead of reaching
+    // freed memory.
+    #[test]
+    fn test_node_bucket_dropped() {
+        with_bucket(|root| {
+            let bucket = crate::bucket::Bucket::new(root.tx.clone());
+            let node = leaf(&bucket.0, 3);
+            assert!(Rc::ptr_eq(&node.bucket().unwrap(), &bucket.0));
+
+            drop(bucket);
+            assert_eq!(node.bucket().err(), Some(BoltError::TxClosed));
+            assert_eq!(node.spill().err(), Some(BoltError::TxClosed));
+        });
+    }
 }
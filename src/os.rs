@@ -1,13 +1,38 @@
 //!
-//! 
+//! os platform specific constants and file helpers
 //!
 
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::{fs::FileExt, io::AsRawFd};
+#[cfg(windows)]
+use std::os::windows::{fs::FileExt, io::AsRawHandle};
+#[cfg(any(unix, windows))]
+use std::thread;
+use std::time::Duration;
+#[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+use std::time::Instant;
+
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+#[cfg(windows)]
+use windows_sys::Win32::Storage::FileSystem::{
+    LockFileEx, UnlockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+};
+#[cfg(windows)]
+use windows_sys::Win32::System::IO::OVERLAPPED;
+
+#[cfg(any(unix, windows))]
+use crate::errors::BoltError;
+use crate::errors::{IoOp, IoResultExt, Result};
+
 #[cfg(any(
     target_arch = "x86_64",
     target_arch = "aarch64",
     target_arch = "powerpc64",
     target_arch = "mips64",
-    target_arch= "loongarch64"
+    target_arch = "loongarch64"
 ))]
 // maxMapSize represents the largest mmap size supported by Bolt.
 pub const MAX_MAP_SIZE: u64 = 0xFFFFFFFFFFFF; // 256TB
@@ -16,10 +41,272 @@ pub const MAX_MAP_SIZE: u64 = 0xFFFFFFFFFFFF; // 256TB
     target_arch = "x86",
     target_arch = "arm",
     target_arch = "mips",
-    target_arch = "powerpc"
+    target_arch = "powerpc",
+    target_arch = "wasm32"
 ))]
 // maxMapSize represents the largest mmap size supported by Bolt.
-pub const MAX_MAP_SIZE :u64= 0x7FFFFFFF; // 2GB
+pub const MAX_MAP_SIZE: u64 = 0x7FFFFFFF; // 2GB
 
 // maxAllocSize is the size used when creating array pointers.
-pub const MAX_ALLOC_SIZE :u64= 0x7FFFFFFF;
\ No newline at end of file
+#[allow(dead_code)]
+pub const MAX_ALLOC_SIZE: u64 = 0x7FFFFFFF;
+
+// flockRetryTimeout is the interval between two attempts to acquire the file lock.
+#[cfg(any(unix, windows))]
+const FLOCK_RETRY_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// flock acquires an advisory lock on a file descriptor.
+/// A zero timeout waits indefinitely for the lock.
+#[cfg(any(unix, windows))]
+pub(crate) fn flock(file: &File, exclusive: bool, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        // Attempt to obtain the lock.
+        if try_lock(file, exclusive).op(IoOp::Lock)? {
+            return Ok(());
+        }
+
+        // If we timed out then return an error.
+        if !timeout.is_zero() && start.elapsed() > timeout {
+            return Err(BoltError::Timeout);
+        }
+
+        // Wait for a bit and try again.
+        thread::sleep(FLOCK_RETRY_TIMEOUT);
+    }
+}
+
+// try_lock takes a lock on a file without waiting, returning whether it got
+// it.
+#[cfg(unix)]
+fn try_lock(file: &File, exclusive: bool) -> io::Result<bool> {
+    let flag = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    let rc = unsafe { libc::flock(file.as_raw_fd(), flag | libc::LOCK_NB) };
+    if rc == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        true => Ok(false),
+        false => Err(err),
+    }
+}
+
+// try_lock takes a lock on a file without waiting, returning whether it got
+// it. Windows locks byte ranges and keeps other handles from reading locked
+// bytes, so like Go bbolt it locks the last byte a file could have, which is
+// never read.
+#[cfg(windows)]
+fn try_lock(file: &File, exclusive: bool) -> io::Result<bool> {
+    let mut flags = LOCKFILE_FAIL_IMMEDIATELY;
+    if exclusive {
+        flags |= LOCKFILE_EXCLUSIVE_LOCK;
+    }
+    let mut overlapped = lock_range();
+    let ok = unsafe { LockFileEx(file.as_raw_handle(), flags, 0, 1, 0, &mut overlapped) };
+    if ok != 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+        true => Ok(false),
+        false => Err(err),
+    }
+}
+
+// lock_range returns the OVERLAPPED locks are taken on, pointing at the last
+// byte of the largest file.
+#[cfg(windows)]
+fn lock_range() -> OVERLAPPED {
+    let mut overlapped = OVERLAPPED::default();
+    overlapped.Anonymous.Anonymous.Offset = u32::MAX;
+    overlapped.Anonymous.Anonymous.OffsetHigh = u32::MAX;
+    overlapped
+}
+
+/// flock does nothing on wasm, which has no advisory file locks. Nothing
+/// keeps two processes from opening the file for writing.
+#[cfg(target_family = "wasm")]
+pub(crate) fn flock(_file: &File, _exclusive: bool, _timeout: Duration) -> Result<()> {
+    Ok(())
+}
+
+/// funlock releases an advisory lock on a file descriptor.
+#[cfg(unix)]
+pub(crate) fn funlock(file: &File) -> Result<()> {
+    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+    if rc != 0 {
+        return Err(BoltError::io(IoOp::Lock, std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// funlock releases the lock flock took on a file.
+#[cfg(windows)]
+pub(crate) fn funlock(file: &File) -> Result<()> {
+    let mut overlapped = lock_range();
+    let ok = unsafe { UnlockFileEx(file.as_raw_handle(), 0, 1, 0, &mut overlapped) };
+    if ok == 0 {
+        return Err(BoltError::io(IoOp::Lock, std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// funlock does nothing on wasm, which has no advisory file locks.
+#[cfg(target_family = "wasm")]
+pub(crate) fn funlock(_file: &File) -> Result<()> {
+    Ok(())
+}
+
+/// fdatasync flushes written data to a file descriptor.
+pub(crate) fn fdatasync(file: &File) -> Result<()> {
+    file.sync_data().op(IoOp::Sync)
+}
+
+/// willneed tells the OS that a range of a mapping will be read soon, so it
+/// starts reading it in the background. It is only advice, failures are
+/// ignored.
+#[cfg(unix)]
+pub(crate) fn willneed(buf: &[u8]) {
+    if buf.is_empty() {
+        return;
+    }
+    // The range has to start at a page boundary.
+    let os_page = page_size::get();
+    let addr = buf.as_ptr() as usize;
+    let start = addr - addr % os_page;
+    let len = addr + buf.len() - start;
+    unsafe {
+        libc::madvise(start as *mut libc::c_void, len, libc::MADV_WILLNEED);
+    }
+}
+
+/// willneed does nothing where mappings cannot be advised.
+#[cfg(not(unix))]
+pub(crate) fn willneed(_buf: &[u8]) {}
+
+/// full_fsync flushes a file all the way to the storage medium with
+/// F_FULLFSYNC. Filesystems not supporting it get an fsync instead.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(crate) fn full_fsync(file: &File) -> io::Result<()> {
+    let rc = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_FULLFSYNC) };
+    if rc == -1 {
+        return file.sync_all();
+    }
+    Ok(())
+}
+
+/// full_fsync is fsync where F_FULLFSYNC does not exist.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+pub(crate) fn full_fsync(file: &File) -> io::Result<()> {
+    file.sync_all()
+}
+
+/// read_at reads bytes from a file at offset, returning how many were read.
+#[cfg(unix)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    FileExt::read_at(file, buf, offset)
+}
+
+/// read_at reads bytes from a file at offset, returning how many were read.
+/// The file cursor is moved as well, but the read does not depend on it.
+#[cfg(windows)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    FileExt::seek_read(file, buf, offset)
+}
+
+/// read_at reads bytes from a file at offset, returning how many were read.
+/// Positional reads are not available here, so the file cursor is moved.
+#[cfg(target_family = "wasm")]
+pub(crate) fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::io::{Read, Seek, SeekFrom};
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// write_at writes bytes to a file at offset, returning how many were written.
+#[cfg(unix)]
+pub(crate) fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    FileExt::write_at(file, buf, offset)
+}
+
+/// write_at writes bytes to a file at offset, returning how many were written.
+/// The file cursor is moved as well, but the write does not depend on it.
+#[cfg(windows)]
+pub(crate) fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    FileExt::seek_write(file, buf, offset)
+}
+
+/// write_at writes bytes to a file at offset, returning how many were written.
+/// Positional writes are not available here, so the file cursor is moved.
+#[cfg(target_family = "wasm")]
+pub(crate) fn write_at(mut file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    use std::io::{Seek, SeekFrom, Write};
+    file.seek(SeekFrom::Start(offset))?;
+    file.write(buf)
+}
+
+/// read_exact_at fills buf with the bytes of a file at offset.
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// write_all_at writes all of buf to a file at offset.
+pub(crate) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match write_at(file, buf, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stopwatch measures the time an operation takes. wasm32-unknown-unknown
+/// has no clock, so there it always reads zero instead of panicking.
+pub(crate) struct Stopwatch {
+    #[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+    start: Instant,
+}
+
+#[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+impl Stopwatch {
+    /// start starts measuring.
+    pub(crate) fn start() -> Stopwatch {
+        Stopwatch {
+            start: Instant::now(),
+        }
+    }
+
+    /// elapsed returns the time since the stopwatch was started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+impl Stopwatch {
+    pub(crate) fn start() -> Stopwatch {
+        Stopwatch {}
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::ZERO
+    }
+}
@@ -0,0 +1,306 @@
+//! Reading pages without mmap.
+//!
+//! With Options.page_cache_size set the data file is never memory-mapped:
+//! pages are read with positional reads instead, and the most recently read
+//! ones are kept in an LRU cache shared by the transactions. This lets
+//! databases larger than the address space be opened on 32-bit targets.
+//!
+//! A committed page never changes while a transaction can reach it, so the
+//! cache needs no versioning. An entry only goes stale once its page has been
+//! freed, released and allocated again, which is when it is dropped. The
+//! meta pages are overwritten in place and are never cached.
+//!
+//! The pages of an encrypted database that is memory-mapped are decrypted
+//! out of the mmap, and the latest ones are kept in a DecryptedPages cache of
+//! the same kind, so that only a bounded number of them is held in the clear.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use crate::common::page::{OwnedPage, Page, PgId, PAGE_HEADER_SIZE};
+use crate::crypto::PageCrypt;
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::vfs::Vfs;
+
+/// PageCache reads the pages of a database and caches the latest ones.
+pub(crate) struct PageCache {
+    vfs: Arc<dyn Vfs>,
+    page_size: usize,
+    crypt: PageCrypt,
+    // Number of pages cached at most, overflow pages included.
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+#[derive(Default)]
+struct Lru {
+    // Cached pages with their length in pages and the tick of their last use.
+    entries: HashMap<PgId, (Bytes, usize, u64)>,
+    // Ids of the cached pages by the tick of their last use.
+    order: BTreeMap<u64, PgId>,
+    // Number of pages cached, overflow pages included.
+    len: usize,
+    tick: u64,
+}
+
+impl Lru {
+    fn get(&mut self, id: PgId) -> Option<Bytes> {
+        let (page, _, used) = self.entries.get_mut(&id)?;
+        self.tick += 1;
+        self.order.remove(used);
+        self.order.insert(self.tick, id);
+        *used = self.tick;
+        Some(page.clone())
+    }
+
+    fn insert(&mut self, id: PgId, page: Bytes, pages: usize, capacity: usize) {
+        if pages > capacity {
+            return;
+        }
+        self.remove(id);
+        while self.len + pages > capacity {
+            let (_, oldest) = self.order.pop_first().unwrap();
+            let (_, n, _) = self.entries.remove(&oldest).unwrap();
+            self.len -= n;
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, id);
+        self.entries.insert(id, (page, pages, self.tick));
+        self.len += pages;
+    }
+
+    fn remove(&mut self, id: PgId) {
+        if let Some((_, n, used)) = self.entries.remove(&id) {
+            self.order.remove(&used);
+            self.len -= n;
+        }
+    }
+}
+
+impl PageCache {
+    /// new returns an empty cache of capacity pages reading from vfs.
+    pub(crate) fn new(
+        vfs: Arc<dyn Vfs>,
+        page_size: usize,
+        crypt: PageCrypt,
+        capacity: usize,
+    ) -> PageCache {
+        PageCache {
+            vfs,
+            page_size,
+            crypt,
+            capacity,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// read returns the page with a given id along with its overflow pages,
+    /// decrypted if the database is encrypted, and whether it was cached.
+    /// high is the high water mark of the transaction reading the page.
+    /// Returns BoltError::Corrupted if the page does not end below it.
+    pub(crate) fn read(&self, id: PgId, high: PgId) -> Result<(Bytes, bool)> {
+        if let Some(page) = self.lru.lock().unwrap().get(id) {
+            return Ok((page, true));
+        }
+
+        let overflow = self.overflow(id)? as usize;
+        if id.saturating_add(overflow as PgId) >= high {
+            return Err(beyond_high_water(id));
+        }
+        let mut page = OwnedPage::new((overflow + 1) * self.page_size);
+        self.read_exact_at(page.buf_mut(), id * self.page_size as u64)
+            .map_err(|err| err.with_pgid(id))?;
+        let page = self.crypt.open(page.into_bytes())?;
+
+        let mut lru = self.lru.lock().unwrap();
+        lru.insert(id, page.clone(), overflow + 1, self.capacity);
+        Ok((page, false))
+    }
+
+    /// overflow returns the number of overflow pages of the page with a
+    /// given id, reading only its header unless it is cached.
+    pub(crate) fn overflow(&self, id: PgId) -> Result<u32> {
+        if let Some(page) = self.lru.lock().unwrap().get(id) {
+            return Ok(Page::from_slice(&page).overflow());
+        }
+        let mut header = OwnedPage::new(PAGE_HEADER_SIZE);
+        let pos = id
+            .checked_mul(self.page_size as u64)
+            .ok_or_else(|| beyond_high_water(id))?;
+        self.read_exact_at(header.buf_mut(), pos)
+            .map_err(|err| err.with_pgid(id))?;
+        Ok(Page::from_slice(header.buf()).overflow())
+    }
+
+    /// read_at returns the raw bytes of the file between two offsets, without
+    /// going through the cache.
+    pub(crate) fn read_at(&self, start: usize, end: usize) -> Result<Bytes> {
+        let mut buf = OwnedPage::new(end.saturating_sub(start));
+        self.read_exact_at(buf.buf_mut(), start as u64)?;
+        Ok(buf.into_bytes())
+    }
+
+    /// invalidate drops the entries of count pages from pgid on, which are
+    /// about to be written.
+    pub(crate) fn invalidate(&self, pgid: PgId, count: usize) {
+        let mut lru = self.lru.lock().unwrap();
+        for id in pgid..pgid + count as PgId {
+            lru.remove(id);
+        }
+    }
+
+    /// len returns the number of pages cached, overflow pages included.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.lru.lock().unwrap().len
+    }
+
+    // read_exact_at fills buf with the bytes of the file at offset.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> Result<()> {
+        while !buf.is_empty() {
+            match self.vfs.read_at(buf, offset).op(IoOp::Read)? {
+                0 => return Err(BoltError::corrupted("page lies beyond the end of the file")),
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// DecryptedPages caches the latest pages decrypted out of the mmap of an
+/// encrypted database.
+pub(crate) struct DecryptedPages {
+    // Number of pages cached at most, overflow pages included.
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+impl DecryptedPages {
+    /// new returns an empty cache of capacity pages.
+    pub(crate) fn new(capacity: usize) -> DecryptedPages {
+        DecryptedPages {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// get returns the decrypted page with a given id, if it is cached.
+    pub(crate) fn get(&self, id: PgId) -> Option<Bytes> {
+        self.lru.lock().unwrap().get(id)
+    }
+
+    /// insert caches the decrypted page with a given id, spanning pages
+    /// pages, evicting the least recently used ones to make room.
+    pub(crate) fn insert(&self, id: PgId, page: Bytes, pages: usize) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.insert(id, page, pages, self.capacity);
+    }
+
+    /// invalidate drops the entries of count pages from pgid on, which are
+    /// about to be written.
+    pub(crate) fn invalidate(&self, pgid: PgId, count: usize) {
+        let mut lru = self.lru.lock().unwrap();
+        for id in pgid..pgid + count as PgId {
+            lru.remove(id);
+        }
+    }
+
+    /// len returns the number of pages cached, overflow pages included.
+    #[cfg(all(test, feature = "encryption"))]
+    pub(crate) fn len(&self) -> usize {
+        self.lru.lock().unwrap().len
+    }
+}
+
+fn beyond_high_water(id: PgId) -> BoltError {
+    BoltError::corrupted("page lies beyond the high water mark").with_pgid(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+    use crate::diff::diff;
+
+    fn open(path: &std::path::Path) -> DB {
+        let options = Options {
+            page_cache_size: Some(1024),
+            ..Default::default()
+        };
+        DB::open(path, options).unwrap()
+    }
+
+    // Ensure that a database read without mmap can be written, read, checked
+    // and copied, and that readers never see pages of later transactions.
+    #[test]
+    fn test_page_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(&dir.path().join("db"));
+        let put = |value: u8| {
+            db.update(|tx| {
+                let b = tx.create_bucket_if_not_exists(b"widgets")?;
+                for i in 0..1000u32 {
+                    b.put(format!("{:04}", i).as_bytes(), &[value; 100])?;
+                }
+                b.put(b"large", &[value; 10000])
+            })
+            .unwrap();
+        };
+        put(1);
+
+        let reader = db.begin(false).unwrap();
+        for value in 2..5 {
+            put(value);
+        }
+        let b = reader.bucket(b"widgets").unwrap();
+        assert_eq!(b.get(b"0999"), Some(&[1; 100][..]));
+        assert_eq!(b.get(b"large"), Some(&[1; 10000][..]));
+        reader.rollback().unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            let mut n = 0;
+            b.for_each(|_, v| {
+                assert!(v.unwrap().iter().all(|&byte| byte == 4));
+                n += 1;
+                Ok(())
+            })?;
+            assert_eq!(n, 1001);
+            let stats = tx.stats();
+            assert!(stats.page_cache_miss > 0);
+            assert!(stats.page_cache_hit > 0);
+            Ok(())
+        })
+        .unwrap();
+        assert!(db.0.page_cache().unwrap().len() > 0);
+
+        let copy = dir.path().join("copy");
+        db.view(|tx| tx.copy_file(&copy)).unwrap();
+        assert_eq!(diff(&db, &open(&copy)).unwrap(), vec![]);
+    }
+
+    // Ensure that the least recently used pages are evicted first, counting
+    // overflow pages against the capacity.
+    #[test]
+    fn test_page_cache_eviction() {
+        let mut lru = Lru::default();
+        lru.insert(1, Bytes::new(), 1, 4);
+        lru.insert(2, Bytes::new(), 2, 4);
+        lru.insert(3, Bytes::new(), 1, 4);
+        assert!(lru.get(1).is_some());
+        lru.insert(4, Bytes::new(), 1, 4);
+        assert_eq!(lru.len, 3);
+        assert!(lru.get(2).is_none());
+        lru.insert(5, Bytes::new(), 5, 4);
+        assert!(lru.get(5).is_none());
+        let mut ids: Vec<_> = lru.entries.keys().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 3, 4]);
+    }
+}
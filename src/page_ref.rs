@@ -0,0 +1,263 @@
+//! Read-only access to the pages of the B-tree, for tools.
+//!
+//! Tx::for_each_page walks the branch and leaf pages of every bucket and hands
+//! out a PageRef for each. The element table and every key and value of a page
+//! are checked to lie within the page before it is handed out, so inspecting
+//! a corrupted file returns BoltError::Corrupted instead of reading out of
+//! bounds.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::common::bucket::InBucket;
+use crate::common::page::{
+    Page, PgId, BRANCH_PAGE_ELEMENT_SIZE, LEAF_PAGE_ELEMENT_SIZE, PAGE_HEADER_SIZE,
+};
+use crate::errors::{BoltError, Result};
+use crate::tx::Tx;
+
+/// PageRef is a branch or leaf page, borrowed from the transaction that read it.
+#[derive(Clone, Copy)]
+pub struct PageRef<'a> {
+    page: &'a Page,
+    depth: usize,
+}
+
+impl<'a> PageRef<'a> {
+    /// new checks the elements of a page spanning size bytes.
+    pub(crate) fn new(page: &'a Page, size: usize, depth: usize) -> Result<PageRef<'a>> {
+        if !page.is_branch_page() && !page.is_leaf_page() {
+            let reason = format!("unexpected {} page in bucket", page.typ());
+            return Err(BoltError::corrupted(reason).with_pgid(page.id()));
+        }
+        page.try_validate(size)
+            .map_err(|_| BoltError::corrupted("page element out of bounds").with_pgid(page.id()))?;
+        Ok(PageRef { page, depth })
+    }
+
+    /// id returns the id of the page.
+    pub fn id(&self) -> u64 {
+        self.page.id()
+    }
+
+    /// is_branch returns whether the page is a branch page, whose elements
+    /// point to child pages.
+    pub fn is_branch(&self) -> bool {
+        self.page.is_branch_page()
+    }
+
+    /// is_leaf returns whether the page is a leaf page, whose elements are
+    /// keys with their values or nested buckets.
+    pub fn is_leaf(&self) -> bool {
+        self.page.is_leaf_page()
+    }
+
+    /// depth returns the depth of the page within its bucket, zero for the
+    /// root page.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// count returns the number of elements of the page.
+    pub fn count(&self) -> usize {
+        self.page.count() as usize
+    }
+
+    /// overflow returns the number of pages following the page that it spans.
+    pub fn overflow(&self) -> u32 {
+        self.page.overflow()
+    }
+
+    /// used returns the number of bytes the page uses, header included.
+    pub fn used(&self) -> usize {
+        let count = self.count();
+        if count == 0 {
+            return PAGE_HEADER_SIZE;
+        }
+        if self.is_branch() {
+            let last = self.page.branch_page_element(count - 1);
+            PAGE_HEADER_SIZE
+                + BRANCH_PAGE_ELEMENT_SIZE * (count - 1)
+                + (last.pos() + last.ksize()) as usize
+        } else {
+            let last = self.page.leaf_page_element(count - 1);
+            PAGE_HEADER_SIZE
+                + LEAF_PAGE_ELEMENT_SIZE * (count - 1)
+                + (last.pos() + last.ksize() + last.vsize()) as usize
+        }
+    }
+
+    /// key returns the key of an element, or None if there is no such element.
+    /// The keys of a branch page are the first keys of its children, or in
+    /// format version 3 separators at most as long, see
+    /// Options.branch_prefix_compression.
+    pub fn key(&self, index: usize) -> Option<Cow<'a, [u8]>> {
+        if index >= self.count() {
+            return None;
+        }
+        match self.is_branch() {
+            true => Some(self.page.branch_key(index)),
+            false => Some(Cow::Borrowed(self.page.leaf_page_element(index).key())),
+        }
+    }
+
+    /// key_preview returns the key of an element for display, with bytes
+    /// that are not printable ASCII escaped and cut after max_len bytes.
+    pub fn key_preview(&self, index: usize, max_len: usize) -> Option<String> {
+        let key = self.key(index)?;
+        let shown = &key[..key.len().min(max_len)];
+        let more = if key.len() > max_len { "..." } else { "" };
+        Some(format!("{}{}", shown.escape_ascii(), more))
+    }
+
+    /// value returns the value of an element of a leaf page, which is the
+    /// bucket header for a nested bucket. Returns None on branch pages.
+    pub fn value(&self, index: usize) -> Option<&'a [u8]> {
+        match self.is_leaf() {
+            true => self.page.leaf_page_elements().get(index).map(|e| e.value()),
+            false => None,
+        }
+    }
+
+    /// is_bucket returns whether an element of a leaf page is a nested bucket.
+    pub fn is_bucket(&self, index: usize) -> bool {
+        self.is_leaf()
+            && self
+                .page
+                .leaf_page_elements()
+                .get(index)
+                .is_some_and(|e| e.is_bucket_entry())
+    }
+
+    /// child returns the page an element of a branch page points to.
+    /// Returns None on leaf pages.
+    pub fn child(&self, index: usize) -> Option<u64> {
+        match self.is_branch() {
+            true => self
+                .page
+                .branch_page_elements()
+                .get(index)
+                .map(|e| e.pgid()),
+            false => None,
+        }
+    }
+}
+
+impl fmt::Debug for PageRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageRef")
+            .field("id", &self.id())
+            .field("type", &self.page.typ())
+            .field("depth", &self.depth)
+            .field("count", &self.count())
+            .field("overflow", &self.overflow())
+            .finish()
+    }
+}
+
+impl Tx {
+    /// for_each_page calls f with every branch and leaf page of the buckets,
+    /// nested buckets included, parents before their children. The pages of
+    /// inline buckets are part of their parent's leaf and are not visited.
+    /// If f returns an error then the walk is stopped and the error is
+    /// returned to the caller.
+    pub fn for_each_page<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(u64, PageRef<'_>) -> Result<()>,
+    {
+        let page_size = self.db()?.0.page_size();
+        let root = self.meta().root_bucket().root_page();
+        let mut seen = HashSet::new();
+        self.walk_pages(root, 0, page_size, &mut seen, &mut f)
+    }
+
+    fn walk_pages<F>(
+        &self,
+        pgid: PgId,
+        depth: usize,
+        page_size: usize,
+        seen: &mut HashSet<PgId>,
+        f: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(u64, PageRef<'_>) -> Result<()>,
+    {
+        if !seen.insert(pgid) {
+            return Err(BoltError::corrupted("page reachable twice").with_pgid(pgid));
+        }
+        let page = self.page(pgid)?;
+        let size = page_size * (page.overflow() as usize + 1);
+        let page = PageRef::new(&page, size, depth)?;
+        f(pgid, page)?;
+
+        for index in 0..page.count() {
+            if let Some(child) = page.child(index) {
+                self.walk_pages(child, depth + 1, page_size, seen, f)?;
+            } else if page.is_bucket(index) {
+                let value = page.value(index).unwrap_or_default();
+                let bucket = InBucket::try_from_bytes(value).ok_or_else(|| {
+                    BoltError::corrupted("bucket header is truncated").with_pgid(pgid)
+                })?;
+                if bucket.root_page() != 0 {
+                    self.walk_pages(bucket.root_page(), 0, page_size, seen, f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // Ensure that every page of every bucket is visited, with its keys.
+    #[test]
+    fn test_tx_for_each_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.put(b"\x00bin", b"v")?;
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let stats = tx.bucket(b"widgets").unwrap().stats()?;
+            let (mut leaves, mut branches, mut keys) = (0, 0, 0);
+            tx.for_each_page(|pgid, page| {
+                assert_eq!(page.id(), pgid);
+                assert!(page.used() <= db.page_size() * (page.overflow() as usize + 1));
+                if page.is_leaf() {
+                    leaves += 1;
+                    keys += page.count();
+                } else {
+                    branches += 1;
+                    assert_eq!(page.depth(), 0);
+                    assert_eq!(page.key_preview(0, 3).unwrap(), "\\x00bi...");
+                    assert!(page.child(0).is_some() && page.value(0).is_none());
+                }
+                Ok(())
+            })?;
+            // The root leaf is visited as well, the inline bucket is not.
+            assert_eq!(leaves, stats.leaf_page_n + 1);
+            assert_eq!(branches, stats.branch_page_n);
+            assert_eq!(keys, stats.key_n);
+
+            let mut visited = 0;
+            let err = tx.for_each_page(|_, _| {
+                visited += 1;
+                Err(BoltError::Invalid)
+            });
+            assert_eq!((err, visited), (Err(BoltError::Invalid), 1));
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! Limiting the rate of writes.
+//!
+//! With Options.write_rate_limit set, commits pace the writes of their dirty
+//! pages to that many bytes per second on average, so that a database sharing
+//! a disk with other services does not starve them of IO, at the cost of
+//! longer commits. Compaction and defragmentation write through commits, so
+//! they are paced as well. Meta pages are small and written at once.
+//!
+//! The limiter is a token bucket: the bytes not written while the database
+//! is idle can be written without waiting, up to MAX_BURST worth of them.
+//! TxStats.throttle_time tells how long a transaction waited for it.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::os::Stopwatch;
+
+// Longest time of writes that can be saved up while idle.
+const MAX_BURST: Duration = Duration::from_millis(100);
+
+/// WriteLimiter paces the writes of a database to a number of bytes per
+/// second.
+pub(crate) struct WriteLimiter {
+    bytes_per_sec: u64,
+    clock: Stopwatch,
+    // Time on the clock at which the bytes written so far are within the rate.
+    due: Mutex<Duration>,
+}
+
+impl WriteLimiter {
+    /// new returns a limiter allowing bytes_per_sec bytes per second, which
+    /// must not be zero.
+    pub(crate) fn new(bytes_per_sec: u64) -> WriteLimiter {
+        debug_assert!(bytes_per_sec > 0);
+        WriteLimiter {
+            bytes_per_sec,
+            clock: Stopwatch::start(),
+            due: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// bytes_per_sec returns the rate writes are paced to.
+    pub(crate) fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// wait sleeps until n more bytes can be written within the rate, and
+    /// returns how long it slept.
+    pub(crate) fn wait(&self, n: usize) -> Duration {
+        let now = self.clock.elapsed();
+        let mut due = self.due.lock().unwrap();
+        let start = (*due).max(now.saturating_sub(MAX_BURST));
+        *due = start + Duration::from_secs_f64(n as f64 / self.bytes_per_sec as f64);
+        let ahead = due.saturating_sub(now);
+        drop(due);
+
+        if !ahead.is_zero() {
+            thread::sleep(ahead);
+        }
+        ahead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // Ensure that writes beyond the burst are paced to the rate.
+    #[test]
+    fn test_write_limiter() {
+        let limiter = WriteLimiter::new(100_000);
+        // A tenth of a second worth of bytes goes through at once after
+        // being idle.
+        thread::sleep(MAX_BURST);
+        assert!(limiter.wait(10_000) < Duration::from_millis(10));
+
+        let start = Stopwatch::start();
+        let waited = limiter.wait(5_000) + limiter.wait(5_000);
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert!(waited >= Duration::from_millis(90));
+    }
+
+    // Ensure that commits are paced, and that the wait shows in their stats.
+    #[test]
+    fn test_write_rate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_rate_limit: Some(1 << 20),
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        let start = Stopwatch::start();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(&i.to_be_bytes(), &[0x5a; 500])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        // About 550KB were written, less the burst.
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(db.stats().tx_stats.throttle_time >= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,242 @@
+//! Renaming and copying buckets.
+//!
+//! A nested bucket is stored as a header under its name in the parent, so
+//! Bucket::rename_bucket moves that header to the new name without touching
+//! the pages of the bucket. Open handles on the bucket, and on the buckets
+//! nested in it, follow the rename. Bucket::copy_to instead copies every key
+//! and nested bucket, for instance into a bucket of another database.
+
+use std::sync::Arc;
+
+use crate::bucket::{is_hidden, Bucket};
+use crate::common::inode::Key;
+use crate::common::page::BUCKET_LEAF_FLAG;
+use crate::errors::{BoltError, Result};
+
+impl<'tx> Bucket<'tx> {
+    /// rename_bucket renames the nested bucket old to new. The bucket keeps
+    /// its pages, only its header moves, along with the TTLs of its keys.
+    /// Returns BoltError::BucketNotFound if old does not exist,
+    /// BoltError::BucketExists if new does, and BoltError::IncompatibleValue
+    /// if either is a key.
+    pub fn rename_bucket(&self, old: &[u8], new: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        if new.is_empty() {
+            return Err(BoltError::BucketNameRequired);
+        } else if new.len() > self.tx()?.db()?.0.max_key_size() {
+            return Err(BoltError::KeyTooLarge);
+        } else if self.0.path.borrow().is_empty() && (is_hidden(old) || is_hidden(new)) {
+            return Err(BoltError::IncompatibleValue);
+        }
+
+        let mut c = self.cursor();
+        let (k, header, flags) = c.seek_item(old)?.ok_or(BoltError::BucketNotFound)?;
+        if old != &k[..] {
+            return Err(BoltError::BucketNotFound);
+        } else if (flags & BUCKET_LEAF_FLAG) == 0 {
+            return Err(BoltError::IncompatibleValue);
+        }
+        if let Some((k, _, flags)) = self.cursor().seek_item(new)? {
+            if new == &k[..] {
+                if (flags & BUCKET_LEAF_FLAG) != 0 {
+                    return Err(BoltError::BucketExists);
+                }
+                return Err(BoltError::IncompatibleValue);
+            }
+        }
+
+        // Observers see the keys move, as if the bucket was deleted and
+        // created again.
+        self.bucket(old).ok_or(BoltError::BucketNotFound)?;
+        let child = self.0.buckets.borrow_mut().remove(old);
+        let child = child.ok_or(BoltError::Unexpected("renamed bucket not cached"))?;
+        let capturing = child.is_capturing();
+        if capturing {
+            record_keys(&child, true)?;
+        }
+
+        // Move the header. A cached handle spills over the new header on
+        // commit, with the changes made through it.
+        c.node()?.del(old);
+        let mut c = self.cursor();
+        c.seek_item(new)?;
+        let new = Key::copy_from_slice(new);
+        c.node()?
+            .put(&new, new.clone(), header, 0, BUCKET_LEAF_FLAG);
+        self.0
+            .buckets
+            .borrow_mut()
+            .insert(new.clone(), child.clone());
+        rename_path(&child, self.0.path.borrow().len(), &new);
+
+        self.move_ttls(old, &new)?;
+        if capturing {
+            record_keys(&child, false)?;
+        }
+        Ok(())
+    }
+
+    /// copy_to copies the keys and nested buckets of the bucket into dest,
+    /// along with the sequence and the TTLs of keys. Keys dest already has
+    /// are overwritten, and nested buckets it already has are merged. dest
+    /// may belong to another transaction, or another database; to copy a
+    /// bucket to a new name, create it first.
+    /// Returns BoltError::SameBuckets if dest is the bucket or is nested in it.
+    pub fn copy_to(&self, dest: &Bucket<'_>) -> Result<()> {
+        dest.check_writable()?;
+        let (tx, dest_tx) = (self.tx()?, dest.tx()?);
+        if Arc::ptr_eq(&tx.0, &dest_tx.0) && dest.0.path.borrow().starts_with(&self.0.path.borrow())
+        {
+            return Err(BoltError::SameBuckets);
+        }
+        copy_bucket(self, dest)
+    }
+}
+
+// copy_bucket copies the keys and nested buckets of src into dest.
+fn copy_bucket(src: &Bucket, dest: &Bucket) -> Result<()> {
+    dest.set_sequence(src.sequence())?;
+    src.for_each(|k, v| match v {
+        Some(v) => match src.ttl(k) {
+            Some(ttl) => dest.put_with_ttl(k, v, ttl),
+            None => dest.put(k, v),
+        },
+        None => {
+            let child = src.bucket(k).ok_or(BoltError::BucketNotFound)?;
+            copy_bucket(&child, &dest.create_bucket_if_not_exists(k)?)
+        }
+    })
+}
+
+// rename_path sets the name at depth in the path of a bucket, and of the
+// cached buckets nested in it.
+fn rename_path(bucket: &Bucket, depth: usize, name: &Key) {
+    bucket.0.path.borrow_mut()[depth] = name.clone();
+    for child in bucket.0.buckets.borrow().values() {
+        rename_path(child, depth, name);
+    }
+}
+
+// record_keys records the keys of a bucket and of its nested buckets as
+// deleted or put, for observers.
+fn record_keys(bucket: &Bucket, deleted: bool) -> Result<()> {
+    bucket.for_each(|k, v| match v {
+        Some(v) if deleted => {
+            bucket.record_change(k, Some(v.to_vec()), None);
+            Ok(())
+        }
+        Some(v) => {
+            bucket.record_change(k, None, Some(v.to_vec()));
+            Ok(())
+        }
+        None => record_keys(&bucket.bucket(k).ok_or(BoltError::BucketNotFound)?, deleted),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::db::{Options, DB};
+
+    // Ensure that a renamed bucket keeps its keys, nested buckets and TTLs,
+    // including the changes made through handles opened before the rename.
+    #[test]
+    fn test_rename_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:04}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            b.put_with_ttl(b"ttl", b"v", Duration::from_secs(60))?;
+            tx.create_bucket(b"gadgets")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let sub = b.bucket(b"sub").unwrap();
+            assert_eq!(
+                tx.rename_bucket(b"missing", b"x"),
+                Err(BoltError::BucketNotFound)
+            );
+            assert_eq!(
+                tx.rename_bucket(b"widgets", b"gadgets"),
+                Err(BoltError::BucketExists)
+            );
+            assert_eq!(
+                b.rename_bucket(b"ttl", b"x"),
+                Err(BoltError::IncompatibleValue)
+            );
+
+            tx.rename_bucket(b"widgets", b"things")?;
+            sub.put(b"baz", b"qux")?;
+            b.put_with_ttl(b"later", b"v", Duration::from_secs(60))?;
+            assert!(tx.bucket(b"widgets").is_none());
+            assert!(tx.bucket(b"things").unwrap().ttl(b"ttl").is_some());
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            assert!(tx.bucket(b"widgets").is_none());
+            let b = tx.bucket(b"things").unwrap();
+            assert_eq!(b.get(b"0999"), Some(&[0x5a; 100][..]));
+            assert!(b.ttl(b"ttl").is_some());
+            assert!(b.ttl(b"later").is_some());
+            let sub = b.bucket(b"sub").unwrap();
+            assert_eq!(sub.get(b"foo"), Some(&b"bar"[..]));
+            assert_eq!(sub.get(b"baz"), Some(&b"qux"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a bucket is copied deeply into a bucket of another database.
+    #[test]
+    fn test_bucket_copy_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        let other = DB::open(dir.path().join("other"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.set_sequence(42)?;
+            b.put(b"foo", b"bar")?;
+            b.create_bucket(b"sub")?.put(b"baz", b"qux")?;
+            assert_eq!(
+                b.copy_to(&b.bucket(b"sub").unwrap()),
+                Err(BoltError::SameBuckets)
+            );
+
+            let dest = tx.create_bucket(b"copy")?;
+            b.copy_to(&dest)?;
+            assert_eq!(dest.bucket(b"sub").unwrap().get(b"baz"), Some(&b"qux"[..]));
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|src| {
+            other.update(|tx| {
+                src.bucket(b"widgets")
+                    .unwrap()
+                    .copy_to(&tx.create_bucket(b"widgets")?)
+            })
+        })
+        .unwrap();
+        other
+            .view(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.sequence(), 42);
+                assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+                assert_eq!(b.bucket(b"sub").unwrap().get(b"baz"), Some(&b"qux"[..]));
+                Ok(())
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,515 @@
+//! Recovery of the keys of a database whose tree is broken.
+//!
+//! salvage does not follow the tree from the meta page down, as a damaged
+//! branch page would cut off everything below it. It scans the file page by
+//! page instead and keeps every branch and leaf page whose elements lie within
+//! the page. The bucket of a leaf page is then found by walking up the links
+//! pointing to it: branch elements point to their children and bucket values
+//! to the root page of their bucket.
+//!
+//! Leaf pages no link leads to, because the page pointing to them is lost, are
+//! recovered into ORPHAN_BUCKET, in a nested bucket named after the id of the
+//! topmost page that is left, as an 8 byte big-endian integer.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::bucket::Bucket;
+use crate::common::meta::{Meta, META_PAGE_SIZE};
+use crate::common::page::{OwnedPage, Page, PgId, PAGE_HEADER_SIZE, PGID_SIZE};
+use crate::common::types::PGID_NO_FREELIST;
+use crate::db::{get_page_size, Options, DB};
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::tx::Tx;
+
+/// ORPHAN_BUCKET is the top-level bucket salvage puts the keys in whose
+/// bucket could not be determined.
+pub const ORPHAN_BUCKET: &[u8] = b"salvage.orphans";
+
+// Pages sizes tried when neither meta page tells the page size.
+const PAGE_SIZES: [usize; 8] = [512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+
+/// SalvageReport describes what salvage recovered.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// Number of pages of the source file.
+    pub pages: u64,
+    /// Number of buckets written to the destination, including the nested
+    /// buckets of ORPHAN_BUCKET.
+    pub buckets: u64,
+    /// Number of keys written to the destination.
+    pub keys: u64,
+    /// Number of keys written to ORPHAN_BUCKET.
+    pub orphaned_keys: u64,
+    /// Pages and elements that could not be recovered, by page id, with the
+    /// reason.
+    pub lost: Vec<(u64, String)>,
+}
+
+/// salvage copies every key of the database file at src that can still be
+/// read into a new database at dst, even if src cannot be opened or its tree
+/// is broken. Pages on the freelist are skipped. Keys whose bucket cannot be
+/// determined are put into ORPHAN_BUCKET.
+///
+/// Values are copied as they are stored, so compressed buckets stay
+/// compressed. Encrypted databases cannot be salvaged. src must not be in use
+/// and dst must not exist yet.
+pub fn salvage<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<SalvageReport> {
+    let dst = dst.as_ref();
+    if dst.exists() {
+        let err = io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("file already exists: {}", dst.display()),
+        );
+        return Err(BoltError::io(IoOp::Open, err));
+    }
+
+    let file = File::open(src.as_ref()).op(IoOp::Open)?;
+    let data = OwnedPage::from_vec(std::fs::read(src.as_ref()).op(IoOp::Read)?);
+    let page_size = match get_page_size(&file, data.size()) {
+        Ok(page_size) => page_size,
+        Err(_) => guess_page_size(data.buf()),
+    };
+    let mut scan = Scan::new(data.buf(), page_size);
+    scan.scan();
+
+    let db = DB::open(
+        dst,
+        Options {
+            page_size,
+            ..Default::default()
+        },
+    )?;
+    let result = scan.recover(&db);
+    db.close()?;
+    result?;
+    Ok(scan.report)
+}
+
+// guess_page_size returns the page size at which the most pages carry their
+// own id in their header.
+fn guess_page_size(data: &[u8]) -> usize {
+    let matches = |page_size: usize| {
+        (2..data.len() / page_size)
+            .filter(|&id| {
+                let page = Page::from_slice(&data[id * page_size..]);
+                page.id() == id as PgId && (page.is_branch_page() || page.is_leaf_page())
+            })
+            .count()
+    };
+    PAGE_SIZES
+        .iter()
+        .copied()
+        .max_by_key(|&page_size| matches(page_size))
+        .unwrap_or(PAGE_SIZES[3])
+}
+
+// Link is what points to a page.
+#[derive(Clone, Debug)]
+enum Link {
+    // An element of a branch page.
+    Branch(PgId),
+    // The value of a bucket in a leaf page, with the name of the bucket.
+    Bucket(PgId, Vec<u8>),
+}
+
+// Scan holds the branch and leaf pages found in a file.
+struct Scan<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    // Root page of the root bucket, if a meta page is valid.
+    root: Option<PgId>,
+    // Ids of the pages on the freelist.
+    freed: HashSet<PgId>,
+    // Branch and leaf pages whose elements lie within the page, by id, with
+    // the number of bytes backing them.
+    pages: BTreeMap<PgId, usize>,
+    // The first link found to each page.
+    links: HashMap<PgId, Link>,
+    report: SalvageReport,
+}
+
+impl<'a> Scan<'a> {
+    fn new(data: &'a [u8], page_size: usize) -> Self {
+        Scan {
+            data,
+            page_size,
+            root: None,
+            freed: HashSet::new(),
+            pages: BTreeMap::new(),
+            links: HashMap::new(),
+            report: SalvageReport {
+                pages: (data.len() / page_size) as u64,
+                ..Default::default()
+            },
+        }
+    }
+
+    // page returns the page with the given id, if its header lies within the file.
+    fn page(&self, id: PgId) -> Option<&'a Page> {
+        let pos = (id as usize).checked_mul(self.page_size)?;
+        let buf = self.data.get(pos..)?;
+        Page::try_from_slice(buf).ok()
+    }
+
+    // len returns the number of bytes of the file backing the page.
+    fn len(&self, id: PgId, page: &Page) -> usize {
+        let len = (page.overflow() as usize + 1) * self.page_size;
+        len.min(self.data.len() - id as usize * self.page_size)
+    }
+
+    // scan reads the meta pages and the freelist, then every other page.
+    fn scan(&mut self) {
+        let meta = (0..2)
+            .filter_map(|id| {
+                let page = self.page(id)?;
+                if self.len(id, page) < PAGE_HEADER_SIZE + META_PAGE_SIZE {
+                    return None;
+                }
+                let meta = page.meta();
+                match meta.validate() {
+                    Ok(()) => Some(meta),
+                    Err(err) => {
+                        let reason = format!("invalid meta page: {}", err);
+                        self.report.lost.push((id, reason));
+                        None
+                    }
+                }
+            })
+            .max_by_key(Meta::txid);
+        if let Some(meta) = meta {
+            self.root = Some(meta.root_bucket().root_page());
+            if meta.freelist() != PGID_NO_FREELIST {
+                self.read_freelist(meta.freelist());
+            }
+        }
+
+        let mut id = 2;
+        while let Some(page) = self.page(id) {
+            let next = id + 1;
+            if page.fast_check(id).is_err() || self.freed.contains(&id) {
+                id = next;
+                continue;
+            }
+            let len = self.len(id, page);
+            if page.is_branch_page() || page.is_leaf_page() {
+                match page.try_validate(len) {
+                    Ok(()) => self.add(id, page, len),
+                    Err(_) => {
+                        let reason = "elements lie outside the page".to_string();
+                        self.report.lost.push((id, reason));
+                        id = next;
+                        continue;
+                    }
+                }
+            }
+            id += page.overflow() as PgId + 1;
+        }
+
+        // Report the links to pages that were not found.
+        let mut missing: Vec<(PgId, PgId)> = self
+            .links
+            .iter()
+            .filter(|(id, _)| !self.pages.contains_key(id))
+            .map(|(&id, link)| match link {
+                Link::Branch(from) | Link::Bucket(from, _) => (id, *from),
+            })
+            .collect();
+        missing.sort_unstable();
+        for (id, from) in missing {
+            let reason = format!("referenced by page {} but not a valid branch or leaf", from);
+            self.report.lost.push((id, reason));
+        }
+    }
+
+    // read_freelist reads the ids of the free pages, if the freelist page is intact.
+    fn read_freelist(&mut self, id: PgId) {
+        let page = match self.page(id) {
+            Some(page) if page.id() == id && page.is_freelist_page() => page,
+            _ => {
+                let reason = "invalid freelist page, free pages are scanned too".to_string();
+                self.report.lost.push((id, reason));
+                return;
+            }
+        };
+        let len = self.len(id, page);
+        if len < PAGE_HEADER_SIZE + PGID_SIZE {
+            return;
+        }
+        let (start, count) = page.freelist_page_count();
+        if PAGE_HEADER_SIZE + (start + count) * PGID_SIZE > len {
+            let reason = "freelist is truncated, free pages are scanned too".to_string();
+            self.report.lost.push((id, reason));
+            return;
+        }
+        self.freed.extend(page.freelist_page_ids());
+    }
+
+    // add records a valid branch or leaf page and the links it holds.
+    fn add(&mut self, id: PgId, page: &Page, len: usize) {
+        self.pages.insert(id, len);
+        if page.is_branch_page() {
+            for elem in page.branch_page_elements() {
+                self.links.entry(elem.pgid()).or_insert(Link::Branch(id));
+            }
+            return;
+        }
+        for elem in page.leaf_page_elements() {
+            if let Some(bucket) = elem.bucket().filter(|bucket| bucket.root_page() != 0) {
+                let link = Link::Bucket(id, elem.key().to_vec());
+                self.links.entry(bucket.root_page()).or_insert(link);
+            }
+        }
+    }
+
+    // path returns the names of the buckets leading to the bucket a page
+    // belongs to, or the id of the topmost page left and the names leading
+    // from it if no link leads up to the root bucket.
+    fn path(&self, mut id: PgId) -> (Option<PgId>, Vec<Vec<u8>>) {
+        let mut names = Vec::new();
+        let mut seen = HashSet::new();
+        loop {
+            if Some(id) == self.root {
+                names.reverse();
+                return (None, names);
+            }
+            match self.links.get(&id) {
+                Some(Link::Branch(from)) if seen.insert(id) => id = *from,
+                Some(Link::Bucket(from, name)) if seen.insert(id) => {
+                    names.push(name.clone());
+                    id = *from;
+                }
+                _ => {
+                    names.reverse();
+                    return (Some(id), names);
+                }
+            }
+        }
+    }
+
+    // recover writes the elements of every leaf page to db, a page per transaction.
+    fn recover(&mut self, db: &DB) -> Result<()> {
+        let mut buckets = HashSet::new();
+        let leaves: Vec<(PgId, usize)> = self
+            .pages
+            .iter()
+            .map(|(&id, &len)| (id, len))
+            .filter(|&(id, _)| self.page(id).is_some_and(|page| page.is_leaf_page()))
+            .collect();
+        for (id, len) in leaves {
+            let (orphan, mut names) = self.path(id);
+            if let Some(top) = orphan {
+                names.splice(0..0, [ORPHAN_BUCKET.to_vec(), top.to_be_bytes().to_vec()]);
+            }
+            let page = Page::from_slice(&self.data[id as usize * self.page_size..][..len]);
+            db.update(|tx| {
+                let mut recovery = Recovery {
+                    tx,
+                    id,
+                    orphan: orphan.is_some(),
+                    buckets: &mut buckets,
+                    report: &mut self.report,
+                };
+                recovery.leaf(&names, page)
+            })?;
+        }
+        self.report.buckets = buckets.len() as u64;
+        Ok(())
+    }
+}
+
+// Recovery writes the elements of a leaf page in a transaction.
+struct Recovery<'a, 'b> {
+    tx: &'a Tx,
+    // Id of the page being recovered.
+    id: PgId,
+    // Whether the page is recovered into ORPHAN_BUCKET.
+    orphan: bool,
+    buckets: &'b mut HashSet<Vec<Vec<u8>>>,
+    report: &'b mut SalvageReport,
+}
+
+impl<'a> Recovery<'a, '_> {
+    // bucket returns the bucket at path, creating it and its parents if needed.
+    fn bucket(&mut self, path: &[Vec<u8>]) -> Result<Bucket<'a>> {
+        let mut b = self.tx.root();
+        for (i, name) in path.iter().enumerate() {
+            b = b.create_bucket_if_not_exists(name)?;
+            self.buckets.insert(path[..=i].to_vec());
+        }
+        Ok(b)
+    }
+
+    // leaf writes the elements of a leaf page to the bucket at path.
+    fn leaf(&mut self, path: &[Vec<u8>], page: &Page) -> Result<()> {
+        for elem in page.leaf_page_elements() {
+            let (key, value) = (elem.key(), elem.value());
+            let mut child = path.to_vec();
+            child.push(key.to_vec());
+
+            if elem.is_bucket_entry() {
+                let b = match self.bucket(&child) {
+                    Ok(b) => b,
+                    Err(err) => {
+                        self.lose(key, err);
+                        continue;
+                    }
+                };
+                // Nested buckets with pages of their own are recovered with
+                // those pages; only inline buckets are stored in the value.
+                if let Some(bucket) = elem.bucket().filter(|bucket| bucket.root_page() == 0) {
+                    self.inline(&child, &b, &value[std::mem::size_of_val(&bucket)..]);
+                }
+                continue;
+            }
+
+            // The root bucket only holds buckets.
+            let result = match path.is_empty() {
+                true => {
+                    let orphans = [ORPHAN_BUCKET.to_vec(), self.id.to_be_bytes().to_vec()];
+                    self.bucket(&orphans).and_then(|b| b.put(key, value))
+                }
+                false => self.bucket(path).and_then(|b| b.put(key, value)),
+            };
+            match result {
+                Ok(()) => {
+                    self.report.keys += 1;
+                    if self.orphan || path.is_empty() {
+                        self.report.orphaned_keys += 1;
+                    }
+                }
+                Err(err) => self.lose(key, err),
+            }
+        }
+        Ok(())
+    }
+
+    // inline writes the keys of an inline bucket stored in buf to b.
+    fn inline(&mut self, path: &[Vec<u8>], b: &Bucket, buf: &[u8]) {
+        let page = OwnedPage::from_slice(buf);
+        let elems = Page::try_from_slice(page.buf()).and_then(|p| p.try_leaf_elements(buf.len()));
+        let elems = match elems {
+            Ok(elems) => elems,
+            Err(_) => {
+                let reason = "inline bucket is corrupt".to_string();
+                return self.lose(path.last().unwrap(), reason);
+            }
+        };
+        for elem in elems.iter().filter(|elem| !elem.is_bucket_entry()) {
+            match b.put(elem.key(), elem.value()) {
+                Ok(()) => {
+                    self.report.keys += 1;
+                    if self.orphan {
+                        self.report.orphaned_keys += 1;
+                    }
+                }
+                Err(err) => self.lose(elem.key(), err),
+            }
+        }
+    }
+
+    // lose reports a key that could not be recovered.
+    fn lose(&mut self, key: &[u8], reason: impl std::fmt::Display) {
+        let reason = format!("key \"{}\": {}", key.escape_ascii(), reason);
+        self.report.lost.push((self.id, reason));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surgeon;
+
+    // count returns the number of keys in a bucket and its nested buckets.
+    fn count(b: &Bucket) -> Result<usize> {
+        let mut n = 0;
+        b.for_each(|k, v| {
+            n += match v {
+                Some(_) => 1,
+                None => count(&b.bucket(k).unwrap())?,
+            };
+            Ok(())
+        })?;
+        Ok(n)
+    }
+
+    fn fill(db: &DB) {
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(&i.to_be_bytes(), &[1; 100])?;
+            }
+            let sub = b.create_bucket(b"sub")?;
+            sub.put(b"foo", b"bar")?;
+            tx.create_bucket(b"empty")?;
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_salvage() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let db = DB::open(&src, Options::default()).unwrap();
+        fill(&db);
+        db.close().unwrap();
+
+        let dst = dir.path().join("dst");
+        let report = salvage(&src, &dst).unwrap();
+        assert_eq!(report.keys, 1001);
+        assert_eq!(report.orphaned_keys, 0);
+        assert_eq!(report.buckets, 3);
+        assert!(report.lost.is_empty(), "{:?}", report.lost);
+
+        let db = DB::open(&dst, Options::default()).unwrap();
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(count(&b)?, 1001);
+            assert_eq!(b.bucket(b"sub").unwrap().get(b"foo"), Some(&b"bar"[..]));
+            assert!(tx.bucket(b"empty").is_some());
+            assert!(tx.bucket(ORPHAN_BUCKET).is_none());
+            Ok(())
+        })
+        .unwrap();
+        assert!(salvage(&src, &dst).is_err());
+    }
+
+    // Ensure that the leaves below a lost branch page are recovered as orphans.
+    #[test]
+    fn test_salvage_broken_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let db = DB::open(&src, Options::default()).unwrap();
+        fill(&db);
+        let root = db
+            .view(|tx| Ok(tx.bucket(b"widgets").unwrap().root()))
+            .unwrap();
+        let page_size = db.0.page_size();
+        db.close().unwrap();
+        assert!(surgeon::clear_page(&src, root).unwrap());
+        // Break the page entirely, not just its elements.
+        let mut buf = std::fs::read(&src).unwrap();
+        let pos = root as usize * page_size + 8;
+        buf[pos..pos + 2].copy_from_slice(&[0, 0]);
+        std::fs::write(&src, &buf).unwrap();
+
+        let dst = dir.path().join("dst");
+        let report = salvage(&src, &dst).unwrap();
+        assert_eq!(report.keys, 1001);
+        assert_eq!(report.orphaned_keys, 1001);
+        assert!(report
+            .lost
+            .iter()
+            .any(|(id, reason)| *id == root && reason.starts_with("referenced by page")));
+
+        let db = DB::open(&dst, Options::default()).unwrap();
+        db.view(|tx| {
+            assert_eq!(count(&tx.bucket(ORPHAN_BUCKET).unwrap())?, 1001);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
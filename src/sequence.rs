@@ -0,0 +1,87 @@
+//! A sequence for the whole database.
+//!
+//! Buckets have a sequence of their own, see Bucket::next_sequence. The
+//! sequence of the database is the sequence of a hidden bucket at the root,
+//! under SEQUENCE_BUCKET, which holds no keys: it stays inline in the root,
+//! so that taking an id only rewrites the page holding its header.
+
+use crate::db::DB;
+use crate::errors::Result;
+use crate::tx::Tx;
+
+/// SEQUENCE_BUCKET is the name of the hidden bucket whose sequence is the
+/// sequence of the database. Tx::for_each does not report it.
+pub(crate) const SEQUENCE_BUCKET: &[u8] = b"\x00bbolt.seq";
+
+impl Tx {
+    /// next_id returns the next integer of the sequence of the database,
+    /// starting at one. Ids are unique and increasing within the file as
+    /// long as the transaction commits; a rollback gives its ids out again.
+    pub fn next_id(&self) -> Result<u64> {
+        self.root()
+            .create_bucket_if_not_exists(SEQUENCE_BUCKET)?
+            .next_sequence()
+    }
+
+    /// last_id returns the last integer taken from the sequence of the
+    /// database, or zero if none was.
+    pub fn last_id(&self) -> u64 {
+        self.root()
+            .bucket(SEQUENCE_BUCKET)
+            .map_or(0, |b| b.sequence())
+    }
+}
+
+impl DB {
+    /// next_id returns the next integer of the sequence of the database, in
+    /// a write transaction of its own. Callers writing anyway should use
+    /// Tx::next_id, which shares the commit of their transaction.
+    pub fn next_id(&self) -> Result<u64> {
+        self.update(|tx| tx.next_id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    // Ensure that ids increase across transactions and reopening, that a
+    // rollback gives them out again, and that the bucket stays hidden.
+    #[test]
+    fn test_next_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.view(|tx| Ok(tx.last_id())).unwrap(), 0);
+        assert_eq!(db.next_id().unwrap(), 1);
+        assert_eq!(db.next_id().unwrap(), 2);
+
+        let tx = db.begin_rw().unwrap();
+        assert_eq!(tx.next_id().unwrap(), 3);
+        tx.rollback().unwrap();
+        db.update(|tx| {
+            tx.create_bucket(b"widgets")?;
+            assert_eq!(tx.next_id()?, 3);
+            assert_eq!(tx.next_id()?, 4);
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.next_id().unwrap(), 5);
+        db.view(|tx| {
+            assert_eq!(tx.last_id(), 5);
+            let mut names = Vec::new();
+            tx.for_each(|name, _| {
+                names.push(name.to_vec());
+                Ok(())
+            })?;
+            assert_eq!(names, vec![b"widgets".to_vec()]);
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
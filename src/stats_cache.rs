@@ -0,0 +1,127 @@
+//! Caching the stats of buckets.
+//!
+//! Bucket::stats walks every page of a bucket and its nested buckets. With
+//! Options.cache_bucket_stats the stats of the subtree below each page are
+//! kept once computed. Committed pages never change until they are freed,
+//! released and allocated again, so an entry stays valid until its page is
+//! allocated, which is when it is dropped. A commit copies the pages from
+//! the changed leaves up to the root, so the next call only walks those and
+//! takes the rest of the tree from the cache. Rolled back transactions never
+//! reach the cache, since stats are only collected from committed pages.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::bucket::BucketStats;
+use crate::common::page::PgId;
+
+// Number of entries past which the cache is emptied, in case pages beyond
+// the end of a shrunk file are never allocated again.
+const MAX_ENTRIES: usize = 1 << 20;
+
+/// SubtreeStats are the stats of the pages below a page.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SubtreeStats {
+    /// Stats of the pages themselves, with the height of the subtree as depth.
+    pub(crate) pages: BucketStats,
+    /// Stats of the buckets nested in the leaves, added up.
+    pub(crate) nested: BucketStats,
+}
+
+/// StatsCache holds the stats of the subtrees of committed pages.
+#[derive(Debug, Default)]
+pub(crate) struct StatsCache(Mutex<HashMap<PgId, Arc<SubtreeStats>>>);
+
+impl StatsCache {
+    /// get returns the stats of the subtree below a page, if cached.
+    pub(crate) fn get(&self, pgid: PgId) -> Option<Arc<SubtreeStats>> {
+        self.0.lock().unwrap().get(&pgid).cloned()
+    }
+
+    /// insert caches the stats of the subtree below a page.
+    pub(crate) fn insert(&self, pgid: PgId, stats: Arc<SubtreeStats>) {
+        let mut entries = self.0.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.insert(pgid, stats);
+    }
+
+    /// invalidate drops the entries of count pages from pgid on, which are
+    /// about to be written.
+    pub(crate) fn invalidate(&self, pgid: PgId, count: usize) {
+        let mut entries = self.0.lock().unwrap();
+        if entries.is_empty() {
+            return;
+        }
+        for id in pgid..pgid + count as PgId {
+            entries.remove(&id);
+        }
+    }
+
+    /// len returns the number of cached subtrees.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Options, DB};
+
+    // Ensure that cached stats equal walked ones after commits, nested
+    // buckets and rollbacks.
+    #[test]
+    fn test_cached_bucket_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            cache_bucket_stats: true,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        let check = |db: &DB| {
+            db.view(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                let stats = b.stats()?;
+                assert_eq!(stats, b.walk_stats()?);
+                assert_eq!(b.stats()?, stats);
+                Ok(stats)
+            })
+            .unwrap()
+        };
+
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..2000u32 {
+                b.put(format!("{:05}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.create_bucket(b"inline")?.put(b"foo", b"bar")?;
+            let sub = b.create_bucket(b"sub")?;
+            for i in 0..500u32 {
+                sub.put(format!("{:05}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let before = check(&db);
+        let cached = db.0.stats_cache().unwrap().len();
+        assert_eq!(cached, before.branch_page_n + before.leaf_page_n);
+
+        // A rolled back write changes nothing.
+        let tx = db.begin_rw().unwrap();
+        tx.bucket(b"widgets").unwrap().delete(b"00000").unwrap();
+        tx.rollback().unwrap();
+        assert_eq!(check(&db), before);
+
+        db.update(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            b.delete(b"00000")?;
+            b.bucket(b"sub").unwrap().put(b"new", &[0x5a; 4000])
+        })
+        .unwrap();
+        let after = check(&db);
+        assert_eq!(after.key_n, before.key_n);
+        assert_eq!(after.leaf_overflow_n, before.leaf_overflow_n + 1);
+    }
+}
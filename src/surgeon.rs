@@ -0,0 +1,319 @@
+//! Package surgeon provides low level operations that rewrite single pages of
+//! a database file, to recover data from a database that can no longer be
+//! opened or fails Tx::check.
+//!
+//! Every operation works on a database file that is not open: the file is
+//! locked exclusively while it is being modified. Operate on a copy of the
+//! file, as the changes cannot be undone.
+
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::common::page::{OwnedPage, Page, PgId, PAGE_HEADER_SIZE};
+use crate::common::types::PGID_NO_FREELIST;
+use crate::db::get_page_size;
+use crate::errors::{BoltError, Result};
+use crate::os;
+
+/// clear_page removes every element of a branch or leaf page.
+///
+/// Returns true if pages referenced by the cleared elements, the children of
+/// a branch page or the pages of nested buckets, are now unreachable. They are
+/// not on the freelist either, so the freelist should be rebuilt afterwards.
+pub fn clear_page(path: impl AsRef<Path>, pgid: PgId) -> Result<bool> {
+    let file = PageFile::open(path.as_ref())?;
+    let mut page = file.read_page(pgid)?;
+    if !page.is_branch_page() && !page.is_leaf_page() {
+        return Err(BoltError::Unexpected(
+            "only the elements of branch and leaf pages can be cleared",
+        ));
+    }
+    page.try_validate(page.size())?;
+
+    let abandoned = if page.is_branch_page() {
+        page.count() > 0
+    } else {
+        page.leaf_page_elements()
+            .iter()
+            .filter_map(|elem| elem.bucket())
+            .any(|bucket| bucket.root_page() != 0)
+    };
+
+    page.set_count(0);
+    file.write_page(&mut page)?;
+    Ok(abandoned)
+}
+
+/// copy_page copies the page src, along with its overflow pages, over the
+/// page dst. The pages previously reachable from dst are left unreachable.
+pub fn copy_page(path: impl AsRef<Path>, src: PgId, dst: PgId) -> Result<()> {
+    if src == dst {
+        return Err(BoltError::Unexpected(
+            "source and destination page are the same",
+        ));
+    }
+    let file = PageFile::open(path.as_ref())?;
+    let mut page = file.read_page(src)?;
+    page.set_id(dst);
+    file.write_page(&mut page)
+}
+
+/// revert_meta_page replaces the active meta page with the other one, which
+/// describes the database as of the previous transaction. This undoes the last
+/// commit if its data was lost, e.g. by a torn write.
+pub fn revert_meta_page(path: impl AsRef<Path>) -> Result<()> {
+    let file = PageFile::open(path.as_ref())?;
+    let meta0 = file.read_page(0)?;
+    let meta1 = file.read_page(1)?;
+    let (active, mut other) = if meta1.meta().txid() > meta0.meta().txid() {
+        (meta1, meta0)
+    } else {
+        (meta0, meta1)
+    };
+
+    // The previous meta page has to be intact to be of any use.
+    other.meta().validate()?;
+    other.set_id(active.id());
+    file.write_page(&mut other)
+}
+
+/// abandon_freelist removes the freelist from both meta pages. The next time
+/// the database is opened for writing, the freelist is reconstructed by
+/// scanning the pages reachable from the root bucket, as DB::rebuild_freelist
+/// does. Use it when the freelist page is too damaged to open the database.
+pub fn abandon_freelist(path: impl AsRef<Path>) -> Result<()> {
+    let file = PageFile::open(path.as_ref())?;
+    for id in 0..2 {
+        let mut page = file.read_page(id)?;
+        if !page.is_meta_page() {
+            return Err(BoltError::Invalid);
+        }
+        let mut meta = page.meta();
+        meta.set_freelist(PGID_NO_FREELIST);
+        page.set_meta(&meta);
+        file.write_page(&mut page)?;
+    }
+    Ok(())
+}
+
+/// PageFile gives page level access to a locked database file. Meta pages are
+/// checksummed again as they are written, so the file stays openable no
+/// matter how their contents were changed.
+struct PageFile {
+    file: File,
+    page_size: usize,
+    len: u64,
+}
+
+impl PageFile {
+    fn open(path: &Path) -> Result<PageFile> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // Refuse to touch a database that is in use.
+        os::flock(&file, true, Duration::from_millis(1))?;
+
+        let len = file.metadata()?.len();
+        let page_size = get_page_size(&file, len as usize)?;
+        Ok(PageFile {
+            file,
+            page_size,
+            len,
+        })
+    }
+
+    // read_page reads the page with the given id along with its overflow pages.
+    fn read_page(&self, id: PgId) -> Result<OwnedPage> {
+        let mut page = OwnedPage::new(self.page_size);
+        self.read_at(page.buf_mut(), id)?;
+
+        let overflow = Page::try_from_slice(page.buf())?.overflow() as usize;
+        if overflow > 0 {
+            let mut buf = OwnedPage::new((overflow + 1) * self.page_size);
+            self.read_at(buf.buf_mut(), id)?;
+            page = buf;
+        }
+        Ok(page)
+    }
+
+    fn read_at(&self, buf: &mut [u8], id: PgId) -> Result<()> {
+        let pos = self.offset(id, buf.len())?;
+        os::read_exact_at(&self.file, buf, pos)?;
+        Ok(())
+    }
+
+    // write_page writes the page at the position given by its id, fixing up
+    // the checksum of meta pages first.
+    fn write_page(&self, page: &mut OwnedPage) -> Result<()> {
+        if page.is_meta_page() {
+            if page.size() < PAGE_HEADER_SIZE + page.meta().byte_size() {
+                return Err(BoltError::Invalid);
+            }
+            let mut meta = page.meta();
+            meta.set_checksum(meta.sum64());
+            page.set_meta(&meta);
+        }
+
+        let pos = self.offset(page.id(), page.size())?;
+        os::write_all_at(&self.file, page.buf(), pos)?;
+        os::fdatasync(&self.file)
+    }
+
+    // offset returns the position of the page with the given id, making sure
+    // that len bytes from there on lie within the file.
+    fn offset(&self, id: PgId, len: usize) -> Result<u64> {
+        id.checked_mul(self.page_size as u64)
+            .filter(|pos| pos.saturating_add(len as u64) <= self.len)
+            .ok_or(BoltError::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Options, DB};
+    use crate::tx_check::CheckErrorKind;
+
+    fn create(path: &Path) -> DB {
+        let db = DB::open(path, Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db
+    }
+
+    // leaf_pages returns the ids of the leaf pages of the widgets bucket.
+    fn leaf_pages(db: &DB) -> Vec<PgId> {
+        db.view(|tx| {
+            let root = tx.bucket(b"widgets").unwrap().root();
+            let mut pages = Vec::new();
+            tx.for_each_page_in(root, &mut |page, _, _| {
+                if page.is_leaf_page() {
+                    pages.push(page.id());
+                }
+            })?;
+            Ok(pages)
+        })
+        .unwrap()
+    }
+
+    fn key_count(db: &DB) -> usize {
+        db.view(|tx| {
+            let mut count = 0;
+            tx.bucket(b"widgets").unwrap().for_each(|_, _| {
+                count += 1;
+                Ok(())
+            })?;
+            Ok(count)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_clear_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = create(&path);
+        let pages = leaf_pages(&db);
+        let root = db
+            .view(|tx| Ok(tx.bucket(b"widgets").unwrap().root()))
+            .unwrap();
+        db.close().unwrap();
+
+        // Clearing a leaf page loses its keys only.
+        assert!(!clear_page(&path, pages[0]).unwrap());
+        let db = DB::open(&path, Options::default()).unwrap();
+        let count = key_count(&db);
+        assert!(count < 1000 && count > 0, "{}", count);
+        db.close().unwrap();
+
+        // Clearing a branch page abandons its children.
+        assert!(clear_page(&path, root).unwrap());
+        assert_eq!(
+            clear_page(&path, 0),
+            Err(BoltError::Unexpected(
+                "only the elements of branch and leaf pages can be cleared",
+            ))
+        );
+    }
+
+    #[test]
+    fn test_copy_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = create(&path);
+        let pages = leaf_pages(&db);
+        db.close().unwrap();
+
+        copy_page(&path, pages[0], pages[1]).unwrap();
+        assert!(copy_page(&path, 1 << 20, pages[1]).is_err());
+
+        // The copy breaks the order of the keys.
+        let db = DB::open(&path, Options::default()).unwrap();
+        let report = db.view(|tx| tx.check()).unwrap();
+        assert!(!report.is_ok());
+        assert!(report
+            .errors
+            .iter()
+            .all(|e| matches!(e.kind, CheckErrorKind::OutOfOrder { .. })));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_revert_meta_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = create(&path);
+        db.update(|tx| tx.bucket(b"widgets").unwrap().put(b"last", b"commit"))
+            .unwrap();
+        let txid = db.info().unwrap().txid;
+
+        // The database is locked while it is open.
+        assert_eq!(revert_meta_page(&path), Err(BoltError::Timeout));
+        db.close().unwrap();
+
+        revert_meta_page(&path).unwrap();
+        let db = DB::open(&path, Options::default()).unwrap();
+        let info = db.info().unwrap();
+        assert_eq!(info.txid, txid - 1);
+        assert_eq!(info.checksum, {
+            let meta = db.0.meta().unwrap();
+            meta.sum64()
+        });
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"last"), None);
+            assert!(b.get(b"00000999").is_some());
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_abandon_freelist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = create(&path);
+        db.update(|tx| tx.delete_bucket(b"widgets")).unwrap();
+        let free = db.0.freelist().unwrap().count();
+        assert!(free > 0);
+        db.close().unwrap();
+
+        abandon_freelist(&path).unwrap();
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.0.meta().unwrap().freelist(), PGID_NO_FREELIST);
+        assert!(db.0.freelist().unwrap().count() >= free);
+
+        // The next commit stores the reconstructed freelist again.
+        db.update(|tx| tx.create_bucket(b"widgets").map(|_| ()))
+            .unwrap();
+        assert_ne!(db.0.meta().unwrap().freelist(), PGID_NO_FREELIST);
+        assert_eq!(db.view(|tx| tx.check()).unwrap().errors, vec![]);
+        db.close().unwrap();
+    }
+}
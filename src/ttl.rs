@@ -0,0 +1,518 @@
+//! Per-key expiration.
+//!
+//! Keys put with a TTL are recorded in a hidden bucket at the root, under
+//! TTL_BUCKET, with two entries each:
+//!
+//! ```text
+//! 'k' | path               => expiry   the expiry of a key
+//! 'e' | expiry | path      => ""       keys ordered by expiry, for purging
+//! ```
+//!
+//! path is the names of the buckets leading to the key followed by the key,
+//! each encoded as a byte string of the keys module, and expiry is the time
+//! the key expires at in milliseconds since the UNIX epoch, big-endian.
+//!
+//! Expired keys are skipped by Bucket::get and cursors until DB::purge_expired
+//! deletes them. Putting or deleting a key clears its TTL.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bucket::Bucket;
+use crate::common::inode::Key;
+use crate::common::page::BUCKET_LEAF_FLAG;
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::keys::KeyPart;
+use crate::tx::Tx;
+
+/// TTL_BUCKET is the name of the hidden bucket holding the expiry of keys.
+/// Tx::for_each does not report it.
+pub(crate) const TTL_BUCKET: &[u8] = b"\x00bbolt.ttl";
+
+const KEY_PREFIX: u8 = b'k';
+const EXPIRY_PREFIX: u8 = b'e';
+
+// Number of keys purge_expired deletes per transaction.
+const PURGE_BATCH_SIZE: usize = 1000;
+
+// now returns the current time in milliseconds since the UNIX epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// encode_path encodes the path of a key in a bucket.
+fn encode_path(path: &[Key], key: &[u8], buf: &mut Vec<u8>) {
+    for name in path {
+        name.to_vec().encode_to(buf);
+    }
+    key.to_vec().encode_to(buf);
+}
+
+// decode_path splits an encoded path into the bucket names and the key.
+fn decode_path(mut buf: &[u8]) -> Result<(Vec<Vec<u8>>, Vec<u8>)> {
+    let mut path = Vec::new();
+    while !buf.is_empty() {
+        path.push(Vec::<u8>::decode_from(&mut buf)?);
+    }
+    let key = path.pop().ok_or(BoltError::KeyRequired)?;
+    Ok((path, key))
+}
+
+fn expiry_key(expiry: u64, path: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(9 + path.len());
+    buf.push(EXPIRY_PREFIX);
+    buf.extend_from_slice(&expiry.to_be_bytes());
+    buf.extend_from_slice(path);
+    buf
+}
+
+// expiry_key_of returns the expiry index key for an encoded expiry.
+fn expiry_key_of(expiry: &[u8], path: &[u8]) -> Result<Vec<u8>> {
+    let expiry = u64::from_be_bytes(expiry.try_into().map_err(|_| BoltError::Invalid)?);
+    Ok(expiry_key(expiry, path))
+}
+
+impl Bucket<'_> {
+    /// put_with_ttl sets the value for a key in the bucket, like put, and
+    /// makes the key expire once ttl has passed. Expired keys are no longer
+    /// returned by get and cursors, and are deleted by DB::purge_expired.
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        if self.is_ttl_index() {
+            return Err(BoltError::IncompatibleValue);
+        }
+        self.put(key, value)?;
+
+        let expiry = now().saturating_add(ttl.as_millis() as u64);
+        let index = self.tx()?.root().create_bucket_if_not_exists(TTL_BUCKET)?;
+        let mut path = vec![KEY_PREFIX];
+        encode_path(&self.0.path.borrow(), key, &mut path);
+        index.put(&path, &expiry.to_be_bytes())?;
+        index.put(&expiry_key(expiry, &path[1..]), b"")
+    }
+
+    /// ttl returns the time left before a key expires, or None if the key has
+    /// no TTL. Expired keys that are not purged yet have a TTL of zero.
+    pub fn ttl(&self, key: &[u8]) -> Option<Duration> {
+        let expiry = self.expiry(key)?;
+        Some(Duration::from_millis(expiry.saturating_sub(now())))
+    }
+
+    /// is_expired returns whether the key has a TTL which has passed.
+    pub(crate) fn is_expired(&self, key: &[u8]) -> bool {
+        self.expiry(key).is_some_and(|expiry| expiry <= now())
+    }
+
+    /// clear_ttl removes the TTL of a key, if any.
+    pub(crate) fn clear_ttl(&self, key: &[u8]) -> Result<()> {
+        let index = match self.ttl_index() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let mut path = vec![KEY_PREFIX];
+        encode_path(&self.0.path.borrow(), key, &mut path);
+        if let Some(expiry) = index.get(&path) {
+            let expiry = expiry_key_of(expiry, &path[1..])?;
+            index.delete(&path)?;
+            index.delete(&expiry)?;
+        }
+        Ok(())
+    }
+
+    /// clear_ttls removes the TTLs of the keys of the bucket that match a
+    /// filter, leaving those of nested buckets alone.
+    pub(crate) fn clear_ttls<F>(&self, filter: F) -> Result<()>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let index = match self.ttl_index() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let mut prefix = vec![KEY_PREFIX];
+        for name in self.0.path.borrow().iter() {
+            name.to_vec().encode_to(&mut prefix);
+        }
+
+        let mut found = Vec::new();
+        let mut c = index.cursor();
+        let mut item = c.seek(&prefix);
+        while let Some((k, Some(expiry))) = item {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            let (path, key) = decode_path(&k[1..])?;
+            if path.len() == self.0.path.borrow().len() && filter(&key) {
+                found.push((k.to_vec(), expiry_key_of(expiry, &k[1..])?));
+            }
+            item = c.next();
+        }
+        c.check()?;
+        for (key, expiry) in found {
+            index.delete(&key)?;
+            index.delete(&expiry)?;
+        }
+        Ok(())
+    }
+
+    /// move_ttls moves the TTLs of the keys below the nested bucket old of the
+    /// bucket, nested buckets included, over to the nested bucket new.
+    pub(crate) fn move_ttls(&self, old: &[u8], new: &[u8]) -> Result<()> {
+        let index = match self.tx()?.root().bucket(TTL_BUCKET) {
+            Some(index) if !self.is_ttl_index() => index,
+            _ => return Ok(()),
+        };
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        for name in self.0.path.borrow().iter() {
+            name.to_vec().encode_to(&mut from);
+        }
+        to.extend_from_slice(&from);
+        old.to_vec().encode_to(&mut from);
+        new.to_vec().encode_to(&mut to);
+
+        let mut found = Vec::new();
+        let mut c = index.cursor();
+        let mut item = c.seek(&[&[KEY_PREFIX][..], &from].concat());
+        while let Some((k, Some(expiry))) = item {
+            if !k[1..].starts_with(&from) {
+                break;
+            }
+            let path = [&to, &k[1 + from.len()..]].concat();
+            found.push((k.to_vec(), expiry.to_vec(), path));
+            item = c.next();
+        }
+        c.check()?;
+        for (key, expiry, path) in found {
+            index.delete(&expiry_key_of(&expiry, &key[1..])?)?;
+            index.delete(&key)?;
+            index.put(&[&[KEY_PREFIX][..], &path].concat(), &expiry)?;
+            index.put(&expiry_key_of(&expiry, &path)?, b"")?;
+        }
+        Ok(())
+    }
+
+    /// clear_nested_ttls removes the TTLs of the keys below the nested bucket
+    /// name of the bucket, nested buckets included.
+    pub(crate) fn clear_nested_ttls(&self, name: &[u8]) -> Result<()> {
+        let index = match self.tx()?.root().bucket(TTL_BUCKET) {
+            Some(index) if !self.is_ttl_index() => index,
+            _ => return Ok(()),
+        };
+        let mut prefix = vec![KEY_PREFIX];
+        for name in self.0.path.borrow().iter() {
+            name.to_vec().encode_to(&mut prefix);
+        }
+        name.to_vec().encode_to(&mut prefix);
+
+        let mut found = Vec::new();
+        let mut c = index.cursor();
+        let mut item = c.seek(&prefix);
+        while let Some((k, Some(expiry))) = item {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            found.push((k.to_vec(), expiry_key_of(expiry, &k[1..])?));
+            item = c.next();
+        }
+        c.check()?;
+        for (key, expiry) in found {
+            index.delete(&key)?;
+            index.delete(&expiry)?;
+        }
+        Ok(())
+    }
+
+    // expiry returns when a key expires, in milliseconds since the UNIX epoch.
+    fn expiry(&self, key: &[u8]) -> Option<u64> {
+        let index = self.ttl_index()?;
+        let mut path = vec![KEY_PREFIX];
+        encode_path(&self.0.path.borrow(), key, &mut path);
+        let expiry = index.get(&path)?;
+        Some(u64::from_be_bytes(expiry.try_into().ok()?))
+    }
+
+    // ttl_index returns the bucket holding the TTLs, unless there is none yet
+    // or this is the index itself.
+    fn ttl_index(&self) -> Option<Bucket<'_>> {
+        if self.0.path.borrow().is_empty() || self.is_ttl_index() {
+            return None;
+        }
+        self.tx().ok()?.root().bucket(TTL_BUCKET)
+    }
+
+    /// is_ttl_index returns whether this is the hidden bucket holding the TTLs,
+    /// or a bucket in it.
+    pub(crate) fn is_ttl_index(&self) -> bool {
+        self.0
+            .path
+            .borrow()
+            .first()
+            .is_some_and(|name| name == TTL_BUCKET)
+    }
+}
+
+impl DB {
+    /// purge_expired deletes the keys whose TTL has passed, a batch at a time
+    /// in separate write transactions so that readers and writers are not held
+    /// up for long. Returns the number of keys deleted.
+    pub fn purge_expired(&self) -> Result<usize> {
+        let mut purged = 0;
+        loop {
+            let (n, more) = self.update(purge_batch)?;
+            purged += n;
+            if !more {
+                return Ok(purged);
+            }
+        }
+    }
+}
+
+// purge_batch deletes up to PURGE_BATCH_SIZE expired keys. Returns the number
+// of keys deleted and whether more keys may have expired.
+fn purge_batch(tx: &Tx) -> Result<(usize, bool)> {
+    let index = match tx.root().bucket(TTL_BUCKET) {
+        Some(index) => index,
+        None => return Ok((0, false)),
+    };
+
+    let now = now();
+    let mut expired = Vec::new();
+    let mut c = index.cursor();
+    let mut item = c.seek(&[EXPIRY_PREFIX]);
+    while let Some((k, _)) = item {
+        if k.first() != Some(&EXPIRY_PREFIX) || expired.len() == PURGE_BATCH_SIZE {
+            break;
+        }
+        let expiry = match k.get(1..9) {
+            Some(expiry) => u64::from_be_bytes(expiry.try_into().unwrap()),
+            None => {
+                let reason = format!("ttl index key of {} bytes", k.len());
+                return Err(BoltError::corrupted(reason)
+                    .with_bucket(&[TTL_BUCKET])
+                    .with_key(k));
+            }
+        };
+        if expiry > now {
+            break;
+        }
+        expired.push(k);
+        item = c.next();
+    }
+    c.check()?;
+
+    let mut purged = 0;
+    for k in &expired {
+        let (path, key) = decode_path(&k[9..])?;
+        let bucket = path.iter().try_fold(tx.root(), |b, name| b.bucket(name));
+        match bucket {
+            // Deleting the key clears its TTL.
+            Some(b) if contains(&b, &key)? => {
+                b.delete(&key)?;
+                purged += 1;
+            }
+            // The bucket or the key is gone, only the TTL is left.
+            _ => {
+                let mut path = vec![KEY_PREFIX];
+                path.extend_from_slice(&k[9..]);
+                index.delete(&path)?;
+                index.delete(k)?;
+            }
+        }
+    }
+    Ok((purged, expired.len() == PURGE_BATCH_SIZE))
+}
+
+// contains returns whether a bucket holds a value for key, expired or not.
+fn contains(b: &Bucket, key: &[u8]) -> Result<bool> {
+    match b.cursor().seek_item(key)? {
+        Some((k, _, flags)) => Ok(k == key && (flags & BUCKET_LEAF_FLAG) == 0),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::db::Options;
+
+    #[test]
+    fn test_put_with_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put_with_ttl(b"short", b"1", Duration::from_millis(50))?;
+            b.put_with_ttl(b"long", b"2", Duration::from_secs(3600))?;
+            b.put(b"forever", b"3")?;
+            let sub = b.create_bucket(b"sub")?;
+            sub.put_with_ttl(b"short", b"4", Duration::from_millis(50))?;
+            // Putting the key again clears its TTL.
+            sub.put_with_ttl(b"cleared", b"5", Duration::from_millis(50))?;
+            sub.put(b"cleared", b"5")?;
+            Ok(())
+        })
+        .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"short"), None);
+            assert_eq!(b.get(b"long"), Some(&b"2"[..]));
+            assert!(b.ttl(b"long").unwrap() > Duration::from_secs(3000));
+            assert_eq!(b.ttl(b"short"), Some(Duration::ZERO));
+            assert_eq!(b.ttl(b"forever"), None);
+
+            let mut keys = Vec::new();
+            b.for_each(|k, _| {
+                keys.push(k.to_vec());
+                Ok(())
+            })?;
+            assert_eq!(
+                keys,
+                vec![b"forever".to_vec(), b"long".to_vec(), b"sub".to_vec()]
+            );
+            let mut c = b.cursor();
+            assert_eq!(c.last().map(|(k, _)| k), Some(&b"sub"[..]));
+            assert_eq!(c.seek(b"p").map(|(k, _)| k), Some(&b"sub"[..]));
+            assert_eq!(c.prev().map(|(k, _)| k), Some(&b"long"[..]));
+
+            let sub = b.bucket(b"sub").unwrap();
+            assert_eq!(sub.get(b"short"), None);
+            assert_eq!(sub.get(b"cleared"), Some(&b"5"[..]));
+
+            // The index is hidden.
+            let mut names = Vec::new();
+            tx.for_each(|name, _| {
+                names.push(name.to_vec());
+                Ok(())
+            })?;
+            assert_eq!(names, vec![b"widgets".to_vec()]);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(db.purge_expired().unwrap(), 2);
+        assert_eq!(db.purge_expired().unwrap(), 0);
+        db.view(|tx| {
+            let index = tx.root().bucket(TTL_BUCKET).unwrap();
+            assert_eq!(index.stats()?.key_n, 2);
+            assert!(!contains(&tx.bucket(b"widgets").unwrap(), b"short")?);
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    // Ensure that deleting a bucket removes the TTLs of its keys, nested
+    // buckets included, so a bucket created with the same name keeps its keys.
+    #[test]
+    fn test_delete_bucket_ttls() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            let sub = b.create_bucket(b"sub")?;
+            for i in 0..2500u32 {
+                b.put_with_ttl(&i.to_be_bytes(), b"v", Duration::ZERO)?;
+                sub.put_with_ttl(&i.to_be_bytes(), b"v", Duration::ZERO)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| tx.delete_bucket(b"widgets")).unwrap();
+        db.view(|tx| {
+            assert_eq!(tx.root().bucket(TTL_BUCKET).unwrap().stats()?.key_n, 0);
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+        assert_eq!(db.purge_expired().unwrap(), 0);
+        db.view(|tx| {
+            assert_eq!(
+                tx.bucket(b"widgets").unwrap().get(b"foo"),
+                Some(&b"bar"[..])
+            );
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    // Ensure that a truncated key of the expiry index is reported as
+    // corruption rather than panicking.
+    #[test]
+    fn test_purge_truncated_index_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let index = tx.root().create_bucket_if_not_exists(TTL_BUCKET)?;
+            index.put(&[EXPIRY_PREFIX, 0, 0], b"")
+        })
+        .unwrap();
+        assert!(matches!(
+            db.purge_expired(),
+            Err(BoltError::Corrupted { .. })
+        ));
+        db.close().unwrap();
+    }
+
+    // Ensure that the hidden buckets cannot be opened, listed, created or
+    // deleted through the transaction.
+    #[test]
+    fn test_reserved_bucket_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put_with_ttl(b"foo", b"bar", Duration::from_secs(60))?;
+            tx.next_id()?;
+
+            for name in [TTL_BUCKET, b"\x00bbolt.seq", b"\x00bbolt.other"] {
+                assert!(tx.bucket(name).is_none());
+                assert!(matches!(
+                    tx.delete_bucket(name),
+                    Err(BoltError::IncompatibleValue)
+                ));
+                assert!(matches!(
+                    tx.create_bucket_if_not_exists(name),
+                    Err(BoltError::IncompatibleValue)
+                ));
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let mut names = Vec::new();
+            let mut c = tx.cursor();
+            let mut item = c.first();
+            while let Some((name, _)) = item {
+                names.push(name.to_vec());
+                item = c.next();
+            }
+            assert_eq!(names, vec![b"widgets".to_vec()]);
+            assert_eq!(c.last(), Some((&b"widgets"[..], None)));
+            Ok(())
+        })
+        .unwrap();
+
+        // Clearing the root keeps the hidden buckets.
+        db.update(|tx| tx.cursor().bucket().clear().map(|_| ()))
+            .unwrap();
+        db.view(|tx| {
+            assert!(tx.bucket(b"widgets").is_none());
+            assert!(tx.root().bucket(TTL_BUCKET).is_some());
+            assert_eq!(tx.last_id(), 1);
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+}
@@ -1,13 +1,32 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, Weak};
+use std::time::Duration;
 
-use crate::bucket::Bucket;
+use bytes::Bytes;
+
+use crate::arena::{ArenaRef, PagePool, TxArena};
+use crate::bucket::{self, Bucket};
+use crate::bulk::BulkLoader;
+use crate::changes::{Change, ChangeSet};
+use crate::common::inode::Value;
 use crate::common::meta::Meta;
-use crate::common::page::{OwnedPage, PgId};
-use crate::db::WeakDB;
+use crate::common::page::{
+    OwnedPage, Page, PageInfo, PgId, LEAF_PAGE_ELEMENT_SIZE, PAGE_HEADER_SIZE,
+};
+use crate::common::types::{IGNORE_NO_SYNC, PGID_NO_FREELIST};
+use crate::crypto::PageCrypt;
+use crate::cursor::Cursor;
+use crate::db::{WeakDB, DB};
+use crate::errors::{BoltError, IoOp, IoResultExt, Result};
+use crate::latency::CommitTimes;
+use crate::os::{self, Stopwatch};
+use crate::page_cache::{DecryptedPages, PageCache};
+
+// BeforeCommit is a callback run by commit, which can veto it.
+type BeforeCommit = Box<dyn FnOnce(&Tx) -> Result<()>>;
 
 // Tx represents a read-only or read/write transaction on the database.
 // Read-only transactions can be used for retrieving values for keys and creating cursors.
@@ -25,46 +44,1011 @@ pub struct RawTx {
     /// transaction meta
     meta: RwLock<Meta>,
     /// root bucket
-    root: RwLock<Bucket>,
-    /// cache page
-    pages: RwLock<HashMap<PgId, OwnedPage>>,
+    root: Bucket<'static>,
+    /// dirty pages, their buffers are owned by the arena
+    pages: RwLock<HashMap<PgId, ArenaRef<OwnedPage>>>,
+    /// dirty pages values were streamed to, until the node of their key is
+    /// written to them
+    streamed: Mutex<Vec<PgId>>,
+    /// owns the nodes and dirty pages of the transaction
+    arena: TxArena,
+    /// pool the single-page buffers of a write transaction are recycled through
+    page_pool: Option<Arc<PagePool>>,
+    /// mmap snapshot the transaction reads pages from, only the meta pages
+    /// if the database is read without mmap
+    data: Bytes,
+    /// cache pages are read through if the database is read without mmap
+    page_cache: Option<Arc<PageCache>>,
+    /// page size of the database
+    page_size: usize,
+    /// encryption of the pages of the database
+    crypt: PageCrypt,
+    /// cache of the pages decrypted out of the mmap, if the database is
+    /// encrypted and memory-mapped
+    decrypted: Option<Arc<DecryptedPages>>,
+    /// buffers of the keys and values handed out that are not in the
+    /// snapshot, by address
+    retained: Mutex<BTreeMap<usize, Bytes>>,
     /// transactions stats
     stats: Mutex<TxStats>,
-    /// List of callbacks that will be called after commit
-    commit_handlers: Vec<Box<dyn Fn()>>,
-
-    // WriteFlag specifies the flag for write-related methods like WriteTo().
-    // Tx opens the database file with the specified flag to copy the data.
-    //
-    // By default, the flag is unset, which works well for mostly in-memory
-    // workloads. For databases that are much larger than available RAM,
-    // set the flag to syscall.O_DIRECT to avoid trashing the page cache.
-    write_flag: usize,
+    /// changes made by a write transaction, recorded while the database has observers
+    pub(crate) changes: Mutex<Option<Vec<Change>>>,
+    /// callbacks commit runs before writing the transaction, in order
+    before_commit: Mutex<Vec<BeforeCommit>>,
 }
 
-pub struct Tx(Arc<RawTx>);
-
-unsafe impl Sync for Tx {}
-
-unsafe impl Send for Tx {}
+/// Tx is a read-only or read/write transaction on the database.
+///
+/// A transaction shares unsynchronized state with the buckets and cursors
+/// it hands out, so it is neither Send nor Sync and stays on the thread that
+/// began it. Threads reading concurrently each begin their own transaction
+/// on a shared DB, which is Send and Sync.
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<boltdb_rs::Tx>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<boltdb_rs::Tx>();
+/// ```
+pub struct Tx(pub(crate) Arc<RawTx>);
 
 #[derive(Debug, Clone)]
 pub(crate) struct WeakTx(Weak<RawTx>);
 
 impl WeakTx {
-    pub(crate) fn new() -> Self {
-        Self(Weak::new())
-    }
-
     pub(crate) fn upgrade(&self) -> Option<Tx> {
         self.0.upgrade().map(Tx)
     }
+}
+
+impl Tx {
+    /// new initializes the transaction against the current meta page and mmap snapshot.
+    pub(crate) fn new(db: &DB, writable: bool) -> Result<Tx> {
+        // Copy the meta page since it can be changed by the writer.
+        let mut meta = db.0.meta()?;
+        let data = db.0.data()?;
+
+        // Increment the transaction id and add a page cache for writable transactions.
+        if writable {
+            meta.inc_txid();
+        }
+
+        let root = meta.root_bucket().clone();
+        let tx = Arc::new_cyclic(|weak: &Weak<RawTx>| {
+            let bucket = Bucket::new(WeakTx(weak.clone()));
+            *bucket.0.bucket.borrow_mut() = root;
+
+            RawTx {
+                writable: AtomicBool::new(writable),
+                managed: AtomicBool::new(false),
+                db: RwLock::new(WeakDB::from(db)),
+                meta: RwLock::new(meta),
+                root: bucket,
+                pages: RwLock::new(HashMap::new()),
+                streamed: Mutex::new(Vec::new()),
+                arena: TxArena::default(),
+                page_pool: writable.then(|| db.0.page_pool().clone()),
+                data,
+                page_cache: db.0.page_cache().cloned(),
+                page_size: db.0.page_size(),
+                crypt: db.0.crypt().clone(),
+                decrypted: db.0.decrypted().cloned(),
+                retained: Mutex::new(BTreeMap::new()),
+                stats: Mutex::new(TxStats::default()),
+                changes: Mutex::new((writable && !db.0.observers.is_empty()).then(Vec::new)),
+                before_commit: Mutex::new(Vec::new()),
+            }
+        });
+
+        Ok(Tx(tx))
+    }
+
+    /// id returns the transaction id.
+    pub fn id(&self) -> u64 {
+        self.0.meta.read().unwrap().txid()
+    }
+
+    /// writable returns whether the transaction can perform write operations.
+    pub fn writable(&self) -> bool {
+        self.0.writable.load(Ordering::Acquire)
+    }
+
+    /// db returns a reference to the database that created the transaction.
+    pub fn db(&self) -> Result<DB> {
+        self.0
+            .db
+            .read()
+            .unwrap()
+            .upgrade()
+            .ok_or(BoltError::TxClosed)
+    }
+
+    /// stats retrieves a copy of the current transaction statistics.
+    pub fn stats(&self) -> TxStats {
+        self.0.stats.lock().unwrap().clone()
+    }
+
+    /// stats_mut gives access to the counters of the transaction.
+    pub(crate) fn stats_mut(&self) -> MutexGuard<'_, TxStats> {
+        self.0.stats.lock().unwrap()
+    }
+
+    /// root returns the root bucket of the transaction.
+    pub(crate) fn root(&self) -> Bucket<'static> {
+        self.0.root.clone()
+    }
+
+    /// cursor creates a cursor associated with the root bucket, which skips
+    /// the hidden buckets, see for_each.
+    /// All items in the cursor will return a None value because all root bucket keys point to buckets.
+    /// The cursor is only valid as long as the transaction is open.
+    pub fn cursor(&self) -> Cursor<'_> {
+        self.0.root.cursor().skipping_hidden()
+    }
+
+    /// bucket retrieves a bucket by name.
+    /// Returns None if the bucket does not exist, or if the name is reserved
+    /// for a hidden bucket.
+    pub fn bucket(&self, name: &[u8]) -> Option<Bucket<'_>> {
+        if bucket::is_hidden(name) {
+            return None;
+        }
+        self.0.root.bucket(name)
+    }
+
+    /// create_bucket creates a new bucket.
+    /// Returns an error if the bucket already exists, if the bucket name is blank, or if the bucket name is too long.
+    /// Returns BoltError::IncompatibleValue if the name is reserved for a
+    /// hidden bucket.
+    pub fn create_bucket(&self, name: &[u8]) -> Result<Bucket<'_>> {
+        check_name(name)?;
+        self.0.root.create_bucket(name)
+    }
+
+    /// bulk_loader creates a new bucket and returns a loader filling it with
+    /// keys given in ascending order.
+    /// Returns the errors of create_bucket.
+    pub fn bulk_loader(&self, name: &[u8]) -> Result<BulkLoader<'_>> {
+        check_name(name)?;
+        self.0.root.bulk_loader(name)
+    }
+
+    /// create_bucket_if_not_exists creates a new bucket if it doesn't already exist.
+    /// Returns an error if the bucket name is blank, or if the bucket name is too long.
+    /// Returns BoltError::IncompatibleValue if the name is reserved for a
+    /// hidden bucket.
+    pub fn create_bucket_if_not_exists(&self, name: &[u8]) -> Result<Bucket<'_>> {
+        check_name(name)?;
+        self.0.root.create_bucket_if_not_exists(name)
+    }
+
+    /// delete_bucket deletes a bucket.
+    /// Returns an error if the bucket cannot be found or if the key represents a non-bucket value.
+    /// Returns BoltError::IncompatibleValue if the name is reserved for a
+    /// hidden bucket.
+    pub fn delete_bucket(&self, name: &[u8]) -> Result<()> {
+        self.0.root.delete_bucket(name)
+    }
+
+    /// rename_bucket renames a bucket without copying its keys.
+    /// Returns the errors of Bucket::rename_bucket.
+    pub fn rename_bucket(&self, old: &[u8], new: &[u8]) -> Result<()> {
+        self.0.root.rename_bucket(old, new)
+    }
+
+    /// for_each executes a function for each bucket in the root, except the
+    /// hidden buckets holding the TTLs of keys and the sequence of the
+    /// database.
+    /// If the provided function returns an error then the iteration is stopped and
+    /// the error is returned to the caller.
+    pub fn for_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], Bucket<'_>) -> Result<()>,
+    {
+        self.0.root.for_each_bucket(|name| {
+            if bucket::is_hidden(name) {
+                return Ok(());
+            }
+            let bucket = self.0.root.bucket(name).ok_or(BoltError::BucketNotFound)?;
+            f(name, bucket)
+        })
+    }
+
+    /// retain returns a pointer to the bytes of value that is valid until the
+    /// transaction is dropped. Values of the mmap snapshot are valid that long
+    /// already, any other buffer is kept by the transaction, unless it lies
+    /// within one retained before.
+    pub(crate) fn retain(&self, value: Bytes) -> *const [u8] {
+        let snapshot = self.0.data.as_ptr_range();
+        let range = value.as_ptr_range();
+        let ptr: *const [u8] = &value[..];
+        if value.is_empty() || (snapshot.start <= range.start && range.end <= snapshot.end) {
+            return ptr;
+        }
+        let mut retained = self.0.retained.lock().unwrap();
+        if let Some((_, buf)) = retained.range(..=range.start as usize).next_back() {
+            if range.end <= buf.as_ptr_range().end {
+                return ptr;
+            }
+        }
+        // Buffers within value are kept by it from now on.
+        let (start, end) = (range.start as usize, range.end as usize);
+        let covered: Vec<usize> = retained
+            .range(start..end)
+            .filter(|(_, buf)| buf.as_ptr_range().end as usize <= end)
+            .map(|(&addr, _)| addr)
+            .collect();
+        for addr in covered {
+            retained.remove(&addr);
+        }
+        // The buffer does not move along with the Bytes handle.
+        retained.insert(start, value);
+        ptr
+    }
+
+    /// meta returns a copy of the transaction's meta.
+    pub(crate) fn meta(&self) -> Meta {
+        self.0.meta.read().unwrap().clone()
+    }
+
+    /// addr returns the address of the transaction, which tells apart
+    /// transactions open at once, even with the same id.
+    #[cfg(debug_assertions)]
+    pub(crate) fn addr(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+
+    /// set_format changes the format version and flags of the meta the
+    /// transaction commits.
+    pub(crate) fn set_format(&self, version: u32, flags: u32) {
+        let mut meta = self.0.meta.write().unwrap();
+        meta.set_version(version);
+        meta.set_flags(flags);
+    }
+
+    /// abandon_freelist detaches the stored freelist from the transaction so the
+    /// commit writes a fresh one without freeing the pages of the old one.
+    pub(crate) fn abandon_freelist(&self) {
+        self.0.meta.write().unwrap().set_freelist(PGID_NO_FREELIST);
+    }
+
+    /// set_high_water_mark moves the high water mark of the transaction.
+    pub(crate) fn set_high_water_mark(&self, pgid: PgId) {
+        self.0.meta.write().unwrap().set_pgid(pgid);
+    }
+
+    pub(crate) fn set_managed(&self, managed: bool) {
+        self.0.managed.store(managed, Ordering::Release);
+    }
+
+    /// before_commit registers f to be called by commit once the changes of
+    /// the transaction are spilled, before anything is written. f reads the
+    /// transaction as it is about to be committed, but cannot change it:
+    /// writes return BoltError::TxNotWritable. An error returned by f rolls
+    /// the transaction back and is returned by commit, so that callbacks can
+    /// check invariants of the application or audit its writes. Callbacks
+    /// run in the order they were registered, and are dropped unrun if the
+    /// transaction is rolled back.
+    pub fn before_commit<F>(&self, f: F)
+    where
+        F: FnOnce(&Tx) -> Result<()> + 'static,
+    {
+        self.0.before_commit.lock().unwrap().push(Box::new(f));
+    }
+
+    /// run_before_commit calls the callbacks registered with before_commit,
+    /// with the transaction made read-only meanwhile.
+    fn run_before_commit(&self) -> Result<()> {
+        let callbacks = std::mem::take(&mut *self.0.before_commit.lock().unwrap());
+        if callbacks.is_empty() {
+            return Ok(());
+        }
+
+        // Closing the transaction unlocks the writer only if it is still
+        // writable, even if a callback panics.
+        struct Writable<'a>(&'a AtomicBool);
+        impl Drop for Writable<'_> {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Release);
+            }
+        }
+        self.0.writable.store(false, Ordering::Release);
+        let _writable = Writable(&self.0.writable);
+        callbacks.into_iter().try_for_each(|f| f(self))
+    }
+
+    /// commit writes all changes to disk, updates the meta page and closes the transaction.
+    /// Returns an error if a disk write error occurs, or if commit is
+    /// called on a read-only transaction.
+    /// With group commit, a failed shared sync is returned once the changes
+    /// are visible to new transactions, see Options.group_commit_delay.
+    ///
+    /// The transaction is consumed, so it can only be committed once the
+    /// buckets, cursors and values borrowed from it are no longer used. A
+    /// managed transaction is only lent to its function, which cannot commit it:
+    ///
+    /// ```compile_fail
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let db = boltdb_rs::DB::open(dir.path().join("db"), Default::default()).unwrap();
+    /// db.update(|tx| tx.commit()).unwrap();
+    /// ```
+    pub fn commit(self) -> Result<()> {
+        assert!(
+            !self.0.managed.load(Ordering::Acquire),
+            "managed tx commit not allowed"
+        );
+        let db = self.db()?;
+        if !self.writable() {
+            return Err(BoltError::TxNotWritable);
+        }
+
+        let start = Stopwatch::start();
+        if let Err(err) = self.commit_pages(&db) {
+            self.0.rollback();
+            return Err(err);
+        }
+
+        // Notify the observers while still holding the writer lock, so that
+        // change sets are delivered in commit order.
+        let changes = self.0.changes.lock().unwrap().take();
+        if let Some(changes) = changes.filter(|changes| !changes.is_empty()) {
+            db.0.observers.notify(&ChangeSet {
+                txid: self.id(),
+                changes,
+            });
+        }
+
+        let txid = self.id();
+        let stats = self.stats();
+        self.0.close();
+
+        // Let the queued writers go ahead, the next sync makes this commit
+        // durable along with theirs.
+        let mut sync = stats.sync_time;
+        if let Some(group) = db.0.group_commit() {
+            let wait = Stopwatch::start();
+            group.wait(txid, db.0.vfs(), || db.0.writer_busy())?;
+            sync += wait.elapsed();
+        }
+
+        let times = CommitTimes {
+            total: start.elapsed(),
+            spill: stats.rebalance_time + stats.spill_time,
+            write: stats.write_time - stats.sync_time,
+            sync,
+        };
+        db.0.commit_timer()
+            .record(db.0.logger(), txid, times, stats.write);
+        if let Some(sync) = db.0.background_sync() {
+            sync.committed();
+        }
+
+        if db.0.strict_mode() {
+            db.check_strict()?;
+        }
+        Ok(())
+    }
+
+    /// commit_pages spills the dirty nodes and writes them, the freelist and
+    /// the meta page to disk.
+    fn commit_pages(&self, db: &DB) -> Result<()> {
+        let opgid = self.meta().pgid();
+        let root = self.root();
+
+        // Rebalance nodes which have had deletions.
+        let start = Stopwatch::start();
+        root.0.rebalance()?;
+        if self.stats().rebalance > 0 {
+            self.stats_mut().rebalance_time += start.elapsed();
+        }
+
+        // spill data onto dirty pages.
+        let start = Stopwatch::start();
+        root.spill()?;
+        self.release_streamed(db)?;
+        self.stats_mut().spill_time += start.elapsed();
+
+        // Free the old root bucket.
+        let root = root.0.bucket.borrow().clone();
+        self.0.meta.write().unwrap().set_root_bucket(root);
+
+        self.run_before_commit()?;
+
+        // Free the old freelist because commit writes out a fresh freelist.
+        let meta = self.meta();
+        if meta.freelist() != PGID_NO_FREELIST {
+            db.0.freelist_mut()?
+                .free(meta.txid(), &self.page(meta.freelist())?);
+        }
+
+        if !db.0.no_freelist_sync() {
+            self.commit_freelist(db)?;
+        } else {
+            self.0.meta.write().unwrap().set_freelist(PGID_NO_FREELIST);
+        }
+
+        // If the high water mark has moved up then attempt to grow the database.
+        let pgid = self.meta().pgid();
+        if pgid > opgid {
+            db.0.grow((pgid as usize + 1) * db.0.page_size())?;
+        }
+
+        // Write dirty pages to disk.
+        let start = Stopwatch::start();
+        self.write(db)?;
+
+        // Write meta to disk.
+        self.write_meta(db)?;
+        self.stats_mut().write_time += start.elapsed();
+
+        Ok(())
+    }
+
+    /// commit_freelist writes the freelist onto freshly allocated pages.
+    fn commit_freelist(&self, db: &DB) -> Result<()> {
+        // Allocate new pages for the new free list. This will overestimate
+        // the size of the freelist but not underestimate the size (which would be bad).
+        let size = db.0.freelist()?.size() + db.0.crypt().overhead();
+        let count = size / db.0.page_size() + 1;
+        let id = self.allocate(count)?;
+
+        let freelist = db.0.freelist()?;
+        self.write_page(id, |page| freelist.write(page));
+        self.0.meta.write().unwrap().set_freelist(id);
+
+        Ok(())
+    }
+
+    /// rollback closes the transaction and ignores all previous updates. Read-only
+    /// transactions must be rolled back and not committed.
+    pub fn rollback(self) -> Result<()> {
+        assert!(
+            !self.0.managed.load(Ordering::Acquire),
+            "managed tx rollback not allowed"
+        );
+        if self.db().is_err() {
+            return Err(BoltError::TxClosed);
+        }
+        self.0.non_physical_rollback();
+        Ok(())
+    }
+
+    /// page_info returns page information for a given page number.
+    /// This is only safe for concurrent use when used by a writable transaction.
+    pub fn page_info(&self, id: PgId) -> Result<Option<PageInfo>> {
+        let db = self.db()?;
+        if id >= self.meta().pgid() {
+            return Ok(None);
+        }
+
+        let freelist = db.0.freelist()?;
+
+        // Build the page info.
+        let page = self.page(id)?;
+        let mut info = PageInfo::new();
+        info.set_id(id);
+        info.set_count(page.count() as usize);
+        info.set_overflow_count(page.overflow() as usize);
+
+        // Determine the type (or if it's free).
+        if freelist.freed(id) {
+            info.set_typ("free".to_string());
+        } else {
+            info.set_typ(page.typ());
+        }
+
+        Ok(Some(info))
+    }
+
+    /// allocate returns the id of a contiguous block of `count` dirty pages.
+    /// The pages are taken from the freelist, or from the end of the file when
+    /// no free block is large enough.
+    pub(crate) fn allocate(&self, count: usize) -> Result<PgId> {
+        let db = self.db()?;
+        let page_size = db.0.page_size();
+
+        // Use pages from the freelist if they are available.
+        let allocated = db.0.freelist_mut()?.allocate(self.id(), count);
+        let id = match allocated {
+            Some(id) => {
+                db.0.borrows().check_reuse(id, count);
+                id
+            }
+            None => {
+                // Resize mmap() if we're at the end.
+                let mut meta = self.0.meta.write().unwrap();
+                let id = meta.pgid();
+                let minsz = (id as usize + count + 1) * page_size;
+                if minsz >= db.0.datasz() {
+                    db.0.mmap(minsz)?;
+                }
+
+                // Move the page id high water mark.
+                meta.set_pgid(id + count as PgId);
+                id
+            }
+        };
+        // The pages are about to be written, cached stats and contents of
+        // them are stale.
+        if let Some(cache) = db.0.stats_cache() {
+            cache.invalidate(id, count);
+        }
+        if let Some(cache) = db.0.page_cache() {
+            cache.invalidate(id, count);
+        }
+        if let Some(cache) = db.0.decrypted() {
+            cache.invalidate(id, count);
+        }
+
+        // Allocate a temporary buffer for the page, recycled through the
+        // pool of the database.
+        let mut page = match &self.0.page_pool {
+            Some(pool) => pool.get(count),
+            None => OwnedPage::new(count * page_size),
+        };
+        page.set_id(id);
+        page.set_overflow(count as u32 - 1);
+        let page = self.0.arena.alloc_page(page);
+        self.0.pages.write().unwrap().insert(id, page);
+
+        // Update statistics.
+        let mut stats = self.stats_mut();
+        stats.page_count += 1;
+        stats.page_alloc += (count * page_size) as i64;
+
+        Ok(id)
+    }
+
+    /// stream_value allocates the pages of a leaf holding only key, with a
+    /// value made of header and the next len bytes of reader, and reads the
+    /// value into them. It returns the pages and the value, which is not
+    /// copied: spill writes the leaf of the key to the pages if the value is
+    /// still the only one in it, see Node::streamed_split, and they are
+    /// freed otherwise.
+    /// Returns an io error if reader fails or ends before len bytes.
+    pub(crate) fn stream_value<R: Read>(
+        &self,
+        key: &[u8],
+        header: &[u8],
+        mut reader: R,
+        len: usize,
+    ) -> Result<(PgId, Value)> {
+        let start = PAGE_HEADER_SIZE + LEAF_PAGE_ELEMENT_SIZE + key.len();
+        let end = start + header.len() + len;
+        let count = (end + self.0.crypt.overhead()).div_ceil(self.0.page_size);
+        let id = self.allocate(count)?;
+        let page = self.0.pages.read().unwrap()[&id];
+
+        // Nothing refers to the pages yet.
+        let buf = unsafe { page.get_mut() }.buf_mut();
+        buf[start..start + header.len()].copy_from_slice(header);
+        if let Err(err) = reader
+            .read_exact(&mut buf[start + header.len()..end])
+            .op(IoOp::Read)
+        {
+            self.free_dirty(&self.db()?, id)?;
+            return Err(err);
+        }
+        self.0.streamed.lock().unwrap().push(id);
+        Ok((id, page.slice(start..end)))
+    }
+
+    /// adopt_streamed returns whether a node of count pages can be written
+    /// to the pages id a value was streamed to, and hands them over to it if
+    /// so.
+    pub(crate) fn adopt_streamed(&self, id: PgId, count: usize) -> bool {
+        let mut streamed = self.0.streamed.lock().unwrap();
+        let Some(index) = streamed.iter().position(|&streamed| streamed == id) else {
+            return false;
+        };
+        let overflow = self.0.pages.read().unwrap()[&id].overflow();
+        if overflow as usize + 1 != count {
+            return false;
+        }
+        streamed.swap_remove(index);
+        true
+    }
+
+    // release_streamed frees the pages of the streamed values no node was
+    // written to, because they were overwritten or deleted since.
+    fn release_streamed(&self, db: &DB) -> Result<()> {
+        let streamed = std::mem::take(&mut *self.0.streamed.lock().unwrap());
+        for id in streamed {
+            self.free_dirty(db, id)?;
+        }
+        Ok(())
+    }
+
+    // free_dirty frees dirty pages, which are not written then.
+    fn free_dirty(&self, db: &DB, id: PgId) -> Result<()> {
+        if let Some(page) = self.0.pages.write().unwrap().remove(&id) {
+            db.0.freelist_mut()?
+                .free(self.id(), Page::from_slice(page.buf()));
+        }
+        Ok(())
+    }
+
+    /// write_page runs `f` against a dirty page allocated by this transaction.
+    pub(crate) fn write_page<F>(&self, id: PgId, f: F)
+    where
+        F: FnOnce(&mut OwnedPage),
+    {
+        let pages = self.0.pages.read().unwrap();
+        let page = pages.get(&id).expect("write to a page that is not dirty");
+        // Dirty pages are only written to while spilling, never while read.
+        f(unsafe { page.get_mut() });
+    }
+
+    /// write writes any dirty pages to disk.
+    fn write(&self, db: &DB) -> Result<()> {
+        let page_size = db.0.page_size();
+
+        // Sort pages by id.
+        let mut pages: Vec<(PgId, ArenaRef<OwnedPage>)> =
+            self.0.pages.write().unwrap().drain().collect();
+        pages.sort_unstable_by_key(|(id, _)| *id);
+
+        // Write pages to disk in order. Encrypted pages are sealed in a copy,
+        // reused from one page to the next.
+        let mut sealed = Vec::new();
+        for (id, page) in &pages {
+            let size = (page.overflow() as usize + 1) * page_size;
+            let buf = &page.buf()[..size];
+            if let Some(limiter) = db.0.write_limiter() {
+                self.stats_mut().throttle_time += limiter.wait(size);
+            }
+            if self.0.crypt.is_enabled() {
+                sealed.clear();
+                sealed.extend_from_slice(buf);
+                self.0.crypt.seal(&mut sealed)?;
+                db.0.write_all_at(&sealed, *id * page_size as u64)
+            } else {
+                db.0.write_all_at(buf, *id * page_size as u64)
+            }
+            .map_err(|err| err.with_pgid(*id))?;
+        }
+
+        // Ignore file sync if flag is set on DB.
+        if !db.0.no_sync() || IGNORE_NO_SYNC {
+            let start = Stopwatch::start();
+            db.0.sync()?;
+            self.stats_mut().sync_time += start.elapsed();
+        }
+
+        // Remember which pages this transaction wrote, for incremental
+        // backups, once they are on disk.
+        let txid = self.meta().txid();
+        let mut written = db.0.written.lock().unwrap();
+        for (id, page) in &pages {
+            written.record(*id, page.overflow(), txid);
+        }
+        drop(written);
+
+        self.stats_mut().write += pages.len() as i64;
+        Ok(())
+    }
+
+    /// write_meta writes the meta to the disk.
+    fn write_meta(&self, db: &DB) -> Result<()> {
+        let page_size = db.0.page_size();
+
+        // Create a temporary buffer for the meta page.
+        let mut page = OwnedPage::new(page_size);
+        self.0.meta.write().unwrap().write(&mut page)?;
+        self.0.crypt.write_verifier(page.buf_mut())?;
+
+        // Write the meta page to file. Transactions beginning meanwhile would
+        // read it half written and fall back to the previous one.
+        let metalock = db.0.lock_meta();
+        db.0.write_all_at(page.buf(), page.id() * page_size as u64)
+            .map_err(|err| err.with_pgid(page.id()))?;
+        drop(metalock);
+        // With group commit the meta page is synced once the writer lock is
+        // released, see commit.
+        if let Some(group) = db.0.group_commit() {
+            group.written(self.id());
+        } else if !db.0.no_sync() || IGNORE_NO_SYNC {
+            let start = Stopwatch::start();
+            db.0.sync_vfs()?;
+            self.stats_mut().sync_time += start.elapsed();
+        }
+
+        // Update statistics.
+        self.stats_mut().write += 1;
+        Ok(())
+    }
+
+    /// data returns the raw bytes of the file between two offsets, as of the
+    /// transaction. Pages of an encrypted database are returned encrypted.
+    pub(crate) fn data(&self, start: usize, end: usize) -> Result<Bytes> {
+        if let Some(cache) = &self.0.page_cache {
+            return cache.read_at(start, end);
+        }
+        if start > end || end > self.0.data.len() {
+            return Err(BoltError::Unexpected("read beyond the mmap"));
+        }
+        Ok(self.0.data.slice(start..end))
+    }
+
+    /// readahead asks the OS to read n pages from id on in the background.
+    /// Pages beyond the mmap are skipped.
+    pub(crate) fn readahead(&self, id: PgId, n: usize) {
+        let page_size = self.0.page_size as u64;
+        let len = self.0.data.len() as u64;
+        let start = id.saturating_mul(page_size).min(len);
+        let end = id
+            .saturating_add(n as u64)
+            .saturating_mul(page_size)
+            .min(len);
+        os::willneed(&self.0.data[start as usize..end as usize]);
+        self.stats_mut().readahead += 1;
+    }
+
+    /// arena returns the arena that owns the nodes and dirty pages of the transaction.
+    pub(crate) fn arena(&self) -> &TxArena {
+        &self.0.arena
+    }
+
+    /// page returns the page with a given id.
+    /// If page has been written to then a temporary buffered page is returned.
+    /// A page read without mmap is kept only as long as the TxPage.
+    /// Returns BoltError::Corrupted if the page lies outside the mmap or is not
+    /// the page it should be, and BoltError::Encryption if it cannot be decrypted.
+    pub(crate) fn page(&self, id: PgId) -> Result<TxPage<'_>> {
+        // Check the dirty pages first.
+        if let Some(page) = self.0.pages.read().unwrap().get(&id) {
+            self.stats_mut().dirty_page_hit += 1;
+            // The page buffer is owned by the arena and lives as long as the transaction.
+            let page = unsafe { &*(Page::from_slice(page.buf()) as *const Page) };
+            return Ok(TxPage::Dirty(page));
+        }
 
-    pub(crate) fn from(tx: &Tx) -> Self {
-        Self(Arc::downgrade(&tx.0))
+        let page = TxPage::Read(self.page_bytes(id)?);
+        page.fast_check(id)?;
+        Ok(page)
+    }
+
+    /// page_bytes returns a shared buffer holding the page with a given id.
+    /// Pages from the mmap are not copied, so slices of the buffer can be
+    /// handed out for as long as they are needed. Pages of an encrypted
+    /// database are decrypted into a buffer of their own.
+    /// Returns BoltError::Corrupted if the page does not lie within the mmap.
+    pub(crate) fn page_bytes(&self, id: PgId) -> Result<Bytes> {
+        // Dirty pages are still being written to, so they are copied.
+        if let Some(page) = self.0.pages.read().unwrap().get(&id) {
+            self.stats_mut().dirty_page_hit += 1;
+            return Ok(OwnedPage::from_slice(page.buf()).into_bytes());
+        }
+
+        if let Some(cache) = &self.0.page_cache {
+            return self.cached_page_bytes(cache, id);
+        }
+
+        let page_size = self.0.page_size as u64;
+        let pos = id * page_size;
+        let overflow = self.page_overflow(id)?;
+        let end = pos + (overflow as u64 + 1) * page_size;
+        if end > self.0.data.len() as u64 {
+            return Err(outside_mmap(id));
+        }
+        {
+            let mut stats = self.stats_mut();
+            stats.page_read += 1;
+            stats.overflow_page_read += overflow as i64;
+        }
+        let buf = self.0.data.slice(pos as usize..end as usize);
+        if !self.0.crypt.is_enabled() {
+            return Ok(buf);
+        }
+
+        let cache = self.0.decrypted.as_ref();
+        if let Some(page) = cache.and_then(|cache| cache.get(id)) {
+            self.stats_mut().decrypted_page_hit += 1;
+            return Ok(page);
+        }
+        let page = self.0.crypt.open(buf)?;
+        if let Some(cache) = cache {
+            cache.insert(id, page.clone(), overflow as usize + 1);
+        }
+        Ok(page)
+    }
+
+    // cached_page_bytes is page_bytes for a database read without mmap. The
+    // page comes from the page cache or the file.
+    fn cached_page_bytes(&self, cache: &PageCache, id: PgId) -> Result<Bytes> {
+        let high = self.0.meta.read().unwrap().pgid();
+        let (page, hit) = cache.read(id, high)?;
+        let mut stats = self.stats_mut();
+        stats.page_read += 1;
+        stats.overflow_page_read += Page::from_slice(&page).overflow() as i64;
+        match hit {
+            true => stats.page_cache_hit += 1,
+            false => stats.page_cache_miss += 1,
+        }
+        Ok(page)
+    }
+
+    /// page_overflow returns the number of overflow pages of the page with a
+    /// given id. Only the page header is read, so the length of the page can
+    /// be checked before the rest of it is touched.
+    /// Returns BoltError::Corrupted if the header does not lie within the mmap.
+    pub(crate) fn page_overflow(&self, id: PgId) -> Result<u32> {
+        if let Some(page) = self.0.pages.read().unwrap().get(&id) {
+            return Ok(Page::from_slice(page.buf()).overflow());
+        }
+        if let Some(cache) = &self.0.page_cache {
+            return cache.overflow(id);
+        }
+
+        let pos = id
+            .checked_mul(self.0.page_size as u64)
+            .ok_or_else(|| outside_mmap(id))?;
+        let header = self.0.data.get(pos as usize..).unwrap_or_default();
+        Page::try_from_slice(header)
+            .map(|page| page.overflow())
+            .map_err(|_| outside_mmap(id))
+    }
+
+    /// for_each_page_in iterates over every page within a given page and executes a function.
+    pub(crate) fn for_each_page_in<F>(&self, pgid: PgId, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&Page, usize, &[PgId]),
+    {
+        let mut stack = Vec::with_capacity(10);
+        stack.push(pgid);
+        self.for_each_page_internal(&mut stack, f)
+    }
+
+    fn for_each_page_internal<F>(&self, stack: &mut Vec<PgId>, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&Page, usize, &[PgId]),
+    {
+        let page = self.page(*stack.last().unwrap())?;
+
+        // Execute function.
+        f(&page, stack.len() - 1, stack);
+
+        // Recursively loop over children.
+        if page.is_branch_page() {
+            for elem in page.branch_page_elements() {
+                stack.push(elem.pgid());
+                self.for_each_page_internal(stack, f)?;
+                stack.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// for_each_bucket_page walks every page reachable from the root bucket,
+    /// descending into nested buckets that own pages. Inline buckets are skipped.
+    pub(crate) fn for_each_bucket_page<F>(&self, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&Page),
+    {
+        let root = self.meta().root_bucket().root_page();
+        self.for_each_bucket_page_internal(root, f)
+    }
+
+    fn for_each_bucket_page_internal<F>(&self, root: PgId, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&Page),
+    {
+        let mut children = Vec::new();
+        self.for_each_page_in(root, &mut |page, _, _| {
+            f(page);
+            if page.is_leaf_page() {
+                children.extend(
+                    page.leaf_page_elements()
+                        .iter()
+                        .filter_map(|elem| elem.bucket())
+                        .map(|bucket| bucket.root_page())
+                        .filter(|&root| root != 0),
+                );
+            }
+        })?;
+
+        for child in children {
+            self.for_each_bucket_page_internal(child, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl RawTx {
+    /// non_physical_rollback is called when the user rolls back directly, in
+    /// this case we do not need to reload the free pages from disk.
+    fn non_physical_rollback(&self) {
+        let db = match self.db.read().unwrap().upgrade() {
+            Some(db) => db,
+            None => return,
+        };
+        if self.writable.load(Ordering::Acquire) {
+            if let Ok(mut freelist) = db.0.freelist_mut() {
+                freelist.rollback(self.meta.read().unwrap().txid());
+            }
+        }
+        self.close();
+    }
+
+    /// rollback needs to reload the free pages from disk in case some system
+    /// error happens like fsync error.
+    fn rollback(&self) {
+        let db = match self.db.read().unwrap().upgrade() {
+            Some(db) => db,
+            None => return,
+        };
+        if self.writable.load(Ordering::Acquire) {
+            if let Ok(mut freelist) = db.0.freelist_mut() {
+                freelist.rollback(self.meta.read().unwrap().txid());
+            }
+            // When the mmap is already gone there is no way to reload the free
+            // pages; the next open rebuilds them anyway.
+            let _ = db.reload_freelist();
+        }
+        self.close();
+    }
+
+    /// close releases the transaction from the database and merges its statistics.
+    fn close(&self) {
+        let db = std::mem::replace(&mut *self.db.write().unwrap(), WeakDB::new());
+        let db = match db.upgrade() {
+            Some(db) => db,
+            None => return,
+        };
+
+        // The pages of the transaction may be reused once it is gone.
+        db.0.borrows().close_tx(self as *const RawTx as usize);
+
+        let stats = self.stats.lock().unwrap().clone();
+        if self.writable.load(Ordering::Acquire) {
+            // Merge statistics.
+            db.0.stats_mut().tx_stats.add(&stats);
+            db.0.update_freelist_stats();
+
+            // Remove writer lock.
+            db.0.unlock_writer();
+        } else {
+            db.0.remove_tx(self.meta.read().unwrap().txid(), &stats);
+        }
+    }
+}
+
+impl Drop for RawTx {
+    fn drop(&mut self) {
+        // A transaction that goes out of scope without being closed is rolled back.
+        self.non_physical_rollback();
+
+        // Nothing reads the dirty pages anymore, hand their buffers back.
+        if let Some(pool) = &self.page_pool {
+            self.pages.get_mut().unwrap().clear();
+            pool.put(self.arena.take_pages());
+        }
     }
 }
-#[derive(Debug)]
+
+/// TxPage is a page returned by Tx::page, either a dirty page of the
+/// transaction or a buffer holding a page read from the file.
+pub(crate) enum TxPage<'tx> {
+    Dirty(&'tx Page),
+    Read(Bytes),
+}
+
+impl Deref for TxPage<'_> {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        match self {
+            TxPage::Dirty(page) => page,
+            TxPage::Read(buf) => Page::from_slice(buf),
+        }
+    }
+}
+
+/// TxStats represents statistics about the actions performed by the transaction.
+#[derive(Debug, Default, Clone)]
 pub struct TxStats {
     // Page statistics.
     // #[deprecated(since = "future version", note = "Use GetPageCount() or IncPageCount() instead")]
@@ -77,6 +1061,16 @@ pub struct TxStats {
     // #[deprecated(since = "future version", note = "Use GetCursorCount() or IncCursorCount() instead")]
     pub cursor_count: i64, // number of cursors created
 
+    pub readahead: i64, // number of readaheads issued by sequential scans
+
+    // Page access statistics.
+    pub page_read: i64, // number of committed pages looked up, from the mmap or decrypted
+    pub overflow_page_read: i64, // number of overflow pages spanned by the pages looked up
+    pub dirty_page_hit: i64, // number of lookups served by the dirty pages of a write transaction
+    pub decrypted_page_hit: i64, // number of page_read served by pages decrypted before
+    pub page_cache_hit: i64, // number of page_read served by the page cache, without mmap
+    pub page_cache_miss: i64, // number of page_read read from the file, without mmap
+
     // Node statistics
     // #[deprecated(since = "future version", note = "Use GetNodeCount() or IncNodeCount() instead")]
     pub node_count: i64, // number of node allocations
@@ -89,7 +1083,7 @@ pub struct TxStats {
     pub rebalance: i64, // number of node rebalances
 
     // #[deprecated(since = "future version", note = "Use GetRebalanceTime() or IncRebalanceTime() instead")]
-    pub rebalance_time: std::time::Duration, // total time spent rebalancing
+    pub rebalance_time: Duration, // total time spent rebalancing
 
     // Split/Spill statistics.
     // #[deprecated(since = "future version", note = "Use GetSplit() or IncSplit() instead")]
@@ -99,12 +1093,464 @@ pub struct TxStats {
     pub spill: i64, // number of nodes spilled
 
     // #[deprecated(since = "future version", note = "Use GetSpillTime() or IncSpillTime() instead")]
-    pub spill_time: std::time::Duration, // total time spent spilling
+    pub spill_time: Duration, // total time spent spilling
 
     // Write statistics.
     // #[deprecated(since = "future version", note = "Use GetWrite() or IncWrite() instead")]
     pub write: i64, // number of writes performed
 
     // #[deprecated(since = "future version", note = "Use GetWriteTime() or IncWriteTime() instead")]
-    pub write_time: std::time::Duration, // total time spent writing to disk
+    pub write_time: Duration, // total time spent writing to disk
+
+    pub sync_time: Duration, // total time spent syncing, part of write_time
+
+    pub throttle_time: Duration, // total time writes waited for Options.write_rate_limit, part of write_time
+}
+
+impl TxStats {
+    /// add merges the counters of another TxStats into this one.
+    pub fn add(&mut self, other: &TxStats) {
+        self.page_count += other.page_count;
+        self.page_alloc += other.page_alloc;
+        self.cursor_count += other.cursor_count;
+        self.readahead += other.readahead;
+        self.page_read += other.page_read;
+        self.overflow_page_read += other.overflow_page_read;
+        self.dirty_page_hit += other.dirty_page_hit;
+        self.decrypted_page_hit += other.decrypted_page_hit;
+        self.page_cache_hit += other.page_cache_hit;
+        self.page_cache_miss += other.page_cache_miss;
+        self.node_count += other.node_count;
+        self.node_deref += other.node_deref;
+        self.rebalance += other.rebalance;
+        self.rebalance_time += other.rebalance_time;
+        self.split += other.split;
+        self.spill += other.spill;
+        self.spill_time += other.spill_time;
+        self.write += other.write;
+        self.write_time += other.write_time;
+        self.sync_time += other.sync_time;
+        self.throttle_time += other.throttle_time;
+    }
+}
+
+// check_name returns BoltError::IncompatibleValue if name is reserved for a
+// hidden bucket.
+fn check_name(name: &[u8]) -> Result<()> {
+    match bucket::is_hidden(name) {
+        true => Err(BoltError::IncompatibleValue),
+        false => Ok(()),
+    }
+}
+
+// outside_mmap returns the error for a page that does not lie within the mmap.
+fn outside_mmap(id: PgId) -> BoltError {
+    BoltError::corrupted("page lies beyond the end of the mmap").with_pgid(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    fn open() -> (tempfile::TempDir, DB) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        (dir, db)
+    }
+
+    // Ensure that committing a read-only transaction returns an error.
+    #[test]
+    fn test_tx_commit_err_tx_not_writable() {
+        let (_dir, db) = open();
+        let tx = db.begin_ro().unwrap();
+        assert_eq!(tx.commit().err(), Some(BoltError::TxNotWritable));
+        // The consumed transaction was rolled back.
+        assert_eq!(db.stats().open_tx_n, 0);
+    }
+
+    // Ensure that commit callbacks see the committed data, cannot write and
+    // can veto the commit, which then leaves the database unchanged.
+    #[test]
+    fn test_tx_before_commit() {
+        let (_dir, db) = open();
+        db.update(|tx| {
+            tx.create_bucket(b"widgets")?.put(b"foo", b"bar")?;
+            tx.before_commit(|tx| {
+                let b = tx.bucket(b"widgets").unwrap();
+                assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+                assert_eq!(b.put(b"baz", b"bat"), Err(BoltError::TxNotWritable));
+                Ok(())
+            });
+            Ok(())
+        })
+        .unwrap();
+
+        let result = db.update(|tx| {
+            tx.bucket(b"widgets").unwrap().put(b"foo", b"")?;
+            tx.before_commit(|tx| match tx.bucket(b"widgets").unwrap().get(b"foo") {
+                Some([]) => Err(BoltError::ValueMismatch),
+                _ => Ok(()),
+            });
+            Ok(())
+        });
+        assert_eq!(result, Err(BoltError::ValueMismatch));
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            assert_eq!(b.get(b"baz"), None);
+            Ok(())
+        })
+        .unwrap();
+
+        // The vetoed transaction released the writer lock.
+        let tx = db.begin_rw().unwrap();
+        assert_eq!(tx.id(), 3);
+        tx.rollback().unwrap();
+    }
+
+    // Ensure that a dropped writable transaction releases the writer lock.
+    #[test]
+    fn test_tx_drop_releases_writer() {
+        let (_dir, db) = open();
+        drop(db.begin_rw().unwrap());
+        let tx = db.begin_rw().unwrap();
+        assert_eq!(tx.id(), 2);
+        tx.rollback().unwrap();
+    }
+
+    // Ensure that write transactions recycle the buffers of their dirty pages.
+    #[test]
+    fn test_tx_recycles_page_buffers() {
+        let (_dir, db) = open();
+        let pool = db.0.page_pool().clone();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let pooled = pool.len();
+        assert!(pooled > 10, "{} buffers pooled", pooled);
+
+        // The next commit reuses them, a rollback hands them back as well.
+        db.update(|tx| tx.bucket(b"widgets").unwrap().put(b"00000000", b"foo"))
+            .unwrap();
+        assert_eq!(pool.len(), pooled);
+        let tx = db.begin_rw().unwrap();
+        tx.bucket(b"widgets").unwrap().delete(b"00000001").unwrap();
+        tx.rollback().unwrap();
+        assert_eq!(pool.len(), pooled);
+
+        db.view(|tx| {
+            assert_eq!(
+                tx.bucket(b"widgets").unwrap().get(b"00000000").unwrap(),
+                b"foo"
+            );
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that page lookups are counted, with their overflow pages.
+    #[test]
+    fn test_tx_page_access_stats() {
+        let (_dir, db) = open();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.put(b"large", &[0x5a; 10000])
+        })
+        .unwrap();
+        let page_size = db.page_size();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let leaves = b.stats()?.leaf_page_n as i64;
+            let before = tx.stats();
+            assert!(before.page_read >= leaves);
+            b.for_each(|_, _| Ok(()))?;
+            let stats = tx.stats();
+            assert!(stats.page_read - before.page_read >= leaves);
+            assert_eq!(
+                stats.overflow_page_read - before.overflow_page_read,
+                (10000 / page_size) as i64
+            );
+            assert_eq!((stats.dirty_page_hit, stats.decrypted_page_hit), (0, 0));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a transaction reading without mmap only keeps the pages
+    // it handed out values of, once each.
+    #[test]
+    fn test_tx_retains_handed_out_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            page_cache_size: Some(16),
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..2000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            let leaves = b.stats()?.leaf_page_n;
+            assert!(leaves > 16);
+            let mut n = 0;
+            b.for_each(|_, _| {
+                n += 1;
+                Ok(())
+            })?;
+            assert_eq!(n, 2000);
+            assert_eq!(tx.0.retained.lock().unwrap().len(), 0);
+
+            let value = b.get(b"00000000").unwrap();
+            assert_eq!(tx.0.retained.lock().unwrap().len(), 1);
+            let mut c = b.cursor();
+            let mut item = c.first();
+            while let Some((_, v)) = item {
+                assert_eq!(v, Some(&[0x5a; 100][..]));
+                item = c.next();
+            }
+            // The value found by get lies in the first leaf.
+            assert_eq!(tx.0.retained.lock().unwrap().len(), leaves);
+            assert_eq!(value, &[0x5a; 100][..]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a commit writes a new meta page and freelist.
+    #[test]
+    fn test_tx_commit_writes_meta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|_| Ok(())).unwrap();
+
+        let meta = db.0.meta().unwrap();
+        assert_eq!(meta.txid(), 2);
+        assert_eq!(meta.freelist(), 4);
+        assert_eq!(meta.pgid(), 5);
+        assert_eq!(db.stats().tx_stats.write, 2);
+        db.close().unwrap();
+        drop(db);
+
+        // The old freelist page is free once the file is reopened.
+        let db = DB::open(&path, Options::default()).unwrap();
+        assert_eq!(db.0.meta().unwrap().txid(), 2);
+        assert_eq!(db.freelist_report().unwrap().free_count, 1);
+        assert!(db.0.freelist().unwrap().freed(2));
+    }
+
+    // Ensure that read-only databases refuse writable transactions.
+    #[test]
+    fn test_tx_read_only_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        DB::open(&path, Options::default())
+            .unwrap()
+            .close()
+            .unwrap();
+
+        let db = DB::open(
+            &path,
+            Options {
+                read_only: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            db.update(|_| Ok(())).err(),
+            Some(BoltError::DatabaseReadOnly)
+        );
+    }
+
+    // Ensure that a corrupted page is reported as an error instead of a panic.
+    #[test]
+    fn test_tx_corrupted_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        let root = db
+            .update(|tx| {
+                let b = tx.create_bucket(b"widgets")?;
+                for i in 0..1000u32 {
+                    b.put(&i.to_be_bytes(), &[0; 64])?;
+                }
+                Ok(())
+            })
+            .and_then(|_| db.view(|tx| Ok(tx.bucket(b"widgets").unwrap().root())))
+            .unwrap();
+        let page_size = db.0.page_size();
+        db.close().unwrap();
+
+        // Clear the flags of the root page of the bucket.
+        let mut buf = std::fs::read(&path).unwrap();
+        let pos = root as usize * page_size + 8;
+        buf[pos..pos + 2].copy_from_slice(&[0, 0]);
+        std::fs::write(&path, &buf).unwrap();
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.view(|tx| {
+            let err = tx.page_info(root).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "corrupted database at page {}: unexpected type/flags: 0",
+                    root
+                )
+            );
+            assert_eq!(err.context().unwrap().pgid, Some(root));
+            let b = tx.bucket(b"widgets").unwrap();
+            assert!(matches!(b.stats(), Err(BoltError::Corrupted { .. })));
+            assert_eq!(b.get(&0u32.to_be_bytes()), None);
+            assert!(matches!(
+                b.contains_key(&0u32.to_be_bytes()),
+                Err(BoltError::Corrupted { .. })
+            ));
+            assert!(matches!(
+                b.get_many(&[0u32.to_be_bytes()]),
+                Err(BoltError::Corrupted { .. })
+            ));
+            assert!(matches!(
+                b.for_each(|_, _| Ok(())),
+                Err(BoltError::Corrupted { .. })
+            ));
+            let mut c = b.cursor();
+            assert_eq!(c.first(), None);
+            assert!(matches!(c.err(), Some(BoltError::Corrupted { .. })));
+            Ok(())
+        })
+        .unwrap();
+
+        // Writes report the bucket and the key they failed at.
+        let err = db
+            .update(|tx| tx.bucket(b"widgets").unwrap().put(b"foo", b"bar"))
+            .unwrap_err();
+        let context = err.context().unwrap();
+        assert_eq!(context.pgid, Some(root));
+        assert_eq!(context.bucket, Some(vec![b"widgets".to_vec()]));
+        assert_eq!(context.key, Some(b"foo".to_vec()));
+        assert!(err.to_string().starts_with(&format!(
+            "corrupted database at page {} in bucket \"widgets\" for key \"foo\": ",
+            root
+        )));
+    }
+
+    // Ensure that the handles meant to be shared across threads can be.
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DB>();
+        assert_send_sync::<Options>();
+        assert_send_sync::<TxStats>();
+        assert_send_sync::<BoltError>();
+    }
+
+    // Ensure that readers on other threads always see a consistent snapshot
+    // while a writer moves values between keys.
+    #[test]
+    fn test_tx_concurrent_readers() {
+        const KEYS: u64 = 16;
+        const TOTAL: u64 = KEYS * 100;
+        let (_dir, db) = open();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"accounts")?;
+            for i in 0..KEYS {
+                b.put(&i.to_be_bytes(), &100u64.to_be_bytes())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let get = |b: &Bucket, key: u64| -> u64 {
+            u64::from_be_bytes(b.get(&key.to_be_bytes()).unwrap().try_into().unwrap())
+        };
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Acquire) {
+                        let sum = db
+                            .view(|tx| {
+                                let b = tx.bucket(b"accounts").unwrap();
+                                Ok((0..KEYS).map(|i| get(&b, i)).sum::<u64>())
+                            })
+                            .unwrap();
+                        assert_eq!(sum, TOTAL);
+                    }
+                });
+            }
+            for i in 0..200 {
+                db.update(|tx| {
+                    let b = tx.bucket(b"accounts").unwrap();
+                    let (from, to) = (i % KEYS, (i * 7 + 3) % KEYS);
+                    if from != to && get(&b, from) > 0 {
+                        b.put(&from.to_be_bytes(), &(get(&b, from) - 1).to_be_bytes())?;
+                        b.put(&to.to_be_bytes(), &(get(&b, to) + 1).to_be_bytes())?;
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            }
+            done.store(true, Ordering::Release);
+        });
+        db.close().unwrap();
+    }
+
+    // Ensure that read transactions on different threads are open at the same
+    // time, next to an uncommitted write transaction they do not see.
+    #[test]
+    fn test_tx_readers_run_in_parallel() {
+        const READERS: usize = 4;
+        let (_dir, db) = open();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+
+        let writer = db.begin_rw().unwrap();
+        writer
+            .bucket(b"widgets")
+            .unwrap()
+            .put(b"foo", b"baz")
+            .unwrap();
+
+        // Every reader waits inside its transaction for all the others.
+        let barrier = std::sync::Barrier::new(READERS);
+        std::thread::scope(|scope| {
+            for _ in 0..READERS {
+                scope.spawn(|| {
+                    db.view(|tx| {
+                        barrier.wait();
+                        assert_eq!(tx.bucket(b"widgets").unwrap().get(b"foo").unwrap(), b"bar");
+                        Ok(())
+                    })
+                    .unwrap();
+                });
+            }
+        });
+        assert_eq!(db.stats().tx_n, READERS);
+
+        writer.commit().unwrap();
+        db.view(|tx| {
+            assert_eq!(tx.bucket(b"widgets").unwrap().get(b"foo").unwrap(), b"baz");
+            Ok(())
+        })
+        .unwrap();
+    }
 }
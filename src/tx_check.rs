@@ -0,0 +1,570 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+
+use crate::common::bucket::InBucket;
+use crate::common::page::{Page, PgId};
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::tx::Tx;
+
+/// CheckReport lists the inconsistencies found by Tx::check.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// Errors are the inconsistencies, in the order they were found.
+    pub errors: Vec<CheckError>,
+}
+
+impl CheckReport {
+    /// is_ok returns whether no inconsistency was found.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// CheckError describes a single inconsistency found by Tx::check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckError {
+    /// Pgid is the page the inconsistency was found on.
+    pub pgid: PgId,
+    /// Bucket is the path of names from the root to the bucket the page
+    /// belongs to. It is empty for pages of the root bucket and for pages
+    /// that belong to no bucket, such as free or orphan pages.
+    pub bucket: Vec<Vec<u8>>,
+    /// Kind tells what is wrong with the page.
+    pub kind: CheckErrorKind,
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {}: {}", self.pgid, self.kind)?;
+        if !self.bucket.is_empty() {
+            let path: Vec<_> = self
+                .bucket
+                .iter()
+                .map(|name| String::from_utf8_lossy(name))
+                .collect();
+            write!(f, " (bucket: {})", path.join("/"))?;
+        }
+        Ok(())
+    }
+}
+
+/// CheckErrorKind is the kind of an inconsistency found by Tx::check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckErrorKind {
+    /// The page is referenced more than once.
+    DoubleReference,
+    /// The page is reachable, but free.
+    ReachableFreed,
+    /// The page is in the freelist more than once.
+    DoubleFree,
+    /// The page is neither reachable nor free.
+    Orphan,
+    /// The page, or one of its overflow pages, lies at or above the high
+    /// water mark.
+    OutOfBounds {
+        /// high water mark of the transaction
+        high: PgId,
+    },
+    /// A key of the page is not below the key after it, or outside of the
+    /// range the keys of its ancestors allow.
+    OutOfOrder {
+        /// key out of order
+        key: Vec<u8>,
+    },
+    /// The page is not of the type expected.
+    InvalidType {
+        /// type the page has
+        typ: String,
+    },
+    /// The page cannot be read or parsed.
+    Malformed {
+        /// what is wrong with the page
+        reason: String,
+    },
+}
+
+impl fmt::Display for CheckErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DoubleReference => write!(f, "multiple references"),
+            Self::ReachableFreed => write!(f, "reachable freed"),
+            Self::DoubleFree => write!(f, "already freed"),
+            Self::Orphan => write!(f, "unreachable unfreed"),
+            Self::OutOfBounds { high } => write!(f, "out of bounds: {}", high),
+            Self::OutOfOrder { key } => write!(f, "key {:?} out of order", key),
+            Self::InvalidType { typ } => write!(f, "invalid type: {}", typ),
+            Self::Malformed { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Tx {
+    /// check performs several consistency checks on the database for this transaction.
+    /// The report lists no error if no inconsistencies are found.
+    ///
+    /// It checks that every page below the high water mark is either reachable
+    /// from the root bucket or free, that no page is referenced twice or both
+    /// reachable and free, and that keys are sorted within and across pages.
+    /// Corrupt pages are reported instead of being followed.
+    pub fn check(&self) -> Result<CheckReport> {
+        // Force loading free list if opened in ReadOnly mode.
+        let db = self.db()?;
+        db.load_freelist()?;
+
+        let meta = self.meta();
+        let mut checker = Checker {
+            tx: self,
+            high: meta.pgid(),
+            freed: HashSet::new(),
+            reachable: HashSet::new(),
+            bucket: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        // Check for pages that appear in the freelist twice.
+        for id in db.0.freelist()?.copy_all() {
+            if !checker.freed.insert(id) {
+                checker.report(id, CheckErrorKind::DoubleFree);
+            }
+        }
+
+        // Track every reachable page.
+        checker.reachable.extend([0, 1]);
+        if meta.is_freelist_persisted() {
+            let freelist = meta.freelist();
+            let overflow = self
+                .page_bytes(freelist)
+                .map_or(0, |buf| Page::from_slice(&buf).overflow());
+            checker
+                .reachable
+                .extend((0..=overflow as PgId).map(|i| freelist + i));
+        }
+
+        // Recursively check buckets.
+        checker.check_bucket(meta.root_bucket().root_page(), &mut Vec::new());
+
+        // Ensure all pages below high water mark are either reachable or freed.
+        for id in 0..checker.high {
+            if !checker.reachable.contains(&id) && !checker.freed.contains(&id) {
+                checker.report(id, CheckErrorKind::Orphan);
+            }
+        }
+
+        for err in &checker.errors {
+            db.0.logger().warn(
+                "consistency check failed",
+                &[("page", &err.pgid), ("reason", &err.kind)],
+            );
+        }
+        db.0.record_check(self.id(), checker.errors.len());
+        Ok(CheckReport {
+            errors: checker.errors,
+        })
+    }
+
+    /// torn_pages returns the problems of the pages reachable from the meta
+    /// page of the transaction that are cut short by the end of the file or
+    /// malformed, as a write torn by a power loss leaves them. Unlike check,
+    /// free and unreachable pages are not looked at. file_pages is the number
+    /// of pages the file holds.
+    pub(crate) fn torn_pages(&self, file_pages: PgId) -> Vec<CheckError> {
+        let meta = self.meta();
+        let mut checker = Checker {
+            tx: self,
+            high: meta.pgid().min(file_pages),
+            freed: HashSet::new(),
+            reachable: HashSet::new(),
+            bucket: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        if meta.is_freelist_persisted() {
+            checker.check_freelist_page(meta.freelist());
+        }
+        checker.check_bucket(meta.root_bucket().root_page(), &mut Vec::new());
+        checker.errors
+    }
+}
+
+impl DB {
+    /// check_torn_writes fails with BoltError::TornWrite if a page reachable
+    /// from the meta page in use is torn, see Options.check_torn_writes.
+    pub(crate) fn check_torn_writes(&self) -> Result<()> {
+        let file_size = self.0.file_size()?;
+        let file_pages = (file_size / self.0.page_size()) as PgId;
+        let errors = self.view(|tx| Ok(tx.torn_pages(file_pages)))?;
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        for err in &errors {
+            self.0.logger().error(
+                "torn write detected",
+                &[("page", &err.pgid), ("reason", &err.kind)],
+            );
+        }
+        let mut pgids: Vec<PgId> = errors.iter().map(|err| err.pgid).collect();
+        pgids.sort_unstable();
+        pgids.dedup();
+        Err(BoltError::TornWrite(pgids))
+    }
+
+    /// check_strict checks the database after a commit in strict mode.
+    pub(crate) fn check_strict(&self) -> Result<()> {
+        let report = self.view(|tx| tx.check())?;
+        if report.is_ok() {
+            return Ok(());
+        }
+        let errors: Vec<String> = report.errors.iter().map(|err| err.to_string()).collect();
+        Err(BoltError::CheckFailed(format!(
+            "strict mode: {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+struct Checker<'a> {
+    tx: &'a Tx,
+    high: PgId,
+    freed: HashSet<PgId>,
+    reachable: HashSet<PgId>,
+    // Path of the bucket being checked.
+    bucket: Vec<Vec<u8>>,
+    errors: Vec<CheckError>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, pgid: PgId, kind: CheckErrorKind) {
+        self.errors.push(CheckError {
+            pgid,
+            bucket: self.bucket.clone(),
+            kind,
+        });
+    }
+
+    fn malformed(&mut self, pgid: PgId, reason: String) {
+        self.report(pgid, CheckErrorKind::Malformed { reason });
+    }
+
+    // check_bucket checks every page of the bucket rooted at root, at path,
+    // then the buckets nested in it. Inline buckets have no pages of their
+    // own.
+    fn check_bucket(&mut self, root: PgId, path: &mut Vec<Vec<u8>>) {
+        if root == 0 {
+            return;
+        }
+
+        let mut children = Vec::new();
+        self.bucket.clone_from(path);
+        self.check_page(root, None, None, &mut children);
+        self.bucket.clear();
+        for (name, child) in children {
+            path.push(name);
+            self.check_bucket(child, path);
+            path.pop();
+        }
+    }
+
+    // check_freelist_page checks that the freelist page lies below the high
+    // water mark and has a sane header.
+    fn check_freelist_page(&mut self, id: PgId) {
+        let out_of_bounds = CheckErrorKind::OutOfBounds { high: self.high };
+        if id >= self.high {
+            return self.report(id, out_of_bounds);
+        }
+        let overflow = match self.tx.page_overflow(id) {
+            Ok(overflow) => overflow as PgId,
+            Err(err) => return self.malformed(id, format!("unreadable: {}", err)),
+        };
+        if id + overflow >= self.high {
+            return self.report(id, out_of_bounds);
+        }
+        match self.tx.page(id) {
+            Ok(page) if page.is_freelist_page() => {}
+            Ok(page) => self.report(id, CheckErrorKind::InvalidType { typ: page.typ() }),
+            Err(err) => self.malformed(id, format!("unreadable: {}", err)),
+        }
+    }
+
+    // check_page checks a page and its descendants. Every key reachable from
+    // the page must be >= min_key and < max_key, which come from its ancestors.
+    // The root pages of nested buckets are collected in children.
+    fn check_page(
+        &mut self,
+        id: PgId,
+        min_key: Option<&[u8]>,
+        max_key: Option<&[u8]>,
+        children: &mut Vec<(Vec<u8>, PgId)>,
+    ) {
+        // Ensure the page ends below the high water mark before reading it.
+        let out_of_bounds = CheckErrorKind::OutOfBounds { high: self.high };
+        if id >= self.high {
+            return self.report(id, out_of_bounds);
+        }
+        let overflow = match self.tx.page_overflow(id) {
+            Ok(overflow) => overflow as PgId,
+            Err(err) => return self.malformed(id, format!("unreadable: {}", err)),
+        };
+        if id + overflow >= self.high {
+            return self.report(id, out_of_bounds);
+        }
+        let buf = match self.tx.page_bytes(id) {
+            Ok(buf) => buf,
+            Err(err) => return self.malformed(id, format!("unreadable: {}", err)),
+        };
+        let page = Page::from_slice(&buf);
+
+        // Ensure each page is only referenced once.
+        for i in 0..=page.overflow() as PgId {
+            if !self.reachable.insert(id + i) {
+                self.report(id + i, CheckErrorKind::DoubleReference);
+            }
+        }
+
+        // We should only encounter un-freed leaf and branch pages.
+        if self.freed.contains(&id) {
+            return self.report(id, CheckErrorKind::ReachableFreed);
+        }
+        if !page.is_branch_page() && !page.is_leaf_page() {
+            return self.report(id, CheckErrorKind::InvalidType { typ: page.typ() });
+        }
+        if page.id() != id {
+            return self.malformed(id, format!("header identifies as page {}", page.id()));
+        }
+        if page.try_validate(buf.len()).is_err() {
+            return self.malformed(id, "elements overflow the page".to_string());
+        }
+
+        // Keys of prefix pages are put back together.
+        let owned: Vec<Cow<[u8]>> = if page.is_branch_page() {
+            (0..page.count() as usize)
+                .map(|i| page.branch_key(i))
+                .collect()
+        } else {
+            page.leaf_page_elements()
+                .iter()
+                .map(|e| Cow::Borrowed(e.key()))
+                .collect()
+        };
+        let keys: Vec<&[u8]> = owned.iter().map(|key| key.as_ref()).collect();
+        self.check_key_order(id, &keys, min_key, max_key);
+
+        if page.is_branch_page() {
+            for (i, elem) in page.branch_page_elements().iter().enumerate() {
+                let max = keys.get(i + 1).copied().or(max_key);
+                self.check_page(elem.pgid(), Some(keys[i]), max, children);
+            }
+            return;
+        }
+
+        for (i, elem) in page.leaf_page_elements().iter().enumerate() {
+            if !elem.is_bucket_entry() {
+                continue;
+            }
+            if elem.value().len() < mem::size_of::<InBucket>() {
+                let reason = format!("bucket header of key {:?} is truncated", keys[i]);
+                self.malformed(id, reason);
+                continue;
+            }
+            let root = InBucket::from_bytes(elem.value()).root_page();
+            if root != 0 {
+                children.push((keys[i].to_vec(), root));
+            }
+        }
+    }
+
+    // check_key_order verifies that the keys of a page are sorted and lie
+    // within the range its ancestors allow.
+    fn check_key_order(
+        &mut self,
+        id: PgId,
+        keys: &[&[u8]],
+        min_key: Option<&[u8]>,
+        max_key: Option<&[u8]>,
+    ) {
+        let out_of_order = |key: &[u8]| CheckErrorKind::OutOfOrder { key: key.to_vec() };
+        for pair in keys.windows(2) {
+            if pair[0] >= pair[1] {
+                self.report(id, out_of_order(pair[0]));
+            }
+        }
+
+        if let (Some(min), Some(first)) = (min_key, keys.first()) {
+            if *first < min {
+                self.report(id, out_of_order(first));
+            }
+        }
+        if let (Some(max), Some(last)) = (max_key, keys.last()) {
+            if *last >= max {
+                self.report(id, out_of_order(last));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    use super::CheckErrorKind;
+    use crate::db::{Options, DB};
+    use crate::errors::BoltError;
+
+    // Ensure that a healthy database passes the check.
+    #[test]
+    fn test_check_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            b.create_bucket(b"sub")?.put(b"foo", b"bar")?;
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| tx.bucket(b"widgets").unwrap().delete(b"00000010"))
+            .unwrap();
+
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // Ensure that a page with a clobbered type is reported.
+    #[test]
+    fn test_check_invalid_page_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let db = DB::open(&path, Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let (root, page_size) = db
+            .view(|tx| Ok(tx.bucket(b"widgets").unwrap().root()))
+            .map(|root| (root, db.info().unwrap().page_size))
+            .unwrap();
+        db.close().unwrap();
+
+        // Turn the root of the bucket into a freelist page.
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(root * page_size as u64 + 8))
+            .unwrap();
+        file.write_all(&0x10u16.to_le_bytes()).unwrap();
+        drop(file);
+
+        let db = DB::open(&path, Options::default()).unwrap();
+        let errors = db.view(|tx| tx.check()).unwrap().errors;
+        assert!(!errors.is_empty());
+        assert_eq!(errors[0].pgid, root);
+        assert_eq!(errors[0].bucket, vec![b"widgets".to_vec()]);
+        let typ = "freelist".to_string();
+        assert_eq!(errors[0].kind, CheckErrorKind::InvalidType { typ });
+        assert_eq!(
+            errors[0].to_string(),
+            format!("page {}: invalid type: freelist (bucket: widgets)", root)
+        );
+        // The pages below the clobbered root can no longer be reached.
+        assert!(errors[1..]
+            .iter()
+            .all(|e| e.kind == CheckErrorKind::Orphan && e.bucket.is_empty()));
+    }
+
+    // Ensure that strict mode reports an inconsistent commit.
+    #[test]
+    fn test_strict_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            strict_mode: true,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+
+        // Leak a page: it is neither reachable nor free.
+        let err = db
+            .update(|tx| {
+                tx.allocate(1)?;
+                Ok(())
+            })
+            .unwrap_err();
+        match err {
+            BoltError::CheckFailed(msg) => {
+                assert!(msg.starts_with("strict mode: page"), "{}", msg);
+                assert!(msg.ends_with("unreachable unfreed"), "{}", msg);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    // Ensure that opening with check_torn_writes reports the pages a torn
+    // write cut short or left malformed.
+    #[test]
+    fn test_check_torn_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = Options {
+            check_torn_writes: true,
+            ..Default::default()
+        };
+        let db = DB::open(&path, options.clone()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..1000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let (mut leaves, high) = db
+            .view(|tx| {
+                let mut leaves = Vec::new();
+                tx.for_each_page_in(tx.bucket(b"widgets").unwrap().root(), &mut |p, _, _| {
+                    if p.is_leaf_page() {
+                        leaves.push(p.id());
+                    }
+                })?;
+                Ok((leaves, tx.meta().pgid()))
+            })
+            .unwrap();
+        let page_size = db.info().unwrap().page_size as u64;
+        db.close().unwrap();
+        DB::open(&path, options.clone()).unwrap().close().unwrap();
+
+        // Zero a leaf page and cut the file in the middle of the last page,
+        // which holds the freelist.
+        leaves.sort_unstable();
+        let (zeroed, cut) = (leaves[0], high - 1);
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(zeroed * page_size)).unwrap();
+        file.write_all(&vec![0; page_size as usize]).unwrap();
+        file.set_len(cut * page_size + page_size / 2).unwrap();
+        drop(file);
+
+        match DB::open(&path, options).err() {
+            Some(BoltError::TornWrite(pgids)) => {
+                assert!(pgids.contains(&zeroed), "{:?}", pgids);
+                assert!(pgids.contains(&cut), "{:?}", pgids);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
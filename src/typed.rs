@@ -0,0 +1,244 @@
+//! Typed buckets store serde types instead of raw bytes.
+//!
+//! Keys and values are converted by a Codec. Cursors still see the encoded
+//! keys, so the iteration order is the byte order of the encoding: the
+//! integers bincode and MessagePack produce do not sort numerically. Encode
+//! keys with the keys module when their order matters.
+
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::bucket::Bucket;
+use crate::errors::{BoltError, Result};
+
+/// Codec converts keys and values to and from their stored bytes.
+pub trait Codec {
+    /// encode serializes a value.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+
+    /// decode deserializes a value.
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+}
+
+/// Bincode is a compact binary codec, and the default one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        bincode::deserialize(bytes).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+}
+
+/// Json stores keys and values as JSON text, which keeps them readable with
+/// tools such as `boltdb-cli get`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        serde_json::from_slice(bytes).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+}
+
+/// MessagePack is a compact, self-describing binary codec. Structs are
+/// stored as maps, so fields can be added without breaking old values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(bytes).map_err(|e| BoltError::Codec(e.to_string()))
+    }
+}
+
+/// TypedBucket wraps a bucket whose keys are of type K and values of type V,
+/// encoded with the codec C.
+///
+/// The bucket borrows its transaction, like Bucket.
+#[derive(Debug)]
+pub struct TypedBucket<'tx, K, V, C = Bincode> {
+    bucket: Bucket<'tx>,
+    _marker: PhantomData<Marker<K, V, C>>,
+}
+
+// TypedBucket owns no K, V or C values, so they do not affect its auto traits.
+type Marker<K, V, C> = fn() -> (K, V, C);
+
+impl<K, V, C> Clone for TypedBucket<'_, K, V, C> {
+    fn clone(&self) -> Self {
+        TypedBucket::new(self.bucket.clone())
+    }
+}
+
+impl<'tx, K, V, C> TypedBucket<'tx, K, V, C> {
+    /// new wraps a bucket.
+    pub fn new(bucket: Bucket<'tx>) -> Self {
+        TypedBucket {
+            bucket,
+            _marker: PhantomData,
+        }
+    }
+
+    /// bucket returns the underlying bucket.
+    pub fn bucket(&self) -> &Bucket<'tx> {
+        &self.bucket
+    }
+}
+
+impl<K, V, C> TypedBucket<'_, K, V, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// get_typed retrieves the value for a key in the bucket.
+    /// Returns None if the key does not exist or if the key is a nested bucket.
+    /// Returns BoltError::Codec if the stored value cannot be decoded as V.
+    pub fn get_typed(&self, key: &K) -> Result<Option<V>> {
+        match self.bucket.get(&C::encode(key)?) {
+            Some(value) => C::decode(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// put_typed sets the value for a key in the bucket.
+    /// If the key exist then its previous value will be overwritten.
+    pub fn put_typed(&self, key: &K, value: &V) -> Result<()> {
+        self.bucket.put(&C::encode(key)?, &C::encode(value)?)
+    }
+
+    /// delete_typed removes a key from the bucket.
+    /// If the key does not exist then nothing is done.
+    pub fn delete_typed(&self, key: &K) -> Result<()> {
+        self.bucket.delete(&C::encode(key)?)
+    }
+
+    /// for_each_typed executes a function for each key/value pair in the
+    /// bucket, in the order of the encoded keys. Nested buckets are skipped.
+    pub fn for_each_typed<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(K, V) -> Result<()>,
+    {
+        self.bucket.for_each(|k, v| match v {
+            Some(v) => f(C::decode(k)?, C::decode(v)?),
+            None => Ok(()),
+        })
+    }
+}
+
+impl<'tx> Bucket<'tx> {
+    /// typed wraps the bucket to store keys of type K and values of type V,
+    /// encoded with the codec C.
+    pub fn typed<K, V, C>(&self) -> TypedBucket<'tx, K, V, C> {
+        TypedBucket::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::db::{Options, DB};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+    }
+
+    fn user(name: &str, age: u32) -> User {
+        User {
+            name: name.to_string(),
+            age,
+            tags: vec!["admin".to_string()],
+        }
+    }
+
+    fn round_trip<C: Codec>() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let users = tx.create_bucket(b"users")?.typed::<String, User, C>();
+            users.put_typed(&"alice".to_string(), &user("alice", 30))?;
+            users.put_typed(&"bob".to_string(), &user("bob", 25))?;
+            users.bucket().create_bucket(b"nested")?;
+            Ok(())
+        })
+        .unwrap();
+
+        db.update(|tx| {
+            let users: TypedBucket<String, User, C> = tx.bucket(b"users").unwrap().typed();
+            assert_eq!(
+                users.get_typed(&"alice".to_string())?,
+                Some(user("alice", 30))
+            );
+            assert_eq!(users.get_typed(&"carol".to_string())?, None);
+
+            let mut seen = Vec::new();
+            users.for_each_typed(|name, user| {
+                assert_eq!(name, user.name);
+                seen.push(name);
+                Ok(())
+            })?;
+            seen.sort();
+            assert_eq!(seen, vec!["alice", "bob"]);
+
+            users.delete_typed(&"bob".to_string())?;
+            assert_eq!(users.get_typed(&"bob".to_string())?, None);
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_typed_bucket_bincode() {
+        round_trip::<Bincode>();
+    }
+
+    #[test]
+    fn test_typed_bucket_json() {
+        round_trip::<Json>();
+    }
+
+    #[test]
+    fn test_typed_bucket_msgpack() {
+        round_trip::<MessagePack>();
+    }
+
+    // Ensure that values of another type are reported instead of misread.
+    #[test]
+    fn test_typed_bucket_decode_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"users")?;
+            b.put(br#""alice""#, b"not json")?;
+            let users = b.typed::<String, User, Json>();
+            assert!(matches!(
+                users.get_typed(&"alice".to_string()),
+                Err(BoltError::Codec(_))
+            ));
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+}
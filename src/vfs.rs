@@ -0,0 +1,499 @@
+//! Storage backends of a database.
+//!
+//! A database reads and writes its pages through a Vfs. Files on disk are
+//! used unless Options.vfs is set, which lets the storage be swapped for an
+//! in-memory one, a gateway to remote storage or a wrapper injecting faults
+//! in tests.
+//!
+//! Files are memory-mapped on unix only. On Windows they are read without
+//! mmap, with Options.page_cache_size set. On wasm32, in a browser or an edge
+//! runtime, the database is kept in a MemoryVfs.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ptr::{self, NonNull};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+#[cfg(unix)]
+use memmap2::MmapOptions;
+
+use crate::errors::Result;
+use crate::os;
+
+/// SyncStrategy selects how written data is made durable. How much each one
+/// guarantees differs across platforms: on macOS fsync only hands the data
+/// to the drive, whose cache may lose it on power loss, while F_FULLFSYNC
+/// also flushes that cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncStrategy {
+    /// fdatasync, flushing the data and the metadata needed to read it.
+    #[default]
+    Fdatasync,
+    /// fsync, flushing the metadata of the file as well.
+    Fsync,
+    /// fcntl(F_FULLFSYNC) on macOS and iOS, flushing the cache of the drive
+    /// too. It is fsync elsewhere, and where F_FULLFSYNC is not supported.
+    FullFsync,
+    /// Nothing is synced. THIS IS UNSAFE, as for Options.no_sync.
+    None,
+}
+
+/// MapOptions are hints on how a snapshot is mapped. Only files on Linux
+/// act on them, other storage ignores them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapOptions {
+    /// Populate pre-faults the mapping with MAP_POPULATE, so that the file is
+    /// read into memory as it is mapped rather than page by page as it is
+    /// first accessed.
+    pub populate: bool,
+    /// HugePages advises the kernel to back the mapping with transparent
+    /// hugepages, which cuts TLB misses over large mappings. Kernels without
+    /// support for them on files ignore the advice.
+    pub huge_pages: bool,
+}
+
+/// Vfs is the storage a database is kept in, addressed like a file.
+///
+/// Pages are read through the snapshot returned by map, so a write must be
+/// visible through the snapshots handed out since the last call to map,
+/// the way the page cache backs a shared mmap of a file. A snapshot must
+/// stay readable as long as it is referenced, even after the storage grew.
+/// With Options.page_cache_size set, map is never called and pages are read
+/// with read_at instead.
+pub trait Vfs: fmt::Debug + Send + Sync {
+    /// read_at reads bytes at offset into buf, returning how many were read.
+    /// Zero is returned at the end of the storage.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// write_at writes bytes of buf at offset, returning how many were written.
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    /// sync makes the written data durable.
+    fn sync(&self) -> io::Result<()>;
+
+    /// sync_with makes the written data durable as strategy says. Storage
+    /// without such distinctions calls sync unless strategy is None.
+    fn sync_with(&self, strategy: SyncStrategy) -> io::Result<()> {
+        match strategy {
+            SyncStrategy::None => Ok(()),
+            _ => self.sync(),
+        }
+    }
+
+    /// len returns the size of the storage in bytes.
+    fn len(&self) -> io::Result<u64>;
+
+    /// is_empty returns whether the storage holds no bytes yet.
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// truncate resizes the storage to len bytes. Bytes added are zero.
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    /// lock keeps other processes from opening the storage for writing, or
+    /// for anything if exclusive is set. A zero timeout waits indefinitely;
+    /// BoltError::Timeout is returned when the timeout elapses.
+    fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()>;
+
+    /// unlock releases the lock taken by lock.
+    fn unlock(&self) -> Result<()>;
+
+    /// map returns a read-only snapshot of the first len bytes of the
+    /// storage. len may exceed the size of the storage.
+    fn map(&self, len: usize) -> io::Result<Bytes>;
+
+    /// map_with returns a snapshot like map, mapped as options say. Storage
+    /// without such distinctions calls map.
+    fn map_with(&self, len: usize, _options: MapOptions) -> io::Result<Bytes> {
+        self.map(len)
+    }
+}
+
+impl Vfs for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        os::read_at(self, buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        os::write_at(self, buf, offset)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn sync_with(&self, strategy: SyncStrategy) -> io::Result<()> {
+        match strategy {
+            SyncStrategy::Fdatasync => self.sync_data(),
+            SyncStrategy::Fsync => self.sync_all(),
+            SyncStrategy::FullFsync => os::full_fsync(self),
+            SyncStrategy::None => Ok(()),
+        }
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+
+    fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()> {
+        os::flock(self, exclusive, timeout)
+    }
+
+    fn unlock(&self) -> Result<()> {
+        os::funlock(self)
+    }
+
+    #[cfg(unix)]
+    fn map(&self, len: usize) -> io::Result<Bytes> {
+        self.map_with(len, MapOptions::default())
+    }
+
+    #[cfg(unix)]
+    fn map_with(&self, len: usize, options: MapOptions) -> io::Result<Bytes> {
+        let mut mmap_options = MmapOptions::new();
+        mmap_options.len(len);
+        if options.populate {
+            mmap_options.populate();
+        }
+        let mmap = unsafe { mmap_options.map(self) }?;
+        // It is only advice, failures are ignored.
+        #[cfg(target_os = "linux")]
+        if options.huge_pages {
+            let _ = mmap.advise(memmap2::Advice::HugePage);
+        }
+        Ok(Bytes::from_owner(mmap))
+    }
+
+    // Files are not memory-mapped on Windows and wasm, read them with
+    // Options.page_cache_size or keep the database in a MemoryVfs instead.
+    #[cfg(not(unix))]
+    fn map(&self, _len: usize) -> io::Result<Bytes> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "files cannot be memory-mapped on this platform, set page_cache_size or use MemoryVfs",
+        ))
+    }
+}
+
+/// MemoryVfs keeps a database in memory. The contents live as long as the
+/// MemoryVfs, so a database can be closed and opened again from it, but
+/// never reach the disk. Locking is a no-op.
+#[derive(Debug, Default)]
+pub struct MemoryVfs(Mutex<Memory>);
+
+#[derive(Debug, Default)]
+struct Memory {
+    // Size of the storage in bytes.
+    len: usize,
+    // Buffer holding the storage, at least len bytes long. It is replaced by
+    // a larger one when the storage outgrows it.
+    region: Option<Arc<Region>>,
+}
+
+impl MemoryVfs {
+    /// new returns an empty in-memory storage.
+    pub fn new() -> MemoryVfs {
+        MemoryVfs::default()
+    }
+
+    /// to_vec returns a copy of the contents of the storage.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let memory = self.0.lock().unwrap();
+        match &memory.region {
+            Some(region) => region.bytes()[..memory.len].to_vec(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Memory {
+    // reserve makes the region at least size bytes long.
+    fn reserve(&mut self, size: usize) -> &Region {
+        let cap = self.region.as_ref().map_or(0, |region| region.len);
+        if cap < size {
+            let region = Region::new(size.max(cap * 2));
+            if let Some(old) = &self.region {
+                region.write(&old.bytes()[..self.len], 0);
+            }
+            self.region = Some(Arc::new(region));
+        }
+        self.region.as_ref().unwrap()
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let memory = self.0.lock().unwrap();
+        let start = (offset as usize).min(memory.len);
+        let n = buf.len().min(memory.len - start);
+        if let Some(region) = &memory.region {
+            buf[..n].copy_from_slice(&region.bytes()[start..start + n]);
+        }
+        Ok(n)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let mut memory = self.0.lock().unwrap();
+        let end = offset as usize + buf.len();
+        memory.reserve(end).write(buf, offset as usize);
+        memory.len = memory.len.max(end);
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.0.lock().unwrap().len as u64)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let mut memory = self.0.lock().unwrap();
+        let len = len as usize;
+        let old = memory.len;
+        // Bytes cut off read as zero when the storage grows again.
+        if len < old {
+            memory.reserve(old).write(&vec![0; old - len], len);
+        }
+        memory.reserve(len);
+        memory.len = len;
+        Ok(())
+    }
+
+    fn lock(&self, _exclusive: bool, _timeout: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    fn unlock(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn map(&self, len: usize) -> io::Result<Bytes> {
+        let mut memory = self.0.lock().unwrap();
+        memory.reserve(len);
+        let region = memory.region.clone().unwrap();
+        Ok(Bytes::from_owner(Snapshot(region)).slice(..len))
+    }
+}
+
+// Region is a zeroed, 8-byte aligned buffer that is written through raw
+// pointers while snapshots of it are read, like a shared mmap.
+struct Region {
+    ptr: NonNull<u64>,
+    len: usize,
+}
+
+unsafe impl Send for Region {}
+
+unsafe impl Sync for Region {}
+
+impl Region {
+    fn new(len: usize) -> Region {
+        let words = vec![0u64; len.div_ceil(8)].into_boxed_slice();
+        let ptr = NonNull::new(Box::into_raw(words) as *mut u64).unwrap();
+        Region { ptr, len }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr() as *const u8, self.len) }
+    }
+
+    // write copies buf into the region at offset, which the caller has
+    // checked to lie within the region.
+    fn write(&self, buf: &[u8], offset: usize) {
+        assert!(offset + buf.len() <= self.len);
+        unsafe {
+            let dst = (self.ptr.as_ptr() as *mut u8).add(offset);
+            ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len());
+        }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        let words = ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len.div_ceil(8));
+        drop(unsafe { Box::from_raw(words) });
+    }
+}
+
+impl fmt::Debug for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Region").field("len", &self.len).finish()
+    }
+}
+
+// Snapshot keeps a region alive for the Bytes handed out by map.
+struct Snapshot(Arc<Region>);
+
+impl AsRef<[u8]> for Snapshot {
+    fn as_ref(&self) -> &[u8] {
+        self.0.bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::db::{Options, DB};
+    use crate::errors::{BoltError, IoOp};
+
+    // Ensure that a database kept in memory grows, survives being reopened
+    // and never touches the path it is named after.
+    #[test]
+    fn test_memory_vfs() {
+        let vfs = Arc::new(MemoryVfs::new());
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            ..Default::default()
+        };
+        let db = DB::open("memory", options.clone()).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..10000u32 {
+                b.put(format!("{:08}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+        assert!(!std::path::Path::new("memory").exists());
+        assert!(vfs.to_vec().len() > 1 << 20);
+
+        let db = DB::open("memory", options).unwrap();
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"00009999").unwrap(), &[0x5a; 100][..]);
+            assert_eq!(tx.check()?.errors, vec![]);
+            Ok(())
+        })
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    // FailingVfs fails syncs once told to.
+    #[derive(Debug, Default)]
+    struct FailingVfs {
+        inner: MemoryVfs,
+        fail: AtomicBool,
+    }
+
+    impl Vfs for FailingVfs {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.inner.read_at(buf, offset)
+        }
+
+        fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.inner.write_at(buf, offset)
+        }
+
+        fn sync(&self) -> io::Result<()> {
+            if self.fail.load(Ordering::Relaxed) {
+                return Err(io::Error::other("injected"));
+            }
+            self.inner.sync()
+        }
+
+        fn len(&self) -> io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn truncate(&self, len: u64) -> io::Result<()> {
+            self.inner.truncate(len)
+        }
+
+        fn lock(&self, exclusive: bool, timeout: Duration) -> Result<()> {
+            self.inner.lock(exclusive, timeout)
+        }
+
+        fn unlock(&self) -> Result<()> {
+            self.inner.unlock()
+        }
+
+        fn map(&self, len: usize) -> io::Result<Bytes> {
+            self.inner.map(len)
+        }
+    }
+
+    // Ensure that errors of a custom storage reach the committing caller.
+    #[test]
+    fn test_vfs_fault_injection() {
+        let vfs = Arc::new(FailingVfs::default());
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            ..Default::default()
+        };
+        let db = DB::open("failing", options).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+
+        vfs.fail.store(true, Ordering::Relaxed);
+        let err = db
+            .update(|tx| tx.bucket(b"widgets").unwrap().put(b"foo", b"baz"))
+            .unwrap_err();
+        match err {
+            BoltError::Io { op, source, .. } => {
+                assert_eq!(op, IoOp::Sync);
+                assert_eq!(source.to_string(), "injected");
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    // Ensure that the sync strategy None skips syncing the storage.
+    #[test]
+    fn test_sync_strategy_none() {
+        let vfs = Arc::new(FailingVfs {
+            fail: AtomicBool::new(true),
+            ..Default::default()
+        });
+        let options = Options {
+            vfs: Some(vfs.clone()),
+            sync_strategy: SyncStrategy::None,
+            ..Default::default()
+        };
+        let db = DB::open("unsynced", options).unwrap();
+        db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+            .unwrap();
+        assert!(vfs.sync_with(SyncStrategy::Fsync).is_err());
+    }
+
+    // Ensure that a file mapped with MAP_POPULATE and hugepage advice reads
+    // the same, across the remaps of a growing database.
+    #[cfg(unix)]
+    #[test]
+    fn test_map_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            mmap_populate: true,
+            mmap_huge_pages: true,
+            ..Default::default()
+        };
+        let db = DB::open(dir.path().join("db"), options).unwrap();
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            for i in 0..10000u32 {
+                b.put(format!("{:05}", i).as_bytes(), &[0x5a; 100])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.view(|tx| {
+            assert_eq!(tx.check()?.errors, vec![]);
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"09999"), Some(&[0x5a; 100][..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+//! Subscriptions to the changes of keys under a prefix.
+
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+
+use crate::changes::ChangeSet;
+use crate::db::DB;
+
+/// Event is a change to a watched key, made by a committed transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The key was created with value.
+    Created { key: Vec<u8>, value: Vec<u8> },
+    /// The value of the key changed from old to new.
+    Updated {
+        key: Vec<u8>,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    /// The key, whose value was old, was deleted.
+    Deleted { key: Vec<u8>, old: Vec<u8> },
+}
+
+impl Event {
+    /// key returns the key that changed.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Event::Created { key, .. }
+            | Event::Updated { key, .. }
+            | Event::Deleted { key, .. } => key,
+        }
+    }
+}
+
+impl DB {
+    /// watch returns a channel receiving an event for every change to a key
+    /// of the top-level bucket starting with prefix, once the transaction
+    /// making it commits. An empty prefix watches the whole bucket.
+    ///
+    /// Events of a transaction are sent in the order the changes were made,
+    /// and transactions in commit order. The watch ends when the receiver is
+    /// dropped. Events queue up unbounded until they are received.
+    pub fn watch(&self, bucket: &[u8], prefix: &[u8]) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        let bucket = bucket.to_vec();
+        let prefix = prefix.to_vec();
+        self.0.observers.add(Arc::new(move |changes: &ChangeSet| {
+            for change in &changes.changes {
+                if change.bucket != [&bucket[..]] || !change.key.starts_with(&prefix) {
+                    continue;
+                }
+                let key = change.key.clone();
+                let event = match (change.old.clone(), change.new.clone()) {
+                    (None, Some(value)) => Event::Created { key, value },
+                    (Some(old), Some(new)) => Event::Updated { key, old, new },
+                    (Some(old), None) => Event::Deleted { key, old },
+                    (None, None) => continue,
+                };
+                // The receiver is gone, stop watching.
+                if tx.send(event).is_err() {
+                    return false;
+                }
+            }
+            true
+        }));
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    #[test]
+    fn test_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+        let rx = db.watch(b"widgets", b"user/");
+
+        db.update(|tx| {
+            let b = tx.create_bucket(b"widgets")?;
+            b.put(b"user/1", b"alice")?;
+            b.put(b"group/1", b"admins")?;
+            b.put(b"user/1", b"bob")?;
+            b.create_bucket(b"sub")?.put(b"user/2", b"carol")?;
+            tx.create_bucket(b"other")?.put(b"user/3", b"dave")?;
+            Ok(())
+        })
+        .unwrap();
+        db.update(|tx| tx.bucket(b"widgets").unwrap().delete(b"user/1"))
+            .unwrap();
+
+        let events: Vec<Event> = rx.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::Created {
+                    key: b"user/1".to_vec(),
+                    value: b"alice".to_vec()
+                },
+                Event::Updated {
+                    key: b"user/1".to_vec(),
+                    old: b"alice".to_vec(),
+                    new: b"bob".to_vec()
+                },
+                Event::Deleted {
+                    key: b"user/1".to_vec(),
+                    old: b"bob".to_vec()
+                },
+            ]
+        );
+        assert_eq!(events[0].key(), b"user/1");
+
+        // Dropping the receiver removes the watch on the next commit.
+        drop(rx);
+        db.update(|tx| tx.bucket(b"widgets").unwrap().put(b"user/4", b"eve"))
+            .unwrap();
+        assert!(db.0.observers.is_empty());
+        db.close().unwrap();
+    }
+}
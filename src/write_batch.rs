@@ -0,0 +1,176 @@
+//! Write batches, changes staged outside of a transaction.
+//!
+//! A WriteBatch records puts, deletes and bucket creations without touching
+//! the database, so it can be built anywhere, kept or handed over, and applied
+//! later in a single write transaction with DB::apply.
+
+use crate::bucket::Bucket;
+use crate::db::DB;
+use crate::errors::{BoltError, Result};
+use crate::tx::Tx;
+
+/// BatchOp is a change recorded in a WriteBatch. Buckets are named by the
+/// names of the bucket and its ancestors, from the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchOp {
+    /// CreateBucket creates a bucket, which must not exist yet.
+    CreateBucket { bucket: Vec<Vec<u8>> },
+    /// Put sets the value for a key in a bucket.
+    Put {
+        bucket: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Delete removes a key from a bucket.
+    Delete { bucket: Vec<Vec<u8>>, key: Vec<u8> },
+}
+
+/// WriteBatch is a list of changes to apply atomically: either all of them
+/// are committed or, if one of them fails, none is.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// new returns an empty batch.
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    /// create_bucket records the creation of a bucket.
+    pub fn create_bucket<B: AsRef<[u8]>>(&mut self, bucket: &[B]) -> &mut WriteBatch {
+        self.ops.push(BatchOp::CreateBucket {
+            bucket: path(bucket),
+        });
+        self
+    }
+
+    /// put records setting the value for a key in a bucket.
+    pub fn put<B: AsRef<[u8]>>(
+        &mut self,
+        bucket: &[B],
+        key: &[u8],
+        value: &[u8],
+    ) -> &mut WriteBatch {
+        self.ops.push(BatchOp::Put {
+            bucket: path(bucket),
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+        self
+    }
+
+    /// delete records removing a key from a bucket.
+    pub fn delete<B: AsRef<[u8]>>(&mut self, bucket: &[B], key: &[u8]) -> &mut WriteBatch {
+        self.ops.push(BatchOp::Delete {
+            bucket: path(bucket),
+            key: key.to_vec(),
+        });
+        self
+    }
+
+    /// ops returns the changes recorded, in the order they are applied.
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    /// len returns the number of changes recorded.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// is_empty returns whether no change was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// apply makes the changes within a write transaction, in order.
+    pub(crate) fn apply(&self, tx: &Tx) -> Result<()> {
+        for op in &self.ops {
+            match op {
+                BatchOp::CreateBucket { bucket } => match bucket.split_last() {
+                    Some((name, [])) => tx.create_bucket(name).map(|_| ())?,
+                    Some((name, parent)) => find(tx, parent)?.create_bucket(name).map(|_| ())?,
+                    None => return Err(BoltError::BucketNameRequired),
+                },
+                BatchOp::Put { bucket, key, value } => find(tx, bucket)?.put(key, value)?,
+                BatchOp::Delete { bucket, key } => find(tx, bucket)?.delete(key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<BatchOp>> for WriteBatch {
+    fn from(ops: Vec<BatchOp>) -> WriteBatch {
+        WriteBatch { ops }
+    }
+}
+
+impl DB {
+    /// apply applies the changes of a batch within a single write
+    /// transaction. If a change fails, the transaction is rolled back and its
+    /// error is returned, so none of the changes are made.
+    pub fn apply(&self, batch: &WriteBatch) -> Result<()> {
+        self.update(|tx| batch.apply(tx))
+    }
+}
+
+// path copies the names of a bucket and its ancestors.
+fn path<B: AsRef<[u8]>>(bucket: &[B]) -> Vec<Vec<u8>> {
+    bucket.iter().map(|name| name.as_ref().to_vec()).collect()
+}
+
+// find returns the bucket at a path.
+fn find<'tx>(tx: &'tx Tx, path: &[Vec<u8>]) -> Result<Bucket<'tx>> {
+    let (first, rest) = path.split_first().ok_or(BoltError::BucketNotFound)?;
+    let mut bucket = tx.bucket(first).ok_or(BoltError::BucketNotFound)?;
+    for name in rest {
+        bucket = bucket.bucket(name).ok_or(BoltError::BucketNotFound)?;
+    }
+    Ok(bucket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Options;
+
+    // Ensure that a batch is applied completely or not at all.
+    #[test]
+    fn test_write_batch_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DB::open(dir.path().join("db"), Options::default()).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch
+            .create_bucket(&[b"widgets"])
+            .create_bucket(&[&b"widgets"[..], b"sub"])
+            .put(&[b"widgets"], b"foo", b"bar")
+            .put(&[b"widgets"], b"baz", b"bat")
+            .put(&[&b"widgets"[..], b"sub"], b"x", b"y")
+            .delete(&[b"widgets"], b"baz");
+        assert_eq!(batch.len(), 6);
+        db.apply(&batch).unwrap();
+
+        // Applying it again fails on the existing bucket, and a failing
+        // change undoes those before it.
+        assert_eq!(db.apply(&batch), Err(BoltError::BucketExists));
+
+        let mut missing = WriteBatch::new();
+        missing
+            .put(&[b"widgets"], b"foo", b"changed")
+            .delete(&[b"gadgets"], b"foo");
+        assert_eq!(db.apply(&missing), Err(BoltError::BucketNotFound));
+
+        db.view(|tx| {
+            let b = tx.bucket(b"widgets").unwrap();
+            assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+            assert_eq!(b.get(b"baz"), None);
+            assert_eq!(b.bucket(b"sub").unwrap().get(b"x"), Some(&b"y"[..]));
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! The on-disk layout of format version 2.
+//!
+//! The fixture below is the file expected after opening a new database with
+//! 4KB pages and running a single update that creates the bucket "widgets"
+//! and puts foo=bar into it. It is assembled byte by byte from the page,
+//! meta, freelist and leaf structures of format version 2, as bbolt declares
+//! them, without going through this crate, so it pins down both how the crate
+//! reads a file and the bytes it writes.
+//!
+//! The fixture is not a file written by Go bbolt, so these tests do not show
+//! that Go bbolt opens files written by this crate, nor the other way around.
+//! Showing that takes Go bbolt itself, which the build does not depend on:
+//! no file written by it is checked in, and nothing runs `bbolt check` on
+//! the files of this crate.
+
+use std::fs;
+use std::hash::Hasher;
+
+use boltdb_rs::{Options, DB};
+use fnv::FnvHasher;
+
+const PAGE_SIZE: usize = 4096;
+const MAGIC: u32 = 0xED0C_DAED;
+const VERSION: u32 = 2;
+
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const META_PAGE: u16 = 0x04;
+const FREELIST_PAGE: u16 = 0x10;
+const BUCKET_LEAF_FLAG: u32 = 0x01;
+
+struct Meta {
+    root: u64,
+    freelist: u64,
+    pgid: u64,
+    txid: u64,
+}
+
+fn put_u16(buf: &mut [u8], off: usize, v: u16) {
+    buf[off..off + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], off: usize, v: u32) {
+    buf[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut [u8], off: usize, v: u64) {
+    buf[off..off + 8].copy_from_slice(&v.to_le_bytes());
+}
+
+// page_header writes `type page struct { id pgid; flags uint16; count uint16; overflow uint32 }`.
+fn page_header(buf: &mut [u8], id: u64, flags: u16, count: u16) {
+    put_u64(buf, 0, id);
+    put_u16(buf, 8, flags);
+    put_u16(buf, 10, count);
+    put_u32(buf, 12, 0);
+}
+
+// meta_page writes a meta page. The checksum is the FNV-1a hash of every
+// meta field before it.
+fn meta_page(buf: &mut [u8], id: u64, m: &Meta) {
+    page_header(buf, id, META_PAGE, 0);
+    let meta = &mut buf[16..];
+    put_u32(meta, 0, MAGIC);
+    put_u32(meta, 4, VERSION);
+    put_u32(meta, 8, PAGE_SIZE as u32);
+    put_u32(meta, 12, 0);
+    put_u64(meta, 16, m.root);
+    put_u64(meta, 24, 0);
+    put_u64(meta, 32, m.freelist);
+    put_u64(meta, 40, m.pgid);
+    put_u64(meta, 48, m.txid);
+    let mut h = FnvHasher::default();
+    h.write(&meta[..56]);
+    put_u64(meta, 56, h.finish());
+}
+
+// leaf_page writes a leaf page. Each element is
+// `{ flags, pos, ksize, vsize uint32 }` and pos is relative to the element.
+fn leaf_page(buf: &mut [u8], id: u64, items: &[(u32, &[u8], &[u8])]) -> usize {
+    page_header(buf, id, LEAF_PAGE, items.len() as u16);
+    let mut off = 16 + 16 * items.len();
+    for (i, (flags, key, value)) in items.iter().enumerate() {
+        let elem = 16 + 16 * i;
+        put_u32(buf, elem, *flags);
+        put_u32(buf, elem + 4, (off - elem) as u32);
+        put_u32(buf, elem + 8, key.len() as u32);
+        put_u32(buf, elem + 12, value.len() as u32);
+        buf[off..off + key.len()].copy_from_slice(key);
+        off += key.len();
+        buf[off..off + value.len()].copy_from_slice(value);
+        off += value.len();
+    }
+    off
+}
+
+fn freelist_page(buf: &mut [u8], id: u64, ids: &[u64]) {
+    page_header(buf, id, FREELIST_PAGE, ids.len() as u16);
+    for (i, id) in ids.iter().enumerate() {
+        put_u64(buf, 16 + 8 * i, *id);
+    }
+}
+
+// inline_bucket returns the value of an inline bucket: the bucket header
+// `{ root pgid; sequence uint64 }` with a zero root, followed by its leaf page.
+fn inline_bucket(items: &[(u32, &[u8], &[u8])]) -> Vec<u8> {
+    let mut buf = vec![0u8; PAGE_SIZE];
+    let n = leaf_page(&mut buf[16..], 0, items);
+    buf.truncate(16 + n);
+    buf
+}
+
+// fixture returns the file expected after:
+//
+//	db, _ := bolt.Open(path, 0600, &bolt.Options{PageSize: 4096})
+//	db.Update(func(tx *bolt.Tx) error {
+//		b, _ := tx.CreateBucket([]byte("widgets"))
+//		return b.Put([]byte("foo"), []byte("bar"))
+//	})
+//	db.Close()
+fn fixture() -> Vec<u8> {
+    let mut file = vec![0u8; 8 * PAGE_SIZE];
+    let mut pages: Vec<&mut [u8]> = file.chunks_mut(PAGE_SIZE).collect();
+
+    // Pages 1 to 3 are left from initialization. The meta page of txid 1 still
+    // points at the empty root leaf and the empty freelist.
+    let initial = Meta {
+        root: 3,
+        freelist: 2,
+        pgid: 4,
+        txid: 1,
+    };
+    meta_page(pages[1], 1, &initial);
+    freelist_page(pages[2], 2, &[]);
+    leaf_page(pages[3], 3, &[]);
+
+    // The update wrote the root bucket to page 4, with widgets inlined, and a
+    // freelist holding the pages it replaced to page 5. The file then grew to
+    // the initial mmap size of 32KB.
+    let widgets = inline_bucket(&[(0, b"foo", b"bar")]);
+    leaf_page(pages[4], 4, &[(BUCKET_LEAF_FLAG, b"widgets", &widgets)]);
+    freelist_page(pages[5], 5, &[2, 3]);
+    let current = Meta {
+        root: 4,
+        freelist: 5,
+        pgid: 6,
+        txid: 2,
+    };
+    meta_page(pages[0], 0, &current);
+
+    file
+}
+
+// Ensure that a file laid out as the fixture is read correctly.
+#[test]
+fn test_read_fixture() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fixture.db");
+    fs::write(&path, fixture()).unwrap();
+
+    let db = DB::open(
+        &path,
+        Options {
+            read_only: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let info = db.info().unwrap();
+    assert_eq!(info.page_size, PAGE_SIZE);
+    assert_eq!(info.version, VERSION);
+    assert_eq!((info.root, info.freelist, info.pgid), (4, 5, 6));
+    assert_eq!(info.txid, 2);
+    db.view(|tx| {
+        let b = tx.bucket(b"widgets").unwrap();
+        assert_eq!(b.get(b"foo"), Some(&b"bar"[..]));
+        assert_eq!(tx.check()?.errors, vec![]);
+        Ok(())
+    })
+    .unwrap();
+    db.close().unwrap();
+
+    // Keep writing to the file, enough to split the bucket out of the root.
+    let db = DB::open(&path, Options::default()).unwrap();
+    db.update(|tx| {
+        let b = tx.bucket(b"widgets").unwrap();
+        for i in 0..1000u32 {
+            b.put(format!("{:08}", i).as_bytes(), &[0x5a; 64])?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    db.view(|tx| {
+        assert_eq!(tx.check()?.errors, vec![]);
+        Ok(())
+    })
+    .unwrap();
+    db.close().unwrap();
+}
+
+// Ensure that the crate writes exactly the bytes of the fixture.
+#[test]
+fn test_write_fixture() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rs.db");
+    let db = DB::open(
+        &path,
+        Options {
+            page_size: PAGE_SIZE,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    db.update(|tx| tx.create_bucket(b"widgets")?.put(b"foo", b"bar"))
+        .unwrap();
+    db.close().unwrap();
+
+    let got = fs::read(&path).unwrap();
+    let want = fixture();
+    assert!(got.len() >= 6 * PAGE_SIZE);
+    for id in 0..6 {
+        let page = id * PAGE_SIZE..(id + 1) * PAGE_SIZE;
+        assert!(got[page.clone()] == want[page], "page {} differs", id);
+    }
+}
+
+// Ensure that branch pages use bbolt's element layout:
+// `{ pos, ksize uint32; pgid pgid }` with pos relative to the element.
+#[test]
+fn test_branch_page_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rs.db");
+    let db = DB::open(
+        &path,
+        Options {
+            page_size: PAGE_SIZE,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    db.update(|tx| {
+        let b = tx.create_bucket(b"widgets")?;
+        for i in 0..1000u32 {
+            b.put(format!("{:08}", i).as_bytes(), &[0x5a; 64])?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    let root = db
+        .view(|tx| Ok(tx.bucket(b"widgets").unwrap().root()))
+        .unwrap();
+    db.close().unwrap();
+
+    let file = fs::read(&path).unwrap();
+    let page = &file[root as usize * PAGE_SIZE..];
+    let u32_at = |off: usize| u32::from_le_bytes(page[off..off + 4].try_into().unwrap());
+    let u64_at = |off: usize| u64::from_le_bytes(page[off..off + 8].try_into().unwrap());
+    assert_eq!(u64_at(0), root);
+    assert_eq!(u16::from_le_bytes([page[8], page[9]]), BRANCH_PAGE);
+
+    let count = u16::from_le_bytes([page[10], page[11]]) as usize;
+    assert!(count > 1);
+    for i in 0..count {
+        let elem = 16 + 16 * i;
+        let (pos, ksize) = (u32_at(elem) as usize, u32_at(elem + 4) as usize);
+        let key = &page[elem + pos..elem + pos + ksize];
+        assert_eq!(key.len(), 8);
+        let child = u64_at(elem + 8);
+        assert!(child > 1 && child != root);
+
+        // The first key of the child is the key of the element.
+        let child = &file[child as usize * PAGE_SIZE..];
+        assert_eq!(u16::from_le_bytes([child[8], child[9]]), LEAF_PAGE);
+        let pos = u32::from_le_bytes(child[20..24].try_into().unwrap()) as usize;
+        assert_eq!(&child[16 + pos..16 + pos + 8], key);
+    }
+}
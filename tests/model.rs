@@ -0,0 +1,236 @@
+//! Model-based testing of the B-tree.
+//!
+//! Random sequences of puts, deletes, bucket creations and deletions, commits
+//! and rollbacks are applied both to a database and to a model made of
+//! BTreeMaps. After every commit and rollback the committed contents of the
+//! database must equal the model, and the database must pass Tx::check. When
+//! a case fails, proptest shrinks it to a minimal sequence of operations.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use boltdb_rs::{BoltError, Bucket, MemoryVfs, Options, Tx, DB};
+use proptest::prelude::*;
+
+// Bucket paths the operations refer to. The last two are nested in the first,
+// so deleting it deletes them as well. Keys are made of other letters, so
+// they never collide with bucket names.
+const BUCKETS: &[&[&[u8]]] = &[
+    &[b"x0"],
+    &[b"x1"],
+    &[b"x2"],
+    &[b"x0", b"y0"],
+    &[b"x0", b"y1"],
+];
+
+type Path = Vec<Vec<u8>>;
+type Model = BTreeMap<Path, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Put {
+        bucket: usize,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        bucket: usize,
+        key: Vec<u8>,
+    },
+    CreateBucket(usize),
+    DeleteBucket(usize),
+    Commit,
+    Rollback,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let bucket = 0..BUCKETS.len();
+    // Few distinct keys, so that puts overwrite and deletes hit.
+    let key = proptest::collection::vec(b'a'..=b'd', 1..=4);
+    // Values up to a page, so that leaves split and overflow.
+    let value = prop_oneof![
+        proptest::collection::vec(any::<u8>(), 0..16),
+        (0..4096usize).prop_map(|n| vec![0x5a; n]),
+    ];
+    prop_oneof![
+        10 => (bucket.clone(), key.clone(), value)
+            .prop_map(|(bucket, key, value)| Op::Put { bucket, key, value }),
+        4 => (bucket.clone(), key).prop_map(|(bucket, key)| Op::Delete { bucket, key }),
+        2 => bucket.clone().prop_map(Op::CreateBucket),
+        1 => bucket.prop_map(Op::DeleteBucket),
+        2 => Just(Op::Commit),
+        1 => Just(Op::Rollback),
+    ]
+}
+
+fn path(bucket: usize) -> Path {
+    BUCKETS[bucket].iter().map(|name| name.to_vec()).collect()
+}
+
+// lookup opens the bucket at a path, if it and its parents exist.
+fn lookup<'tx>(tx: &'tx Tx, path: &Path) -> Option<Bucket<'tx>> {
+    let mut b = tx.bucket(&path[0])?;
+    for name in &path[1..] {
+        b = b.bucket(name)?;
+    }
+    Some(b)
+}
+
+// apply applies an operation to the write transaction and the pending model,
+// checking that the database agrees with the model on its outcome.
+fn apply(tx: &Tx, model: &mut Model, op: &Op) {
+    match op {
+        Op::Put { bucket, key, value } => {
+            let path = path(*bucket);
+            match lookup(tx, &path) {
+                Some(b) => {
+                    b.put(key, value).unwrap();
+                    assert_eq!(b.get(key), Some(&value[..]));
+                    model
+                        .get_mut(&path)
+                        .unwrap()
+                        .insert(key.clone(), value.clone());
+                }
+                None => assert!(!model.contains_key(&path)),
+            }
+        }
+        Op::Delete { bucket, key } => {
+            let path = path(*bucket);
+            match lookup(tx, &path) {
+                Some(b) => {
+                    b.delete(key).unwrap();
+                    assert_eq!(b.get(key), None);
+                    model.get_mut(&path).unwrap().remove(key);
+                }
+                None => assert!(!model.contains_key(&path)),
+            }
+        }
+        Op::CreateBucket(bucket) => {
+            let path = path(*bucket);
+            let (name, parent) = path.split_last().unwrap();
+            let result = match parent.is_empty() {
+                true => tx.create_bucket(name).map(|_| ()),
+                false => match lookup(tx, &parent.to_vec()) {
+                    Some(parent) => parent.create_bucket(name).map(|_| ()),
+                    None => {
+                        assert!(!model.contains_key(parent));
+                        return;
+                    }
+                },
+            };
+            match model.contains_key(&path) {
+                true => assert_eq!(result, Err(BoltError::BucketExists)),
+                false => {
+                    result.unwrap();
+                    model.insert(path, BTreeMap::new());
+                }
+            }
+        }
+        Op::DeleteBucket(bucket) => {
+            let path = path(*bucket);
+            let (name, parent) = path.split_last().unwrap();
+            let result = match parent.is_empty() {
+                true => tx.delete_bucket(name),
+                false => match lookup(tx, &parent.to_vec()) {
+                    Some(parent) => parent.delete_bucket(name),
+                    None => {
+                        assert!(!model.contains_key(parent));
+                        return;
+                    }
+                },
+            };
+            match model.contains_key(&path) {
+                true => {
+                    result.unwrap();
+                    model.retain(|p, _| !p.starts_with(&path));
+                }
+                false => assert_eq!(result, Err(BoltError::BucketNotFound)),
+            }
+        }
+        Op::Commit | Op::Rollback => unreachable!(),
+    }
+}
+
+// contents reads every bucket of the database into a model.
+fn contents(tx: &Tx) -> Model {
+    fn walk(b: &Bucket, path: Path, model: &mut Model) {
+        let mut keys = BTreeMap::new();
+        b.for_each(|k, v| {
+            let mut child = path.clone();
+            child.push(k.to_vec());
+            match v {
+                Some(v) => {
+                    keys.insert(k.to_vec(), v.to_vec());
+                }
+                None => walk(&b.bucket(k).unwrap(), child, model),
+            }
+            Ok(())
+        })
+        .unwrap();
+        model.insert(path, keys);
+    }
+
+    let mut model = Model::new();
+    tx.for_each(|name, b| {
+        walk(&b, vec![name.to_vec()], &mut model);
+        Ok(())
+    })
+    .unwrap();
+    model
+}
+
+// verify checks that the committed contents of the database equal the model.
+fn verify(db: &DB, committed: &Model) {
+    db.view(|tx| {
+        assert_eq!(tx.check()?.errors, vec![]);
+        assert_eq!(&contents(tx), committed);
+        Ok(())
+    })
+    .unwrap();
+}
+
+fn run(ops: &[Op], page_size: usize) {
+    let options = Options {
+        page_size,
+        vfs: Some(Arc::new(MemoryVfs::default())),
+        ..Default::default()
+    };
+    let db = DB::open("model", options).unwrap();
+    let mut committed = Model::new();
+    let mut pending = committed.clone();
+    let mut tx = db.begin_rw().unwrap();
+    for op in ops {
+        match op {
+            Op::Commit => {
+                tx.commit().unwrap();
+                committed = pending.clone();
+                verify(&db, &committed);
+                tx = db.begin_rw().unwrap();
+            }
+            Op::Rollback => {
+                tx.rollback().unwrap();
+                pending = committed.clone();
+                verify(&db, &committed);
+                tx = db.begin_rw().unwrap();
+            }
+            op => apply(&tx, &mut pending, op),
+        }
+    }
+    tx.commit().unwrap();
+    verify(&db, &pending);
+}
+
+proptest! {
+    // Ensure that the database behaves like the model, with pages small
+    // enough for the tree to grow several levels deep.
+    #[test]
+    fn test_model_small_pages(ops in proptest::collection::vec(op(), 1..300)) {
+        run(&ops, 1024);
+    }
+
+    // Ensure that the database behaves like the model with default pages.
+    #[test]
+    fn test_model(ops in proptest::collection::vec(op(), 1..300)) {
+        run(&ops, 4096);
+    }
+}
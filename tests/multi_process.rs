@@ -0,0 +1,50 @@
+//! Opening a database from several processes.
+//!
+//! The test process holds the database open while boltdb-cli, in a process
+//! of its own, inspects it: read-only opens share the lock of the file, an
+//! open for writing keeps them out.
+#![cfg(all(unix, feature = "cli"))]
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+use boltdb_rs::{Options, DB};
+
+fn buckets(path: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_boltdb-cli"))
+        .arg("buckets")
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+// Ensure that another process can read the database while it is open
+// read-only, and is refused while it is open for writing.
+#[test]
+fn test_read_only_processes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db");
+    let db = DB::open(&path, Options::default()).unwrap();
+    db.update(|tx| tx.create_bucket(b"widgets").map(|_| ()))
+        .unwrap();
+
+    let output = buckets(&path);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("locked by another process"), "{}", stderr);
+    db.close().unwrap();
+
+    let options = Options {
+        read_only: true,
+        ..Default::default()
+    };
+    let db = DB::open(&path, options).unwrap();
+    let output = buckets(&path);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "widgets\n");
+    db.view(|tx| {
+        assert!(tx.bucket(b"widgets").is_some());
+        Ok(())
+    })
+    .unwrap();
+}